@@ -1,24 +1,32 @@
 //! Cortex-M NVIC
 
-use kernel::common::cells::VolatileCell;
+use kernel::common::regs::ReadWrite;
 use kernel::common::StaticRef;
 
 #[repr(C)]
 // Registers for the NVIC
 struct NvicRegisters {
     // Interrupt set-enable
-    iser: [VolatileCell<u32>; 8],
+    iser: [ReadWrite<u32, NvicSetClear::Register>; 8],
     _reserved1: [u32; 24],
     // Interrupt clear-enable
-    icer: [VolatileCell<u32>; 8],
+    icer: [ReadWrite<u32, NvicSetClear::Register>; 8],
     _reserved2: [u32; 24],
     // Interrupt set-pending (and read pending state)
-    ispr: [VolatileCell<u32>; 8],
-    _reserved3: [VolatileCell<u32>; 24],
+    ispr: [ReadWrite<u32, NvicSetClear::Register>; 8],
+    _reserved3: [u32; 24],
     // Interrupt clear-pending (and read pending state)
-    icpr: [VolatileCell<u32>; 8],
+    icpr: [ReadWrite<u32, NvicSetClear::Register>; 8],
 }
 
+register_bitfields![u32,
+    NvicSetClear [
+        /// For each interrupt in the block, writing 1 sets or clears the
+        /// corresponding state and reading returns the current state.
+        BITS OFFSET(0) NUMBITS(32)
+    ]
+];
+
 // NVIC base address
 const NVIC_BASE_ADDRESS: StaticRef<NvicRegisters> =
     unsafe { StaticRef::new(0xe000e100 as *const NvicRegisters) };
@@ -27,7 +35,7 @@ const NVIC_BASE_ADDRESS: StaticRef<NvicRegisters> =
 pub unsafe fn clear_all_pending() {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
     for icpr in nvic.icpr.iter() {
-        icpr.set(!0)
+        icpr.write(NvicSetClear::BITS.val(!0))
     }
 }
 
@@ -35,7 +43,7 @@ pub unsafe fn clear_all_pending() {
 pub unsafe fn enable_all() {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
     for icer in nvic.iser.iter() {
-        icer.set(!0)
+        icer.write(NvicSetClear::BITS.val(!0))
     }
 }
 
@@ -43,7 +51,7 @@ pub unsafe fn enable_all() {
 pub unsafe fn disable_all() {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
     for icer in nvic.icer.iter() {
-        icer.set(!0)
+        icer.write(NvicSetClear::BITS.val(!0))
     }
 }
 
@@ -53,7 +61,7 @@ pub unsafe fn next_pending() -> Option<u32> {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
 
     for (block, ispr) in nvic.ispr.iter().enumerate() {
-        let ispr = ispr.get();
+        let ispr = ispr.read(NvicSetClear::BITS);
 
         // If there are any high bits there is a pending interrupt
         if ispr != 0 {
@@ -68,7 +76,10 @@ pub unsafe fn next_pending() -> Option<u32> {
 pub unsafe fn has_pending() -> bool {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
 
-    nvic.ispr.iter().fold(0, |i, ispr| ispr.get() | i) != 0
+    nvic.ispr
+        .iter()
+        .fold(0, |i, ispr| ispr.read(NvicSetClear::BITS) | i)
+        != 0
 }
 
 /// An opaque wrapper for a single NVIC interrupt.
@@ -91,7 +102,7 @@ impl Nvic {
         let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
         let idx = self.0 as usize;
 
-        nvic.iser[idx / 32].set(1 << (self.0 & 31));
+        nvic.iser[idx / 32].write(NvicSetClear::BITS.val(1 << (self.0 & 31)));
     }
 
     /// Disable the interrupt
@@ -99,7 +110,7 @@ impl Nvic {
         let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
         let idx = self.0 as usize;
 
-        nvic.icer[idx / 32].set(1 << (self.0 & 31));
+        nvic.icer[idx / 32].write(NvicSetClear::BITS.val(1 << (self.0 & 31)));
     }
 
     /// Clear pending state
@@ -107,6 +118,6 @@ impl Nvic {
         let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
         let idx = self.0 as usize;
 
-        nvic.icpr[idx / 32].set(1 << (self.0 & 31));
+        nvic.icpr[idx / 32].write(NvicSetClear::BITS.val(1 << (self.0 & 31)));
     }
 }
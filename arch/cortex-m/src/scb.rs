@@ -2,55 +2,82 @@
 //!
 //! <http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dui0553a/CIHFDJCA.html>
 
-use kernel::common::cells::VolatileCell;
+use kernel::common::regs::{ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 
 #[repr(C)]
 struct ScbRegisters {
-    cpuid: VolatileCell<u32>,
-    icsr: VolatileCell<u32>,
-    vtor: VolatileCell<u32>,
-    aircr: VolatileCell<u32>,
-    scr: VolatileCell<u32>,
-    ccr: VolatileCell<u32>,
-    shp: [VolatileCell<u32>; 3],
-    shcsr: VolatileCell<u32>,
-    cfsr: VolatileCell<u32>,
-    hfsr: VolatileCell<u32>,
-    dfsr: VolatileCell<u32>,
-    mmfar: VolatileCell<u32>,
-    bfar: VolatileCell<u32>,
-    afsr: VolatileCell<u32>,
-    pfr: [VolatileCell<u32>; 2],
-    dfr: VolatileCell<u32>,
-    adr: VolatileCell<u32>,
-    mmfr: [VolatileCell<u32>; 4],
-    isar: [VolatileCell<u32>; 5],
+    cpuid: ReadOnly<u32>,
+    icsr: ReadWrite<u32>,
+    vtor: ReadWrite<u32>,
+    aircr: ReadWrite<u32, ApplicationInterruptAndReset::Register>,
+    scr: ReadWrite<u32, SystemControl::Register>,
+    ccr: ReadWrite<u32>,
+    shp: [ReadWrite<u32>; 3],
+    shcsr: ReadWrite<u32>,
+    cfsr: ReadWrite<u32>,
+    hfsr: ReadWrite<u32>,
+    dfsr: ReadWrite<u32>,
+    mmfar: ReadWrite<u32>,
+    bfar: ReadWrite<u32>,
+    afsr: ReadWrite<u32>,
+    pfr: [ReadOnly<u32>; 2],
+    dfr: ReadOnly<u32>,
+    adr: ReadOnly<u32>,
+    mmfr: [ReadOnly<u32>; 4],
+    isar: [ReadOnly<u32>; 5],
     _reserved0: [u32; 5],
-    cpacr: VolatileCell<u32>,
+    cpacr: ReadWrite<u32>,
 }
 
+register_bitfields![u32,
+    ApplicationInterruptAndReset [
+        /// Must be written as 0x05FA, or the write is ignored.
+        VECTKEY         OFFSET(16) NUMBITS(16) [
+            Key = 0x05FA
+        ],
+
+        /// Interrupt priority grouping.
+        PRIGROUP        OFFSET(8)  NUMBITS(3) [],
+
+        /// Request a system reset.
+        SYSRESETREQ     OFFSET(2)  NUMBITS(1) []
+    ],
+
+    SystemControl [
+        /// Wake on any pending interrupt, including disabled ones.
+        SEVONPEND       OFFSET(4)  NUMBITS(1),
+
+        /// Use deep sleep (1) rather than sleep (0) on WFI.
+        SLEEPDEEP       OFFSET(2)  NUMBITS(1),
+
+        /// Sleep again on return from an exception handler to thread mode.
+        SLEEPONEXIT     OFFSET(1)  NUMBITS(1)
+    ]
+];
+
 const SCB: StaticRef<ScbRegisters> = unsafe { StaticRef::new(0xE000ED00 as *const ScbRegisters) };
 
 /// Allow the core to go into deep sleep on WFI.
 ///
 /// The specific definition of "deep sleep" is chip specific.
 pub unsafe fn set_sleepdeep() {
-    let scr = SCB.scr.get();
-    SCB.scr.set(scr | 1 << 2);
+    SCB.scr.modify(SystemControl::SLEEPDEEP::SET);
 }
 
 /// Do not allow the core to go into deep sleep on WFI.
 ///
 /// The specific definition of "deep sleep" is chip specific.
 pub unsafe fn unset_sleepdeep() {
-    let scr = SCB.scr.get();
-    SCB.scr.set(scr & !(1 << 2));
+    SCB.scr.modify(SystemControl::SLEEPDEEP::CLEAR);
 }
 
 /// Software reset using the ARM System Control Block
 pub unsafe fn reset() {
-    let aircr = SCB.aircr.get();
-    let reset = (0x5FA << 16) | (aircr & (0x7 << 8)) | (1 << 2);
-    SCB.aircr.set(reset);
+    let prigroup = SCB.aircr.read(ApplicationInterruptAndReset::PRIGROUP);
+    SCB.aircr.write(
+        ApplicationInterruptAndReset::VECTKEY::Key
+            + ApplicationInterruptAndReset::PRIGROUP.val(prigroup)
+            + ApplicationInterruptAndReset::SYSRESETREQ::SET,
+    );
 }
@@ -54,6 +54,7 @@ register_bitfields![u32,
 /// Documented in the Cortex-MX Devices Generic User Guide, Chapter 4.4
 pub struct SysTick {
     hertz: u32,
+    disabled: bool,
 }
 
 const BASE_ADDR: *const SystickRegisters = 0xE000E010 as *const SystickRegisters;
@@ -66,7 +67,22 @@ impl SysTick {
     /// Use this constructor if the core implementation has a pre-calibration
     /// value in hardware.
     pub unsafe fn new() -> SysTick {
-        SysTick { hertz: 0 }
+        SysTick {
+            hertz: 0,
+            disabled: false,
+        }
+    }
+
+    /// Initialize a `SysTick` that is never started and never expires
+    ///
+    /// Use this constructor if the SysTick is unreliable on the chip, or to
+    /// keep it off to save power. The kernel's timer preemption must then be
+    /// turned off with `kernel::set_preemption(false)`.
+    pub unsafe fn new_disabled() -> SysTick {
+        SysTick {
+            hertz: 0,
+            disabled: true,
+        }
     }
 
     /// Initialize the `SysTick` with an explicit clock speed
@@ -99,6 +115,9 @@ impl SysTick {
 
 impl kernel::SysTick for SysTick {
     fn set_timer(&self, us: u32) {
+        if self.disabled {
+            return;
+        }
         let reload = {
             // We need to convert from microseconds to native tics, which could overflow in 32-bit
             // arithmetic. So we convert to 64-bit. 64-bit division is an expensive subroutine, but
@@ -118,6 +137,9 @@ impl kernel::SysTick for SysTick {
     }
 
     fn greater_than(&self, us: u32) -> bool {
+        if self.disabled {
+            return true;
+        }
         let tics = {
             // We need to convert from microseconds to native tics, which could overflow in 32-bit
             // arithmetic. So we convert to 64-bit. 64-bit division is an expensive subroutine, but
@@ -133,17 +155,38 @@ impl kernel::SysTick for SysTick {
         value > tics
     }
 
+    fn remaining_us(&self) -> u32 {
+        if self.disabled {
+            return u32::max_value();
+        }
+        let hertz = self.hertz() as u64;
+        if hertz == 0 {
+            return 0;
+        }
+        let value = SYSTICK_BASE.syst_cvr.read(CurrentValue::CURRENT) as u64;
+        (value * 1_000_000 / hertz) as u32
+    }
+
     fn overflowed(&self) -> bool {
+        if self.disabled {
+            return false;
+        }
         SYSTICK_BASE.syst_csr.is_set(ControlAndStatus::COUNTFLAG)
     }
 
     fn reset(&self) {
+        if self.disabled {
+            return;
+        }
         SYSTICK_BASE.syst_csr.set(0);
         SYSTICK_BASE.syst_rvr.set(0);
         SYSTICK_BASE.syst_cvr.set(0);
     }
 
     fn enable(&self, with_interrupt: bool) {
+        if self.disabled {
+            return;
+        }
         if with_interrupt {
             SYSTICK_BASE.syst_csr.write(
                 ControlAndStatus::ENABLE::SET
@@ -179,6 +179,37 @@ pub unsafe extern "C" fn switch_to_user(
     user_stack as *mut u8
 }
 
+#[cfg(not(target_os = "none"))]
+pub unsafe extern "C" fn kernel_stack_overflow(_faulting_stack: *mut u32) {}
+
+#[cfg(target_os = "none")]
+#[naked]
+/// Called by a HardFault handler when the kernel stack has overflowed into
+/// its guard. Moves the stack pointer back to the top of the kernel stack, so
+/// that there is room to report the fault, and panics. `faulting_stack` is
+/// the kernel stack pointer at the time of the fault.
+pub unsafe extern "C" fn kernel_stack_overflow(_faulting_stack: *mut u32) {
+    asm!(
+        "
+    ldr r1, =_estack
+    msr msp, r1
+    b kernel_stack_overflow_panic"
+    );
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kernel_stack_overflow_panic(faulting_stack: *const u32) -> ! {
+    let stacked_lr = *faulting_stack.offset(5);
+    let stacked_pc = *faulting_stack.offset(6);
+    panic!(
+        "Kernel stack overflow.\n\
+         \tlr  0x{:x}\n\
+         \tpc  0x{:x}\n\
+         \tsp  0x{:x}\n",
+        stacked_lr, stacked_pc, faulting_stack as u32
+    );
+}
+
 // Table 2.5
 // http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dui0553a/CHDBIBGJ.html
 pub fn ipsr_isr_number_to_str(isr_number: usize) -> &'static str {
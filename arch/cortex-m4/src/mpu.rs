@@ -1,5 +1,6 @@
 //! Implementation of the ARM memory protection unit.
 
+use core::cell::Cell;
 use kernel;
 use kernel::common::cells::VolatileCell;
 use kernel::common::math::PowerOfTwo;
@@ -107,12 +108,29 @@ pub struct MpuRegisters {
 const MPU_BASE_ADDRESS: StaticRef<MpuRegisters> =
     unsafe { StaticRef::new(0xE000ED90 as *const MpuRegisters) };
 
+/// The end of the kernel stack guard, or 0 if there is none. Read by the
+/// HardFault handler, which cannot reach the `MPU`.
+static mut KERNEL_STACK_GUARD_END: usize = 0;
+
+/// Whether a fault with the kernel stack pointer at `sp` is a kernel stack
+/// overflow, that is, whether `sp` has reached the kernel stack guard.
+pub fn kernel_stack_overflowed(sp: usize) -> bool {
+    unsafe { sp < KERNEL_STACK_GUARD_END }
+}
+
 /// Constructor field is private to limit who can create a new MPU
-pub struct MPU(StaticRef<MpuRegisters>);
+pub struct MPU {
+    registers: StaticRef<MpuRegisters>,
+    /// The base address and attributes of the kernel stack guard region.
+    stack_guard: Cell<Option<(u32, u32)>>,
+}
 
 impl MPU {
     pub const unsafe fn new() -> MPU {
-        MPU(MPU_BASE_ADDRESS)
+        MPU {
+            registers: MPU_BASE_ADDRESS,
+            stack_guard: Cell::new(None),
+        }
     }
 }
 
@@ -120,7 +138,7 @@ type Region = kernel::mpu::Region;
 
 impl kernel::mpu::MPU for MPU {
     fn enable_mpu(&self) {
-        let regs = &*self.0;
+        let regs = &*self.registers;
 
         // Enable the MPU, disable it during HardFault/NMI handlers, allow
         // privileged code access to all unprotected memory.
@@ -137,7 +155,7 @@ impl kernel::mpu::MPU for MPU {
     }
 
     fn disable_mpu(&self) {
-        let regs = &*self.0;
+        let regs = &*self.registers;
         regs.control.set(0b0);
     }
 
@@ -155,15 +173,16 @@ impl kernel::mpu::MPU for MPU {
 
         // There are two possibilities we support:
         //
-        // 1. The base address is aligned exactly to the size of the region,
-        //    which uses an MPU region with the exact base address and size of
-        //    the memory region.
+        // 1. The length is a power of two and the base address is aligned
+        //    exactly to it, which uses an MPU region with the exact base
+        //    address and size of the memory region.
         //
         // 2. Otherwise, we can use a larger MPU region and expose only MPU
-        //    subregions, as long as the memory region's base address is aligned
-        //    to 1/8th of a larger region size.
+        //    subregions, as long as the memory region's base address and
+        //    length are multiples of 1/8th of a larger region size. The
+        //    kernel finds the smallest such region.
 
-        if start % len == 0 {
+        if len.is_power_of_two() && start % len == 0 {
             // Memory base aligned to memory size - straight forward case
             let region_len = PowerOfTwo::floor(len as u32);
             if region_len.exp::<u32>() < 5 {
@@ -184,71 +203,10 @@ impl kernel::mpu::MPU for MPU {
             })
         } else {
             // Memory base not aligned to memory size
-
-            // Which (power-of-two) subregion size would align with the base
-            // address?
-            //
-            // We find this by taking smallest binary substring of the base
-            // address with exactly one bit:
-            //
-            //      1 << (start.trailing_zeros())
-            let subregion_size = {
-                let tz = start.trailing_zeros();
-                // `start` should never be 0 because of that's taken care of by
-                // the previous branch, but in case it is, do the right thing
-                // anyway.
-                if tz < 32 {
-                    (1 as usize) << tz
-                } else {
-                    0
-                }
-            };
-
-            // Once we have a subregion size, we get a region size by
-            // multiplying it by the number of subregions per region.
-            let region_size = subregion_size * 8;
-            // Finally, we calculate the region base by finding the nearest
-            // address below `start` that aligns with the region size.
-            let region_start = start - (start % region_size);
-
-            if region_size + region_start - start < len {
-                // Sanity check that the amount left over space in the region
-                // after `start` is at least as large as the memory region we
-                // want to reference.
-                return None;
-            }
-            if len % subregion_size != 0 {
-                // Sanity check that there is some integer X such that
-                // subregion_size * X == len so none of `len` is left over when
-                // we take the max_subregion.
-                return None;
-            }
-
-            // The index of the first subregion to activate is the number of
-            // regions between `region_start` (MPU) and `start` (memory).
-            let min_subregion = (start - region_start) / subregion_size;
-            // The index of the last subregion to activate is the number of
-            // regions that fit in `len`, plus the `min_subregion`, minus one
-            // (because subregions are zero-indexed).
-            let max_subregion = min_subregion + len / subregion_size - 1;
-
-            let region_len = PowerOfTwo::floor(region_size as u32);
-            if region_len.exp::<u32>() < 7 {
-                // Subregions only supported for regions sizes 128 bytes and up.
-                return None;
-            } else if region_len.exp::<u32>() > 32 {
-                // Region sizes must be 4GB or smaller
-                return None;
-            }
-
-            // Turn the min/max subregion into a bitfield where all bits are `1`
-            // except for the bits whose index lie within
-            // [min_subregion, max_subregion]
-            //
-            // Note: Rust ranges are minimum inclusive, maximum exclusive, hence
-            // max_subregion + 1.
-            let subregion_mask =
-                (min_subregion..(max_subregion + 1)).fold(!0, |res, i| res & !(1 << i)) & 0xff;
+            let layout = kernel::mpu::region_layout(start, len)?;
+            let region_start = layout.start;
+            let region_len = PowerOfTwo::floor(layout.size as u32);
+            let subregion_mask = layout.subregion_mask() as u32;
 
             let xn = execute as u32;
             let ap = access as u32;
@@ -266,10 +224,95 @@ impl kernel::mpu::MPU for MPU {
     }
 
     fn set_mpu(&self, region: Region) {
-        let regs = &*self.0;
+        let regs = &*self.registers;
 
         regs.region_base_address.set(region.base_address());
 
         regs.region_attributes_and_size.set(region.attributes());
     }
+
+    fn set_kernel_stack_guard(&self, start: usize, len: usize) -> bool {
+        let region = <MPU as kernel::mpu::MPU>::create_region(
+            0,
+            start,
+            len,
+            kernel::mpu::ExecutePermission::ExecutionNotPermitted,
+            kernel::mpu::AccessPermission::NoAccess,
+        );
+        match region {
+            Some(region) => {
+                self.stack_guard
+                    .set(Some((region.base_address(), region.attributes())));
+                unsafe {
+                    KERNEL_STACK_GUARD_END = start + len;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn enable_kernel_mpu(&self) {
+        let regs = &*self.registers;
+        regs.control.set(0b0);
+
+        self.stack_guard.get().map(|(base_address, attributes)| {
+            // The guard takes region 0, and the regions set up for the last
+            // process are cleared.
+            regs.region_base_address.set(base_address);
+            regs.region_attributes_and_size.set(attributes);
+            for region_num in 1..8 {
+                self.set_mpu(Region::empty(region_num));
+            }
+
+            // Privileged code can still access all other memory. The MPU is
+            // disabled in the HardFault handler, so it can use the guard as
+            // stack when reporting the overflow.
+            regs.control.set(0b101);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MPU;
+    use kernel::mpu::{AccessPermission, ExecutePermission, MPU as MpuTrait};
+
+    fn region(start: usize, len: usize) -> Option<(u32, u32)> {
+        <MPU as MpuTrait>::create_region(
+            1,
+            start,
+            len,
+            ExecutePermission::ExecutionNotPermitted,
+            AccessPermission::ReadWrite,
+        )
+        .map(|region| (region.base_address(), region.attributes()))
+    }
+
+    #[test]
+    fn aligned_power_of_two() {
+        let (base, attributes) = region(0x2000_0100, 256).unwrap();
+        assert_eq!(base, 0x2000_0100 | 1 << 4 | 1);
+        // SIZE is log2(len) - 1 and no subregion is disabled.
+        assert_eq!((attributes >> 1) & 0x1f, 7);
+        assert_eq!((attributes >> 8) & 0xff, 0);
+    }
+
+    #[test]
+    fn non_power_of_two_aligned_to_its_length() {
+        // 0x20000040 is a multiple of 192 but not of 128, so a whole region
+        // would also cover the 64 bytes below the memory.
+        let (base, attributes) = region(0x2000_0040, 192).unwrap();
+        // A 256 byte region at 0x20000000 with only subregions 2 to 7, the
+        // 192 bytes from 0x20000040, enabled.
+        assert_eq!(base, 0x2000_0000 | 1 << 4 | 1);
+        assert_eq!((attributes >> 1) & 0x1f, 7);
+        assert_eq!((attributes >> 8) & 0xff, 0b0000_0011);
+    }
+
+    #[test]
+    fn uncoverable() {
+        assert_eq!(region(0x2000_0040, 0), None);
+        assert_eq!(region(0x2000_0004, 100), None);
+    }
 }
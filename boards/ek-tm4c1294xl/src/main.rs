@@ -164,19 +164,19 @@ pub unsafe fn reset_handler() {
 
     // set GPIO driver controlling remaining GPIO pins
     let gpio_pins = static_init!(
-        [&'static tm4c129x::gpio::GPIOPin; 4],
+        [Option<&'static tm4c129x::gpio::GPIOPin>; 4],
         [
-            &tm4c129x::gpio::PM[3],
-            &tm4c129x::gpio::PH[2],
-            &tm4c129x::gpio::PC[6],
-            &tm4c129x::gpio::PC[7],
+            Some(&tm4c129x::gpio::PM[3]),
+            Some(&tm4c129x::gpio::PH[2]),
+            Some(&tm4c129x::gpio::PC[6]),
+            Some(&tm4c129x::gpio::PC[7]),
         ]
     );
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, tm4c129x::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
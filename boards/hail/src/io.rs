@@ -1,3 +1,4 @@
+use capsules;
 use core::fmt::*;
 use core::panic::PanicInfo;
 use core::str;
@@ -41,6 +42,8 @@ impl Write for Writer {
 #[no_mangle]
 #[panic_implementation]
 pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
+    capsules::boot_info::record_panic(&sam4l::pm::PM);
+
     // turn off the non panic leds, just in case
     let led_green = &sam4l::gpio::PA[14];
     led_green.enable_output();
@@ -21,6 +21,7 @@ use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use kernel::hil;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::Controller;
+use kernel::mpu::MPU;
 use kernel::Platform;
 
 /// Support routines for debugging I/O.
@@ -57,6 +58,18 @@ static mut PROCESSES: [Option<&'static mut kernel::procs::Process<'static>>; NUM
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
 
+// Sensors that may be fitted, by their bit in the sensor inventory. Some Hail
+// revisions do not populate all of them.
+const SI7021_SENSOR: usize = 0;
+const ISL29035_SENSOR: usize = 1;
+const FXOS8700CQ_SENSOR: usize = 2;
+
+static SENSOR_PROBES: [capsules::sensor_probe::Probe; 3] = [
+    capsules::si7021::PROBE,
+    capsules::isl29035::PROBE,
+    capsules::fxos8700cq::PROBE,
+];
+
 /// A structure representing this platform that holds references to all
 /// capsules for this platform.
 struct Hail {
@@ -70,6 +83,7 @@ struct Hail {
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     humidity: &'static capsules::humidity::HumiditySensor<'static>,
+    sensor_probe: &'static capsules::sensor_probe::SensorProbe<'static>,
     spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
     nrf51822: &'static capsules::nrf51822_serialization::Nrf51822Serialization<
         'static,
@@ -82,6 +96,13 @@ struct Hail {
     ipc: kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     dac: &'static capsules::dac::Dac<'static>,
+    boot_info: &'static capsules::boot_info::BootInfo<'static, sam4l::pm::PowerManager>,
+    kernel_info: &'static capsules::kernel_info::KernelInfo,
+    device_id: &'static capsules::device_id::DeviceIdentity<'static, sam4l::serial_num::SerialNum>,
+    bootloader_attributes: &'static capsules::bootloader_attributes::BootloaderAttributes<
+        'static,
+        sam4l::flashcalw::FLASHCALW,
+    >,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -97,13 +118,23 @@ impl Platform for Hail {
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::spi::DRIVER_NUM => f(Some(self.spi)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
-            capsules::ambient_light::DRIVER_NUM => f(Some(self.ambient_light)),
+            capsules::ambient_light::DRIVER_NUM
+                if self.sensor_probe.is_present(ISL29035_SENSOR) =>
+            {
+                f(Some(self.ambient_light))
+            }
             capsules::adc::DRIVER_NUM => f(Some(self.adc)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
-            capsules::humidity::DRIVER_NUM => f(Some(self.humidity)),
-            capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
-            capsules::ninedof::DRIVER_NUM => f(Some(self.ninedof)),
+            capsules::humidity::DRIVER_NUM if self.sensor_probe.is_present(SI7021_SENSOR) => {
+                f(Some(self.humidity))
+            }
+            capsules::temperature::DRIVER_NUM if self.sensor_probe.is_present(SI7021_SENSOR) => {
+                f(Some(self.temp))
+            }
+            capsules::ninedof::DRIVER_NUM if self.sensor_probe.is_present(FXOS8700CQ_SENSOR) => {
+                f(Some(self.ninedof))
+            }
 
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
 
@@ -111,6 +142,11 @@ impl Platform for Hail {
 
             capsules::dac::DRIVER_NUM => f(Some(self.dac)),
 
+            capsules::boot_info::DRIVER_NUM => f(Some(self.boot_info)),
+            capsules::kernel_info::DRIVER_NUM => f(Some(self.kernel_info)),
+            capsules::device_id::DRIVER_NUM => f(Some(self.device_id)),
+            capsules::bootloader_attributes::DRIVER_NUM => f(Some(self.bootloader_attributes)),
+
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -191,6 +227,45 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Capture the reset reason before anything else can reset the chip
+    let boot_info = static_init!(
+        capsules::boot_info::BootInfo<'static, sam4l::pm::PowerManager>,
+        capsules::boot_info::BootInfo::new(&sam4l::pm::PM)
+    );
+    boot_info.initialize();
+
+    let device_id = static_init!(
+        capsules::device_id::DeviceIdentity<'static, sam4l::serial_num::SerialNum>,
+        capsules::device_id::DeviceIdentity::new(
+            &sam4l::serial_num::SERIAL_NUM,
+            kernel::Grant::create()
+        )
+    );
+
+    let kernel_info = static_init!(
+        capsules::kernel_info::KernelInfo,
+        capsules::kernel_info::KernelInfo::new(
+            capsules::kernel_info::FEATURE_IPC,
+            kernel::Grant::create()
+        )
+    );
+
+    // Apps can read, but not change, the attributes set by the bootloader.
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    pub static mut FLASH_PAGEBUFFER: sam4l::flashcalw::Sam4lPage =
+        sam4l::flashcalw::Sam4lPage::new();
+    let bootloader_attributes = static_init!(
+        capsules::bootloader_attributes::BootloaderAttributes<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::bootloader_attributes::BootloaderAttributes::new(
+            &sam4l::flashcalw::FLASH_CONTROLLER,
+            &mut FLASH_PAGEBUFFER,
+            capsules::bootloader_attributes::ATTRIBUTES_ADDRESS,
+            false,
+            kernel::Grant::create()
+        )
+    );
+    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, bootloader_attributes);
+
     // Configure kernel debug gpios as early as possible
     kernel::debug::assign_gpios(
         Some(&sam4l::gpio::PA[13]),
@@ -200,6 +275,10 @@ pub unsafe fn reset_handler() {
 
     let mut chip = sam4l::chip::Sam4l::new();
 
+    // Fault on kernel stack overflow rather than overwriting the memory below.
+    chip.mpu
+        .set_kernel_stack_guard(STACK_MEMORY.as_ptr() as usize, 256);
+
     let console = static_init!(
         capsules::console::Console<sam4l::usart::USART>,
         capsules::console::Console::new(
@@ -231,10 +310,25 @@ pub unsafe fn reset_handler() {
         MuxAlarm::new(&sam4l::ast::AST)
     );
     ast.configure(mux_alarm);
+    kernel::deadline::set_source(mux_alarm);
 
     let sensors_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C1));
     sam4l::i2c::I2C1.set_master_client(sensors_i2c);
 
+    // Only expose the drivers of the sensors this board has.
+    let sensor_probe_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0));
+    let sensor_probe = static_init!(
+        capsules::sensor_probe::SensorProbe<'static>,
+        capsules::sensor_probe::SensorProbe::new(
+            sensor_probe_i2c,
+            &SENSOR_PROBES,
+            &mut capsules::sensor_probe::BUFFER
+        )
+    );
+    sensor_probe_i2c.set_client(sensor_probe);
+    kernel_info.set_inventory(sensor_probe);
+    sensor_probe.start();
+
     // SI7021 Temperature / Humidity Sensor, address: 0x40
     let si7021_i2c = static_init!(
         capsules::virtual_i2c::I2CDevice,
@@ -301,6 +395,17 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
+    let yield_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let yield_timer = static_init!(
+        capsules::yield_timer::YieldTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::yield_timer::YieldTimer::new(yield_virtual_alarm)
+    );
+    yield_virtual_alarm.set_client(yield_timer);
+    kernel::set_yield_timer(yield_timer);
+
     // FXOS8700CQ accelerometer, device address 0x1e
     let fxos8700_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0x1e));
     let fxos8700 = static_init!(
@@ -340,7 +445,7 @@ pub unsafe fn reset_handler() {
     // Create the SPI system call capsule, passing the client
     let spi_syscalls = static_init!(
         capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
-        capsules::spi::Spi::new(syscall_spi_device)
+        capsules::spi::Spi::new(syscall_spi_device, kernel::Grant::create())
     );
 
     spi_syscalls.config_buffers(&mut SPI_READ_BUF, &mut SPI_WRITE_BUF);
@@ -408,6 +513,7 @@ pub unsafe fn reset_handler() {
         )
     );
     sam4l::adc::ADC0.set_client(adc);
+    adc.set_reference_voltages(3_300_000, 0);
 
     // Setup RNG
     let rng = static_init!(
@@ -418,19 +524,19 @@ pub unsafe fn reset_handler() {
 
     // set GPIO driver controlling remaining GPIO pins
     let gpio_pins = static_init!(
-        [&'static sam4l::gpio::GPIOPin; 4],
+        [Option<&'static sam4l::gpio::GPIOPin>; 4],
         [
-            &sam4l::gpio::PB[14], // D0
-            &sam4l::gpio::PB[15], // D1
-            &sam4l::gpio::PB[11], // D6
-            &sam4l::gpio::PB[12],
+            Some(&sam4l::gpio::PB[14]), // D0
+            Some(&sam4l::gpio::PB[15]), // D1
+            Some(&sam4l::gpio::PB[11]), // D6
+            Some(&sam4l::gpio::PB[12]),
         ]
     ); // D7
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
@@ -454,6 +560,7 @@ pub unsafe fn reset_handler() {
         ambient_light: ambient_light,
         temp: temp,
         humidity: humidity,
+        sensor_probe: sensor_probe,
         ninedof: ninedof,
         spi: spi_syscalls,
         nrf51822: nrf_serialization,
@@ -464,6 +571,10 @@ pub unsafe fn reset_handler() {
         ipc: kernel::ipc::IPC::new(),
         crc: crc,
         dac: dac,
+        boot_info: boot_info,
+        kernel_info: kernel_info,
+        device_id: device_id,
+        bootloader_attributes: bootloader_attributes,
     };
 
     // Need to reset the nRF on boot
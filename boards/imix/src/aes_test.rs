@@ -3,39 +3,48 @@ use capsules::test::aes::TestAes128Ctr;
 use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE};
 use sam4l::aes::{Aes, AES};
 
+// Each test can only be set up once. If it already was, it is still using
+// its buffers, so running it again does nothing.
+
 pub unsafe fn run_aes128_ctr() {
-    let t = static_init_test_ctr();
+    let t = match static_init_test_ctr() {
+        Some(t) => t,
+        None => return,
+    };
     AES.set_client(t);
 
     t.run();
 }
 
 pub unsafe fn run_aes128_cbc() {
-    let t = static_init_test_cbc();
+    let t = match static_init_test_cbc() {
+        Some(t) => t,
+        None => return,
+    };
     AES.set_client(t);
 
     t.run();
 }
 
-unsafe fn static_init_test_ctr() -> &'static mut TestAes128Ctr<'static, Aes<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-    let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+unsafe fn static_init_test_ctr() -> Option<&'static mut TestAes128Ctr<'static, Aes<'static>>> {
+    let source = try_static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE])?;
+    let data = try_static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE])?;
+    let key = try_static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE])?;
+    let iv = try_static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE])?;
 
-    static_init!(
+    try_static_init!(
         TestAes128Ctr<'static, Aes>,
         TestAes128Ctr::new(&AES, key, iv, source, data)
     )
 }
 
-unsafe fn static_init_test_cbc() -> &'static mut TestAes128Cbc<'static, Aes<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-    let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+unsafe fn static_init_test_cbc() -> Option<&'static mut TestAes128Cbc<'static, Aes<'static>>> {
+    let source = try_static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE])?;
+    let data = try_static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE])?;
+    let key = try_static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE])?;
+    let iv = try_static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE])?;
 
-    static_init!(
+    try_static_init!(
         TestAes128Cbc<'static, Aes>,
         TestAes128Cbc::new(&AES, key, iv, source, data)
     )
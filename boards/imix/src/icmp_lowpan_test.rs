@@ -34,6 +34,7 @@ use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
 use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_router::IP6Router;
 use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
 use capsules::net::sixlowpan::sixlowpan_compression;
 use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
@@ -111,9 +112,14 @@ pub unsafe fn initialize_all(
     );
     radio_mac.set_transmit_client(ip6_sender);
 
+    // Send every packet over the radio
+    let ip6_router = static_init!(IP6Router<'static>, IP6Router::new());
+    let lowpan_interface = ip6_router.add_interface(ip6_sender).unwrap();
+    ip6_router.add_route(IPAddr::new(), 0, lowpan_interface);
+
     let icmp_send_struct = static_init!(
-        ICMP6SendStruct<'static, IP6SendStruct<'static>>,
-        ICMP6SendStruct::new(ip6_sender)
+        ICMP6SendStruct<'static, IP6Router<'static>>,
+        ICMP6SendStruct::new(ip6_router)
     );
 
     let app_lowpan_frag_test = static_init!(
@@ -126,7 +132,7 @@ pub unsafe fn initialize_all(
         )
     );
 
-    ip6_sender.set_client(icmp_send_struct);
+    ip6_router.set_client(icmp_send_struct);
     icmp_send_struct.set_client(app_lowpan_frag_test);
     app_lowpan_frag_test.alarm.set_client(app_lowpan_frag_test);
 
@@ -1,3 +1,4 @@
+use capsules;
 use core::fmt::*;
 use core::panic::PanicInfo;
 use cortexm4;
@@ -40,6 +41,8 @@ impl Write for Writer {
 #[no_mangle]
 #[panic_implementation]
 pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
+    capsules::boot_info::record_panic(&sam4l::pm::PM);
+
     let led = &mut led::LedLow::new(&mut sam4l::gpio::PC[10]);
     let writer = &mut WRITER;
     debug::panic(&mut [led], writer, pi, &cortexm4::support::nop)
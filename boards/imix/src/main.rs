@@ -10,7 +10,7 @@
 
 extern crate capsules;
 #[allow(unused_imports)]
-#[macro_use(debug, debug_gpio, static_init)]
+#[macro_use(debug, debug_gpio, static_init, try_static_init)]
 extern crate kernel;
 extern crate cortexm4;
 extern crate sam4l;
@@ -18,7 +18,7 @@ extern crate sam4l;
 use capsules::alarm::AlarmDriver;
 use capsules::ieee802154::device::MacDevice;
 use capsules::ieee802154::mac::{AwakeMac, Mac};
-use capsules::rf233::RF233;
+use capsules::rf233::{RF233Part, RF233};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
@@ -29,6 +29,7 @@ use kernel::hil::spi::SpiMaster;
 use kernel::hil::symmetric_encryption;
 use kernel::hil::symmetric_encryption::{AES128, AES128CCM};
 use kernel::hil::Controller;
+use kernel::mpu::MPU;
 
 /// Support routines for debugging I/O.
 ///
@@ -64,6 +65,9 @@ const NUM_PROCS: usize = 2;
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
+// Whether to print where the memory allocated with `static_init!` went.
+const DEBUG_STATIC_MEMORY: bool = false;
+
 #[link_section = ".app_memory"]
 static mut APP_MEMORY: [u8; 16384] = [0; 16384];
 
@@ -103,6 +107,9 @@ struct Imix {
         sam4l::usart::USART,
     >,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    boot_info: &'static capsules::boot_info::BootInfo<'static, sam4l::pm::PowerManager>,
+    kernel_info: &'static capsules::kernel_info::KernelInfo,
+    device_id: &'static capsules::device_id::DeviceIdentity<'static, sam4l::serial_num::SerialNum>,
 }
 
 // The RF233 radio stack requires our buffers for its SPI operations:
@@ -151,6 +158,9 @@ impl kernel::Platform for Imix {
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::boot_info::DRIVER_NUM => f(Some(self.boot_info)),
+            capsules::kernel_info::DRIVER_NUM => f(Some(self.kernel_info)),
+            capsules::device_id::DRIVER_NUM => f(Some(self.device_id)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -244,6 +254,29 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Capture the reset reason before anything else can reset the chip
+    let boot_info = static_init!(
+        capsules::boot_info::BootInfo<'static, sam4l::pm::PowerManager>,
+        capsules::boot_info::BootInfo::new(&sam4l::pm::PM)
+    );
+    boot_info.initialize();
+
+    let device_id = static_init!(
+        capsules::device_id::DeviceIdentity<'static, sam4l::serial_num::SerialNum>,
+        capsules::device_id::DeviceIdentity::new(
+            &sam4l::serial_num::SERIAL_NUM,
+            kernel::Grant::create()
+        )
+    );
+
+    let kernel_info = static_init!(
+        capsules::kernel_info::KernelInfo,
+        capsules::kernel_info::KernelInfo::new(
+            capsules::kernel_info::FEATURE_IPC,
+            kernel::Grant::create()
+        )
+    );
+
     power::configure_submodules(power::SubmoduleConfig {
         rf233: true,
         nrf51422: true,
@@ -291,6 +324,7 @@ pub unsafe fn reset_handler() {
         MuxAlarm::new(&sam4l::ast::AST)
     );
     ast.configure(mux_alarm);
+    kernel::deadline::set_source(mux_alarm);
 
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
@@ -302,6 +336,17 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
+    let yield_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let yield_timer = static_init!(
+        capsules::yield_timer::YieldTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::yield_timer::YieldTimer::new(yield_virtual_alarm)
+    );
+    yield_virtual_alarm.set_client(yield_timer);
+    kernel::set_yield_timer(yield_timer);
+
     // # I2C Sensors
 
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2));
@@ -348,7 +393,7 @@ pub unsafe fn reset_handler() {
     // Create the SPI systemc call capsule, passing the client
     let spi_syscalls = static_init!(
         capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
-        capsules::spi::Spi::new(syscall_spi_device)
+        capsules::spi::Spi::new(syscall_spi_device, kernel::Grant::create())
     );
 
     // System call capsule requires static buffers so it can
@@ -394,9 +439,10 @@ pub unsafe fn reset_handler() {
             &sam4l::gpio::PA[09], // reset
             &sam4l::gpio::PA[10], // sleep
             &sam4l::gpio::PA[08], // irq
-            &sam4l::gpio::PA[08]
+            &sam4l::gpio::PA[08], //  irq_ctl
+            RF233Part::new()
         )
-    ); //  irq_ctl
+    );
     sam4l::gpio::PA[08].set_client(rf233);
 
     // FXOS8700CQ accelerometer, device address 0x1e
@@ -444,19 +490,20 @@ pub unsafe fn reset_handler() {
         )
     );
     sam4l::adc::ADC0.set_client(adc);
+    adc.set_reference_voltages(3_300_000, 0);
 
     // # GPIO
     // set GPIO driver controlling remaining GPIO pins
     let gpio_pins = static_init!(
-        [&'static sam4l::gpio::GPIOPin; 7],
+        [Option<&'static sam4l::gpio::GPIOPin>; 7],
         [
-            &sam4l::gpio::PC[31], // P2
-            &sam4l::gpio::PC[30], // P3
-            &sam4l::gpio::PC[29], // P4
-            &sam4l::gpio::PC[28], // P5
-            &sam4l::gpio::PC[27], // P6
-            &sam4l::gpio::PC[26], // P7
-            &sam4l::gpio::PA[20], // P8
+            Some(&sam4l::gpio::PC[31]), // P2
+            Some(&sam4l::gpio::PC[30]), // P3
+            Some(&sam4l::gpio::PC[29]), // P4
+            Some(&sam4l::gpio::PC[28]), // P5
+            Some(&sam4l::gpio::PC[27]), // P6
+            Some(&sam4l::gpio::PC[26]), // P7
+            Some(&sam4l::gpio::PA[20]), // P8
         ]
     );
 
@@ -464,7 +511,7 @@ pub unsafe fn reset_handler() {
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
@@ -560,9 +607,12 @@ pub unsafe fn reset_handler() {
     mac_device.set_key_procedure(radio_driver);
     mac_device.set_device_procedure(radio_driver);
     radio_mac.set_transmit_client(radio_driver);
+    // Keep each app below a 10% duty cycle
+    radio_driver.set_tx_quota(&sam4l::ast::AST, 3125, 1024);
     radio_mac.set_receive_client(radio_driver);
     radio_mac.set_pan(0xABCD);
     radio_mac.set_address(0x1008);
+    radio_mac.set_address_long(device_id.eui64());
 
     // Configure the USB controller
     let usb_client = static_init!(
@@ -624,10 +674,17 @@ pub unsafe fn reset_handler() {
         usb_driver: usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
+        boot_info: boot_info,
+        kernel_info: kernel_info,
+        device_id: device_id,
     };
 
     let mut chip = sam4l::chip::Sam4l::new();
 
+    // Fault on kernel stack overflow rather than overwriting the memory below.
+    chip.mpu
+        .set_kernel_stack_guard(STACK_MEMORY.as_ptr() as usize, 256);
+
     // Need to reset the nRF on boot, toggle it's SWDIO
     sam4l::gpio::PB[07].enable();
     sam4l::gpio::PB[07].enable_output();
@@ -645,6 +702,17 @@ pub unsafe fn reset_handler() {
     rf233.reset();
     rf233.start();
 
+    if DEBUG_STATIC_MEMORY {
+        kernel::common::static_memory::for_each(|allocation| {
+            debug!("{:6} {}", allocation.size, allocation.name);
+        });
+        debug!(
+            "{:6} bytes in {} allocations",
+            kernel::common::static_memory::total(),
+            kernel::common::static_memory::count()
+        );
+    }
+
     debug!("Initialization complete. Entering main loop");
     extern "C" {
         /// Beginning of the ROM region containing app images.
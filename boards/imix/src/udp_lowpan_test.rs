@@ -31,6 +31,7 @@ extern crate sam4l;
 use capsules::ieee802154::device::MacDevice;
 use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_router::IP6Router;
 use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
 use capsules::net::sixlowpan::sixlowpan_compression;
 use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
@@ -127,9 +128,14 @@ pub unsafe fn initialize_all(
     );
     radio_mac.set_transmit_client(ip6_sender);
 
+    // Send every packet over the radio
+    let ip6_router = static_init!(IP6Router<'static>, IP6Router::new());
+    let lowpan_interface = ip6_router.add_interface(ip6_sender).unwrap();
+    ip6_router.add_route(IPAddr::new(), 0, lowpan_interface);
+
     let udp_send_struct = static_init!(
-        UDPSendStruct<'static, IP6SendStruct<'static>>,
-        UDPSendStruct::new(ip6_sender)
+        UDPSendStruct<'static, IP6Router<'static>>,
+        UDPSendStruct::new(ip6_router)
     );
 
     let app_lowpan_frag_test = static_init!(
@@ -141,7 +147,7 @@ pub unsafe fn initialize_all(
             udp_send_struct
         )
     );
-    ip6_sender.set_client(udp_send_struct);
+    ip6_router.set_client(udp_send_struct);
     udp_send_struct.set_client(app_lowpan_frag_test);
     app_lowpan_frag_test.alarm.set_client(app_lowpan_frag_test);
 
@@ -145,37 +145,37 @@ pub unsafe fn reset_handler() {
 
     // Setup for remaining GPIO pins
     let gpio_pins = static_init!(
-        [&'static cc26xx::gpio::GPIOPin; 22],
+        [Option<&'static cc26xx::gpio::GPIOPin>; 22],
         [
-            &cc26xx::gpio::PORT[1],
-            &cc26xx::gpio::PORT[5],
-            &cc26xx::gpio::PORT[8],
-            &cc26xx::gpio::PORT[9],
-            &cc26xx::gpio::PORT[10],
-            &cc26xx::gpio::PORT[11],
-            &cc26xx::gpio::PORT[12],
-            &cc26xx::gpio::PORT[15],
-            &cc26xx::gpio::PORT[16],
-            &cc26xx::gpio::PORT[17],
-            &cc26xx::gpio::PORT[18],
-            &cc26xx::gpio::PORT[19],
-            &cc26xx::gpio::PORT[20],
-            &cc26xx::gpio::PORT[21],
-            &cc26xx::gpio::PORT[22],
-            &cc26xx::gpio::PORT[23],
-            &cc26xx::gpio::PORT[24],
-            &cc26xx::gpio::PORT[25],
-            &cc26xx::gpio::PORT[26],
-            &cc26xx::gpio::PORT[27],
-            &cc26xx::gpio::PORT[30],
-            &cc26xx::gpio::PORT[31],
+            Some(&cc26xx::gpio::PORT[1]),
+            Some(&cc26xx::gpio::PORT[5]),
+            Some(&cc26xx::gpio::PORT[8]),
+            Some(&cc26xx::gpio::PORT[9]),
+            Some(&cc26xx::gpio::PORT[10]),
+            Some(&cc26xx::gpio::PORT[11]),
+            Some(&cc26xx::gpio::PORT[12]),
+            Some(&cc26xx::gpio::PORT[15]),
+            Some(&cc26xx::gpio::PORT[16]),
+            Some(&cc26xx::gpio::PORT[17]),
+            Some(&cc26xx::gpio::PORT[18]),
+            Some(&cc26xx::gpio::PORT[19]),
+            Some(&cc26xx::gpio::PORT[20]),
+            Some(&cc26xx::gpio::PORT[21]),
+            Some(&cc26xx::gpio::PORT[22]),
+            Some(&cc26xx::gpio::PORT[23]),
+            Some(&cc26xx::gpio::PORT[24]),
+            Some(&cc26xx::gpio::PORT[25]),
+            Some(&cc26xx::gpio::PORT[26]),
+            Some(&cc26xx::gpio::PORT[27]),
+            Some(&cc26xx::gpio::PORT[30]),
+            Some(&cc26xx::gpio::PORT[31]),
         ]
     );
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, cc26xx::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
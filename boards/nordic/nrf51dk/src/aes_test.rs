@@ -2,8 +2,12 @@ use capsules::test::aes::TestAes128Ctr;
 use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE};
 use nrf5x::aes::{AesECB, AESECB};
 
+/// Does nothing if the test has already been set up.
 pub fn run() {
-    let t = static_init_test();
+    let t = match static_init_test() {
+        Some(t) => t,
+        None => return,
+    };
 
     unsafe {
         AESECB.set_client(t);
@@ -12,14 +16,14 @@ pub fn run() {
     t.run();
 }
 
-fn static_init_test() -> &'static mut TestAes128Ctr<'static, AesECB<'static>> {
+fn static_init_test() -> Option<&'static mut TestAes128Ctr<'static, AesECB<'static>>> {
     unsafe {
-        let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-        let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-        let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-        let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+        let source = try_static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE])?;
+        let data = try_static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE])?;
+        let key = try_static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE])?;
+        let iv = try_static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE])?;
 
-        static_init!(
+        try_static_init!(
             TestAes128Ctr<'static, AesECB>,
             TestAes128Ctr::new(&AESECB, key, iv, source, data)
         )
@@ -49,7 +49,7 @@
 
 extern crate capsules;
 #[allow(unused_imports)]
-#[macro_use(debug, debug_verbose, debug_gpio, static_init)]
+#[macro_use(debug, debug_verbose, debug_gpio, static_init, try_static_init)]
 extern crate kernel;
 extern crate cortexm0;
 extern crate nrf51;
@@ -198,19 +198,19 @@ pub unsafe fn reset_handler() {
     }
 
     let gpio_pins = static_init!(
-        [&'static nrf5x::gpio::GPIOPin; 11],
+        [Option<&'static nrf5x::gpio::GPIOPin>; 11],
         [
-            &nrf5x::gpio::PORT[1],  // Bottom left header on DK board
-            &nrf5x::gpio::PORT[2],  //   |
-            &nrf5x::gpio::PORT[3],  //   V
-            &nrf5x::gpio::PORT[4],  //
-            &nrf5x::gpio::PORT[5],  //
-            &nrf5x::gpio::PORT[6],  // -----
-            &nrf5x::gpio::PORT[16], //
-            &nrf5x::gpio::PORT[15], //
-            &nrf5x::gpio::PORT[14], //
-            &nrf5x::gpio::PORT[13], //
-            &nrf5x::gpio::PORT[12], //
+            Some(&nrf5x::gpio::PORT[1]),  // Bottom left header on DK board
+            Some(&nrf5x::gpio::PORT[2]),  //   |
+            Some(&nrf5x::gpio::PORT[3]),  //   V
+            Some(&nrf5x::gpio::PORT[4]),  //
+            Some(&nrf5x::gpio::PORT[5]),  //
+            Some(&nrf5x::gpio::PORT[6]),  // -----
+            Some(&nrf5x::gpio::PORT[16]), //
+            Some(&nrf5x::gpio::PORT[15]), //
+            Some(&nrf5x::gpio::PORT[14]), //
+            Some(&nrf5x::gpio::PORT[13]), //
+            Some(&nrf5x::gpio::PORT[12]), //
         ]
     );
 
@@ -225,7 +225,7 @@ pub unsafe fn reset_handler() {
         capsules::gpio::GPIO<'static, nrf5x::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
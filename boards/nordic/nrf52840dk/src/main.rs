@@ -60,21 +60,21 @@ pub unsafe fn reset_handler() {
 
     // GPIOs
     let gpio_pins = static_init!(
-        [&'static nrf5x::gpio::GPIOPin; 13],
+        [Option<&'static nrf5x::gpio::GPIOPin>; 13],
         [
-            &nrf5x::gpio::PORT[3], // Bottom right header on DK board
-            &nrf5x::gpio::PORT[4],
-            &nrf5x::gpio::PORT[28],
-            &nrf5x::gpio::PORT[29],
-            &nrf5x::gpio::PORT[30],
-            &nrf5x::gpio::PORT[10], // Top right header on DK board
-            &nrf5x::gpio::PORT[9],
-            &nrf5x::gpio::PORT[8],
-            &nrf5x::gpio::PORT[7],
-            &nrf5x::gpio::PORT[6],
-            &nrf5x::gpio::PORT[5],
-            &nrf5x::gpio::PORT[1],
-            &nrf5x::gpio::PORT[0],
+            Some(&nrf5x::gpio::PORT[3]), // Bottom right header on DK board
+            Some(&nrf5x::gpio::PORT[4]),
+            Some(&nrf5x::gpio::PORT[28]),
+            Some(&nrf5x::gpio::PORT[29]),
+            Some(&nrf5x::gpio::PORT[30]),
+            Some(&nrf5x::gpio::PORT[10]), // Top right header on DK board
+            Some(&nrf5x::gpio::PORT[9]),
+            Some(&nrf5x::gpio::PORT[8]),
+            Some(&nrf5x::gpio::PORT[7]),
+            Some(&nrf5x::gpio::PORT[6]),
+            Some(&nrf5x::gpio::PORT[5]),
+            Some(&nrf5x::gpio::PORT[1]),
+            Some(&nrf5x::gpio::PORT[0]),
         ]
     );
 
@@ -1,3 +1,4 @@
+use capsules;
 use core::fmt::Write;
 use core::panic::PanicInfo;
 use cortexm4;
@@ -40,6 +41,8 @@ impl Write for Writer {
 #[panic_implementation]
 /// Panic handler
 pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
+    capsules::boot_info::record_panic(&nrf52::power::POWER);
+
     // The nRF52 DK LEDs (see back of board)
     const LED1_PIN: usize = 17;
     let led = &mut led::LedLow::new(&mut nrf5x::gpio::PORT[LED1_PIN]);
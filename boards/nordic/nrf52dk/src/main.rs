@@ -67,7 +67,7 @@
 
 extern crate capsules;
 #[allow(unused_imports)]
-#[macro_use(debug, debug_verbose, debug_gpio, static_init)]
+#[macro_use(debug, debug_verbose, debug_gpio, static_init, try_static_init)]
 extern crate kernel;
 extern crate cortexm4;
 extern crate nrf52;
@@ -123,23 +123,23 @@ pub unsafe fn reset_handler() {
 
     // GPIOs
     let gpio_pins = static_init!(
-        [&'static nrf5x::gpio::GPIOPin; 15],
+        [Option<&'static nrf5x::gpio::GPIOPin>; 15],
         [
-            &nrf5x::gpio::PORT[3], // Bottom right header on DK board
-            &nrf5x::gpio::PORT[4],
-            &nrf5x::gpio::PORT[28],
-            &nrf5x::gpio::PORT[29],
-            &nrf5x::gpio::PORT[30],
-            &nrf5x::gpio::PORT[31], // -----
-            &nrf5x::gpio::PORT[12], // Top mid header on DK board
-            &nrf5x::gpio::PORT[11], // -----
-            &nrf5x::gpio::PORT[27], // Top left header on DK board
-            &nrf5x::gpio::PORT[26],
-            &nrf5x::gpio::PORT[2],
-            &nrf5x::gpio::PORT[25],
-            &nrf5x::gpio::PORT[24],
-            &nrf5x::gpio::PORT[23],
-            &nrf5x::gpio::PORT[22], // -----
+            Some(&nrf5x::gpio::PORT[3]), // Bottom right header on DK board
+            Some(&nrf5x::gpio::PORT[4]),
+            Some(&nrf5x::gpio::PORT[28]),
+            Some(&nrf5x::gpio::PORT[29]),
+            Some(&nrf5x::gpio::PORT[30]),
+            Some(&nrf5x::gpio::PORT[31]), // -----
+            Some(&nrf5x::gpio::PORT[12]), // Top mid header on DK board
+            Some(&nrf5x::gpio::PORT[11]), // -----
+            Some(&nrf5x::gpio::PORT[27]), // Top left header on DK board
+            Some(&nrf5x::gpio::PORT[26]),
+            Some(&nrf5x::gpio::PORT[2]),
+            Some(&nrf5x::gpio::PORT[25]),
+            Some(&nrf5x::gpio::PORT[24]),
+            Some(&nrf5x::gpio::PORT[23]),
+            Some(&nrf5x::gpio::PORT[22]), // -----
         ]
     );
 
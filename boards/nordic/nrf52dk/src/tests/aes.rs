@@ -9,19 +9,23 @@ use nrf5x::aes::{AesECB, AESECB};
 ///     aes::run();
 /// ```
 ///
+/// Running the tests again once they have been set up does nothing.
 pub unsafe fn run() {
-    let t = static_init_test();
+    let t = match static_init_test() {
+        Some(t) => t,
+        None => return,
+    };
     AESECB.set_client(t);
     t.run();
 }
 
-unsafe fn static_init_test() -> &'static mut TestAes128Ctr<'static, AesECB<'static>> {
-    let source = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
-    let data = static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE]);
-    let key = static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE]);
-    let iv = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+unsafe fn static_init_test() -> Option<&'static mut TestAes128Ctr<'static, AesECB<'static>>> {
+    let source = try_static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE])?;
+    let data = try_static_init!([u8; 6 * AES128_BLOCK_SIZE], [0; 6 * AES128_BLOCK_SIZE])?;
+    let key = try_static_init!([u8; AES128_KEY_SIZE], [0; AES128_KEY_SIZE])?;
+    let iv = try_static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE])?;
 
-    static_init!(
+    try_static_init!(
         TestAes128Ctr<'static, AesECB>,
         TestAes128Ctr::new(&AESECB, key, iv, source, data)
     )
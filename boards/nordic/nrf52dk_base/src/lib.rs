@@ -19,6 +19,13 @@ pub struct Platform {
         nrf52::radio::Radio,
         VirtualMuxAlarm<'static, Rtc>,
     >,
+    esb: &'static capsules::esb::EsbDriver<
+        'static,
+        nrf52::radio::Radio,
+        VirtualMuxAlarm<'static, Rtc>,
+    >,
+    boot_info: &'static capsules::boot_info::BootInfo<'static, nrf52::power::Power>,
+    device_id: &'static capsules::device_id::DeviceIdentity<'static, nrf52::ficr::Ficr>,
     button: &'static capsules::button::Button<'static, nrf5x::gpio::GPIOPin>,
     console: &'static capsules::console::Console<'static, nrf52::uart::Uarte>,
     gpio: &'static capsules::gpio::GPIO<'static, nrf5x::gpio::GPIOPin>,
@@ -45,7 +52,10 @@ impl kernel::Platform for Platform {
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
+            capsules::esb::DRIVER_NUM => f(Some(self.esb)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
+            capsules::boot_info::DRIVER_NUM => f(Some(self.boot_info)),
+            capsules::device_id::DRIVER_NUM => f(Some(self.device_id)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -55,7 +65,7 @@ impl kernel::Platform for Platform {
 /// Generic function for starting an nrf52dk board.
 pub unsafe fn setup_board(
     button_rst_pin: usize,
-    gpio_pins: &'static mut [&'static nrf5x::gpio::GPIOPin],
+    gpio_pins: &'static mut [Option<&'static nrf5x::gpio::GPIOPin>],
     debug_pin1_index: usize,
     debug_pin2_index: usize,
     debug_pin3_index: usize,
@@ -76,6 +86,21 @@ pub unsafe fn setup_board(
     while !nrf52::nvmc::NVMC.is_ready() {}
     uicr.set_psel1_reset_pin(button_rst_pin);
 
+    // Capture the reset reason before anything else can reset the chip
+    let boot_info = static_init!(
+        capsules::boot_info::BootInfo<'static, nrf52::power::Power>,
+        capsules::boot_info::BootInfo::new(&nrf52::power::POWER)
+    );
+    boot_info.initialize();
+
+    let device_id = static_init!(
+        capsules::device_id::DeviceIdentity<'static, nrf52::ficr::Ficr>,
+        capsules::device_id::DeviceIdentity::new(
+            &nrf52::ficr::FICR_INSTANCE,
+            kernel::Grant::create()
+        )
+    );
+
     // Configure kernel debug gpios as early as possible
     kernel::debug::assign_gpios(
         Some(&nrf5x::gpio::PORT[debug_pin1_index]),
@@ -87,7 +112,7 @@ pub unsafe fn setup_board(
         capsules::gpio::GPIO<'static, nrf5x::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins)
     );
-    for pin in gpio_pins.iter() {
+    for pin in gpio_pins.iter().filter_map(|pin| *pin) {
         pin.set_client(gpio);
     }
 
@@ -128,6 +153,17 @@ pub unsafe fn setup_board(
         capsules::alarm::AlarmDriver::new(virtual_alarm1, kernel::Grant::create())
     );
     virtual_alarm1.set_client(alarm);
+
+    let yield_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let yield_timer = static_init!(
+        capsules::yield_timer::YieldTimer<'static, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::yield_timer::YieldTimer::new(yield_virtual_alarm)
+    );
+    yield_virtual_alarm.set_client(yield_timer);
+    kernel::set_yield_timer(yield_timer);
     let ble_radio_virtual_alarm = static_init!(
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
         capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
@@ -179,6 +215,22 @@ pub unsafe fn setup_board(
     );
     ble_radio_virtual_alarm.set_client(ble_radio);
 
+    let esb_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let esb = static_init!(
+        capsules::esb::EsbDriver<'static, nrf52::radio::Radio, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::esb::EsbDriver::new(
+            &nrf52::radio::RADIO,
+            esb_virtual_alarm,
+            kernel::Grant::create()
+        )
+    );
+    kernel::hil::esb::Esb::set_transmit_client(&nrf52::radio::RADIO, esb);
+    kernel::hil::esb::Esb::set_receive_client(&nrf52::radio::RADIO, esb);
+    esb_virtual_alarm.set_client(esb);
+
     let temp = static_init!(
         capsules::temperature::TemperatureSensor<'static>,
         capsules::temperature::TemperatureSensor::new(
@@ -207,8 +259,11 @@ pub unsafe fn setup_board(
     while !nrf52::clock::CLOCK.high_started() {}
 
     let platform = Platform {
+        boot_info: boot_info,
+        device_id: device_id,
         button: button,
         ble_radio: ble_radio,
+        esb: esb,
         console: console,
         led: led,
         gpio: gpio,
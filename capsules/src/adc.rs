@@ -27,7 +27,20 @@
 //!     )
 //! );
 //! sam4l::adc::ADC0.set_client(adc);
+//! adc.set_reference_voltages(3_300_000, 0);
 //! ```
+//!
+//! Calibration
+//! -----------
+//!
+//! Applications can select the ADC's reference and calibrate the offset and
+//! gain of samples against known inputs: a sample of an input at 0 V gives
+//! the offset, and then a sample of an input at a known voltage gives the
+//! gain. The kernel then converts raw samples to microvolts using the
+//! calibration and the full scale of the selected reference, so readings are
+//! consistent across chips. Recalibrating corrects for drift with
+//! temperature. The calibration is kept until the reference is changed, and
+//! can be read out and restored, for example after a reboot.
 
 use core::cell::Cell;
 use core::cmp;
@@ -40,7 +53,7 @@ pub const DRIVER_NUM: usize = 0x00000005;
 
 /// ADC application driver, used by applications to interact with ADC.
 /// Not currently virtualized, only one application can use it at a time.
-pub struct Adc<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> {
+pub struct Adc<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + hil::adc::AdcReference + 'a> {
     // ADC driver
     adc: &'a A,
     channels: &'a [&'a <A as hil::adc::Adc>::Channel],
@@ -59,6 +72,16 @@ pub struct Adc<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> {
     next_samples_outstanding: Cell<usize>,
     using_app_buf1: Cell<bool>,
 
+    // Calibration state
+    supply_uv: Cell<u32>,
+    external_uv: Cell<u32>,
+    /// Raw count of an input at 0 V.
+    offset: Cell<i32>,
+    /// Correction applied to counts above the offset, in 16.16 fixed point.
+    gain: Cell<u32>,
+    /// The voltage of the input a gain calibration is sampling.
+    calibration_uv: Cell<u32>,
+
     // ADC buffers
     adc_buf1: TakeCell<'static, [u16]>,
     adc_buf2: TakeCell<'static, [u16]>,
@@ -74,8 +97,13 @@ enum AdcMode {
     ContinuousSample = 1,
     SingleBuffer = 2,
     ContinuousBuffer = 3,
+    OffsetCalibration = 4,
+    GainCalibration = 5,
 }
 
+/// A gain of 1 in the 16.16 fixed point format of `Adc::gain`.
+const UNITY_GAIN: u32 = 1 << 16;
+
 /// Holds buffers that the application has passed us
 pub struct App {
     app_buf1: Option<AppSlice<Shared, u8>>,
@@ -100,7 +128,7 @@ pub static mut ADC_BUFFER2: [u16; 128] = [0; 128];
 pub static mut ADC_BUFFER3: [u16; 128] = [0; 128];
 
 /// Functions to create, initialize, and interact with the ADC
-impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Adc<'a, A> {
+impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + hil::adc::AdcReference + 'a> Adc<'a, A> {
     /// Create a new Adc application interface
     ///
     /// adc - ADC driver to provide application access to
@@ -133,6 +161,13 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Adc<'a, A> {
             next_samples_outstanding: Cell::new(0),
             using_app_buf1: Cell::new(true),
 
+            // Calibration state
+            supply_uv: Cell::new(0),
+            external_uv: Cell::new(0),
+            offset: Cell::new(0),
+            gain: Cell::new(UNITY_GAIN),
+            calibration_uv: Cell::new(0),
+
             // ADC buffers
             adc_buf1: TakeCell::new(adc_buf1),
             adc_buf2: TakeCell::new(adc_buf2),
@@ -140,6 +175,17 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Adc<'a, A> {
         }
     }
 
+    /// Tell the driver the voltages of the references that the chip cannot
+    /// know, for converting samples to microvolts
+    ///
+    /// supply_uv - supply voltage in microvolts
+    /// external_uv - voltage on the external reference pin in microvolts, or
+    ///               0 if there is none
+    pub fn set_reference_voltages(&self, supply_uv: u32, external_uv: u32) {
+        self.supply_uv.set(supply_uv);
+        self.external_uv.set(external_uv);
+    }
+
     /// Store a buffer we've regained ownership of and return a handle to it
     /// The handle can have `map` called on it in order to process the data in
     /// the buffer
@@ -429,6 +475,82 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Adc<'a, A> {
         ReturnCode::SUCCESS
     }
 
+    /// Select the reference for later samples, clearing the calibration
+    ///
+    /// reference - 0 for the internal reference, 1 for the supply, 2 for the
+    ///             external reference pin
+    fn set_reference(&self, reference: usize) -> ReturnCode {
+        let reference = match reference {
+            0 => hil::adc::Reference::Internal,
+            1 => hil::adc::Reference::Supply,
+            2 => hil::adc::Reference::External,
+            _ => return ReturnCode::EINVAL,
+        };
+        let res = self.adc.set_reference(reference);
+        if res == ReturnCode::SUCCESS {
+            self.offset.set(0);
+            self.gain.set(UNITY_GAIN);
+        }
+        res
+    }
+
+    /// The input voltage in microvolts of a full-scale sample
+    fn full_scale_uv(&self) -> u32 {
+        let reference_uv = match self.adc.get_reference() {
+            hil::adc::Reference::Internal => 0,
+            hil::adc::Reference::Supply => self.supply_uv.get(),
+            hil::adc::Reference::External => self.external_uv.get(),
+        };
+        self.adc.get_full_scale_uv(reference_uv)
+    }
+
+    /// Convert a raw sample to microvolts using the calibration
+    ///
+    /// sample - raw ADC count
+    fn to_microvolts(&self, sample: usize) -> u32 {
+        let counts = cmp::max(sample as i64 - self.offset.get() as i64, 0) as u64;
+        let corrected = (counts * self.gain.get() as u64) >> 16;
+        ((corrected * self.full_scale_uv() as u64) >> self.adc.get_resolution_bits()) as u32
+    }
+
+    /// Take a sample to calibrate the offset or the gain
+    ///
+    /// channel - index into `channels` array, which channel to sample
+    /// mode - which calibration to perform
+    /// input_uv - the voltage of the input for a gain calibration
+    fn calibrate(&self, channel: usize, mode: AdcMode, input_uv: u32) -> ReturnCode {
+        if mode == AdcMode::GainCalibration && input_uv == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.calibration_uv.set(input_uv);
+        let res = self.sample(channel);
+        if res == ReturnCode::SUCCESS {
+            self.mode.set(mode);
+        }
+        res
+    }
+
+    /// Update the calibration with a sample taken for it, and return the new
+    /// offset or gain
+    ///
+    /// sample - raw ADC count of the calibration input
+    fn calibration_done(&self, mode: AdcMode, sample: u16) -> usize {
+        if mode == AdcMode::OffsetCalibration {
+            self.offset.set(sample as i32);
+            return sample as usize;
+        }
+
+        // The gain is left unchanged if the sample is not above the offset
+        let measured = sample as i64 - self.offset.get() as i64;
+        let full_scale = self.full_scale_uv() as u64;
+        if measured > 0 && full_scale > 0 {
+            let expected =
+                ((self.calibration_uv.get() as u64) << self.adc.get_resolution_bits()) / full_scale;
+            self.gain.set(((expected << 16) / measured as u64) as u32);
+        }
+        self.gain.get() as usize
+    }
+
     /// Stops sampling the ADC
     /// Any active operation by the ADC is canceled. No additional callbacks
     /// will occur. Also retrieves buffers from the ADC (if any)
@@ -466,7 +588,9 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Adc<'a, A> {
 }
 
 /// Callbacks from the ADC driver
-impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> hil::adc::Client for Adc<'a, A> {
+impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + hil::adc::AdcReference + 'a> hil::adc::Client
+    for Adc<'a, A>
+{
     /// Single sample operation complete
     /// Collects the sample and provides a callback to the application
     ///
@@ -496,6 +620,20 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> hil::adc::Client for Ad
                     sample as usize,
                 );
             });
+        } else if self.active.get()
+            && (self.mode.get() == AdcMode::OffsetCalibration
+                || self.mode.get() == AdcMode::GainCalibration)
+        {
+            // calibration sample complete, clean up state
+            let mode = self.mode.get();
+            self.active.set(false);
+            self.mode.set(AdcMode::NoMode);
+
+            // perform callback with the new offset or gain
+            let value = self.calibration_done(mode, sample);
+            self.callback.get().map(|mut callback| {
+                callback.schedule(mode as usize, self.channel.get(), value);
+            });
         } else {
             // operation probably canceled. Make sure state is consistent. No
             // callback
@@ -506,7 +644,9 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> hil::adc::Client for Ad
 }
 
 /// Callbacks from the High Speed ADC driver
-impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> hil::adc::HighSpeedClient for Adc<'a, A> {
+impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + hil::adc::AdcReference + 'a>
+    hil::adc::HighSpeedClient for Adc<'a, A>
+{
     /// Internal buffer has filled from a buffered sampling operation.
     /// Copies data over to application buffer, determines if more data is
     /// needed, and performs a callback to the application if ready. If
@@ -763,7 +903,9 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> hil::adc::HighSpeedClie
 }
 
 /// Implementations of application syscalls
-impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Driver for Adc<'a, A> {
+impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + hil::adc::AdcReference + 'a> Driver
+    for Adc<'a, A>
+{
     /// Provides access to a buffer from the application to store data in or
     /// read data from
     ///
@@ -812,17 +954,34 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Driver for Adc<'a, A> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // subscribe to ADC sample done (from all types of sampling)
             0 => {
                 // set callback
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
+                Ok(self.callback.replace(callback))
             }
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// The ADC serves the process whose callback it holds. If that process
+    /// has terminated, stop sampling for it and drop its callback and
+    /// buffers.
+    fn app_terminated(&self, app_id: AppId) {
+        let serving = self
+            .callback
+            .get()
+            .map_or(false, |callback| callback.app_id() == app_id);
+        if serving {
+            self.stop_sampling();
+            self.callback.set(None);
+            self.app.map(|state| {
+                state.app_buf1 = None;
+                state.app_buf2 = None;
+            });
         }
     }
 
@@ -831,6 +990,22 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Driver for Adc<'a, A> {
     /// command_num - which command call this is
     /// data - value sent by the application, varying uses
     /// _appid - application identifier, unused
+    ///
+    /// Commands 6 and later select the reference and calibrate samples:
+    ///
+    /// - 6: select reference `data` (0 internal, 1 supply, 2 external),
+    ///      clearing the calibration
+    /// - 7: get the reference
+    /// - 8: sample channel `data`, which must be at 0 V, to calibrate the
+    ///      offset. The callback's first argument is 4 and its third the
+    ///      offset
+    /// - 9: sample channel `data`, which must be at `data2` microvolts, to
+    ///      calibrate the gain. The callback's first argument is 5 and its
+    ///      third the gain
+    /// - 10: restore a calibration: offset `data` and gain `data2`
+    /// - 11: get the offset
+    /// - 12: get the gain, in 16.16 fixed point
+    /// - 13: convert the raw sample `data` to microvolts
     fn command(
         &self,
         command_num: usize,
@@ -838,6 +1013,9 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Driver for Adc<'a, A> {
         frequency: usize,
         _appid: AppId,
     ) -> ReturnCode {
+        // arguments of the calibration commands
+        let data = channel;
+        let data2 = frequency;
         match command_num {
             // check if present
             0 => ReturnCode::SuccessWithValue {
@@ -859,6 +1037,46 @@ impl<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed + 'a> Driver for Adc<'a, A> {
             // Stop sampling
             5 => self.stop_sampling(),
 
+            // Select reference
+            6 => self.set_reference(data),
+
+            // Get reference
+            7 => ReturnCode::SuccessWithValue {
+                value: match self.adc.get_reference() {
+                    hil::adc::Reference::Internal => 0,
+                    hil::adc::Reference::Supply => 1,
+                    hil::adc::Reference::External => 2,
+                },
+            },
+
+            // Calibrate offset
+            8 => self.calibrate(data, AdcMode::OffsetCalibration, 0),
+
+            // Calibrate gain
+            9 => self.calibrate(data, AdcMode::GainCalibration, data2 as u32),
+
+            // Restore calibration
+            10 => {
+                self.offset.set(data as i32);
+                self.gain.set(data2 as u32);
+                ReturnCode::SUCCESS
+            }
+
+            // Get offset
+            11 => ReturnCode::SuccessWithValue {
+                value: self.offset.get() as usize,
+            },
+
+            // Get gain
+            12 => ReturnCode::SuccessWithValue {
+                value: self.gain.get() as usize,
+            },
+
+            // Convert sample to microvolts
+            13 => ReturnCode::SuccessWithValue {
+                value: self.to_microvolts(data) as usize,
+            },
+
             // default
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -0,0 +1,516 @@
+//! Provides userspace applications with AES-128 encryption and decryption.
+//!
+//! Applications never see key material. The board provides a table of keys,
+//! and applications refer to a key by its handle, its index in that table.
+//! An operation is started with a key, a mode (CTR, CBC or CCM) and a
+//! direction, and then data is processed in chunks, each of which fits the
+//! allowed buffer. The driver carries the counter or chaining value from one
+//! chunk to the next, so a message can be much larger than the buffer.
+//!
+//! CCM operates on a whole message at once: each chunk is a separate message,
+//! with its own nonce, and the tag is appended to (or checked against the bytes
+//! after) the message.
+//!
+//! Each key has a policy for the IVs and nonces it may be used with when
+//! encrypting, which the driver enforces:
+//!
+//! - `NoncePolicy::Any` places no restriction.
+//! - `NoncePolicy::Unique` rejects a nonce used in any of the last
+//!   `RECENT_NONCES` encryptions with the key.
+//! - `NoncePolicy::Increasing` requires each nonce, read as a big-endian
+//!   number, to be greater than the previous one.
+//!
+//! Only one operation can be in progress at a time; others are refused with
+//! `EBUSY` until it is finished.
+//!
+//! Usage
+//! -----
+//!
+//! The driver shares the AES hardware with a CCM instance that is used only
+//! by it.
+//!
+//! ```rust
+//! static mut AES_BUF: [u8; 128] = [0; 128];
+//! static mut AES_CCM_BUF: [u8; 3 * 16 + 128] = [0; 3 * 16 + 128];
+//!
+//! let keys = static_init!(
+//!     [capsules::aes::KeySlot; 1],
+//!     [capsules::aes::KeySlot::new(KEY, capsules::aes::NoncePolicy::Unique)]
+//! );
+//! let ccm = static_init!(
+//!     capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
+//!     capsules::aes_ccm::AES128CCM::new(&sam4l::aes::AES, &mut AES_CCM_BUF)
+//! );
+//! let aes = static_init!(
+//!     capsules::aes::AesDriver<
+//!         sam4l::aes::Aes<'static>,
+//!         capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
+//!     >,
+//!     capsules::aes::AesDriver::new(
+//!         &sam4l::aes::AES,
+//!         ccm,
+//!         keys,
+//!         &mut AES_BUF,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! sam4l::aes::AES.set_client(aes);
+//! ccm.set_client(aes);
+//! sam4l::aes::AES.enable();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+    CCM_NONCE_LENGTH,
+};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x40000;
+
+/// The number of nonces remembered for keys with `NoncePolicy::Unique`.
+pub const RECENT_NONCES: usize = 4;
+
+/// Restrictions on the IVs and nonces a key encrypts with.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum NoncePolicy {
+    Any,
+    Unique,
+    Increasing,
+}
+
+/// A key and the state needed to enforce its nonce policy.
+pub struct KeySlot {
+    key: [u8; AES128_KEY_SIZE],
+    policy: NoncePolicy,
+    recent: Cell<[Option<[u8; AES128_BLOCK_SIZE]>; RECENT_NONCES]>,
+    next_recent: Cell<usize>,
+}
+
+impl KeySlot {
+    pub fn new(key: [u8; AES128_KEY_SIZE], policy: NoncePolicy) -> KeySlot {
+        KeySlot {
+            key: key,
+            policy: policy,
+            recent: Cell::new([None; RECENT_NONCES]),
+            next_recent: Cell::new(0),
+        }
+    }
+
+    /// Record `nonce` as used for encryption. Returns false, without
+    /// recording it, if the key's policy does not allow it.
+    fn use_nonce(&self, nonce: &[u8; AES128_BLOCK_SIZE]) -> bool {
+        let mut recent = self.recent.get();
+        let latest = (self.next_recent.get() + RECENT_NONCES - 1) % RECENT_NONCES;
+        let allowed = match self.policy {
+            NoncePolicy::Any => true,
+            NoncePolicy::Unique => !recent.iter().any(|n| *n == Some(*nonce)),
+            NoncePolicy::Increasing => recent[latest].map_or(true, |prev| *nonce > prev),
+        };
+        if allowed {
+            recent[self.next_recent.get()] = Some(*nonce);
+            self.recent.set(recent);
+            self.next_recent
+                .set((self.next_recent.get() + 1) % RECENT_NONCES);
+        }
+        allowed
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Ctr,
+    Cbc,
+    Ccm,
+}
+
+/// An operation started with command 1.
+#[derive(Copy, Clone)]
+struct Operation {
+    key: usize,
+    mode: Mode,
+    encrypting: bool,
+    mic_len: usize,
+    /// The counter or chaining value for the next chunk.
+    iv: [u8; AES128_BLOCK_SIZE],
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    data: Option<AppSlice<Shared, u8>>,
+    iv: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct AesDriver<A: 'static, C: 'static> {
+    aes: &'static A,
+    ccm: &'static C,
+    keys: &'static [KeySlot],
+    buffer: TakeCell<'static, [u8]>,
+    apps: Grant<App>,
+    /// The application whose operation is in progress, and the operation.
+    current: Cell<Option<(AppId, Operation)>>,
+    /// The length of the chunk being processed, if one is.
+    chunk_len: Cell<Option<usize>>,
+}
+
+impl<A, C> AesDriver<A, C>
+where
+    A: AES128<'static> + AES128Ctr + AES128CBC,
+    C: AES128CCM<'static> + symmetric_encryption::Client<'static>,
+{
+    pub fn new(
+        aes: &'static A,
+        ccm: &'static C,
+        keys: &'static [KeySlot],
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> AesDriver<A, C> {
+        AesDriver {
+            aes: aes,
+            ccm: ccm,
+            keys: keys,
+            buffer: TakeCell::new(buffer),
+            apps: grant,
+            current: Cell::new(None),
+            chunk_len: Cell::new(None),
+        }
+    }
+
+    /// Start an operation for `appid` with the key `key`. `config` holds the
+    /// mode in bits 0-7, the direction in bit 8 and, for CCM, the tag length
+    /// in bits 16-23.
+    fn start(&self, key: usize, config: usize, appid: AppId) -> ReturnCode {
+        if self.current.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let mode = match config & 0xff {
+            0 => Mode::Ctr,
+            1 => Mode::Cbc,
+            2 => Mode::Ccm,
+            _ => return ReturnCode::EINVAL,
+        };
+        let encrypting = config & (1 << 8) != 0;
+        let mic_len = (config >> 16) & 0xff;
+        let slot = match self.keys.get(key) {
+            Some(slot) => slot,
+            None => return ReturnCode::EINVAL,
+        };
+        if mode == Mode::Ccm && ![4, 6, 8, 10, 12, 14, 16].contains(&mic_len) {
+            return ReturnCode::EINVAL;
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                if app.callback.is_none() || app.data.is_none() {
+                    return ReturnCode::EINVAL;
+                }
+                let iv = match read_iv(&app.iv, mode) {
+                    Some(iv) => iv,
+                    None => return ReturnCode::ESIZE,
+                };
+                // CCM nonces are checked for each message instead.
+                if encrypting && mode != Mode::Ccm && !slot.use_nonce(&iv) {
+                    return ReturnCode::EALREADY;
+                }
+                self.current.set(Some((
+                    appid,
+                    Operation {
+                        key: key,
+                        mode: mode,
+                        encrypting: encrypting,
+                        mic_len: mic_len,
+                        iv: iv,
+                    },
+                )));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Process the next chunk of the operation of `appid`: the first `len`
+    /// bytes of its data buffer for CTR and CBC, or for CCM a message of
+    /// `len` bytes following `a_len` bytes of authenticated data.
+    fn crypt(&self, len: usize, a_len: usize, appid: AppId) -> ReturnCode {
+        let (owner, op) = match self.current.get() {
+            Some(current) => current,
+            None => return ReturnCode::EOFF,
+        };
+        if owner != appid || self.chunk_len.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+        let slot = &self.keys[op.key];
+        let total = match op.mode {
+            Mode::Ccm => a_len + len + op.mic_len,
+            _ => len,
+        };
+
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                let data = match app.data {
+                    Some(ref data) => data,
+                    None => return Err(ReturnCode::EINVAL),
+                };
+                if total > data.len() || total > buffer.len() {
+                    return Err(ReturnCode::ESIZE);
+                }
+                if op.mode != Mode::Ccm && len % AES128_BLOCK_SIZE != 0 {
+                    return Err(ReturnCode::EINVAL);
+                }
+                if op.mode == Mode::Ccm {
+                    // Each CCM message has its own nonce.
+                    let nonce = match read_iv(&app.iv, Mode::Ccm) {
+                        Some(nonce) => nonce,
+                        None => return Err(ReturnCode::ESIZE),
+                    };
+                    if op.encrypting && !slot.use_nonce(&nonce) {
+                        return Err(ReturnCode::EALREADY);
+                    }
+                    self.ccm.set_nonce(&nonce[..CCM_NONCE_LENGTH]);
+                }
+                buffer[..total].copy_from_slice(&data.as_ref()[..total]);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if let Err(err) = res {
+            self.buffer.replace(buffer);
+            return err;
+        }
+
+        match op.mode {
+            Mode::Ccm => {
+                self.ccm.set_key(&slot.key);
+                match self
+                    .ccm
+                    .crypt(buffer, 0, a_len, len, op.mic_len, true, op.encrypting)
+                {
+                    (ReturnCode::SUCCESS, _) => {
+                        self.chunk_len.set(Some(total));
+                        ReturnCode::SUCCESS
+                    }
+                    (err, buffer) => {
+                        buffer.map(|buffer| self.buffer.replace(buffer));
+                        err
+                    }
+                }
+            }
+            Mode::Ctr | Mode::Cbc => {
+                self.aes.set_key(&slot.key);
+                self.aes.set_iv(&op.iv);
+                if op.mode == Mode::Ctr {
+                    self.aes.set_mode_aes128ctr(op.encrypting);
+                } else {
+                    self.aes.set_mode_aes128cbc(op.encrypting);
+                }
+                if op.mode == Mode::Cbc && !op.encrypting && len > 0 {
+                    // The next chunk chains from the last ciphertext block,
+                    // which is about to be overwritten.
+                    let mut next = op;
+                    next.iv
+                        .copy_from_slice(&buffer[len - AES128_BLOCK_SIZE..len]);
+                    self.current.set(Some((appid, next)));
+                }
+                self.aes.start_message();
+                match self.aes.crypt(None, buffer, 0, len) {
+                    None => {
+                        self.chunk_len.set(Some(len));
+                        ReturnCode::SUCCESS
+                    }
+                    Some((err, _, buffer)) => {
+                        self.current.set(Some((appid, op)));
+                        self.buffer.replace(buffer);
+                        err
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finish the operation of `appid`.
+    fn finish(&self, appid: AppId) -> ReturnCode {
+        match self.current.get() {
+            Some((owner, _)) if owner == appid => {
+                if self.chunk_len.get().is_some() {
+                    ReturnCode::EBUSY
+                } else {
+                    self.current.set(None);
+                    ReturnCode::SUCCESS
+                }
+            }
+            Some(_) => ReturnCode::EBUSY,
+            None => ReturnCode::EALREADY,
+        }
+    }
+
+    /// Copy the processed chunk back to the application and tell it.
+    fn chunk_done(&self, buffer: &'static mut [u8], res: ReturnCode, tag_valid: bool) {
+        let len = self.chunk_len.take().unwrap_or(0);
+        if let Some((appid, mut op)) = self.current.get() {
+            match op.mode {
+                Mode::Ctr => increment_counter(&mut op.iv, len / AES128_BLOCK_SIZE),
+                Mode::Cbc if op.encrypting && len > 0 => {
+                    op.iv.copy_from_slice(&buffer[len - AES128_BLOCK_SIZE..len]);
+                }
+                _ => {}
+            }
+            self.current.set(Some((appid, op)));
+
+            let _ = self.apps.enter(appid, |app, _| {
+                if let Some(ref mut data) = app.data {
+                    let n = cmp::min(len, data.len());
+                    data.as_mut()[..n].copy_from_slice(&buffer[..n]);
+                }
+                app.callback
+                    .map(|mut cb| cb.schedule(From::from(res), len, tag_valid as usize));
+            });
+        }
+        self.buffer.replace(buffer);
+    }
+}
+
+/// The IV or nonce the application has allowed, padded to a block.
+fn read_iv(iv: &Option<AppSlice<Shared, u8>>, mode: Mode) -> Option<[u8; AES128_BLOCK_SIZE]> {
+    let len = match mode {
+        Mode::Ccm => CCM_NONCE_LENGTH,
+        _ => AES128_BLOCK_SIZE,
+    };
+    iv.as_ref().and_then(|iv| {
+        if iv.len() < len {
+            None
+        } else {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            block[..len].copy_from_slice(&iv.as_ref()[..len]);
+            Some(block)
+        }
+    })
+}
+
+/// Add `blocks` to the big-endian counter `counter`.
+fn increment_counter(counter: &mut [u8; AES128_BLOCK_SIZE], blocks: usize) {
+    let mut carry = blocks as u64;
+    for byte in counter.iter_mut().rev() {
+        if carry == 0 {
+            break;
+        }
+        let sum = *byte as u64 + (carry & 0xff);
+        *byte = sum as u8;
+        carry = (carry >> 8) + (sum >> 8);
+    }
+}
+
+impl<A, C> symmetric_encryption::Client<'static> for AesDriver<A, C>
+where
+    A: AES128<'static> + AES128Ctr + AES128CBC,
+    C: AES128CCM<'static> + symmetric_encryption::Client<'static>,
+{
+    fn crypt_done(&'static self, source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        match self.current.get() {
+            // The CCM instance is running its own passes over the hardware.
+            Some((_, ref op)) if op.mode == Mode::Ccm => self.ccm.crypt_done(source, dest),
+            _ => self.chunk_done(dest, ReturnCode::SUCCESS, false),
+        }
+    }
+}
+
+impl<A, C> symmetric_encryption::CCMClient for AesDriver<A, C>
+where
+    A: AES128<'static> + AES128Ctr + AES128CBC,
+    C: AES128CCM<'static> + symmetric_encryption::Client<'static>,
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.chunk_done(buf, res, tag_is_valid);
+    }
+}
+
+impl<A, C> Driver for AesDriver<A, C>
+where
+    A: AES128<'static> + AES128Ctr + AES128CBC,
+    C: AES128CCM<'static> + symmetric_encryption::Client<'static>,
+{
+    /// Share buffers with the driver.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The data to process, which is replaced by the result.
+    /// - `1`: The IV or initial counter (16 bytes), or for CCM the nonce (13
+    ///        bytes).
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.data = slice;
+                    } else {
+                        app.iv = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the completion of chunks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A chunk has been processed. The callback signature is
+    ///        `fn(status: ReturnCode, len: usize, tag_valid: bool)`, where
+    ///        `tag_valid` is only meaningful for CCM decryption.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check. Returns the number of keys.
+    /// - `1`: Start an operation with the key with handle `data1`. `data2`
+    ///        holds the mode (0 CTR, 1 CBC, 2 CCM) in bits 0-7, 1 to encrypt
+    ///        or 0 to decrypt in bit 8 and, for CCM, the tag length in bits
+    ///        16-23. The IV must already be allowed, and is checked against
+    ///        the key's nonce policy.
+    /// - `2`: Process a chunk. For CTR and CBC, the first `data1` bytes of
+    ///        the data buffer, a multiple of 16. For CCM, a message of
+    ///        `data1` bytes following `data2` bytes of authenticated data,
+    ///        with room for the tag after it, using the allowed nonce.
+    /// - `3`: Finish the operation.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.keys.len(),
+            },
+            1 => self.start(data1, data2, appid),
+            2 => self.crypt(data1, data2, appid),
+            3 => self.finish(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
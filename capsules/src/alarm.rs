@@ -1,28 +1,58 @@
 //! Provides userspace applications with a alarm API.
+//!
+//! Each application has `MAX_ALARMS` independent alarms, identified by an
+//! index. Commands 3 and 4 operate on alarm 0, while commands 5 and 6 take the
+//! index of the alarm to use. All of an application's alarms share one
+//! callback, which is passed the index of the alarm that expired.
+//!
+//! An alarm can be set for an absolute clock value or relative to now, and
+//! can be made periodic. A periodic alarm is re-armed by the kernel one period
+//! after the time it was due, not after the time it fired or the callback ran,
+//! so its expirations do not drift.
+//!
+//! If the board measures the rate of the alarm's clock, for example with a
+//! `ClockTrim`, it can pass the measurement to `set_measured_frequency()`, and
+//! applications will be given the measured frequency instead of the nominal
+//! one to convert between ticks and time.
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil::time::{self, Alarm, Frequency};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = 0x00000000;
 
+/// The number of alarms each application can have outstanding at once.
+pub const MAX_ALARMS: usize = 4;
+
 #[derive(Copy, Clone, Debug)]
 enum Expiration {
     Disabled,
     Abs(u32),
+    /// Expires at the first value and then every second value ticks.
+    Periodic(u32, u32),
+}
+
+impl Expiration {
+    fn time(&self) -> Option<u32> {
+        match *self {
+            Expiration::Disabled => None,
+            Expiration::Abs(exp) | Expiration::Periodic(exp, _) => Some(exp),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct AlarmData {
-    expiration: Expiration,
+    expirations: [Expiration; MAX_ALARMS],
     callback: Option<Callback>,
 }
 
 impl Default for AlarmData {
     fn default() -> AlarmData {
         AlarmData {
-            expiration: Expiration::Disabled,
+            expirations: [Expiration::Disabled; MAX_ALARMS],
             callback: None,
         }
     }
@@ -33,6 +63,7 @@ pub struct AlarmDriver<'a, A: Alarm + 'a> {
     num_armed: Cell<usize>,
     app_alarm: Grant<AlarmData>,
     prev: Cell<u32>,
+    measured_frequency: Cell<Option<&'a time::MeasuredFrequency>>,
 }
 
 impl<'a, A: Alarm> AlarmDriver<'a, A> {
@@ -42,23 +73,30 @@ impl<'a, A: Alarm> AlarmDriver<'a, A> {
             num_armed: Cell::new(0),
             app_alarm: grant,
             prev: Cell::new(0),
+            measured_frequency: Cell::new(None),
         }
     }
 
+    /// Report the frequency measured by `measured` to applications.
+    pub fn set_measured_frequency(&self, measured: &'a time::MeasuredFrequency) {
+        self.measured_frequency.set(Some(measured));
+    }
+
     fn reset_active_alarm(&self, now: u32) -> Option<u32> {
         self.prev.set(now);
         let mut next_alarm = u32::max_value();
         let mut next_dist = u32::max_value();
         for alarm in self.app_alarm.iter() {
-            alarm.enter(|alarm, _| match alarm.expiration {
-                Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+            alarm.enter(|alarm, _| {
+                for expiration in alarm.expirations.iter() {
+                    if let Some(exp) = expiration.time() {
+                        let t_dist = exp.wrapping_sub(now);
+                        if next_dist > t_dist {
+                            next_alarm = exp;
+                            next_dist = t_dist;
+                        }
                     }
                 }
-                Expiration::Disabled => {}
             });
         }
         if next_alarm != u32::max_value() {
@@ -68,6 +106,43 @@ impl<'a, A: Alarm> AlarmDriver<'a, A> {
             None
         }
     }
+
+    /// Arm `expiration` to fire at `time`.
+    fn set(&self, expiration: &mut Expiration, time: usize) -> (ReturnCode, bool) {
+        // if previously unarmed, but now will become armed
+        if let Expiration::Disabled = *expiration {
+            self.num_armed.set(self.num_armed.get() + 1);
+        }
+        *expiration = Expiration::Abs(time as u32);
+        (ReturnCode::SuccessWithValue { value: time }, true)
+    }
+
+    /// Make the armed `expiration` repeat every `period` ticks.
+    fn set_period(&self, expiration: &mut Expiration, period: usize) -> (ReturnCode, bool) {
+        match expiration.time() {
+            Some(exp) if period != 0 => {
+                *expiration = Expiration::Periodic(exp, period as u32);
+                (ReturnCode::SUCCESS, false)
+            }
+            Some(_) => (ReturnCode::EINVAL, false),
+            // Only an armed alarm has a time to start from
+            None => (ReturnCode::EOFF, false),
+        }
+    }
+
+    fn stop(&self, expiration: &mut Expiration) -> (ReturnCode, bool) {
+        match *expiration {
+            Expiration::Disabled => {
+                // Request to stop when already stopped
+                (ReturnCode::EALREADY, false)
+            }
+            Expiration::Abs(_) | Expiration::Periodic(..) => {
+                *expiration = Expiration::Disabled;
+                self.num_armed.set(self.num_armed.get() - 1);
+                (ReturnCode::SUCCESS, true)
+            }
+        }
+    }
 }
 
 impl<'a, A: Alarm> Driver for AlarmDriver<'a, A> {
@@ -81,25 +156,29 @@ impl<'a, A: Alarm> Driver for AlarmDriver<'a, A> {
         _subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         self.app_alarm
             .enter(app_id, |td, _allocator| {
-                td.callback = callback;
-                ReturnCode::SUCCESS
+                Ok(mem::replace(&mut td.callback, callback))
             })
-            .unwrap_or_else(|err| err.into())
+            .unwrap_or_else(|err| Err(err.into()))
     }
 
     /// Setup and read the alarm.
     ///
     /// ### `command_num`
     ///
-    /// - `0`: Driver check.
+    /// - `0`: Driver check. Returns the number of alarms per application.
     /// - `1`: Return the clock frequency in Hz.
     /// - `2`: Read the the current clock value
-    /// - `3`: Stop the alarm if it is outstanding
-    /// - `4`: Set an alarm to fire at a given clock value `time`.
-    fn command(&self, cmd_type: usize, data: usize, _: usize, caller_id: AppId) -> ReturnCode {
+    /// - `3`: Stop alarm 0 if it is outstanding
+    /// - `4`: Set alarm 0 to fire at a given clock value `time`.
+    /// - `5`: Set alarm `data2` to fire at a given clock value `time`.
+    /// - `6`: Stop alarm `data` if it is outstanding.
+    /// - `7`: Set alarm `data2` to fire `data` ticks from now.
+    /// - `8`: Make the outstanding alarm `data2` fire again every `data`
+    ///        ticks after its expiration.
+    fn command(&self, cmd_type: usize, data: usize, data2: usize, caller_id: AppId) -> ReturnCode {
         // Returns the error code to return to the user and whether we need to
         // reset which is the next active alarm. We only _don't_ reset if we're
         // disabling the underlying alarm anyway, if the underlying alarm is
@@ -109,9 +188,13 @@ impl<'a, A: Alarm> Driver for AlarmDriver<'a, A> {
             .enter(caller_id, |td, _alloc| {
                 let now = self.alarm.now();
                 let (return_code, reset) = match cmd_type {
-                    0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
+                    0 /* check if present */ => {
+                        (ReturnCode::SuccessWithValue { value: MAX_ALARMS }, false)
+                    },
                     1 /* Get clock frequency */ => {
-                        let freq = <A::Frequency>::frequency() as usize;
+                        let freq = self.measured_frequency.get().map_or(
+                            <A::Frequency>::frequency(),
+                            |measured| measured.measured_frequency()) as usize;
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
@@ -120,31 +203,41 @@ impl<'a, A: Alarm> Driver for AlarmDriver<'a, A> {
                     },
                     3 /* Stop */ => {
                         let alarm_id = data as u32;
-                        match td.expiration {
-                            Expiration::Disabled => {
-                                // Request to stop when already stopped
-                                (ReturnCode::EALREADY, false)
-                            },
-                            Expiration::Abs(exp) if exp != alarm_id => {
+                        match td.expirations[0].time() {
+                            Some(exp) if exp != alarm_id => {
                                 // Request to stop invalid alarm id
                                 (ReturnCode::EINVAL, false)
                             },
-                            _ => {
-                                td.expiration = Expiration::Disabled;
-                                let new_num_armed = self.num_armed.get() - 1;
-                                self.num_armed.set(new_num_armed);
-                                (ReturnCode::SUCCESS, true)
-                            }
+                            _ => self.stop(&mut td.expirations[0]),
                         }
                     },
                     4 /* Set absolute expiration */ => {
-                        let time = data;
-                        // if previously unarmed, but now will become armed
-                        if let Expiration::Disabled = td.expiration {
-                            self.num_armed.set(self.num_armed.get() + 1);
+                        self.set(&mut td.expirations[0], data)
+                    },
+                    5 /* Set absolute expiration of an alarm */ => {
+                        match td.expirations.get_mut(data2) {
+                            Some(expiration) => self.set(expiration, data),
+                            None => (ReturnCode::EINVAL, false),
+                        }
+                    },
+                    6 /* Stop an alarm */ => {
+                        match td.expirations.get_mut(data) {
+                            Some(expiration) => self.stop(expiration),
+                            None => (ReturnCode::EINVAL, false),
+                        }
+                    },
+                    7 /* Set relative expiration of an alarm */ => {
+                        let time = now.wrapping_add(data as u32) as usize;
+                        match td.expirations.get_mut(data2) {
+                            Some(expiration) => self.set(expiration, time),
+                            None => (ReturnCode::EINVAL, false),
+                        }
+                    },
+                    8 /* Make an alarm periodic */ => {
+                        match td.expirations.get_mut(data2) {
+                            Some(expiration) => self.set_period(expiration, data),
+                            None => (ReturnCode::EINVAL, false),
                         }
-                        td.expiration = Expiration::Abs(time as u32);
-                        (ReturnCode::SuccessWithValue { value: time }, true)
                     },
                     _ => (ReturnCode::ENOSUPPORT, false)
                 };
@@ -165,14 +258,27 @@ impl<'a, A: Alarm> time::Client for AlarmDriver<'a, A> {
     fn fired(&self) {
         let now = self.alarm.now();
         self.app_alarm.each(|alarm| {
-            if let Expiration::Abs(exp) = alarm.expiration {
-                let expired = has_expired(exp, now, self.prev.get());
-                if expired {
-                    alarm.expiration = Expiration::Disabled;
-                    self.num_armed.set(self.num_armed.get() - 1);
-                    alarm
-                        .callback
-                        .map(|mut cb| cb.schedule(now as usize, exp as usize, 0));
+            let callback = alarm.callback;
+            for (index, expiration) in alarm.expirations.iter_mut().enumerate() {
+                if let Some(exp) = expiration.time() {
+                    let expired = has_expired(exp, now, self.prev.get());
+                    if expired {
+                        match *expiration {
+                            Expiration::Periodic(_, period) => {
+                                // Re-arm relative to when the alarm was due,
+                                // skipping any periods that have already
+                                // passed.
+                                let periods = now.wrapping_sub(exp) / period + 1;
+                                let next = exp.wrapping_add(periods.wrapping_mul(period));
+                                *expiration = Expiration::Periodic(next, period);
+                            }
+                            _ => {
+                                *expiration = Expiration::Disabled;
+                                self.num_armed.set(self.num_armed.get() - 1);
+                            }
+                        }
+                        callback.map(|mut cb| cb.schedule(now as usize, exp as usize, index));
+                    }
                 }
             }
         });
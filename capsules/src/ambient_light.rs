@@ -11,6 +11,7 @@
 //! ```
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil;
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
@@ -69,16 +70,15 @@ impl<'a> Driver for AmbientLight<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 => self
                 .apps
                 .enter(app_id, |app, _| {
-                    app.callback = callback;
-                    ReturnCode::SUCCESS
+                    Ok(mem::replace(&mut app.callback, callback))
                 })
-                .unwrap_or_else(|err| err.into()),
-            _ => ReturnCode::ENOSUPPORT,
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
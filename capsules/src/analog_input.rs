@@ -0,0 +1,303 @@
+//! Provides userspace with the positions of analog sticks and dials.
+//!
+//! Joysticks, potentiometers and similar controls produce a voltage that an
+//! ADC channel can sample. `AnalogInput` samples each configured axis in
+//! turn every `interval_ms`, converts the raw sample to a position between
+//! `-FULL_SCALE` and `FULL_SCALE`, and tells applications when a position
+//! changes, so they do not need to poll the ADC themselves.
+//!
+//! Each axis is described by an `Axis`:
+//!
+//! - `center` is the sample at rest, for example the middle of the range for
+//!   a self-centering stick, or `0` for a dial that reports only positive
+//!   positions.
+//! - `dead_zone` is how far from `center` a sample may be and still count as
+//!   the rest position, to hide noise and sticks that do not center exactly.
+//! - `span` is how far from `center` the sample is at full deflection.
+//!
+//! Positions between the dead zone and full deflection are scaled linearly.
+//! A change is only reported once it is at least `MIN_CHANGE`, or when the
+//! axis reaches the rest position or full deflection, so noise does not
+//! cause a stream of callbacks.
+//!
+//! The capsule needs the ADC to itself, so a board cannot also give the
+//! same ADC to the raw ADC driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let axes = static_init!(
+//!     [capsules::analog_input::Axis<'static, sam4l::adc::AdcChannel>; 2],
+//!     [
+//!         capsules::analog_input::Axis {
+//!             channel: &sam4l::adc::CHANNEL_AD0,
+//!             center: 2048,
+//!             dead_zone: 100,
+//!             span: 2000,
+//!         },
+//!         capsules::analog_input::Axis {
+//!             channel: &sam4l::adc::CHANNEL_AD1,
+//!             center: 2048,
+//!             dead_zone: 100,
+//!             span: 2000,
+//!         },
+//!     ]
+//! );
+//!
+//! let analog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let analog_input = static_init!(
+//!     capsules::analog_input::AnalogInput<
+//!         'static,
+//!         sam4l::adc::Adc,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::analog_input::AnalogInput::new(
+//!         &sam4l::adc::ADC0,
+//!         analog_alarm,
+//!         axes,
+//!         20,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! sam4l::adc::ADC0.set_client(analog_input);
+//! analog_alarm.set_client(analog_input);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Subscribe
+//!
+//! - `0`: Callback when the position of an axis changes, with the index of
+//!   the axis and its new position as a signed 32-bit number.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: the number of axes.
+//! - `1`: Start reporting changes to this application. Sampling runs while
+//!   any application has started it.
+//! - `2`: Stop reporting changes to this application.
+//! - `3`: Get the last position of axis `data`, offset by `FULL_SCALE` so it
+//!   is never negative.
+//!   - Return: the position plus `FULL_SCALE`, or `EINVAL` if there is no
+//!     such axis.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::hil::adc;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60005;
+
+/// The position of an axis at full deflection.
+pub const FULL_SCALE: i32 = 1000;
+
+/// The smallest change in position that is reported.
+pub const MIN_CHANGE: i32 = 10;
+
+/// The most axes a board can configure.
+pub const MAX_AXES: usize = 8;
+
+/// How to sample and scale one axis.
+pub struct Axis<'a, C: 'a> {
+    pub channel: &'a C,
+    pub center: u16,
+    pub dead_zone: u16,
+    pub span: u16,
+}
+
+impl<'a, C> Axis<'a, C> {
+    /// Convert a raw sample to a position.
+    fn position(&self, sample: u16) -> i32 {
+        let offset = sample as i32 - self.center as i32;
+        let distance = offset.abs() - self.dead_zone as i32;
+        if distance <= 0 {
+            return 0;
+        }
+        let travel = cmp::max(self.span as i32 - self.dead_zone as i32, 1);
+        let position = cmp::min(distance * FULL_SCALE / travel, FULL_SCALE);
+        if offset < 0 {
+            -position
+        } else {
+            position
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    subscribed: bool,
+}
+
+pub struct AnalogInput<'a, A: adc::Adc + 'a, T: Alarm + 'a> {
+    adc: &'a A,
+    alarm: &'a T,
+    axes: &'a [Axis<'a, A::Channel>],
+    interval_ms: u32,
+    /// The last reported position of each axis.
+    positions: [Cell<i32>; MAX_AXES],
+    /// The axis being sampled.
+    index: Cell<usize>,
+    sampling: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: adc::Adc, T: Alarm> AnalogInput<'a, A, T> {
+    /// Sample `axes` every `interval_ms`. Only the first `MAX_AXES` are
+    /// used.
+    pub fn new(
+        adc: &'a A,
+        alarm: &'a T,
+        axes: &'a [Axis<'a, A::Channel>],
+        interval_ms: u32,
+        grant: Grant<App>,
+    ) -> AnalogInput<'a, A, T> {
+        let len = cmp::min(axes.len(), MAX_AXES);
+        AnalogInput {
+            adc: adc,
+            alarm: alarm,
+            axes: &axes[..len],
+            interval_ms: interval_ms,
+            positions: Default::default(),
+            index: Cell::new(0),
+            sampling: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn any_subscribed(&self) -> bool {
+        let mut subscribed = false;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| subscribed = subscribed || app.subscribed);
+        }
+        subscribed
+    }
+
+    /// Start sampling if an application wants reports and it is not already
+    /// running.
+    fn start(&self) {
+        if !self.sampling.get() && !self.axes.is_empty() {
+            self.sampling.set(true);
+            self.index.set(0);
+            self.sample_next();
+        }
+    }
+
+    fn sample_next(&self) {
+        let res = self.adc.sample(self.axes[self.index.get()].channel);
+        if res != ReturnCode::SUCCESS {
+            self.schedule();
+        }
+    }
+
+    /// Wait for the next round of samples, or stop if no application is
+    /// listening.
+    fn schedule(&self) {
+        self.index.set(0);
+        if !self.any_subscribed() {
+            self.sampling.set(false);
+            return;
+        }
+        let interval = self.interval_ms * <T::Frequency>::frequency() / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(interval));
+    }
+
+    fn report(&self, index: usize, position: i32) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.subscribed {
+                    app.callback
+                        .map(|mut cb| cb.schedule(index, position as usize, 0));
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> adc::Client for AnalogInput<'a, A, T> {
+    fn sample_ready(&self, sample: u16) {
+        let index = self.index.get();
+        let position = self.axes[index].position(sample);
+        let last = self.positions[index].get();
+        let at_limit = position == 0 || position.abs() == FULL_SCALE;
+        if (position - last).abs() >= MIN_CHANGE || (at_limit && position != last) {
+            self.positions[index].set(position);
+            self.report(index, position);
+        }
+
+        if index + 1 < self.axes.len() {
+            self.index.set(index + 1);
+            self.sample_next();
+        } else {
+            self.schedule();
+        }
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> time::Client for AnalogInput<'a, A, T> {
+    fn fired(&self) {
+        self.sample_next();
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> Driver for AnalogInput<'a, A, T> {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.axes.len(),
+            },
+
+            1 | 2 => {
+                let res = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.subscribed = command_num == 1;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if res == ReturnCode::SUCCESS && command_num == 1 {
+                    self.start();
+                }
+                res
+            }
+
+            3 => match self.axes.get(data) {
+                Some(_) => ReturnCode::SuccessWithValue {
+                    value: (self.positions[data].get() + FULL_SCALE) as usize,
+                },
+                None => ReturnCode::EINVAL,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
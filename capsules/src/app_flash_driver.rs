@@ -20,6 +20,7 @@
 //!         kernel::Grant::create(), &mut APP_FLASH_BUFFER));
 //! ```
 
+use core::mem;
 use core::cell::Cell;
 use core::cmp;
 use kernel::common::cells::TakeCell;
@@ -98,7 +99,12 @@ impl<'a> AppFlash<'a> {
                                     *c = d[i];
                                 }
 
-                                self.driver.write(buffer, flash_address, length)
+                                let (rc, buffer) = self.driver.write(buffer, flash_address, length);
+                                if rc != ReturnCode::SUCCESS {
+                                    buffer.map(|buffer| self.buffer.replace(buffer));
+                                    self.current_app.set(None);
+                                }
+                                rc
                             })
                         })
                 } else {
@@ -153,8 +159,12 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for AppFlash<'a> {
                                     *c = d[i];
                                 }
 
-                                self.driver.write(buffer, flash_address, length)
-                                    == ReturnCode::SUCCESS
+                                let (rc, buffer) = self.driver.write(buffer, flash_address, length);
+                                if rc != ReturnCode::SUCCESS {
+                                    buffer.map(|buffer| self.buffer.replace(buffer));
+                                    self.current_app.set(None);
+                                }
+                                rc == ReturnCode::SUCCESS
                             }
                         })
                     })
@@ -203,16 +213,13 @@ impl<'a> Driver for AppFlash<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 => self
                 .apps
-                .enter(app_id, |app, _| {
-                    app.callback = callback;
-                    ReturnCode::SUCCESS
-                })
-                .unwrap_or_else(|err| err.into()),
-            _ => ReturnCode::ENOSUPPORT,
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
@@ -0,0 +1,491 @@
+//! Driver for the Microchip ATECC608 secure element.
+//!
+//! <https://www.microchip.com/wwwproducts/en/ATECC608A>
+//!
+//! The ATECC608 stores up to 16 keys in slots that the MCU cannot read, and
+//! signs with them on its behalf, so a board's private keys never enter the
+//! MCU's memory. This driver provides ECDSA P-256 signing with a stored key
+//! and verification against a given public key through `hil::ecdsa`, and the
+//! chip's random number generator through `hil::rng`. Keys are written and
+//! the slots configured and locked when the chip is provisioned, not by this
+//! driver.
+//!
+//! The chip sleeps until the MCU wakes it by holding SDA low for at least
+//! 60 µs, which the driver does by writing to I2C address 0, so the bus must
+//! run at 100 kHz or slower. For each operation the driver wakes the chip,
+//! sends the commands, waits the longest time they can take to execute and
+//! reads their responses, then sends the chip to idle. The chip puts itself
+//! to sleep 1.3 s after waking, which every operation finishes well within.
+//!
+//! Every packet carries a CRC, and a response that fails it fails the
+//! operation.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let atecc608_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x60)
+//! );
+//! let atecc608_wake = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x00)
+//! );
+//! let atecc608_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let atecc608 = static_init!(
+//!     capsules::atecc608::Atecc608<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::atecc608::Atecc608::new(
+//!         atecc608_i2c,
+//!         atecc608_wake,
+//!         atecc608_alarm,
+//!         &mut capsules::atecc608::BUFFER
+//!     )
+//! );
+//! atecc608_i2c.set_client(atecc608);
+//! atecc608_wake.set_client(atecc608);
+//! atecc608_alarm.set_client(atecc608);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::ecdsa::{self, DIGEST_LEN, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use kernel::hil::i2c;
+use kernel::hil::rng;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Buffer for I2C packets, large enough for a `Verify` command.
+pub static mut BUFFER: [u8; 136] = [0; 136];
+
+/// The word address that starts each write.
+const WORD_ADDRESS_IDLE: u8 = 0x02;
+const WORD_ADDRESS_COMMAND: u8 = 0x03;
+
+/// The response to a wake: a status packet with status `0x11`.
+const WAKE_RESPONSE: [u8; 4] = [0x04, 0x11, 0x33, 0x43];
+
+/// Time from the wake pulse until the chip accepts commands, rounded up.
+const WAKE_DELAY_MS: u32 = 2;
+
+const OPCODE_NONCE: u8 = 0x16;
+const OPCODE_RANDOM: u8 = 0x1b;
+const OPCODE_SIGN: u8 = 0x41;
+const OPCODE_VERIFY: u8 = 0x45;
+
+/// `Nonce` mode that loads the given 32 bytes into TempKey unchanged.
+const NONCE_MODE_PASSTHROUGH: u8 = 0x03;
+/// `Sign` mode that signs the digest in TempKey.
+const SIGN_MODE_EXTERNAL: u8 = 0x80;
+/// `Verify` mode that checks against a public key given with the command.
+const VERIFY_MODE_EXTERNAL: u8 = 0x02;
+/// The key type of a NIST P-256 public key.
+const KEY_TYPE_P256: u16 = 0x0004;
+
+/// Status of a `Verify` whose signature does not match.
+const STATUS_MISCOMPARE: u8 = 0x01;
+
+/// The number of key slots.
+const NUM_SLOTS: usize = 16;
+
+/// The commands the driver sends.
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Nonce,
+    Random,
+    Sign,
+    Verify,
+}
+
+impl Command {
+    /// The longest the command takes to execute, from the datasheet.
+    fn execution_ms(&self) -> u32 {
+        match *self {
+            Command::Nonce => 7,
+            Command::Random => 23,
+            Command::Sign => 115,
+            Command::Verify => 105,
+        }
+    }
+
+    /// The length of the response packet when the command succeeds.
+    fn response_len(&self) -> usize {
+        match *self {
+            Command::Nonce | Command::Verify => 4,
+            Command::Random => 35,
+            Command::Sign => 3 + SIGNATURE_LEN,
+        }
+    }
+}
+
+/// The operations a client can ask for.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Random,
+    Sign,
+    Verify,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Holding SDA low for the wake pulse.
+    Waking,
+    /// Waiting for the chip to wake up.
+    WakeDelay,
+    ReadingWake,
+    Sending(Command),
+    Executing(Command),
+    Reading(Command),
+    GoingIdle,
+}
+
+pub struct Atecc608<'a, A: time::Alarm + 'a> {
+    i2c: &'a i2c::I2CDevice,
+    /// A device at address 0, written to for the wake pulse.
+    wake: &'a i2c::I2CDevice,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    key: Cell<u8>,
+    /// The client's buffer for the sign or verify in progress.
+    client_buffer: TakeCell<'static, [u8]>,
+    result: Cell<ReturnCode>,
+    valid: Cell<bool>,
+    /// Whether randomness was asked for during another operation.
+    random_pending: Cell<bool>,
+    ecdsa_client: Cell<Option<&'static ecdsa::Client>>,
+    rng_client: Cell<Option<&'static rng::Client>>,
+}
+
+impl<'a, A: time::Alarm + 'a> Atecc608<'a, A> {
+    pub fn new(
+        i2c: &'a i2c::I2CDevice,
+        wake: &'a i2c::I2CDevice,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+    ) -> Atecc608<'a, A> {
+        Atecc608 {
+            i2c: i2c,
+            wake: wake,
+            alarm: alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Idle),
+            key: Cell::new(0),
+            client_buffer: TakeCell::empty(),
+            result: Cell::new(ReturnCode::SUCCESS),
+            valid: Cell::new(false),
+            random_pending: Cell::new(false),
+            ecdsa_client: Cell::new(None),
+            rng_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_rng_client(&self, client: &'static rng::Client) {
+        self.rng_client.set(Some(client));
+    }
+
+    fn set_delay(&self, ms: u32) {
+        let interval = ms * <A::Frequency>::frequency() / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(interval));
+    }
+
+    /// Wake the chip to carry out `operation`.
+    fn start(&self, operation: Operation) {
+        self.operation.set(operation);
+        self.result.set(ReturnCode::SUCCESS);
+        self.buffer.take().map(|buffer| {
+            buffer[0] = 0;
+            self.state.set(State::Waking);
+            self.wake.enable();
+            self.wake.write(buffer, 1);
+        });
+    }
+
+    /// Send `command`, taking its data from the client's buffer.
+    fn send(&self, command: Command) {
+        self.buffer.take().map(|buffer| {
+            let (opcode, param1, param2, data_len) = match command {
+                Command::Nonce => (OPCODE_NONCE, NONCE_MODE_PASSTHROUGH, 0, DIGEST_LEN),
+                Command::Random => (OPCODE_RANDOM, 0, 0, 0),
+                Command::Sign => (OPCODE_SIGN, SIGN_MODE_EXTERNAL, self.key.get() as u16, 0),
+                Command::Verify => (
+                    OPCODE_VERIFY,
+                    VERIFY_MODE_EXTERNAL,
+                    KEY_TYPE_P256,
+                    SIGNATURE_LEN + PUBLIC_KEY_LEN,
+                ),
+            };
+            self.client_buffer.map(|data| {
+                let data = match command {
+                    Command::Nonce => &data[..DIGEST_LEN],
+                    Command::Verify => &data[DIGEST_LEN..DIGEST_LEN + data_len],
+                    _ => &data[..0],
+                };
+                buffer[6..6 + data.len()].copy_from_slice(data);
+            });
+
+            // The count covers everything but the word address.
+            let count = 7 + data_len;
+            buffer[0] = WORD_ADDRESS_COMMAND;
+            buffer[1] = count as u8;
+            buffer[2] = opcode;
+            buffer[3] = param1;
+            buffer[4] = param2 as u8;
+            buffer[5] = (param2 >> 8) as u8;
+            let crc = crc16(&buffer[1..count - 1]);
+            buffer[count - 1] = crc as u8;
+            buffer[count] = (crc >> 8) as u8;
+
+            self.state.set(State::Sending(command));
+            self.i2c.write(buffer, (count + 1) as u8);
+        });
+    }
+
+    /// Check the response to `command` in `buffer` and carry on with the
+    /// operation.
+    fn handle_response(&self, command: Command, buffer: &[u8]) {
+        let count = buffer[0] as usize;
+        if count < 4 || count > command.response_len() {
+            self.fail(ReturnCode::FAIL);
+            return;
+        }
+        let crc = crc16(&buffer[..count - 2]);
+        if buffer[count - 2] != crc as u8 || buffer[count - 1] != (crc >> 8) as u8 {
+            self.fail(ReturnCode::FAIL);
+            return;
+        }
+
+        match command {
+            Command::Nonce if buffer[1] == 0 => match self.operation.get() {
+                Operation::Sign => self.send(Command::Sign),
+                _ => self.send(Command::Verify),
+            },
+            Command::Verify if buffer[1] == 0 || buffer[1] == STATUS_MISCOMPARE => {
+                self.valid.set(buffer[1] == 0);
+                self.go_idle();
+            }
+            Command::Random | Command::Sign if count == command.response_len() => {
+                if command == Command::Sign {
+                    self.client_buffer.map(|signature| {
+                        signature[..SIGNATURE_LEN].copy_from_slice(&buffer[1..1 + SIGNATURE_LEN])
+                    });
+                }
+                // The random numbers stay in the buffer, after the word
+                // address that sends the chip to idle.
+                self.go_idle();
+            }
+            // A status packet reporting an error.
+            _ => self.fail(ReturnCode::FAIL),
+        }
+    }
+
+    fn fail(&self, result: ReturnCode) {
+        self.result.set(result);
+        self.go_idle();
+    }
+
+    /// Send the chip to idle, which keeps its state but uses little power.
+    fn go_idle(&self) {
+        self.buffer.take().map(|buffer| {
+            buffer[0] = WORD_ADDRESS_IDLE;
+            self.state.set(State::GoingIdle);
+            self.i2c.write(buffer, 1);
+        });
+    }
+
+    /// Report the result of the operation to the client.
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        let result = self.result.get();
+
+        match operation {
+            Operation::Random => {
+                self.random_pending.set(false);
+                if result == ReturnCode::SUCCESS {
+                    let more = self.buffer.map_or(false, |buffer| {
+                        let random = &buffer[1..33];
+                        let mut words = random.chunks(4).map(|b| {
+                            b[0] as u32
+                                | (b[1] as u32) << 8
+                                | (b[2] as u32) << 16
+                                | (b[3] as u32) << 24
+                        });
+                        self.rng_client.get().map_or(false, |client| {
+                            client.randomness_available(&mut words) == rng::Continue::More
+                        })
+                    });
+                    self.random_pending.set(more);
+                }
+            }
+            Operation::Sign => {
+                self.client_buffer.take().map(|buffer| {
+                    self.ecdsa_client
+                        .get()
+                        .map(move |client| client.sign_done(result, buffer));
+                });
+            }
+            Operation::Verify => {
+                let valid = self.valid.get();
+                self.client_buffer.take().map(|buffer| {
+                    self.ecdsa_client
+                        .get()
+                        .map(move |client| client.verify_done(result, valid, buffer));
+                });
+            }
+            Operation::Idle => {}
+        }
+
+        if self.random_pending.get() && self.operation.get() == Operation::Idle {
+            self.start(Operation::Random);
+        }
+    }
+}
+
+/// The CRC-16 of ATECC packets, with polynomial 0x8005 and the bits of each
+/// byte taken least significant first.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        for bit in 0..8 {
+            let data_bit = (byte >> bit) & 1 == 1;
+            let crc_bit = crc >> 15 == 1;
+            crc <<= 1;
+            if data_bit != crc_bit {
+                crc ^= 0x8005;
+            }
+        }
+    }
+    crc
+}
+
+impl<'a, A: time::Alarm + 'a> i2c::I2CClient for Atecc608<'a, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let ok = error == i2c::Error::CommandComplete;
+        match self.state.get() {
+            State::Waking => {
+                // The sleeping chip does not acknowledge address 0.
+                self.buffer.replace(buffer);
+                self.wake.disable();
+                self.i2c.enable();
+                self.state.set(State::WakeDelay);
+                self.set_delay(WAKE_DELAY_MS);
+            }
+            State::ReadingWake => {
+                let awake = ok && buffer[..4] == WAKE_RESPONSE;
+                self.buffer.replace(buffer);
+                if !awake {
+                    self.fail(ReturnCode::ENODEVICE);
+                    return;
+                }
+                match self.operation.get() {
+                    Operation::Random => self.send(Command::Random),
+                    _ => self.send(Command::Nonce),
+                }
+            }
+            State::Sending(command) => {
+                self.buffer.replace(buffer);
+                if !ok {
+                    self.fail(ReturnCode::FAIL);
+                    return;
+                }
+                self.state.set(State::Executing(command));
+                self.set_delay(command.execution_ms());
+            }
+            State::Reading(command) => {
+                if !ok {
+                    self.buffer.replace(buffer);
+                    self.fail(ReturnCode::FAIL);
+                    return;
+                }
+                // Copy the response out so the buffer is free for the next
+                // command.
+                let mut response = [0; 3 + SIGNATURE_LEN];
+                let len = command.response_len();
+                response[..len].copy_from_slice(&buffer[..len]);
+                self.buffer.replace(buffer);
+                self.handle_response(command, &response[..len]);
+            }
+            State::GoingIdle => {
+                self.buffer.replace(buffer);
+                self.finish();
+            }
+            State::Idle | State::WakeDelay | State::Executing(_) => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> time::Client for Atecc608<'a, A> {
+    fn fired(&self) {
+        let (state, len) = match self.state.get() {
+            State::WakeDelay => (State::ReadingWake, WAKE_RESPONSE.len()),
+            State::Executing(command) => (State::Reading(command), command.response_len()),
+            _ => return,
+        };
+        self.buffer.take().map(|buffer| {
+            self.state.set(state);
+            self.i2c.read(buffer, len as u8);
+        });
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> ecdsa::EcdsaP256 for Atecc608<'a, A> {
+    fn set_client(&self, client: &'static ecdsa::Client) {
+        self.ecdsa_client.set(Some(client));
+    }
+
+    fn sign(
+        &self,
+        key: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.operation.get() != Operation::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if buffer.len() < SIGNATURE_LEN {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        if key >= NUM_SLOTS {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        self.key.set(key as u8);
+        self.client_buffer.replace(buffer);
+        self.start(Operation::Sign);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn verify(&self, buffer: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.operation.get() != Operation::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if buffer.len() < DIGEST_LEN + SIGNATURE_LEN + PUBLIC_KEY_LEN {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        self.valid.set(false);
+        self.client_buffer.replace(buffer);
+        self.start(Operation::Verify);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> rng::RNG for Atecc608<'a, A> {
+    fn get(&self) {
+        if self.operation.get() == Operation::Idle {
+            self.start(Operation::Random);
+        } else if self.operation.get() != Operation::Random {
+            self.random_pending.set(true);
+        }
+    }
+}
@@ -10,12 +10,19 @@
 //! Data payloads are limited to 31 bytes since the maximum advertising channel
 //! protocol data unit (PDU) is 37 bytes and includes a 6-byte header.
 //!
+//! Each process has `MAX_ADV_SETS` advertising sets, each with its own data,
+//! PDU type and interval, so that a process can for example broadcast a
+//! non-connectable beacon and a connectable presence at the same time. The
+//! advertising data allow and the start and stop commands act on the selected
+//! set, which is set 0 unless the process selects another with command 6. A
+//! process can not scan while any of its sets is advertising.
+//!
 //! ### Allow system call
 //!
 //! The allow systems calls are used for buffers from allocated by userland
 //!
 //! There are two different buffers:
-//! * 0: Advertising data
+//! * 0: Advertising data of the selected advertising set
 //! * 1: Passive scanning buffer
 //!
 //! The possible return codes from the 'allow' system call indicate the following:
@@ -46,9 +53,10 @@
 //! `command number` is used to specify the specific operation, currently
 //! the following commands are supported:
 //!
-//! * 0: start advertisement
-//! * 1: stop advertisement or scanning
+//! * 0: start advertising the selected set
+//! * 1: stop advertising the selected set, or scanning
 //! * 5: start scanning
+//! * 6: select the advertising set numbered `subcommand number`
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
@@ -97,6 +105,8 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::iter;
+use core::mem;
 use kernel;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
@@ -113,13 +123,25 @@ const PACKET_ADDR_LEN: usize = 6;
 const PACKET_LENGTH: usize = 39;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
 
+/// The number of advertising sets each process has.
+pub const MAX_ADV_SETS: usize = 2;
+
+/// Whether a process has given the driver a buffer yet, and its scanning
+/// state. Advertising sets keep their own state.
 #[derive(PartialEq, Debug)]
 enum BLEState {
     NotInitialized,
     Initialized,
     ScanningIdle,
     Scanning(RadioChannel),
-    AdvertisingIdle,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum AdvState {
+    Stopped,
+    /// Waiting for the next advertising event
+    Idle,
+    /// In an advertising event, sending on this channel
     Advertising(RadioChannel),
 }
 
@@ -142,6 +164,13 @@ impl AlarmData {
             expiration: Expiration::Disabled,
         }
     }
+
+    fn expired(&self, now: u32) -> bool {
+        match self.expiration {
+            Expiration::Abs(exp) => now.wrapping_sub(self.t0) >= exp.wrapping_sub(self.t0),
+            Expiration::Disabled => false,
+        }
+    }
 }
 
 type AdvPduType = u8;
@@ -159,16 +188,40 @@ const SCAN_RESP: AdvPduType = 0b0100;
 const CONNECT_IND: AdvPduType = 0b0101;
 const ADV_SCAN_IND: AdvPduType = 0b0110;
 
+/// Advertising data that a process advertises with its own PDU type and
+/// interval.
+struct AdvSet {
+    state: AdvState,
+    alarm_data: AlarmData,
+    adv_data: Option<kernel::AppSlice<kernel::Shared, u8>>,
+    pdu_type: AdvPduType,
+    interval_ms: u32,
+}
+
+impl AdvSet {
+    fn new() -> AdvSet {
+        AdvSet {
+            state: AdvState::Stopped,
+            alarm_data: AlarmData::new(),
+            adv_data: None,
+            pdu_type: ADV_NONCONN_IND,
+            interval_ms: 200,
+        }
+    }
+}
+
 /// Process specific memory
 pub struct App {
     process_status: Option<BLEState>,
+    /// The alarm for the next scanning event
     alarm_data: AlarmData,
 
     // Advertising meta-data
-    adv_data: Option<kernel::AppSlice<kernel::Shared, u8>>,
+    adv_sets: [AdvSet; MAX_ADV_SETS],
+    /// The set that the advertising data allow and the start and stop
+    /// commands act on.
+    selected_set: usize,
     address: [u8; PACKET_ADDR_LEN],
-    pdu_type: AdvPduType,
-    advertisement_interval_ms: u32,
     tx_power: u8,
     /// The state of an app-specific pseudo random number.
     ///
@@ -180,20 +233,21 @@ pub struct App {
     // Scanning meta-data
     scan_buffer: Option<kernel::AppSlice<kernel::Shared, u8>>,
     scan_callback: Option<kernel::Callback>,
+    scan_interval_ms: u32,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             alarm_data: AlarmData::new(),
-            adv_data: None,
+            adv_sets: [AdvSet::new(), AdvSet::new()],
+            selected_set: 0,
             scan_buffer: None,
             address: [0; PACKET_ADDR_LEN],
-            pdu_type: ADV_NONCONN_IND,
             scan_callback: None,
+            scan_interval_ms: 200,
             process_status: Some(BLEState::NotInitialized),
             tx_power: 0,
-            advertisement_interval_ms: 200,
             // Just use any non-zero starting value by default
             random_nonce: 0xdeadbeef,
         }
@@ -229,12 +283,25 @@ impl App {
         ReturnCode::SUCCESS
     }
 
-    fn send_advertisement<'a, B, A>(&self, ble: &BLE<'a, B, A>, channel: RadioChannel) -> ReturnCode
+    fn is_advertising(&self) -> bool {
+        self.adv_sets
+            .iter()
+            .any(|set| set.state != AdvState::Stopped)
+    }
+
+    fn send_advertisement<'a, B, A>(
+        &self,
+        ble: &BLE<'a, B, A>,
+        set: usize,
+        channel: RadioChannel,
+    ) -> ReturnCode
     where
         B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig + 'a,
         A: kernel::hil::time::Alarm + 'a,
     {
-        self.adv_data
+        let pdu_type = self.adv_sets[set].pdu_type;
+        self.adv_sets[set]
+            .adv_data
             .as_ref()
             .map(|adv_data| {
                 ble.kernel_tx
@@ -246,8 +313,8 @@ impl App {
                         let payload_len = adv_data_corrected.len() + PACKET_ADDR_LEN;
                         {
                             let (header, payload) = kernel_tx.split_at_mut(2);
-                            header[0] = self.pdu_type;
-                            match self.pdu_type {
+                            header[0] = pdu_type;
+                            match pdu_type {
                                 ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
                                     // Set TxAdd because AdvA field is going to be a "random"
                                     // address
@@ -288,13 +355,28 @@ impl App {
         self.random_nonce
     }
 
-    // Set the next alarm for this app using the period and provided start time.
-    fn set_next_alarm<F: Frequency>(&mut self, now: u32) {
-        self.alarm_data.t0 = now;
+    // An alarm one interval from the provided start time, plus a pseudo-random
+    // delay.
+    fn next_alarm<F: Frequency>(&mut self, now: u32, interval_ms: u32) -> AlarmData {
         let nonce = self.random_nonce() % 10;
 
-        let period_ms = (self.advertisement_interval_ms + nonce) * F::frequency() / 1000;
-        self.alarm_data.expiration = Expiration::Abs(now.wrapping_add(period_ms));
+        let period_ms = (interval_ms + nonce) * F::frequency() / 1000;
+        AlarmData {
+            t0: now,
+            expiration: Expiration::Abs(now.wrapping_add(period_ms)),
+        }
+    }
+
+    // Set the next scanning alarm for this app.
+    fn set_next_scan_alarm<F: Frequency>(&mut self, now: u32) {
+        let interval_ms = self.scan_interval_ms;
+        self.alarm_data = self.next_alarm::<F>(now, interval_ms);
+    }
+
+    // Set the next alarm of an advertising set of this app.
+    fn set_next_adv_alarm<F: Frequency>(&mut self, set: usize, now: u32) {
+        let interval_ms = self.adv_sets[set].interval_ms;
+        self.adv_sets[set].alarm_data = self.next_alarm::<F>(now, interval_ms);
     }
 }
 
@@ -308,7 +390,8 @@ where
     app: kernel::Grant<App>,
     kernel_tx: kernel::common::cells::TakeCell<'static, [u8]>,
     alarm: &'a A,
-    sending_app: Cell<Option<kernel::AppId>>,
+    /// The app and advertising set in an advertising event
+    sending_app: Cell<Option<(kernel::AppId, usize)>>,
     receiving_app: Cell<Option<kernel::AppId>>,
 }
 
@@ -346,15 +429,17 @@ where
         let mut next_alarm = u32::max_value();
         let mut next_dist = u32::max_value();
         for app in self.app.iter() {
-            app.enter(|app, _| match app.alarm_data.expiration {
-                Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+            app.enter(|app, _| {
+                let adv_alarms = app.adv_sets.iter().map(|set| &set.alarm_data);
+                for alarm_data in iter::once(&app.alarm_data).chain(adv_alarms) {
+                    if let Expiration::Abs(exp) = alarm_data.expiration {
+                        let t_dist = exp.wrapping_sub(now);
+                        if next_dist > t_dist {
+                            next_alarm = exp;
+                            next_dist = t_dist;
+                        }
                     }
                 }
-                Expiration::Disabled => {}
             });
         }
         if next_alarm != u32::max_value() {
@@ -385,31 +470,18 @@ where
         let now = self.alarm.now();
 
         self.app.each(|app| {
-            if let Expiration::Abs(exp) = app.alarm_data.expiration {
-                let expired =
-                    now.wrapping_sub(app.alarm_data.t0) >= exp.wrapping_sub(app.alarm_data.t0);
-                if expired {
-                    if self.busy.get() {
-                        // The radio is currently busy, so we won't be able to start the
-                        // operation at the appropriate time. Instead, reschedule the
-                        // operation for later. This is _kind_ of simulating actual
-                        // on-air interference
-                        debug!("BLE: operation delayed for app {:?}", app.appid());
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
-                        return;
-                    }
-
+            if app.alarm_data.expired(now) {
+                if self.busy.get() {
+                    // The radio is currently busy, so we won't be able to start the
+                    // operation at the appropriate time. Instead, reschedule the
+                    // operation for later. This is _kind_ of simulating actual
+                    // on-air interference
+                    debug!("BLE: operation delayed for app {:?}", app.appid());
+                    app.set_next_scan_alarm::<A::Frequency>(self.alarm.now());
+                } else {
                     app.alarm_data.expiration = Expiration::Disabled;
 
                     match app.process_status {
-                        Some(BLEState::AdvertisingIdle) => {
-                            self.busy.set(true);
-                            app.process_status =
-                                Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
-                            self.sending_app.set(Some(app.appid()));
-                            self.radio.set_tx_power(app.tx_power);
-                            app.send_advertisement(&self, RadioChannel::AdvertisingChannel37);
-                        }
                         Some(BLEState::ScanningIdle) => {
                             self.busy.set(true);
                             app.process_status =
@@ -427,6 +499,36 @@ where
                     }
                 }
             }
+
+            for set in 0..MAX_ADV_SETS {
+                if !app.adv_sets[set].alarm_data.expired(now) {
+                    continue;
+                }
+                if self.busy.get() {
+                    debug!("BLE: operation delayed for app {:?}", app.appid());
+                    app.set_next_adv_alarm::<A::Frequency>(set, self.alarm.now());
+                    continue;
+                }
+
+                app.adv_sets[set].alarm_data.expiration = Expiration::Disabled;
+
+                match app.adv_sets[set].state {
+                    AdvState::Idle => {
+                        self.busy.set(true);
+                        app.adv_sets[set].state =
+                            AdvState::Advertising(RadioChannel::AdvertisingChannel37);
+                        self.sending_app.set(Some((app.appid(), set)));
+                        self.radio.set_tx_power(app.tx_power);
+                        app.send_advertisement(&self, set, RadioChannel::AdvertisingChannel37);
+                    }
+                    state => debug!(
+                        "app: {:?} \t set {} invalid state {:?}",
+                        app.appid(),
+                        set,
+                        state
+                    ),
+                }
+            }
         });
         self.reset_active_alarm();
     }
@@ -488,7 +590,7 @@ where
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_scan_alarm::<A::Frequency>(self.alarm.now());
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -508,28 +610,26 @@ where
     // The ReturnCode indicates valid CRC or not, not used yet but could be used for
     // re-transmissions for invalid CRCs
     fn transmit_event(&self, _crc_ok: ReturnCode) {
-        if let Some(appid) = self.sending_app.get() {
+        if let Some((appid, set)) = self.sending_app.get() {
             let _ = self.app.enter(appid, |app, _| {
-                match app.process_status {
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38));
-                        self.sending_app.set(Some(app.appid()));
+                match app.adv_sets[set].state {
+                    AdvState::Advertising(RadioChannel::AdvertisingChannel37) => {
+                        app.adv_sets[set].state =
+                            AdvState::Advertising(RadioChannel::AdvertisingChannel38);
                         self.radio.set_tx_power(app.tx_power);
-                        app.send_advertisement(&self, RadioChannel::AdvertisingChannel38);
+                        app.send_advertisement(&self, set, RadioChannel::AdvertisingChannel38);
                     }
 
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39));
-                        self.sending_app.set(Some(app.appid()));
-                        app.send_advertisement(&self, RadioChannel::AdvertisingChannel39);
+                    AdvState::Advertising(RadioChannel::AdvertisingChannel38) => {
+                        app.adv_sets[set].state =
+                            AdvState::Advertising(RadioChannel::AdvertisingChannel39);
+                        app.send_advertisement(&self, set, RadioChannel::AdvertisingChannel39);
                     }
 
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39)) => {
+                    AdvState::Advertising(RadioChannel::AdvertisingChannel39) => {
                         self.busy.set(false);
-                        app.process_status = Some(BLEState::AdvertisingIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.adv_sets[set].state = AdvState::Idle;
+                        app.set_next_adv_alarm::<A::Frequency>(set, self.alarm.now());
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -558,15 +658,19 @@ where
             0 => self
                 .app
                 .enter(appid, |app, _| {
-                    if let Some(BLEState::Initialized) = app.process_status {
+                    let set = app.selected_set;
+                    if app.process_status == Some(BLEState::Initialized)
+                        && app.adv_sets[set].state == AdvState::Stopped
+                        && app.adv_sets[set].adv_data.is_some()
+                    {
                         let pdu_type = data as AdvPduType;
                         match pdu_type {
                             ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
-                                app.pdu_type = pdu_type;
-                                app.process_status = Some(BLEState::AdvertisingIdle);
+                                app.adv_sets[set].pdu_type = pdu_type;
+                                app.adv_sets[set].state = AdvState::Idle;
                                 app.random_nonce = self.alarm.now();
-                                app.advertisement_interval_ms = cmp::max(20, interval as u32);
-                                app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                                app.adv_sets[set].interval_ms = cmp::max(20, interval as u32);
+                                app.set_next_adv_alarm::<A::Frequency>(set, self.alarm.now());
                                 self.reset_active_alarm();
                                 ReturnCode::SUCCESS
                             }
@@ -581,12 +685,19 @@ where
             // Stop periodic advertisements or passive scanning
             1 => self
                 .app
-                .enter(appid, |app, _| match app.process_status {
-                    Some(BLEState::AdvertisingIdle) | Some(BLEState::ScanningIdle) => {
+                .enter(appid, |app, _| {
+                    let set = app.selected_set;
+                    if app.process_status == Some(BLEState::ScanningIdle) {
                         app.process_status = Some(BLEState::Initialized);
+                        app.alarm_data.expiration = Expiration::Disabled;
+                        ReturnCode::SUCCESS
+                    } else if app.adv_sets[set].state == AdvState::Idle {
+                        app.adv_sets[set].state = AdvState::Stopped;
+                        app.adv_sets[set].alarm_data.expiration = Expiration::Disabled;
                         ReturnCode::SUCCESS
+                    } else {
+                        ReturnCode::EBUSY
                     }
-                    _ => ReturnCode::EBUSY,
                 })
                 .unwrap_or_else(|err| err.into()),
 
@@ -601,7 +712,7 @@ where
                 self.app
                     .enter(appid, |app, _| {
                         if app.process_status != Some(BLEState::ScanningIdle)
-                            && app.process_status != Some(BLEState::AdvertisingIdle)
+                            && !app.is_advertising()
                         {
                             match data as u8 {
                                 tx_power @ 0...10 | tx_power @ 0xec...0xff => {
@@ -625,9 +736,9 @@ where
             5 => self
                 .app
                 .enter(appid, |app, _| {
-                    if let Some(BLEState::Initialized) = app.process_status {
+                    if app.process_status == Some(BLEState::Initialized) && !app.is_advertising() {
                         app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_scan_alarm::<A::Frequency>(self.alarm.now());
                         self.reset_active_alarm();
                         ReturnCode::SUCCESS
                     } else {
@@ -636,6 +747,19 @@ where
                 })
                 .unwrap_or_else(|err| err.into()),
 
+            // Select the advertising set
+            6 => self
+                .app
+                .enter(appid, |app, _| {
+                    if data < MAX_ADV_SETS {
+                        app.selected_set = data;
+                        ReturnCode::SUCCESS
+                    } else {
+                        ReturnCode::EINVAL
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -651,9 +775,17 @@ where
             0 => self
                 .app
                 .enter(appid, |app, _| {
-                    app.adv_data = slice;
+                    let set = app.selected_set;
+                    // A set that is advertising needs its data, but the data
+                    // can be replaced between advertising events.
+                    if slice.is_none() && app.adv_sets[set].state != AdvState::Stopped {
+                        return ReturnCode::EBUSY;
+                    }
+                    app.adv_sets[set].adv_data = slice;
                     if let ReturnCode::SUCCESS = app.generate_random_address(appid) {
-                        app.process_status = Some(BLEState::Initialized);
+                        if app.process_status == Some(BLEState::NotInitialized) {
+                            app.process_status = Some(BLEState::Initialized);
+                        }
                         ReturnCode::SUCCESS
                     } else {
                         ReturnCode::FAIL
@@ -684,20 +816,19 @@ where
         subscribe_num: usize,
         callback: Option<kernel::Callback>,
         app_id: kernel::AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<kernel::Callback>, ReturnCode> {
         match subscribe_num {
             // Callback for scanning
             0 => self
                 .app
                 .enter(app_id, |app, _| match app.process_status {
                     Some(BLEState::NotInitialized) | Some(BLEState::Initialized) => {
-                        app.scan_callback = callback;
-                        ReturnCode::SUCCESS
+                        Ok(mem::replace(&mut app.scan_callback, callback))
                     }
-                    _ => ReturnCode::EINVAL,
+                    _ => Err(ReturnCode::EINVAL),
                 })
-                .unwrap_or_else(|err| err.into()),
-            _ => ReturnCode::ENOSUPPORT,
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 }
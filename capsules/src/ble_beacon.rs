@@ -0,0 +1,639 @@
+//! Builds standard BLE beacon advertisements for apps.
+//!
+//! Apps advertise with the BLE advertising driver, which sends whatever
+//! advertising data the app allows it. This capsule writes iBeacon and
+//! Eddystone (UID, URL, TLM and EID) frames into that same buffer, so an app
+//! can become a beacon with a couple of commands instead of encoding the
+//! frames itself. TLM and EID frames change over time, and the
+//! capsule keeps them up to date:
+//!
+//! * TLM frames report the temperature, read from a `TemperatureDriver` if
+//!   the board provides one, and the time since the capsule started counting.
+//!   No battery HIL exists yet, so the battery voltage is reported as 0,
+//!   which Eddystone defines as "not supported". The advertising count is
+//!   estimated from the app's advertising interval.
+//! * EID frames are computed from the app's identity key with AES-128 and
+//!   rotated every 2^K seconds, as the Eddystone-EID specification describes.
+//!   The block cipher is run in CTR mode over a zero block, which gives the
+//!   same result as encrypting the counter block in ECB mode.
+//!
+//! Time is counted in seconds from the first TLM or EID command. A resolver
+//! learns it when the beacon is registered.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow` System Call
+//!
+//! * `0`: The advertisement buffer, which the app also allows to the BLE
+//!        advertising driver as its advertising data. It must hold at least
+//!        31 bytes; frames are written from the start and the rest of the
+//!        buffer is zeroed.
+//! * `1`: The configuration buffer, used by the commands below.
+//!
+//! ### `command` System Call
+//!
+//! The static frames are written immediately and the commands return their
+//! length. TLM and EID frames are rewritten until command 6 or another frame
+//! is chosen.
+//!
+//! * `0`: Driver check.
+//! * `1`: iBeacon. Config: the 16-byte proximity UUID. `r2`: the major
+//!        number in the upper 16 bits and the minor number in the lower 16.
+//!        `r3`: the measured power at 1 m, in dBm.
+//! * `2`: Eddystone-UID. Config: the 10-byte namespace followed by the 6-byte
+//!        instance. `r2`: the transmit power at 0 m, in dBm.
+//! * `3`: Eddystone-URL. Config: the URL, such as `https://example.com/`,
+//!        which is compressed with the Eddystone scheme and expansion codes.
+//!        `r2`: the transmit power at 0 m, in dBm. `r3`: the length of the
+//!        URL. Returns `ESIZE` if the compressed URL is longer than 17 bytes
+//!        and `EINVAL` if it has no known scheme.
+//! * `4`: Eddystone-TLM. `r2`: the app's advertising interval in ms.
+//! * `5`: Eddystone-EID. Config: the 16-byte identity key. `r2`: the
+//!        transmit power at 0 m, in dBm. `r3`: the rotation exponent K, from 0
+//!        to 15. The frame is written once its first EID is computed.
+//! * `6`: Stop updating the app's TLM or EID frame. The buffer keeps the last
+//!        frame written.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let beacon = static_init!(
+//!     capsules::ble_beacon::BeaconDriver<'static, VirtualMuxAlarm<'static, Rtc>, AesECB>,
+//!     capsules::ble_beacon::BeaconDriver::new(
+//!         beacon_virtual_alarm,
+//!         &nrf5x::aes::AESECB,
+//!         Some(&nrf5x::temperature::TEMP),
+//!         kernel::Grant::create(),
+//!         &mut capsules::ble_beacon::AES_SRC,
+//!         &mut capsules::ble_beacon::AES_DST
+//!     )
+//! );
+//! beacon_virtual_alarm.set_client(beacon);
+//! nrf5x::aes::AESECB.set_client(beacon);
+//! kernel::hil::sensors::TemperatureDriver::set_client(&nrf5x::temperature::TEMP, beacon);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::ble_advertising::{self, AdvDataBuilder, ADV_DATA_MAX_LEN};
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128_BLOCK_SIZE};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30003;
+
+pub static mut AES_SRC: [u8; AES128_BLOCK_SIZE] = [0; AES128_BLOCK_SIZE];
+pub static mut AES_DST: [u8; AES128_BLOCK_SIZE] = [0; AES128_BLOCK_SIZE];
+
+/// How often TLM frames get a new temperature reading, in seconds.
+const TLM_TEMPERATURE_PERIOD_S: u32 = 10;
+
+/// Eddystone TLM temperature meaning "not supported".
+const TLM_NO_TEMPERATURE: i16 = -0x8000;
+
+/// The 16-bit service UUID of Eddystone, little endian.
+const EDDYSTONE_UUID: [u8; 2] = [0xaa, 0xfe];
+
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+const EDDYSTONE_EID: u8 = 0x30;
+
+const URL_MAX_LEN: usize = 17;
+
+/// Eddystone-URL scheme prefixes, by code.
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Eddystone-URL expansions, by code. Each expansion ending in '/' comes
+/// before the same expansion without it, so the longer one is matched first.
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Updating {
+    Nothing,
+    /// Advertising interval in ms
+    Tlm(u32),
+    /// Transmit power and rotation exponent
+    Eid(i8, u8),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum EidStep {
+    TemporaryKey,
+    Eid,
+}
+
+pub struct App {
+    adv_buffer: Option<AppSlice<Shared, u8>>,
+    config: Option<AppSlice<Shared, u8>>,
+    updating: Updating,
+    identity_key: [u8; 16],
+    /// The rotation period (time >> K) of the EID in the advertisement buffer
+    eid_period: Option<u32>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            adv_buffer: None,
+            config: None,
+            updating: Updating::Nothing,
+            identity_key: [0; 16],
+            eid_period: None,
+        }
+    }
+}
+
+/// Copies `frame` to the start of the app's advertisement buffer and zeroes
+/// the rest. Returns the length of the frame.
+fn write_frame(app: &mut App, frame: &[u8]) -> ReturnCode {
+    app.adv_buffer
+        .as_mut()
+        .map_or(ReturnCode::EINVAL, |buffer| {
+            let buffer = buffer.as_mut();
+            if buffer.len() < frame.len() {
+                return ReturnCode::ESIZE;
+            }
+            buffer[..frame.len()].copy_from_slice(frame);
+            for byte in buffer[frame.len()..].iter_mut() {
+                *byte = 0;
+            }
+            ReturnCode::SuccessWithValue { value: frame.len() }
+        })
+}
+
+/// Builds an Eddystone advertisement carrying `frame` as service data, and
+/// returns its length.
+fn eddystone_adv(frame: &[u8], adv: &mut [u8; ADV_DATA_MAX_LEN]) -> usize {
+    let mut builder = AdvDataBuilder::new(adv);
+    builder.push(
+        ble_advertising::AD_TYPE_FLAGS,
+        &[ble_advertising::AD_FLAGS_LE_GENERAL_DISCOVERABLE],
+    );
+    builder.push(
+        ble_advertising::AD_TYPE_COMPLETE_16BIT_UUIDS,
+        &EDDYSTONE_UUID,
+    );
+    builder.push_with(
+        ble_advertising::AD_TYPE_SERVICE_DATA_16BIT,
+        2 + frame.len(),
+        |data| {
+            data[..2].copy_from_slice(&EDDYSTONE_UUID);
+            data[2..].copy_from_slice(frame);
+        },
+    );
+    builder.len()
+}
+
+/// Compresses `url` with the Eddystone-URL codes into `encoded`, returning
+/// the scheme code and the length of the encoded URL.
+fn encode_url(url: &[u8], encoded: &mut [u8; URL_MAX_LEN]) -> Result<(u8, usize), ReturnCode> {
+    let (scheme, mut rest) = URL_SCHEMES
+        .iter()
+        .enumerate()
+        .find(|&(_, prefix)| url.starts_with(prefix.as_bytes()))
+        .map(|(code, prefix)| (code as u8, &url[prefix.len()..]))
+        .ok_or(ReturnCode::EINVAL)?;
+    let mut len = 0;
+    while !rest.is_empty() {
+        if len == URL_MAX_LEN {
+            return Err(ReturnCode::ESIZE);
+        }
+        let expansion = URL_EXPANSIONS
+            .iter()
+            .position(|expansion| rest.starts_with(expansion.as_bytes()));
+        match expansion {
+            Some(code) => {
+                encoded[len] = code as u8;
+                rest = &rest[URL_EXPANSIONS[code].len()..];
+            }
+            None => {
+                // Only graphic characters can be sent as they are
+                if rest[0] <= 0x20 || rest[0] >= 0x7f {
+                    return Err(ReturnCode::EINVAL);
+                }
+                encoded[len] = rest[0];
+                rest = &rest[1..];
+            }
+        }
+        len += 1;
+    }
+    Ok((scheme, len))
+}
+
+pub struct BeaconDriver<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> {
+    alarm: &'a A,
+    aes: &'a E,
+    temperature: Option<&'a TemperatureDriver>,
+    apps: Grant<App>,
+    /// Seconds since the capsule started counting, if it has
+    time: Cell<Option<u32>>,
+    /// When the alarm last fired
+    last_tick: Cell<u32>,
+    /// The last temperature read, in 8.8 fixed point degrees Celsius
+    temperature_value: Cell<i16>,
+    reading_temperature: Cell<bool>,
+    aes_src: TakeCell<'a, [u8]>,
+    aes_dst: TakeCell<'a, [u8]>,
+    /// The app whose EID is being computed, the rotation period it is for and
+    /// the step of the computation
+    eid_app: Cell<Option<(AppId, u32, EidStep)>>,
+}
+
+impl<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> BeaconDriver<'a, A, E> {
+    pub fn new(
+        alarm: &'a A,
+        aes: &'a E,
+        temperature: Option<&'a TemperatureDriver>,
+        grant: Grant<App>,
+        aes_src: &'a mut [u8],
+        aes_dst: &'a mut [u8],
+    ) -> BeaconDriver<'a, A, E> {
+        BeaconDriver {
+            alarm: alarm,
+            aes: aes,
+            temperature: temperature,
+            apps: grant,
+            time: Cell::new(None),
+            last_tick: Cell::new(0),
+            temperature_value: Cell::new(TLM_NO_TEMPERATURE),
+            reading_temperature: Cell::new(false),
+            aes_src: TakeCell::new(aes_src),
+            aes_dst: TakeCell::new(aes_dst),
+            eid_app: Cell::new(None),
+        }
+    }
+
+    /// Start counting seconds, if the capsule is not already.
+    fn start_time(&self) {
+        if self.time.get().is_none() {
+            self.time.set(Some(0));
+            let now = self.alarm.now();
+            self.last_tick.set(now);
+            self.alarm
+                .set_alarm(now.wrapping_add(<A::Frequency>::frequency()));
+        }
+    }
+
+    fn configure<F>(&self, appid: AppId, len: usize, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App, &[u8]) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| {
+                let mut config = [0; ADV_DATA_MAX_LEN];
+                match app.config {
+                    Some(ref slice) if slice.len() >= len && len <= config.len() => {
+                        config[..len].copy_from_slice(&slice.as_ref()[..len]);
+                    }
+                    _ => return ReturnCode::EINVAL,
+                }
+                app.updating = Updating::Nothing;
+                closure(app, &config[..len])
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn write_ibeacon(&self, appid: AppId, major_minor: usize, power: usize) -> ReturnCode {
+        self.configure(appid, 16, |app, uuid| {
+            let mut adv = [0; ADV_DATA_MAX_LEN];
+            let len = {
+                let mut builder = AdvDataBuilder::new(&mut adv);
+                builder.push(
+                    ble_advertising::AD_TYPE_FLAGS,
+                    &[ble_advertising::AD_FLAGS_LE_GENERAL_DISCOVERABLE],
+                );
+                builder.push_with(ble_advertising::AD_TYPE_MANUFACTURER_DATA, 25, |data| {
+                    // Apple, then the iBeacon type and length
+                    data[0..4].copy_from_slice(&[0x4c, 0x00, 0x02, 0x15]);
+                    data[4..20].copy_from_slice(uuid);
+                    data[20] = (major_minor >> 24) as u8;
+                    data[21] = (major_minor >> 16) as u8;
+                    data[22] = (major_minor >> 8) as u8;
+                    data[23] = major_minor as u8;
+                    data[24] = power as u8;
+                });
+                builder.len()
+            };
+            write_frame(app, &adv[..len])
+        })
+    }
+
+    fn write_uid(&self, appid: AppId, power: usize) -> ReturnCode {
+        self.configure(appid, 16, |app, uid| {
+            let mut frame = [0; 20];
+            frame[0] = EDDYSTONE_UID;
+            frame[1] = power as u8;
+            frame[2..18].copy_from_slice(uid);
+            let mut adv = [0; ADV_DATA_MAX_LEN];
+            let len = eddystone_adv(&frame, &mut adv);
+            write_frame(app, &adv[..len])
+        })
+    }
+
+    fn write_url(&self, appid: AppId, power: usize, url_len: usize) -> ReturnCode {
+        self.configure(appid, url_len, |app, url| {
+            let mut encoded = [0; URL_MAX_LEN];
+            match encode_url(url, &mut encoded) {
+                Ok((scheme, len)) => {
+                    let mut frame = [0; 3 + URL_MAX_LEN];
+                    frame[0] = EDDYSTONE_URL;
+                    frame[1] = power as u8;
+                    frame[2] = scheme;
+                    frame[3..3 + len].copy_from_slice(&encoded[..len]);
+                    let mut adv = [0; ADV_DATA_MAX_LEN];
+                    let len = eddystone_adv(&frame[..3 + len], &mut adv);
+                    write_frame(app, &adv[..len])
+                }
+                Err(err) => err,
+            }
+        })
+    }
+
+    fn write_tlm(&self, app: &mut App, interval_ms: u32) -> ReturnCode {
+        let time = self.time.get().unwrap_or(0);
+        let adv_count = (time as u64 * 1000 / interval_ms as u64) as u32;
+        let deciseconds = time.wrapping_mul(10);
+        let temperature = self.temperature_value.get() as u16;
+        let frame = [
+            EDDYSTONE_TLM,
+            0, // Unencrypted TLM
+            0, // Battery voltage not supported
+            0,
+            (temperature >> 8) as u8,
+            temperature as u8,
+            (adv_count >> 24) as u8,
+            (adv_count >> 16) as u8,
+            (adv_count >> 8) as u8,
+            adv_count as u8,
+            (deciseconds >> 24) as u8,
+            (deciseconds >> 16) as u8,
+            (deciseconds >> 8) as u8,
+            deciseconds as u8,
+        ];
+        let mut adv = [0; ADV_DATA_MAX_LEN];
+        let len = eddystone_adv(&frame, &mut adv);
+        write_frame(app, &adv[..len])
+    }
+
+    fn start_tlm(&self, appid: AppId, interval_ms: usize) -> ReturnCode {
+        if interval_ms == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.start_time();
+        self.apps
+            .enter(appid, |app, _| {
+                app.updating = Updating::Nothing;
+                let result = self.write_tlm(app, interval_ms as u32);
+                if let ReturnCode::SuccessWithValue { .. } = result {
+                    app.updating = Updating::Tlm(interval_ms as u32);
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn start_eid(&self, appid: AppId, power: usize, exponent: usize) -> ReturnCode {
+        if exponent > 15 {
+            return ReturnCode::EINVAL;
+        }
+        let result = self.configure(appid, 16, |app, key| {
+            if app
+                .adv_buffer
+                .as_ref()
+                .map_or(true, |buffer| buffer.len() < ADV_DATA_MAX_LEN)
+            {
+                return ReturnCode::EINVAL;
+            }
+            app.identity_key.copy_from_slice(key);
+            app.eid_period = None;
+            app.updating = Updating::Eid(power as i8, exponent as u8);
+            ReturnCode::SUCCESS
+        });
+        if result == ReturnCode::SUCCESS {
+            self.start_time();
+            self.compute_next_eid();
+        }
+        result
+    }
+
+    /// Start computing the EID of the next app whose EID is out of date, if
+    /// no EID is being computed.
+    fn compute_next_eid(&self) {
+        if self.eid_app.get().is_some() {
+            return;
+        }
+        let time = match self.time.get() {
+            Some(time) => time,
+            None => return,
+        };
+        for app in self.apps.iter() {
+            let started = app.enter(|app, _| match app.updating {
+                Updating::Eid(_, exponent) => {
+                    let period = time >> exponent;
+                    if app.eid_period == Some(period) {
+                        return false;
+                    }
+                    // The temporary key depends only on the upper 16 bits of
+                    // the time
+                    let mut iv = [0; AES128_BLOCK_SIZE];
+                    iv[11] = 0xff;
+                    iv[14] = (time >> 24) as u8;
+                    iv[15] = (time >> 16) as u8;
+                    if self.crypt(&app.identity_key, &iv) {
+                        self.eid_app
+                            .set(Some((app.appid(), period, EidStep::TemporaryKey)));
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            });
+            if started {
+                break;
+            }
+        }
+    }
+
+    /// Encrypt the block `input` with `key`, as CTR mode with `input` as the
+    /// counter over a zero block. Returns whether encryption started.
+    fn crypt(&self, key: &[u8], input: &[u8]) -> bool {
+        let (src, dst) = match (self.aes_src.take(), self.aes_dst.take()) {
+            (Some(src), Some(dst)) => (src, dst),
+            (src, dst) => {
+                src.map(|src| self.aes_src.replace(src));
+                dst.map(|dst| self.aes_dst.replace(dst));
+                return false;
+            }
+        };
+        for byte in src.iter_mut() {
+            *byte = 0;
+        }
+        self.aes.enable();
+        self.aes.set_mode_aes128ctr(true);
+        if self.aes.set_key(key) != ReturnCode::SUCCESS
+            || self.aes.set_iv(input) != ReturnCode::SUCCESS
+        {
+            self.aes_src.replace(src);
+            self.aes_dst.replace(dst);
+            return false;
+        }
+        self.aes.start_message();
+        match self.aes.crypt(Some(src), dst, 0, AES128_BLOCK_SIZE) {
+            None => true,
+            Some((_, src, dst)) => {
+                src.map(|src| self.aes_src.replace(src));
+                self.aes_dst.replace(dst);
+                false
+            }
+        }
+    }
+
+    /// Handle a finished encryption of the EID computation for `appid`.
+    fn eid_crypt_done(&self, appid: AppId, period: u32, step: EidStep) {
+        let mut block = [0; AES128_BLOCK_SIZE];
+        self.aes_dst
+            .map(|dst| block.copy_from_slice(&dst[..AES128_BLOCK_SIZE]));
+        let _ = self.apps.enter(appid, |app, _| {
+            if let Updating::Eid(power, exponent) = app.updating {
+                // The key may have changed, or the period passed, since the
+                // computation started
+                let time = self.time.get().unwrap_or(0);
+                if time >> exponent != period {
+                    return;
+                }
+                match step {
+                    EidStep::TemporaryKey => {
+                        let masked_time = period << exponent;
+                        let mut iv = [0; AES128_BLOCK_SIZE];
+                        iv[11] = exponent;
+                        iv[12] = (masked_time >> 24) as u8;
+                        iv[13] = (masked_time >> 16) as u8;
+                        iv[14] = (masked_time >> 8) as u8;
+                        iv[15] = masked_time as u8;
+                        if self.crypt(&block, &iv) {
+                            self.eid_app.set(Some((appid, period, EidStep::Eid)));
+                        }
+                    }
+                    EidStep::Eid => {
+                        let mut frame = [0; 10];
+                        frame[0] = EDDYSTONE_EID;
+                        frame[1] = power as u8;
+                        frame[2..10].copy_from_slice(&block[..8]);
+                        let mut adv = [0; ADV_DATA_MAX_LEN];
+                        let len = eddystone_adv(&frame, &mut adv);
+                        if let ReturnCode::SuccessWithValue { .. } = write_frame(app, &adv[..len]) {
+                            app.eid_period = Some(period);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> time::Client for BeaconDriver<'a, A, E> {
+    fn fired(&self) {
+        let tick = self
+            .last_tick
+            .get()
+            .wrapping_add(<A::Frequency>::frequency());
+        self.last_tick.set(tick);
+        self.alarm
+            .set_alarm(tick.wrapping_add(<A::Frequency>::frequency()));
+        let time = self.time.get().unwrap_or(0).wrapping_add(1);
+        self.time.set(Some(time));
+
+        if time % TLM_TEMPERATURE_PERIOD_S == 0 && !self.reading_temperature.get() {
+            self.temperature.map(|temperature| {
+                if temperature.read_temperature() == ReturnCode::SUCCESS {
+                    self.reading_temperature.set(true);
+                }
+            });
+        }
+        self.apps.each(|app| {
+            if let Updating::Tlm(interval_ms) = app.updating {
+                self.write_tlm(app, interval_ms);
+            }
+        });
+        self.compute_next_eid();
+    }
+}
+
+impl<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> TemperatureClient
+    for BeaconDriver<'a, A, E>
+{
+    fn callback(&self, value: usize) {
+        self.reading_temperature.set(false);
+        // Hundredths of a degree to 8.8 fixed point
+        let centidegrees = value as isize as i32;
+        self.temperature_value
+            .set((centidegrees * 256 / 100) as i16);
+    }
+}
+
+impl<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> symmetric_encryption::Client<'a>
+    for BeaconDriver<'a, A, E>
+{
+    fn crypt_done(&self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        source.map(|src| self.aes_src.replace(src));
+        self.aes_dst.replace(dest);
+        if let Some((appid, period, step)) = self.eid_app.get() {
+            self.eid_app.set(None);
+            self.eid_crypt_done(appid, period, step);
+        }
+        // Continue with this app's next step or the next app
+        self.compute_next_eid();
+    }
+}
+
+impl<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + 'a> Driver for BeaconDriver<'a, A, E> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.adv_buffer = slice;
+                    } else {
+                        app.config = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, r2: usize, r3: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.write_ibeacon(appid, r2, r3),
+            2 => self.write_uid(appid, r2),
+            3 => self.write_url(appid, r2, r3),
+            4 => self.start_tlm(appid, r2),
+            5 => self.start_eid(appid, r2, r3),
+            6 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.updating = Updating::Nothing;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
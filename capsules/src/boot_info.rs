@@ -0,0 +1,139 @@
+//! Provides userspace with the reason for the last reset and boot history.
+//!
+//! At boot the capsule reads the chip reset cause and a small retained
+//! register provided by `hil::reset::ResetInfo`. The retained register holds
+//! a boot counter and a flag that the board's panic handler sets with
+//! `record_panic()`, so after a watchdog or pin reset the kernel (and apps)
+//! can tell that the previous run ended in a panic.
+//!
+//! The boot counter only counts since the last power-on or brown-out reset,
+//! since that is when the retained register is cleared by the hardware.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let boot_info = static_init!(
+//!     capsules::boot_info::BootInfo<'static, nrf52::power::Power>,
+//!     capsules::boot_info::BootInfo::new(&nrf52::power::POWER));
+//! boot_info.initialize();
+//! ```
+//!
+//! and in the board's panic handler:
+//!
+//! ```rust
+//! capsules::boot_info::record_panic(&nrf52::power::POWER);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Command
+//!
+//! All values are captured at boot, so this capsule only uses the `command`
+//! syscall.
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: `SUCCESS`.
+//! - `1`: Get the reset reason.
+//!   - Return: the `hil::reset::ResetReason` value: `0` power-on, `1`
+//!     brown-out, `2` watchdog, `3` lockup, `4` software, `5` reset pin, `6`
+//!     wake up from sleep, `7` unknown.
+//! - `2`: Get the boot count since the last power-on reset, including this
+//!   boot.
+//! - `3`: Get whether the previous boot ended in a kernel panic.
+//!   - Return: `1` if it did, `0` otherwise.
+
+use core::cell::Cell;
+use kernel::hil::reset::{ResetInfo, ResetReason};
+use kernel::{AppId, Driver, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10001;
+
+/// Retained register bit set by `record_panic()`.
+const PANIC_FLAG: u16 = 1 << 15;
+/// Retained register bits holding the boot counter.
+const BOOT_COUNT_MASK: u16 = !PANIC_FLAG;
+
+/// Mark in the retained register that the kernel panicked. Call this from the
+/// board's panic handler, before anything that may reset the chip.
+pub fn record_panic<R: ResetInfo>(reset_info: &R) {
+    let retained = reset_info.read_retained();
+    reset_info.write_retained(retained | PANIC_FLAG);
+}
+
+pub struct BootInfo<'a, R: ResetInfo + 'a> {
+    reset_info: &'a R,
+    reset_reason: Cell<ResetReason>,
+    boot_count: Cell<u16>,
+    last_panicked: Cell<bool>,
+}
+
+impl<'a, R: ResetInfo> BootInfo<'a, R> {
+    pub fn new(reset_info: &'a R) -> BootInfo<'a, R> {
+        BootInfo {
+            reset_info: reset_info,
+            reset_reason: Cell::new(ResetReason::Unknown),
+            boot_count: Cell::new(0),
+            last_panicked: Cell::new(false),
+        }
+    }
+
+    /// Capture the reset state and update the retained register for this
+    /// boot. Must be called exactly once during board initialization.
+    pub fn initialize(&self) {
+        let reason = self.reset_info.reset_reason();
+        let retained = match reason {
+            // The retained register is undefined after these resets.
+            ResetReason::PowerOn | ResetReason::BrownOut => 0,
+            _ => self.reset_info.read_retained(),
+        };
+
+        let count = match retained & BOOT_COUNT_MASK {
+            BOOT_COUNT_MASK => BOOT_COUNT_MASK,
+            count => count + 1,
+        };
+        self.reset_reason.set(reason);
+        self.boot_count.set(count);
+        self.last_panicked.set(retained & PANIC_FLAG != 0);
+
+        self.reset_info.write_retained(count);
+    }
+
+    pub fn reset_reason(&self) -> ResetReason {
+        self.reset_reason.get()
+    }
+
+    pub fn boot_count(&self) -> u16 {
+        self.boot_count.get()
+    }
+
+    pub fn last_panicked(&self) -> bool {
+        self.last_panicked.get()
+    }
+}
+
+impl<'a, R: ResetInfo> Driver for BootInfo<'a, R> {
+    fn command(&self, command_num: usize, _: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: self.reset_reason.get() as usize,
+            },
+
+            2 => ReturnCode::SuccessWithValue {
+                value: self.boot_count.get() as usize,
+            },
+
+            3 => ReturnCode::SuccessWithValue {
+                value: self.last_panicked.get() as usize,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Provides userspace with the attributes stored by the Tock bootloader.
+//!
+//! The bootloader keeps key-value attributes, such as the board name, serial
+//! number and hardware revision, in internal flash where they survive
+//! reflashing the kernel and apps. There are `NUM_ATTRIBUTES` slots of 64
+//! bytes, each an 8-byte NUL-padded key, a length byte and up to 55 bytes of
+//! value. A slot whose length is greater than 55, as in erased flash, is
+//! empty.
+//!
+//! The board decides whether apps may change attributes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut PAGEBUFFER: sam4l::flashcalw::Sam4lPage =
+//!     sam4l::flashcalw::Sam4lPage::new();
+//! let attributes = static_init!(
+//!     capsules::bootloader_attributes::BootloaderAttributes<
+//!         'static,
+//!         sam4l::flashcalw::FLASHCALW,
+//!     >,
+//!     capsules::bootloader_attributes::BootloaderAttributes::new(
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         &mut PAGEBUFFER,
+//!         capsules::bootloader_attributes::ATTRIBUTES_ADDRESS,
+//!         false,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, attributes);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: Buffer for an attribute: the 8-byte key followed by the value.
+//!   Should be at least 63 bytes long.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Callback when a read or write finishes, with the `ReturnCode` and,
+//!   for reads, the length of the value.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: `SUCCESS`.
+//! - `1`: Get the number of attribute slots.
+//! - `2`: Read the attribute in slot `data` into the allowed buffer.
+//!   - Return: `SUCCESS` if the read started, `EINVAL` if the slot does not
+//!     exist, `ENOMEM` if no buffer is allowed, or `EBUSY` if another read or
+//!     write is in progress.
+//! - `3`: Write the attribute in slot `data` from the allowed buffer, with a
+//!   value of `data2` bytes.
+//!   - Return: as for `2`, or `ESIZE` if the value is longer than 55 bytes
+//!     or the buffer is too short, or `ERESERVE` if the board does not allow
+//!     apps to write attributes.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10003;
+
+/// Where the Tock bootloader keeps its attributes.
+pub const ATTRIBUTES_ADDRESS: usize = 0x600;
+pub const NUM_ATTRIBUTES: usize = 16;
+pub const KEY_LEN: usize = 8;
+pub const MAX_VALUE_LEN: usize = 55;
+const ATTRIBUTE_LEN: usize = 64;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Operation {
+    Idle,
+    Read(usize),
+    /// Reading the page holding the slot, to rewrite it with the new value.
+    Write(usize, usize),
+}
+
+pub struct BootloaderAttributes<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    pagebuffer: TakeCell<'static, F::Page>,
+    address: usize,
+    writable: bool,
+    apps: Grant<App>,
+    operation: Cell<Operation>,
+    current_app: Cell<Option<AppId>>,
+}
+
+impl<'a, F: hil::flash::Flash> BootloaderAttributes<'a, F> {
+    /// Read attributes from `flash` at `address`. Apps may change them only
+    /// if `writable` is set.
+    pub fn new(
+        flash: &'a F,
+        pagebuffer: &'static mut F::Page,
+        address: usize,
+        writable: bool,
+        grant: Grant<App>,
+    ) -> BootloaderAttributes<'a, F> {
+        BootloaderAttributes {
+            flash: flash,
+            pagebuffer: TakeCell::new(pagebuffer),
+            address: address,
+            writable: writable,
+            apps: grant,
+            operation: Cell::new(Operation::Idle),
+            current_app: Cell::new(None),
+        }
+    }
+
+    /// Start `operation` on slot `index` for `appid`.
+    fn start(&self, appid: AppId, index: usize, operation: Operation) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if index >= NUM_ATTRIBUTES {
+            return ReturnCode::EINVAL;
+        }
+        self.pagebuffer
+            .take()
+            .map_or(ReturnCode::EBUSY, |pagebuffer| {
+                let page_size = pagebuffer.as_mut().len();
+                let page = (self.address + index * ATTRIBUTE_LEN) / page_size;
+                let res = self.flash.read_page(page, pagebuffer);
+                if res == ReturnCode::SUCCESS {
+                    self.operation.set(operation);
+                    self.current_app.set(Some(appid));
+                }
+                res
+            })
+    }
+
+    /// The offset of slot `index` in its page.
+    fn slot_offset(&self, index: usize, page_size: usize) -> usize {
+        (self.address + index * ATTRIBUTE_LEN) % page_size
+    }
+
+    fn done(&self, res: ReturnCode, len: usize) {
+        self.operation.set(Operation::Idle);
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(res), len, 0));
+            });
+        });
+    }
+}
+
+impl<'a, F: hil::flash::Flash> hil::flash::Client<F> for BootloaderAttributes<'a, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, error: hil::flash::Error) {
+        let appid = match self.current_app.get() {
+            Some(appid) => appid,
+            None => {
+                self.pagebuffer.replace(pagebuffer);
+                return;
+            }
+        };
+        if error != hil::flash::Error::CommandComplete {
+            self.pagebuffer.replace(pagebuffer);
+            self.done(ReturnCode::FAIL, 0);
+            return;
+        }
+
+        let page_size = pagebuffer.as_mut().len();
+        match self.operation.get() {
+            Operation::Read(index) => {
+                let offset = self.slot_offset(index, page_size);
+                let len = {
+                    let slot = &pagebuffer.as_mut()[offset..offset + ATTRIBUTE_LEN];
+                    let len = slot[KEY_LEN] as usize;
+                    let len = if len > MAX_VALUE_LEN { 0 } else { len };
+                    self.apps
+                        .enter(appid, |app, _| {
+                            app.buffer.as_mut().map_or(0, |buffer| {
+                                let buffer = buffer.as_mut();
+                                for (i, byte) in buffer.iter_mut().take(KEY_LEN).enumerate() {
+                                    *byte = if len == 0 { 0 } else { slot[i] };
+                                }
+                                let copy_len = cmp::min(len, buffer.len().saturating_sub(KEY_LEN));
+                                buffer[KEY_LEN..KEY_LEN + copy_len]
+                                    .copy_from_slice(&slot[KEY_LEN + 1..KEY_LEN + 1 + copy_len]);
+                                len
+                            })
+                        })
+                        .unwrap_or(0)
+                };
+                self.pagebuffer.replace(pagebuffer);
+                self.done(ReturnCode::SUCCESS, len);
+            }
+            Operation::Write(index, len) => {
+                let offset = self.slot_offset(index, page_size);
+                let copied = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.buffer.as_ref().map_or(false, |buffer| {
+                            let data = buffer.as_ref();
+                            if data.len() < KEY_LEN + len {
+                                return false;
+                            }
+                            let slot = &mut pagebuffer.as_mut()[offset..offset + ATTRIBUTE_LEN];
+                            slot[..KEY_LEN].copy_from_slice(&data[..KEY_LEN]);
+                            slot[KEY_LEN] = len as u8;
+                            for (i, byte) in slot[KEY_LEN + 1..].iter_mut().enumerate() {
+                                *byte = if i < len { data[KEY_LEN + i] } else { 0 };
+                            }
+                            true
+                        })
+                    })
+                    .unwrap_or(false);
+                if !copied {
+                    self.pagebuffer.replace(pagebuffer);
+                    self.done(ReturnCode::ESIZE, 0);
+                    return;
+                }
+
+                let page = (self.address + index * ATTRIBUTE_LEN) / page_size;
+                let res = self.flash.write_page(page, pagebuffer);
+                if res != ReturnCode::SUCCESS {
+                    self.done(res, 0);
+                }
+            }
+            Operation::Idle => {
+                self.pagebuffer.replace(pagebuffer);
+            }
+        }
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+        if error == hil::flash::Error::CommandComplete {
+            self.done(ReturnCode::SUCCESS, 0);
+        } else {
+            self.done(ReturnCode::FAIL, 0);
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<'a, F: hil::flash::Flash> Driver for BootloaderAttributes<'a, F> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: NUM_ATTRIBUTES,
+            },
+
+            2 | 3 => {
+                let buffer_len = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.buffer.as_ref().map(|buffer| buffer.len())
+                    })
+                    .unwrap_or(None);
+                match buffer_len {
+                    None => ReturnCode::ENOMEM,
+                    Some(_) if command_num == 2 => self.start(appid, data, Operation::Read(data)),
+                    Some(_) if !self.writable => ReturnCode::ERESERVE,
+                    Some(len) if data2 > MAX_VALUE_LEN || len < KEY_LEN + data2 => {
+                        ReturnCode::ESIZE
+                    }
+                    Some(_) => self.start(appid, data, Operation::Write(data, data2)),
+                }
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
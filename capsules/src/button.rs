@@ -50,6 +50,7 @@
 //!   of the button.
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil;
 use kernel::hil::gpio::{Client, InterruptMode};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
@@ -130,18 +131,15 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 => self
                 .apps
-                .enter(app_id, |cntr, _| {
-                    cntr.0 = callback;
-                    ReturnCode::SUCCESS
-                })
-                .unwrap_or_else(|err| err.into()),
+                .enter(app_id, |cntr, _| Ok(mem::replace(&mut cntr.0, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
@@ -0,0 +1,154 @@
+//! Measures the rate of a drifting low-frequency clock against an accurate
+//! reference.
+//!
+//! Boards without a 32 kHz crystal run their alarms from an RC oscillator,
+//! which can be off by a few percent and drifts with temperature. Long
+//! alarms set assuming the nominal frequency end up off by seconds per hour.
+//! `ClockTrim` periodically counts how many ticks of a high-frequency
+//! reference, normally a timer clocked from the high-frequency crystal, pass
+//! during a fixed number of low-frequency ticks, and from that computes the
+//! actual low-frequency rate. The result is available through
+//! `hil::time::MeasuredFrequency`, and the alarm driver reports it to
+//! applications so they convert between time and ticks correctly.
+//!
+//! Both measurements are taken when the low-frequency alarm fires, so
+//! interrupt latency affects the start and end alike and cancels out. A
+//! measurement more than `MAX_ERROR_PERCENT` away from the nominal frequency
+//! is assumed to have been delayed by a long critical section and is
+//! discarded.
+//!
+//! The reference must not wrap during a measurement window: a 16 MHz, 32-bit
+//! counter wraps after 268 seconds, well above `WINDOW_SECONDS`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf5x::timer::ALARM1.start();
+//! let trim_alarm = static_init!(
+//!     capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let clock_trim = static_init!(
+//!     capsules::clock_trim::ClockTrim<
+//!         'static,
+//!         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!         nrf5x::timer::TimerAlarm,
+//!     >,
+//!     capsules::clock_trim::ClockTrim::new(trim_alarm, &nrf5x::timer::ALARM1, 60)
+//! );
+//! trim_alarm.set_client(clock_trim);
+//! alarm.set_measured_frequency(clock_trim);
+//! clock_trim.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{self, Alarm, Frequency};
+
+/// Length of each measurement in seconds of the low-frequency clock.
+pub const WINDOW_SECONDS: u32 = 4;
+
+/// Measurements further than this from the nominal frequency are discarded.
+pub const MAX_ERROR_PERCENT: u32 = 5;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting to start the next measurement.
+    Waiting,
+    /// Counting reference ticks since the window started.
+    Measuring,
+}
+
+pub struct ClockTrim<'a, L: Alarm + 'a, H: Alarm + 'a> {
+    low: &'a L,
+    reference: &'a H,
+    /// Low-frequency ticks between the end of one measurement and the start
+    /// of the next.
+    interval: u32,
+    state: Cell<State>,
+    window_start: Cell<u32>,
+    frequency: Cell<u32>,
+    measurements: Cell<usize>,
+}
+
+impl<'a, L: Alarm, H: Alarm> ClockTrim<'a, L, H> {
+    /// Measure `low` against `reference`, which must already be counting,
+    /// every `interval_seconds`.
+    pub fn new(low: &'a L, reference: &'a H, interval_seconds: u32) -> ClockTrim<'a, L, H> {
+        ClockTrim {
+            low: low,
+            reference: reference,
+            interval: interval_seconds * <L::Frequency>::frequency(),
+            state: Cell::new(State::Idle),
+            window_start: Cell::new(0),
+            frequency: Cell::new(<L::Frequency>::frequency()),
+            measurements: Cell::new(0),
+        }
+    }
+
+    /// Take the first measurement now, and then keep measuring every
+    /// interval.
+    pub fn start(&self) {
+        if self.state.get() == State::Idle {
+            self.state.set(State::Waiting);
+            // Start the window on a tick edge rather than part way through.
+            self.low.set_alarm(self.low.now().wrapping_add(2));
+        }
+    }
+
+    /// Stop measuring. The last measured frequency is still reported.
+    pub fn stop(&self) {
+        self.state.set(State::Idle);
+        self.low.disable();
+    }
+
+    /// The number of measurements that have been accepted.
+    pub fn measurements(&self) -> usize {
+        self.measurements.get()
+    }
+
+    /// Compute the low-frequency rate from `elapsed` reference ticks in one
+    /// window.
+    fn update(&self, elapsed: u32) {
+        let nominal = <L::Frequency>::frequency();
+        if elapsed == 0 {
+            return;
+        }
+        let window = (nominal * WINDOW_SECONDS) as u64;
+        let measured = window * <H::Frequency>::frequency() as u64 / elapsed as u64;
+        let max_error = nominal as u64 * MAX_ERROR_PERCENT as u64 / 100;
+        if measured > nominal as u64 + max_error || measured + max_error < nominal as u64 {
+            return;
+        }
+        self.frequency.set(measured as u32);
+        self.measurements.set(self.measurements.get() + 1);
+    }
+}
+
+impl<'a, L: Alarm, H: Alarm> time::Client for ClockTrim<'a, L, H> {
+    fn fired(&self) {
+        let reference_now = self.reference.now();
+        let alarm = self.low.get_alarm();
+        match self.state.get() {
+            State::Idle => {}
+            State::Waiting => {
+                self.window_start.set(reference_now);
+                self.state.set(State::Measuring);
+                let window = <L::Frequency>::frequency() * WINDOW_SECONDS;
+                self.low.set_alarm(alarm.wrapping_add(window));
+            }
+            State::Measuring => {
+                self.update(reference_now.wrapping_sub(self.window_start.get()));
+                self.state.set(State::Waiting);
+                self.low.set_alarm(alarm.wrapping_add(self.interval));
+            }
+        }
+    }
+}
+
+impl<'a, L: Alarm, H: Alarm> time::MeasuredFrequency for ClockTrim<'a, L, H> {
+    fn measured_frequency(&self) -> u32 {
+        self.frequency.get()
+    }
+}
@@ -36,6 +36,7 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::mem;
 use kernel::common::cells::TakeCell;
 use kernel::hil::uart::{self, Client, UART};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
@@ -250,21 +251,19 @@ impl<'a, U: UART> Driver for Console<'a, U> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             1 /* putstr/write_done */ => {
                 self.apps.enter(app_id, |app, _| {
-                    app.write_callback = callback;
-                    ReturnCode::SUCCESS
-                }).unwrap_or_else(|err| err.into())
+                    Ok(mem::replace(&mut app.write_callback, callback))
+                }).unwrap_or_else(|err| Err(err.into()))
             },
             2 /* getnstr done */ => {
                 self.apps.enter(app_id, |app, _| {
-                    app.read_callback = callback;
-                    ReturnCode::SUCCESS
-                }).unwrap_or_else(|err| err.into())
+                    Ok(mem::replace(&mut app.read_callback, callback))
+                }).unwrap_or_else(|err| Err(err.into()))
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => Err(ReturnCode::ENOSUPPORT)
         }
     }
 
@@ -0,0 +1,233 @@
+//! Records the state of a process that faults to nonvolatile storage.
+//!
+//! When a process faults, the `CrashDump` copies its registers, fault
+//! status, memory map and live stack into a buffer and writes it to a region
+//! of nonvolatile storage set aside by the board. Each fault overwrites the
+//! previous dump. A fault that happens while the previous dump is still
+//! being written is not recorded, but is counted.
+//!
+//! Dumps are copied when the kernel learns of the fault, before the process
+//! is restarted. If the process is configured to panic the kernel on a
+//! fault, the dump is copied but the kernel panics before it is written.
+//!
+//! Format
+//! ------
+//!
+//! All words are 32-bit little-endian.
+//!
+//! ```text
+//! Offset | Contents
+//! ------ | ---------------------------------------------------------------
+//! 0      | Magic, `DUMP_MAGIC`
+//! 4      | Format version, `DUMP_VERSION`
+//! 8      | Length of the dump in bytes, including this header
+//! 12     | Sequence number, counting from 0 at boot
+//! 16     | Process index
+//! 20     | Process name, NUL-padded or truncated to 16 bytes
+//! 36     | r0, r1, r2, r3, r12, lr, pc, xpsr, sp
+//! 72     | CFSR, HFSR, MMFAR, BFAR
+//! 88     | Flash start and end, RAM start and end, application break,
+//!        | kernel memory break and lowest stack pointer
+//! 116    | Number of stack bytes that follow
+//! 120    | Stack contents, starting at sp
+//! ```
+//!
+//! `tools/crash_dump.py` prints a dump read back from the storage.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let crash_dump = static_init!(
+//!     capsules::crash_dump::CrashDump<'static>,
+//!     capsules::crash_dump::CrashDump::new(
+//!         kernel::process_memory::ProcessMemory::new(),
+//!         storage,
+//!         0x3F000,
+//!         0x1000,
+//!         &mut capsules::crash_dump::BUFFER
+//!     )
+//! );
+//! storage.set_client(crash_dump);
+//! crash_dump.enable();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::process_memory::{FaultObserver, ProcessMemory};
+use kernel::{AppId, ReturnCode};
+
+/// "CDMP"
+pub const DUMP_MAGIC: u32 = 0x504D4443;
+pub const DUMP_VERSION: u32 = 1;
+
+const NAME_LEN: usize = 16;
+const HEADER_LEN: usize = 120;
+
+pub static mut BUFFER: [u8; 1024] = [0; 1024];
+
+pub struct CrashDump<'a> {
+    memory: ProcessMemory,
+    storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+    region_start: usize,
+    region_len: usize,
+    buffer: TakeCell<'static, [u8]>,
+    sequence: Cell<u32>,
+    missed: Cell<usize>,
+}
+
+impl<'a> CrashDump<'a> {
+    /// Write dumps to the `region_len` bytes of `storage` at `region_start`.
+    /// Dumps are truncated to the smaller of the region and `buffer`.
+    pub fn new(
+        memory: ProcessMemory,
+        storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+        region_start: usize,
+        region_len: usize,
+        buffer: &'static mut [u8],
+    ) -> CrashDump<'a> {
+        CrashDump {
+            memory: memory,
+            storage: storage,
+            region_start: region_start,
+            region_len: region_len,
+            buffer: TakeCell::new(buffer),
+            sequence: Cell::new(0),
+            missed: Cell::new(0),
+        }
+    }
+
+    /// Start recording faults.
+    pub fn enable(&'static self) {
+        self.memory.set_fault_observer(self);
+    }
+
+    /// The number of faults recorded so far.
+    pub fn dumps(&self) -> u32 {
+        self.sequence.get()
+    }
+
+    /// The number of faults not recorded because a dump was being written or
+    /// the storage could not start the write.
+    pub fn missed(&self) -> usize {
+        self.missed.get()
+    }
+
+    /// Write the dump of `appid` into `buffer`, returning its length.
+    fn serialize(&self, appid: AppId, buffer: &mut [u8]) -> usize {
+        let (layout, registers) = match (self.memory.layout(appid), self.memory.registers(appid)) {
+            (Some(layout), Some(registers)) => (layout, registers),
+            _ => return 0,
+        };
+        if buffer.len() < HEADER_LEN {
+            return 0;
+        }
+
+        write_u32(buffer, 0, DUMP_MAGIC);
+        write_u32(buffer, 4, DUMP_VERSION);
+        write_u32(buffer, 12, self.sequence.get());
+        write_u32(buffer, 16, appid.idx() as u32);
+        let mut offset = 20;
+
+        let name = self.memory.name(appid).unwrap_or("").as_bytes();
+        for i in 0..NAME_LEN {
+            buffer[offset + i] = *name.get(i).unwrap_or(&0);
+        }
+        offset += NAME_LEN;
+
+        let status = self.memory.fault_status();
+        let words = [
+            registers.r0,
+            registers.r1,
+            registers.r2,
+            registers.r3,
+            registers.r12,
+            registers.lr,
+            registers.pc,
+            registers.xpsr,
+            registers.sp,
+            status.cfsr as usize,
+            status.hfsr as usize,
+            status.mmfar as usize,
+            status.bfar as usize,
+            layout.flash_start,
+            layout.flash_end,
+            layout.memory_start,
+            layout.memory_end,
+            layout.app_break,
+            layout.kernel_memory_break,
+            layout.min_stack_pointer,
+        ];
+
+        // The live stack runs from sp up to where the stack started, or to
+        // the application break if that is not known.
+        let stack_top = layout
+            .stack_start
+            .filter(|start| *start > registers.sp)
+            .unwrap_or(layout.app_break);
+        let stack_len = cmp::min(
+            stack_top.saturating_sub(registers.sp),
+            buffer.len() - HEADER_LEN,
+        );
+        let stack_len = match self.memory.read(
+            appid,
+            registers.sp,
+            &mut buffer[HEADER_LEN..HEADER_LEN + stack_len],
+        ) {
+            ReturnCode::SUCCESS => stack_len,
+            _ => 0,
+        };
+
+        for word in words.iter().chain([stack_len].iter()) {
+            write_u32(buffer, offset, *word as u32);
+            offset += 4;
+        }
+
+        let len = HEADER_LEN + stack_len;
+        write_u32(buffer, 8, len as u32);
+        debug_assert_eq!(offset, HEADER_LEN);
+        len
+    }
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, word: u32) {
+    buffer[offset] = word as u8;
+    buffer[offset + 1] = (word >> 8) as u8;
+    buffer[offset + 2] = (word >> 16) as u8;
+    buffer[offset + 3] = (word >> 24) as u8;
+}
+
+impl<'a> FaultObserver for CrashDump<'a> {
+    fn process_faulted(&self, appid: AppId) {
+        match self.buffer.take() {
+            Some(buffer) => {
+                let limit = cmp::min(buffer.len(), self.region_len);
+                let len = self.serialize(appid, &mut buffer[..limit]);
+                if len == 0 {
+                    self.buffer.replace(buffer);
+                    return;
+                }
+                let (rc, buffer) = self.storage.write(buffer, self.region_start, len);
+                if rc == ReturnCode::SUCCESS {
+                    self.sequence.set(self.sequence.get() + 1);
+                } else {
+                    buffer.map(|buffer| self.buffer.replace(buffer));
+                    self.missed.set(self.missed.get() + 1);
+                }
+            }
+            None => self.missed.set(self.missed.get() + 1),
+        }
+    }
+}
+
+impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for CrashDump<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+}
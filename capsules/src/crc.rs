@@ -15,6 +15,15 @@
 //!
 //! ```
 //!
+//! ## Streaming
+//!
+//! Data larger than one allowed buffer can be checksummed in pieces. The
+//! application starts a calculation with command 3, then for each piece
+//! allows a buffer holding it and issues command 4, waiting for the callback
+//! before reusing the buffer. Command 5 delivers the result. The CRC unit
+//! belongs to the application from command 3 until command 5, and other
+//! requests wait until then.
+//!
 //! ## CRC Algorithms
 //!
 //! The capsule supports two general purpose CRC algorithms, as well as a few
@@ -66,6 +75,7 @@
 //! the SAM4L.
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil;
 use kernel::hil::crc::CrcAlg;
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
@@ -90,6 +100,11 @@ pub struct Crc<'a, C: hil::crc::CRC + 'a> {
     crc_unit: &'a C,
     apps: Grant<App>,
     serving_app: Cell<Option<AppId>>,
+    /// The application with a streaming calculation in progress
+    stream_app: Cell<Option<AppId>>,
+    /// Whether an update of the streaming calculation is in progress, and
+    /// how many bytes it covers
+    update_len: Cell<Option<usize>>,
 }
 
 impl<'a, C: hil::crc::CRC> Crc<'a, C> {
@@ -112,11 +127,13 @@ impl<'a, C: hil::crc::CRC> Crc<'a, C> {
             crc_unit: crc_unit,
             apps: apps,
             serving_app: Cell::new(None),
+            stream_app: Cell::new(None),
+            update_len: Cell::new(None),
         }
     }
 
     fn serve_waiting_apps(&self) {
-        if self.serving_app.get().is_some() {
+        if self.serving_app.get().is_some() || self.stream_app.get().is_some() {
             // A computation is in progress
             return;
         }
@@ -210,17 +227,16 @@ impl<'a, C: hil::crc::CRC> Driver for Crc<'a, C> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // Set callback for CRC result
             0 => self
                 .apps
                 .enter(app_id, |app, _| {
-                    app.callback = callback;
-                    ReturnCode::SUCCESS
+                    Ok(mem::replace(&mut app.callback, callback))
                 })
-                .unwrap_or_else(|err| err.into()),
-            _ => ReturnCode::ENOSUPPORT,
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
@@ -251,6 +267,20 @@ impl<'a, C: hil::crc::CRC> Driver for Crc<'a, C> {
     ///       queued and the callback will be invoked when the CRC
     ///       computation is complete.
     ///
+    ///   *   `3`: Starts a streaming computation using the algorithm
+    ///       given by the driver-specific argument. Returns `EBUSY` if
+    ///       the CRC unit is in use.
+    ///
+    ///   *   `4`: Adds the buffer provided by `allow` to the streaming
+    ///       computation. The callback is invoked with the number of
+    ///       bytes added as its `result` once the buffer may be reused.
+    ///       Returns `EOFF` if this application has not started a
+    ///       streaming computation and `EBUSY` if an update is already
+    ///       in progress.
+    ///
+    ///   *   `5`: Finishes the streaming computation. The callback is
+    ///       invoked with its result.
+    ///
     /// ### Algorithm
     ///
     /// The CRC algorithms supported by this driver are listed below.  In
@@ -316,12 +346,97 @@ impl<'a, C: hil::crc::CRC> Driver for Crc<'a, C> {
                 result
             }
 
+            // Start a streaming computation
+            3 => match alg_from_user_int(algorithm) {
+                Some(alg) => {
+                    if self.serving_app.get().is_some() || self.stream_app.get().is_some() {
+                        ReturnCode::EBUSY
+                    } else {
+                        let r = self.crc_unit.init(alg);
+                        if r == ReturnCode::SUCCESS {
+                            self.stream_app.set(Some(appid));
+                        }
+                        r
+                    }
+                }
+                None => ReturnCode::EINVAL,
+            },
+
+            // Add the allowed buffer to the streaming computation
+            4 => {
+                if self.stream_app.get() != Some(appid) {
+                    ReturnCode::EOFF
+                } else if self.update_len.get().is_some() {
+                    ReturnCode::EBUSY
+                } else {
+                    self.apps
+                        .enter(appid, |app, _| {
+                            if app.callback.is_none() {
+                                return ReturnCode::EINVAL;
+                            }
+                            match app.buffer.take() {
+                                Some(buffer) => {
+                                    let r = self.crc_unit.update(buffer.as_ref());
+                                    if r == ReturnCode::SUCCESS {
+                                        self.update_len.set(Some(buffer.len()));
+                                    }
+                                    app.buffer = Some(buffer);
+                                    r
+                                }
+                                None => ReturnCode::EINVAL,
+                            }
+                        })
+                        .unwrap_or_else(|err| err.into())
+                }
+            }
+
+            // Finish the streaming computation
+            5 => {
+                if self.stream_app.get() != Some(appid) {
+                    ReturnCode::EOFF
+                } else if self.update_len.get().is_some() {
+                    ReturnCode::EBUSY
+                } else {
+                    let result = self.crc_unit.finalize();
+                    self.stream_app.set(None);
+                    let r = self
+                        .apps
+                        .enter(appid, |app, _| {
+                            if let Some(mut callback) = app.callback {
+                                callback.schedule(
+                                    From::from(ReturnCode::SUCCESS),
+                                    result as usize,
+                                    0,
+                                );
+                            }
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or_else(|err| err.into());
+                    self.serve_waiting_apps();
+                    r
+                }
+            }
+
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 }
 
 impl<'a, C: hil::crc::CRC> hil::crc::Client for Crc<'a, C> {
+    fn update_done(&self) {
+        let len = self.update_len.take().unwrap_or(0);
+        if let Some(appid) = self.stream_app.get() {
+            self.apps
+                .enter(appid, |app, _| {
+                    if let Some(mut callback) = app.callback {
+                        callback.schedule(From::from(ReturnCode::SUCCESS), len, 0);
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into());
+        }
+    }
+
     fn receive_result(&self, result: u32) {
         if let Some(appid) = self.serving_app.get() {
             self.apps
@@ -0,0 +1,138 @@
+//! Provides the kernel and userspace with a stable identifier for the board.
+//!
+//! The identifier comes from the chip's factory registers through
+//! `hil::device_id::DeviceId`, so it stays the same across reboots and
+//! reflashing. The capsule also derives an EUI-64 from it for use as a
+//! hardware address, for example for an 802.15.4 radio. The chip vendor has
+//! not assigned these addresses from an IEEE block, so the EUI-64 is marked
+//! as locally administered.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let device_id = static_init!(
+//!     capsules::device_id::DeviceIdentity<'static, sam4l::serial_num::SerialNum>,
+//!     capsules::device_id::DeviceIdentity::new(
+//!         &sam4l::serial_num::SERIAL_NUM,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! mac_device.set_address_long(device_id.eui64());
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: Buffer that receives the identifier or EUI-64. Must be at least 8
+//!   bytes long.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: `SUCCESS`.
+//! - `1`: Copy the 64-bit device identifier, most significant byte first,
+//!   into the allowed buffer.
+//!   - Return: `8`, or `ENOMEM` if no buffer is allowed, or `ESIZE` if the
+//!     buffer is shorter than 8 bytes.
+//! - `2`: Copy the EUI-64 into the allowed buffer.
+//!   - Return: as for `1`.
+
+use kernel::hil::device_id::DeviceId;
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10004;
+
+pub const ID_LEN: usize = 8;
+
+/// Set in the first byte of an EUI-64 that was not assigned by the IEEE.
+const LOCALLY_ADMINISTERED: u8 = 0x02;
+/// Set in the first byte of a group address.
+const MULTICAST: u8 = 0x01;
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct DeviceIdentity<'a, D: DeviceId + 'a> {
+    device: &'a D,
+    apps: Grant<App>,
+}
+
+impl<'a, D: DeviceId> DeviceIdentity<'a, D> {
+    pub fn new(device: &'a D, grant: Grant<App>) -> DeviceIdentity<'a, D> {
+        DeviceIdentity {
+            device: device,
+            apps: grant,
+        }
+    }
+
+    /// The 64-bit identifier of the chip.
+    pub fn device_id(&self) -> u64 {
+        self.device.device_id()
+    }
+
+    /// The EUI-64 derived from the identifier, most significant byte first.
+    pub fn eui64(&self) -> [u8; ID_LEN] {
+        let mut eui64 = id_bytes(self.device_id());
+        eui64[0] = (eui64[0] | LOCALLY_ADMINISTERED) & !MULTICAST;
+        eui64
+    }
+
+    /// Copy `id` into the buffer `appid` has allowed.
+    fn copy_to_app(&self, appid: AppId, id: [u8; ID_LEN]) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| match app.buffer {
+                None => ReturnCode::ENOMEM,
+                Some(ref mut buffer) if buffer.len() < ID_LEN => ReturnCode::ESIZE,
+                Some(ref mut buffer) => {
+                    buffer.as_mut()[..ID_LEN].copy_from_slice(&id);
+                    ReturnCode::SuccessWithValue { value: ID_LEN }
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+fn id_bytes(id: u64) -> [u8; ID_LEN] {
+    let mut bytes = [0; ID_LEN];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (id >> (8 * (ID_LEN - 1 - i))) as u8;
+    }
+    bytes
+}
+
+impl<'a, D: DeviceId> Driver for DeviceIdentity<'a, D> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.copy_to_app(appid, id_bytes(self.device_id())),
+            2 => self.copy_to_app(appid, self.eui64()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Shared userland driver for distance sensors.
+//!
+//! You need a device that provides the `hil::sensors::Distance` trait.
+//!
+//! ```rust
+//! let distance = static_init!(
+//!     capsules::distance::DistanceSensor<'static>,
+//!     capsules::distance::DistanceSensor::new(hcsr04, kernel::Grant::create())
+//! );
+//! hil::sensors::Distance::set_client(hcsr04, distance);
+//! ```
+
+use core::cell::Cell;
+use core::mem;
+use kernel::hil;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x60006;
+
+/// Per-process metadata
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct DistanceSensor<'a> {
+    sensor: &'a hil::sensors::Distance,
+    command_pending: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a> DistanceSensor<'a> {
+    pub fn new(sensor: &'a hil::sensors::Distance, grant: Grant<App>) -> DistanceSensor {
+        DistanceSensor {
+            sensor: sensor,
+            command_pending: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn enqueue_sensor_reading(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    ReturnCode::EBUSY
+                } else {
+                    app.pending = true;
+                    if !self.command_pending.get() {
+                        self.command_pending.set(true);
+                        self.sensor.read_distance();
+                    }
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a> Driver for DistanceSensor<'a> {
+    /// Subscribe to distance readings
+    ///
+    /// ### `subscribe`
+    ///
+    /// - `0`: Subscribe to distance readings. The callback signature is
+    /// `fn(millimeters: usize, in_range: usize)`, where `millimeters` is the
+    /// distance to the nearest object and `in_range` is `0` if no object was
+    /// within range of the sensor.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Initiate distance readings
+    ///
+    /// Sensor readings are coalesced if processes request them concurrently.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Check driver presence
+    /// - `1`: Start a distance reading
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 => self.enqueue_sensor_reading(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> hil::sensors::DistanceClient for DistanceSensor<'a> {
+    fn callback(&self, millimeters: Option<usize>) {
+        self.command_pending.set(false);
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                if let Some(mut callback) = app.callback {
+                    callback.schedule(millimeters.unwrap_or(0), millimeters.is_some() as usize, 0);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,420 @@
+//! Provides userspace with Enhanced ShockBurst (ESB), the proprietary 2.4 GHz
+//! packet protocol of nRF24 transceivers.
+//!
+//! This lets an app take the place of either end of an existing nRF24 link:
+//! it sets the address, channel and data rate the link uses, then sends
+//! packets of up to 32 bytes or receives them. With acknowledgements enabled
+//! each packet sent waits for the receiver to acknowledge it, and is
+//! retransmitted up to the number of times the app chooses before the app is
+//! told it was not acknowledged.
+//!
+//! Each app has its own configuration, which is applied when the app starts
+//! sending or receiving. One app uses the radio at a time: the others get
+//! `EBUSY` while an app is sending or receiving.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::esb::Esb` trait, and an alarm
+//! to time out waiting for acknowledgements.
+//!
+//! ```rust
+//! let esb = static_init!(
+//!     capsules::esb::EsbDriver<'static, nrf52::radio::Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::esb::EsbDriver::new(
+//!         &nrf52::radio::RADIO,
+//!         esb_virtual_alarm,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! kernel::hil::esb::Esb::set_transmit_client(&nrf52::radio::RADIO, esb);
+//! kernel::hil::esb::Esb::set_receive_client(&nrf52::radio::RADIO, esb);
+//! esb_virtual_alarm.set_client(esb);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Allow
+//!
+//! - `0`: The packet to send.
+//! - `1`: Where received packets are written. A packet longer than the
+//!   buffer is cut short.
+//! - `2`: The address, for command `1`.
+//!
+//! ### Subscribe
+//!
+//! - `0`: A packet was sent. The callback signature is
+//!   `fn(result: usize, retransmits: usize)`, where `result` is `SUCCESS`, or
+//!   `ENOACK` if acknowledgements are enabled and none arrived, and
+//!   `retransmits` is how many times the packet was sent again.
+//! - `1`: A packet was received. The callback signature is
+//!   `fn(len: usize)`, where `len` is the length of its payload.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Set the address to the first `data1` bytes, 3 to 5, of the address
+//!   buffer, in the order they are sent. The default is `E7E7E7E7E7`.
+//! - `2`: Set the channel to `data1`, from 0 to 100, which is 2400 MHz plus
+//!   that many MHz, and the data rate to `data2`: `0` for 1 Mbit/s, `1` for
+//!   2 Mbit/s and `2` for 250 kbit/s. The default is channel 2 at 2 Mbit/s.
+//! - `3`: Enable acknowledgements if `data1` is 1, or disable them if it is
+//!   0, and retransmit a packet that is not acknowledged up to `data2` times.
+//!   The default is enabled, with 3 retransmits.
+//! - `4`: Send the first `data1` bytes of the packet buffer.
+//! - `5`: Start receiving packets.
+//! - `6`: Stop receiving packets.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::hil::esb::{self, DataRate, Esb};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30004;
+
+/// Time allowed, on top of sending the packet and its acknowledgement, for
+/// the radios to turn round.
+const ACK_TURNAROUND_US: u32 = 500;
+
+/// The bits of a packet that are not payload: the preamble, at most 5 bytes
+/// of address, the packet control field and the CRC.
+const PACKET_OVERHEAD_BITS: u32 = (1 + 5 + 2 + 2) * 8;
+
+pub struct App {
+    tx_callback: Option<Callback>,
+    rx_callback: Option<Callback>,
+    tx_buffer: Option<AppSlice<Shared, u8>>,
+    rx_buffer: Option<AppSlice<Shared, u8>>,
+    address_buffer: Option<AppSlice<Shared, u8>>,
+    address: [u8; 5],
+    address_len: usize,
+    channel: u8,
+    rate: DataRate,
+    auto_ack: bool,
+    retransmits: usize,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            tx_callback: None,
+            rx_callback: None,
+            tx_buffer: None,
+            rx_buffer: None,
+            address_buffer: None,
+            address: [0xe7; 5],
+            address_len: 5,
+            channel: 2,
+            rate: DataRate::Rate2Mbit,
+            auto_ack: true,
+            retransmits: 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Sending a packet, with the number of retransmits made so far
+    Transmitting(usize),
+    Receiving,
+}
+
+pub struct EsbDriver<'a, R: Esb + 'a, A: Alarm + 'a> {
+    radio: &'a R,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// The app using the radio
+    current_app: Cell<Option<AppId>>,
+    state: Cell<State>,
+    /// The payload length of the packet being sent
+    tx_len: Cell<usize>,
+}
+
+impl<'a, R: Esb + 'a, A: Alarm + 'a> EsbDriver<'a, R, A> {
+    pub fn new(radio: &'a R, alarm: &'a A, grant: Grant<App>) -> EsbDriver<'a, R, A> {
+        EsbDriver {
+            radio: radio,
+            alarm: alarm,
+            apps: grant,
+            current_app: Cell::new(None),
+            state: Cell::new(State::Idle),
+            tx_len: Cell::new(0),
+        }
+    }
+
+    fn configure_radio(&self, app: &App) {
+        self.radio.set_address(&app.address[..app.address_len]);
+        self.radio.set_channel(app.channel, app.rate);
+        self.radio.set_auto_ack(app.auto_ack);
+    }
+
+    fn set_address(&self, appid: AppId, len: usize) -> ReturnCode {
+        if len < 3 || len > 5 {
+            return ReturnCode::EINVAL;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let mut address = [0; 5];
+                match app.address_buffer {
+                    Some(ref slice) if slice.len() >= len => {
+                        address[..len].copy_from_slice(&slice.as_ref()[..len]);
+                    }
+                    _ => return ReturnCode::EINVAL,
+                }
+                app.address = address;
+                app.address_len = len;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn set_channel(&self, appid: AppId, channel: usize, rate: usize) -> ReturnCode {
+        let rate = match rate {
+            0 => DataRate::Rate1Mbit,
+            1 => DataRate::Rate2Mbit,
+            2 => DataRate::Rate250Kbit,
+            _ => return ReturnCode::EINVAL,
+        };
+        if channel > 100 {
+            return ReturnCode::EINVAL;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.channel = channel as u8;
+                app.rate = rate;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn set_auto_ack(&self, appid: AppId, enabled: usize, retransmits: usize) -> ReturnCode {
+        if enabled > 1 {
+            return ReturnCode::EINVAL;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.auto_ack = enabled == 1;
+                app.retransmits = retransmits;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn transmit(&self, appid: AppId, len: usize) -> ReturnCode {
+        if self.current_app.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let mut payload = [0; esb::MAX_PAYLOAD_LEN];
+                match app.tx_buffer {
+                    Some(ref slice) if slice.len() >= len => {
+                        if len > payload.len() {
+                            return ReturnCode::ESIZE;
+                        }
+                        payload[..len].copy_from_slice(&slice.as_ref()[..len]);
+                    }
+                    _ => return ReturnCode::EINVAL,
+                }
+                self.configure_radio(app);
+                let result = self.radio.transmit(&payload[..len]);
+                if result == ReturnCode::SUCCESS {
+                    self.current_app.set(Some(appid));
+                    self.state.set(State::Transmitting(0));
+                    self.tx_len.set(len);
+                    if app.auto_ack {
+                        self.start_ack_timeout(app.rate, len);
+                    }
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn start_ack_timeout(&self, rate: DataRate, payload_len: usize) {
+        let kbps = match rate {
+            DataRate::Rate1Mbit => 1000,
+            DataRate::Rate2Mbit => 2000,
+            DataRate::Rate250Kbit => 250,
+        };
+        let bits = 2 * PACKET_OVERHEAD_BITS + payload_len as u32 * 8;
+        let us = ACK_TURNAROUND_US + bits * 1000 / kbps;
+        let ticks = (<A::Frequency>::frequency() as u64 * us as u64 / 1_000_000) as u32;
+        // An alarm less than a tick away might not fire
+        let ticks = cmp::max(ticks, 2);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn start_receive(&self, appid: AppId) -> ReturnCode {
+        if self.current_app.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                self.configure_radio(app);
+                let result = self.radio.start_receive();
+                if result == ReturnCode::SUCCESS {
+                    self.current_app.set(Some(appid));
+                    self.state.set(State::Receiving);
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn stop_receive(&self, appid: AppId) -> ReturnCode {
+        if self.current_app.get() != Some(appid) || self.state.get() != State::Receiving {
+            return ReturnCode::EINVAL;
+        }
+        self.radio.stop();
+        self.current_app.set(None);
+        self.state.set(State::Idle);
+        ReturnCode::SUCCESS
+    }
+
+    /// Tell the app sending a packet that it is done.
+    fn transmit_finished(&self, result: ReturnCode, retransmits: usize) {
+        self.state.set(State::Idle);
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback
+                    .as_mut()
+                    .map(|cb| cb.schedule(isize::from(result) as usize, retransmits, 0));
+            });
+        });
+    }
+}
+
+impl<'a, R: Esb + 'a, A: Alarm + 'a> esb::TxClient for EsbDriver<'a, R, A> {
+    fn transmit_done(&self, result: ReturnCode) {
+        if let State::Transmitting(retransmits) = self.state.get() {
+            self.alarm.disable();
+            self.transmit_finished(result, retransmits);
+        }
+    }
+}
+
+impl<'a, R: Esb + 'a, A: Alarm + 'a> esb::RxClient for EsbDriver<'a, R, A> {
+    fn receive(&self, payload: &[u8]) {
+        if self.state.get() != State::Receiving {
+            return;
+        }
+        self.current_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let len = app.rx_buffer.as_mut().map_or(0, |buffer| {
+                    let len = cmp::min(buffer.len(), payload.len());
+                    buffer.as_mut()[..len].copy_from_slice(&payload[..len]);
+                    len
+                });
+                app.rx_callback.as_mut().map(|cb| cb.schedule(len, 0, 0));
+            });
+        });
+    }
+}
+
+impl<'a, R: Esb + 'a, A: Alarm + 'a> time::Client for EsbDriver<'a, R, A> {
+    /// No acknowledgement arrived in time
+    fn fired(&self) {
+        if let State::Transmitting(retransmits) = self.state.get() {
+            self.radio.stop();
+            let app = self.current_app.get().and_then(|appid| {
+                self.apps
+                    .enter(appid, |app, _| (app.retransmits, app.rate))
+                    .ok()
+            });
+            match app {
+                Some((max_retransmits, rate)) if retransmits < max_retransmits => {
+                    if self.radio.retransmit() == ReturnCode::SUCCESS {
+                        self.state.set(State::Transmitting(retransmits + 1));
+                        self.start_ack_timeout(rate, self.tx_len.get());
+                    } else {
+                        self.transmit_finished(ReturnCode::FAIL, retransmits);
+                    }
+                }
+                _ => self.transmit_finished(ReturnCode::ENOACK, retransmits),
+            }
+        }
+    }
+}
+
+impl<'a, R: Esb + 'a, A: Alarm + 'a> Driver for EsbDriver<'a, R, A> {
+    /// Share buffers with the driver
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The packet to send
+    /// - `1`: Where received packets are written
+    /// - `2`: The address
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.tx_buffer = slice,
+                    1 => app.rx_buffer = slice,
+                    2 => app.address_buffer = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Subscribe to packet events
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A packet was sent
+    /// - `1`: A packet was received
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        self.apps
+            .enter(appid, |app, _| match subscribe_num {
+                0 => Ok(mem::replace(&mut app.tx_callback, callback)),
+                1 => Ok(mem::replace(&mut app.rx_callback, callback)),
+                _ => Err(ReturnCode::ENOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Configure the link, and send and receive packets
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check
+    /// - `1`: Set the address to the first `data1` bytes of the address
+    ///   buffer
+    /// - `2`: Set the channel to `data1` and the data rate to `data2`
+    /// - `3`: Enable or disable acknowledgements, and set the number of
+    ///   retransmits to `data2`
+    /// - `4`: Send `data1` bytes of the packet buffer
+    /// - `5`: Start receiving packets
+    /// - `6`: Stop receiving packets
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.set_address(appid, data1),
+            2 => self.set_channel(appid, data1, data2),
+            3 => self.set_auto_ack(appid, data1, data2),
+            4 => self.transmit(appid, data1),
+            5 => self.start_receive(appid),
+            6 => self.stop_receive(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
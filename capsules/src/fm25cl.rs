@@ -132,12 +132,17 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> FM25CL<'a, S> {
         );
     }
 
-    pub fn write(&self, address: u16, buffer: &'static mut [u8], len: u16) -> ReturnCode {
+    pub fn write(
+        &self,
+        address: u16,
+        buffer: &'static mut [u8],
+        len: u16,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         self.configure_spi();
 
-        self.txbuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, move |txbuffer| {
+        match self.txbuffer.take() {
+            None => (ReturnCode::ERESERVE, Some(buffer)),
+            Some(txbuffer) => {
                 txbuffer[0] = Opcodes::WriteEnable as u8;
 
                 let write_len = cmp::min(txbuffer.len(), len as usize);
@@ -149,33 +154,54 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> FM25CL<'a, S> {
                 self.client_write_len.set(write_len as u16);
 
                 self.state.set(State::WriteEnable);
-                self.spi.read_write_bytes(txbuffer, None, 1)
-            })
+                let rc = self.spi.read_write_bytes(txbuffer, None, 1);
+                self.started(rc)
+            }
+        }
     }
 
-    pub fn read(&self, address: u16, buffer: &'static mut [u8], len: u16) -> ReturnCode {
+    pub fn read(
+        &self,
+        address: u16,
+        buffer: &'static mut [u8],
+        len: u16,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         self.configure_spi();
 
-        self.txbuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, |txbuffer| {
-                self.rxbuffer
-                    .take()
-                    .map_or(ReturnCode::ERESERVE, move |rxbuffer| {
-                        txbuffer[0] = Opcodes::ReadMemory as u8;
-                        txbuffer[1] = ((address >> 8) & 0xFF) as u8;
-                        txbuffer[2] = (address & 0xFF) as u8;
+        match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(txbuffer), Some(rxbuffer)) => {
+                txbuffer[0] = Opcodes::ReadMemory as u8;
+                txbuffer[1] = ((address >> 8) & 0xFF) as u8;
+                txbuffer[2] = (address & 0xFF) as u8;
 
-                        // Save the user buffer for later
-                        self.client_buffer.replace(buffer);
+                // Save the user buffer for later
+                self.client_buffer.replace(buffer);
 
-                        let read_len = cmp::min(rxbuffer.len() - 3, len as usize);
+                let read_len = cmp::min(rxbuffer.len() - 3, len as usize);
 
-                        self.state.set(State::ReadMemory);
-                        self.spi
-                            .read_write_bytes(txbuffer, Some(rxbuffer), read_len + 3)
-                    })
-            })
+                self.state.set(State::ReadMemory);
+                let rc = self
+                    .spi
+                    .read_write_bytes(txbuffer, Some(rxbuffer), read_len + 3);
+                self.started(rc)
+            }
+            (txbuffer, rxbuffer) => {
+                txbuffer.map(|txbuffer| self.txbuffer.replace(txbuffer));
+                rxbuffer.map(|rxbuffer| self.rxbuffer.replace(rxbuffer));
+                (ReturnCode::ERESERVE, Some(buffer))
+            }
+        }
+    }
+
+    /// If the SPI transfer that starts a read or write could not be started,
+    /// go back to idle and hand the client's buffer back.
+    fn started(&self, rc: ReturnCode) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rc == ReturnCode::SUCCESS {
+            (rc, None)
+        } else {
+            self.state.set(State::Idle);
+            (rc, self.client_buffer.take())
+        }
     }
 }
 
@@ -298,11 +324,21 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> hil::nonvolatile_storage::Nonvolatil
         self.client.set(Some(client));
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         self.read(address as u16, buffer, length as u16)
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         self.write(address as u16, buffer, length as u16)
     }
 }
@@ -20,11 +20,12 @@
 //! ```
 
 use core::cell::Cell;
-use kernel::common::cells::TakeCell;
+use i2c_transaction::{I2CTransaction, RegOp};
 use kernel::hil;
 use kernel::hil::gpio;
 use kernel::hil::i2c::{Error, I2CClient, I2CDevice};
 use kernel::ReturnCode;
+use sensor_probe::Probe;
 
 pub static mut BUF: [u8; 6] = [0; 6];
 
@@ -147,6 +148,36 @@ enum Registers {
     AFfmtThsZLsb = 0x78,
 }
 
+/// Configure the data ready interrupt.
+/// Detects the FXOS8700CQ at its default address by its `WHO_AM_I` value.
+pub const PROBE: Probe = Probe {
+    address: 0x1e,
+    register: Registers::WhoAmI as u8,
+    mask: 0xff,
+    expected: 0xc7,
+};
+
+const SETUP_INTERRUPT: &[RegOp] = &[RegOp::Write(&[
+    Registers::CtrlReg4 as u8,
+    1, // CtrlReg4 data ready interrupt
+    1, // CtrlReg5 drdy on pin 1
+])];
+
+/// Enable the accelerometer.
+const ACTIVATE: &[RegOp] = &[RegOp::Write(&[Registers::CtrlReg1 as u8, 1])];
+
+/// Read the 6 accel registers for xyz, then put the chip into standby mode.
+const READ_ACCEL: &[RegOp] = &[
+    RegOp::WriteRead(&[Registers::OutXMsb as u8], 6),
+    RegOp::Write(&[Registers::CtrlReg1 as u8, 0]), // Set the active bit to 0.
+];
+
+/// Enable the magnetometer and take a one-shot reading.
+const READ_MAG: &[RegOp] = &[
+    RegOp::Write(&[Registers::MCtrlReg1 as u8, 0b00100001]),
+    RegOp::WriteRead(&[Registers::MOutXMsb as u8], 6),
+];
+
 #[derive(Clone, Copy, PartialEq)]
 enum State {
     /// Sensor is in standby mode
@@ -161,24 +192,17 @@ enum State {
     /// Activate sensor to take readings
     ReadAccelWaiting,
 
-    /// Reading accelerometer data
+    /// Reading accelerometer data and deactivating the sensor
     ReadAccelReading,
 
-    /// Deactivate sensor
-    ReadAccelDeactivating(i16, i16, i16),
-
-    /// Configuring reading the magnetometer
-    ReadMagStart,
-
-    /// Have the magnetometer values and sending them to application
-    ReadMagValues,
+    /// Reading the magnetometer
+    ReadMag,
 }
 
 pub struct Fxos8700cq<'a> {
-    i2c: &'a I2CDevice,
+    transaction: I2CTransaction<'a>,
     interrupt_pin1: &'a gpio::Pin,
     state: Cell<State>,
-    buffer: TakeCell<'static, [u8]>,
     callback: Cell<Option<&'static hil::sensors::NineDofClient>>,
 }
 
@@ -189,123 +213,100 @@ impl<'a> Fxos8700cq<'a> {
         buffer: &'static mut [u8],
     ) -> Fxos8700cq<'a> {
         Fxos8700cq {
-            i2c: i2c,
+            transaction: I2CTransaction::new(i2c, buffer),
             interrupt_pin1: interrupt_pin1,
             state: Cell::new(State::Disabled),
-            buffer: TakeCell::new(buffer),
             callback: Cell::new(None),
         }
     }
 
+    fn start(&self, ops: &'static [RegOp], state: State) {
+        if self.transaction.start(ops) == ReturnCode::SUCCESS {
+            self.state.set(state);
+        } else {
+            self.state.set(State::Disabled);
+        }
+    }
+
     fn start_read_accel(&self) {
+        if self.state.get() != State::Disabled {
+            return;
+        }
         // Need an interrupt pin
         self.interrupt_pin1.make_input();
-
-        self.buffer.take().map(|buf| {
-            self.i2c.enable();
-            // Configure the data ready interrupt.
-            buf[0] = Registers::CtrlReg4 as u8;
-            buf[1] = 1; // CtrlReg4 data ready interrupt
-            buf[2] = 1; // CtrlReg5 drdy on pin 1
-            self.i2c.write(buf, 3);
-            self.state.set(State::ReadAccelSetup);
-        });
+        self.start(SETUP_INTERRUPT, State::ReadAccelSetup);
     }
 
     fn start_read_magnetometer(&self) {
-        self.buffer.take().map(|buf| {
-            self.i2c.enable();
-            // Configure the magnetometer.
-            buf[0] = Registers::MCtrlReg1 as u8;
-            buf[1] = 0b00100001; // Enable magnetometer and one-shot read.
-            self.i2c.write(buf, 2);
-            self.state.set(State::ReadMagStart);
-        });
+        if self.state.get() == State::Disabled {
+            self.start(READ_MAG, State::ReadMag);
+        }
     }
 }
 
 impl<'a> gpio::Client for Fxos8700cq<'a> {
     fn fired(&self, _: usize) {
-        self.buffer.take().map(|buffer| {
+        if self.state.get() == State::ReadAccelWaiting {
             self.interrupt_pin1.disable_interrupt();
 
             // When we get this interrupt we can read the sample.
-            self.i2c.enable();
-            buffer[0] = Registers::OutXMsb as u8;
-            self.i2c.write_read(buffer, 1, 6); // read 6 accel registers for xyz
-            self.state.set(State::ReadAccelReading);
-        });
+            self.start(READ_ACCEL, State::ReadAccelReading);
+        }
     }
 }
 
 impl<'a> I2CClient for Fxos8700cq<'a> {
-    fn command_complete(&self, buffer: &'static mut [u8], _error: Error) {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        let (data, error) = match self.transaction.command_complete(buffer, error) {
+            Some(result) => result,
+            None => return,
+        };
+        if error != Error::CommandComplete {
+            self.interrupt_pin1.disable_interrupt();
+            self.state.set(State::Disabled);
+            return;
+        }
+        let data = data.as_slice();
+
         match self.state.get() {
             State::ReadAccelSetup => {
                 // Setup the interrupt so we know when the sample is ready
                 self.interrupt_pin1
                     .enable_interrupt(0, gpio::InterruptMode::FallingEdge);
-
-                // Enable the accelerometer.
-                buffer[0] = Registers::CtrlReg1 as u8;
-                buffer[1] = 1;
-                self.i2c.write(buffer, 2);
-                self.state.set(State::ReadAccelWait);
+                self.start(ACTIVATE, State::ReadAccelWait);
             }
             State::ReadAccelWait => {
                 if self.interrupt_pin1.read() == false {
                     // Sample is already ready.
                     self.interrupt_pin1.disable_interrupt();
-                    buffer[0] = Registers::OutXMsb as u8;
-                    self.i2c.write_read(buffer, 1, 6); // read 6 accel registers for xyz
-                    self.state.set(State::ReadAccelReading);
+                    self.start(READ_ACCEL, State::ReadAccelReading);
                 } else {
                     // Wait for the interrupt to trigger
-                    self.buffer.replace(buffer);
-                    self.i2c.disable();
                     self.state.set(State::ReadAccelWaiting);
                 }
             }
             State::ReadAccelReading => {
-                let x = (((buffer[0] as i16) << 8) | buffer[1] as i16) >> 2;
-                let y = (((buffer[2] as i16) << 8) | buffer[3] as i16) >> 2;
-                let z = (((buffer[4] as i16) << 8) | buffer[5] as i16) >> 2;
+                let x = (((data[0] as i16) << 8) | data[1] as i16) >> 2;
+                let y = (((data[2] as i16) << 8) | data[3] as i16) >> 2;
+                let z = (((data[4] as i16) << 8) | data[5] as i16) >> 2;
 
                 let x = ((x as isize) * 244) / 1000;
                 let y = ((y as isize) * 244) / 1000;
                 let z = ((z as isize) * 244) / 1000;
 
-                // Now put the chip into standby mode.
-                buffer[0] = Registers::CtrlReg1 as u8;
-                buffer[1] = 0; // Set the active bit to 0.
-                self.i2c.write(buffer, 2);
-                self.state
-                    .set(State::ReadAccelDeactivating(x as i16, y as i16, z as i16));
-            }
-            State::ReadAccelDeactivating(x, y, z) => {
-                self.i2c.disable();
                 self.state.set(State::Disabled);
-                self.buffer.replace(buffer);
                 self.callback.get().map(|cb| {
                     cb.callback(x as usize, y as usize, z as usize);
                 });
             }
-            State::ReadMagStart => {
-                // One shot measurement taken, now read result.
-                buffer[0] = Registers::MOutXMsb as u8;
-                self.state.set(State::ReadMagValues);
-                self.i2c.write_read(buffer, 1, 6);
-            }
-            State::ReadMagValues => {
-                let x = (((buffer[0] as u16) << 8) | buffer[1] as u16) as i16;
-                let y = (((buffer[2] as u16) << 8) | buffer[3] as u16) as i16;
-                let z = (((buffer[4] as u16) << 8) | buffer[5] as u16) as i16;
+            State::ReadMag => {
+                let x = (((data[0] as u16) << 8) | data[1] as u16) as i16;
+                let y = (((data[2] as u16) << 8) | data[3] as u16) as i16;
+                let z = (((data[4] as u16) << 8) | data[5] as u16) as i16;
 
                 // Can immediately return values as the one-shot mode automatically
                 // disables the fxo after taking the measurement.
-                self.i2c.disable();
                 self.state.set(State::Disabled);
-                self.buffer.replace(buffer);
 
                 self.callback
                     .get()
@@ -3,25 +3,28 @@
 //! GPIOs are presented through a driver interface with synchronous commands
 //! and a callback for interrupts.
 //!
-//! This capsule takes an array of pins to expose as generic GPIOs.
-//! Note that this capsule is used for general purpose GPIOs. Pins that are
-//! attached to LEDs or buttons are generally wired directly to those capsules,
-//! not through this capsule as an intermediary.
+//! This capsule takes an array of pins to expose as generic GPIOs. The array
+//! is the export list: userspace can use exactly the pins in it, by their
+//! index, and no others. An entry can be `None` to keep pin numbers matching
+//! the board's labels without exporting a pin that the kernel uses, such as a
+//! radio's reset line. Note that this capsule is used for general purpose
+//! GPIOs. Pins that are attached to LEDs or buttons are generally wired
+//! directly to those capsules, not through this capsule as an intermediary.
 //!
 //! Usage
 //! -----
 //!
 //! ```rust
 //! let gpio_pins = static_init!(
-//!     [&'static sam4l::gpio::GPIOPin; 4],
-//!     [&sam4l::gpio::PB[14],
-//!      &sam4l::gpio::PB[15],
-//!      &sam4l::gpio::PB[11],
-//!      &sam4l::gpio::PB[12]]);
+//!     [Option<&'static sam4l::gpio::GPIOPin>; 4],
+//!     [Some(&sam4l::gpio::PB[14]),
+//!      Some(&sam4l::gpio::PB[15]),
+//!      None, // Radio reset
+//!      Some(&sam4l::gpio::PB[12])]);
 //! let gpio = static_init!(
 //!     capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
 //!     capsules::gpio::GPIO::new(gpio_pins));
-//! for pin in gpio_pins.iter() {
+//! for pin in gpio_pins.iter().filter_map(|pin| *pin) {
 //!     pin.set_client(gpio);
 //! }
 //! ```
@@ -37,34 +40,64 @@
 //!
 //! Commands control and query GPIO information, namely how many GPIOs are
 //! present, the GPIO direction and state, and whether they should interrupt.
+//! Several pins can be read or written at once, as a bitmask of pin numbers.
 //!
 //! ### Subscribes
 //!
 //! The GPIO interface provides only one callback, which is used for pins that
-//! have had interrupts enabled.
+//! have had interrupts enabled. Pins that change at high rates can have their
+//! interrupts coalesced, so that interrupts that arrive before the callback
+//! runs are delivered as one callback with a count.
 
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = 0x00000004;
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil::gpio::{Client, InputMode, InterruptMode, Pin, PinCtl};
 use kernel::{AppId, Callback, Driver, ReturnCode};
 
 pub struct GPIO<'a, G: Pin + 'a> {
-    pins: &'a [&'a G],
+    pins: &'a [Option<&'a G>],
     callback: Cell<Option<Callback>>,
+    coalesce: Cell<bool>,
 }
 
 impl<'a, G: Pin + PinCtl> GPIO<'a, G> {
-    pub fn new(pins: &'a [&'a G]) -> GPIO<'a, G> {
+    pub fn new(pins: &'a [Option<&'a G>]) -> GPIO<'a, G> {
         GPIO {
             pins: pins,
             callback: Cell::new(None),
+            coalesce: Cell::new(false),
         }
     }
 
-    fn configure_input_pin(&self, pin_num: usize, config: usize) -> ReturnCode {
-        let pin = self.pins[pin_num];
+    /// The exported pin `pin_num`, if there is one.
+    fn pin(&self, pin_num: usize) -> Option<&'a G> {
+        self.pins.get(pin_num).and_then(|pin| *pin)
+    }
+
+    /// The pins in `mask`, a bitmask of pin numbers, as long as every one is
+    /// exported.
+    fn masked_pins(&self, mask: usize) -> Option<impl Iterator<Item = (usize, &'a G)> + 'a> {
+        let bits = 8 * mem::size_of::<usize>();
+        let exported = (0..bits)
+            .filter(|&pin_num| mask & (1 << pin_num) != 0)
+            .all(|pin_num| self.pin(pin_num).is_some());
+        if !exported {
+            return None;
+        }
+        Some(
+            self.pins
+                .iter()
+                .enumerate()
+                .take(bits)
+                .filter(move |&(pin_num, _)| mask & (1 << pin_num) != 0)
+                .filter_map(|(pin_num, pin)| pin.map(|pin| (pin_num, pin))),
+        )
+    }
+
+    fn configure_input_pin(&self, pin: &G, config: usize) -> ReturnCode {
         pin.make_input();
         match config {
             0 => {
@@ -83,21 +116,20 @@ impl<'a, G: Pin + PinCtl> GPIO<'a, G> {
         }
     }
 
-    fn configure_interrupt(&self, pin_num: usize, config: usize) -> ReturnCode {
-        let pins = self.pins.as_ref();
+    fn configure_interrupt(&self, pin: &G, pin_num: usize, config: usize) -> ReturnCode {
         match config {
             0 => {
-                pins[pin_num].enable_interrupt(pin_num, InterruptMode::EitherEdge);
+                pin.enable_interrupt(pin_num, InterruptMode::EitherEdge);
                 ReturnCode::SUCCESS
             }
 
             1 => {
-                pins[pin_num].enable_interrupt(pin_num, InterruptMode::RisingEdge);
+                pin.enable_interrupt(pin_num, InterruptMode::RisingEdge);
                 ReturnCode::SUCCESS
             }
 
             2 => {
-                pins[pin_num].enable_interrupt(pin_num, InterruptMode::FallingEdge);
+                pin.enable_interrupt(pin_num, InterruptMode::FallingEdge);
                 ReturnCode::SUCCESS
             }
 
@@ -109,13 +141,21 @@ impl<'a, G: Pin + PinCtl> GPIO<'a, G> {
 impl<'a, G: Pin> Client for GPIO<'a, G> {
     fn fired(&self, pin_num: usize) {
         // read the value of the pin
-        let pins = self.pins.as_ref();
-        let pin_state = pins[pin_num].read();
+        let pin_state = self
+            .pins
+            .get(pin_num)
+            .and_then(|pin| *pin)
+            .map_or(false, |pin| pin.read());
 
         // schedule callback with the pin number and value
-        self.callback
-            .get()
-            .map(|mut cb| cb.schedule(pin_num, pin_state as usize, 0));
+        let coalesce = self.coalesce.get();
+        self.callback.get().map(|mut cb| {
+            if coalesce {
+                cb.schedule_coalesced(pin_num, pin_state as usize)
+            } else {
+                cb.schedule(pin_num, pin_state as usize, 0)
+            }
+        });
     }
 }
 
@@ -125,23 +165,22 @@ impl<'a, G: Pin + PinCtl> Driver for GPIO<'a, G> {
     /// ### `subscribe_num`
     ///
     /// - `0`: Subscribe to interrupts from all pins with interrupts enabled.
-    ///        The callback signature is `fn(pin_num: usize, pin_state: bool)`
+    ///        The callback signature is `fn(pin_num: usize, pin_state: bool)`,
+    ///        or `fn(pin_num: usize, pin_state: bool, count: usize)` if
+    ///        interrupts are coalesced.
     fn subscribe(
         &self,
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // subscribe to all pin interrupts (no affect or reliance on
             // individual pins being configured as interrupts)
-            0 => {
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
-            }
+            0 => Ok(self.callback.replace(callback)),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
@@ -175,109 +214,119 @@ impl<'a, G: Pin + PinCtl> Driver for GPIO<'a, G> {
     /// - `7`: Configure interrupt on `pin` with `irq_config` in 0x00XX00000
     /// - `8`: Disable interrupt on `pin`.
     /// - `9`: Disable `pin`.
+    /// - `10`: Coalesce interrupts if `data` is 1, deliver each one
+    ///         separately if it is 0.
+    /// - `11`: Read the pins in the bitmask `data1`. Returns their values as
+    ///         a bitmask.
+    /// - `12`: Set the pins in the bitmask `data1` whose bits are set in
+    ///         `data2`, and clear the others.
+    ///
+    /// Commands on a pin that is not exported return `EINVAL`, as do `11`
+    /// and `12` if any pin in the mask is not exported, in which case no pin
+    /// is read or written. The pins of `11` and `12` are read or written
+    /// together, without any other kernel code running in between.
     fn command(&self, command_num: usize, data1: usize, data2: usize, _: AppId) -> ReturnCode {
-        let pins = self.pins.as_ref();
-        let pin = data1;
+        let pin_num = data1;
         match command_num {
             // number of pins
             0 => ReturnCode::SuccessWithValue {
-                value: pins.len() as usize,
+                value: self.pins.len() as usize,
             },
 
             // enable output
-            1 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].make_output();
-                    ReturnCode::SUCCESS
-                }
-            }
+            1 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.make_output();
+                ReturnCode::SUCCESS
+            }),
 
             // set pin
-            2 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].set();
-                    ReturnCode::SUCCESS
-                }
-            }
+            2 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.set();
+                ReturnCode::SUCCESS
+            }),
 
             // clear pin
-            3 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].clear();
-                    ReturnCode::SUCCESS
-                }
-            }
+            3 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.clear();
+                ReturnCode::SUCCESS
+            }),
 
             // toggle pin
-            4 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].toggle();
-                    ReturnCode::SUCCESS
-                }
-            }
+            4 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.toggle();
+                ReturnCode::SUCCESS
+            }),
 
             // enable and configure input
             5 => {
                 let pin_config = data2;
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
+                self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
                     self.configure_input_pin(pin, pin_config)
-                }
+                })
             }
 
             // read input
-            6 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    let pin_state = pins[pin].read();
-                    ReturnCode::SuccessWithValue {
-                        value: pin_state as usize,
-                    }
-                }
-            }
+            6 => self
+                .pin(pin_num)
+                .map_or(ReturnCode::EINVAL, |pin| ReturnCode::SuccessWithValue {
+                    value: pin.read() as usize,
+                }),
 
             // configure interrupts on pin
             // (no affect or reliance on registered callback)
             7 => {
                 let irq_config = data2;
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    self.configure_interrupt(pin, irq_config)
-                }
+                self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                    self.configure_interrupt(pin, pin_num, irq_config)
+                })
             }
 
             // disable interrupts on pin, also disables pin
             // (no affect or reliance on registered callback)
-            8 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].disable_interrupt();
-                    pins[pin].disable();
-                    ReturnCode::SUCCESS
-                }
-            }
+            8 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.disable_interrupt();
+                pin.disable();
+                ReturnCode::SUCCESS
+            }),
 
             // disable pin
-            9 => {
-                if pin >= pins.len() {
-                    ReturnCode::EINVAL /* impossible pin */
-                } else {
-                    pins[pin].disable();
+            9 => self.pin(pin_num).map_or(ReturnCode::EINVAL, |pin| {
+                pin.disable();
+                ReturnCode::SUCCESS
+            }),
+
+            // coalesce interrupt callbacks
+            10 => match data1 {
+                0 => {
+                    self.coalesce.set(false);
                     ReturnCode::SUCCESS
                 }
-            }
+                1 => {
+                    self.coalesce.set(true);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            },
+
+            // read several pins
+            11 => self.masked_pins(data1).map_or(ReturnCode::EINVAL, |pins| {
+                let value = pins.fold(0, |value, (pin_num, pin)| {
+                    value | ((pin.read() as usize) << pin_num)
+                });
+                ReturnCode::SuccessWithValue { value: value }
+            }),
+
+            // write several pins
+            12 => self.masked_pins(data1).map_or(ReturnCode::EINVAL, |pins| {
+                for (pin_num, pin) in pins {
+                    if data2 & (1 << pin_num) != 0 {
+                        pin.set();
+                    } else {
+                        pin.clear();
+                    }
+                }
+                ReturnCode::SUCCESS
+            }),
 
             // default
             _ => ReturnCode::ENOSUPPORT,
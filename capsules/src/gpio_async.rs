@@ -101,22 +101,16 @@ impl<'a, Port: hil::gpio_async::Port> Driver for GPIOAsync<'a, Port> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // Set callback for `done()` events
-            0 => {
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
-            }
+            0 => Ok(self.callback.replace(callback)),
 
             // Set callback for pin interrupts
-            1 => {
-                self.interrupt_callback.set(callback);
-                ReturnCode::SUCCESS
-            }
+            1 => Ok(self.interrupt_callback.replace(callback)),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
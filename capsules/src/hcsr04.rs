@@ -0,0 +1,158 @@
+//! Driver for HC-SR04 and similar ultrasonic distance sensors.
+//!
+//! The sensor is started by a pulse of at least 10 µs on its trigger pin. It
+//! then sends an ultrasonic burst and holds its echo pin high until the echo
+//! returns, so the length of the echo pulse is the round-trip time of sound
+//! to the nearest object. The driver timestamps both edges of the echo pulse
+//! with an alarm's counter and converts the pulse length to millimeters.
+//!
+//! The resolution depends on the alarm's frequency: a 16 kHz counter gives
+//! about 10 mm, a 1 MHz or faster counter better than 1 mm. If the echo has
+//! not ended within `TIMEOUT_MS`, or it is longer than an object at
+//! `MAX_RANGE_MM` would produce, nothing was in range.
+//!
+//! The echo pin of a 5 V sensor must be level shifted before it is connected
+//! to a 3.3 V microcontroller.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hcsr04_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let hcsr04 = static_init!(
+//!     capsules::hcsr04::Hcsr04<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::hcsr04::Hcsr04::new(
+//!         &sam4l::gpio::PC[31],
+//!         &sam4l::gpio::PC[30],
+//!         hcsr04_alarm
+//!     )
+//! );
+//! sam4l::gpio::PC[30].set_client(hcsr04);
+//! hcsr04_alarm.set_client(hcsr04);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{self, DistanceClient};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+
+/// The longest distance the sensor can measure.
+pub const MAX_RANGE_MM: u32 = 4000;
+
+/// How long to wait for the echo pulse to end. The sensor holds the echo pin
+/// high for about 38 ms when no echo returns.
+pub const TIMEOUT_MS: u32 = 60;
+
+/// The shortest trigger pulse the sensor responds to.
+const TRIGGER_US: u32 = 10;
+
+/// The speed of sound in air at 20 °C in millimeters per millisecond. Sound
+/// travels to the object and back, so the distance is
+/// `echo_us * SOUND_MM_PER_MS / 2000`.
+const SOUND_MM_PER_MS: u32 = 343;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Triggered, waiting for the echo pin to go high.
+    Triggered,
+    /// The echo pin went high at the given counter value.
+    Echo(u32),
+}
+
+pub struct Hcsr04<'a, A: Alarm + 'a> {
+    trigger: &'a gpio::Pin,
+    echo: &'a gpio::Pin,
+    alarm: &'a A,
+    state: Cell<State>,
+    client: Cell<Option<&'static DistanceClient>>,
+}
+
+impl<'a, A: Alarm> Hcsr04<'a, A> {
+    pub fn new(trigger: &'a gpio::Pin, echo: &'a gpio::Pin, alarm: &'a A) -> Hcsr04<'a, A> {
+        trigger.make_output();
+        trigger.clear();
+        echo.make_input();
+        Hcsr04 {
+            trigger: trigger,
+            echo: echo,
+            alarm: alarm,
+            state: Cell::new(State::Idle),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Send the trigger pulse, timed with the alarm's counter. The pulse is
+    /// rounded up to whole ticks, plus one since the first tick may end
+    /// immediately.
+    fn pulse_trigger(&self) {
+        let hz = <A::Frequency>::frequency() as u64;
+        let ticks = (TRIGGER_US as u64 * hz / 1_000_000) as u32 + 2;
+        self.trigger.set();
+        let start = self.alarm.now();
+        while self.alarm.now().wrapping_sub(start) < ticks {}
+        self.trigger.clear();
+    }
+
+    /// Convert the length of an echo pulse to a distance.
+    fn distance(ticks: u32) -> Option<usize> {
+        let echo_us = ticks as u64 * 1_000_000 / <A::Frequency>::frequency() as u64;
+        let millimeters = echo_us * SOUND_MM_PER_MS as u64 / 2000;
+        if millimeters > MAX_RANGE_MM as u64 {
+            None
+        } else {
+            Some(millimeters as usize)
+        }
+    }
+
+    fn finish(&self, millimeters: Option<usize>) {
+        self.echo.disable_interrupt();
+        self.alarm.disable();
+        self.state.set(State::Idle);
+        self.client.get().map(|client| client.callback(millimeters));
+    }
+}
+
+impl<'a, A: Alarm> sensors::Distance for Hcsr04<'a, A> {
+    fn set_client(&self, client: &'static DistanceClient) {
+        self.client.set(Some(client));
+    }
+
+    fn read_distance(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.state.set(State::Triggered);
+        self.echo
+            .enable_interrupt(0, gpio::InterruptMode::EitherEdge);
+        let timeout = TIMEOUT_MS * <A::Frequency>::frequency() / 1000;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(timeout));
+        self.pulse_trigger();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm> gpio::Client for Hcsr04<'a, A> {
+    fn fired(&self, _: usize) {
+        let now = self.alarm.now();
+        match self.state.get() {
+            State::Triggered if self.echo.read() => self.state.set(State::Echo(now)),
+            State::Echo(start) if !self.echo.read() => {
+                self.finish(Self::distance(now.wrapping_sub(start)));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm> time::Client for Hcsr04<'a, A> {
+    fn fired(&self) {
+        if self.state.get() != State::Idle {
+            self.finish(None);
+        }
+    }
+}
@@ -47,6 +47,7 @@
 //! ```
 
 use core::cell::Cell;
+use core::mem;
 use kernel::hil;
 use kernel::ReturnCode;
 use kernel::{AppId, Callback, Driver, Grant};
@@ -102,13 +103,16 @@ impl<'a> HumiditySensor<'a> {
         }
     }
 
-    fn configure_callback(&self, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+    fn configure_callback(
+        &self,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
         self.apps
             .enter(app_id, |app, _| {
-                app.callback = callback;
-                ReturnCode::SUCCESS
+                Ok(mem::replace(&mut app.callback, callback))
             })
-            .unwrap_or_else(|err| err.into())
+            .unwrap_or_else(|err| Err(err.into()))
     }
 }
 
@@ -132,11 +136,11 @@ impl<'a> Driver for HumiditySensor<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // subscribe to temperature reading with callback
             0 => self.configure_callback(callback, app_id),
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
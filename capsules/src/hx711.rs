@@ -0,0 +1,202 @@
+//! Driver for the HX711 load cell amplifier.
+//!
+//! The HX711 is a 24-bit ADC with a programmable gain amplifier, used to read
+//! the bridge of a load cell. It has no bus interface: it pulls its data pin
+//! low when a conversion is ready, and the driver then shifts the result out
+//! by pulsing the clock pin. The number of extra clock pulses after the 24
+//! data bits selects the input and gain for the next conversion.
+//!
+//! Each reading averages `samples` conversions and converts the result to
+//! milligrams as `(raw - offset) * 1000 / counts_per_gram`. The board
+//! calibrates the load cell once by reading the raw value with no load, which
+//! is `offset`, and with a known weight, from which it computes
+//! `counts_per_gram`.
+//!
+//! A reading always discards the first conversion, which may be stale or
+//! have been taken with a different gain. At the default 10 samples per
+//! second a reading of 4 samples therefore takes about half a second.
+//!
+//! The chip powers down if the clock pin stays high for more than 60 µs, so
+//! each conversion is shifted out in one go from the kernel's main loop,
+//! where interrupt handlers only delay it briefly.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hx711 = static_init!(
+//!     capsules::hx711::Hx711<'static>,
+//!     capsules::hx711::Hx711::new(
+//!         &sam4l::gpio::PA[16],
+//!         &sam4l::gpio::PA[17],
+//!         capsules::hx711::Gain::A128,
+//!         4,
+//!         -52_000,
+//!         420
+//!     )
+//! );
+//! sam4l::gpio::PA[17].set_client(hx711);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{self, WeightClient};
+use kernel::ReturnCode;
+
+/// The input and gain used for conversions.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Gain {
+    /// Channel A with a gain of 128.
+    A128,
+    /// Channel B with a gain of 32.
+    B32,
+    /// Channel A with a gain of 64.
+    A64,
+}
+
+impl Gain {
+    /// The number of clock pulses that read a conversion and select this gain
+    /// for the next one.
+    fn pulses(&self) -> usize {
+        match *self {
+            Gain::A128 => 25,
+            Gain::B32 => 26,
+            Gain::A64 => 27,
+        }
+    }
+}
+
+pub struct Hx711<'a> {
+    clock: &'a gpio::Pin,
+    data: &'a gpio::Pin,
+    gain: Cell<Gain>,
+    samples: Cell<u8>,
+    offset: Cell<i32>,
+    counts_per_gram: Cell<i32>,
+    /// Conversions still to read for the current reading, including the one
+    /// that is discarded.
+    remaining: Cell<usize>,
+    sum: Cell<i64>,
+    client: Cell<Option<&'static WeightClient>>,
+}
+
+impl<'a> Hx711<'a> {
+    pub fn new(
+        clock: &'a gpio::Pin,
+        data: &'a gpio::Pin,
+        gain: Gain,
+        samples: u8,
+        offset: i32,
+        counts_per_gram: i32,
+    ) -> Hx711<'a> {
+        clock.make_output();
+        clock.clear();
+        data.make_input();
+        Hx711 {
+            clock: clock,
+            data: data,
+            gain: Cell::new(gain),
+            samples: Cell::new(if samples == 0 { 1 } else { samples }),
+            offset: Cell::new(offset),
+            counts_per_gram: Cell::new(counts_per_gram),
+            remaining: Cell::new(0),
+            sum: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.remaining.get() != 0
+    }
+
+    /// Select the input and gain for following readings.
+    pub fn set_gain(&self, gain: Gain) -> ReturnCode {
+        if self.busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.gain.set(gain);
+        ReturnCode::SUCCESS
+    }
+
+    /// Set how many conversions each following reading averages.
+    pub fn set_samples(&self, samples: u8) -> ReturnCode {
+        if self.busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.samples.set(if samples == 0 { 1 } else { samples });
+        ReturnCode::SUCCESS
+    }
+
+    /// Set the calibration used to convert conversions to milligrams.
+    pub fn set_calibration(&self, offset: i32, counts_per_gram: i32) {
+        self.offset.set(offset);
+        self.counts_per_gram.set(counts_per_gram);
+    }
+
+    /// Shift out the waiting conversion and select the gain for the next one.
+    fn shift_in(&self) -> i32 {
+        let mut raw: u32 = 0;
+        for i in 0..self.gain.get().pulses() {
+            self.clock.set();
+            if i < 24 {
+                raw = (raw << 1) | self.data.read() as u32;
+            }
+            self.clock.clear();
+        }
+        // Sign extend the 24-bit two's complement result.
+        ((raw << 8) as i32) >> 8
+    }
+
+    fn milligrams(&self, raw: i64) -> i32 {
+        let counts_per_gram = self.counts_per_gram.get() as i64;
+        if counts_per_gram == 0 {
+            return 0;
+        }
+        ((raw - self.offset.get() as i64) * 1000 / counts_per_gram) as i32
+    }
+}
+
+impl<'a> sensors::Weight for Hx711<'a> {
+    fn set_client(&self, client: &'static WeightClient) {
+        self.client.set(Some(client));
+    }
+
+    fn read_weight(&self) -> ReturnCode {
+        if self.busy() {
+            return ReturnCode::EBUSY;
+        }
+        let samples = self.samples.get() as usize;
+        self.sum.set(0);
+        // A conversion that is already waiting will not produce an edge, so
+        // read it now. It is the one that is discarded.
+        if !self.data.read() {
+            self.shift_in();
+            self.remaining.set(samples);
+        } else {
+            self.remaining.set(samples + 1);
+        }
+        self.data
+            .enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> gpio::Client for Hx711<'a> {
+    fn fired(&self, _: usize) {
+        let remaining = self.remaining.get();
+        if remaining == 0 || self.data.read() {
+            return;
+        }
+        let raw = self.shift_in();
+        if remaining <= self.samples.get() as usize {
+            self.sum.set(self.sum.get() + raw as i64);
+        }
+        self.remaining.set(remaining - 1);
+        if remaining == 1 {
+            self.data.disable_interrupt();
+            let average = self.sum.get() / self.samples.get() as i64;
+            let milligrams = self.milligrams(average);
+            self.client.get().map(|client| client.callback(milligrams));
+        }
+    }
+}
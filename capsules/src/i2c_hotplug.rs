@@ -0,0 +1,267 @@
+//! Detects I2C devices that are connected and disconnected at runtime.
+//!
+//! Devices on a connector, such as sensor daughterboards, may not be there at
+//! boot, or may be unplugged while the board runs. Rather than have their
+//! drivers fail every transaction, the board gives `I2CHotplug` the addresses
+//! to watch. It probes each one in turn on the shared bus with a one-byte
+//! read, every `interval_ms`, and tells its client and applications when a
+//! device starts or stops acknowledging its address. Drivers can then be
+//! started when their device appears and left idle when it is gone.
+//!
+//! A probe reads a byte from the device. That is harmless for most devices,
+//! but boards should not list devices for which a read has side effects.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut HOTPLUG_ADDRESSES: [u8; 2] = [0x40, 0x1e];
+//!
+//! let hotplug_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0));
+//! let hotplug_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let hotplug = static_init!(
+//!     capsules::i2c_hotplug::I2CHotplug<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::i2c_hotplug::I2CHotplug::new(
+//!         hotplug_i2c,
+//!         hotplug_alarm,
+//!         &HOTPLUG_ADDRESSES,
+//!         1000,
+//!         &mut capsules::i2c_hotplug::BUFFER,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! hotplug_i2c.set_client(hotplug);
+//! hotplug_alarm.set_client(hotplug);
+//! hotplug.start();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Subscribe
+//!
+//! - `0`: Callback when a watched device appears or disappears, with its
+//!   address and `1` if it is now present or `0` if it is gone.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: the number of watched addresses.
+//! - `1`: Get which devices are present.
+//!   - Return: a bitmask with bit `i` set if the device at index `i` acked
+//!     its last probe.
+//! - `2`: Get the address at index `data`.
+//!   - Return: the address, or `EINVAL` if there is no such index.
+
+use core::cell::Cell;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil::i2c::{self, Error};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use virtual_i2c::I2CDevice;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x20007;
+
+/// The most addresses that can be watched, one per bit of the presence mask.
+pub const MAX_ADDRESSES: usize = 31;
+
+pub static mut BUFFER: [u8; 1] = [0; 1];
+
+/// Receives notifications when a watched device appears or disappears.
+pub trait HotplugClient {
+    fn device_changed(&self, addr: u8, present: bool);
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct I2CHotplug<'a, A: Alarm + 'a> {
+    i2c: &'a I2CDevice<'a>,
+    alarm: &'a A,
+    addresses: &'a [u8],
+    interval_ms: u32,
+    /// Bit `i` is set if the device at `addresses[i]` is present.
+    present: Cell<u32>,
+    /// The index of the address being probed.
+    index: Cell<usize>,
+    running: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    client: Cell<Option<&'a HotplugClient>>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm> I2CHotplug<'a, A> {
+    /// Watch `addresses`, probing all of them every `interval_ms`. Only the
+    /// first `MAX_ADDRESSES` are watched.
+    pub fn new(
+        i2c: &'a I2CDevice<'a>,
+        alarm: &'a A,
+        addresses: &'a [u8],
+        interval_ms: u32,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> I2CHotplug<'a, A> {
+        let len = if addresses.len() > MAX_ADDRESSES {
+            MAX_ADDRESSES
+        } else {
+            addresses.len()
+        };
+        I2CHotplug {
+            i2c: i2c,
+            alarm: alarm,
+            addresses: &addresses[..len],
+            interval_ms: interval_ms,
+            present: Cell::new(0),
+            index: Cell::new(0),
+            running: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            client: Cell::new(None),
+            apps: grant,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a HotplugClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Start probing, beginning with a scan of every address now.
+    pub fn start(&self) {
+        if self.running.get() || self.addresses.is_empty() {
+            return;
+        }
+        self.running.set(true);
+        self.index.set(0);
+        self.probe();
+    }
+
+    /// Stop probing once the current probe finishes.
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.alarm.disable();
+    }
+
+    /// Whether `addr` is watched and acked its last probe.
+    pub fn is_present(&self, addr: u8) -> bool {
+        self.addresses
+            .iter()
+            .position(|a| *a == addr)
+            .map_or(false, |i| self.present.get() & (1 << i) != 0)
+    }
+
+    fn probe(&self) {
+        self.buffer.take().map(|buffer| {
+            self.i2c.set_address(self.addresses[self.index.get()]);
+            i2c::I2CDevice::enable(self.i2c);
+            i2c::I2CDevice::read(self.i2c, buffer, 1);
+        });
+    }
+
+    /// Record the result of probing the device at `index`.
+    fn update(&self, index: usize, present: bool) {
+        let mask = self.present.get();
+        let was_present = mask & (1 << index) != 0;
+        if present == was_present {
+            return;
+        }
+        if present {
+            self.present.set(mask | (1 << index));
+        } else {
+            self.present.set(mask & !(1 << index));
+        }
+
+        let addr = self.addresses[index];
+        self.client
+            .get()
+            .map(|client| client.device_changed(addr, present));
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(addr as usize, present as usize, 0));
+            });
+        }
+    }
+}
+
+impl<'a, A: Alarm> i2c::I2CClient for I2CHotplug<'a, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        self.buffer.replace(buffer);
+        i2c::I2CDevice::disable(self.i2c);
+
+        // Any error other than a missing acknowledgement of the address, such
+        // as lost arbitration, says nothing about whether the device is there.
+        let index = self.index.get();
+        match error {
+            Error::CommandComplete | Error::DataNak => self.update(index, true),
+            Error::AddressNak => self.update(index, false),
+            _ => {}
+        }
+
+        if !self.running.get() {
+            return;
+        }
+        let next = index + 1;
+        if next < self.addresses.len() {
+            self.index.set(next);
+            self.probe();
+        } else {
+            self.index.set(0);
+            let interval = self.interval_ms * <A::Frequency>::frequency() / 1000;
+            self.alarm
+                .set_alarm(self.alarm.now().wrapping_add(interval));
+        }
+    }
+}
+
+impl<'a, A: Alarm> time::Client for I2CHotplug<'a, A> {
+    fn fired(&self) {
+        if self.running.get() {
+            self.probe();
+        }
+    }
+}
+
+impl<'a, A: Alarm> Driver for I2CHotplug<'a, A> {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.addresses.len(),
+            },
+            1 => ReturnCode::SuccessWithValue {
+                value: self.present.get() as usize,
+            },
+            2 => match self.addresses.get(data) {
+                Some(addr) => ReturnCode::SuccessWithValue {
+                    value: *addr as usize,
+                },
+                None => ReturnCode::EINVAL,
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
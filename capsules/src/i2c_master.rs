@@ -0,0 +1,257 @@
+//! Provides userspace applications with an I2C master interface.
+//!
+//! Applications can talk to any device on the bus, which makes it possible
+//! to prototype a driver for a new sensor entirely in userspace. The driver
+//! sits on top of the I2C mux, so the bus stays shared with the kernel's own
+//! drivers, and supports:
+//!
+//! - writes, reads, and writes followed by a read after a repeated start,
+//!   which is how most devices' registers are read;
+//! - 7-bit and 10-bit addresses;
+//! - reporting the outcome of each transaction to the application.
+//!
+//! Each application shares one buffer with the driver. Bytes to write are
+//! taken from its start, and bytes read are stored at its start. One
+//! transaction runs at a time; others are refused with `EBUSY`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut I2C_MASTER_BUF: [u8; 64] = [0; 64];
+//!
+//! let i2c_master_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0));
+//! let i2c_master = static_init!(
+//!     capsules::i2c_master::I2CMasterDriver<'static>,
+//!     capsules::i2c_master::I2CMasterDriver::new(
+//!         i2c_master_i2c,
+//!         &mut I2C_MASTER_BUF,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! i2c_master_i2c.set_client(i2c_master);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil::i2c::{self, Error};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use virtual_i2c::I2CDevice;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x20003;
+
+/// Set in the address argument of a command to use a 10-bit address.
+pub const TEN_BIT_ADDRESS: usize = 1 << 15;
+
+/// A 10-bit address is sent as this 7-bit address, with the top two bits of
+/// the address in its low bits, followed by a byte with the rest of it.
+const TEN_BIT_PREFIX: u8 = 0x78;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Transfer {
+    Write(usize),
+    Read(usize),
+    WriteRead(usize, usize),
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct I2CMasterDriver<'a> {
+    i2c: &'a I2CDevice<'a>,
+    buffer: TakeCell<'static, [u8]>,
+    apps: Grant<App>,
+    /// The app whose transaction is running, and the bytes it will read.
+    current: Cell<Option<(AppId, usize)>>,
+}
+
+impl<'a> I2CMasterDriver<'a> {
+    pub fn new(
+        i2c: &'a I2CDevice<'a>,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> I2CMasterDriver<'a> {
+        I2CMasterDriver {
+            i2c: i2c,
+            buffer: TakeCell::new(buffer),
+            apps: grant,
+            current: Cell::new(None),
+        }
+    }
+
+    fn start(&self, address: usize, transfer: Transfer, appid: AppId) -> ReturnCode {
+        if self.current.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        // 10-bit addresses take one byte of the write.
+        let (addr, prefix) = if address & TEN_BIT_ADDRESS != 0 {
+            let address = address & !TEN_BIT_ADDRESS;
+            if address > 0x3ff {
+                return ReturnCode::EINVAL;
+            }
+            (TEN_BIT_PREFIX | (address >> 8) as u8, Some(address as u8))
+        } else if address > 0x7f {
+            return ReturnCode::EINVAL;
+        } else {
+            (address as u8, None)
+        };
+        let offset = prefix.map_or(0, |_| 1);
+
+        let (write_len, read_len) = match transfer {
+            Transfer::Write(len) => (len, 0),
+            Transfer::Read(len) => (0, len),
+            Transfer::WriteRead(write_len, read_len) => (write_len, read_len),
+        };
+        if offset + write_len > 255 || read_len > 255 {
+            return ReturnCode::ESIZE;
+        }
+
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                let data = match app.buffer {
+                    Some(ref data) => data,
+                    None => return ReturnCode::ERESERVE,
+                };
+                let len = cmp::max(write_len, read_len);
+                if len > data.len() || offset + len > buffer.len() {
+                    return ReturnCode::ESIZE;
+                }
+                prefix.map(|low| buffer[0] = low);
+                buffer[offset..offset + write_len].copy_from_slice(&data.as_ref()[..write_len]);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            self.buffer.replace(buffer);
+            return res;
+        }
+
+        self.current.set(Some((appid, read_len)));
+        self.i2c.set_address(addr);
+        i2c::I2CDevice::enable(self.i2c);
+        let write_len = (offset + write_len) as u8;
+        match transfer {
+            Transfer::Write(_) => i2c::I2CDevice::write(self.i2c, buffer, write_len),
+            // A 10-bit read has to write the rest of the address first.
+            Transfer::Read(_) if offset > 0 => {
+                i2c::I2CDevice::write_read(self.i2c, buffer, write_len, read_len as u8)
+            }
+            Transfer::Read(_) => i2c::I2CDevice::read(self.i2c, buffer, read_len as u8),
+            Transfer::WriteRead(..) => {
+                i2c::I2CDevice::write_read(self.i2c, buffer, write_len, read_len as u8)
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> i2c::I2CClient for I2CMasterDriver<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        i2c::I2CDevice::disable(self.i2c);
+        self.current.take().map(|(appid, read_len)| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let status = match error {
+                    Error::CommandComplete => 0,
+                    Error::AddressNak => 1,
+                    Error::DataNak => 2,
+                    Error::ArbitrationLost => 3,
+                    Error::Overrun => 4,
+                };
+                if error == Error::CommandComplete {
+                    app.buffer.as_mut().map(|data| {
+                        let len = cmp::min(read_len, data.len());
+                        data.as_mut()[..len].copy_from_slice(&buffer[..len]);
+                    });
+                }
+                app.callback.map(|mut cb| cb.schedule(status, read_len, 0));
+            });
+        });
+        self.buffer.replace(buffer);
+    }
+}
+
+impl<'a> Driver for I2CMasterDriver<'a> {
+    /// Share a buffer with the driver.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The bytes to write, and where bytes read are stored.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the completion of transactions.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A transaction has finished. The callback signature is
+    ///        `fn(status: usize, read_len: usize)`, where `status` is 0 if
+    ///        every byte was acknowledged, 1 if the address was not, 2 if a
+    ///        data byte was not, 3 if arbitration was lost and 4 on a receive
+    ///        overrun. Bytes read are only stored if the status is 0.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Start a transaction with the device at address `data1`: a 7-bit
+    /// address, or a 10-bit address with `TEN_BIT_ADDRESS` set.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Write `data2` bytes.
+    /// - `2`: Read `data2` bytes.
+    /// - `3`: Write the number of bytes in bits 0-7 of `data2`, then after a
+    ///        repeated start read the number of bytes in bits 8-15.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.start(data1, Transfer::Write(data2), appid),
+            2 => self.start(data1, Transfer::Read(data2), appid),
+            3 => self.start(
+                data1,
+                Transfer::WriteRead(data2 & 0xff, (data2 >> 8) & 0xff),
+                appid,
+            ),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -13,6 +13,7 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::mem;
 use kernel::common::cells::{MapCell, TakeCell};
 use kernel::hil;
 use kernel::ReturnCode;
@@ -264,17 +265,15 @@ impl<'a> Driver for I2CMasterSlaveDriver<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
-            0 => {
-                self.app.map(|app| {
-                    app.callback = callback;
-                });
-                ReturnCode::SUCCESS
-            }
+            0 => Ok(self
+                .app
+                .map(|app| mem::replace(&mut app.callback, callback))
+                .unwrap_or(None)),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
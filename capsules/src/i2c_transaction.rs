@@ -0,0 +1,185 @@
+//! Multi-step register transactions for I2C devices.
+//!
+//! Most I2C sensors are driven by short, fixed sequences of register
+//! accesses: write a configuration register, select a register and read some
+//! bytes back, and so on. `I2CTransaction` runs such a sequence, given as a
+//! table of `RegOp`s, and collects the bytes read along the way, so that a
+//! driver only needs states for the points where it has to wait for
+//! something other than the bus (a conversion timer or a data ready
+//! interrupt).
+//!
+//! The I2C bus is enabled for the duration of a transaction. A transaction
+//! started while another is running is queued and runs once the current one
+//! finishes; there is room for one queued transaction.
+//!
+//! A driver embeds an `I2CTransaction` and forwards its
+//! `I2CClient::command_complete` callback to it:
+//!
+//! ```rust
+//! const READ_WHO_AM_I: &[RegOp] = &[RegOp::WriteRead(&[0x0d], 1)];
+//!
+//! impl<'a> I2CClient for Driver<'a> {
+//!     fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+//!         if let Some((data, error)) = self.transaction.command_complete(buffer, error) {
+//!             if error == Error::CommandComplete {
+//!                 let who_am_i = data.as_slice()[0];
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::i2c::{Error, I2CDevice};
+use kernel::ReturnCode;
+
+/// The maximum number of bytes a transaction can read.
+pub const MAX_READ_LEN: usize = 16;
+
+/// One step of a transaction.
+#[derive(Copy, Clone)]
+pub enum RegOp {
+    /// Write the bytes, usually a register address followed by its new
+    /// value.
+    Write(&'static [u8]),
+    /// Write the bytes, usually a register address, then read the given
+    /// number of bytes.
+    WriteRead(&'static [u8], u8),
+    /// Read the given number of bytes.
+    Read(u8),
+}
+
+/// The bytes read by a transaction, in order.
+#[derive(Copy, Clone)]
+pub struct RegData {
+    bytes: [u8; MAX_READ_LEN],
+    len: usize,
+}
+
+impl RegData {
+    fn new() -> RegData {
+        RegData {
+            bytes: [0; MAX_READ_LEN],
+            len: 0,
+        }
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        for &byte in data {
+            if self.len < MAX_READ_LEN {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+pub struct I2CTransaction<'a> {
+    i2c: &'a I2CDevice,
+    buffer: TakeCell<'static, [u8]>,
+    ops: Cell<&'static [RegOp]>,
+    index: Cell<usize>,
+    data: Cell<RegData>,
+    queued: Cell<Option<&'static [RegOp]>>,
+}
+
+impl<'a> I2CTransaction<'a> {
+    /// `buffer` must be large enough for the longest write or read of any
+    /// transaction.
+    pub fn new(i2c: &'a I2CDevice, buffer: &'static mut [u8]) -> I2CTransaction<'a> {
+        I2CTransaction {
+            i2c: i2c,
+            buffer: TakeCell::new(buffer),
+            ops: Cell::new(&[]),
+            index: Cell::new(0),
+            data: Cell::new(RegData::new()),
+            queued: Cell::new(None),
+        }
+    }
+
+    /// Whether a transaction is running.
+    pub fn is_busy(&self) -> bool {
+        self.buffer.is_none()
+    }
+
+    /// Run the transaction `ops`, or queue it if one is already running.
+    /// Returns EBUSY if a transaction is already queued.
+    pub fn start(&self, ops: &'static [RegOp]) -> ReturnCode {
+        if ops.is_empty() {
+            return ReturnCode::EINVAL;
+        }
+        match self.buffer.take() {
+            Some(buffer) => {
+                self.ops.set(ops);
+                self.index.set(0);
+                self.data.set(RegData::new());
+                self.i2c.enable();
+                self.issue(buffer);
+                ReturnCode::SUCCESS
+            }
+            None => {
+                if self.queued.get().is_some() {
+                    ReturnCode::EBUSY
+                } else {
+                    self.queued.set(Some(ops));
+                    ReturnCode::SUCCESS
+                }
+            }
+        }
+    }
+
+    fn issue(&self, buffer: &'static mut [u8]) {
+        match self.ops.get()[self.index.get()] {
+            RegOp::Write(bytes) => {
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                self.i2c.write(buffer, bytes.len() as u8);
+            }
+            RegOp::WriteRead(bytes, read_len) => {
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                self.i2c.write_read(buffer, bytes.len() as u8, read_len);
+            }
+            RegOp::Read(read_len) => {
+                self.i2c.read(buffer, read_len);
+            }
+        }
+    }
+
+    /// Continue the transaction after an I2C command completes. Returns the
+    /// bytes read and `Error::CommandComplete` once the transaction has
+    /// finished, or the data read so far and the error if a step failed.
+    /// Returns `None` while the transaction is still running.
+    pub fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        error: Error,
+    ) -> Option<(RegData, Error)> {
+        let ops = self.ops.get();
+        let index = self.index.get();
+        let read_len = match ops[index] {
+            RegOp::Write(_) => 0,
+            RegOp::WriteRead(_, read_len) | RegOp::Read(read_len) => read_len as usize,
+        };
+        if error == Error::CommandComplete {
+            let mut data = self.data.get();
+            data.append(&buffer[..read_len]);
+            self.data.set(data);
+
+            if index + 1 < ops.len() {
+                self.index.set(index + 1);
+                self.issue(buffer);
+                return None;
+            }
+        }
+
+        self.i2c.disable();
+        let data = self.data.get();
+        self.buffer.replace(buffer);
+        self.queued.take().map(|ops| self.start(ops));
+        Some((data, error))
+    }
+}
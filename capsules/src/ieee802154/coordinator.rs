@@ -0,0 +1,398 @@
+//! IEEE 802.15.4 PAN coordinator.
+//!
+//! Lets a Tock device anchor a non-beacon-enabled PAN: it answers beacon
+//! requests with beacons, accepts association requests from joining devices,
+//! assigns them short addresses and keeps a table of associated devices.
+//!
+//! Association responses are sent to the joining device as soon as its
+//! request is processed, rather than being held until the device polls with a
+//! data request, so joining devices must keep their receiver on until the
+//! response arrives. A device that polls anyway is sent its response again.
+//!
+//! Usage
+//! -----
+//!
+//! The coordinator is a user of the MAC device, usually through the MAC mux
+//! so that the userspace radio driver can share the radio:
+//!
+//! ```rust
+//! let coordinator_mac = static_init!(
+//!     capsules::ieee802154::virtual_mac::MacUser<'static>,
+//!     capsules::ieee802154::virtual_mac::MacUser::new(mux_mac));
+//! mux_mac.add_user(coordinator_mac);
+//! let coordinator = static_init!(
+//!     capsules::ieee802154::coordinator::Coordinator<'static>,
+//!     capsules::ieee802154::coordinator::Coordinator::new(
+//!         coordinator_mac,
+//!         &mut capsules::ieee802154::coordinator::BUFFER));
+//! coordinator_mac.set_transmit_client(coordinator);
+//! coordinator_mac.set_receive_client(coordinator);
+//!
+//! coordinator_mac.set_pan(0xABCD);
+//! coordinator_mac.set_address(0x0000);
+//! coordinator_mac.config_commit();
+//! coordinator.set_association_permit(true);
+//! ```
+
+use core::cell::Cell;
+use ieee802154::device::{MacDevice, RxClient, TxClient};
+use kernel::common::cells::{MapCell, TakeCell};
+use kernel::hil::radio;
+use kernel::ReturnCode;
+use net::ieee802154::{FrameType, Header, MacAddress, PanID};
+
+/// Maximum number of devices that can be associated at once.
+pub const MAX_DEVICES: usize = 16;
+
+pub static mut BUFFER: [u8; radio::MAX_BUF_SIZE] = [0; radio::MAX_BUF_SIZE];
+
+const BROADCAST_PAN: PanID = 0xffff;
+const BROADCAST_ADDRESS: u16 = 0xffff;
+/// Short address for devices that must use their extended address.
+const NO_SHORT_ADDRESS: u16 = 0xfffe;
+
+// MAC command frame identifiers (IEEE 802.15.4-2015, Table 7-49)
+const CMD_ASSOCIATION_REQUEST: u8 = 0x01;
+const CMD_ASSOCIATION_RESPONSE: u8 = 0x02;
+const CMD_DISASSOCIATION_NOTIFICATION: u8 = 0x03;
+const CMD_DATA_REQUEST: u8 = 0x04;
+const CMD_BEACON_REQUEST: u8 = 0x07;
+
+/// Capability information bit requesting a short address.
+const CAPABILITY_ALLOCATE_ADDRESS: u8 = 1 << 7;
+
+/// Association status codes.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AssociationStatus {
+    Successful = 0x00,
+    PanAtCapacity = 0x01,
+    PanAccessDenied = 0x02,
+}
+
+/// A device associated with this coordinator.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Device {
+    pub long_addr: [u8; 8],
+    pub short_addr: u16,
+    /// Capability information sent in the association request.
+    pub capability: u8,
+    /// Signal strength, in dBm, and link quality of the last frame received
+    /// from the device.
+    pub rssi: i8,
+    pub lqi: u8,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device {
+            long_addr: [0; 8],
+            short_addr: NO_SHORT_ADDRESS,
+            capability: 0,
+            rssi: 0,
+            lqi: 0,
+        }
+    }
+}
+
+/// A frame waiting for the transmit buffer.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Pending {
+    Beacon,
+    AssociationResponse([u8; 8], u16, AssociationStatus),
+}
+
+pub struct Coordinator<'a> {
+    mac: &'a MacDevice<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    pending: Cell<Option<Pending>>,
+    association_permit: Cell<bool>,
+    devices: MapCell<[Device; MAX_DEVICES]>,
+    num_devices: Cell<usize>,
+    next_short_addr: Cell<u16>,
+}
+
+impl<'a> Coordinator<'a> {
+    pub fn new(mac: &'a MacDevice<'a>, tx_buf: &'static mut [u8]) -> Coordinator<'a> {
+        Coordinator {
+            mac: mac,
+            tx_buf: TakeCell::new(tx_buf),
+            pending: Cell::new(None),
+            association_permit: Cell::new(false),
+            devices: MapCell::new(Default::default()),
+            num_devices: Cell::new(0),
+            next_short_addr: Cell::new(0x0001),
+        }
+    }
+
+    /// Allow or refuse new devices joining the PAN. Refused devices receive an
+    /// association response with `PanAccessDenied`.
+    pub fn set_association_permit(&self, permit: bool) {
+        self.association_permit.set(permit);
+    }
+
+    /// Broadcast a beacon.
+    pub fn send_beacon(&self) -> ReturnCode {
+        self.send(Pending::Beacon)
+    }
+
+    /// The number of associated devices.
+    pub fn num_devices(&self) -> usize {
+        self.num_devices.get()
+    }
+
+    /// The associated device at `index`, if `index` is valid.
+    pub fn get_device(&self, index: usize) -> Option<Device> {
+        if index < self.num_devices.get() {
+            self.devices.map(|devices| devices[index])
+        } else {
+            None
+        }
+    }
+
+    /// Forget the device with extended address `long_addr`. Its short
+    /// address is not reused.
+    pub fn remove_device(&self, long_addr: [u8; 8]) -> ReturnCode {
+        let num_devices = self.num_devices.get();
+        self.devices.map_or(ReturnCode::FAIL, |devices| {
+            match devices[..num_devices]
+                .iter()
+                .position(|device| device.long_addr == long_addr)
+            {
+                Some(index) => {
+                    for i in index..(num_devices - 1) {
+                        devices[i] = devices[i + 1];
+                    }
+                    self.num_devices.set(num_devices - 1);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Find a device by extended address and record the link quality of a
+    /// frame just received from it.
+    fn update_device(&self, long_addr: [u8; 8], rssi: i8, lqi: u8) -> Option<Device> {
+        let num_devices = self.num_devices.get();
+        self.devices.and_then(|devices| {
+            devices[..num_devices]
+                .iter_mut()
+                .find(|device| device.long_addr == long_addr)
+                .map(|device| {
+                    device.rssi = rssi;
+                    device.lqi = lqi;
+                    *device
+                })
+        })
+    }
+
+    /// Allocates the next free short address, skipping our own address and
+    /// the reserved ones.
+    fn allocate_short_addr(&self) -> u16 {
+        let own = self.mac.get_address();
+        let mut addr = self.next_short_addr.get();
+        while addr == own || addr >= NO_SHORT_ADDRESS {
+            addr = if addr >= NO_SHORT_ADDRESS {
+                0x0001
+            } else {
+                addr + 1
+            };
+        }
+        self.next_short_addr.set(addr + 1);
+        addr
+    }
+
+    /// Admit a device into the table, or find its existing entry, returning
+    /// the short address it should use.
+    fn associate(
+        &self,
+        long_addr: [u8; 8],
+        capability: u8,
+        rssi: i8,
+        lqi: u8,
+    ) -> (u16, AssociationStatus) {
+        if let Some(device) = self.update_device(long_addr, rssi, lqi) {
+            return (device.short_addr, AssociationStatus::Successful);
+        }
+        if !self.association_permit.get() {
+            return (NO_SHORT_ADDRESS, AssociationStatus::PanAccessDenied);
+        }
+        let num_devices = self.num_devices.get();
+        if num_devices == MAX_DEVICES {
+            return (NO_SHORT_ADDRESS, AssociationStatus::PanAtCapacity);
+        }
+
+        let short_addr = if capability & CAPABILITY_ALLOCATE_ADDRESS != 0 {
+            self.allocate_short_addr()
+        } else {
+            NO_SHORT_ADDRESS
+        };
+        self.devices.map(|devices| {
+            devices[num_devices] = Device {
+                long_addr: long_addr,
+                short_addr: short_addr,
+                capability: capability,
+                rssi: rssi,
+                lqi: lqi,
+            };
+        });
+        self.num_devices.set(num_devices + 1);
+        (short_addr, AssociationStatus::Successful)
+    }
+
+    /// Transmit `frame` now, or remember it until the transmit buffer is
+    /// free. Only one frame can wait; a later one replaces it.
+    fn send(&self, frame: Pending) -> ReturnCode {
+        match self.tx_buf.take() {
+            Some(buf) => self.transmit(buf, frame),
+            None => {
+                self.pending.set(Some(frame));
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    fn transmit(&self, buf: &'static mut [u8], frame: Pending) -> ReturnCode {
+        let pan = self.mac.get_pan();
+        let src_addr = MacAddress::Short(self.mac.get_address());
+        let prepared = match frame {
+            Pending::Beacon => self.mac.prepare_beacon_frame(buf, pan, src_addr),
+            // Association responses come from our extended address
+            Pending::AssociationResponse(long_addr, _, _) => self.mac.prepare_command_frame(
+                buf,
+                pan,
+                MacAddress::Long(long_addr),
+                pan,
+                MacAddress::Long(self.mac.get_address_long()),
+            ),
+        };
+
+        let mut frame_buf = match prepared {
+            Ok(frame_buf) => frame_buf,
+            Err(buf) => {
+                self.tx_buf.replace(buf);
+                return ReturnCode::FAIL;
+            }
+        };
+
+        let rval = match frame {
+            Pending::Beacon => {
+                // Superframe specification: beacon and superframe order 15
+                // (non-beacon-enabled), final CAP slot 15, PAN coordinator,
+                // association permit. Followed by empty GTS and pending
+                // address fields.
+                let permit = if self.association_permit.get() {
+                    0x80
+                } else {
+                    0x00
+                };
+                frame_buf.append_payload(&[0xff, 0x4f | permit, 0x00, 0x00])
+            }
+            Pending::AssociationResponse(_, short_addr, status) => frame_buf.append_payload(&[
+                CMD_ASSOCIATION_RESPONSE,
+                short_addr as u8,
+                (short_addr >> 8) as u8,
+                status as u8,
+            ]),
+        };
+        if rval != ReturnCode::SUCCESS {
+            self.tx_buf.replace(frame_buf.into_buf());
+            return rval;
+        }
+
+        let (rval, buf) = self.mac.transmit(frame_buf);
+        if let Some(buf) = buf {
+            self.tx_buf.replace(buf);
+        }
+        rval
+    }
+
+    fn handle_command(&self, header: &Header, payload: &[u8], rssi: i8, lqi: u8) {
+        if payload.is_empty() {
+            return;
+        }
+        // Association commands always come from the extended address
+        let long_addr = match header.src_addr {
+            Some(MacAddress::Long(long_addr)) => Some(long_addr),
+            _ => None,
+        };
+
+        match (payload[0], long_addr) {
+            (CMD_BEACON_REQUEST, _) => {
+                let _ = self.send_beacon();
+            }
+            (CMD_ASSOCIATION_REQUEST, Some(long_addr)) if payload.len() >= 2 => {
+                if header.dst_pan == Some(self.mac.get_pan()) {
+                    let (short_addr, status) = self.associate(long_addr, payload[1], rssi, lqi);
+                    let _ = self.send(Pending::AssociationResponse(long_addr, short_addr, status));
+                }
+            }
+            (CMD_DATA_REQUEST, Some(long_addr)) => {
+                if let Some(device) = self.update_device(long_addr, rssi, lqi) {
+                    let _ = self.send(Pending::AssociationResponse(
+                        long_addr,
+                        device.short_addr,
+                        AssociationStatus::Successful,
+                    ));
+                }
+            }
+            (CMD_DISASSOCIATION_NOTIFICATION, Some(long_addr)) => {
+                let _ = self.remove_device(long_addr);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> TxClient for Coordinator<'a> {
+    fn send_done(&self, spi_buf: &'static mut [u8], _acked: bool, _result: ReturnCode) {
+        match self.pending.get() {
+            Some(frame) => {
+                self.pending.set(None);
+                let _ = self.transmit(spi_buf, frame);
+            }
+            None => {
+                self.tx_buf.replace(spi_buf);
+            }
+        }
+    }
+}
+
+impl<'a> RxClient for Coordinator<'a> {
+    fn receive<'b>(
+        &self,
+        buf: &'b [u8],
+        header: Header<'b>,
+        data_offset: usize,
+        data_len: usize,
+        rssi: i8,
+        lqi: u8,
+    ) {
+        // Commands are addressed to our PAN, or to the broadcast PAN for
+        // beacon requests and association requests from unassociated devices
+        let pan = self.mac.get_pan();
+        let pan_match = match header.dst_pan {
+            Some(dst_pan) => dst_pan == pan || dst_pan == BROADCAST_PAN,
+            None => false,
+        };
+        let addr_match = match header.dst_addr {
+            Some(MacAddress::Short(addr)) => {
+                addr == self.mac.get_address() || addr == BROADCAST_ADDRESS
+            }
+            Some(MacAddress::Long(addr)) => addr == self.mac.get_address_long(),
+            None => false,
+        };
+        if header.frame_type != FrameType::MACCommand || !pan_match || !addr_match {
+            return;
+        }
+
+        if data_offset + data_len <= buf.len() {
+            self.handle_command(
+                &header,
+                &buf[data_offset..data_offset + data_len],
+                rssi,
+                lqi,
+            );
+        }
+    }
+}
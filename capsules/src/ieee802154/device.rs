@@ -2,7 +2,7 @@
 //! Any IEEE 802.15.4 MAC device should expose the following high-level
 //! functionality:
 //!
-//! - Configuration of addresses and transmit power
+//! - Configuration of addresses, channel and transmit power
 //! - Preparing frames (data frame, command frames, beacon frames)
 //! - Transmitting and receiving frames
 //!
@@ -27,6 +27,10 @@ pub trait MacDevice<'a> {
     fn get_address_long(&self) -> [u8; 8];
     /// The 16-bit PAN ID of the MAC device
     fn get_pan(&self) -> u16;
+    /// The 802.15.4 channel of the MAC device
+    fn get_channel(&self) -> u8;
+    /// The transmit power of the MAC device, in dBm
+    fn get_tx_power(&self) -> i8;
 
     /// Set the short 16-bit address of the MAC device
     fn set_address(&self, addr: u16);
@@ -34,6 +38,20 @@ pub trait MacDevice<'a> {
     fn set_address_long(&self, addr: [u8; 8]);
     /// Set the 16-bit PAN ID of the MAC device
     fn set_pan(&self, id: u16);
+    /// Set the 802.15.4 channel of the MAC device
+    fn set_channel(&self, chan: u8) -> ReturnCode;
+    /// Set the transmit power of the MAC device, in dBm
+    fn set_tx_power(&self, power: i8) -> ReturnCode;
+    /// Enable or disable dropping received frames that are not addressed to
+    /// this device
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode;
+
+    /// The timestamp of the frame being passed to the receive client, if the
+    /// radio timestamps frames
+    fn rx_timestamp(&self) -> Option<u64>;
+    /// The timestamp of the frame whose transmission is being reported to the
+    /// transmit client, if the radio timestamps frames
+    fn tx_timestamp(&self) -> Option<u64>;
 
     /// This method must be called after one or more calls to `set_*`. If
     /// `set_*` is called without calling `config_commit`, there is no guarantee
@@ -69,6 +87,27 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares an unsecured MAC command frame, whose payload starts with the
+    /// command identifier. Acknowledgement is requested unless `dst_addr` is
+    /// the broadcast address.
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<Frame, &'static mut [u8]>;
+
+    /// Prepares an unsecured beacon frame. The payload must start with the
+    /// superframe specification.
+    fn prepare_beacon_frame(
+        &self,
+        buf: &'static mut [u8],
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
@@ -110,5 +149,15 @@ pub trait RxClient {
     /// `buf`, so that the payload of the frame is contained in
     /// `buf[data_offset..data_offset + data_len]`.
     /// - `data_len`: Length of the data payload
-    fn receive<'a>(&self, buf: &'a [u8], header: Header<'a>, data_offset: usize, data_len: usize);
+    /// - `rssi`: Received signal strength of the frame, in dBm
+    /// - `lqi`: Link quality indicator reported by the radio
+    fn receive<'a>(
+        &self,
+        buf: &'a [u8],
+        header: Header<'a>,
+        data_offset: usize,
+        data_len: usize,
+        rssi: i8,
+        lqi: u8,
+    );
 }
@@ -3,11 +3,19 @@
 //! Implements a userspace interface for sending and receiving IEEE 802.15.4
 //! frames. Also provides a minimal list-based interface for managing keys and
 //! known link neighbors, which is needed for 802.15.4 security.
+//!
+//! The board can also give each app a transmit quota, so that one app cannot
+//! keep the radio busy or push the device over a regional duty-cycle limit.
+//! Each app has a token bucket that fills at a fixed number of payload bytes
+//! per second, up to a burst size; a transmission that does not fit in the
+//! bucket is refused with `EBUSY`.
 
+use core::mem;
 use core::cell::Cell;
 use core::cmp::min;
 use ieee802154::{device, framer};
 use kernel::common::cells::{MapCell, TakeCell};
+use kernel::hil::time::{Alarm, Frequency};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 use net::ieee802154::{AddressMode, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use net::stream::{decode_bytes, decode_u8, encode_bytes, encode_u8, SResult};
@@ -151,6 +159,10 @@ pub struct App {
     app_write: Option<AppSlice<Shared, u8>>,
     app_cfg: Option<AppSlice<Shared, u8>>,
     pending_tx: Option<(u16, Option<(SecurityLevel, KeyId)>)>,
+    /// Payload bytes left in the app's transmit quota, as of `tx_refill_time`.
+    /// `None` until the quota is first used, when the bucket starts full.
+    tx_tokens: Option<u32>,
+    tx_refill_time: u32,
 }
 
 impl Default for App {
@@ -162,10 +174,42 @@ impl Default for App {
             app_write: None,
             app_cfg: None,
             pending_tx: None,
+            tx_tokens: None,
+            tx_refill_time: 0,
         }
     }
 }
 
+/// The clock the driver reads to refill transmit quotas. Any alarm will do;
+/// the driver only reads its counter and never sets it.
+pub trait TxQuotaClock {
+    /// The current value of the wrapping counter, in ticks.
+    fn now(&self) -> u32;
+
+    /// The number of ticks per second.
+    fn frequency(&self) -> u32;
+}
+
+impl<A: Alarm> TxQuotaClock for A {
+    fn now(&self) -> u32 {
+        Alarm::now(self)
+    }
+
+    fn frequency(&self) -> u32 {
+        <A::Frequency>::frequency()
+    }
+}
+
+/// The transmit quota the board gives each app.
+#[derive(Copy, Clone)]
+struct TxQuota<'a> {
+    clock: &'a TxQuotaClock,
+    /// Payload bytes per second
+    rate: u32,
+    /// Size of the bucket, in payload bytes
+    burst: u32,
+}
+
 pub struct RadioDriver<'a> {
     /// Underlying MAC device, possibly multiplexed
     mac: &'a device::MacDevice<'a>,
@@ -189,6 +233,9 @@ pub struct RadioDriver<'a> {
 
     /// Buffer that stores the IEEE 802.15.4 frame to be transmitted.
     kernel_tx: TakeCell<'static, [u8]>,
+
+    /// Transmit quota of each app, if the board set one.
+    tx_quota: Cell<Option<TxQuota<'a>>>,
 }
 
 impl<'a> RadioDriver<'a> {
@@ -206,9 +253,49 @@ impl<'a> RadioDriver<'a> {
             apps: grant,
             current_app: Cell::new(None),
             kernel_tx: TakeCell::new(kernel_tx),
+            tx_quota: Cell::new(None),
         }
     }
 
+    /// Limit each app to transmitting `rate` payload bytes per second on
+    /// average, and at most `burst` bytes at once. `clock` is used to
+    /// measure how long apps have been waiting for their quota to refill.
+    ///
+    /// For example, at 250 kbit/s a `rate` of 3125 keeps each app below a
+    /// 10% duty cycle, not counting frame headers and acknowledgements.
+    pub fn set_tx_quota(&self, clock: &'a TxQuotaClock, rate: u32, burst: u32) {
+        self.tx_quota.set(Some(TxQuota {
+            clock: clock,
+            rate: rate,
+            burst: burst,
+        }));
+    }
+
+    /// Refills `app`'s token bucket for the time since it was last refilled,
+    /// and returns the number of payload bytes the app may transmit now.
+    /// Returns `None` if there is no quota.
+    fn refill_tx_tokens(&self, app: &mut App) -> Option<u32> {
+        self.tx_quota.get().map(|quota| {
+            let now = quota.clock.now();
+            let tokens = match app.tx_tokens {
+                None => quota.burst,
+                Some(tokens) => {
+                    let elapsed = now.wrapping_sub(app.tx_refill_time) as u64;
+                    let earned = elapsed * quota.rate as u64 / quota.clock.frequency() as u64;
+                    if earned == 0 {
+                        // Keep counting from the last refill so that short
+                        // intervals add up
+                        return tokens;
+                    }
+                    min(quota.burst as u64, tokens as u64 + earned) as u32
+                }
+            };
+            app.tx_tokens = Some(tokens);
+            app.tx_refill_time = now;
+            tokens
+        })
+    }
+
     // Neighbor management functions
 
     /// Add a new neighbor to the end of the list if there is still space
@@ -420,6 +507,15 @@ impl<'a> RadioDriver<'a> {
                     return ReturnCode::SUCCESS;
                 }
             };
+            let payload_len = app.app_write.as_ref().map_or(0, |payload| payload.len()) as u32;
+            let tokens = self.refill_tx_tokens(app);
+            if let Some(tokens) = tokens {
+                if self.tx_quota.get().map_or(false, |quota| payload_len > quota.burst) {
+                    return ReturnCode::ESIZE;
+                } else if payload_len > tokens {
+                    return ReturnCode::EBUSY;
+                }
+            }
             let result = self.kernel_tx.take().map_or(ReturnCode::ENOMEM, |kbuf| {
                 // Prepare the frame headers
                 let pan = self.mac.get_pan();
@@ -459,6 +555,7 @@ impl<'a> RadioDriver<'a> {
                 result
             });
             if result == ReturnCode::SUCCESS {
+                app.tx_tokens = tokens.map(|tokens| tokens - payload_len);
                 self.current_app.set(Some(appid));
             }
             result
@@ -528,7 +625,9 @@ impl<'a> Driver for RadioDriver<'a> {
     ///
     /// ### `allow_num`
     ///
-    /// - `0`: Read buffer. Will contain the received frame.
+    /// - `0`: Read buffer. Will contain the received frame, followed by the
+    ///        RSSI (signed, in dBm) and LQI bytes if the buffer is large
+    ///        enough.
     /// - `1`: Write buffer. Contains the frame payload to be transmitted.
     /// - `2`: Config buffer. Used to contain miscellaneous data associated with
     ///        some commands because the system call parameters / return codes are
@@ -564,17 +663,17 @@ impl<'a> Driver for RadioDriver<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
-            0 => self.do_with_app(app_id, |app| {
-                app.rx_callback = callback;
-                ReturnCode::SUCCESS
-            }),
-            1 => self.do_with_app(app_id, |app| {
-                app.tx_callback = callback;
-                ReturnCode::SUCCESS
-            }),
-            _ => ReturnCode::ENOSUPPORT,
+            0 => self
+                .apps
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.rx_callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.tx_callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
@@ -596,15 +695,18 @@ impl<'a> Driver for RadioDriver<'a> {
     /// - `3`: Set long MAC address.
     ///        app_cfg (in): 8 bytes: the long MAC address.
     /// - `4`: Set PAN ID.
-    /// - `5`: Set channel.
-    /// - `6`: Set transmission power.
-    /// - `7`: Commit any configuration changes.
+    /// - `5`: Set channel (11-26).
+    /// - `6`: Set transmission power in dBm, as a signed value.
+    /// - `7`: Commit any configuration changes. The addresses, PAN ID,
+    ///        channel and transmission power only reach the radio once this
+    ///        is called.
     /// - `8`: Get the short MAC address.
     /// - `9`: Get the long MAC address.
     ///        app_cfg (out): 8 bytes: the long MAC address.
     /// - `10`: Get the PAN ID.
     /// - `11`: Get the channel.
-    /// - `12`: Get the transmission power.
+    /// - `12`: Get the transmission power, as the low byte of the returned
+    ///        value interpreted as a signed integer.
     /// - `13`: Get the maximum number of neighbors.
     /// - `14`: Get the current number of neighbors.
     /// - `15`: Get the short address of the neighbor at an index.
@@ -627,6 +729,17 @@ impl<'a> Driver for RadioDriver<'a> {
     ///                      9 bytes: the key ID (might not use all bytes) +
     ///                      16 bytes: the key.
     /// - `25`: Remove the key at an index.
+    /// - `26`: Transmit a frame to the given short address.
+    ///        app_cfg (in): 1 byte: the security level +
+    ///                      1 byte: the key ID mode +
+    ///                      9 bytes: the key ID (might not use all bytes).
+    ///        Returns `EBUSY` if the app's transmit quota does not have room
+    ///        for the payload, and `ESIZE` if the payload is larger than the
+    ///        whole quota.
+    /// - `27`: Enable (1) or disable (0) filtering of received frames by
+    ///        destination address. Takes effect immediately.
+    /// - `28`: Get the number of payload bytes the app may transmit now.
+    ///        Returns `ENOSUPPORT` if the board sets no transmit quota.
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
             0 => ReturnCode::SUCCESS,
@@ -651,10 +764,8 @@ impl<'a> Driver for RadioDriver<'a> {
                 self.mac.set_pan(arg1 as u16);
                 ReturnCode::SUCCESS
             }
-            // XXX: Setting channel DEPRECATED by MAC layer channel control
-            5 => ReturnCode::ENOSUPPORT,
-            // XXX: Setting tx power DEPRECATED by MAC layer tx power control
-            6 => ReturnCode::ENOSUPPORT,
+            5 => self.mac.set_channel(arg1 as u8),
+            6 => self.mac.set_tx_power(arg1 as i8),
             7 => {
                 self.mac.config_commit();
                 ReturnCode::SUCCESS
@@ -677,10 +788,20 @@ impl<'a> Driver for RadioDriver<'a> {
                     value: (pan as usize) + 1,
                 }
             }
-            // XXX: Getting channel DEPRECATED by MAC layer channel control
-            11 => ReturnCode::ENOSUPPORT,
-            // XXX: Getting tx power DEPRECATED by MAC layer tx power control
-            12 => ReturnCode::ENOSUPPORT,
+            11 => {
+                // Guarantee that the channel is positive by adding 1
+                ReturnCode::SuccessWithValue {
+                    value: (self.mac.get_channel() as usize) + 1,
+                }
+            }
+            12 => {
+                // The power is negative for most settings, so return its
+                // two's complement byte, plus 1 to guarantee it is positive
+                let power = self.mac.get_tx_power();
+                ReturnCode::SuccessWithValue {
+                    value: (power as u8 as usize) + 1,
+                }
+            }
             13 => {
                 // Guarantee that it is positive by adding 1
                 ReturnCode::SuccessWithValue {
@@ -790,9 +911,23 @@ impl<'a> Driver for RadioDriver<'a> {
                     self.do_next_tx_sync(appid)
                 })
             }
+            27 => self.mac.set_address_filtering(arg1 != 0),
+            28 => self.do_with_app(appid, |app| {
+                self.refill_tx_tokens(app)
+                    .map_or(ReturnCode::ENOSUPPORT, |tokens| {
+                        ReturnCode::SuccessWithValue {
+                            value: tokens as usize,
+                        }
+                    })
+            }),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
+
+    /// Version 1 added command 28.
+    fn version(&self) -> usize {
+        1
+    }
 }
 
 impl<'a> device::TxClient for RadioDriver<'a> {
@@ -827,7 +962,15 @@ fn encode_address(addr: &Option<MacAddress>) -> usize {
 }
 
 impl<'a> device::RxClient for RadioDriver<'a> {
-    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+    fn receive<'b>(
+        &self,
+        buf: &'b [u8],
+        header: Header<'b>,
+        data_offset: usize,
+        data_len: usize,
+        rssi: i8,
+        lqi: u8,
+    ) {
         self.apps.each(|app| {
             app.app_read.take().as_mut().map(|rbuf| {
                 let rbuf = rbuf.as_mut();
@@ -837,14 +980,21 @@ impl<'a> device::RxClient for RadioDriver<'a> {
                 rbuf[..len].copy_from_slice(&buf[..len]);
                 rbuf[0] = data_offset as u8;
                 rbuf[1] = data_len as u8;
+                // Followed by two bytes, if there is room: the RSSI and LQI.
+                if len + 2 <= rbuf.len() {
+                    rbuf[len] = rssi as u8;
+                    rbuf[len + 1] = lqi;
+                }
 
                 // Encode useful parts of the header in 3 usizes
                 let pans = encode_pans(&header.dst_pan, &header.src_pan);
                 let dst_addr = encode_address(&header.dst_addr);
                 let src_addr = encode_address(&header.src_addr);
+                // Received frames often need a prompt reply, so let them
+                // overtake the process's other queued callbacks.
                 app.rx_callback
                     .take()
-                    .map(|mut cb| cb.schedule(pans, dst_addr, src_addr));
+                    .map(|mut cb| cb.schedule_urgent(pans, dst_addr, src_addr));
             });
         });
     }
@@ -73,6 +73,7 @@ use core::cell::Cell;
 use ieee802154::device::{MacDevice, RxClient, TxClient};
 use ieee802154::mac::Mac;
 use kernel::common::cells::MapCell;
+use kernel::common::lease::Lease;
 use kernel::hil::radio;
 use kernel::hil::symmetric_encryption::{AES128CCM, CCMClient};
 use kernel::ReturnCode;
@@ -120,6 +121,11 @@ impl Frame {
         self.buf
     }
 
+    /// The type of the frame
+    pub fn frame_type(&self) -> FrameType {
+        self.info.frame_type
+    }
+
     /// Calculates how much more data this frame can hold
     pub fn remaining_data_capacity(&self) -> usize {
         self.buf.len() - radio::PSDU_OFFSET - radio::MFR_SIZE - self.info.secured_length()
@@ -305,11 +311,18 @@ pub struct Framer<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> {
     /// associated state information.
     tx_state: MapCell<TxState>,
     tx_client: Cell<Option<&'a TxClient>>,
+    /// The frame buffer while the MAC below holds it
+    tx_lease: Lease,
 
     /// Reception pipeline state. Similar to the above, this should never be
     /// `None`, except when transitioning between states.
     rx_state: MapCell<RxState>,
     rx_client: Cell<Option<&'a RxClient>>,
+    /// Signal strength, link quality and timestamp of the frame in the
+    /// reception pipeline.
+    rx_rssi: Cell<i8>,
+    rx_lqi: Cell<u8>,
+    rx_timestamp: Cell<Option<u64>>,
 }
 
 impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
@@ -322,8 +335,12 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
             device_procedure: Cell::new(None),
             tx_state: MapCell::new(TxState::Idle),
             tx_client: Cell::new(None),
+            tx_lease: Lease::new("framer tx"),
             rx_state: MapCell::new(RxState::Idle),
             rx_client: Cell::new(None),
+            rx_rssi: Cell::new(0),
+            rx_lqi: Cell::new(0),
+            rx_timestamp: Cell::new(None),
         }
     }
 
@@ -337,6 +354,28 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
         self.device_procedure.set(Some(device_procedure));
     }
 
+    /// Encodes an unsecured header into `buf` and wraps it as a `Frame`.
+    fn prepare_unsecured_frame(
+        &self,
+        buf: &'static mut [u8],
+        header: Header,
+    ) -> Result<Frame, &'static mut [u8]> {
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => Ok(Frame {
+                buf: buf,
+                info: FrameInfo {
+                    frame_type: header.frame_type,
+                    mac_payload_offset: mac_payload_offset,
+                    data_offset: data_offset,
+                    data_len: 0,
+                    mic_len: 0,
+                    security_params: None,
+                },
+            }),
+            None => Err(buf),
+        }
+    }
+
     /// Look up the key using the IEEE 802.15.4 KeyDescriptor lookup prodecure
     /// implemented elsewhere.
     fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<([u8; 16])> {
@@ -456,7 +495,14 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
                 } else {
                     // No security needed, can yield the frame immediately
                     self.rx_client.get().map(|client| {
-                        client.receive(&buf, header, radio::PSDU_OFFSET + data_offset, data_len);
+                        client.receive(
+                            &buf,
+                            header,
+                            radio::PSDU_OFFSET + data_offset,
+                            data_len,
+                            self.rx_rssi.get(),
+                            self.rx_lqi.get(),
+                        );
                     });
                     None
                 }
@@ -529,7 +575,9 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
                         (TxState::Encrypting(info), (ReturnCode::SUCCESS, None))
                     }
                     TxState::ReadyToTransmit(info, buf) => {
+                        self.tx_lease.lend(buf);
                         let (rval, buf) = self.mac.transmit(buf, info.secured_length());
+                        buf.as_ref().map(|buf| self.tx_lease.returned(buf));
                         match rval {
                             // If the radio is busy, just wait for either a
                             // transmit_done or config_done callback to trigger
@@ -538,6 +586,7 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
                                 match buf {
                                     None => {
                                         // The radio forgot to return the buffer.
+                                        debug_assert!(false, "radio kept the buffer it refused");
                                         (TxState::Idle, (ReturnCode::FAIL, None))
                                     }
                                     Some(buf) => (
@@ -628,6 +677,8 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
                                 header,
                                 radio::PSDU_OFFSET + data_offset,
                                 frame_len - data_offset,
+                                self.rx_rssi.get(),
+                                self.rx_lqi.get(),
                             );
                         });
                     }
@@ -666,6 +717,14 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
         self.mac.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.mac.get_channel()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.mac.get_tx_power()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mac.set_address(addr)
     }
@@ -678,6 +737,26 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
         self.mac.set_pan(id)
     }
 
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.mac.set_channel(chan)
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.mac.set_tx_power(power)
+    }
+
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode {
+        self.mac.set_address_filtering(enabled)
+    }
+
+    fn rx_timestamp(&self) -> Option<u64> {
+        self.rx_timestamp.get()
+    }
+
+    fn tx_timestamp(&self) -> Option<u64> {
+        self.mac.tx_timestamp()
+    }
+
     fn config_commit(&self) {
         self.mac.config_commit()
     }
@@ -763,6 +842,59 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
         }
     }
 
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let header = Header {
+            frame_type: FrameType::MACCommand,
+            frame_pending: false,
+            // Broadcast frames must not request acknowledgement
+            ack_requested: dst_addr != MacAddress::Short(0xffff),
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: Some(dst_pan),
+            dst_addr: Some(dst_addr),
+            src_pan: Some(src_pan),
+            src_addr: Some(src_addr),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        self.prepare_unsecured_frame(buf, header)
+    }
+
+    fn prepare_beacon_frame(
+        &self,
+        buf: &'static mut [u8],
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let header = Header {
+            frame_type: FrameType::Beacon,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: None,
+            dst_addr: None,
+            src_pan: Some(src_pan),
+            src_addr: Some(src_addr),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        self.prepare_unsecured_frame(buf, header)
+    }
+
     fn transmit(&self, frame: Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         let Frame { buf, info } = frame;
         let state = match self.tx_state.take() {
@@ -772,6 +904,11 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
             Some(state) => state,
         };
         match state {
+            TxState::Idle if self.tx_lease.is_lent() => {
+                // The last frame is still being sent
+                self.tx_state.replace(TxState::Idle);
+                (ReturnCode::EBUSY, Some(buf))
+            }
             TxState::Idle => {
                 let next_state = self.outgoing_frame_security(buf, info);
                 self.tx_state.replace(next_state);
@@ -787,6 +924,7 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
 
 impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> radio::TxClient for Framer<'a, M, A> {
     fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.tx_lease.returned(buf);
         self.data_sequence.set(self.data_sequence.get() + 1);
         self.tx_client.get().map(move |client| {
             client.send_done(buf, acked, result);
@@ -795,7 +933,15 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> radio::TxClient for Framer<'a, M, A
 }
 
 impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> radio::RxClient for Framer<'a, M, A> {
-    fn receive(&self, buf: &'static mut [u8], frame_len: usize, crc_valid: bool, _: ReturnCode) {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        rssi: i8,
+        lqi: u8,
+        crc_valid: bool,
+        _: ReturnCode,
+    ) {
         // Drop all frames with invalid CRC
         if !crc_valid {
             self.mac.set_receive_buffer(buf);
@@ -807,6 +953,9 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> radio::RxClient for Framer<'a, M, A
                 RxState::Idle => {
                     // We can start processing a new received frame only if
                     // the reception pipeline is free
+                    self.rx_rssi.set(rssi);
+                    self.rx_lqi.set(lqi);
+                    self.rx_timestamp.set(self.mac.rx_timestamp());
                     self.incoming_frame_security(buf, frame_len)
                 }
                 other_state => {
@@ -1,7 +1,8 @@
 //! Specifies the interface for IEEE 802.15.4 MAC protocol layers. MAC protocols
-//! expose similar configuration (address, PAN, transmission power) options
-//! as ieee802154::device::MacDevice layers above it, but retain control over
-//! radio power management and channel selection. All frame processing should
+//! expose similar configuration (address, PAN, channel, transmission power)
+//! options as ieee802154::device::MacDevice layers above it, but retain control
+//! over radio power management. A MAC protocol that selects channels itself
+//! may refuse requests to change the channel. All frame processing should
 //! be completed above this layer such that Mac implementations receive fully
 //! formatted 802.15.4 MAC frames for transmission.
 //!
@@ -14,6 +15,8 @@ use kernel::hil::radio;
 use kernel::ReturnCode;
 use net::ieee802154::{Header, MacAddress};
 
+const BROADCAST_ADDRESS: u16 = 0xffff;
+
 pub trait Mac {
     /// Initializes the layer; may require a buffer to temporarily retaining frames to be
     /// transmitted
@@ -34,6 +37,10 @@ pub trait Mac {
     fn get_address_long(&self) -> [u8; 8];
     /// The 16-bit PAN id of the radio
     fn get_pan(&self) -> u16;
+    /// The 802.15.4 channel of the radio
+    fn get_channel(&self) -> u8;
+    /// The transmit power of the radio, in dBm
+    fn get_tx_power(&self) -> i8;
 
     /// Sets the short 16-bit address of the radio
     fn set_address(&self, addr: u16);
@@ -41,6 +48,19 @@ pub trait Mac {
     fn set_address_long(&self, addr: [u8; 8]);
     /// Sets the 16-bit PAN id of the radio
     fn set_pan(&self, id: u16);
+    /// Sets the 802.15.4 channel of the radio
+    fn set_channel(&self, chan: u8) -> ReturnCode;
+    /// Sets the transmit power of the radio, in dBm
+    fn set_tx_power(&self, power: i8) -> ReturnCode;
+    /// Enables or disables dropping received frames that are not addressed to
+    /// this device. Returns ENOSUPPORT if the protocol cannot operate without
+    /// filtering.
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode;
+
+    /// Timestamp of the last received frame, if the radio timestamps frames
+    fn rx_timestamp(&self) -> Option<u64>;
+    /// Timestamp of the last transmitted frame, if the radio timestamps frames
+    fn tx_timestamp(&self) -> Option<u64>;
 
     /// Must be called after one or more calls to `set_*`. If
     /// `set_*` is called without calling `config_commit`, there is no guarantee
@@ -68,6 +88,7 @@ pub trait Mac {
 ///
 pub struct AwakeMac<'a, R: radio::Radio + 'a> {
     radio: &'a R,
+    address_filtering: Cell<bool>,
 
     tx_client: Cell<Option<&'static radio::TxClient>>,
     rx_client: Cell<Option<&'static radio::RxClient>>,
@@ -77,6 +98,7 @@ impl<'a, R: radio::Radio + 'a> AwakeMac<'a, R> {
     pub fn new(radio: &'a R) -> AwakeMac<'a, R> {
         AwakeMac {
             radio: radio,
+            address_filtering: Cell::new(true),
             tx_client: Cell::new(None),
             rx_client: Cell::new(None),
         }
@@ -121,6 +143,35 @@ impl<'a, R: radio::Radio + 'a> Mac for AwakeMac<'a, R> {
         self.radio.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.radio.get_channel()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.radio.get_tx_power()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.radio.set_channel(chan)
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.radio.set_tx_power(power)
+    }
+
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode {
+        self.address_filtering.set(enabled);
+        ReturnCode::SUCCESS
+    }
+
+    fn rx_timestamp(&self) -> Option<u64> {
+        self.radio.rx_timestamp()
+    }
+
+    fn tx_timestamp(&self) -> Option<u64> {
+        self.radio.tx_timestamp()
+    }
+
     fn config_commit(&self) {
         self.radio.config_commit()
     }
@@ -159,15 +210,19 @@ impl<'a, R: radio::Radio + 'a> radio::RxClient for AwakeMac<'a, R> {
         &self,
         buf: &'static mut [u8],
         frame_len: usize,
+        rssi: i8,
+        lqi: u8,
         crc_valid: bool,
         result: ReturnCode,
     ) {
         // Filter packets by destination because radio is in promiscuous mode
-        let mut addr_match = false;
+        let mut addr_match = !self.address_filtering.get();
         if let Some((_, (header, _))) = Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
             if let Some(dst_addr) = header.dst_addr {
                 addr_match = match dst_addr {
-                    MacAddress::Short(addr) => addr == self.radio.get_address(),
+                    MacAddress::Short(addr) => {
+                        addr == self.radio.get_address() || addr == BROADCAST_ADDRESS
+                    }
                     MacAddress::Long(long_addr) => long_addr == self.radio.get_address_long(),
                 };
             }
@@ -175,7 +230,7 @@ impl<'a, R: radio::Radio + 'a> radio::RxClient for AwakeMac<'a, R> {
 
         if addr_match {
             self.rx_client.get().map(move |c| {
-                c.receive(buf, frame_len, crc_valid, result);
+                c.receive(buf, frame_len, rssi, lqi, crc_valid, result);
             });
         } else {
             self.radio.set_receive_buffer(buf);
@@ -1,6 +1,8 @@
+pub mod coordinator;
 pub mod device;
 pub mod framer;
 pub mod mac;
+pub mod timesync;
 pub mod virtual_mac;
 pub mod xmac;
 
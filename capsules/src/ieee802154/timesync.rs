@@ -0,0 +1,330 @@
+//! Time synchronization over IEEE 802.15.4.
+//!
+//! One device, the root, periodically broadcasts sync frames. Every other
+//! device follows the first root it hears and disciplines its
+//! `timestamp::Timestamp64` so that its network time matches the root's.
+//!
+//! Frames are timestamped by the radio at the end of the start-of-frame
+//! delimiter, which is the same instant for the sender and its receivers. Because the sender only
+//! learns the timestamp of a frame once it has been sent, each sync frame
+//! carries the network time at which the previous sync frame was sent. A
+//! follower pairs it with its own timestamp of the previous frame to get the
+//! offset of its clock, and uses successive offsets to estimate the rate
+//! difference between the clocks.
+//!
+//! Sync frames are unsecured broadcast data frames with the payload
+//!
+//! ```text
+//! +----------+-----+-------+----------------------------------+
+//! | dispatch | seq | flags | network time of frame seq - 1    |
+//! +----------+-----+-------+----------------------------------+
+//!  1 byte     1     1       8 bytes, little endian
+//! ```
+//!
+//! The dispatch byte lies in the 6LoWPAN "not a LoWPAN frame" range, so 6LoWPAN
+//! receivers ignore sync frames.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timesync_mac = static_init!(
+//!     capsules::ieee802154::virtual_mac::MacUser<'static>,
+//!     capsules::ieee802154::virtual_mac::MacUser::new(mux_mac));
+//! mux_mac.add_user(timesync_mac);
+//! let timesync = static_init!(
+//!     capsules::ieee802154::timesync::TimeSync<'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::ieee802154::timesync::TimeSync::new(
+//!         timesync_mac,
+//!         timesync_virtual_alarm,
+//!         timestamp,
+//!         &mut capsules::ieee802154::timesync::BUFFER));
+//! timesync_mac.set_transmit_client(timesync);
+//! timesync_mac.set_receive_client(timesync);
+//! timesync_virtual_alarm.set_client(timesync);
+//!
+//! timesync.start_root(10000); // Or timesync.start_follower()
+//! ```
+
+use core::cell::Cell;
+use ieee802154::device::{MacDevice, RxClient, TxClient};
+use kernel::common::cells::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ieee802154::{FrameType, Header, MacAddress};
+use timestamp::Timestamp64;
+
+pub static mut BUFFER: [u8; radio::MAX_BUF_SIZE] = [0; radio::MAX_BUF_SIZE];
+
+const BROADCAST_PAN: u16 = 0xffff;
+const BROADCAST_ADDRESS: u16 = 0xffff;
+
+/// 6LoWPAN NALP dispatch (0b00xxxxxx) identifying sync frames.
+const DISPATCH: u8 = 0x05;
+const PAYLOAD_LEN: usize = 11;
+
+/// The network time of the previous frame is present.
+const FLAG_PREVIOUS_VALID: u8 = 1 << 0;
+
+/// Rate corrections beyond this are assumed to be measurement errors.
+const MAX_SKEW_PPB: i64 = 500_000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Role {
+    Idle,
+    Root,
+    Follower,
+}
+
+pub struct TimeSync<'a, A: Alarm + 'a> {
+    mac: &'a MacDevice<'a>,
+    alarm: &'a A,
+    clock: &'a Timestamp64<'a, A>,
+    tx_buf: TakeCell<'static, [u8]>,
+    role: Cell<Role>,
+    period_ms: Cell<u32>,
+    seq: Cell<u8>,
+
+    /// Root: network time at which the previous sync frame was sent.
+    last_tx: Cell<Option<u64>>,
+
+    /// Follower: the root being followed, and the sequence number and local
+    /// timestamp of the last sync frame received from it.
+    root: Cell<Option<MacAddress>>,
+    last_rx: Cell<Option<(u8, u64)>>,
+    /// Follower: the last (local, network) time pair, for estimating skew.
+    last_sample: Cell<Option<(u64, u64)>>,
+    synchronized: Cell<bool>,
+}
+
+impl<'a, A: Alarm + 'a> TimeSync<'a, A> {
+    pub fn new(
+        mac: &'a MacDevice<'a>,
+        alarm: &'a A,
+        clock: &'a Timestamp64<'a, A>,
+        tx_buf: &'static mut [u8],
+    ) -> TimeSync<'a, A> {
+        TimeSync {
+            mac: mac,
+            alarm: alarm,
+            clock: clock,
+            tx_buf: TakeCell::new(tx_buf),
+            role: Cell::new(Role::Idle),
+            period_ms: Cell::new(0),
+            seq: Cell::new(0),
+            last_tx: Cell::new(None),
+            root: Cell::new(None),
+            last_rx: Cell::new(None),
+            last_sample: Cell::new(None),
+            synchronized: Cell::new(false),
+        }
+    }
+
+    /// Act as the time source, broadcasting a sync frame every `period_ms`
+    /// milliseconds.
+    pub fn start_root(&self, period_ms: u32) -> ReturnCode {
+        if period_ms == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.stop();
+        self.role.set(Role::Root);
+        self.period_ms.set(period_ms);
+        self.synchronized.set(true);
+        self.set_next_alarm();
+        ReturnCode::SUCCESS
+    }
+
+    /// Follow the first root heard.
+    pub fn start_follower(&self) -> ReturnCode {
+        self.stop();
+        self.role.set(Role::Follower);
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop sending or following sync frames. The clock keeps its current
+    /// correction.
+    pub fn stop(&self) {
+        self.alarm.disable();
+        self.role.set(Role::Idle);
+        self.last_tx.set(None);
+        self.root.set(None);
+        self.last_rx.set(None);
+        self.last_sample.set(None);
+        self.synchronized.set(false);
+    }
+
+    pub fn role(&self) -> Role {
+        self.role.get()
+    }
+
+    /// Whether network time follows a root: always true for the root, and
+    /// true for a follower once it has received two consecutive sync frames.
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized.get()
+    }
+
+    fn set_next_alarm(&self) {
+        let ticks = self.period_ms.get() as u64 * A::Frequency::frequency() as u64 / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(ticks as u32));
+    }
+
+    fn send_sync(&self) {
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            // The previous sync frame is still being sent
+            None => return,
+        };
+
+        let pan = self.mac.get_pan();
+        let frame = self.mac.prepare_data_frame(
+            buf,
+            pan,
+            MacAddress::Short(BROADCAST_ADDRESS),
+            pan,
+            MacAddress::Short(self.mac.get_address()),
+            None,
+        );
+        let mut frame = match frame {
+            Ok(frame) => frame,
+            Err(buf) => {
+                self.tx_buf.replace(buf);
+                return;
+            }
+        };
+
+        let mut payload = [0; PAYLOAD_LEN];
+        payload[0] = DISPATCH;
+        payload[1] = self.seq.get();
+        if let Some(network) = self.last_tx.get() {
+            payload[2] = FLAG_PREVIOUS_VALID;
+            for i in 0..8 {
+                payload[3 + i] = (network >> (8 * i)) as u8;
+            }
+        }
+        if frame.append_payload(&payload) != ReturnCode::SUCCESS {
+            self.tx_buf.replace(frame.into_buf());
+            return;
+        }
+
+        let (rval, buf) = self.mac.transmit(frame);
+        if let Some(buf) = buf {
+            self.tx_buf.replace(buf);
+        }
+        if rval != ReturnCode::SUCCESS {
+            // The next frame cannot refer to this one
+            self.last_tx.set(None);
+            self.seq.set(self.seq.get().wrapping_add(1));
+        }
+    }
+
+    fn handle_sync(&self, payload: &[u8], rx_local: u64) {
+        let seq = payload[1];
+
+        if payload[2] & FLAG_PREVIOUS_VALID != 0 {
+            match self.last_rx.get() {
+                Some((last_seq, last_local)) if last_seq == seq.wrapping_sub(1) => {
+                    let mut network = 0;
+                    for i in 0..8 {
+                        network |= (payload[3 + i] as u64) << (8 * i);
+                    }
+                    self.add_sample(last_local, network);
+                }
+                _ => {}
+            }
+        }
+        self.last_rx.set(Some((seq, rx_local)));
+    }
+
+    /// Discipline the clock so `local` corresponds to `network`.
+    fn add_sample(&self, local: u64, network: u64) {
+        let mut skew = self.clock.skew_ppb() as i64;
+        if let Some((last_local, last_network)) = self.last_sample.get() {
+            let local_elapsed = local.wrapping_sub(last_local) as i64;
+            let network_elapsed = network.wrapping_sub(last_network) as i64;
+            if local_elapsed > 0 {
+                let measured = (network_elapsed - local_elapsed) * 1_000_000_000 / local_elapsed;
+                if measured.abs() <= MAX_SKEW_PPB {
+                    // Average out timestamping jitter
+                    skew = if self.synchronized.get() {
+                        (skew + measured) / 2
+                    } else {
+                        measured
+                    };
+                }
+            }
+            self.synchronized.set(true);
+        }
+        self.clock.discipline(local, network, skew as i32);
+        self.last_sample.set(Some((local, network)));
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for TimeSync<'a, A> {
+    fn fired(&self) {
+        if self.role.get() == Role::Root {
+            self.set_next_alarm();
+            self.send_sync();
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> TxClient for TimeSync<'a, A> {
+    fn send_done(&self, spi_buf: &'static mut [u8], _acked: bool, result: ReturnCode) {
+        self.tx_buf.replace(spi_buf);
+        let timestamp = if result == ReturnCode::SUCCESS {
+            self.mac.tx_timestamp()
+        } else {
+            None
+        };
+        self.last_tx
+            .set(timestamp.map(|local| self.clock.local_to_network(local)));
+        self.seq.set(self.seq.get().wrapping_add(1));
+    }
+}
+
+impl<'a, A: Alarm + 'a> RxClient for TimeSync<'a, A> {
+    fn receive<'b>(
+        &self,
+        buf: &'b [u8],
+        header: Header<'b>,
+        data_offset: usize,
+        data_len: usize,
+        _rssi: i8,
+        _lqi: u8,
+    ) {
+        if self.role.get() != Role::Follower
+            || header.frame_type != FrameType::Data
+            || header.security.is_some()
+            || data_len != PAYLOAD_LEN
+            || data_offset + data_len > buf.len()
+        {
+            return;
+        }
+        let payload = &buf[data_offset..data_offset + data_len];
+        if payload[0] != DISPATCH {
+            return;
+        }
+
+        let pan = self.mac.get_pan();
+        match header.dst_pan {
+            Some(dst_pan) if dst_pan == pan || dst_pan == BROADCAST_PAN => {}
+            _ => return,
+        }
+        let src_addr = match header.src_addr {
+            Some(src_addr) => src_addr,
+            None => return,
+        };
+        match self.root.get() {
+            Some(root) if root != src_addr => return,
+            Some(_) => {}
+            None => self.root.set(Some(src_addr)),
+        }
+
+        if let Some(rx_local) = self.mac.rx_timestamp() {
+            self.handle_sync(payload, rx_local);
+        }
+    }
+}
@@ -8,6 +8,13 @@
 //! Every radio frame received is provided to all listening clients so that each
 //! client can perform its own frame filtering logic.
 //!
+//! Frames sent while another is in flight are queued, up to `TX_QUEUE_DEPTH`
+//! per user, and sent in order of `Priority`: beacons and MAC commands first,
+//! then data frames at the priority of the user that sent them. A user can
+//! also send a frame with `transmit_with`, to choose its priority and which
+//! client is told when it has been sent, so that for example a routing layer
+//! can queue a control message next to a stream of bulk data.
+//!
 //! Usage
 //! -----
 //!
@@ -27,35 +34,68 @@
 //! ```
 
 use core::cell::Cell;
-use ieee802154::{device, framer};
+use ieee802154::framer;
+use ieee802154::device::{self, TxClient};
 use kernel::common::cells::MapCell;
 use kernel::common::{List, ListLink, ListNode};
 use kernel::ReturnCode;
 use net::ieee802154::*;
 
+/// How many frames each `MacUser` can have waiting, not counting one being
+/// sent.
+pub const TX_QUEUE_DEPTH: usize = 4;
+
+/// How soon a queued frame is sent. Frames are sent highest priority first,
+/// and frames of the same priority in the order they were queued, whichever
+/// user queued them.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Priority {
+    /// Bulk data, such as the fragments of a large IP packet
+    Bulk,
+    Normal,
+    /// Network control, such as beacons, MAC commands and routing messages
+    Control,
+}
+
 /// IEE 802.15.4 MAC device muxer that keeps a list of MAC users and sequences
 /// any pending transmission requests. Any received frames from the underlying
 /// MAC device are sent to all users.
 pub struct MuxMac<'a> {
     mac: &'a device::MacDevice<'a>,
     users: List<'a, MacUser<'a>>,
-    inflight: Cell<Option<&'a MacUser<'a>>>,
+    /// Whether a frame is being sent, and the client to tell when it is done
+    sending: Cell<bool>,
+    sending_client: Cell<Option<&'a TxClient>>,
+    /// Stamped on each queued frame to keep frames of the same priority in
+    /// order
+    next_seq: Cell<usize>,
 }
 
 impl<'a> device::TxClient for MuxMac<'a> {
     fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.inflight.get().map(move |user| {
-            self.inflight.set(None);
-            user.send_done(spi_buf, acked, result);
-        });
+        let client = self.sending_client.get();
+        self.sending.set(false);
+        self.sending_client.set(None);
+        // Start the next frame before reporting this one, so that a client
+        // sending again from its callback waits behind the frames already
+        // queued.
         self.do_next_op_async();
+        client.map(move |client| client.send_done(spi_buf, acked, result));
     }
 }
 
 impl<'a> device::RxClient for MuxMac<'a> {
-    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+    fn receive<'b>(
+        &self,
+        buf: &'b [u8],
+        header: Header<'b>,
+        data_offset: usize,
+        data_len: usize,
+        rssi: i8,
+        lqi: u8,
+    ) {
         for user in self.users.iter() {
-            user.receive(buf, header, data_offset, data_len);
+            user.receive(buf, header, data_offset, data_len, rssi, lqi);
         }
     }
 }
@@ -65,7 +105,9 @@ impl<'a> MuxMac<'a> {
         MuxMac {
             mac: mac,
             users: List::new(),
-            inflight: Cell::new(None),
+            sending: Cell::new(false),
+            sending_client: Cell::new(None),
+            next_seq: Cell::new(0),
         }
     }
 
@@ -75,106 +117,104 @@ impl<'a> MuxMac<'a> {
         self.users.push_head(user);
     }
 
-    /// Gets the next `MacUser` and operation to perform if an operation is not
-    /// already underway.
-    fn get_next_op_if_idle(&self) -> Option<(&'a MacUser<'a>, Op)> {
-        match self.inflight.get() {
-            Some(_) => None,
-            None => {
-                let mnode = self.users.iter().find(|node| {
-                    node.operation.take().map_or(false, |op| {
-                        let pending = op != Op::Idle;
-                        node.operation.replace(op);
-                        pending
-                    })
+    fn has_queued(&self) -> bool {
+        self.users
+            .iter()
+            .any(|user| user.queue.iter().any(|slot| slot.is_some()))
+    }
+
+    /// Takes the queued frame of the highest priority that was queued first.
+    fn dequeue(&self) -> Option<QueuedFrame<'a>> {
+        let next_seq = self.next_seq.get();
+        let mut best: Option<(&MapCell<QueuedFrame<'a>>, Priority, usize)> = None;
+        for user in self.users.iter() {
+            for slot in user.queue.iter() {
+                slot.map(|queued| {
+                    let age = next_seq.wrapping_sub(queued.seq);
+                    let better = best.map_or(true, |(_, priority, best_age)| {
+                        (queued.priority, age) > (priority, best_age)
+                    });
+                    if better {
+                        best = Some((slot, queued.priority, age));
+                    }
                 });
-                mnode.and_then(|node| {
-                    node.operation.take().map(|op| {
-                        node.operation.replace(Op::Idle);
-                        (node, op)
-                    })
-                })
             }
         }
+        best.and_then(|(slot, _, _)| slot.take())
     }
 
-    /// Performs a non-idle operation on a `MacUser` asynchronously: that is, if the
-    /// transmission operation results in immediate failure, then return the
-    /// buffer to the `MacUser` via its transmit client.
-    fn perform_op_async(&self, node: &'a MacUser<'a>, op: Op) {
-        if let Op::Transmit(frame) = op {
-            let (result, mbuf) = self.mac.transmit(frame);
+    /// Begins sending queued frames, in order, if nothing is being sent.
+    /// Since this is being called asynchronously, frames that fail
+    /// immediately are returned to their clients via the `send_done`
+    /// callback, and the next frame is tried.
+    fn do_next_op_async(&self) {
+        while !self.sending.get() {
+            let queued = match self.dequeue() {
+                Some(queued) => queued,
+                None => break,
+            };
+            let (result, mbuf) = self.mac.transmit(queued.frame);
             // If a buffer is returned, the transmission failed,
             // otherwise it succeeded.
-            mbuf.map(|buf| {
-                node.send_done(buf, false, result);
-            }).unwrap_or_else(|| {
-                self.inflight.set(Some(node));
-            });
+            match mbuf {
+                Some(buf) => {
+                    queued
+                        .client
+                        .map(move |client| client.send_done(buf, false, result));
+                }
+                None => {
+                    self.sending.set(true);
+                    self.sending_client.set(queued.client);
+                }
+            }
         }
     }
 
-    /// Performs a non-idle operation on a `MacUser` synchronously, returning
-    /// the error code and the buffer immediately.
-    fn perform_op_sync(
+    /// Sends `frame` right away if nothing is being sent or waiting to be
+    /// sent, returning the error code and the buffer synchronously if it fails
+    /// immediately. Otherwise queues it in `queue`, or returns `ENOMEM` and the
+    /// buffer if the queue is full.
+    fn transmit(
         &self,
-        node: &'a MacUser<'a>,
-        op: Op,
-    ) -> Option<(ReturnCode, Option<&'static mut [u8]>)> {
-        if let Op::Transmit(frame) = op {
+        queue: &[MapCell<QueuedFrame<'a>>],
+        frame: framer::Frame,
+        priority: Priority,
+        client: Option<&'a TxClient>,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.sending.get() && !self.has_queued() {
             let (result, mbuf) = self.mac.transmit(frame);
-            if result == ReturnCode::SUCCESS {
-                self.inflight.set(Some(node));
+            if mbuf.is_none() {
+                self.sending.set(true);
+                self.sending_client.set(client);
             }
-            Some((result, mbuf))
-        } else {
-            None
+            return (result, mbuf);
         }
-    }
 
-    /// Begins the next outstanding transmission if there is no ongoing
-    /// operation and there is a user waiting to transmit a frame.
-    /// Since this is being called asynchronously, return any buffers to the active
-    /// `tx_client` via the `send_done` callback in the event of failure.
-    fn do_next_op_async(&self) {
-        self.get_next_op_if_idle()
-            .map(|(node, op)| self.perform_op_async(node, op));
-    }
-
-    /// Begins the next outstanding transmission if there is no ongoing
-    /// operation and there is a user waiting to transmit a frame. Since this is
-    /// being called synchronously, there is a need to identify the MacUser that
-    /// just queued its transmission request. This can only be done by comparing
-    /// the raw pointer references of the two users, since there is no
-    /// type-level way to guarantee that the enqueued user is actually in this Mux device's
-    /// `users` list. It's safe because the raw pointer references are never
-    /// dereferenced.
-    ///
-    /// If the newly-enqueued transmission is immediately executed by this mux
-    /// device but fails immediately, return the buffer synchronously.
-    fn do_next_op_sync(
-        &self,
-        new_node: &MacUser<'a>,
-    ) -> Option<(ReturnCode, Option<&'static mut [u8]>)> {
-        self.get_next_op_if_idle().and_then(|(node, op)| {
-            if node as *const _ == new_node as *const _ {
-                // The new node's operation is the one being scheduled, so the
-                // operation is synchronous
-                self.perform_op_sync(node, op)
-            } else {
-                // The operation being scheduled is not the new node, so the
-                // operation is asynchronous with respect to the new node.
-                self.perform_op_async(node, op);
-                None
+        // While the queue is being worked through, the frame is picked up by
+        // `do_next_op_async` once the frames ahead of it are sent.
+        match queue.iter().find(|slot| slot.is_none()) {
+            None => (ReturnCode::ENOMEM, Some(frame.into_buf())),
+            Some(slot) => {
+                let seq = self.next_seq.get();
+                self.next_seq.set(seq.wrapping_add(1));
+                slot.put(QueuedFrame {
+                    frame: frame,
+                    priority: priority,
+                    seq: seq,
+                    client: client,
+                });
+                (ReturnCode::SUCCESS, None)
             }
-        })
+        }
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
-enum Op {
-    Idle,
-    Transmit(framer::Frame),
+/// A frame waiting to be sent, and the client to tell when it has been.
+struct QueuedFrame<'a> {
+    frame: framer::Frame,
+    priority: Priority,
+    seq: usize,
+    client: Option<&'a TxClient>,
 }
 
 /// Keep state for each Mac user. All users of the virtualized MAC interface
@@ -184,37 +224,67 @@ enum Op {
 /// all MacUsers because there is only one MAC device. For example, the MAC
 /// device address is shared, so calling `set_address` on one `MacUser` sets the
 /// MAC address for all `MacUser`s.
+///
+/// Each user can queue up to `TX_QUEUE_DEPTH` frames while another is being
+/// sent.
 pub struct MacUser<'a> {
     mux: &'a MuxMac<'a>,
-    operation: MapCell<Op>,
+    queue: [MapCell<QueuedFrame<'a>>; TX_QUEUE_DEPTH],
+    /// The priority of the data frames sent with `transmit`
+    priority: Cell<Priority>,
     next: ListLink<'a, MacUser<'a>>,
     tx_client: Cell<Option<&'a device::TxClient>>,
     rx_client: Cell<Option<&'a device::RxClient>>,
 }
 
 impl<'a> MacUser<'a> {
-    pub const fn new(mux: &'a MuxMac<'a>) -> MacUser<'a> {
+    pub fn new(mux: &'a MuxMac<'a>) -> MacUser<'a> {
         MacUser {
             mux: mux,
-            operation: MapCell::new(Op::Idle),
+            queue: [
+                MapCell::empty(),
+                MapCell::empty(),
+                MapCell::empty(),
+                MapCell::empty(),
+            ],
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             tx_client: Cell::new(None),
             rx_client: Cell::new(None),
         }
     }
-}
 
-impl<'a> MacUser<'a> {
-    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.tx_client
-            .get()
-            .map(move |client| client.send_done(spi_buf, acked, result));
+    /// Set the priority of the data frames this user sends with `transmit`,
+    /// `Normal` by default. Beacon and MAC command frames are always sent
+    /// with `Control` priority.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
     }
 
-    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+    /// Send `frame` with the given priority, and tell `client` rather than
+    /// the transmit client of this user when it has been sent. Returns
+    /// `ENOMEM` and the buffer if the queue of this user is full.
+    pub fn transmit_with(
+        &self,
+        frame: framer::Frame,
+        priority: Priority,
+        client: &'a TxClient,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.mux.transmit(&self.queue, frame, priority, Some(client))
+    }
+
+    fn receive<'b>(
+        &self,
+        buf: &'b [u8],
+        header: Header<'b>,
+        data_offset: usize,
+        data_len: usize,
+        rssi: i8,
+        lqi: u8,
+    ) {
         self.rx_client
             .get()
-            .map(move |client| client.receive(buf, header, data_offset, data_len));
+            .map(move |client| client.receive(buf, header, data_offset, data_len, rssi, lqi));
     }
 }
 
@@ -245,6 +315,14 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.mux.mac.get_channel()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.mux.mac.get_tx_power()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mux.mac.set_address(addr)
     }
@@ -257,6 +335,26 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.set_pan(id)
     }
 
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.mux.mac.set_channel(chan)
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.mux.mac.set_tx_power(power)
+    }
+
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode {
+        self.mux.mac.set_address_filtering(enabled)
+    }
+
+    fn rx_timestamp(&self) -> Option<u64> {
+        self.mux.mac.rx_timestamp()
+    }
+
+    fn tx_timestamp(&self) -> Option<u64> {
+        self.mux.mac.tx_timestamp()
+    }
+
     fn config_commit(&self) {
         self.mux.mac.config_commit()
     }
@@ -279,24 +377,36 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux
+            .mac
+            .prepare_command_frame(buf, dst_pan, dst_addr, src_pan, src_addr)
+    }
+
+    fn prepare_beacon_frame(
+        &self,
+        buf: &'static mut [u8],
+        src_pan: PanID,
+        src_addr: MacAddress,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux.mac.prepare_beacon_frame(buf, src_pan, src_addr)
+    }
+
     fn transmit(&self, frame: framer::Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         // If the muxer is idle, immediately transmit the frame, otherwise
-        // attempt to queue the transmission request. However, each MAC user can
-        // only have one pending transmission request, so if there already is a
-        // pending transmission then we must fail to entertain this one.
-        self.operation
-            .take()
-            .map_or((ReturnCode::FAIL, None), |op| match op {
-                Op::Idle => {
-                    self.operation.replace(Op::Transmit(frame));
-                    self.mux
-                        .do_next_op_sync(self)
-                        .unwrap_or((ReturnCode::SUCCESS, None))
-                }
-                Op::Transmit(old_frame) => {
-                    self.operation.replace(Op::Transmit(old_frame));
-                    (ReturnCode::EBUSY, Some(frame.into_buf()))
-                }
-            })
+        // queue it behind the frames of at least its priority.
+        let priority = match frame.frame_type() {
+            FrameType::Beacon | FrameType::MACCommand => Priority::Control,
+            _ => self.priority.get(),
+        };
+        self.mux
+            .transmit(&self.queue, frame, priority, self.tx_client.get())
     }
 }
@@ -20,6 +20,12 @@
 //!   * Since X-MAC relies on proper sleep/wake behavior for all nodes, any
 //!     node with this implementation will not be able to communicate correctly
 //!     with non-XMAC-wrapped radios.
+//!   * The sleep interval and listen time can be changed with
+//!     `set_sleep_interval()`. The radio is on for roughly
+//!     `listen / (sleep + listen)` of the time when idle, so a 10 ms listen
+//!     time and a 1 s sleep interval keep it under 1%. Transmitters send
+//!     preambles for one sleep interval, so every node in a network must use
+//!     the same sleep interval.
 //!
 //! Usage
 //! -----
@@ -55,6 +61,8 @@
 //! rf233.set_power_client(xmac);
 //!
 //! xmac.initialize(&mut MAC_BUF);
+//! // Optionally trade latency for power: sleep 1 s, listen 10 ms.
+//! xmac.set_sleep_interval(1000, 10);
 //!
 //! // We can now use the XMac driver to instantiate a MacDevice like a Framer
 //! let mac_device = static_init!(
@@ -85,20 +93,18 @@ use kernel::hil::time::{self, Alarm, Frequency, Time};
 use kernel::ReturnCode;
 use net::ieee802154::*;
 
-// Time the radio will remain awake listening for packets before sleeping.
-// Observing the RF233, receive callbacks for preambles are generated only after
-// having been awake for more than 4-6 ms; 10 ms is a safe amount of time where
-// we are very likely to pick up any incoming preambles, and is half as much
-// as the 20 ms lower bound in Buettner et al.
+// Default time the radio will remain awake listening for packets before
+// sleeping. Observing the RF233, receive callbacks for preambles are generated
+// only after having been awake for more than 4-6 ms; 10 ms is a safe amount of
+// time where we are very likely to pick up any incoming preambles, and is half
+// as much as the 20 ms lower bound in Buettner et al.
 const WAKE_TIME_MS: u32 = 10;
-// Time the radio will sleep between wakes. Configurable to any desired value
-// less than or equal to the max time the transmitter sends preambles before
-// abandoning the transmission.
+// Default time the radio will sleep between wakes.
 const SLEEP_TIME_MS: u32 = 250;
-// Time the radio will continue to send preamble packets before aborting the
-// transmission and returning ENOACK. Should be at least as large as the maximum
-// sleep time for any node in the network.
-const PREAMBLE_TX_MS: u32 = 251;
+// Time the radio will continue to send preamble packets, beyond the sleep
+// interval, before aborting the transmission and returning ENOACK. Preambles
+// are sent for at least the sleep time of any node in the network.
+const PREAMBLE_TX_GUARD_MS: u32 = 1;
 
 // Maximum backoff for a transmitter attempting to send a data packet, when the
 // node has detected a data packet sent to the same destination from another
@@ -160,6 +166,9 @@ pub struct XMac<'a, R: radio::Radio + 'a, A: Alarm + 'a> {
     tx_preamble_buf: TakeCell<'static, [u8]>,
 
     rx_pending: Cell<bool>,
+
+    sleep_time_ms: Cell<u32>,
+    wake_time_ms: Cell<u32>,
 }
 
 impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> XMac<'a, R, A> {
@@ -179,13 +188,34 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> XMac<'a, R, A> {
             tx_preamble_seq_num: Cell::new(0),
             tx_preamble_buf: TakeCell::empty(),
             rx_pending: Cell::new(false),
+            sleep_time_ms: Cell::new(SLEEP_TIME_MS),
+            wake_time_ms: Cell::new(WAKE_TIME_MS),
+        }
+    }
+
+    /// Set how long the radio sleeps between wakes and how long it listens
+    /// for preambles each time it wakes, in milliseconds. Takes effect at
+    /// the next wake.
+    pub fn set_sleep_interval(&self, sleep_ms: u32, listen_ms: u32) -> ReturnCode {
+        if sleep_ms == 0 || listen_ms == 0 {
+            return ReturnCode::EINVAL;
         }
+        self.sleep_time_ms.set(sleep_ms);
+        self.wake_time_ms.set(listen_ms);
+        ReturnCode::SUCCESS
+    }
+
+    fn preamble_time(&self) -> u32 {
+        self.sleep_time_ms.get() + PREAMBLE_TX_GUARD_MS
     }
 
     fn sleep_time(&self) -> u32 {
         // TODO (ongoing) modify based on traffic load to efficiently schedule
-        // sleep. Currently sleeps for a constant amount of time.
-        SLEEP_TIME_MS
+        // sleep. Currently sleeps for a constant amount of time, shortened so
+        // that the radio is listening again one sleep interval after it
+        // stopped.
+        let wake_latency_ms = (self.radio.wake_latency_us() + 999) / 1000;
+        self.sleep_time_ms.get().saturating_sub(wake_latency_ms)
     }
 
     fn sleep(&self) {
@@ -296,6 +326,8 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> XMac<'a, R, A> {
         &self,
         buf: &'static mut [u8],
         len: usize,
+        rssi: i8,
+        lqi: u8,
         crc_valid: bool,
         result: ReturnCode,
     ) {
@@ -303,7 +335,7 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> XMac<'a, R, A> {
         self.sleep();
 
         self.rx_client.get().map(move |c| {
-            c.receive(buf, len, crc_valid, result);
+            c.receive(buf, len, rssi, lqi, crc_valid, result);
         });
     }
 }
@@ -379,6 +411,40 @@ impl<'a, R: radio::Radio + 'a, A: Alarm> Mac for XMac<'a, R, A> {
         self.radio.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.radio.get_channel()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.radio.get_tx_power()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.radio.set_channel(chan)
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.radio.set_tx_power(power)
+    }
+
+    // Preamble backoff and wakeups depend on the destination address, so
+    // filtering cannot be turned off.
+    fn set_address_filtering(&self, enabled: bool) -> ReturnCode {
+        if enabled {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::ENOSUPPORT
+        }
+    }
+
+    fn rx_timestamp(&self) -> Option<u64> {
+        self.radio.rx_timestamp()
+    }
+
+    fn tx_timestamp(&self) -> Option<u64> {
+        self.radio.tx_timestamp()
+    }
+
     fn config_commit(&self) {
         self.radio.config_commit()
     }
@@ -439,7 +505,7 @@ impl<'a, R: radio::Radio + 'a, A: Alarm> Mac for XMac<'a, R, A> {
         // If the radio is on, start the preamble timer and start transmitting
         if self.radio.is_on() {
             self.state.set(XMacState::TX_PREAMBLE);
-            self.set_timer_ms::<A>(PREAMBLE_TX_MS);
+            self.set_timer_ms::<A>(self.preamble_time());
             self.transmit_preamble();
 
         // If the radio is currently sleeping, wake it and indicate that when
@@ -466,7 +532,7 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> time::Client for XMac<'a, R, A> {
                     self.state.set(XMacState::STARTUP);
                     self.radio.start();
                 } else {
-                    self.set_timer_ms::<A>(WAKE_TIME_MS);
+                    self.set_timer_ms::<A>(self.wake_time_ms.get());
                     self.state.set(XMacState::AWAKE);
                 }
             }
@@ -506,11 +572,11 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> radio::PowerClient for XMac<'a, R,
                 if self.tx_preamble_pending.get() {
                     self.tx_preamble_pending.set(false);
                     self.state.set(XMacState::TX_PREAMBLE);
-                    self.set_timer_ms::<A>(PREAMBLE_TX_MS);
+                    self.set_timer_ms::<A>(self.preamble_time());
                     self.transmit_preamble();
                 } else {
                     self.state.set(XMacState::AWAKE);
-                    self.set_timer_ms::<A>(WAKE_TIME_MS);
+                    self.set_timer_ms::<A>(self.wake_time_ms.get());
                 }
             }
         }
@@ -556,6 +622,8 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> radio::RxClient for XMac<'a, R, A>
         &self,
         buf: &'static mut [u8],
         frame_len: usize,
+        rssi: i8,
+        lqi: u8,
         crc_valid: bool,
         result: ReturnCode,
     ) {
@@ -617,7 +685,7 @@ impl<'a, R: radio::Radio + 'a, A: Alarm + 'a> radio::RxClient for XMac<'a, R, A>
 
         if data_received {
             self.rx_pending.set(false);
-            self.call_rx_client(buf, frame_len, crc_valid, result);
+            self.call_rx_client(buf, frame_len, rssi, lqi, crc_valid, result);
         } else {
             self.radio.set_receive_buffer(buf);
         }
@@ -0,0 +1,567 @@
+//! Receives and sends infrared remote control codes.
+//!
+//! Frames are received from a demodulating IR receiver, such as a TSOP38238,
+//! whose output is connected to a GPIO pin. These receivers pull their output
+//! low while they see the carrier, so the driver timestamps every edge with
+//! an alarm's counter and records the length of each mark (carrier on) and
+//! space (carrier off). A gap of `FRAME_GAP_US` ends the frame, which is then
+//! decoded as NEC or RC5 and delivered to applications.
+//!
+//! Frames are sent by driving an IR LED from a PWM pin: the carrier is
+//! switched on for each mark and off for each space, timed by the alarm. The
+//! receiver is ignored while a frame is being sent, since it sees the
+//! driver's own transmission.
+//!
+//! Supported protocols:
+//!
+//! - NEC: a 38 kHz carrier and pulse distance coding of an 8-bit address, an
+//!   8-bit command and their inverses. Remotes that send a 16-bit address
+//!   instead of the inverted address are also supported. A key that is held
+//!   down sends repeat frames, which are reported as repeats of the last code.
+//! - RC5: a 36 kHz carrier and Manchester coding of a 5-bit address and a
+//!   7-bit command (RC5X). A frame is reported as a repeat if its toggle bit
+//!   has not changed since the last frame with the same code.
+//!
+//! The alarm's resolution limits decoding: each edge is timestamped to one
+//! tick, and durations must be within 25% of nominal. A 16 kHz alarm gives
+//! ticks of 61 µs, enough for both protocols.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ir_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let infrared = static_init!(
+//!     capsules::infrared::Infrared<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::infrared::Infrared::new(
+//!         &sam4l::gpio::PA[16],
+//!         ir_led_pwm,
+//!         ir_alarm,
+//!         &mut capsules::infrared::BUFFER,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! sam4l::gpio::PA[16].set_client(infrared);
+//! ir_alarm.set_client(infrared);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Subscribe
+//!
+//! - `0`: Callback when a code is received, with the protocol (`0` for NEC,
+//!   `1` for RC5), the address, and the command with bit 8 set if the frame
+//!   repeats the last one.
+//! - `1`: Callback when a code this application sent has been transmitted.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: `SUCCESS`.
+//! - `1`: Transmit a code. `data` holds the address in its low 16 bits and
+//!   the command in bits 16 to 23, and `data2` is the protocol.
+//!   - Return: `SUCCESS` if the frame is being sent, `EBUSY` if a frame is
+//!     being sent or received, or `EINVAL` if the protocol is unknown or the
+//!     address or command do not fit in it.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil::gpio;
+use kernel::hil::pwm::PwmPin;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x00000007;
+
+/// The number of marks and spaces in the longest frame, an NEC frame.
+pub const MAX_DURATIONS: usize = 67;
+
+pub static mut BUFFER: [u16; MAX_DURATIONS] = [0; MAX_DURATIONS];
+
+/// A space this long ends a frame.
+pub const FRAME_GAP_US: u32 = 10000;
+
+const NEC_CARRIER_HZ: usize = 38000;
+const NEC_LEADER_MARK_US: u16 = 9000;
+const NEC_LEADER_SPACE_US: u16 = 4500;
+const NEC_REPEAT_SPACE_US: u16 = 2250;
+const NEC_BIT_MARK_US: u16 = 560;
+const NEC_ONE_SPACE_US: u16 = 1690;
+const NEC_ZERO_SPACE_US: u16 = 560;
+
+const RC5_CARRIER_HZ: usize = 36000;
+const RC5_HALF_BIT_US: u16 = 889;
+const RC5_BITS: usize = 14;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    Nec = 0,
+    Rc5 = 1,
+}
+
+/// A remote control code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Code {
+    pub protocol: Protocol,
+    pub address: u16,
+    pub command: u8,
+}
+
+/// A decoded frame.
+enum Frame {
+    Nec(Code),
+    /// An NEC repeat frame, sent while the key of the last code is held.
+    NecRepeat,
+    /// An RC5 frame and its toggle bit.
+    Rc5(Code, bool),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Receiving,
+    Transmitting,
+}
+
+#[derive(Default)]
+pub struct App {
+    receive_callback: Option<Callback>,
+    transmit_callback: Option<Callback>,
+}
+
+pub struct Infrared<'a, A: Alarm + 'a> {
+    receiver: &'a gpio::Pin,
+    led: &'a PwmPin,
+    alarm: &'a A,
+    state: Cell<State>,
+    /// The marks and spaces of the frame being received or sent, in µs,
+    /// starting with a mark.
+    buffer: TakeCell<'static, [u16]>,
+    /// The number of durations received, or to send.
+    count: Cell<usize>,
+    /// The index of the next duration to send.
+    index: Cell<usize>,
+    /// The counter value of the last received edge, or of the next edge to
+    /// send.
+    edge: Cell<u32>,
+    carrier_hz: Cell<usize>,
+    /// The last code received and its toggle bit.
+    last: Cell<Option<(Code, bool)>>,
+    /// The toggle bit of the next RC5 frame to send.
+    toggle: Cell<bool>,
+    transmitting_app: Cell<Option<AppId>>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm> Infrared<'a, A> {
+    /// `buffer` must hold at least `MAX_DURATIONS` durations.
+    pub fn new(
+        receiver: &'a gpio::Pin,
+        led: &'a PwmPin,
+        alarm: &'a A,
+        buffer: &'static mut [u16],
+        grant: Grant<App>,
+    ) -> Infrared<'a, A> {
+        receiver.make_input();
+        receiver.enable_interrupt(0, gpio::InterruptMode::EitherEdge);
+        led.stop();
+        Infrared {
+            receiver: receiver,
+            led: led,
+            alarm: alarm,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            count: Cell::new(0),
+            index: Cell::new(0),
+            edge: Cell::new(0),
+            carrier_hz: Cell::new(0),
+            last: Cell::new(None),
+            toggle: Cell::new(false),
+            transmitting_app: Cell::new(None),
+            apps: grant,
+        }
+    }
+
+    fn ticks_to_us(ticks: u32) -> u16 {
+        let us = ticks as u64 * 1_000_000 / <A::Frequency>::frequency() as u64;
+        cmp::min(us, u16::max_value() as u64) as u16
+    }
+
+    fn us_to_ticks(us: u32) -> u32 {
+        (us as u64 * <A::Frequency>::frequency() as u64 / 1_000_000) as u32
+    }
+
+    /// End the frame if no edge follows within `FRAME_GAP_US` of `now`.
+    fn set_gap_timeout(&self, now: u32) {
+        self.alarm
+            .set_alarm(now.wrapping_add(Self::us_to_ticks(FRAME_GAP_US)));
+    }
+
+    fn received(&self, frame: Frame) {
+        let (code, toggle, repeat) = match frame {
+            Frame::Nec(code) => (code, false, false),
+            Frame::NecRepeat => match self.last.get() {
+                Some((code, _)) if code.protocol == Protocol::Nec => (code, false, true),
+                _ => return,
+            },
+            Frame::Rc5(code, toggle) => (code, toggle, self.last.get() == Some((code, toggle))),
+        };
+        self.last.set(Some((code, toggle)));
+
+        let command = code.command as usize | (repeat as usize) << 8;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.receive_callback.map(|mut cb| {
+                    cb.schedule(code.protocol as usize, code.address as usize, command)
+                });
+            });
+        }
+    }
+
+    fn transmit(&self, appid: AppId, code: Code) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let toggle = self.toggle.get();
+        let count = self.buffer.map_or(0, |buffer| {
+            if buffer.len() < MAX_DURATIONS {
+                return 0;
+            }
+            match code.protocol {
+                Protocol::Nec => encode_nec(code, buffer),
+                Protocol::Rc5 => encode_rc5(code, toggle, buffer),
+            }
+        });
+        if count == 0 {
+            return ReturnCode::ENOMEM;
+        }
+        if code.protocol == Protocol::Rc5 {
+            self.toggle.set(!toggle);
+        }
+
+        self.receiver.disable_interrupt();
+        self.state.set(State::Transmitting);
+        self.carrier_hz.set(match code.protocol {
+            Protocol::Nec => NEC_CARRIER_HZ,
+            Protocol::Rc5 => RC5_CARRIER_HZ,
+        });
+        self.count.set(count);
+        self.index.set(0);
+        self.edge.set(self.alarm.now());
+        self.transmitting_app.set(Some(appid));
+        self.transmit_next();
+        ReturnCode::SUCCESS
+    }
+
+    /// Start the next mark or space, or finish the frame.
+    fn transmit_next(&self) {
+        let index = self.index.get();
+        if index >= self.count.get() {
+            self.led.stop();
+            self.state.set(State::Idle);
+            self.receiver
+                .enable_interrupt(0, gpio::InterruptMode::EitherEdge);
+            self.transmitting_app.take().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.transmit_callback.map(|mut cb| cb.schedule(0, 0, 0));
+                });
+            });
+            return;
+        }
+
+        if index % 2 == 0 {
+            let duty_cycle = self.led.get_maximum_duty_cycle() / 3;
+            self.led.start(self.carrier_hz.get(), duty_cycle);
+        } else {
+            self.led.stop();
+        }
+        let duration = self.buffer.map_or(0, |buffer| buffer[index]);
+        self.index.set(index + 1);
+        // Time each edge from the start of the frame so errors do not add up.
+        let edge = self
+            .edge
+            .get()
+            .wrapping_add(Self::us_to_ticks(duration as u32));
+        self.edge.set(edge);
+        self.alarm.set_alarm(edge);
+    }
+}
+
+/// Whether `duration` is within 25% of `expected`.
+fn near(duration: u16, expected: u16) -> bool {
+    let duration = duration as u32 * 4;
+    let expected = expected as u32;
+    duration >= expected * 3 && duration <= expected * 5
+}
+
+fn decode(durations: &[u16]) -> Option<Frame> {
+    decode_nec(durations).or_else(|| decode_rc5(durations))
+}
+
+fn decode_nec(durations: &[u16]) -> Option<Frame> {
+    if durations.len() < 3 || !near(durations[0], NEC_LEADER_MARK_US) {
+        return None;
+    }
+    if durations.len() == 3 && near(durations[1], NEC_REPEAT_SPACE_US) {
+        return Some(Frame::NecRepeat);
+    }
+    if durations.len() != MAX_DURATIONS || !near(durations[1], NEC_LEADER_SPACE_US) {
+        return None;
+    }
+
+    // Bits are sent least significant first, each as a mark followed by a
+    // space whose length gives the value.
+    let mut bits: u32 = 0;
+    for i in 0..32 {
+        let mark = durations[2 + 2 * i];
+        let space = durations[3 + 2 * i];
+        if !near(mark, NEC_BIT_MARK_US) {
+            return None;
+        }
+        if near(space, NEC_ONE_SPACE_US) {
+            bits |= 1 << i;
+        } else if !near(space, NEC_ZERO_SPACE_US) {
+            return None;
+        }
+    }
+
+    let command = (bits >> 16) as u8;
+    if (bits >> 24) as u8 != !command {
+        return None;
+    }
+    let address = bits as u8;
+    let address = if (bits >> 8) as u8 == !address {
+        address as u16
+    } else {
+        bits as u16
+    };
+    Some(Frame::Nec(Code {
+        protocol: Protocol::Nec,
+        address: address,
+        command: command,
+    }))
+}
+
+fn decode_rc5(durations: &[u16]) -> Option<Frame> {
+    // Rebuild the frame's half bits, setting those with the carrier on. The
+    // first half bit is a space, which the idle line before the frame hides,
+    // and a final space is hidden by the gap after it.
+    let mut halves: u32 = 0;
+    let mut count = 1;
+    for (i, &duration) in durations.iter().enumerate() {
+        let len = if near(duration, RC5_HALF_BIT_US) {
+            1
+        } else if near(duration, 2 * RC5_HALF_BIT_US) {
+            2
+        } else {
+            return None;
+        };
+        for _ in 0..len {
+            if count >= 2 * RC5_BITS {
+                return None;
+            }
+            if i % 2 == 0 {
+                halves |= 1 << count;
+            }
+            count += 1;
+        }
+    }
+    if count < 2 * RC5_BITS - 1 {
+        return None;
+    }
+
+    // A one is a space followed by a mark, a zero a mark followed by a
+    // space. Bits are sent most significant first.
+    let mut frame: u16 = 0;
+    for bit in 0..RC5_BITS {
+        let value = match (halves >> (2 * bit)) & 0b11 {
+            0b10 => 1,
+            0b01 => 0,
+            _ => return None,
+        };
+        frame = (frame << 1) | value;
+    }
+    if frame & (1 << 13) == 0 {
+        return None;
+    }
+
+    // The second start bit is the inverse of the seventh command bit.
+    let mut command = (frame & 0x3f) as u8;
+    if frame & (1 << 12) == 0 {
+        command |= 0x40;
+    }
+    Some(Frame::Rc5(
+        Code {
+            protocol: Protocol::Rc5,
+            address: (frame >> 6) & 0x1f,
+            command: command,
+        },
+        frame & (1 << 11) != 0,
+    ))
+}
+
+/// Fill `buffer` with the marks and spaces of an NEC frame and return how
+/// many there are.
+fn encode_nec(code: Code, buffer: &mut [u16]) -> usize {
+    let address = if code.address <= 0xff {
+        code.address as u32 | (!code.address as u32 & 0xff) << 8
+    } else {
+        code.address as u32
+    };
+    let bits = address | (code.command as u32) << 16 | (!code.command as u32) << 24;
+
+    buffer[0] = NEC_LEADER_MARK_US;
+    buffer[1] = NEC_LEADER_SPACE_US;
+    for i in 0..32 {
+        buffer[2 + 2 * i] = NEC_BIT_MARK_US;
+        buffer[3 + 2 * i] = if bits & (1 << i) != 0 {
+            NEC_ONE_SPACE_US
+        } else {
+            NEC_ZERO_SPACE_US
+        };
+    }
+    buffer[MAX_DURATIONS - 1] = NEC_BIT_MARK_US;
+    MAX_DURATIONS
+}
+
+/// Fill `buffer` with the marks and spaces of an RC5 frame and return how
+/// many there are.
+fn encode_rc5(code: Code, toggle: bool, buffer: &mut [u16]) -> usize {
+    let frame: u16 = 1 << 13
+        | ((code.command & 0x40 == 0) as u16) << 12
+        | (toggle as u16) << 11
+        | (code.address & 0x1f) << 6
+        | (code.command & 0x3f) as u16;
+
+    // Merge consecutive half bits at the same level into one mark or space.
+    // Even indices are marks, so the space that starts the frame is left out.
+    let mut count = 0;
+    for bit in (0..RC5_BITS).rev() {
+        let one = frame & (1 << bit) != 0;
+        for &mark in [!one, one].iter() {
+            if count == 0 && !mark {
+                continue;
+            }
+            if count > 0 && ((count - 1) % 2 == 0) == mark {
+                buffer[count - 1] += RC5_HALF_BIT_US;
+            } else {
+                buffer[count] = RC5_HALF_BIT_US;
+                count += 1;
+            }
+        }
+    }
+    // A trailing space is just the line going idle.
+    if count % 2 == 0 {
+        count -= 1;
+    }
+    count
+}
+
+impl<'a, A: Alarm> gpio::Client for Infrared<'a, A> {
+    fn fired(&self, _: usize) {
+        let now = self.alarm.now();
+        let mark = !self.receiver.read();
+        match self.state.get() {
+            State::Idle => {
+                if mark {
+                    self.state.set(State::Receiving);
+                    self.count.set(0);
+                    self.edge.set(now);
+                    self.set_gap_timeout(now);
+                }
+            }
+            State::Receiving => {
+                let duration = Self::ticks_to_us(now.wrapping_sub(self.edge.get()));
+                let count = self.count.get();
+                self.buffer.map(|buffer| {
+                    if count < buffer.len() {
+                        buffer[count] = duration;
+                    }
+                });
+                self.count.set(count + 1);
+                self.edge.set(now);
+                self.set_gap_timeout(now);
+            }
+            State::Transmitting => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm> time::Client for Infrared<'a, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::Receiving => {
+                self.state.set(State::Idle);
+                let count = self.count.get();
+                let frame = self.buffer.map_or(None, |buffer| {
+                    let count = cmp::min(count, buffer.len());
+                    decode(&buffer[..count])
+                });
+                frame.map(|frame| self.received(frame));
+            }
+            State::Transmitting => self.transmit_next(),
+        }
+    }
+}
+
+impl<'a, A: Alarm> Driver for Infrared<'a, A> {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.receive_callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.transmit_callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                let address = data & 0xffff;
+                let command = (data >> 16) & 0xff;
+                let protocol = match data2 {
+                    0 => Protocol::Nec,
+                    1 if address <= 0x1f && command <= 0x7f => Protocol::Rc5,
+                    _ => return ReturnCode::EINVAL,
+                };
+                if data >> 24 != 0 {
+                    return ReturnCode::EINVAL;
+                }
+                self.transmit(
+                    appid,
+                    Code {
+                        protocol: protocol,
+                        address: address as u16,
+                        command: command as u8,
+                    },
+                )
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
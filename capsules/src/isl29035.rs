@@ -26,62 +26,71 @@
 //! ```
 
 use core::cell::Cell;
-use kernel::common::cells::TakeCell;
+use i2c_transaction::{I2CTransaction, RegOp};
 use kernel::hil::i2c::{Error, I2CClient, I2CDevice};
 use kernel::hil::sensors::{AmbientLight, AmbientLightClient};
 use kernel::hil::time::{self, Frequency};
 use kernel::ReturnCode;
+use sensor_probe::Probe;
 
 pub static mut BUF: [u8; 3] = [0; 3];
 
+/// Detects the ISL29035 by the device ID bits of its ID register (0x0f).
+pub const PROBE: Probe = Probe {
+    address: 0x44,
+    register: 0x0f,
+    mask: 0x38,
+    expected: 0x28,
+};
+
+const ENABLE: &[RegOp] = &[RegOp::Write(&[
+    0,
+    // CMD 1 Register:
+    // Interrupt persist for 1 integration cycle (bits 0 & 1)
+    // Measure ALS continuously (buts 5,6 & 7)
+    // Bit 2 is the interrupt bit
+    // Bits 3 & 4 are reserved
+    0b10100000,
+    // CMD 2 Register:
+    // Range 4000 (bits 0, 1)
+    // ADC resolution 8-bit (bits 2,3)
+    // Other bits are reserved
+    0b00001001,
+])];
+
+/// Read the light intensity, then power down the sensor.
+const READ_LI: &[RegOp] = &[RegOp::WriteRead(&[0x02], 2), RegOp::Write(&[0, 0])];
+
 #[derive(Copy, Clone, PartialEq)]
 enum State {
     Disabled,
     Enabling,
     Integrating,
     ReadingLI,
-    Disabling(usize),
 }
 
 pub struct Isl29035<'a, A: time::Alarm + 'a> {
-    i2c: &'a I2CDevice,
+    transaction: I2CTransaction<'a>,
     alarm: &'a A,
     state: Cell<State>,
-    buffer: TakeCell<'static, [u8]>,
     client: Cell<Option<&'a AmbientLightClient>>,
 }
 
 impl<'a, A: time::Alarm + 'a> Isl29035<'a, A> {
     pub fn new(i2c: &'a I2CDevice, alarm: &'a A, buffer: &'static mut [u8]) -> Isl29035<'a, A> {
         Isl29035 {
-            i2c: i2c,
+            transaction: I2CTransaction::new(i2c, buffer),
             alarm: alarm,
             state: Cell::new(State::Disabled),
-            buffer: TakeCell::new(buffer),
             client: Cell::new(None),
         }
     }
 
     pub fn start_read_lux(&self) {
         if self.state.get() == State::Disabled {
-            self.buffer.take().map(|buf| {
-                self.i2c.enable();
-                buf[0] = 0;
-                // CMD 1 Register:
-                // Interrupt persist for 1 integration cycle (bits 0 & 1)
-                // Measure ALS continuously (buts 5,6 & 7)
-                // Bit 2 is the interrupt bit
-                // Bits 3 & 4 are reserved
-                buf[1] = 0b10100000;
-
-                // CMD 2 Register:
-                // Range 4000 (bits 0, 1)
-                // ADC resolution 8-bit (bits 2,3)
-                // Other bits are reserved
-                buf[2] = 0b00001001;
-                self.i2c.write(buf, 3);
+            if self.transaction.start(ENABLE) == ReturnCode::SUCCESS {
                 self.state.set(State::Enabling);
-            });
+            }
         }
     }
 }
@@ -99,20 +108,27 @@ impl<'a, A: time::Alarm + 'a> AmbientLight for Isl29035<'a, A> {
 
 impl<'a, A: time::Alarm + 'a> time::Client for Isl29035<'a, A> {
     fn fired(&self) {
-        self.buffer.take().map(|buffer| {
-            // Turn on i2c to send commands.
-            self.i2c.enable();
-
-            buffer[0] = 0x02 as u8;
-            self.i2c.write_read(buffer, 1, 2);
-            self.state.set(State::ReadingLI);
-        });
+        if self.state.get() == State::Integrating {
+            if self.transaction.start(READ_LI) == ReturnCode::SUCCESS {
+                self.state.set(State::ReadingLI);
+            } else {
+                self.state.set(State::Disabled);
+            }
+        }
     }
 }
 
 impl<'a, A: time::Alarm + 'a> I2CClient for Isl29035<'a, A> {
-    fn command_complete(&self, buffer: &'static mut [u8], _error: Error) {
-        // TODO(alevy): handle I2C errors
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        let (data, error) = match self.transaction.command_complete(buffer, error) {
+            Some(result) => result,
+            None => return,
+        };
+        if error != Error::CommandComplete {
+            self.state.set(State::Disabled);
+            return;
+        }
+
         match self.state.get() {
             State::Enabling => {
                 // Set a timer to wait for the conversion to be done.
@@ -122,8 +138,6 @@ impl<'a, A: time::Alarm + 'a> I2CClient for Isl29035<'a, A> {
                 self.alarm.set_alarm(tics);
 
                 // Now wait for timer to expire
-                self.buffer.replace(buffer);
-                self.i2c.disable();
                 self.state.set(State::Integrating);
             }
             State::ReadingLI => {
@@ -134,17 +148,10 @@ impl<'a, A: time::Alarm + 'a> I2CClient for Isl29035<'a, A> {
                 //
                 // For a given Range and n (-bits of ADC resolution):
                 // Lux = Data * (Range / 2^n)
-                let data = buffer[0] as usize; //((buffer[1] as usize) << 8) | buffer[0] as usize;
+                let data = data.as_slice()[0] as usize; //((data[1] as usize) << 8) | data[0] as usize;
                 let lux = (data * 4000) >> 8;
 
-                buffer[0] = 0;
-                self.i2c.write(buffer, 2);
-                self.state.set(State::Disabling(lux));
-            }
-            State::Disabling(lux) => {
-                self.i2c.disable();
                 self.state.set(State::Disabled);
-                self.buffer.replace(buffer);
                 self.client.get().map(|client| client.callback(lux));
             }
             _ => {}
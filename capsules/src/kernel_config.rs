@@ -0,0 +1,295 @@
+//! Persistent kernel configuration.
+//!
+//! Stores a small, typed set of board settings (board name, 802.15.4 PAN ID
+//! and channel, kernel log level and a clock trim value) in nonvolatile
+//! storage, so that a board can be reconfigured without reflashing the
+//! kernel. The settings are read back at boot with `load()`; if the stored
+//! record is missing or corrupt the defaults are used instead.
+//!
+//! The record is a fixed 32 byte structure protected by a magic number and a
+//! checksum, written at a board-chosen address through any
+//! `hil::nonvolatile_storage::NonvolatileStorage` implementation, such as the
+//! kernel region of `nonvolatile_storage_driver`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kernel_config = static_init!(
+//!     capsules::kernel_config::KernelConfig<'static>,
+//!     capsules::kernel_config::KernelConfig::new(
+//!         nonvolatile_storage,         // Kernel accessible storage.
+//!         0x1000,                      // Address of the config record.
+//!         &mut capsules::kernel_config::BUFFER));
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, kernel_config);
+//! kernel_config.set_client(board_config_user);
+//! kernel_config.load();
+//! ```
+
+use core::cell::Cell;
+use core::str;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Length of the stored configuration record.
+pub const RECORD_LENGTH: usize = 32;
+
+/// Maximum length of the board name in bytes.
+pub const BOARD_NAME_LENGTH: usize = 16;
+
+pub static mut BUFFER: [u8; RECORD_LENGTH] = [0; RECORD_LENGTH];
+
+/// "KCFG"
+const MAGIC: u32 = 0x4b434647;
+const VERSION: u8 = 1;
+
+/// Verbosity of kernel debug output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogLevel {
+    Error = 0,
+    Warning = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl LogLevel {
+    fn from_u8(value: u8) -> Option<LogLevel> {
+        match value {
+            0 => Some(LogLevel::Error),
+            1 => Some(LogLevel::Warning),
+            2 => Some(LogLevel::Info),
+            3 => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// The configurable settings.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// UTF-8 board name, padded with zeros.
+    pub board_name: [u8; BOARD_NAME_LENGTH],
+    pub pan_id: u16,
+    pub channel: u8,
+    pub log_level: LogLevel,
+    /// Signed trim applied to the board's main oscillator calibration.
+    pub clock_trim: i16,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            board_name: [0; BOARD_NAME_LENGTH],
+            pan_id: 0xABCD,
+            channel: 26,
+            log_level: LogLevel::Info,
+            clock_trim: 0,
+        }
+    }
+}
+
+impl Config {
+    /// The board name, up to the first zero byte.
+    pub fn board_name(&self) -> &str {
+        let end = self
+            .board_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(BOARD_NAME_LENGTH);
+        str::from_utf8(&self.board_name[0..end]).unwrap_or("")
+    }
+
+    /// Set the board name, truncated to `BOARD_NAME_LENGTH` bytes.
+    pub fn set_board_name(&mut self, name: &str) {
+        self.board_name = [0; BOARD_NAME_LENGTH];
+        for (dst, src) in self.board_name.iter_mut().zip(name.bytes()) {
+            *dst = src;
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        put_u32(buf, 0, MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.log_level as u8;
+        buf[6] = self.channel;
+        buf[7] = 0;
+        put_u16(buf, 8, self.pan_id);
+        put_u16(buf, 10, self.clock_trim as u16);
+        buf[12..12 + BOARD_NAME_LENGTH].copy_from_slice(&self.board_name);
+        let checksum = checksum(&buf[0..28]);
+        put_u32(buf, 28, checksum);
+    }
+
+    fn decode(buf: &[u8]) -> Option<Config> {
+        if get_u32(buf, 0) != MAGIC
+            || buf[4] != VERSION
+            || get_u32(buf, 28) != checksum(&buf[0..28])
+        {
+            return None;
+        }
+        LogLevel::from_u8(buf[5]).map(|log_level| {
+            let mut board_name = [0; BOARD_NAME_LENGTH];
+            board_name.copy_from_slice(&buf[12..12 + BOARD_NAME_LENGTH]);
+            Config {
+                board_name: board_name,
+                pan_id: get_u16(buf, 8),
+                channel: buf[6],
+                log_level: log_level,
+                clock_trim: get_u16(buf, 10) as i16,
+            }
+        })
+    }
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    put_u16(buf, offset, value as u16);
+    put_u16(buf, offset + 2, (value >> 16) as u16);
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    get_u16(buf, offset) as u32 | (get_u16(buf, offset + 2) as u32) << 16
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Notified when the configuration has been loaded or saved.
+pub trait ConfigClient {
+    /// The configuration is available. `stored` is false if no valid record
+    /// was found and the defaults are in use.
+    fn config_loaded(&self, config: Config, stored: bool);
+
+    fn config_saved(&self, result: ReturnCode);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Loading,
+    Saving,
+}
+
+pub struct KernelConfig<'a> {
+    storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+    address: usize,
+    buffer: TakeCell<'static, [u8]>,
+    config: Cell<Config>,
+    state: Cell<State>,
+    client: Cell<Option<&'static ConfigClient>>,
+}
+
+impl<'a> KernelConfig<'a> {
+    pub fn new(
+        storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+        address: usize,
+        buffer: &'static mut [u8],
+    ) -> KernelConfig<'a> {
+        KernelConfig {
+            storage: storage,
+            address: address,
+            buffer: TakeCell::new(buffer),
+            config: Cell::new(Config::default()),
+            state: Cell::new(State::Idle),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static ConfigClient) {
+        self.client.set(Some(client));
+    }
+
+    /// The current settings. These are the defaults until `load()` finishes.
+    pub fn get(&self) -> Config {
+        self.config.get()
+    }
+
+    /// Replace the current settings. They are not persisted until `save()`.
+    pub fn set(&self, config: Config) {
+        self.config.set(config);
+    }
+
+    /// Read the stored configuration. `ConfigClient::config_loaded()` is
+    /// called when done.
+    pub fn load(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            if buffer.len() < RECORD_LENGTH {
+                self.buffer.replace(buffer);
+                return ReturnCode::ESIZE;
+            }
+            self.state.set(State::Loading);
+            let (rc, buffer) = self.storage.read(buffer, self.address, RECORD_LENGTH);
+            if rc != ReturnCode::SUCCESS {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.state.set(State::Idle);
+            }
+            rc
+        })
+    }
+
+    /// Persist the current settings. `ConfigClient::config_saved()` is called
+    /// when done.
+    pub fn save(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            if buffer.len() < RECORD_LENGTH {
+                self.buffer.replace(buffer);
+                return ReturnCode::ESIZE;
+            }
+            self.config.get().encode(buffer);
+            self.state.set(State::Saving);
+            let (rc, buffer) = self.storage.write(buffer, self.address, RECORD_LENGTH);
+            if rc != ReturnCode::SUCCESS {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.state.set(State::Idle);
+            }
+            rc
+        })
+    }
+}
+
+impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for KernelConfig<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let stored = if length >= RECORD_LENGTH {
+            Config::decode(buffer)
+        } else {
+            None
+        };
+        self.buffer.replace(buffer);
+        self.state.set(State::Idle);
+
+        stored.map(|config| self.config.set(config));
+        self.client
+            .get()
+            .map(|client| client.config_loaded(self.config.get(), stored.is_some()));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        self.state.set(State::Idle);
+
+        let result = if length >= RECORD_LENGTH {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.client.get().map(|client| client.config_saved(result));
+    }
+}
@@ -0,0 +1,157 @@
+//! Provides userspace with the kernel version and the features it supports.
+//!
+//! Libraries such as libtock use this to pick code paths at run time rather
+//! than assuming the features of the kernel they were written for. Features
+//! that depend on how the board is set up, like IPC, are given by the board.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kernel_info = static_init!(
+//!     capsules::kernel_info::KernelInfo,
+//!     capsules::kernel_info::KernelInfo::new(
+//!         capsules::kernel_info::FEATURE_IPC,
+//!         kernel::Grant::create()));
+//! ```
+//!
+//! Boards that detect their sensors at boot can also report which were found
+//! with `set_inventory()`.
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: Buffer for the kernel version string.
+//!
+//! ### Command
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//!   - Return: `SUCCESS`.
+//! - `1`: Get the system call ABI revision.
+//! - `2`: Get the feature bitmap, a combination of the `FEATURE_*` bits.
+//! - `3`: Get the length of the kernel version string in bytes.
+//! - `4`: Copy the kernel version string, as produced by `git describe`
+//!   (for example `release-1.2-12-g1234567`), into the allowed buffer.
+//!   - Return: the number of bytes copied, or `ENOMEM` if no buffer has been
+//!     allowed. The string is truncated to the length of the buffer and is
+//!     not NUL-terminated.
+//! - `5`: Get the sensor inventory, a bitmap of the board's sensors that
+//!   were detected. Which sensor each bit stands for is board specific.
+//!   - Return: the bitmap, `EBUSY` if detection has not finished, or
+//!     `ENOSUPPORT` if the board does not detect its sensors.
+
+use core::cell::Cell;
+use kernel;
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10002;
+
+/// Inter-process communication is available.
+pub const FEATURE_IPC: usize = 1 << 0;
+/// The exit system call is available.
+pub const FEATURE_EXIT: usize = 1 << 1;
+/// A 64-bit time source is available.
+pub const FEATURE_TIME64: usize = 1 << 2;
+/// The kernel's driver discovery interface (`kernel::QUERY_DRIVER_NUM`) is
+/// available. Always set.
+pub const FEATURE_DRIVER_QUERY: usize = 1 << 3;
+
+/// Reports which of the board's sensors are fitted.
+pub trait Inventory {
+    /// A bitmap of the sensors that were found, or `None` if detection has
+    /// not finished.
+    fn inventory(&self) -> Option<usize>;
+}
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct KernelInfo {
+    features: usize,
+    inventory: Cell<Option<&'static Inventory>>,
+    apps: Grant<App>,
+}
+
+impl KernelInfo {
+    /// `features` are the board-dependent `FEATURE_*` bits.
+    pub fn new(features: usize, grant: Grant<App>) -> KernelInfo {
+        KernelInfo {
+            features: features | FEATURE_DRIVER_QUERY,
+            inventory: Cell::new(None),
+            apps: grant,
+        }
+    }
+
+    pub fn set_inventory(&self, inventory: &'static Inventory) {
+        self.inventory.set(Some(inventory));
+    }
+}
+
+impl Driver for KernelInfo {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: kernel::ABI_REVISION,
+            },
+
+            2 => ReturnCode::SuccessWithValue {
+                value: self.features,
+            },
+
+            3 => ReturnCode::SuccessWithValue {
+                value: kernel::KERNEL_VERSION.len(),
+            },
+
+            4 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer.as_mut().map_or(ReturnCode::ENOMEM, |buffer| {
+                        let version = kernel::KERNEL_VERSION.as_bytes();
+                        let len = version.len().min(buffer.len());
+                        buffer.as_mut()[..len].copy_from_slice(&version[..len]);
+                        ReturnCode::SuccessWithValue { value: len }
+                    })
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            5 => self
+                .inventory
+                .get()
+                .map_or(ReturnCode::ENOSUPPORT, |inventory| {
+                    match inventory.inventory() {
+                        Some(value) => ReturnCode::SuccessWithValue { value: value },
+                        None => ReturnCode::EBUSY,
+                    }
+                }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -12,41 +12,74 @@ pub mod test;
 pub mod net;
 
 pub mod adc;
+pub mod aes;
 pub mod aes_ccm;
 pub mod alarm;
 pub mod ambient_light;
+pub mod analog_input;
+pub mod at86rf2xx;
+pub mod at86rf2xx_const;
+pub mod atecc608;
 pub mod app_flash_driver;
 pub mod ble_advertising_driver;
+pub mod ble_beacon;
+pub mod boot_info;
+pub mod bootloader_attributes;
 pub mod button;
+pub mod clock_trim;
 pub mod console;
+pub mod crash_dump;
 pub mod crc;
 pub mod dac;
+pub mod device_id;
+pub mod distance;
+pub mod esb;
 pub mod fm25cl;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
+pub mod hcsr04;
 pub mod humidity;
+pub mod hx711;
+pub mod i2c_hotplug;
+pub mod i2c_master;
 pub mod i2c_master_slave_driver;
+pub mod i2c_transaction;
+pub mod infrared;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kernel_config;
+pub mod kernel_info;
 pub mod led;
 pub mod lps25hb;
 pub mod ltc294x;
+pub mod management_agent;
 pub mod max17205;
 pub mod mcp23008;
+pub mod moisture;
+pub mod nfc_tag;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod pca9544a;
+pub mod pcap_sniffer;
+pub mod process_console;
+pub mod rf212;
+pub mod rf212_const;
 pub mod rf233;
 pub mod rf233_const;
+pub mod rf_frontend;
 pub mod rng;
 pub mod sdcard;
+pub mod sdio_sdcard;
 pub mod segger_rtt;
+pub mod sensor_probe;
 pub mod si7021;
+pub mod soil_moisture;
 pub mod spi;
 pub mod temperature;
+pub mod timestamp;
 pub mod tmp006;
 pub mod tsl2561;
 pub mod usb;
@@ -56,3 +89,5 @@ pub mod virtual_alarm;
 pub mod virtual_flash;
 pub mod virtual_i2c;
 pub mod virtual_spi;
+pub mod weight;
+pub mod yield_timer;
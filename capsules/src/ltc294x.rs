@@ -485,15 +485,24 @@ impl<'a> Driver for LTC294XDriver<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
-            0 => {
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
-            }
+            0 => Ok(self.callback.replace(callback)),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Forget the callback of a process that terminated, so that readings
+    /// and alerts are no longer delivered to it.
+    fn app_terminated(&self, app_id: AppId) {
+        let subscribed = self
+            .callback
+            .get()
+            .map_or(false, |callback| callback.app_id() == app_id);
+        if subscribed {
+            self.callback.set(None);
         }
     }
 
@@ -0,0 +1,392 @@
+//! A management protocol for host tools, over a dedicated UART.
+//!
+//! The agent lets a host list processes, read board attributes and upload an
+//! app image into a staging region of nonvolatile storage while the kernel
+//! keeps running, rather than halting the board over JTAG. Installing a
+//! staged image is left to whatever reads the staging region, such as a
+//! bootloader.
+//!
+//! Framing
+//! -------
+//!
+//! Every request and response is a frame:
+//!
+//! ```text
+//! Byte     | Contents
+//! -------- | -------------------------------------------------------------
+//! 0        | `SYNC`
+//! 1        | Command. Responses set `RESPONSE` (bit 7) in the command.
+//! 2-3      | Payload length, little-endian, at most `MAX_PAYLOAD`
+//! 4...     | Payload
+//! last 4   | CRC-32 (IEEE 802.3), little-endian, of all bytes but the sync
+//! ```
+//!
+//! The agent handles one request at a time and ignores bytes until the next
+//! `SYNC`, so the host should wait for each response. The first byte of
+//! every response payload is the status, a `ReturnCode` as a signed byte. A
+//! request with a bad CRC gets a `FAIL` response.
+//!
+//! Commands
+//! --------
+//!
+//! Multi-byte values are little-endian.
+//!
+//! - `CMD_PING`: no request payload. Responds with the protocol version (1
+//!   byte), the size of the staging region (4 bytes) and the kernel version
+//!   string.
+//! - `CMD_LIST_PROCESSES`: request is the first process slot to list (1
+//!   byte). Responds with an entry per process, as many as fit: slot (1),
+//!   state (1: 0 running, 1 yielded, 2 faulted, 3 stopped), name length (1)
+//!   and name.
+//! - `CMD_ATTRIBUTE`: request is an attribute index (1 byte). Responds with
+//!   the key length (1), key and value, or `EINVAL` past the last attribute.
+//! - `CMD_STAGING_WRITE`: request is an offset into the staging region (4)
+//!   followed by the data. Responds once the data is written.
+//! - `CMD_STAGING_READ`: request is an offset (4) and a length (2), at most
+//!   `MAX_PAYLOAD - 1`. Responds with the data.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let agent = static_init!(
+//!     capsules::management_agent::ManagementAgent<'static, usart::USART>,
+//!     capsules::management_agent::ManagementAgent::new(
+//!         &usart::USART1,
+//!         115200,
+//!         kernel::process_memory::ProcessMemory::new(),
+//!         &[("board", "hail"), ("arch", "cortex-m4")],
+//!         storage,
+//!         0x40000,
+//!         0x20000,
+//!         &mut capsules::management_agent::TX_BUF,
+//!         &mut capsules::management_agent::RX_BUF
+//!     )
+//! );
+//! hil::uart::UART::set_client(&usart::USART1, agent);
+//! storage.set_client(agent);
+//! agent.initialize();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel;
+use kernel::common::cells::TakeCell;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::uart::{self, UART};
+use kernel::process_memory::ProcessMemory;
+use kernel::procs::State;
+use kernel::ReturnCode;
+
+pub const SYNC: u8 = 0x7E;
+pub const RESPONSE: u8 = 0x80;
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const MAX_PAYLOAD: usize = 256;
+
+pub const CMD_PING: u8 = 0x00;
+pub const CMD_LIST_PROCESSES: u8 = 0x01;
+pub const CMD_ATTRIBUTE: u8 = 0x02;
+pub const CMD_STAGING_WRITE: u8 = 0x10;
+pub const CMD_STAGING_READ: u8 = 0x11;
+
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 4;
+
+pub static mut TX_BUF: [u8; HEADER_LEN + MAX_PAYLOAD + CRC_LEN] =
+    [0; HEADER_LEN + MAX_PAYLOAD + CRC_LEN];
+pub static mut RX_BUF: [u8; MAX_PAYLOAD + CRC_LEN] = [0; MAX_PAYLOAD + CRC_LEN];
+
+#[derive(Copy, Clone, PartialEq)]
+enum RxState {
+    Sync,
+    Header,
+    Body,
+}
+
+pub struct ManagementAgent<'a, U: UART + 'a> {
+    uart: &'a U,
+    baud_rate: u32,
+    processes: ProcessMemory,
+    attributes: &'static [(&'static str, &'static str)],
+    storage: &'a NonvolatileStorage,
+    staging_start: usize,
+    staging_len: usize,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_state: Cell<RxState>,
+    /// The command and payload length of the frame being received.
+    command: Cell<u8>,
+    payload_len: Cell<usize>,
+}
+
+impl<'a, U: UART> ManagementAgent<'a, U> {
+    /// Stage app images in the `staging_len` bytes of `storage` at
+    /// `staging_start`. `attributes` are the key-value pairs reported by
+    /// `CMD_ATTRIBUTE`.
+    pub fn new(
+        uart: &'a U,
+        baud_rate: u32,
+        processes: ProcessMemory,
+        attributes: &'static [(&'static str, &'static str)],
+        storage: &'a NonvolatileStorage,
+        staging_start: usize,
+        staging_len: usize,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> ManagementAgent<'a, U> {
+        ManagementAgent {
+            uart: uart,
+            baud_rate: baud_rate,
+            processes: processes,
+            attributes: attributes,
+            storage: storage,
+            staging_start: staging_start,
+            staging_len: staging_len,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_state: Cell::new(RxState::Sync),
+            command: Cell::new(0),
+            payload_len: Cell::new(0),
+        }
+    }
+
+    pub fn initialize(&self) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        self.receive_sync();
+    }
+
+    /// Wait for the start of the next request.
+    fn receive_sync(&self) {
+        self.rx_buffer.take().map(|buffer| {
+            self.rx_state.set(RxState::Sync);
+            self.uart.receive(buffer, 1);
+        });
+    }
+
+    /// Send a response to the current command. `fill` writes the payload
+    /// after the status byte and returns its length.
+    fn respond<F: FnOnce(&mut [u8]) -> usize>(&self, status: ReturnCode, fill: F) {
+        self.tx_buffer.take().map(|buffer| {
+            let len = {
+                let payload = &mut buffer[HEADER_LEN..HEADER_LEN + MAX_PAYLOAD];
+                payload[0] = isize::from(status) as u8;
+                1 + fill(&mut payload[1..])
+            };
+            buffer[0] = SYNC;
+            buffer[1] = self.command.get() | RESPONSE;
+            buffer[2] = len as u8;
+            buffer[3] = (len >> 8) as u8;
+            let crc = crc32(CRC_INIT, &buffer[1..HEADER_LEN + len]) ^ CRC_INIT;
+            write_u32(&mut buffer[HEADER_LEN + len..], crc);
+            self.uart.transmit(buffer, HEADER_LEN + len + CRC_LEN);
+        });
+    }
+
+    fn ping(&self) {
+        self.respond(ReturnCode::SUCCESS, |payload| {
+            payload[0] = PROTOCOL_VERSION;
+            write_u32(&mut payload[1..], self.staging_len as u32);
+            let version = kernel::KERNEL_VERSION.as_bytes();
+            let len = cmp::min(version.len(), payload.len() - 5);
+            payload[5..5 + len].copy_from_slice(&version[..len]);
+            5 + len
+        });
+    }
+
+    fn list_processes(&self, first: usize) {
+        self.respond(ReturnCode::SUCCESS, |payload| {
+            let mut len = 0;
+            for slot in first..self.processes.num_slots() {
+                let appid = match self.processes.appid(slot) {
+                    Some(appid) => appid,
+                    None => continue,
+                };
+                let name = self.processes.name(appid).unwrap_or("").as_bytes();
+                let name_len = cmp::min(name.len(), 255);
+                if len + 3 + name_len > payload.len() {
+                    break;
+                }
+                payload[len] = slot as u8;
+                payload[len + 1] = match self.processes.state(appid) {
+                    Some(State::Running) => 0,
+                    Some(State::Yielded) => 1,
+                    Some(State::StoppedRunning) | Some(State::StoppedYielded) => 3,
+                    _ => 2,
+                };
+                payload[len + 2] = name_len as u8;
+                payload[len + 3..len + 3 + name_len].copy_from_slice(&name[..name_len]);
+                len += 3 + name_len;
+            }
+            len
+        });
+    }
+
+    fn attribute(&self, index: usize) {
+        match self.attributes.get(index) {
+            Some(&(key, value)) => self.respond(ReturnCode::SUCCESS, |payload| {
+                let key = &key.as_bytes()[..cmp::min(key.len(), payload.len() - 1)];
+                let value_len = cmp::min(value.len(), payload.len() - 1 - key.len());
+                payload[0] = key.len() as u8;
+                payload[1..1 + key.len()].copy_from_slice(key);
+                payload[1 + key.len()..1 + key.len() + value_len]
+                    .copy_from_slice(&value.as_bytes()[..value_len]);
+                1 + key.len() + value_len
+            }),
+            None => self.respond(ReturnCode::EINVAL, |_| 0),
+        }
+    }
+
+    /// Whether `len` bytes at `offset` fit in the staging region.
+    fn in_staging(&self, offset: usize, len: usize) -> bool {
+        offset
+            .checked_add(len)
+            .map_or(false, |end| end <= self.staging_len)
+    }
+
+    /// Handle a complete request whose payload is at the start of `buffer`.
+    /// Returns the buffer unless it was passed to the storage.
+    fn handle_request(&self, buffer: &'static mut [u8]) -> Option<&'static mut [u8]> {
+        let len = self.payload_len.get();
+        match self.command.get() {
+            CMD_PING => self.ping(),
+            CMD_LIST_PROCESSES if len >= 1 => self.list_processes(buffer[0] as usize),
+            CMD_ATTRIBUTE if len >= 1 => self.attribute(buffer[0] as usize),
+            CMD_STAGING_WRITE if len >= 4 => {
+                let offset = read_u32(buffer) as usize;
+                let data_len = len - 4;
+                if !self.in_staging(offset, data_len) {
+                    self.respond(ReturnCode::EINVAL, |_| 0);
+                } else {
+                    // Move the data to the start of the buffer for the
+                    // storage.
+                    for i in 0..data_len {
+                        buffer[i] = buffer[i + 4];
+                    }
+                    let (res, buffer) =
+                        self.storage
+                            .write(buffer, self.staging_start + offset, data_len);
+                    if res != ReturnCode::SUCCESS {
+                        self.respond(res, |_| 0);
+                    }
+                    return buffer;
+                }
+            }
+            CMD_STAGING_READ if len >= 6 => {
+                let offset = read_u32(buffer) as usize;
+                let read_len = buffer[4] as usize | (buffer[5] as usize) << 8;
+                if read_len > MAX_PAYLOAD - 1 || !self.in_staging(offset, read_len) {
+                    self.respond(ReturnCode::EINVAL, |_| 0);
+                } else {
+                    let (res, buffer) =
+                        self.storage
+                            .read(buffer, self.staging_start + offset, read_len);
+                    if res != ReturnCode::SUCCESS {
+                        self.respond(res, |_| 0);
+                    }
+                    return buffer;
+                }
+            }
+            CMD_LIST_PROCESSES | CMD_ATTRIBUTE | CMD_STAGING_WRITE | CMD_STAGING_READ => {
+                self.respond(ReturnCode::EINVAL, |_| 0)
+            }
+            _ => self.respond(ReturnCode::ENOSUPPORT, |_| 0),
+        }
+        Some(buffer)
+    }
+}
+
+impl<'a, U: UART> uart::Client for ManagementAgent<'a, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        self.receive_sync();
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        let ok = error == uart::Error::CommandComplete;
+        match self.rx_state.get() {
+            RxState::Sync => {
+                if ok && rx_len == 1 && buffer[0] == SYNC {
+                    self.rx_state.set(RxState::Header);
+                    self.uart.receive(buffer, HEADER_LEN - 1);
+                } else {
+                    self.uart.receive(buffer, 1);
+                }
+            }
+            RxState::Header => {
+                let len = buffer[1] as usize | (buffer[2] as usize) << 8;
+                if !ok || rx_len != HEADER_LEN - 1 || len > MAX_PAYLOAD {
+                    self.rx_state.set(RxState::Sync);
+                    self.uart.receive(buffer, 1);
+                } else {
+                    self.command.set(buffer[0]);
+                    self.payload_len.set(len);
+                    self.rx_state.set(RxState::Body);
+                    self.uart.receive(buffer, len + CRC_LEN);
+                }
+            }
+            RxState::Body => {
+                let len = self.payload_len.get();
+                if !ok || rx_len != len + CRC_LEN {
+                    self.rx_state.set(RxState::Sync);
+                    self.uart.receive(buffer, 1);
+                    return;
+                }
+
+                let header = [self.command.get(), len as u8, (len >> 8) as u8];
+                let crc = crc32(crc32(CRC_INIT, &header), &buffer[..len]) ^ CRC_INIT;
+                if crc != read_u32(&buffer[len..]) {
+                    self.rx_buffer.replace(buffer);
+                    self.respond(ReturnCode::FAIL, |_| 0);
+                } else {
+                    self.handle_request(buffer)
+                        .map(|buffer| self.rx_buffer.replace(buffer));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, U: UART> NonvolatileStorageClient for ManagementAgent<'a, U> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.respond(ReturnCode::SUCCESS, |payload| {
+            payload[..length].copy_from_slice(&buffer[..length]);
+            length
+        });
+        self.rx_buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.rx_buffer.replace(buffer);
+        self.respond(ReturnCode::SUCCESS, |_| 0);
+    }
+}
+
+const CRC_INIT: u32 = 0xFFFFFFFF;
+
+/// Continue a CRC-32 (IEEE 802.3, reflected) over `data`.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    crc
+}
+
+fn read_u32(buffer: &[u8]) -> u32 {
+    buffer[0] as u32 | (buffer[1] as u32) << 8 | (buffer[2] as u32) << 16 | (buffer[3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], word: u32) {
+    buffer[0] = word as u8;
+    buffer[1] = (word >> 8) as u8;
+    buffer[2] = (word >> 16) as u8;
+    buffer[3] = (word >> 24) as u8;
+}
@@ -0,0 +1,114 @@
+//! Shared userland driver for soil moisture sensors.
+//!
+//! You need a device that provides the `hil::sensors::Moisture` trait.
+//!
+//! ```rust
+//! let moisture = static_init!(
+//!     capsules::moisture::MoistureSensor<'static>,
+//!     capsules::moisture::MoistureSensor::new(soil_moisture, kernel::Grant::create())
+//! );
+//! hil::sensors::Moisture::set_client(soil_moisture, moisture);
+//! ```
+
+use core::cell::Cell;
+use core::mem;
+use kernel::hil;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x60008;
+
+/// Per-process metadata
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct MoistureSensor<'a> {
+    sensor: &'a hil::sensors::Moisture,
+    command_pending: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a> MoistureSensor<'a> {
+    pub fn new(sensor: &'a hil::sensors::Moisture, grant: Grant<App>) -> MoistureSensor {
+        MoistureSensor {
+            sensor: sensor,
+            command_pending: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn enqueue_sensor_reading(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    ReturnCode::EBUSY
+                } else {
+                    app.pending = true;
+                    if !self.command_pending.get() {
+                        self.command_pending.set(true);
+                        self.sensor.read_moisture();
+                    }
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a> Driver for MoistureSensor<'a> {
+    /// Subscribe to moisture readings
+    ///
+    /// ### `subscribe`
+    ///
+    /// - `0`: Subscribe to moisture readings. The callback signature is
+    /// `fn(moisture: usize)`, where `moisture` is in hundredths of percent.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Initiate moisture readings
+    ///
+    /// Sensor readings are coalesced if processes request them concurrently.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Check driver presence
+    /// - `1`: Start a moisture reading
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 => self.enqueue_sensor_reading(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> hil::sensors::MoistureClient for MoistureSensor<'a> {
+    fn callback(&self, value: usize) {
+        self.command_pending.set(false);
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                if let Some(mut callback) = app.callback {
+                    callback.schedule(value, 0, 0);
+                }
+            }
+        });
+    }
+}
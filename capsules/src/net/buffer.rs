@@ -0,0 +1,251 @@
+//! Shared pool of packet buffers.
+//!
+//! Rather than each layer of the network stack owning its own static buffers
+//! and copying packets between them, a layer allocates a `PacketBuffer` from a
+//! `PacketPool` and passes it down the stack. Buffers are reference counted:
+//! cloning a `PacketBuffer` adds a reference, dropping one removes it, and the
+//! slot returns to the pool when the last reference is dropped. This lets the
+//! sender keep a handle to a packet (for example, to retransmit it) while the
+//! lower layers are transmitting it.
+//!
+//! Each slot has a fixed size. The packet occupies a window of the slot,
+//! which can grow towards the front (`prepend`, using headroom reserved at
+//! allocation) or the back (`append`, using the remaining tailroom), so that
+//! a layer can add its header in place in front of the payload of the layer
+//! above.
+//!
+//! A layer that hands the memory of a slot to hardware or to a lower layer
+//! that needs a `&'static mut [u8]` can `lend` it; the contents cannot be
+//! accessed through any handle until the memory is given back with `restore`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let packet_slots = static_init!(
+//!     [capsules::net::buffer::PacketSlot; capsules::net::buffer::POOL_SLOTS],
+//!     Default::default());
+//! let packet_pool = static_init!(
+//!     capsules::net::buffer::PacketPool<'static>,
+//!     capsules::net::buffer::PacketPool::new(
+//!         &mut capsules::net::buffer::POOL_MEMORY,
+//!         packet_slots));
+//!
+//! let packet = packet_pool.alloc(0).unwrap();
+//! packet.append(b"Hello");
+//! udp_sender.send_buffer_to(dst_addr, 5683, 5683, packet);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::ReturnCode;
+
+/// Size of each slot: the IPv6 minimum MTU.
+pub const SLOT_SIZE: usize = 1280;
+
+/// Number of packets the default pool holds.
+pub const POOL_SLOTS: usize = 4;
+
+pub static mut POOL_MEMORY: [u8; SLOT_SIZE * POOL_SLOTS] = [0; SLOT_SIZE * POOL_SLOTS];
+
+/// State of one slot in a `PacketPool`.
+pub struct PacketSlot {
+    buf: TakeCell<'static, [u8]>,
+    refs: Cell<usize>,
+    /// The packet is `buf[head..tail]`.
+    head: Cell<usize>,
+    tail: Cell<usize>,
+}
+
+impl Default for PacketSlot {
+    fn default() -> PacketSlot {
+        PacketSlot {
+            buf: TakeCell::empty(),
+            refs: Cell::new(0),
+            head: Cell::new(0),
+            tail: Cell::new(0),
+        }
+    }
+}
+
+pub struct PacketPool<'a> {
+    slots: &'a [PacketSlot],
+    slot_size: usize,
+}
+
+impl<'a> PacketPool<'a> {
+    /// Divide `memory` evenly among `slots`.
+    pub fn new(memory: &'static mut [u8], slots: &'a [PacketSlot]) -> PacketPool<'a> {
+        let slot_size = if slots.is_empty() {
+            0
+        } else {
+            memory.len() / slots.len()
+        };
+        let mut remaining = memory;
+        for slot in slots.iter() {
+            let (buf, rest) = { remaining }.split_at_mut(slot_size);
+            slot.buf.replace(buf);
+            remaining = rest;
+        }
+        PacketPool {
+            slots: slots,
+            slot_size: slot_size,
+        }
+    }
+
+    /// Allocate an empty packet with `headroom` bytes reserved in front of
+    /// it. Returns `None` if all slots are in use or `headroom` exceeds the
+    /// slot size.
+    pub fn alloc(&'a self, headroom: usize) -> Option<PacketBuffer<'a>> {
+        if headroom > self.slot_size {
+            return None;
+        }
+        self.slots
+            .iter()
+            .position(|slot| slot.refs.get() == 0)
+            .map(|index| {
+                let slot = &self.slots[index];
+                slot.refs.set(1);
+                slot.head.set(headroom);
+                slot.tail.set(headroom);
+                PacketBuffer {
+                    pool: self,
+                    index: index,
+                }
+            })
+    }
+
+    /// The number of free slots.
+    pub fn available(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.refs.get() == 0)
+            .count()
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+}
+
+/// A reference to a packet in a `PacketPool`.
+pub struct PacketBuffer<'a> {
+    pool: &'a PacketPool<'a>,
+    index: usize,
+}
+
+impl<'a> PacketBuffer<'a> {
+    fn slot(&self) -> &PacketSlot {
+        &self.pool.slots[self.index]
+    }
+
+    /// The length of the packet.
+    pub fn len(&self) -> usize {
+        self.slot().tail.get() - self.slot().head.get()
+    }
+
+    /// Offset of the packet from the start of the slot.
+    pub fn offset(&self) -> usize {
+        self.slot().head.get()
+    }
+
+    /// Space available in front of the packet.
+    pub fn headroom(&self) -> usize {
+        self.slot().head.get()
+    }
+
+    /// Space available after the packet.
+    pub fn tailroom(&self) -> usize {
+        self.pool.slot_size - self.slot().tail.get()
+    }
+
+    /// Whether other handles refer to the same packet.
+    pub fn is_shared(&self) -> bool {
+        self.slot().refs.get() > 1
+    }
+
+    /// Grow the packet by `len` bytes at the front, returning ESIZE if there
+    /// is not enough headroom. The new bytes should be written with
+    /// `map`.
+    pub fn prepend(&self, len: usize) -> ReturnCode {
+        let head = self.slot().head.get();
+        if len > head {
+            return ReturnCode::ESIZE;
+        }
+        self.slot().head.set(head - len);
+        ReturnCode::SUCCESS
+    }
+
+    /// Copy `data` to the end of the packet, returning ESIZE if there is not
+    /// enough tailroom or EBUSY if the memory is lent out.
+    pub fn append(&self, data: &[u8]) -> ReturnCode {
+        let tail = self.slot().tail.get();
+        if data.len() > self.tailroom() {
+            return ReturnCode::ESIZE;
+        }
+        self.slot().buf.map_or(ReturnCode::EBUSY, |buf| {
+            buf[tail..tail + data.len()].copy_from_slice(data);
+            self.slot().tail.set(tail + data.len());
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Remove `len` bytes from the front of the packet, for example once the
+    /// header they contain has been processed.
+    pub fn trim_front(&self, len: usize) -> ReturnCode {
+        if len > self.len() {
+            return ReturnCode::ESIZE;
+        }
+        self.slot().head.set(self.slot().head.get() + len);
+        ReturnCode::SUCCESS
+    }
+
+    /// Remove `len` bytes from the end of the packet.
+    pub fn trim_back(&self, len: usize) -> ReturnCode {
+        if len > self.len() {
+            return ReturnCode::ESIZE;
+        }
+        self.slot().tail.set(self.slot().tail.get() - len);
+        ReturnCode::SUCCESS
+    }
+
+    /// Access the packet in place. Returns `None` while the memory is lent
+    /// out.
+    pub fn map<F, R>(&self, closure: F) -> Option<R>
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let (head, tail) = (self.slot().head.get(), self.slot().tail.get());
+        self.slot().buf.map(|buf| closure(&mut buf[head..tail]))
+    }
+
+    /// Take the memory of the whole slot, so that it can be handed to a
+    /// layer that needs a static buffer. The packet starts at `offset()`
+    /// and is `len()` bytes long. Returns `None` if the memory is already
+    /// lent out. The memory must be restored before the last handle is
+    /// dropped, or the slot cannot be used again.
+    pub fn lend(&self) -> Option<&'static mut [u8]> {
+        self.slot().buf.take()
+    }
+
+    /// Give back memory taken with `lend`.
+    pub fn restore(&self, buf: &'static mut [u8]) {
+        self.slot().buf.replace(buf);
+    }
+}
+
+impl<'a> Clone for PacketBuffer<'a> {
+    fn clone(&self) -> PacketBuffer<'a> {
+        self.slot().refs.set(self.slot().refs.get() + 1);
+        PacketBuffer {
+            pool: self.pool,
+            index: self.index,
+        }
+    }
+}
+
+impl<'a> Drop for PacketBuffer<'a> {
+    fn drop(&mut self) {
+        self.slot().refs.set(self.slot().refs.get() - 1);
+    }
+}
@@ -2,6 +2,7 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html] struct and associated helper functions.
 
+use core::cmp;
 use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use net::ipv6::ipv6::IP6Header;
 use net::udp::udp::UDPHeader;
@@ -68,6 +69,21 @@ impl IPAddr {
         }
     }
 
+    // Whether the first prefix_len bits of the address are those of prefix
+    pub fn matches_prefix(&self, prefix: &IPAddr, prefix_len: u8) -> bool {
+        let full_bytes = cmp::min(prefix_len / 8, 16) as usize;
+        let remaining = (prefix_len & 0x7) as usize;
+        if self.0[0..full_bytes] != prefix.0[0..full_bytes] {
+            return false;
+        }
+        if remaining != 0 && full_bytes < 16 {
+            let mask = (0xff as u8) << (8 - remaining);
+            (self.0[full_bytes] & mask) == (prefix.0[full_bytes] & mask)
+        } else {
+            true
+        }
+    }
+
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
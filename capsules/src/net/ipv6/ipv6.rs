@@ -293,19 +293,45 @@ impl<'a> IPPayload<'a> {
         if self.payload.len() < payload.len() {
             // TODO: Error
         }
-        self.payload.copy_from_slice(&payload);
+        self.payload[..payload.len()].copy_from_slice(&payload);
+        self.set_transport_header(transport_header, payload.len())
+    }
+
+    /// This function sets the TransportHeader for a payload that is already
+    /// in the payload buffer, such as one lent from a `PacketBuffer`.
+    ///
+    /// # Arguments
+    ///
+    /// `transport_header` - The new `TransportHeader` header for the payload
+    /// `payload_len` - The length of the transport payload
+    ///
+    /// # Return Value
+    ///
+    /// `(u8, u16)` - Returns a tuple of the `ip6_nh` type of the
+    /// `transport_header` and the total length of the `IPPayload`
+    /// (when serialized)
+    pub fn set_transport_header(
+        &mut self,
+        transport_header: TransportHeader,
+        payload_len: usize,
+    ) -> (u8, u16) {
         match transport_header {
             TransportHeader::UDP(mut udp_header) => {
-                let length = (payload.len() + udp_header.get_hdr_size()) as u16;
+                let length = (payload_len + udp_header.get_hdr_size()) as u16;
                 udp_header.set_len(length);
+                self.header = TransportHeader::UDP(udp_header);
                 (ip6_nh::UDP, length)
             }
             TransportHeader::ICMP(mut icmp_header) => {
-                let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
+                let length = (payload_len + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            other_header => {
+                self.header = other_header;
+                (ip6_nh::NO_NEXT, payload_len as u16)
+            }
         }
     }
 
@@ -442,8 +468,27 @@ impl<'a> IP6Packet<'a> {
         self.header.set_payload_len(payload_len);
     }
 
+    /// This function sets the transport header for a transport payload of
+    /// `payload_len` bytes that is already at the start of the payload
+    /// buffer, and sets the `IP6Header` next header and payload length
+    /// fields to match.
+    ///
+    /// # Arguments
+    ///
+    /// `transport_header` - The `TransportHeader` to be set as the next header
+    /// `payload_len` - The length of the transport payload
+    pub fn set_transport_header(&mut self, transport_header: TransportHeader, payload_len: usize) {
+        let (next_header, payload_len) = self
+            .payload
+            .set_transport_header(transport_header, payload_len);
+        self.header.set_next_header(next_header);
+        self.header.set_payload_len(payload_len);
+    }
+
     // TODO: Currently, the receive path is unimplemented, and this function
-    // should *not* be called
+    // should *not* be called. Packet filter hooks, which would let a
+    // privileged capsule accept or drop inbound packets by address and port
+    // before they reach UDP, wait on that path as well.
     pub fn decode(buf: &[u8], ip6_packet: &mut IP6Packet) -> Result<usize, ()> {
         let (_offset, header) = IP6Header::decode(buf).done().ok_or(())?;
         ip6_packet.header = header;
@@ -0,0 +1,188 @@
+//! This file contains the [IP6Router](struct.IP6Router.html), which
+//! implements the `IP6Sender` trait on top of several network interfaces at
+//! once, such as a 6LoWPAN radio and a SLIP serial link
+//! ([net::slip](../../slip/index.html)). Each packet is sent over the
+//! interface chosen by a small routing table, so the upper layers (UDP,
+//! ICMPv6) do not need to know which links the board has.
+//!
+//! Routes map an address prefix to an interface; the longest matching prefix
+//! wins, and a route with a prefix length of 0 is the default route.
+//!
+//! The router only sends packets that originate on this device. Forwarding
+//! packets received on one interface onto another, as a 6LoWPAN border router
+//! between the radio and a SLIP link to a host would, waits on the receive
+//! path: `IP6Packet::decode` is unimplemented, so neither a packet
+//! reassembled by 6LoWPAN nor a frame handed to a `SlipRxClient` can be
+//! turned back into an `IP6Packet` to send on over another interface.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let router = static_init!(IP6Router<'static>, IP6Router::new());
+//! let lowpan = router.add_interface(sixlowpan_interface).unwrap();
+//! let slip = router.add_interface(slip_interface).unwrap();
+//! router.add_route(MESH_PREFIX, 64, lowpan);
+//! router.add_route(IPAddr::new(), 0, slip);
+//!
+//! let udp_send_struct = static_init!(
+//!     UDPSendStruct<'static, IP6Router<'static>>,
+//!     UDPSendStruct::new(router)
+//! );
+//! router.set_client(udp_send_struct);
+//! ```
+
+use core::cell::Cell;
+use kernel::ReturnCode;
+use net::buffer::PacketBuffer;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::TransportHeader;
+use net::ipv6::ipv6_send::{IP6Client, IP6Sender, NetworkInterface};
+
+/// The maximum number of interfaces a router sends over.
+pub const MAX_INTERFACES: usize = 4;
+
+/// The maximum number of entries in the routing table.
+pub const MAX_ROUTES: usize = 8;
+
+#[derive(Copy, Clone)]
+struct Route {
+    prefix: IPAddr,
+    prefix_len: u8,
+    interface: usize,
+}
+
+pub struct IP6Router<'a> {
+    interfaces: [Cell<Option<&'a NetworkInterface<'a>>>; MAX_INTERFACES],
+    routes: [Cell<Option<Route>>; MAX_ROUTES],
+    client: Cell<Option<&'a IP6Client>>,
+}
+
+impl<'a> IP6Router<'a> {
+    pub fn new() -> IP6Router<'a> {
+        IP6Router {
+            interfaces: Default::default(),
+            routes: Default::default(),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Add an interface to send packets over, and become its client.
+    /// Returns the index of the interface, which names it in routes, or
+    /// `None` if `MAX_INTERFACES` interfaces have already been added.
+    pub fn add_interface(&'a self, interface: &'a NetworkInterface<'a>) -> Option<usize> {
+        self.interfaces
+            .iter()
+            .position(|slot| slot.get().is_none())
+            .map(|index| {
+                interface.set_client(self);
+                self.interfaces[index].set(Some(interface));
+                index
+            })
+    }
+
+    /// Send packets for addresses starting with the first `prefix_len` bits
+    /// of `prefix` over interface `interface`. A route for the same prefix
+    /// is replaced.
+    ///
+    /// Returns `EINVAL` if `interface` has not been added or `prefix_len` is
+    /// more than 128, and `ENOMEM` if the routing table is full.
+    pub fn add_route(&self, prefix: IPAddr, prefix_len: u8, interface: usize) -> ReturnCode {
+        if prefix_len > 128 || self.interface(interface).is_none() {
+            return ReturnCode::EINVAL;
+        }
+        let slot = self
+            .find_route(prefix, prefix_len)
+            .or_else(|| self.routes.iter().find(|slot| slot.get().is_none()));
+        match slot {
+            Some(slot) => {
+                slot.set(Some(Route {
+                    prefix: prefix,
+                    prefix_len: prefix_len,
+                    interface: interface,
+                }));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Remove the route for `prefix`. Returns `EINVAL` if there is none.
+    pub fn remove_route(&self, prefix: IPAddr, prefix_len: u8) -> ReturnCode {
+        match self.find_route(prefix, prefix_len) {
+            Some(slot) => {
+                slot.set(None);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    /// The interface packets to `dst` are sent over, if there is a route to
+    /// it.
+    pub fn route(&self, dst: IPAddr) -> Option<&'a NetworkInterface<'a>> {
+        self.routes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|route| dst.matches_prefix(&route.prefix, route.prefix_len))
+            .max_by_key(|route| route.prefix_len)
+            .and_then(|route| self.interface(route.interface))
+    }
+
+    fn interface(&self, index: usize) -> Option<&'a NetworkInterface<'a>> {
+        self.interfaces.get(index).and_then(|slot| slot.get())
+    }
+
+    fn find_route(&self, prefix: IPAddr, prefix_len: u8) -> Option<&Cell<Option<Route>>> {
+        self.routes.iter().find(|slot| {
+            slot.get().map_or(false, |route| {
+                route.prefix_len == prefix_len && route.prefix.matches_prefix(&prefix, prefix_len)
+            })
+        })
+    }
+}
+
+impl<'a> IP6Sender<'a> for IP6Router<'a> {
+    fn set_client(&self, client: &'a IP6Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Sets the source address of every interface. Boards whose interfaces
+    /// are on different networks set the address of each interface instead.
+    fn set_addr(&self, src_addr: IPAddr) {
+        for slot in self.interfaces.iter() {
+            slot.get().map(|interface| interface.set_addr(src_addr));
+        }
+    }
+
+    /// Returns `EINVAL` if there is no route to `dst`.
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.route(dst).map_or(ReturnCode::EINVAL, |interface| {
+            interface.send_to(dst, transport_header, payload)
+        })
+    }
+
+    /// Returns `EINVAL` if there is no route to `dst`.
+    fn send_buffer(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode {
+        match self.route(dst) {
+            Some(interface) => interface.send_buffer(dst, transport_header, packet),
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+impl<'a> IP6Client for IP6Router<'a> {
+    fn send_done(&self, result: ReturnCode) {
+        self.client.get().map(|client| client.send_done(result));
+    }
+}
@@ -4,22 +4,29 @@
 //! must be implemented by upper layers to receive the `send_done` callback
 //! when a transmission has completed.
 //!
-//! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! The [NetworkInterface](trait.NetworkInterface.html) trait is the interface
+//! to a single link that IPv6 packets can be sent over. The
+//! [IP6Router](../ipv6_router/struct.IP6Router.html) implements `IP6Sender`
+//! on top of one or more network interfaces, choosing the interface for each
+//! packet from its routing table.
+//!
+//! This file also includes an implementation of the `NetworkInterface` trait,
+//! which sends an IPv6 packet using 6LoWPAN.
 
 // Additional Work and Known Problems
 // ----------------------------------
 // The main areas for additional work is with regards to the interface provided
 // by `IP6Sender`. The current interface differs from the one provided in
 // the networking stack overview document, and should be changed to better
-// reflect that document. Additionally, the specific implementation is
-// over 6LoWPAN, and should be separated from the generic IPv6 sending
-// interface.
+// reflect that document.
 
 use core::cell::Cell;
+use core::mem;
 use ieee802154::device::{MacDevice, TxClient};
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{MapCell, TakeCell};
+use kernel::common::lease::Lease;
 use kernel::ReturnCode;
+use net::buffer::PacketBuffer;
 use net::ieee802154::MacAddress;
 use net::ipv6::ip_utils::IPAddr;
 use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
@@ -39,9 +46,8 @@ pub trait IP6Client {
 }
 
 /// This trait provides a basic IPv6 sending interface. It exposes basic
-/// configuration information for the IPv6 layer (setting the source address,
-/// setting the gateway MAC address), as well as a way to send an IPv6
-/// packet.
+/// configuration information for the IPv6 layer (setting the source address),
+/// as well as a way to send an IPv6 packet.
 pub trait IP6Sender<'a> {
     /// This method sets the `IP6Client` for the `IP6Sender` instance, which
     /// receives the `send_done` callback when transmission has finished.
@@ -59,60 +65,107 @@ pub trait IP6Sender<'a> {
     /// from this instance of `IP6Sender`
     fn set_addr(&self, src_addr: IPAddr);
 
-    /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// This method sends the provided transport header and payload to the
+    /// given destination IP address
     ///
     /// # Arguments
-    /// `gateway` - MAC address to send the constructed packet to
-    fn set_gateway(&self, gateway: MacAddress);
+    /// `dst` - IPv6 address to send the packet to
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
+        -> ReturnCode;
 
-    /// This method sets the `IP6Header` for the `IP6Sender` instance
+    /// This method sends the provided transport header and the transport
+    /// payload in `packet` to the given destination IP address without
+    /// copying the payload. The payload must start at the beginning of the
+    /// packet buffer slot, i.e. be allocated without headroom, as the headers
+    /// are serialized while the packet is compressed. The packet buffer is
+    /// released once the `send_done` callback is issued.
     ///
     /// # Arguments
-    /// `ip6_header` - New `IP6Header` that subsequent packets sent via this
-    /// `IP6Sender` instance will use
-    fn set_header(&mut self, ip6_header: IP6Header);
+    /// `dst` - IPv6 address to send the packet to
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `packet` - Packet buffer containing the transport payload
+    fn send_buffer(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode;
+}
 
-    /// This method sends the provided transport header and payload to the
-    /// given destination IP address
+/// This trait is implemented by each link the IPv6 layer can send packets
+/// over, such as a 6LoWPAN radio, an Ethernet MAC or a WiFi module that
+/// offloads its own stack. Each interface has its own source address, and
+/// issues `send_done` to its client once a packet has been sent.
+pub trait NetworkInterface<'a> {
+    /// This method sets the `IP6Client` for the interface, which receives
+    /// the `send_done` callback when transmission has finished.
     ///
     /// # Arguments
-    /// `dst` - IPv6 address to send the packet to
-    /// `transport_header` - The `TransportHeader` for the packet being sent
-    /// `payload` - The transport payload for the packet being sent
+    /// `client` - Client that implements the `IP6Client` trait to receive the
+    /// `send_done` callback
+    fn set_client(&self, client: &'a IP6Client);
+
+    /// This method returns the source address of packets sent over the
+    /// interface.
+    fn addr(&self) -> IPAddr;
+
+    /// This method sets the source address of packets sent over the
+    /// interface.
+    ///
+    /// # Arguments
+    /// `src_addr` - `IPAddr` to set as the source address
+    fn set_addr(&self, src_addr: IPAddr);
+
+    /// This method sends the provided transport header and payload to the
+    /// given destination IP address over the interface. It behaves as
+    /// `IP6Sender::send_to`.
     fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
         -> ReturnCode;
+
+    /// This method sends the transport payload in `packet` to the given
+    /// destination IP address over the interface. It behaves as
+    /// `IP6Sender::send_buffer`.
+    fn send_buffer(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode;
 }
 
-/// This struct is a specific implementation of the `IP6Sender` trait. This
-/// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
+/// This struct is a specific implementation of the `NetworkInterface` trait.
+/// This struct sends the packet using 6LoWPAN over a generic `MacDevice`
+/// object.
 pub struct IP6SendStruct<'a> {
     // We want the ip6_packet field to be a TakeCell so that it is easy to mutate
     ip6_packet: TakeCell<'static, IP6Packet<'static>>,
     src_addr: Cell<IPAddr>,
     gateway: Cell<MacAddress>,
     tx_buf: TakeCell<'static, [u8]>,
+    /// The frame buffer while it is being fragmented into or sent
+    tx_lease: Lease,
+    // While sending from a packet buffer, its memory replaces the payload
+    // buffer of ip6_packet, which is kept in payload_buf
+    packet: MapCell<PacketBuffer<'a>>,
+    payload_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a MacDevice<'a>,
     client: Cell<Option<&'a IP6Client>>,
 }
 
-impl<'a> IP6Sender<'a> for IP6SendStruct<'a> {
+impl<'a> NetworkInterface<'a> for IP6SendStruct<'a> {
     fn set_client(&self, client: &'a IP6Client) {
         self.client.set(Some(client));
     }
 
-    fn set_addr(&self, src_addr: IPAddr) {
-        self.src_addr.set(src_addr);
+    fn addr(&self) -> IPAddr {
+        self.src_addr.get()
     }
 
-    fn set_gateway(&self, gateway: MacAddress) {
-        self.gateway.set(gateway);
-    }
-
-    fn set_header(&mut self, ip6_header: IP6Header) {
-        self.ip6_packet
-            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
     }
 
     fn send_to(
@@ -121,10 +174,47 @@ impl<'a> IP6Sender<'a> for IP6SendStruct<'a> {
         transport_header: TransportHeader,
         payload: &[u8],
     ) -> ReturnCode {
+        if self.packet.is_some() {
+            return ReturnCode::EBUSY;
+        }
         self.sixlowpan.init(SRC_MAC_ADDR, DST_MAC_ADDR, None);
         self.init_packet(dst, transport_header, payload);
         self.send_next_fragment()
     }
+
+    fn send_buffer(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode {
+        if self.packet.is_some() {
+            return ReturnCode::EBUSY;
+        } else if packet.offset() != 0 {
+            return ReturnCode::EINVAL;
+        } else if self.ip6_packet.is_none() {
+            return ReturnCode::ENOMEM;
+        }
+        let buf = match packet.lend() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+
+        let payload_len = packet.len();
+        self.ip6_packet.map(move |ip6_packet| {
+            self.payload_buf
+                .replace(mem::replace(&mut ip6_packet.payload.payload, buf));
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_transport_header(transport_header, payload_len);
+            ip6_packet.set_transport_checksum();
+        });
+        self.packet.replace(packet);
+
+        self.sixlowpan.init(SRC_MAC_ADDR, DST_MAC_ADDR, None);
+        self.send_next_fragment()
+    }
 }
 
 impl<'a> IP6SendStruct<'a> {
@@ -139,12 +229,24 @@ impl<'a> IP6SendStruct<'a> {
             src_addr: Cell::new(IPAddr::new()),
             gateway: Cell::new(DST_MAC_ADDR),
             tx_buf: TakeCell::new(tx_buf),
+            tx_lease: Lease::new("6lowpan tx"),
+            packet: MapCell::empty(),
+            payload_buf: TakeCell::empty(),
             sixlowpan: sixlowpan,
             radio: radio,
             client: Cell::new(None),
         }
     }
 
+    /// This method sets the gateway/next hop MAC address for packets sent
+    /// over the radio.
+    ///
+    /// # Arguments
+    /// `gateway` - MAC address to send the constructed packet to
+    pub fn set_gateway(&self, gateway: MacAddress) {
+        self.gateway.set(gateway);
+    }
+
     fn init_packet(&self, dst_addr: IPAddr, transport_header: TransportHeader, payload: &[u8]) {
         self.ip6_packet.map(|ip6_packet| {
             ip6_packet.header = IP6Header::default();
@@ -157,33 +259,62 @@ impl<'a> IP6SendStruct<'a> {
 
     // Returns EBUSY if the tx_buf is not there
     fn send_next_fragment(&self) -> ReturnCode {
-        self.ip6_packet
-            .map(move |ip6_packet| match self.tx_buf.take() {
+        // The packet buffer cannot be released while ip6_packet is borrowed,
+        // so completion is signaled afterwards
+        let mut completed = None;
+        let result = self
+            .ip6_packet
+            .map(|ip6_packet| match self.tx_buf.take() {
                 Some(tx_buf) => {
+                    self.tx_lease.lend(tx_buf);
                     let next_frame = self.sixlowpan.next_fragment(ip6_packet, tx_buf, self.radio);
 
                     match next_frame {
                         Ok((is_done, frame)) => {
                             if is_done {
-                                self.tx_buf.replace(frame.into_buf());
-                                self.send_completed(ReturnCode::SUCCESS);
+                                self.return_tx_buf(frame.into_buf());
+                                completed = Some(ReturnCode::SUCCESS);
                             } else {
-                                self.radio.transmit(frame);
+                                let (retcode, buf) = self.radio.transmit(frame);
+                                // The MAC gives the buffer back if it cannot
+                                // send the frame
+                                buf.map(|buf| {
+                                    self.return_tx_buf(buf);
+                                    completed = Some(retcode);
+                                });
                             }
                         }
                         Err((retcode, buf)) => {
-                            self.tx_buf.replace(buf);
-                            self.send_completed(retcode);
+                            self.return_tx_buf(buf);
+                            completed = Some(retcode);
                         }
                     }
                     ReturnCode::SUCCESS
                 }
                 None => ReturnCode::EBUSY,
             })
-            .unwrap_or(ReturnCode::ENOMEM)
+            .unwrap_or(ReturnCode::ENOMEM);
+        if let Some(retcode) = completed {
+            self.send_completed(retcode);
+        }
+        result
+    }
+
+    fn return_tx_buf(&self, tx_buf: &'static mut [u8]) {
+        self.tx_lease.returned(tx_buf);
+        self.tx_buf.replace(tx_buf);
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        // Return the memory of the packet buffer being sent, if any, and
+        // drop our reference to it
+        self.packet.take().map(|packet| {
+            self.ip6_packet.map(|ip6_packet| {
+                self.payload_buf.take().map(|payload_buf| {
+                    packet.restore(mem::replace(&mut ip6_packet.payload.payload, payload_buf));
+                });
+            });
+        });
         self.client
             .get()
             .map(move |client| client.send_done(result));
@@ -192,7 +323,7 @@ impl<'a> IP6SendStruct<'a> {
 
 impl<'a> TxClient for IP6SendStruct<'a> {
     fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.tx_buf.replace(tx_buf);
+        self.return_tx_buf(tx_buf);
         debug!("sendDone return code is: {:?}, acked: {}", result, acked);
         //The below code introduces a delay between frames to prevent
         // a race condition on the receiver
@@ -1,3 +1,4 @@
 pub mod ip_utils;
 pub mod ipv6;
+pub mod ipv6_router;
 pub mod ipv6_send;
@@ -1,7 +1,9 @@
 //! Modules for IPv6 over 6LoWPAN stack
 
+pub mod buffer;
 pub mod frag_utils;
 pub mod sixlowpan;
+pub mod slip;
 pub mod util;
 #[macro_use]
 pub mod stream;
@@ -0,0 +1,334 @@
+//! SLIP (RFC 1055) framing of IPv6 packets over a UART.
+//!
+//! `SlipInterface` is a `NetworkInterface`, so a board can route packets to
+//! a host over a serial cable next to, or instead of, the radio. This is
+//! useful for developing the network stack without radio infrastructure. On
+//! a Linux host the other end of the cable is set up with:
+//!
+//! ```text
+//! slattach -p slip -s 115200 /dev/ttyUSB0 &
+//! ip link set sl0 up
+//! ip -6 addr add fd00::1/64 dev sl0
+//! ```
+//!
+//! Each packet is sent as one frame, ending with an `END` byte, with `END`
+//! and `ESC` bytes in the packet escaped. Frames received from the host are
+//! unescaped and passed whole to the `SlipRxClient`. The interface needs a
+//! UART of its own, as SLIP frames cannot be interleaved with console output.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let slip = static_init!(
+//!     capsules::net::slip::SlipInterface<'static, usart::USART>,
+//!     capsules::net::slip::SlipInterface::new(
+//!         &usart::USART3,
+//!         slip_ip6_packet,
+//!         &mut capsules::net::slip::PACKET_BUF,
+//!         &mut capsules::net::slip::TX_BUF,
+//!         &mut capsules::net::slip::RX_BUF,
+//!         &mut capsules::net::slip::RX_PACKET_BUF
+//!     )
+//! );
+//! hil::uart::UART::set_client(&usart::USART3, slip);
+//! slip.initialize();
+//! let slip_interface = ip6_router.add_interface(slip).unwrap();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::uart::{self, UARTReceiveAdvanced};
+use kernel::ReturnCode;
+use net::buffer::PacketBuffer;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::ipv6::ipv6_send::{IP6Client, NetworkInterface};
+
+/// The largest packet sent or received, the IPv6 minimum MTU.
+pub const MTU: usize = 1280;
+
+pub static mut PACKET_BUF: [u8; MTU] = [0; MTU];
+pub static mut TX_BUF: [u8; 64] = [0; 64];
+pub static mut RX_BUF: [u8; 64] = [0; 64];
+pub static mut RX_PACKET_BUF: [u8; MTU] = [0; MTU];
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+/// Receive returns once the line has been idle for this many bit periods.
+const INTERBYTE_TIMEOUT: u8 = 100;
+
+/// Objects that implement this trait receive the IPv6 packets that arrive
+/// over the serial line.
+pub trait SlipRxClient {
+    fn receive(&self, packet: &[u8]);
+}
+
+pub struct SlipInterface<'a, U: UARTReceiveAdvanced + 'a> {
+    uart: &'a U,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    src_addr: Cell<IPAddr>,
+    // The serialized packet being sent, of which tx_offset bytes have been
+    // framed so far
+    packet_buf: TakeCell<'static, [u8]>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_offset: Cell<usize>,
+    tx_len: Cell<usize>,
+    sending: Cell<bool>,
+    rx_buf: TakeCell<'static, [u8]>,
+    // The frame being received, of which rx_len bytes have arrived
+    rx_packet: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_escaped: Cell<bool>,
+    // Set when a frame did not fit in rx_packet, until its END arrives
+    rx_overflow: Cell<bool>,
+    client: Cell<Option<&'a IP6Client>>,
+    rx_client: Cell<Option<&'a SlipRxClient>>,
+}
+
+impl<'a, U: UARTReceiveAdvanced> SlipInterface<'a, U> {
+    pub fn new(
+        uart: &'a U,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        packet_buf: &'static mut [u8],
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        rx_packet: &'static mut [u8],
+    ) -> SlipInterface<'a, U> {
+        SlipInterface {
+            uart: uart,
+            ip6_packet: TakeCell::new(ip6_packet),
+            src_addr: Cell::new(IPAddr::new()),
+            packet_buf: TakeCell::new(packet_buf),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_offset: Cell::new(0),
+            tx_len: Cell::new(0),
+            sending: Cell::new(false),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_packet: TakeCell::new(rx_packet),
+            rx_len: Cell::new(0),
+            rx_escaped: Cell::new(false),
+            rx_overflow: Cell::new(false),
+            client: Cell::new(None),
+            rx_client: Cell::new(None),
+        }
+    }
+
+    /// Configure the UART and start receiving frames.
+    pub fn initialize(&self) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: 115200,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        self.rx_buf
+            .take()
+            .map(|buf| self.uart.receive_automatic(buf, INTERBYTE_TIMEOUT));
+    }
+
+    pub fn set_rx_client(&self, client: &'a SlipRxClient) {
+        self.rx_client.set(Some(client));
+    }
+
+    // Serializes the packet into packet_buf and starts sending it
+    fn send_packet(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        if self.sending.get() {
+            return ReturnCode::EBUSY;
+        }
+        let src_addr = self.src_addr.get();
+        let encoded = self
+            .ip6_packet
+            .map_or(Err(ReturnCode::ENOMEM), |ip6_packet| {
+                if payload.len() > ip6_packet.payload.payload.len() {
+                    return Err(ReturnCode::ESIZE);
+                }
+                ip6_packet.header = IP6Header::default();
+                ip6_packet.header.src_addr = src_addr;
+                ip6_packet.header.dst_addr = dst;
+                ip6_packet.set_payload(transport_header, payload);
+                ip6_packet.set_transport_checksum();
+                self.packet_buf
+                    .map_or(Err(ReturnCode::ENOMEM), |packet_buf| {
+                        if ip6_packet.get_total_len() as usize > packet_buf.len() {
+                            return Err(ReturnCode::ESIZE);
+                        }
+                        ip6_packet
+                            .encode(packet_buf)
+                            .done()
+                            .map_or(Err(ReturnCode::FAIL), |(len, _)| Ok(len))
+                    })
+            });
+        match encoded {
+            Ok(len) => {
+                self.tx_offset.set(0);
+                self.tx_len.set(len);
+                self.sending.set(true);
+                self.send_next_chunk();
+                ReturnCode::SUCCESS
+            }
+            Err(err) => err,
+        }
+    }
+
+    // Frames as much of the rest of the packet as fits in tx_buf and
+    // transmits it. The first chunk starts with an END, which flushes any
+    // line noise the host received, and the last chunk ends with one.
+    fn send_next_chunk(&self) {
+        self.tx_buf.take().map(|tx_buf| {
+            let mut len = 0;
+            if self.tx_offset.get() == 0 {
+                tx_buf[0] = END;
+                len = 1;
+            }
+            self.packet_buf.map(|packet_buf| {
+                let mut offset = self.tx_offset.get();
+                // Leave room for an escaped byte
+                while offset < self.tx_len.get() && len + 2 <= tx_buf.len() {
+                    match packet_buf[offset] {
+                        END => {
+                            tx_buf[len] = ESC;
+                            tx_buf[len + 1] = ESC_END;
+                            len += 2;
+                        }
+                        ESC => {
+                            tx_buf[len] = ESC;
+                            tx_buf[len + 1] = ESC_ESC;
+                            len += 2;
+                        }
+                        byte => {
+                            tx_buf[len] = byte;
+                            len += 1;
+                        }
+                    }
+                    offset += 1;
+                }
+                self.tx_offset.set(offset);
+            });
+            if self.tx_offset.get() == self.tx_len.get() && len < tx_buf.len() {
+                tx_buf[len] = END;
+                len += 1;
+                // Nothing is left to send once this chunk is out
+                self.tx_len.set(0);
+            }
+            self.uart.transmit(tx_buf, len);
+        });
+    }
+
+    fn receive_byte(&self, byte: u8) {
+        if byte == END {
+            let len = self.rx_len.get();
+            if len > 0 && !self.rx_overflow.get() {
+                self.rx_packet.map(|rx_packet| {
+                    self.rx_client
+                        .get()
+                        .map(|client| client.receive(&rx_packet[..len]));
+                });
+            }
+            self.rx_len.set(0);
+            self.rx_escaped.set(false);
+            self.rx_overflow.set(false);
+            return;
+        }
+        if byte == ESC {
+            self.rx_escaped.set(true);
+            return;
+        }
+        let byte = if self.rx_escaped.get() {
+            self.rx_escaped.set(false);
+            match byte {
+                ESC_END => END,
+                ESC_ESC => ESC,
+                // A protocol violation; RFC 1055 keeps the byte as it is
+                other => other,
+            }
+        } else {
+            byte
+        };
+        let len = self.rx_len.get();
+        self.rx_packet.map(|rx_packet| {
+            if len < rx_packet.len() {
+                rx_packet[len] = byte;
+                self.rx_len.set(len + 1);
+            } else {
+                self.rx_overflow.set(true);
+            }
+        });
+    }
+}
+
+impl<'a, U: UARTReceiveAdvanced> NetworkInterface<'a> for SlipInterface<'a, U> {
+    fn set_client(&self, client: &'a IP6Client) {
+        self.client.set(Some(client));
+    }
+
+    fn addr(&self) -> IPAddr {
+        self.src_addr.get()
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.send_packet(dst, transport_header, payload)
+    }
+
+    /// The payload is copied out of `packet`, which is released before this
+    /// returns.
+    fn send_buffer(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode {
+        packet
+            .map(|payload| self.send_packet(dst, transport_header, payload))
+            .unwrap_or(ReturnCode::EBUSY)
+    }
+}
+
+impl<'a, U: UARTReceiveAdvanced> uart::Client for SlipInterface<'a, U> {
+    fn transmit_complete(&self, tx_buf: &'static mut [u8], error: uart::Error) {
+        self.tx_buf.replace(tx_buf);
+        if error != uart::Error::CommandComplete {
+            self.tx_len.set(0);
+        }
+        if self.tx_len.get() != 0 {
+            self.send_next_chunk();
+        } else {
+            self.sending.set(false);
+            let result = if error == uart::Error::CommandComplete {
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            };
+            self.client.get().map(|client| client.send_done(result));
+        }
+    }
+
+    fn receive_complete(&self, rx_buf: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if error == uart::Error::CommandComplete {
+            for &byte in rx_buf[..rx_len].iter() {
+                self.receive_byte(byte);
+            }
+        } else {
+            // Bytes were lost, so drop the rest of the frame
+            self.rx_overflow.set(true);
+        }
+        self.uart.receive_automatic(rx_buf, INTERBYTE_TIMEOUT);
+    }
+}
@@ -1,7 +1,12 @@
 /* Though TCP has not yet been implemented for the Tock Networking stackm
    this file defines the structure of the TCPHeader and TCPPacket structs
    so that TCPPacket can be included for clarity as part of the
-   TransportPacket enum */
+   TransportPacket enum
+
+   Protocols layered over TCP, such as a TLS-PSK session layer for talking
+   to MQTT brokers, need a TCP capsule with a socket interface to build on,
+   and TLS 1.2 also needs a SHA-256 HIL for its PRF and HMAC, which the
+   kernel does not have yet either. They wait on both. */
 
 pub struct TCPHeader {
     pub src_port: u16,
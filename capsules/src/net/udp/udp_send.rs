@@ -4,9 +4,17 @@
 //! [UDPSendClient](trait.UDPSendClient.html) trait is implemented by the
 //! upper layer to allow them to receive the `send_done` callback once
 //! transmission has completed.
+//!
+//! There is no receive path yet: the IPv6 layer does not hand received
+//! datagrams up to UDP, and there is no UDP driver for applications. Clients
+//! that need replies wait on that: an SNTP client to set the wall-clock time,
+//! which also needs the chips to gain a calendar RTC HIL for it to set, and a
+//! DNS and mDNS resolver, which also has to answer queries for the device's
+//! own name.
 
 use core::cell::Cell;
 use kernel::ReturnCode;
+use net::buffer::PacketBuffer;
 use net::ipv6::ip_utils::IPAddr;
 use net::ipv6::ipv6::TransportHeader;
 use net::ipv6::ipv6_send::{IP6Client, IP6Sender};
@@ -59,6 +67,37 @@ pub trait UDPSender<'a> {
     /// Returns any synchronous errors or success. Note that any asynchrounous
     /// errors are returned via the callback.
     fn send(&self, dest: IPAddr, udp_header: UDPHeader, buf: &'a [u8]) -> ReturnCode;
+
+    /// This function is the same as `send_to`, except that the payload is
+    /// sent from a packet buffer without being copied. The payload must be
+    /// at the start of the packet buffer slot (allocated with no headroom).
+    ///
+    /// # Arguments
+    /// `dest` - IPv6 address to send the UDP packet to
+    /// `dst_port` - Destination port to send the packet to
+    /// `src_port` - Port to send the packet from
+    /// `packet` - Packet buffer containing the UDP payload
+    fn send_buffer_to(
+        &self,
+        dest: IPAddr,
+        dst_port: u16,
+        src_port: u16,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode;
+
+    /// This function is the same as `send`, except that the payload is
+    /// sent from a packet buffer without being copied.
+    ///
+    /// # Arguments
+    /// `dest` - IP address to send the UDP packet to
+    /// `udp_header` - Completed UDP header to be sent to the destination
+    /// `packet` - Packet buffer containing the UDP payload
+    fn send_buffer(
+        &self,
+        dest: IPAddr,
+        udp_header: UDPHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode;
 }
 
 /// This is a specific instantiation of the `UDPSender` trait. Note
@@ -89,6 +128,32 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         let transport_header = TransportHeader::UDP(udp_header);
         self.ip_send_struct.send_to(dest, transport_header, buf)
     }
+
+    fn send_buffer_to(
+        &self,
+        dest: IPAddr,
+        dst_port: u16,
+        src_port: u16,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode {
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_dst_port(dst_port);
+        udp_header.set_src_port(src_port);
+        self.send_buffer(dest, udp_header, packet)
+    }
+
+    fn send_buffer(
+        &self,
+        dest: IPAddr,
+        mut udp_header: UDPHeader,
+        packet: PacketBuffer<'a>,
+    ) -> ReturnCode {
+        let total_length = packet.len() + udp_header.get_hdr_size();
+        udp_header.set_len(total_length as u16);
+        let transport_header = TransportHeader::UDP(udp_header);
+        self.ip_send_struct
+            .send_buffer(dest, transport_header, packet)
+    }
 }
 
 impl<'a, T: IP6Sender<'a>> UDPSendStruct<'a, T> {
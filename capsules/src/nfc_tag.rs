@@ -0,0 +1,255 @@
+//! Provides userspace with an NFC tag that phones and other readers can read.
+//!
+//! An application sets the tag to hold a single NDEF record, either a URI
+//! or a text, and readers that come close read it. This is useful for
+//! tap-to-provision, where tapping a phone to the board opens the page that
+//! sets the board up, or hands the phone the board's identity. The tag holds
+//! one record at a time: setting it replaces the record any application set
+//! before.
+//!
+//! Applications that subscribe are told when a reader's field appears and
+//! goes, for example to show that the board is being provisioned.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::nfc::NdefTag` trait.
+//!
+//! ```rust
+//! let nfc_tag = static_init!(
+//!     capsules::nfc_tag::NfcTag<'static, nrf52::nfct::Nfct>,
+//!     capsules::nfc_tag::NfcTag::new(
+//!         &nrf52::nfct::NFCT,
+//!         &mut capsules::nfc_tag::BUFFER,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! kernel::hil::nfc::NdefTag::set_client(&nrf52::nfct::NFCT, nfc_tag);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Allow
+//!
+//! - `0`: The record's content. For a URI record it is the URI, without the
+//!   prefix its identifier code stands for. For a text record it is the IANA
+//!   language code, such as `en`, followed by the text in UTF-8.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Reader field events. The callback signature is
+//!   `fn(present: usize)`, where `present` is `1` when a reader's field
+//!   appears and `0` when it goes.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Present a URI record. `data1` is the URI identifier code from the
+//!   NFC Forum URI record type definition, such as `4` for `https://` or `0`
+//!   for none, and `data2` is the length of the rest of the URI.
+//! - `2`: Present a text record. `data1` is the length of the language code
+//!   and `data2` the length of the text that follows it.
+//! - `3`: Stop presenting the record.
+//!
+//! Setting a record returns `ENOMEM` if no buffer is allowed, `EINVAL` if it
+//! is shorter than the lengths given and `ESIZE` if the record does not fit
+//! in the tag.
+
+use core::cmp;
+use core::mem;
+use kernel::common::cells::TakeCell;
+use kernel::hil::nfc;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30002;
+
+/// Holds the NDEF message while the tag presents it.
+pub static mut BUFFER: [u8; 256] = [0; 256];
+
+/// NDEF record header flags: the first (MB) and last (ME) record of the
+/// message, and a short record (SR) with a 1-byte payload length.
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_SR: u8 = 0x10;
+
+/// Type name format of the NFC Forum well-known types.
+const TNF_WELL_KNOWN: u8 = 0x01;
+
+/// Well-known record types.
+const TYPE_URI: u8 = b'U';
+const TYPE_TEXT: u8 = b'T';
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct NfcTag<'a, T: nfc::NdefTag + 'a> {
+    tag: &'a T,
+    /// The message buffer, while the tag is not presenting it.
+    buffer: TakeCell<'static, [u8]>,
+    capacity: usize,
+    apps: Grant<App>,
+}
+
+impl<'a, T: nfc::NdefTag> NfcTag<'a, T> {
+    pub fn new(tag: &'a T, buffer: &'static mut [u8], grant: Grant<App>) -> NfcTag<'a, T> {
+        NfcTag {
+            tag: tag,
+            capacity: buffer.len(),
+            buffer: TakeCell::new(buffer),
+            apps: grant,
+        }
+    }
+
+    /// Present a message made of a single record of `record_type`, whose
+    /// payload is `first` followed by the first `len` bytes the process
+    /// allowed.
+    fn present(&self, appid: AppId, record_type: u8, first: u8, len: usize) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                let content = match app.buffer {
+                    Some(ref slice) => slice,
+                    None => return ReturnCode::ENOMEM,
+                };
+                if len > content.len() {
+                    return ReturnCode::EINVAL;
+                }
+
+                let payload_len = len + 1;
+                let short = payload_len <= 0xff;
+                let header_len = if short { 4 } else { 7 };
+                let message_len = header_len + payload_len;
+
+                if message_len > cmp::min(self.capacity, self.tag.max_message_len()) {
+                    return ReturnCode::ESIZE;
+                }
+
+                // The buffer is with the tag if it is presenting a record.
+                let buffer = match self.buffer.take().or_else(|| self.tag.disable()) {
+                    Some(buffer) => buffer,
+                    None => return ReturnCode::FAIL,
+                };
+
+                buffer[1] = 1;
+                if short {
+                    buffer[0] = FLAG_MB | FLAG_ME | FLAG_SR | TNF_WELL_KNOWN;
+                    buffer[2] = payload_len as u8;
+                } else {
+                    buffer[0] = FLAG_MB | FLAG_ME | TNF_WELL_KNOWN;
+                    for i in 0..4 {
+                        buffer[2 + i] = (payload_len >> (24 - i * 8)) as u8;
+                    }
+                }
+                buffer[header_len - 1] = record_type;
+                buffer[header_len] = first;
+                buffer[header_len + 1..message_len].copy_from_slice(&content.as_ref()[..len]);
+
+                let (result, buffer) = self.tag.enable(buffer, message_len);
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.tag.disable().map(|buffer| self.buffer.replace(buffer));
+        ReturnCode::SUCCESS
+    }
+
+    fn notify(&self, present: bool) {
+        self.apps.each(|app| {
+            app.callback
+                .map(|mut callback| callback.schedule(present as usize, 0, 0));
+        });
+    }
+}
+
+impl<'a, T: nfc::NdefTag> Driver for NfcTag<'a, T> {
+    /// Share the content of the record.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The URI, or the language code and text, of the record
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to reader field events
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A reader's field appeared or went
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Set the record the tag presents
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check
+    /// - `1`: Present a URI record with identifier code `data1` and a URI
+    ///   of `data2` bytes
+    /// - `2`: Present a text record with a language code of `data1` bytes
+    ///   and a text of `data2` bytes
+    /// - `3`: Stop presenting the record
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.present(appid, TYPE_URI, data1 as u8, data2),
+            2 => {
+                // The status byte holds the length of the language code, and
+                // a clear top bit for UTF-8.
+                if data1 > 0x3f {
+                    return ReturnCode::EINVAL;
+                }
+                self.present(appid, TYPE_TEXT, data1 as u8, data1.saturating_add(data2))
+            }
+            3 => self.stop(),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, T: nfc::NdefTag> nfc::Client for NfcTag<'a, T> {
+    fn field_detected(&self) {
+        self.notify(true);
+    }
+
+    fn field_lost(&self) {
+        self.notify(false);
+    }
+}
@@ -12,6 +12,7 @@
 //! hil::sensors::NineDof::set_client(fxos8700, ninedof);
 //! ```
 
+use core::mem;
 use core::cell::Cell;
 use kernel::hil;
 use kernel::ReturnCode;
@@ -148,16 +149,13 @@ impl<'a> Driver for NineDof<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 => self
                 .apps
-                .enter(app_id, |app, _| {
-                    app.callback = callback;
-                    ReturnCode::SUCCESS
-                })
-                .unwrap_or_else(|err| err.into()),
-            _ => ReturnCode::ENOSUPPORT,
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
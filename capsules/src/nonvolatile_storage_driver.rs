@@ -1,9 +1,11 @@
 //! This provides kernel and userspace access to nonvolatile memory.
 //!
-//! This is an initial implementation that does not provide safety for
-//! individual userland applications. Each application has full access to
-//! the entire memory space that has been provided to userland. Future revisions
-//! should update this to limit applications to only their allocated regions.
+//! An application that declares a storage region in its TBF header is
+//! limited to that region: its offsets are translated so the region starts at
+//! `0`, and it cannot read or write outside of it. Applications without a
+//! declared region have access to the entire memory space that has been
+//! provided to userland, unless the board calls `enable_app_isolation()`, in
+//! which case they have no access at all.
 //!
 //! However, the kernel accessible memory does not have to be the same range
 //! as the userspace accessible address space. The kernel memory can overlap
@@ -51,9 +53,34 @@
 //!         &mut capsules::nonvolatile_storage_driver::BUFFER));
 //! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, nonvolatile_storage);
 //! ```
+//!
+//! Power-fail-safe writes
+//! ----------------------
+//!
+//! By default a userspace write goes straight to its destination, so losing
+//! power part way through can leave a record that is half old and half new.
+//! Boards can optionally enable two-phase commit for userspace writes:
+//!
+//! ```rust
+//! nonvolatile_storage.enable_transactions(
+//!     0x7fc00,                     // Address of the journal record.
+//!     0x7fe00,                     // Address of the shadow area, at least as
+//!                                  // long as `BUFFER`.
+//!     &mut capsules::nonvolatile_storage_driver::JOURNAL_BUFFER);
+//! ```
+//!
+//! Each write is first stored in the shadow area, then a sealed journal
+//! record naming the destination is written, then the data is copied to its
+//! destination and finally the journal record is cleared. If power is lost
+//! before the journal is sealed the destination was never touched; if it is
+//! lost afterwards, `enable_transactions()` finds the sealed record on the
+//! next boot and replays the copy from the shadow area before any other
+//! request is served. The journal and shadow area must not overlap the
+//! userspace or kernel regions, and should be on separate flash pages.
 
 use core::cell::Cell;
 use core::cmp;
+use core::mem;
 use kernel::common::cells::TakeCell;
 use kernel::hil;
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
@@ -63,6 +90,17 @@ pub const DRIVER_NUM: usize = 0x50001;
 
 pub static mut BUFFER: [u8; 512] = [0; 512];
 
+/// Buffer for journal records, used when transactions are enabled.
+pub static mut JOURNAL_BUFFER: [u8; JOURNAL_LENGTH] = [0; JOURNAL_LENGTH];
+
+/// Size of a journal record: destination address, length, data checksum
+/// and seal, each a little endian `u32`.
+pub const JOURNAL_LENGTH: usize = 16;
+
+/// Mixed into the seal so that erased or zeroed storage is never a valid
+/// journal record.
+const JOURNAL_MAGIC: u32 = 0x4e565458;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum NonvolatileCommand {
     UserspaceRead,
@@ -71,10 +109,40 @@ pub enum NonvolatileCommand {
     KernelWrite,
 }
 
+/// Progress of a two-phase commit or of the recovery of one.
+#[derive(Clone, Copy, PartialEq)]
+enum TransactionState {
+    Idle,
+    WritingShadow {
+        address: usize,
+        length: usize,
+    },
+    WritingJournal {
+        address: usize,
+        length: usize,
+    },
+    WritingTarget {
+        length: usize,
+    },
+    ClearingJournal {
+        length: usize,
+    },
+    RecoveryReadingJournal,
+    RecoveryReadingShadow {
+        address: usize,
+        length: usize,
+        checksum: u32,
+    },
+    RecoveryWritingTarget,
+    RecoveryClearingJournal,
+}
+
 #[derive(Clone, Copy)]
 pub enum NonvolatileUser {
     App { app_id: AppId },
     Kernel,
+    /// An app that terminated while its request was running.
+    Terminated,
 }
 
 pub struct App {
@@ -118,6 +186,8 @@ pub struct NonvolatileStorage<'a> {
     userspace_start_address: usize,
     // How many bytes allocated to userspace.
     userspace_length: usize,
+    // Whether apps without a storage region in their header are denied access.
+    isolate_apps: Cell<bool>,
     // The first byte that is accessible from the kernel.
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
@@ -136,6 +206,15 @@ pub struct NonvolatileStorage<'a> {
     kernel_readwrite_length: Cell<usize>,
     // Where to read/write from the kernel request.
     kernel_readwrite_address: Cell<usize>,
+
+    // Physical address of the journal record, if transactions are enabled.
+    journal_address: Cell<Option<usize>>,
+    // Physical address of the shadow copy of the data being written.
+    shadow_address: Cell<usize>,
+    // Buffer for reading and writing the journal record.
+    journal_buffer: TakeCell<'static, [u8]>,
+    // Where the current transaction is.
+    transaction: Cell<TransactionState>,
 }
 
 impl<'a> NonvolatileStorage<'a> {
@@ -155,6 +234,7 @@ impl<'a> NonvolatileStorage<'a> {
             current_user: Cell::new(None),
             userspace_start_address: userspace_start_address,
             userspace_length: userspace_length,
+            isolate_apps: Cell::new(false),
             kernel_start_address: kernel_start_address,
             kernel_length: kernel_length,
             kernel_client: Cell::new(None),
@@ -163,9 +243,77 @@ impl<'a> NonvolatileStorage<'a> {
             kernel_buffer: TakeCell::empty(),
             kernel_readwrite_length: Cell::new(0),
             kernel_readwrite_address: Cell::new(0),
+            journal_address: Cell::new(None),
+            shadow_address: Cell::new(0),
+            journal_buffer: TakeCell::empty(),
+            transaction: Cell::new(TransactionState::Idle),
         }
     }
 
+    /// Deny storage access to apps that do not declare a storage region in
+    /// their TBF header, so that no app can touch another app's data.
+    pub fn enable_app_isolation(&self) {
+        self.isolate_apps.set(true);
+    }
+
+    /// Returns the physical start address and length of the part of the
+    /// userspace region that `appid` may access. A declared region that does
+    /// not lie within the userspace region gives no access at all.
+    fn app_region(&self, appid: AppId) -> Option<(usize, usize)> {
+        match appid.get_storage_region() {
+            Some((offset, size)) => offset
+                .checked_add(size)
+                .filter(|&end| end <= self.userspace_length)
+                .and_then(|_| self.userspace_start_address.checked_add(offset))
+                .map(|start| (start, size)),
+            None => {
+                if self.isolate_apps.get() {
+                    None
+                } else {
+                    Some((self.userspace_start_address, self.userspace_length))
+                }
+            }
+        }
+    }
+
+    /// Make every userspace write a two-phase commit through a journal record
+    /// at `journal_address` and a shadow area at `shadow_address`, which must
+    /// be at least as long as the internal buffer.
+    ///
+    /// This also recovers a write that was interrupted by a reset, so it must
+    /// be called at boot before any app can use the storage. Requests made
+    /// while recovery is running are queued until it completes.
+    pub fn enable_transactions(
+        &self,
+        journal_address: usize,
+        shadow_address: usize,
+        journal_buffer: &'static mut [u8],
+    ) -> ReturnCode {
+        if journal_buffer.len() < JOURNAL_LENGTH {
+            return ReturnCode::ESIZE;
+        }
+        if self.current_user.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.journal_address.set(Some(journal_address));
+        self.shadow_address.set(shadow_address);
+
+        // Hold the storage for the duration of recovery so that nothing else
+        // can observe the unrecovered destination.
+        self.current_user.set(Some(NonvolatileUser::Kernel));
+        self.transaction
+            .set(TransactionState::RecoveryReadingJournal);
+        let (rc, journal_buffer) = self
+            .driver
+            .read(journal_buffer, journal_address, JOURNAL_LENGTH);
+        if rc != ReturnCode::SUCCESS {
+            journal_buffer.map(|buffer| self.journal_buffer.replace(buffer));
+            self.transaction.set(TransactionState::Idle);
+            self.current_user.set(None);
+        }
+        rc
+    }
+
     // Check so see if we are doing something. If not, go ahead and do this
     // command. If so, this is queued and will be run when the pending
     // command completes.
@@ -181,9 +329,13 @@ impl<'a> NonvolatileStorage<'a> {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
                 // Userspace sees memory that starts at address 0 even if it
                 // is offset in the physical memory.
-                if offset >= self.userspace_length
-                    || length > self.userspace_length
-                    || offset + length > self.userspace_length
+                let region_length = match app_id.and_then(|appid| self.app_region(appid)) {
+                    Some((_, region_length)) => region_length,
+                    None => return ReturnCode::ERESERVE,
+                };
+                if offset >= region_length
+                    || length > region_length
+                    || offset + length > region_length
                 {
                     return ReturnCode::EINVAL;
                 }
@@ -206,6 +358,11 @@ impl<'a> NonvolatileStorage<'a> {
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
                 app_id.map_or(ReturnCode::FAIL, |appid| {
+                    // Calculate where we want to actually read from in the
+                    // physical storage.
+                    let physical_address =
+                        self.app_region(appid).map_or(0, |(start, _)| start) + offset;
+
                     self.apps
                         .enter(appid, |app, _| {
                             // Get the length of the correct allowed buffer.
@@ -255,7 +412,7 @@ impl<'a> NonvolatileStorage<'a> {
                                     });
                                 }
 
-                                self.userspace_call_driver(command, offset, active_len)
+                                self.userspace_call_driver(command, physical_address, active_len)
                             } else {
                                 // Some app is using the storage, we must wait.
                                 if app.pending_command == true {
@@ -266,7 +423,7 @@ impl<'a> NonvolatileStorage<'a> {
                                     // We can store this, so lets do it.
                                     app.pending_command = true;
                                     app.command = command;
-                                    app.offset = offset;
+                                    app.offset = physical_address;
                                     app.length = active_len;
                                     ReturnCode::SUCCESS
                                 }
@@ -286,17 +443,23 @@ impl<'a> NonvolatileStorage<'a> {
                             // Nothing is using this, lets go!
                             self.current_user.set(Some(NonvolatileUser::Kernel));
 
-                            match command {
+                            let (rc, kernel_buffer) = match command {
                                 NonvolatileCommand::KernelRead => {
                                     self.driver.read(kernel_buffer, offset, active_len)
                                 }
                                 NonvolatileCommand::KernelWrite => {
                                     self.driver.write(kernel_buffer, offset, active_len)
                                 }
-                                _ => ReturnCode::FAIL,
+                                _ => (ReturnCode::FAIL, Some(kernel_buffer)),
+                            };
+                            if rc != ReturnCode::SUCCESS {
+                                kernel_buffer.map(|buffer| self.kernel_buffer.replace(buffer));
+                                self.current_user.set(None);
                             }
+                            rc
                         } else {
                             if self.kernel_pending_command.get() == true {
+                                self.kernel_buffer.replace(kernel_buffer);
                                 ReturnCode::ENOMEM
                             } else {
                                 self.kernel_pending_command.set(true);
@@ -315,39 +478,253 @@ impl<'a> NonvolatileStorage<'a> {
     fn userspace_call_driver(
         &self,
         command: NonvolatileCommand,
-        offset: usize,
+        physical_address: usize,
         length: usize,
     ) -> ReturnCode {
-        // Calculate where we want to actually read from in the physical
-        // storage.
-        let physical_address = offset + self.userspace_start_address;
-
-        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+        let rc = self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
             // Check that the internal buffer and the buffer that was
             // allowed are long enough.
             let active_len = cmp::min(length, buffer.len());
 
-            // self.current_app.set(Some(appid));
-            match command {
+            let (rc, buffer) = match command {
                 NonvolatileCommand::UserspaceRead => {
                     self.driver.read(buffer, physical_address, active_len)
                 }
-                NonvolatileCommand::UserspaceWrite => {
-                    self.driver.write(buffer, physical_address, active_len)
+                NonvolatileCommand::UserspaceWrite => match self.journal_address.get() {
+                    Some(_) => self.transaction_start(buffer, physical_address, active_len),
+                    None => self.driver.write(buffer, physical_address, active_len),
+                },
+                _ => (ReturnCode::FAIL, Some(buffer)),
+            };
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            rc
+        });
+
+        // If nothing was started, release the storage for the next user.
+        if rc != ReturnCode::SUCCESS {
+            self.transaction.set(TransactionState::Idle);
+            self.current_user.set(None);
+        }
+        rc
+    }
+
+    /// Begin a two-phase commit by writing the data to the shadow area.
+    fn transaction_start(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let checksum = checksum(&buffer[0..length]);
+        let journal_ready = self.journal_buffer.map_or(false, |journal| {
+            encode_journal(journal, address, length, checksum);
+            true
+        });
+        if !journal_ready {
+            return (ReturnCode::ERESERVE, Some(buffer));
+        }
+
+        self.transaction.set(TransactionState::WritingShadow {
+            address: address,
+            length: length,
+        });
+        self.driver.write(buffer, self.shadow_address.get(), length)
+    }
+
+    /// Write the journal record from the journal buffer, or clear it.
+    fn write_journal(&self, clear: bool) {
+        match (self.journal_address.get(), self.journal_buffer.take()) {
+            (Some(journal_address), Some(journal)) => {
+                if clear {
+                    for b in journal[0..JOURNAL_LENGTH].iter_mut() {
+                        *b = 0;
+                    }
                 }
-                _ => ReturnCode::FAIL,
+                let result = self.driver.write(journal, journal_address, JOURNAL_LENGTH);
+                self.transaction_issued(result, &self.journal_buffer);
+            }
+            (_, journal) => {
+                journal.map(|journal| self.journal_buffer.replace(journal));
+                self.transaction_failed(ReturnCode::ERESERVE);
             }
-        })
+        }
+    }
+
+    /// Read or write the data of the transaction from the internal buffer.
+    fn transfer_data(&self, command: NonvolatileCommand, address: usize, length: usize) {
+        match self.buffer.take() {
+            Some(data) => {
+                let result = match command {
+                    NonvolatileCommand::UserspaceRead => self.driver.read(data, address, length),
+                    _ => self.driver.write(data, address, length),
+                };
+                self.transaction_issued(result, &self.buffer);
+            }
+            None => self.transaction_failed(ReturnCode::ERESERVE),
+        }
+    }
+
+    /// Check that the storage started the next operation of the transaction.
+    /// If it did not, `buffer` goes back where it came from and the
+    /// transaction is abandoned.
+    fn transaction_issued(
+        &self,
+        result: (ReturnCode, Option<&'static mut [u8]>),
+        buffer: &TakeCell<'static, [u8]>,
+    ) {
+        let (rc, returned) = result;
+        if rc != ReturnCode::SUCCESS {
+            returned.map(|returned| buffer.replace(returned));
+            self.transaction_failed(rc);
+        }
+    }
+
+    /// Abandon the current transaction or recovery, report `rc` to the app
+    /// whose write it was and serve the next request.
+    ///
+    /// A write abandoned after its journal record was sealed is replayed on
+    /// the next boot, as if power had been lost. Recovery is run on behalf
+    /// of the kernel, so a failed recovery is not reported to anyone and is
+    /// retried on the next boot.
+    fn transaction_failed(&self, rc: ReturnCode) {
+        self.transaction.set(TransactionState::Idle);
+        let user = self.current_user.get();
+        self.current_user.set(None);
+        if let Some(NonvolatileUser::App { app_id }) = user {
+            let _ = self.apps.enter(app_id, |app, _| {
+                app.callback_write
+                    .map(|mut cb| cb.schedule(usize::from(rc), 0, 0));
+            });
+        }
+        self.check_queue();
+    }
+
+    /// Advance the transaction state machine after a storage operation that
+    /// it issued completed.
+    fn transaction_done(&self, buffer: &'static mut [u8]) {
+        match self.transaction.get() {
+            TransactionState::WritingShadow { address, length } => {
+                self.buffer.replace(buffer);
+                if let Some(NonvolatileUser::Terminated) = self.current_user.get() {
+                    // Nothing was committed, so the destination is untouched
+                    // and the write can be dropped with the app.
+                    self.transaction.set(TransactionState::Idle);
+                    self.current_user.set(None);
+                    self.check_queue();
+                    return;
+                }
+
+                // The shadow copy is complete, seal the journal record.
+                self.transaction.set(TransactionState::WritingJournal {
+                    address: address,
+                    length: length,
+                });
+                self.write_journal(false);
+            }
+            TransactionState::WritingJournal { address, length } => {
+                // The write is now committed, update the destination.
+                self.journal_buffer.replace(buffer);
+                self.transaction
+                    .set(TransactionState::WritingTarget { length: length });
+                self.transfer_data(NonvolatileCommand::UserspaceWrite, address, length);
+            }
+            TransactionState::WritingTarget { length } => {
+                self.buffer.replace(buffer);
+                self.transaction
+                    .set(TransactionState::ClearingJournal { length: length });
+                self.write_journal(true);
+            }
+            TransactionState::ClearingJournal { length } => {
+                self.journal_buffer.replace(buffer);
+                self.transaction.set(TransactionState::Idle);
+                self.current_user.get().map(|user| {
+                    self.current_user.set(None);
+                    if let NonvolatileUser::App { app_id } = user {
+                        let _ = self.apps.enter(app_id, |app, _| {
+                            app.callback_write.map(|mut cb| cb.schedule(length, 0, 0));
+                        });
+                    }
+                });
+                self.check_queue();
+            }
+            TransactionState::RecoveryReadingJournal => {
+                match decode_journal(buffer) {
+                    Some((address, length, checksum))
+                        if length <= self.buffer.map_or(0, |b| b.len()) =>
+                    {
+                        self.journal_buffer.replace(buffer);
+                        self.transaction
+                            .set(TransactionState::RecoveryReadingShadow {
+                                address: address,
+                                length: length,
+                                checksum: checksum,
+                            });
+                        self.transfer_data(
+                            NonvolatileCommand::UserspaceRead,
+                            self.shadow_address.get(),
+                            length,
+                        );
+                    }
+                    // No committed write was interrupted.
+                    _ => {
+                        self.journal_buffer.replace(buffer);
+                        self.recovery_done();
+                    }
+                }
+            }
+            TransactionState::RecoveryReadingShadow {
+                address,
+                length,
+                checksum: expected,
+            } => {
+                if checksum(&buffer[0..length]) == expected {
+                    self.transaction
+                        .set(TransactionState::RecoveryWritingTarget);
+                    let result = self.driver.write(buffer, address, length);
+                    self.transaction_issued(result, &self.buffer);
+                } else {
+                    // The journal cannot be trusted, drop it.
+                    self.buffer.replace(buffer);
+                    self.transaction
+                        .set(TransactionState::RecoveryClearingJournal);
+                    self.write_journal(true);
+                }
+            }
+            TransactionState::RecoveryWritingTarget => {
+                self.buffer.replace(buffer);
+                self.transaction
+                    .set(TransactionState::RecoveryClearingJournal);
+                self.write_journal(true);
+            }
+            TransactionState::RecoveryClearingJournal => {
+                self.journal_buffer.replace(buffer);
+                self.recovery_done();
+            }
+            TransactionState::Idle => {}
+        }
+    }
+
+    fn recovery_done(&self) {
+        self.transaction.set(TransactionState::Idle);
+        self.current_user.set(None);
+        self.check_queue();
     }
 
     fn check_queue(&self) {
+        // A kernel client that was just called back may already have issued
+        // its next request.
+        if self.current_user.get().is_some() {
+            return;
+        }
+
         // Check if there are any pending events.
         if self.kernel_pending_command.get() {
             self.kernel_buffer.take().map(|kernel_buffer| {
                 self.kernel_pending_command.set(false);
                 self.current_user.set(Some(NonvolatileUser::Kernel));
 
-                match self.kernel_command.get() {
+                let command = self.kernel_command.get();
+                let (rc, buffer) = match command {
                     NonvolatileCommand::KernelRead => self.driver.read(
                         kernel_buffer,
                         self.kernel_readwrite_address.get(),
@@ -358,7 +735,19 @@ impl<'a> NonvolatileStorage<'a> {
                         self.kernel_readwrite_address.get(),
                         self.kernel_readwrite_length.get(),
                     ),
-                    _ => ReturnCode::FAIL,
+                    _ => (ReturnCode::FAIL, Some(kernel_buffer)),
+                };
+                if rc != ReturnCode::SUCCESS {
+                    self.current_user.set(None);
+                    // The client interface has no error, so the kernel is
+                    // told that nothing was read or written.
+                    buffer.map(|buffer| {
+                        self.kernel_client.get().map(move |client| match command {
+                            NonvolatileCommand::KernelRead => client.read_done(buffer, 0),
+                            _ => client.write_done(buffer, 0),
+                        })
+                    });
+                    self.check_queue();
                 }
             });
         } else {
@@ -370,8 +759,15 @@ impl<'a> NonvolatileStorage<'a> {
                         self.current_user.set(Some(NonvolatileUser::App {
                             app_id: app.appid(),
                         }));
-                        self.userspace_call_driver(app.command, app.offset, app.length)
-                            == ReturnCode::SUCCESS
+                        let rc = self.userspace_call_driver(app.command, app.offset, app.length);
+                        if rc != ReturnCode::SUCCESS {
+                            let callback = match app.command {
+                                NonvolatileCommand::UserspaceRead => app.callback_read,
+                                _ => app.callback_write,
+                            };
+                            callback.map(|mut cb| cb.schedule(usize::from(rc), 0, 0));
+                        }
+                        rc == ReturnCode::SUCCESS
                     } else {
                         false
                     }
@@ -384,9 +780,52 @@ impl<'a> NonvolatileStorage<'a> {
     }
 }
 
+/// Simple Fletcher-32 style checksum over the data of a transaction.
+fn checksum(data: &[u8]) -> u32 {
+    let mut a: u32 = 0;
+    let mut b: u32 = 0;
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65535;
+        b = (b + a) % 65535;
+    }
+    (b << 16) | a
+}
+
+fn encode_journal(journal: &mut [u8], address: usize, length: usize, checksum: u32) {
+    let seal = JOURNAL_MAGIC ^ address as u32 ^ length as u32 ^ checksum;
+    let fields = [address as u32, length as u32, checksum, seal];
+    for (i, field) in fields.iter().enumerate() {
+        for j in 0..4 {
+            journal[i * 4 + j] = (field >> (8 * j)) as u8;
+        }
+    }
+}
+
+/// Returns the destination address, length and checksum of a sealed journal
+/// record.
+fn decode_journal(journal: &[u8]) -> Option<(usize, usize, u32)> {
+    let mut fields = [0u32; 4];
+    for (i, field) in fields.iter_mut().enumerate() {
+        for j in 0..4 {
+            *field |= (journal[i * 4 + j] as u32) << (8 * j);
+        }
+    }
+    let [address, length, checksum, seal] = fields;
+    if seal == JOURNAL_MAGIC ^ address ^ length ^ checksum {
+        Some((address as usize, length as usize, checksum))
+    } else {
+        None
+    }
+}
+
 /// This is the callback client for the underlying physical storage driver.
 impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStorage<'a> {
     fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.transaction.get() != TransactionState::Idle {
+            self.transaction_done(buffer);
+            return;
+        }
+
         // Switch on which user of this capsule generated this callback.
         self.current_user.get().map(|user| {
             self.current_user.set(None);
@@ -397,7 +836,7 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStora
                     });
                 }
                 NonvolatileUser::App { app_id } => {
-                    let _ = self.apps.enter(app_id, move |app, _| {
+                    let _ = self.apps.enter(app_id, |app, _| {
                         // Need to copy in the contents of the buffer
                         app.buffer_read.as_mut().map(|app_buffer| {
                             let read_len = cmp::min(app_buffer.len(), length);
@@ -408,12 +847,16 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStora
                             }
                         });
 
-                        // Replace the buffer we used to do this read.
-                        self.buffer.replace(buffer);
-
                         // And then signal the app.
                         app.callback_read.map(|mut cb| cb.schedule(length, 0, 0));
                     });
+
+                    // Replace the buffer we used to do this read, even if the
+                    // app is gone.
+                    self.buffer.replace(buffer);
+                }
+                NonvolatileUser::Terminated => {
+                    self.buffer.replace(buffer);
                 }
             }
         });
@@ -422,6 +865,11 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStora
     }
 
     fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.transaction.get() != TransactionState::Idle {
+            self.transaction_done(buffer);
+            return;
+        }
+
         // Switch on which user of this capsule generated this callback.
         self.current_user.get().map(|user| {
             self.current_user.set(None);
@@ -432,14 +880,17 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStora
                     });
                 }
                 NonvolatileUser::App { app_id } => {
-                    let _ = self.apps.enter(app_id, move |app, _| {
-                        // Replace the buffer we used to do this write.
-                        self.buffer.replace(buffer);
+                    // Replace the buffer we used to do this write.
+                    self.buffer.replace(buffer);
 
-                        // And then signal the app.
+                    // And then signal the app.
+                    let _ = self.apps.enter(app_id, |app, _| {
                         app.callback_write.map(|mut cb| cb.schedule(length, 0, 0));
                     });
                 }
+                NonvolatileUser::Terminated => {
+                    self.buffer.replace(buffer);
+                }
             }
         });
 
@@ -453,14 +904,38 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorage for NonvolatileStorage<'a>
         self.kernel_client.set(Some(client));
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        // Only one kernel request can be queued.
+        if self.kernel_buffer.is_some() {
+            return (ReturnCode::ENOMEM, Some(buffer));
+        }
         self.kernel_buffer.replace(buffer);
-        self.enqueue_command(NonvolatileCommand::KernelRead, address, length, None)
+        match self.enqueue_command(NonvolatileCommand::KernelRead, address, length, None) {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            rc => (rc, self.kernel_buffer.take()),
+        }
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        // Only one kernel request can be queued.
+        if self.kernel_buffer.is_some() {
+            return (ReturnCode::ENOMEM, Some(buffer));
+        }
         self.kernel_buffer.replace(buffer);
-        self.enqueue_command(NonvolatileCommand::KernelWrite, address, length, None)
+        match self.enqueue_command(NonvolatileCommand::KernelWrite, address, length, None) {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            rc => (rc, self.kernel_buffer.take()),
+        }
     }
 }
 
@@ -496,22 +971,34 @@ impl<'a> Driver for NonvolatileStorage<'a> {
     ///
     /// - `0`: Setup a read done callback.
     /// - `1`: Setup a write done callback.
+    ///
+    /// The callbacks receive the number of bytes read or written, or a
+    /// negative error code if a queued or journaled request failed.
     fn subscribe(
         &self,
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         self.apps
-            .enter(app_id, |app, _| {
-                match subscribe_num {
-                    0 => app.callback_read = callback,
-                    1 => app.callback_write = callback,
-                    _ => return ReturnCode::ENOSUPPORT,
-                }
-                ReturnCode::SUCCESS
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => Ok(mem::replace(&mut app.callback_read, callback)),
+                1 => Ok(mem::replace(&mut app.callback_write, callback)),
+                _ => Err(ReturnCode::ENOSUPPORT),
             })
-            .unwrap_or_else(|err| err.into())
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// A request of an app that terminated cannot be stopped part way. It
+    /// keeps the storage until the operation in progress completes, and is
+    /// then dropped without being reported, unless it is a write whose
+    /// journal record was already sealed, which is finished.
+    fn app_terminated(&self, app_id: AppId) {
+        if let Some(NonvolatileUser::App { app_id: current }) = self.current_user.get() {
+            if current == app_id {
+                self.current_user.set(Some(NonvolatileUser::Terminated));
+            }
+        }
     }
 
     /// Command interface.
@@ -521,7 +1008,7 @@ impl<'a> Driver for NonvolatileStorage<'a> {
     /// ### `command_num`
     ///
     /// - `0`: Return SUCCESS if this driver is included on the platform.
-    /// - `1`: Return the number of bytes available to userspace.
+    /// - `1`: Return the number of bytes available to this app.
     /// - `2`: Start a read from the nonvolatile storage.
     /// - `3`: Start a write to the nonvolatile_storage.
     fn command(&self, arg0: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
@@ -530,8 +1017,10 @@ impl<'a> Driver for NonvolatileStorage<'a> {
         match command_num {
             0 => /* This driver exists. */ ReturnCode::SUCCESS,
 
-            // How many bytes are accessible from userspace.
-            1 => ReturnCode::SuccessWithValue { value: self.userspace_length },
+            // How many bytes are accessible from this app.
+            1 => ReturnCode::SuccessWithValue {
+                value: self.app_region(appid).map_or(0, |(_, length)| length),
+            },
 
             // Issue a read
             2 => {
@@ -82,6 +82,19 @@ impl<'a, F: hil::flash::Flash + 'a> NonvolatileToPages<'a, F> {
             buffer_index: Cell::new(0),
         }
     }
+
+    /// Finish starting a read or write with the result of the first page
+    /// operation. If it failed, go back to idle and hand the caller's buffer
+    /// back. The flash HIL does not return the page buffer, so a failed page
+    /// operation prevents any further reads or writes.
+    fn started(&self, rc: ReturnCode) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rc == ReturnCode::SUCCESS {
+            (rc, None)
+        } else {
+            self.state.set(State::Idle);
+            (rc, self.buffer.take())
+        }
+    }
 }
 
 impl<'a, F: hil::flash::Flash + 'a> hil::nonvolatile_storage::NonvolatileStorage
@@ -91,14 +104,19 @@ impl<'a, F: hil::flash::Flash + 'a> hil::nonvolatile_storage::NonvolatileStorage
         self.client.set(client);
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         if self.state.get() != State::Idle {
-            return ReturnCode::EBUSY;
+            return (ReturnCode::EBUSY, Some(buffer));
         }
 
-        self.pagebuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, move |pagebuffer| {
+        match self.pagebuffer.take() {
+            None => (ReturnCode::ERESERVE, Some(buffer)),
+            Some(pagebuffer) => {
                 let page_size = pagebuffer.as_mut().len();
 
                 // Just start reading. We'll worry about how much of the page we
@@ -109,18 +127,25 @@ impl<'a, F: hil::flash::Flash + 'a> hil::nonvolatile_storage::NonvolatileStorage
                 self.length.set(length);
                 self.remaining_length.set(length);
                 self.buffer_index.set(0);
-                self.driver.read_page(address / page_size, pagebuffer)
-            })
+                let rc = self.driver.read_page(address / page_size, pagebuffer);
+                self.started(rc)
+            }
+        }
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         if self.state.get() != State::Idle {
-            return ReturnCode::EBUSY;
+            return (ReturnCode::EBUSY, Some(buffer));
         }
 
-        self.pagebuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, move |pagebuffer| {
+        match self.pagebuffer.take() {
+            None => (ReturnCode::ERESERVE, Some(buffer)),
+            Some(pagebuffer) => {
                 let page_size = pagebuffer.as_mut().len();
 
                 self.state.set(State::Write);
@@ -139,16 +164,19 @@ impl<'a, F: hil::flash::Flash + 'a> hil::nonvolatile_storage::NonvolatileStorage
                     self.address.set(address + page_size);
                     self.remaining_length.set(length - page_size);
                     self.buffer_index.set(page_size);
-                    self.driver.write_page(address / page_size, pagebuffer)
+                    let rc = self.driver.write_page(address / page_size, pagebuffer);
+                    self.started(rc)
                 } else {
                     // Need to do a read first.
                     self.buffer.replace(buffer);
                     self.address.set(address);
                     self.remaining_length.set(length);
                     self.buffer_index.set(0);
-                    self.driver.read_page(address / page_size, pagebuffer)
+                    let rc = self.driver.read_page(address / page_size, pagebuffer);
+                    self.started(rc)
                 }
-            })
+            }
+        }
     }
 }
 
@@ -17,6 +17,7 @@
 //! ```
 
 use core::cmp;
+use core::mem;
 use kernel::common::cells::{MapCell, TakeCell};
 use kernel::hil::uart::{self, Client, UARTReceiveAdvanced};
 use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
@@ -126,25 +127,57 @@ impl<'a, U: UARTReceiveAdvanced> Driver for Nrf51822Serialization<'a, U> {
         subscribe_type: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_type {
             // Add a callback
             0 => {
-                // work-around because `MapCell` don't provide `map_or_else`
-                if self.app.map(|app| app.callback = callback).is_none() == true {
-                    return ReturnCode::FAIL;
-                }
+                let previous = match self
+                    .app
+                    .map(|app| mem::replace(&mut app.callback, callback))
+                {
+                    Some(previous) => previous,
+                    None => return Err(ReturnCode::FAIL),
+                };
 
                 // Start the receive now that we have a callback.
-                self.rx_buffer.take().map_or(ReturnCode::FAIL, |buffer| {
-                    self.uart.receive_automatic(buffer, 250);
-                    ReturnCode::SUCCESS
-                })
+                self.rx_buffer
+                    .take()
+                    .map_or(Err(ReturnCode::FAIL), |buffer| {
+                        self.uart.receive_automatic(buffer, 250);
+                        Ok(previous)
+                    })
             }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
+    /// Drop the callback and buffers of a process that terminated. Data
+    /// received from the nRF is discarded until another process allows a
+    /// receive buffer.
+    fn app_terminated(&self, app_id: AppId) {
+        self.app.map(|app| {
+            if app.callback.map_or(false, |cb| cb.app_id() == app_id) {
+                app.callback = None;
+            }
+            if app
+                .tx_buffer
+                .as_ref()
+                .map_or(false, |slice| slice.app_id() == app_id)
+            {
+                app.tx_buffer = None;
+            }
+            if app
+                .rx_buffer
+                .as_ref()
+                .map_or(false, |slice| slice.app_id() == app_id)
+            {
+                app.rx_buffer = None;
+                app.rx_recv_so_far = 0;
+                app.rx_recv_total = 0;
+            }
+        });
+    }
+
     /// Issue a command to the Nrf51822Serialization driver.
     ///
     /// ### `command_type`
@@ -0,0 +1,327 @@
+//! Radio packet sniffer that streams captured frames in pcap format.
+//!
+//! Puts an 802.15.4 radio into promiscuous mode, or listens on a BLE
+//! advertising channel, and writes every received frame to a UART as a pcap
+//! stream. The serial port can then be opened directly by Wireshark
+//! (`wireshark -k -i /dev/ttyUSB0`) or saved to a file with any terminal
+//! program.
+//!
+//! The pcap global header is written once by `PcapWriter::initialize()`, so
+//! the host should start reading before the board is reset. Each frame is
+//! stamped with the time since boot as reported by the alarm. Frames that
+//! arrive while the previous record is still being transmitted are dropped and
+//! counted; `PcapWriter::dropped()` reports the total.
+//!
+//! The sniffer takes over the radio's receive client, so it cannot be used
+//! together with the 802.15.4 MAC or the BLE advertising driver on the same
+//! radio, and the UART must not be shared with the console.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pcap_writer = static_init!(
+//!     capsules::pcap_sniffer::PcapWriter<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>, sam4l::usart::USART>,
+//!     capsules::pcap_sniffer::PcapWriter::new(
+//!         &sam4l::usart::USART3,
+//!         sniffer_alarm,
+//!         921600,
+//!         capsules::pcap_sniffer::LINKTYPE_IEEE802_15_4_WITHFCS,
+//!         &mut capsules::pcap_sniffer::BUFFER));
+//! hil::uart::UART::set_client(&sam4l::usart::USART3, pcap_writer);
+//!
+//! let sniffer = static_init!(
+//!     capsules::pcap_sniffer::Ieee802154Sniffer<'static, RF233Device, VirtualMuxAlarm<'static, sam4l::ast::Ast>, sam4l::usart::USART>,
+//!     capsules::pcap_sniffer::Ieee802154Sniffer::new(rf233, pcap_writer));
+//! rf233.set_receive_client(sniffer, &mut RF233_RX_BUF);
+//!
+//! pcap_writer.initialize();
+//! sniffer.start(26);
+//! ```
+//!
+//! For BLE, use `LINKTYPE_BLUETOOTH_LE_LL`, create a `BleSniffer` over the
+//! `BleAdvertisementDriver` and set it as the radio's receive client.
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::ble_advertising::{self, RadioChannel};
+use kernel::hil::radio;
+use kernel::hil::time::{Alarm, Frequency};
+use kernel::hil::uart::{self, UART};
+use kernel::ReturnCode;
+
+/// IEEE 802.15.4 frames including the FCS.
+pub const LINKTYPE_IEEE802_15_4_WITHFCS: u32 = 195;
+/// BLE link layer packets, starting with the access address and ending with
+/// the CRC.
+pub const LINKTYPE_BLUETOOTH_LE_LL: u32 = 251;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Largest frame that is stored in a record; longer frames are truncated.
+pub const SNAPLEN: usize = 256;
+
+pub static mut BUFFER: [u8; RECORD_HEADER_LEN + SNAPLEN] = [0; RECORD_HEADER_LEN + SNAPLEN];
+
+/// Access address used by all BLE advertising channel packets.
+const BLE_ADVERTISING_ACCESS_ADDRESS: u32 = 0x8e89bed6;
+const BLE_ADVERTISING_CRC_INIT: u32 = 0x555555;
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    put_u16(buf, offset, value as u16);
+    put_u16(buf, offset + 2, (value >> 16) as u16);
+}
+
+/// Formats frames as pcap records and writes them to a UART.
+pub struct PcapWriter<'a, A: Alarm + 'a, U: UART + 'a> {
+    uart: &'a U,
+    alarm: &'a A,
+    baud_rate: u32,
+    link_type: u32,
+    buffer: TakeCell<'static, [u8]>,
+    last_now: Cell<u32>,
+    wraps: Cell<u32>,
+    dropped: Cell<u32>,
+}
+
+impl<'a, A: Alarm, U: UART> PcapWriter<'a, A, U> {
+    pub fn new(
+        uart: &'a U,
+        alarm: &'a A,
+        baud_rate: u32,
+        link_type: u32,
+        buffer: &'static mut [u8],
+    ) -> PcapWriter<'a, A, U> {
+        PcapWriter {
+            uart: uart,
+            alarm: alarm,
+            baud_rate: baud_rate,
+            link_type: link_type,
+            buffer: TakeCell::new(buffer),
+            last_now: Cell::new(0),
+            wraps: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Configure the UART and write the pcap global header.
+    pub fn initialize(&self) -> ReturnCode {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            put_u32(buffer, 0, PCAP_MAGIC);
+            put_u16(buffer, 4, 2); // Version 2.4
+            put_u16(buffer, 6, 4);
+            put_u32(buffer, 8, 0); // Timestamps are in UTC
+            put_u32(buffer, 12, 0); // Timestamp accuracy
+            put_u32(buffer, 16, SNAPLEN as u32);
+            put_u32(buffer, 20, self.link_type);
+            self.uart.transmit(buffer, GLOBAL_HEADER_LEN);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Number of frames dropped because the UART was busy.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.get()
+    }
+
+    /// Time since boot as (seconds, microseconds). The alarm counter must be
+    /// sampled at least once per wraparound for this to stay accurate, which
+    /// on a busy channel it is.
+    fn timestamp(&self) -> (u32, u32) {
+        let now = self.alarm.now();
+        if now < self.last_now.get() {
+            self.wraps.set(self.wraps.get() + 1);
+        }
+        self.last_now.set(now);
+
+        let freq = A::Frequency::frequency() as u64;
+        let ticks = (self.wraps.get() as u64) << 32 | now as u64;
+        let usecs = (ticks % freq) * 1_000_000 / freq;
+        ((ticks / freq) as u32, usecs as u32)
+    }
+
+    /// Start a record for a frame of `len` bytes and let `fill` copy the
+    /// frame into the slice it is given, which is truncated to `SNAPLEN`.
+    fn capture<F>(&self, len: usize, fill: F)
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let (secs, usecs) = self.timestamp();
+        let captured = self.buffer.take().map(|buffer| {
+            let incl_len = ::core::cmp::min(
+                ::core::cmp::min(len, SNAPLEN),
+                buffer.len() - RECORD_HEADER_LEN,
+            );
+            put_u32(buffer, 0, secs);
+            put_u32(buffer, 4, usecs);
+            put_u32(buffer, 8, incl_len as u32);
+            put_u32(buffer, 12, len as u32);
+            fill(&mut buffer[RECORD_HEADER_LEN..RECORD_HEADER_LEN + incl_len]);
+            self.uart.transmit(buffer, RECORD_HEADER_LEN + incl_len);
+        });
+        if captured.is_none() {
+            self.dropped.set(self.dropped.get() + 1);
+        }
+    }
+}
+
+impl<'a, A: Alarm, U: UART> uart::Client for PcapWriter<'a, A, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.buffer.replace(buffer);
+    }
+
+    fn receive_complete(&self, _buffer: &'static mut [u8], _rx_len: usize, _error: uart::Error) {}
+}
+
+/// Captures every frame an 802.15.4 radio receives.
+pub struct Ieee802154Sniffer<'a, R: radio::Radio + 'a, A: Alarm + 'a, U: UART + 'a> {
+    radio: &'a R,
+    writer: &'a PcapWriter<'a, A, U>,
+}
+
+impl<'a, R: radio::Radio, A: Alarm, U: UART> Ieee802154Sniffer<'a, R, A, U> {
+    pub fn new(radio: &'a R, writer: &'a PcapWriter<'a, A, U>) -> Ieee802154Sniffer<'a, R, A, U> {
+        Ieee802154Sniffer {
+            radio: radio,
+            writer: writer,
+        }
+    }
+
+    /// Switch the radio to promiscuous mode on `channel`.
+    pub fn start(&self, channel: u8) -> ReturnCode {
+        let rval = self.radio.set_channel(channel);
+        if rval != ReturnCode::SUCCESS {
+            return rval;
+        }
+        let rval = self.radio.set_promiscuous(true);
+        if rval != ReturnCode::SUCCESS {
+            return rval;
+        }
+        self.radio.config_commit();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm, U: UART> radio::RxClient for Ieee802154Sniffer<'a, R, A, U> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        _rssi: i8,
+        _lqi: u8,
+        _crc_valid: bool,
+        result: ReturnCode,
+    ) {
+        // Frames with a bad FCS are captured as well; Wireshark flags them.
+        let psdu_len = frame_len + radio::MFR_SIZE;
+        if result == ReturnCode::SUCCESS && radio::PSDU_OFFSET + psdu_len <= buf.len() {
+            let psdu = &buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + psdu_len];
+            self.writer.capture(psdu_len, |record| {
+                let len = record.len();
+                record.copy_from_slice(&psdu[0..len]);
+            });
+        }
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+/// Computes the 24-bit BLE CRC over `data`, returned in the order its bytes
+/// are sent over the air.
+fn ble_crc(data: &[u8]) -> [u8; 3] {
+    let mut crc = BLE_ADVERTISING_CRC_INIT;
+    for &byte in data {
+        for bit in 0..8 {
+            let feedback = ((crc >> 23) & 1) ^ ((byte as u32 >> bit) & 1);
+            crc = (crc << 1) & 0xffffff;
+            if feedback != 0 {
+                crc ^= 0x00065b;
+            }
+        }
+    }
+    let reverse = |b: u8| (0..8).fold(0, |acc, i| acc | ((b >> i) & 1) << (7 - i));
+    [
+        reverse((crc >> 16) as u8),
+        reverse((crc >> 8) as u8),
+        reverse(crc as u8),
+    ]
+}
+
+/// Captures advertising channel packets received by a BLE radio.
+///
+/// The radio reports only the PDU, so the advertising access address is added
+/// in front and the CRC is recomputed, as expected by
+/// `LINKTYPE_BLUETOOTH_LE_LL`. Only PDUs that passed the radio's CRC check are
+/// captured.
+pub struct BleSniffer<
+    'a,
+    B: ble_advertising::BleAdvertisementDriver + 'a,
+    A: Alarm + 'a,
+    U: UART + 'a,
+> {
+    radio: &'a B,
+    writer: &'a PcapWriter<'a, A, U>,
+    channel: Cell<Option<RadioChannel>>,
+}
+
+impl<'a, B: ble_advertising::BleAdvertisementDriver, A: Alarm, U: UART> BleSniffer<'a, B, A, U> {
+    pub fn new(radio: &'a B, writer: &'a PcapWriter<'a, A, U>) -> BleSniffer<'a, B, A, U> {
+        BleSniffer {
+            radio: radio,
+            writer: writer,
+            channel: Cell::new(None),
+        }
+    }
+
+    /// Listen continuously on one advertising channel.
+    pub fn start(&self, channel: RadioChannel) -> ReturnCode {
+        match channel {
+            RadioChannel::AdvertisingChannel37
+            | RadioChannel::AdvertisingChannel38
+            | RadioChannel::AdvertisingChannel39 => {
+                self.channel.set(Some(channel));
+                self.radio.receive_advertisement(channel);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+}
+
+impl<'a, B: ble_advertising::BleAdvertisementDriver, A: Alarm, U: UART> ble_advertising::RxClient
+    for BleSniffer<'a, B, A, U>
+{
+    fn receive_event(&self, buf: &'static mut [u8], len: u8, result: ReturnCode) {
+        let len = len as usize;
+        if result == ReturnCode::SUCCESS && len <= buf.len() {
+            let pdu = &buf[0..len];
+            let crc = ble_crc(pdu);
+            self.writer.capture(4 + len + 3, |record| {
+                let mut packet = [0; 4];
+                put_u32(&mut packet, 0, BLE_ADVERTISING_ACCESS_ADDRESS);
+                let bytes = packet.iter().chain(pdu.iter()).chain(crc.iter());
+                for (dst, src) in record.iter_mut().zip(bytes) {
+                    *dst = *src;
+                }
+            });
+        }
+
+        // The radio stops after every packet.
+        self.channel
+            .get()
+            .map(|channel| self.radio.receive_advertisement(channel));
+    }
+}
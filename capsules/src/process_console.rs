@@ -0,0 +1,313 @@
+//! A text console for controlling processes over a UART, for bring-up.
+//!
+//! The console reads a line at a time, echoing what is typed, and answers
+//! these commands:
+//!
+//! - `list`: list the processes, with their slot, name, state and how many
+//!   times they have been restarted after a fault.
+//! - `stop <app>`: stop scheduling the process until it is started again.
+//! - `start <app>`: schedule a stopped process again, where it left off.
+//! - `fault <app>`: handle the process as if it had faulted, which panics
+//!   the kernel, restarts the process or stops it for good according to its
+//!   fault response.
+//! - `status`: the kernel version and how many processes are in each state.
+//!
+//! An `<app>` is either the name of a process or its slot. Errors are
+//! printed as the `ReturnCode` of the kernel call, for example `EALREADY`
+//! when stopping a stopped process.
+//!
+//! The console needs a UART of its own, so a board typically gives it the
+//! UART the userspace console would otherwise use while it is being brought
+//! up.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let process_console = static_init!(
+//!     capsules::process_console::ProcessConsole<'static, usart::USART>,
+//!     capsules::process_console::ProcessConsole::new(
+//!         &usart::USART0,
+//!         115200,
+//!         kernel::process_control::ProcessControl::new(),
+//!         &mut capsules::process_console::WRITE_BUF,
+//!         &mut capsules::process_console::READ_BUF,
+//!         &mut capsules::process_console::COMMAND_BUF
+//!     )
+//! );
+//! hil::uart::UART::set_client(&usart::USART0, process_console);
+//! process_console.initialize();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt::{self, Write};
+use core::str;
+use kernel;
+use kernel::common::cells::TakeCell;
+use kernel::hil::uart::{self, UART};
+use kernel::process_control::ProcessControl;
+use kernel::procs::State;
+use kernel::{AppId, ReturnCode};
+
+pub static mut WRITE_BUF: [u8; 128] = [0; 128];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+
+const PROMPT: &'static str = "tock$ ";
+const HELP: &'static str = "Commands: list, stop <app>, start <app>, fault <app>, status";
+
+pub struct ProcessConsole<'a, U: UART + 'a> {
+    uart: &'a U,
+    baud_rate: u32,
+    control: ProcessControl,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    command_buffer: TakeCell<'static, [u8]>,
+    command_len: Cell<usize>,
+    /// The byte typed before, to treat CR LF as one line ending.
+    last_typed: Cell<u8>,
+    /// A whole line has been typed, and is run once the UART is free.
+    command_ready: Cell<bool>,
+    /// The next slot to print while `list` is running.
+    listing: Cell<Option<usize>>,
+}
+
+impl<'a, U: UART> ProcessConsole<'a, U> {
+    pub fn new(
+        uart: &'a U,
+        baud_rate: u32,
+        control: ProcessControl,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        command_buffer: &'static mut [u8],
+    ) -> ProcessConsole<'a, U> {
+        ProcessConsole {
+            uart: uart,
+            baud_rate: baud_rate,
+            control: control,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            command_buffer: TakeCell::new(command_buffer),
+            command_len: Cell::new(0),
+            last_typed: Cell::new(0),
+            command_ready: Cell::new(false),
+            listing: Cell::new(None),
+        }
+    }
+
+    pub fn initialize(&self) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        self.write(format_args!("{}", PROMPT));
+        self.rx_buffer.take().map(|buffer| {
+            self.uart.receive(buffer, 1);
+        });
+    }
+
+    /// Format `args` into the transmit buffer and send it. Longer output is
+    /// cut short. Returns `false`, sending nothing, if the UART is busy.
+    fn write(&self, args: fmt::Arguments) -> bool {
+        self.tx_buffer
+            .take()
+            .map(|buffer| {
+                let len = {
+                    let mut writer = BufferWriter {
+                        buffer: buffer,
+                        len: 0,
+                    };
+                    let _ = writer.write_fmt(args);
+                    writer.len
+                };
+                self.uart.transmit(buffer, len);
+            })
+            .is_some()
+    }
+
+    /// Add a typed byte to the command, echoing it if the UART is free.
+    fn typed(&self, byte: u8) {
+        let last = self.last_typed.replace(byte);
+        match byte {
+            b'\n' if last == b'\r' => {}
+            b'\r' | b'\n' => {
+                self.command_ready.set(true);
+                if self.tx_buffer.is_some() {
+                    self.run_command();
+                }
+            }
+            // Backspace and delete
+            0x08 | 0x7F => {
+                if self.command_len.get() > 0 {
+                    self.command_len.set(self.command_len.get() - 1);
+                    self.write(format_args!("\x08 \x08"));
+                }
+            }
+            0x20...0x7E => {
+                self.command_buffer.map(|command| {
+                    let len = self.command_len.get();
+                    if len < command.len() {
+                        command[len] = byte;
+                        self.command_len.set(len + 1);
+                        self.write(format_args!("{}", byte as char));
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// The process named by `app`, by name or by slot.
+    fn find(&self, app: &str) -> Option<AppId> {
+        self.control.find(app).or_else(|| {
+            app.parse::<usize>()
+                .ok()
+                .and_then(|slot| self.control.appid(slot))
+        })
+    }
+
+    fn run_command(&self) {
+        self.command_ready.set(false);
+        let len = self.command_len.get();
+        self.command_len.set(0);
+        self.command_buffer.map(|command| {
+            let line = str::from_utf8(&command[..len]).unwrap_or("");
+            let mut words = line.split_whitespace();
+            let (name, app) = (words.next(), words.next());
+            let call: Option<fn(&ProcessControl, AppId) -> ReturnCode> = match name {
+                Some("stop") => Some(ProcessControl::stop),
+                Some("start") => Some(ProcessControl::resume),
+                Some("fault") => Some(ProcessControl::fault),
+                _ => None,
+            };
+            match (name, app, call) {
+                (None, _, _) => {
+                    self.write(format_args!("\r\n{}", PROMPT));
+                }
+                (Some("list"), None, _) => {
+                    self.write(format_args!(
+                        "\r\n{:<5}{:<20}{:<10}{}\r\n",
+                        "Slot", "Name", "State", "Restarts"
+                    ));
+                    self.listing.set(Some(0));
+                }
+                (Some("status"), None, _) => {
+                    let mut counts = [0; 4];
+                    for slot in 0..self.control.num_slots() {
+                        self.control.appid(slot).map(|appid| {
+                            let state = self.control.state(appid).unwrap_or(State::Fault);
+                            counts[state_index(state)] += 1;
+                        });
+                    }
+                    self.write(format_args!(
+                        "\r\nTock {}: {} running, {} yielded, {} stopped, {} faulted\r\n{}",
+                        kernel::KERNEL_VERSION,
+                        counts[0],
+                        counts[1],
+                        counts[2],
+                        counts[3],
+                        PROMPT
+                    ));
+                }
+                (Some(name), Some(app), Some(call)) => {
+                    let result = self
+                        .find(app)
+                        .map_or(ReturnCode::EINVAL, |appid| call(&self.control, appid));
+                    if result == ReturnCode::SUCCESS {
+                        self.write(format_args!("\r\n{}", PROMPT));
+                    } else {
+                        self.write(format_args!(
+                            "\r\n{} {}: {:?}\r\n{}",
+                            name, app, result, PROMPT
+                        ));
+                    }
+                }
+                _ => {
+                    self.write(format_args!("\r\n{}\r\n{}", HELP, PROMPT));
+                }
+            }
+        });
+    }
+
+    /// Print the line of `list` for the first process from `slot` on, or the
+    /// prompt once all have been printed.
+    fn list_from(&self, slot: usize) {
+        let next = (slot..self.control.num_slots())
+            .filter_map(|slot| self.control.appid(slot).map(|appid| (slot, appid)))
+            .next();
+        match next {
+            None => {
+                self.listing.set(None);
+                self.write(format_args!("{}", PROMPT));
+            }
+            Some((slot, appid)) => {
+                self.listing.set(Some(slot + 1));
+                let state = match self.control.state(appid) {
+                    Some(State::Running) => "running",
+                    Some(State::Yielded) => "yielded",
+                    Some(State::StoppedRunning) | Some(State::StoppedYielded) => "stopped",
+                    _ => "faulted",
+                };
+                self.write(format_args!(
+                    "{:<5}{:<20}{:<10}{}\r\n",
+                    slot,
+                    self.control.name(appid).unwrap_or(""),
+                    state,
+                    self.control.restart_count(appid).unwrap_or(0)
+                ));
+            }
+        }
+    }
+}
+
+/// Where `status` counts a process in the given state.
+fn state_index(state: State) -> usize {
+    match state {
+        State::Running => 0,
+        State::Yielded => 1,
+        State::StoppedRunning | State::StoppedYielded => 2,
+        State::Fault => 3,
+    }
+}
+
+impl<'a, U: UART> uart::Client for ProcessConsole<'a, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        if let Some(slot) = self.listing.get() {
+            self.list_from(slot);
+        } else if self.command_ready.get() {
+            self.run_command();
+        }
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        let byte = buffer[0];
+        self.uart.receive(buffer, 1);
+        // What is typed while a command runs is dropped.
+        if error == uart::Error::CommandComplete
+            && rx_len == 1
+            && !self.command_ready.get()
+            && self.listing.get().is_none()
+        {
+            self.typed(byte);
+        }
+    }
+}
+
+/// Formats into a buffer, dropping what does not fit.
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Write for BufferWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = cmp::min(s.len(), self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
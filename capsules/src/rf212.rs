@@ -0,0 +1,230 @@
+//! Driver for sending 802.15.4 packets with an Atmel AT86RF212B, a sub-GHz
+//! transceiver.
+//!
+//! Sub-GHz signals travel further and through more walls than 2.4 GHz ones,
+//! which suits sensors deployed inside buildings. The radio shares its SPI
+//! interface and state machine with the RF233 and runs on the same
+//! `at86rf2xx` driver, so the MAC layers and the 802.15.4 capsule run on it
+//! unchanged. This module describes what is particular to the RF212: its
+//! bands, modulations and transmit power steps.
+//!
+//! The radio works in one of two bands, each using one modulation at a time:
+//!
+//! - `Band::Eu868`: one channel, channel 0, at 868.3 MHz, with BPSK at
+//!   20 kbit/s or O-QPSK at 100 kbit/s.
+//! - `Band::Na915`: channels 1 to 10, at 906 MHz plus 2 MHz for each channel
+//!   after the first, with BPSK at 40 kbit/s or O-QPSK at 250 kbit/s.
+//!
+//! The band and modulation are chosen with `set_band` and `set_modulation`
+//! and, like the other settings, take effect on the next `config_commit`;
+//! the radio stops receiving while it changes modulation. The radio starts
+//! on channel 1 of the 915 MHz band with O-QPSK, whose 250 kbit/s matches
+//! the 2.4 GHz PHY.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let rf212_spi = static_init!(
+//!     VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     VirtualSpiMasterDevice::new(mux_spi, 3)
+//! );
+//! let rf212 = static_init!(
+//!     capsules::rf212::RF212<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
+//!     capsules::rf212::RF212::new(
+//!         rf212_spi,
+//!         &sam4l::gpio::PA[09], // reset
+//!         &sam4l::gpio::PA[10], // sleep
+//!         &sam4l::gpio::PA[08], // irq
+//!         &sam4l::gpio::PA[08],
+//!         capsules::rf212::RF212Part::new(),
+//!     )
+//! );
+//! sam4l::gpio::PA[08].set_client(rf212);
+//! rf212.set_band(capsules::rf212::Band::Eu868);
+//! rf212.set_modulation(capsules::rf212::Modulation::Oqpsk);
+//! ```
+
+use at86rf2xx::{AT86RF2xx, Part};
+use at86rf2xx_const::*;
+use core::cell::Cell;
+use kernel::hil::spi;
+use rf212_const::*;
+
+// Worst-case time from start() until the radio can receive. Leaving SLEEP
+// takes up to 420 us for the crystal to settle plus 200 us for the PLL to
+// lock.
+const SLEEP_WAKE_LATENCY_US: u32 = 650;
+
+// The highest and lowest transmit power, in dBm. Each TX_PWR step below the
+// highest lowers the output power by about 1 dB.
+const MAX_TX_POWER: i8 = 5;
+const MIN_TX_POWER: i8 = -25;
+
+/// The frequency bands of the radio.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Band {
+    /// 868.3 MHz, channel 0.
+    Eu868,
+    /// 906 to 924 MHz, channels 1 to 10.
+    Na915,
+}
+
+/// The modulations of the radio. The data rate depends on the band.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Modulation {
+    /// BPSK, channel page 0: 20 kbit/s at 868 MHz and 40 kbit/s at 915 MHz.
+    Bpsk,
+    /// O-QPSK, channel page 2: 100 kbit/s at 868 MHz and 250 kbit/s at
+    /// 915 MHz.
+    Oqpsk,
+}
+
+impl Band {
+    fn first_channel(self) -> u8 {
+        match self {
+            Band::Eu868 => 0,
+            Band::Na915 => 1,
+        }
+    }
+
+    fn has_channel(self, chan: u8) -> bool {
+        match self {
+            Band::Eu868 => chan == 0,
+            Band::Na915 => chan >= 1 && chan <= 10,
+        }
+    }
+}
+
+// The TRX_CTRL_2 setting for the PHY mode
+fn phy_mode_setting(band: Band, modulation: Modulation) -> u8 {
+    let mode = match (band, modulation) {
+        (Band::Eu868, Modulation::Bpsk) => 0,
+        (Band::Na915, Modulation::Bpsk) => TRX_CTRL_2_SUB_MODE,
+        (Band::Eu868, Modulation::Oqpsk) => TRX_CTRL_2_BPSK_OQPSK | TRX_CTRL_2_OQPSK_SCRAM_EN,
+        (Band::Na915, Modulation::Oqpsk) => {
+            TRX_CTRL_2_BPSK_OQPSK | TRX_CTRL_2_SUB_MODE | TRX_CTRL_2_OQPSK_SCRAM_EN
+        }
+    };
+    TRX_CTRL_2_RX_SAFE_MODE | mode
+}
+
+// The RF_CTRL_0 setting for the modulation: the datasheet recommends a
+// larger TX gain offset for BPSK than for O-QPSK.
+fn rf_ctrl_0_setting(modulation: Modulation) -> u8 {
+    match modulation {
+        Modulation::Bpsk => RF_CTRL_0_GC_TX_OFFS_2DB,
+        Modulation::Oqpsk => RF_CTRL_0_GC_TX_OFFS_1DB,
+    }
+}
+
+// Received power in dBm for a PHY_ED_LEVEL reading of 0
+fn rssi_base(band: Band, modulation: Modulation) -> i8 {
+    match (band, modulation) {
+        (Band::Eu868, Modulation::Bpsk) => -100,
+        (Band::Na915, Modulation::Bpsk) => -99,
+        (Band::Eu868, Modulation::Oqpsk) => -98,
+        (Band::Na915, Modulation::Oqpsk) => -97,
+    }
+}
+
+// Air time of one octet, in microseconds
+fn octet_us(band: Band, modulation: Modulation) -> u64 {
+    match (band, modulation) {
+        (Band::Eu868, Modulation::Bpsk) => 400,
+        (Band::Na915, Modulation::Bpsk) => 200,
+        (Band::Eu868, Modulation::Oqpsk) => 80,
+        (Band::Na915, Modulation::Oqpsk) => 32,
+    }
+}
+
+pub type RF212<'a, S> = AT86RF2xx<'a, S, RF212Part>;
+
+pub struct RF212Part {
+    band: Cell<Band>,
+    modulation: Cell<Modulation>,
+}
+
+impl RF212Part {
+    pub fn new() -> RF212Part {
+        RF212Part {
+            band: Cell::new(Band::Na915),
+            modulation: Cell::new(Modulation::Oqpsk),
+        }
+    }
+}
+
+impl Part for RF212Part {
+    const INTERRUPT_ID: usize = 0x2124;
+    const RX_LEASE_NAME: &'static str = "rf212 rx";
+    const MAX_TX_POWER: i8 = MAX_TX_POWER;
+    const MIN_TX_POWER: i8 = MIN_TX_POWER;
+    const TRX_CTRL_1: u8 = TRX_CTRL_1;
+
+    fn power_to_setting(power: i8) -> u8 {
+        let power = if power > MAX_TX_POWER {
+            MAX_TX_POWER
+        } else if power < MIN_TX_POWER {
+            MIN_TX_POWER
+        } else {
+            power
+        };
+        PHY_TX_PWR_GC_PA | ((MAX_TX_POWER - power) as u8 & PHY_TX_PWR_TX_PWR_MASK)
+    }
+
+    fn default_channel(&self) -> u8 {
+        self.band.get().first_channel()
+    }
+
+    fn has_channel(&self, chan: u8) -> bool {
+        self.band.get().has_channel(chan)
+    }
+
+    fn trx_ctrl_2(&self) -> u8 {
+        phy_mode_setting(self.band.get(), self.modulation.get())
+    }
+
+    fn trx_rpc(&self) -> u8 {
+        rf_ctrl_0_setting(self.modulation.get())
+    }
+
+    fn rssi_base(&self) -> i8 {
+        rssi_base(self.band.get(), self.modulation.get())
+    }
+
+    fn octet_us(&self) -> u64 {
+        octet_us(self.band.get(), self.modulation.get())
+    }
+
+    fn wake_latency_us(&self) -> u32 {
+        SLEEP_WAKE_LATENCY_US
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice + 'a> RF212<'a, S> {
+    /// Set the frequency band. This moves the radio to the first channel of
+    /// the band if its channel is not in it. Like the other settings this
+    /// takes effect on the next config_commit.
+    pub fn set_band(&self, band: Band) {
+        if band != self.part().band.get() {
+            self.part().band.set(band);
+            self.phy_changed();
+        }
+    }
+
+    /// Set the modulation, which takes effect on the next config_commit.
+    pub fn set_modulation(&self, modulation: Modulation) {
+        if modulation != self.part().modulation.get() {
+            self.part().modulation.set(modulation);
+            self.phy_changed();
+        }
+    }
+
+    pub fn get_band(&self) -> Band {
+        self.part().band.get()
+    }
+
+    pub fn get_modulation(&self) -> Modulation {
+        self.part().modulation.get()
+    }
+}
@@ -0,0 +1,21 @@
+//! Support for the RF212 capsule
+//!
+//! The registers and flags the RF212 shares with the other AT86RF2xx radios
+//! are in `at86rf2xx_const`. Register 0x16, TRX_RPC on the RF233, is
+//! RF_CTRL_0 on the RF212.
+
+use at86rf2xx_const::*;
+
+// These are particular flags of different registers.
+pub const TRX_CTRL_1_PA_EXT_EN: u8 = 1 << 7;
+pub const PHY_TX_PWR_GC_PA: u8 = 3 << 5;
+pub const PHY_TX_PWR_TX_PWR_MASK: u8 = 0x1F;
+pub const TRX_CTRL_2_OQPSK_SCRAM_EN: u8 = 1 << 5;
+pub const TRX_CTRL_2_BPSK_OQPSK: u8 = 1 << 3;
+pub const TRX_CTRL_2_SUB_MODE: u8 = 1 << 2;
+pub const RF_CTRL_0_GC_TX_OFFS_1DB: u8 = 2;
+pub const RF_CTRL_0_GC_TX_OFFS_2DB: u8 = 3;
+
+// Flag combinations that are used in initialization.
+pub const TRX_CTRL_1: u8 =
+    (TRX_CTRL_1_PA_EXT_EN | TRX_CTRL_1_SPI_CMD_TRX_STATUS | TRX_CTRL_1_AUTO_CRC);
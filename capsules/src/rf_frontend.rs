@@ -0,0 +1,101 @@
+//! An RF frontend controlled with GPIO pins.
+//!
+//! `GpioFrontend` drives the enable pins of an external PA and LNA, which
+//! are active high, and an antenna switch with one select pin, low for
+//! antenna 0 and high for antenna 1. A board leaves out the pins its
+//! frontend does not have, and passes the frontend to its radio driver,
+//! which switches it around each transmission and reception.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let frontend = static_init!(
+//!     capsules::rf_frontend::GpioFrontend<'static>,
+//!     capsules::rf_frontend::GpioFrontend::new(
+//!         Some(&nrf5x::gpio::PORT[17]), // PA enable
+//!         Some(&nrf5x::gpio::PORT[19]), // LNA enable
+//!         Some(&nrf5x::gpio::PORT[20]), // antenna select
+//!     )
+//! );
+//! nrf52::radio::RADIO.set_frontend(frontend);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::rf_frontend::RfFrontend;
+use kernel::ReturnCode;
+
+pub struct GpioFrontend<'a> {
+    pa: Option<&'a gpio::Pin>,
+    lna: Option<&'a gpio::Pin>,
+    antenna_select: Option<&'a gpio::Pin>,
+    antenna: Cell<usize>,
+}
+
+impl<'a> GpioFrontend<'a> {
+    /// Make the pins outputs, with the PA and LNA disabled and antenna 0
+    /// selected.
+    pub fn new(
+        pa: Option<&'a gpio::Pin>,
+        lna: Option<&'a gpio::Pin>,
+        antenna_select: Option<&'a gpio::Pin>,
+    ) -> GpioFrontend<'a> {
+        for pin in [pa, lna, antenna_select].iter() {
+            pin.map(|pin| {
+                pin.make_output();
+                pin.clear();
+            });
+        }
+        GpioFrontend {
+            pa: pa,
+            lna: lna,
+            antenna_select: antenna_select,
+            antenna: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> RfFrontend for GpioFrontend<'a> {
+    fn transmit(&self) {
+        self.lna.map(|pin| pin.clear());
+        self.pa.map(|pin| pin.set());
+    }
+
+    fn receive(&self) {
+        self.pa.map(|pin| pin.clear());
+        self.lna.map(|pin| pin.set());
+    }
+
+    fn sleep(&self) {
+        self.pa.map(|pin| pin.clear());
+        self.lna.map(|pin| pin.clear());
+    }
+
+    fn antennas(&self) -> usize {
+        if self.antenna_select.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn select_antenna(&self, antenna: usize) -> ReturnCode {
+        if antenna >= self.antennas() {
+            return ReturnCode::EINVAL;
+        }
+        self.antenna_select.map(|pin| {
+            if antenna == 0 {
+                pin.clear();
+            } else {
+                pin.set();
+            }
+        });
+        self.antenna.set(antenna);
+        ReturnCode::SUCCESS
+    }
+
+    fn antenna(&self) -> usize {
+        self.antenna.get()
+    }
+}
@@ -4,6 +4,15 @@
 //! randomness. A single command starts the RNG, the callback is called when the
 //! requested amount of randomness is received, or the buffer is filled.
 //!
+//! Randomness is not handed to applications until the source is seeded: the
+//! first `SEED_BITS` bits worth of entropy it produces after boot are
+//! discarded, and requests made before then wait until it has. This keeps
+//! applications that generate keys on first boot from receiving output from a
+//! generator that has not yet warmed up. A board whose source produces less
+//! than one bit of entropy per bit of output can say so with
+//! `set_entropy_per_word()`, and can call `seed()` to start seeding at boot
+//! rather than at the first request.
+//!
 //! Usage
 //! -----
 //!
@@ -12,15 +21,21 @@
 //!         capsules::rng::SimpleRng<'static, sam4l::trng::Trng>,
 //!         capsules::rng::SimpleRng::new(&sam4l::trng::TRNG, kernel::Grant::create()));
 //! sam4l::trng::TRNG.set_client(rng);
+//! rng.seed();
 //! ```
 
+use core::mem;
 use core::cell::Cell;
+use core::cmp;
 use kernel::hil::rng;
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x40001;
 
+/// The bits of entropy the source must produce before its output is used.
+pub const SEED_BITS: usize = 256;
+
 pub struct App {
     callback: Option<Callback>,
     buffer: Option<AppSlice<Shared, u8>>,
@@ -43,6 +58,10 @@ pub struct SimpleRng<'a, RNG: rng::RNG + 'a> {
     rng: &'a RNG,
     apps: Grant<App>,
     getting_randomness: Cell<bool>,
+    /// The estimated bits of entropy in each 32-bit word from the source.
+    entropy_per_word: Cell<usize>,
+    /// The estimated bits of entropy the source has produced, saturating.
+    entropy_bits: Cell<usize>,
 }
 
 impl<'a, RNG: rng::RNG> SimpleRng<'a, RNG> {
@@ -51,12 +70,50 @@ impl<'a, RNG: rng::RNG> SimpleRng<'a, RNG> {
             rng: rng,
             apps: grant,
             getting_randomness: Cell::new(false),
+            entropy_per_word: Cell::new(32),
+            entropy_bits: Cell::new(0),
+        }
+    }
+
+    /// Set the estimated bits of entropy in each 32-bit word the source
+    /// produces, 32 by default. Seeding takes longer for lower estimates.
+    pub fn set_entropy_per_word(&self, bits: usize) {
+        self.entropy_per_word.set(cmp::max(1, cmp::min(bits, 32)));
+    }
+
+    /// Start seeding now rather than when an application first asks for
+    /// randomness.
+    pub fn seed(&self) {
+        if !self.is_seeded() && !self.getting_randomness.get() {
+            self.getting_randomness.set(true);
+            self.rng.get();
         }
     }
+
+    pub fn is_seeded(&self) -> bool {
+        self.entropy_bits.get() >= SEED_BITS
+    }
+
+    /// Count `words` words from the source towards the entropy estimate.
+    fn add_entropy(&self, words: usize) {
+        self.entropy_bits.set(
+            self.entropy_bits
+                .get()
+                .saturating_add(words.saturating_mul(self.entropy_per_word.get())),
+        );
+    }
 }
 
 impl<'a, RNG: rng::RNG> rng::Client for SimpleRng<'a, RNG> {
     fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        // Discard output until the source is seeded.
+        while !self.is_seeded() {
+            match randomness.next() {
+                Some(_) => self.add_entropy(1),
+                None => return rng::Continue::More,
+            }
+        }
+
         let mut done = true;
         for cntr in self.apps.iter() {
             cntr.enter(|app, _| {
@@ -90,6 +147,7 @@ impl<'a, RNG: rng::RNG> rng::Client for SimpleRng<'a, RNG> {
                             {
                                 // 4. For each word of randomness input, update
                                 //    the remaining and idx and add to buffer.
+                                self.add_entropy(1);
                                 for (i, b) in outs.iter_mut().enumerate() {
                                     *b = ((inp >> i * 8) & 0xff) as u8;
                                     app.remaining -= 1;
@@ -154,21 +212,25 @@ impl<'a, RNG: rng::RNG> Driver for SimpleRng<'a, RNG> {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 => self
                 .apps
-                .enter(app_id, |app, _| {
-                    app.callback = callback;
-                    ReturnCode::SUCCESS
-                })
-                .unwrap_or_else(|err| err.into()),
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Ask for `data` random bytes. If the source is not yet seeded,
+    ///        the request waits until it is.
+    /// - `2`: Get the estimated bits of entropy the source has produced since
+    ///        boot. The source is seeded once this reaches `SEED_BITS`.
     fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
             0 => /* Check if exists */ ReturnCode::SUCCESS,
@@ -193,6 +255,11 @@ impl<'a, RNG: rng::RNG> Driver for SimpleRng<'a, RNG> {
                     })
                     .unwrap_or_else(|err| err.into())
             }
+
+            // Estimated entropy produced so far.
+            2 => ReturnCode::SuccessWithValue {
+                value: self.entropy_bits.get(),
+            },
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -26,7 +26,8 @@
 //! sam4l::gpio::PA[17].set_client(sdcard);
 //!
 //! let sdcard_driver = static_init!(
-//!     capsules::sdcard::SDCardDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::sdcard::SDCardDriver<'static,
+//!         capsules::sdcard::SDCard<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
 //!     capsules::sdcard::SDCardDriver::new(sdcard, &mut capsules::sdcard::KERNEL_BUFFER));
 //! sdcard.set_client(sdcard_driver);
 //! ```
@@ -39,6 +40,7 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::mem;
 use kernel::common::cells::{MapCell, TakeCell};
 use kernel::hil;
 use kernel::hil::time::Frequency;
@@ -156,7 +158,7 @@ enum AlarmState {
 
 /// Error codes returned if an SD card transaction fails
 #[derive(Clone, Copy, Debug, PartialEq)]
-enum ErrorCode {
+pub enum ErrorCode {
     CardStateChanged = -1,
     InitializationFailure = -2,
     ReadFailure = -3,
@@ -188,6 +190,18 @@ pub trait SDCardClient {
     fn error(&self, error: u32);
 }
 
+/// Block access to an SD card, independent of the bus used to reach it.
+///
+/// This is implemented by the SPI mode `SDCard` here and by
+/// `sdio_sdcard::SdioSDCard` for native SD bus hosts, so that `SDCardDriver`
+/// and other block users can sit on top of either.
+pub trait SDCardDevice {
+    fn is_installed(&self) -> bool;
+    fn initialize(&self) -> ReturnCode;
+    fn read_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode;
+    fn write_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode;
+}
+
 /// Functions for initializing and accessing an SD card
 impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
     /// Create a new SD card interface
@@ -1328,6 +1342,24 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
     }
 }
 
+impl<'a, A: hil::time::Alarm + 'a> SDCardDevice for SDCard<'a, A> {
+    fn is_installed(&self) -> bool {
+        SDCard::is_installed(self)
+    }
+
+    fn initialize(&self) -> ReturnCode {
+        SDCard::initialize(self)
+    }
+
+    fn read_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode {
+        SDCard::read_blocks(self, buffer, sector, count)
+    }
+
+    fn write_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode {
+        SDCard::write_blocks(self, buffer, sector, count)
+    }
+}
+
 /// Handle callbacks from the SPI peripheral
 impl<'a, A: hil::time::Alarm + 'a> hil::spi::SpiMasterClient for SDCard<'a, A> {
     fn read_write_done(
@@ -1384,8 +1416,8 @@ impl<'a, A: hil::time::Alarm + 'a> hil::gpio::Client for SDCard<'a, A> {
 /// This is used if the SDCard is going to be attached directly to userspace
 /// syscalls. SDCardDriver can be ignored if another capsule is going to build
 /// off of the SDCard instead
-pub struct SDCardDriver<'a, A: hil::time::Alarm + 'a> {
-    sdcard: &'a SDCard<'a, A>,
+pub struct SDCardDriver<'a, D: SDCardDevice + 'a> {
+    sdcard: &'a D,
     app: MapCell<App>,
     kernel_buf: TakeCell<'static, [u8]>,
 }
@@ -1411,16 +1443,13 @@ impl Default for App {
 pub static mut KERNEL_BUFFER: [u8; 512] = [0; 512];
 
 /// Functions for SDCardDriver
-impl<'a, A: hil::time::Alarm + 'a> SDCardDriver<'a, A> {
+impl<'a, D: SDCardDevice + 'a> SDCardDriver<'a, D> {
     /// Create new SD card userland interface
     ///
-    /// sdcard - SDCard interface to provide application access to
+    /// sdcard - SD card interface to provide application access to
     /// kernel_buf - buffer used to hold SD card blocks, must be at least 512
     ///     bytes in length
-    pub fn new(
-        sdcard: &'a SDCard<'a, A>,
-        kernel_buf: &'static mut [u8; 512],
-    ) -> SDCardDriver<'a, A> {
+    pub fn new(sdcard: &'a D, kernel_buf: &'static mut [u8; 512]) -> SDCardDriver<'a, D> {
         // return new SDCardDriver
         SDCardDriver {
             sdcard: sdcard,
@@ -1431,7 +1460,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCardDriver<'a, A> {
 }
 
 /// Handle callbacks from SDCard
-impl<'a, A: hil::time::Alarm + 'a> SDCardClient for SDCardDriver<'a, A> {
+impl<'a, D: SDCardDevice + 'a> SDCardClient for SDCardDriver<'a, D> {
     fn card_detection_changed(&self, installed: bool) {
         self.app.map(|app| {
             app.callback.map(|mut cb| {
@@ -1495,7 +1524,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCardClient for SDCardDriver<'a, A> {
 }
 
 /// Connections to userspace syscalls
-impl<'a, A: hil::time::Alarm + 'a> Driver for SDCardDriver<'a, A> {
+impl<'a, D: SDCardDevice + 'a> Driver for SDCardDriver<'a, D> {
     fn allow(
         &self,
         _appid: AppId,
@@ -1524,15 +1553,15 @@ impl<'a, A: hil::time::Alarm + 'a> Driver for SDCardDriver<'a, A> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // Set callback
-            0 => {
-                self.app.map(|app| app.callback = callback);
-                ReturnCode::SUCCESS
-            }
+            0 => Ok(self
+                .app
+                .map(|app| mem::replace(&mut app.callback, callback))
+                .unwrap_or(None)),
 
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
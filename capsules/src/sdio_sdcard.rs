@@ -0,0 +1,481 @@
+//! Driver for SD cards attached to a native SD bus host controller.
+//!
+//! This runs the SD card protocol in SD bus mode on top of
+//! `hil::sdio::SdioHost`: card identification at 400 kHz, relative address
+//! assignment, CSD parsing to find the card capacity, and finally switching
+//! the card and host to a 4-bit bus at 25 MHz. Block reads and writes then
+//! use the native data lines rather than SPI, so throughput is limited by the
+//! card rather than by the SPI clock.
+//!
+//! `SdioSDCard` implements `sdcard::SDCardDevice` and reports through
+//! `sdcard::SDCardClient`, so it plugs into `sdcard::SDCardDriver` (and any
+//! other block user of those traits) the same way the SPI mode driver does.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sdcard = static_init!(
+//!     capsules::sdio_sdcard::SdioSDCard<'static, chip::sdmmc::Sdmmc>,
+//!     capsules::sdio_sdcard::SdioSDCard::new(&chip::sdmmc::SDMMC));
+//! chip::sdmmc::SDMMC.set_client(sdcard);
+//!
+//! let sdcard_driver = static_init!(
+//!     capsules::sdcard::SDCardDriver<'static,
+//!         capsules::sdio_sdcard::SdioSDCard<'static, chip::sdmmc::Sdmmc>>,
+//!     capsules::sdcard::SDCardDriver::new(sdcard, &mut capsules::sdcard::KERNEL_BUFFER));
+//! sdcard.set_client(sdcard_driver);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::sdio::{self, BusWidth, ResponseType};
+use kernel::ReturnCode;
+use sdcard::{ErrorCode, SDCardClient, SDCardDevice};
+
+/// Identification mode clock.
+const IDENTIFICATION_CLOCK_HZ: u32 = 400_000;
+/// Default speed data transfer clock.
+const TRANSFER_CLOCK_HZ: u32 = 25_000_000;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Number of ACMD41 attempts before giving up on a card that stays busy.
+const MAX_INIT_ATTEMPTS: u16 = 1000;
+
+/// Argument for CMD8: 2.7-3.6V supply and the 0xAA check pattern.
+const CHECK_VOLTAGE_ARG: u32 = 0x1AA;
+/// ACMD41 argument: host supports high capacity cards, 3.2-3.4V window.
+const HCS_OCR_ARG: u32 = 0x4030_0000;
+const OCR_ARG: u32 = 0x0030_0000;
+const OCR_POWER_UP_DONE: u32 = 1 << 31;
+const OCR_CCS: u32 = 1 << 30;
+
+const CMD0_GO_IDLE: u8 = 0;
+const CMD2_ALL_SEND_CID: u8 = 2;
+const CMD3_SEND_RELATIVE_ADDR: u8 = 3;
+const CMD7_SELECT_CARD: u8 = 7;
+const CMD8_SEND_IF_COND: u8 = 8;
+const CMD9_SEND_CSD: u8 = 9;
+const CMD12_STOP_TRANSMISSION: u8 = 12;
+const CMD16_SET_BLOCKLEN: u8 = 16;
+const CMD17_READ_SINGLE: u8 = 17;
+const CMD18_READ_MULTIPLE: u8 = 18;
+const CMD24_WRITE_SINGLE: u8 = 24;
+const CMD25_WRITE_MULTIPLE: u8 = 25;
+const CMD55_APP_CMD: u8 = 55;
+const ACMD6_SET_BUS_WIDTH: u8 = 6;
+const ACMD41_SD_SEND_OP_COND: u8 = 41;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+
+    InitReset,
+    InitCheckVoltage,
+    InitAppCmd { hcs: bool },
+    InitOpCond { hcs: bool },
+    InitSendCid,
+    InitSendRca,
+    InitSendCsd,
+    InitSelect,
+    InitBusWidthAppCmd,
+    InitBusWidth,
+    InitSetBlockLen,
+
+    Reading { count: u32 },
+    Writing { count: u32 },
+    StopRead { count: u32 },
+    StopWrite,
+}
+
+pub struct SdioSDCard<'a, S: sdio::SdioHost + 'a> {
+    host: &'a S,
+    state: Cell<State>,
+    is_initialized: Cell<bool>,
+    block_addressed: Cell<bool>,
+    rca: Cell<u32>,
+    total_size: Cell<u64>,
+    init_attempts: Cell<u16>,
+    client: Cell<Option<&'static SDCardClient>>,
+    client_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, S: sdio::SdioHost> SdioSDCard<'a, S> {
+    pub fn new(host: &'a S) -> SdioSDCard<'a, S> {
+        SdioSDCard {
+            host: host,
+            state: Cell::new(State::Idle),
+            is_initialized: Cell::new(false),
+            block_addressed: Cell::new(false),
+            rca: Cell::new(0),
+            total_size: Cell::new(0),
+            init_attempts: Cell::new(0),
+            client: Cell::new(None),
+            client_buffer: TakeCell::empty(),
+        }
+    }
+
+    pub fn set_client<C: SDCardClient>(&self, client: &'static C) {
+        self.client.set(Some(client));
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized.get()
+    }
+
+    /// Send a command and move to `next` while waiting for its response.
+    fn command(&self, index: u8, argument: u32, response: ResponseType, next: State) {
+        self.state.set(next);
+        let rc = self.host.send_command(index, argument, response);
+        if rc != ReturnCode::SUCCESS {
+            self.fail(ErrorCode::InitializationFailure);
+        }
+    }
+
+    fn fail(&self, error: ErrorCode) {
+        self.state.set(State::Idle);
+        self.client.get().map(|client| client.error(error as u32));
+    }
+
+    /// Convert a block number to the address expected by the card.
+    fn card_address(&self, sector: u32) -> u32 {
+        if self.block_addressed.get() {
+            sector
+        } else {
+            sector * BLOCK_SIZE as u32
+        }
+    }
+
+    fn start_transfer(
+        &self,
+        buffer: &'static mut [u8],
+        sector: u32,
+        count: u32,
+        write: bool,
+    ) -> ReturnCode {
+        if !self.host.card_detected() {
+            return ReturnCode::EUNINSTALLED;
+        }
+        if !self.is_initialized.get() {
+            return ReturnCode::ERESERVE;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if count == 0 || buffer.len() < count as usize * BLOCK_SIZE {
+            return ReturnCode::ESIZE;
+        }
+
+        let address = self.card_address(sector);
+        let (rc, buffer) = if write {
+            let index = if count == 1 {
+                CMD24_WRITE_SINGLE
+            } else {
+                CMD25_WRITE_MULTIPLE
+            };
+            self.state.set(State::Writing { count: count });
+            self.host
+                .write_blocks(index, address, buffer, BLOCK_SIZE, count as usize)
+        } else {
+            let index = if count == 1 {
+                CMD17_READ_SINGLE
+            } else {
+                CMD18_READ_MULTIPLE
+            };
+            self.state.set(State::Reading { count: count });
+            self.host
+                .read_blocks(index, address, buffer, BLOCK_SIZE, count as usize)
+        };
+        if rc != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+            buffer.map(|buffer| self.client_buffer.replace(buffer));
+        }
+        rc
+    }
+}
+
+/// Extract bits `hi..=lo` from a 128-bit register stored most significant
+/// word first.
+fn register_bits(reg: &[u32; 4], hi: usize, lo: usize) -> u32 {
+    let mut value = 0;
+    for bit in (lo..hi + 1).rev() {
+        let word = reg[3 - bit / 32];
+        value = (value << 1) | ((word >> (bit % 32)) & 0x1);
+    }
+    value
+}
+
+/// Compute the card capacity in bytes from its CSD register.
+fn csd_capacity(csd: &[u32; 4]) -> u64 {
+    match register_bits(csd, 127, 126) {
+        // CSD version 1.0: standard capacity cards.
+        0 => {
+            let c_size = register_bits(csd, 73, 62) as u64;
+            let c_size_mult = register_bits(csd, 49, 47) as u64;
+            let read_bl_len = register_bits(csd, 83, 80) as u64;
+            (c_size + 1) << (c_size_mult + 2 + read_bl_len)
+        }
+        // CSD version 2.0: high and extended capacity cards.
+        _ => {
+            let c_size = register_bits(csd, 69, 48) as u64;
+            (c_size + 1) * 512 * 1024
+        }
+    }
+}
+
+impl<'a, S: sdio::SdioHost> SDCardDevice for SdioSDCard<'a, S> {
+    fn is_installed(&self) -> bool {
+        self.host.card_detected()
+    }
+
+    fn initialize(&self) -> ReturnCode {
+        self.is_initialized.set(false);
+        if !self.host.card_detected() {
+            return ReturnCode::EUNINSTALLED;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        self.init_attempts.set(0);
+        self.host.set_bus_width(BusWidth::One);
+        self.host.set_clock(IDENTIFICATION_CLOCK_HZ);
+        self.state.set(State::InitReset);
+        self.host.send_command(CMD0_GO_IDLE, 0, ResponseType::None)
+    }
+
+    fn read_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode {
+        self.start_transfer(buffer, sector, count, false)
+    }
+
+    fn write_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode {
+        self.start_transfer(buffer, sector, count, true)
+    }
+}
+
+impl<'a, S: sdio::SdioHost> sdio::Client for SdioSDCard<'a, S> {
+    fn command_complete(&self, result: ReturnCode, response: [u32; 4]) {
+        match self.state.get() {
+            State::InitReset => {
+                self.command(
+                    CMD8_SEND_IF_COND,
+                    CHECK_VOLTAGE_ARG,
+                    ResponseType::R7,
+                    State::InitCheckVoltage,
+                );
+            }
+            State::InitCheckVoltage => {
+                // Version 1 cards do not answer CMD8 and must not be asked
+                // about high capacity support.
+                let hcs = result == ReturnCode::SUCCESS;
+                if hcs && response[0] & 0xfff != CHECK_VOLTAGE_ARG {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.command(
+                    CMD55_APP_CMD,
+                    0,
+                    ResponseType::R1,
+                    State::InitAppCmd { hcs: hcs },
+                );
+            }
+            State::InitAppCmd { hcs } => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                let arg = if hcs { HCS_OCR_ARG } else { OCR_ARG };
+                self.command(
+                    ACMD41_SD_SEND_OP_COND,
+                    arg,
+                    ResponseType::R3,
+                    State::InitOpCond { hcs: hcs },
+                );
+            }
+            State::InitOpCond { hcs } => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                } else if response[0] & OCR_POWER_UP_DONE == 0 {
+                    // Card is still powering up, ask again.
+                    let attempts = self.init_attempts.get() + 1;
+                    self.init_attempts.set(attempts);
+                    if attempts >= MAX_INIT_ATTEMPTS {
+                        self.fail(ErrorCode::TimeoutFailure);
+                    } else {
+                        self.command(
+                            CMD55_APP_CMD,
+                            0,
+                            ResponseType::R1,
+                            State::InitAppCmd { hcs: hcs },
+                        );
+                    }
+                } else {
+                    self.block_addressed.set(response[0] & OCR_CCS != 0);
+                    self.command(CMD2_ALL_SEND_CID, 0, ResponseType::R2, State::InitSendCid);
+                }
+            }
+            State::InitSendCid => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.command(
+                    CMD3_SEND_RELATIVE_ADDR,
+                    0,
+                    ResponseType::R6,
+                    State::InitSendRca,
+                );
+            }
+            State::InitSendRca => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.rca.set(response[0] & 0xffff_0000);
+                self.command(
+                    CMD9_SEND_CSD,
+                    self.rca.get(),
+                    ResponseType::R2,
+                    State::InitSendCsd,
+                );
+            }
+            State::InitSendCsd => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.total_size.set(csd_capacity(&response));
+                self.command(
+                    CMD7_SELECT_CARD,
+                    self.rca.get(),
+                    ResponseType::R1b,
+                    State::InitSelect,
+                );
+            }
+            State::InitSelect => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.command(
+                    CMD55_APP_CMD,
+                    self.rca.get(),
+                    ResponseType::R1,
+                    State::InitBusWidthAppCmd,
+                );
+            }
+            State::InitBusWidthAppCmd => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                // Argument 0b10 selects the 4-bit bus.
+                self.command(
+                    ACMD6_SET_BUS_WIDTH,
+                    0b10,
+                    ResponseType::R1,
+                    State::InitBusWidth,
+                );
+            }
+            State::InitBusWidth => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.host.set_bus_width(BusWidth::Four);
+                self.host.set_clock(TRANSFER_CLOCK_HZ);
+                // High capacity cards have a fixed 512 byte block length.
+                if self.block_addressed.get() {
+                    self.command_complete(ReturnCode::SUCCESS, [0; 4]);
+                    return;
+                }
+                self.command(
+                    CMD16_SET_BLOCKLEN,
+                    BLOCK_SIZE as u32,
+                    ResponseType::R1,
+                    State::InitSetBlockLen,
+                );
+            }
+            State::InitSetBlockLen => {
+                if result != ReturnCode::SUCCESS {
+                    self.fail(ErrorCode::InitializationFailure);
+                    return;
+                }
+                self.state.set(State::Idle);
+                self.is_initialized.set(true);
+                self.client.get().map(|client| {
+                    client.init_done(BLOCK_SIZE as u32, self.total_size.get());
+                });
+            }
+            State::StopRead { count } => {
+                self.state.set(State::Idle);
+                self.client_buffer.take().map(|buffer| {
+                    self.client.get().map(move |client| {
+                        if result == ReturnCode::SUCCESS {
+                            client.read_done(buffer, count as usize * BLOCK_SIZE);
+                        } else {
+                            client.error(ErrorCode::ReadFailure as u32);
+                        }
+                    });
+                });
+            }
+            State::StopWrite => {
+                self.state.set(State::Idle);
+                self.client_buffer.take().map(|buffer| {
+                    self.client.get().map(move |client| {
+                        if result == ReturnCode::SUCCESS {
+                            client.write_done(buffer);
+                        } else {
+                            client.error(ErrorCode::WriteFailure as u32);
+                        }
+                    });
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn data_complete(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        match self.state.get() {
+            State::Reading { count } => {
+                if result == ReturnCode::SUCCESS && count > 1 {
+                    // Multi block reads stream until told to stop.
+                    self.client_buffer.replace(buffer);
+                    self.state.set(State::StopRead { count: count });
+                    self.host
+                        .send_command(CMD12_STOP_TRANSMISSION, 0, ResponseType::R1b);
+                    return;
+                }
+                self.state.set(State::Idle);
+                self.client.get().map(move |client| {
+                    if result == ReturnCode::SUCCESS {
+                        client.read_done(buffer, BLOCK_SIZE);
+                    } else {
+                        client.error(ErrorCode::ReadFailure as u32);
+                    }
+                });
+            }
+            State::Writing { count } => {
+                if result == ReturnCode::SUCCESS && count > 1 {
+                    self.client_buffer.replace(buffer);
+                    self.state.set(State::StopWrite);
+                    self.host
+                        .send_command(CMD12_STOP_TRANSMISSION, 0, ResponseType::R1b);
+                    return;
+                }
+                self.state.set(State::Idle);
+                self.client.get().map(move |client| {
+                    if result == ReturnCode::SUCCESS {
+                        client.write_done(buffer);
+                    } else {
+                        client.error(ErrorCode::WriteFailure as u32);
+                    }
+                });
+            }
+            _ => {
+                self.client_buffer.replace(buffer);
+            }
+        }
+    }
+}
@@ -21,10 +21,8 @@
 //! Notes
 //! -----
 //!
-//! This capsule requires a timer, but the timer is only there to defer the
-//! `transmit_complete` callback until the next scheduler loop. In the future,
-//! if there is support for software interrupts or deferred calls in capsules,
-//! this timer should be removed.
+//! The `transmit_complete` callback is deferred until the next scheduler loop
+//! with a dynamic deferred call, so the capsule must be registered for one.
 //!
 //! Todo
 //! ----
@@ -37,24 +35,13 @@
 //! ```
 //! pub struct Platform {
 //!     // Other fields omitted for clarity
-//!     console: &'static capsules::console::Console<
-//!         'static,
-//!         capsules::segger_rtt::SeggerRtt<
-//!             'static,
-//!             capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
-//!         >,
-//!     >,
+//!     console: &'static capsules::console::Console<'static, capsules::segger_rtt::SeggerRtt>,
 //! }
 //! ```
 //!
 //! In `reset_handler()`:
 //!
 //! ```
-//! let virtual_alarm_rtt = static_init!(
-//!     capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
-//!     capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
-//! );
-//!
 //! let rtt_memory = static_init!(
 //!     capsules::segger_rtt::SeggerRttMemory,
 //!     capsules::segger_rtt::SeggerRttMemory::new(b"Terminal\0",
@@ -64,20 +51,16 @@
 //! );
 //!
 //! let rtt = static_init!(
-//!     capsules::segger_rtt::SeggerRtt<VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
-//!     capsules::segger_rtt::SeggerRtt::new(virtual_alarm_rtt, rtt_memory,
+//!     capsules::segger_rtt::SeggerRtt,
+//!     capsules::segger_rtt::SeggerRtt::new(rtt_memory,
 //!         &mut capsules::segger_rtt::UP_BUFFER,
 //!         &mut capsules::segger_rtt::DOWN_BUFFER)
 //! );
-//! virtual_alarm_rtt.set_client(rtt);
+//! let handle = kernel::common::dynamic_deferred_call::register(rtt).unwrap();
+//! rtt.set_deferred_call_handle(handle);
 //!
 //! let console = static_init!(
-//!     capsules::console::Console<
-//!         'static,
-//!         capsules::segger_rtt::SeggerRtt<
-//!             capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
-//!         >,
-//!     >,
+//!     capsules::console::Console<'static, capsules::segger_rtt::SeggerRtt>,
 //!     capsules::console::Console::new(
 //!         rtt,
 //!         0, // Baud rate is meaningless with RTT
@@ -91,8 +74,8 @@
 //! ```
 
 use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{DeferredCallHandle, DynamicDeferredCallClient};
 use kernel::hil;
-use kernel::hil::time::Frequency;
 
 /// Buffer for transmitting to the host.
 pub static mut UP_BUFFER: [u8; 1024] = [0; 1024];
@@ -154,8 +137,8 @@ impl SeggerRttMemory {
     }
 }
 
-pub struct SeggerRtt<'a, A: hil::time::Alarm + 'a> {
-    alarm: &'a A, // Dummy alarm so we can get a callback.
+pub struct SeggerRtt {
+    handle: OptionalCell<DeferredCallHandle>,
     config: TakeCell<'static, SeggerRttMemory>,
     up_buffer: TakeCell<'static, [u8]>,
     _down_buffer: TakeCell<'static, [u8]>,
@@ -163,15 +146,14 @@ pub struct SeggerRtt<'a, A: hil::time::Alarm + 'a> {
     client_buffer: TakeCell<'static, [u8]>,
 }
 
-impl<'a, A: hil::time::Alarm + 'a> SeggerRtt<'a, A> {
+impl SeggerRtt {
     pub fn new(
-        alarm: &'a A,
         config: &'static mut SeggerRttMemory,
         up_buffer: &'static mut [u8],
         down_buffer: &'static mut [u8],
-    ) -> SeggerRtt<'a, A> {
+    ) -> SeggerRtt {
         SeggerRtt {
-            alarm: alarm,
+            handle: OptionalCell::empty(),
             config: TakeCell::new(config),
             up_buffer: TakeCell::new(up_buffer),
             _down_buffer: TakeCell::new(down_buffer),
@@ -179,9 +161,15 @@ impl<'a, A: hil::time::Alarm + 'a> SeggerRtt<'a, A> {
             client_buffer: TakeCell::empty(),
         }
     }
+
+    /// Set the handle returned when the capsule registered for deferred
+    /// calls.
+    pub fn set_deferred_call_handle(&self, handle: DeferredCallHandle) {
+        self.handle.set(handle);
+    }
 }
 
-impl<'a, A: hil::time::Alarm + 'a> hil::uart::UART for SeggerRtt<'a, A> {
+impl hil::uart::UART for SeggerRtt {
     fn set_client(&self, client: &'static hil::uart::Client) {
         self.client.set(client);
     }
@@ -209,11 +197,8 @@ impl<'a, A: hil::time::Alarm + 'a> hil::uart::UART for SeggerRtt<'a, A> {
         // Save the client buffer so we can pass it back with the callback.
         self.client_buffer.replace(tx_data);
 
-        // Start a short timer so that we get a callback and can issue the
-        // callback to the client.
-        let interval = (100 as u32) * <A::Frequency>::frequency() / 1000000;
-        let tics = self.alarm.now().wrapping_add(interval);
-        self.alarm.set_alarm(tics);
+        // Issue the callback to the client from the main loop.
+        self.handle.map(|handle| handle.set());
     }
 
     fn receive(&self, _rx_buf: &'static mut [u8], _rx_len: usize) {}
@@ -221,8 +206,8 @@ impl<'a, A: hil::time::Alarm + 'a> hil::uart::UART for SeggerRtt<'a, A> {
     fn abort_receive(&self) {}
 }
 
-impl<'a, A: hil::time::Alarm + 'a> hil::time::Client for SeggerRtt<'a, A> {
-    fn fired(&self) {
+impl DynamicDeferredCallClient for SeggerRtt {
+    fn call(&self, _handle: DeferredCallHandle) {
         self.client.map(|client| {
             self.client_buffer.take().map(|buffer| {
                 client.transmit_complete(buffer, hil::uart::Error::CommandComplete);
@@ -0,0 +1,170 @@
+//! Detects which I2C sensors are fitted to the board.
+//!
+//! Different hardware revisions of a board may populate different sensors.
+//! So that one kernel image can run on all of them, the board can list the
+//! sensors it may have, and `SensorProbe` checks each one once at boot by
+//! reading an identification register, such as `WHO_AM_I`, and comparing it
+//! with the value the chip is known to return. Sensors without such a
+//! register can be probed with a mask of zero, in which case any device that
+//! acknowledges its address counts as present.
+//!
+//! The board uses `is_present()` to only expose a sensor's driver if the
+//! sensor was found, and can give the `SensorProbe` to the kernel info driver
+//! so applications can read the inventory. Probing starts during board
+//! initialization and takes a few milliseconds. Until a sensor has been
+//! probed it is treated as present, so drivers are not hidden from
+//! applications that look for them as soon as they start.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static PROBES: [capsules::sensor_probe::Probe; 2] =
+//!     [capsules::si7021::PROBE, capsules::fxos8700cq::PROBE];
+//!
+//! let probe_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0));
+//! let sensor_probe = static_init!(
+//!     capsules::sensor_probe::SensorProbe<'static>,
+//!     capsules::sensor_probe::SensorProbe::new(
+//!         probe_i2c,
+//!         &PROBES,
+//!         &mut capsules::sensor_probe::BUFFER
+//!     )
+//! );
+//! probe_i2c.set_client(sensor_probe);
+//! kernel_info.set_inventory(sensor_probe);
+//! sensor_probe.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::i2c::{self, Error};
+use kernel_info::Inventory;
+use virtual_i2c::I2CDevice;
+
+/// The most sensors that can be probed, one per bit of the inventory.
+pub const MAX_PROBES: usize = 31;
+
+pub static mut BUFFER: [u8; 1] = [0; 1];
+
+/// How to recognize one sensor.
+#[derive(Copy, Clone)]
+pub struct Probe {
+    /// The sensor's I2C address.
+    pub address: u8,
+    /// The identification register to read.
+    pub register: u8,
+    /// The bits of the register that identify the chip.
+    pub mask: u8,
+    /// The value of those bits for this chip.
+    pub expected: u8,
+}
+
+/// Told when all sensors have been probed.
+pub trait ProbeClient {
+    /// `inventory` has bit `i` set if sensor `i` was found.
+    fn probe_done(&self, inventory: usize);
+}
+
+pub struct SensorProbe<'a> {
+    i2c: &'a I2CDevice<'a>,
+    probes: &'a [Probe],
+    /// Bit `i` is set once sensor `i` has been probed.
+    probed: Cell<u32>,
+    /// Bit `i` is set if sensor `i` was found.
+    present: Cell<u32>,
+    index: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    client: Cell<Option<&'a ProbeClient>>,
+}
+
+impl<'a> SensorProbe<'a> {
+    /// Look for each of `probes`. Only the first `MAX_PROBES` are used.
+    pub fn new(
+        i2c: &'a I2CDevice<'a>,
+        probes: &'a [Probe],
+        buffer: &'static mut [u8],
+    ) -> SensorProbe<'a> {
+        let len = if probes.len() > MAX_PROBES {
+            MAX_PROBES
+        } else {
+            probes.len()
+        };
+        SensorProbe {
+            i2c: i2c,
+            probes: &probes[..len],
+            probed: Cell::new(0),
+            present: Cell::new(0),
+            index: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a ProbeClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Start probing. Must be called once during board initialization.
+    pub fn start(&self) {
+        if self.probes.is_empty() {
+            return;
+        }
+        i2c::I2CDevice::enable(self.i2c);
+        self.index.set(0);
+        self.probe();
+    }
+
+    /// Whether sensor `index` was found, or has not been probed yet.
+    pub fn is_present(&self, index: usize) -> bool {
+        if index >= self.probes.len() {
+            return false;
+        }
+        let bit = 1 << index;
+        self.probed.get() & bit == 0 || self.present.get() & bit != 0
+    }
+
+    fn finished(&self) -> bool {
+        self.probed.get().count_ones() as usize == self.probes.len()
+    }
+
+    fn probe(&self) {
+        let probe = self.probes[self.index.get()];
+        self.buffer.take().map(|buffer| {
+            buffer[0] = probe.register;
+            self.i2c.set_address(probe.address);
+            i2c::I2CDevice::write_read(self.i2c, buffer, 1, 1);
+        });
+    }
+}
+
+impl<'a> i2c::I2CClient for SensorProbe<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        let index = self.index.get();
+        let probe = self.probes[index];
+        if error == Error::CommandComplete && buffer[0] & probe.mask == probe.expected {
+            self.present.set(self.present.get() | (1 << index));
+        }
+        self.probed.set(self.probed.get() | (1 << index));
+        self.buffer.replace(buffer);
+
+        if index + 1 < self.probes.len() {
+            self.index.set(index + 1);
+            self.probe();
+        } else {
+            i2c::I2CDevice::disable(self.i2c);
+            let inventory = self.present.get() as usize;
+            self.client.get().map(|client| client.probe_done(inventory));
+        }
+    }
+}
+
+impl<'a> Inventory for SensorProbe<'a> {
+    fn inventory(&self) -> Option<usize> {
+        if self.finished() {
+            Some(self.present.get() as usize)
+        } else {
+            None
+        }
+    }
+}
@@ -34,12 +34,13 @@
 //! ```
 
 use core::cell::Cell;
+use i2c_transaction::{I2CTransaction, RegOp};
 use kernel;
-use kernel::common::cells::TakeCell;
 use kernel::hil::i2c;
 use kernel::hil::time;
 use kernel::hil::time::Frequency;
 use kernel::ReturnCode;
+use sensor_probe::Probe;
 
 // Buffer to use for I2C messages
 pub static mut BUFFER: [u8; 14] = [0; 14];
@@ -64,26 +65,51 @@ enum Registers {
     ReadFirmwareVersionB = 0xb8,
 }
 
-/// States of the I2C protocol with the LPS331AP.
+/// Detects the SI7021 at its fixed address. It has no identification
+/// register, so any device that answers a read of user register 1 counts.
+pub const PROBE: Probe = Probe {
+    address: 0x40,
+    register: Registers::ReadRHTUserRegister1 as u8,
+    mask: 0,
+    expected: 0,
+};
+
+const READ_ID: &[RegOp] = &[
+    RegOp::Write(&[
+        Registers::ReadElectronicIdByteOneA as u8,
+        Registers::ReadElectronicIdByteOneB as u8,
+    ]),
+    RegOp::Read(8),
+    RegOp::Write(&[
+        Registers::ReadElectronicIdByteTwoA as u8,
+        Registers::ReadElectronicIdByteTwoB as u8,
+    ]),
+    RegOp::Read(6),
+];
+
+const START_TEMPERATURE: &[RegOp] = &[RegOp::Write(&[Registers::MeasTemperatureNoHoldMode as u8])];
+
+const START_HUMIDITY: &[RegOp] = &[RegOp::Write(&[
+    Registers::MeasRelativeHumidityNoHoldMode as u8
+])];
+
+const READ_MEASUREMENT: &[RegOp] = &[RegOp::Read(2)];
+
+/// States of the I2C protocol with the SI7021.
 #[derive(Clone, Copy, PartialEq)]
 enum State {
     Idle,
-    WaitTemp,
-    WaitRh,
 
-    /// States to read the internal ID
-    SelectElectronicId1,
-    ReadElectronicId1,
-    SelectElectronicId2,
-    ReadElectronicId2,
+    /// Reading the internal ID
+    ReadingId,
 
     /// States to take the current measurement
-    TakeTempMeasurementInit,
-    TakeRhMeasurementInit,
-    ReadRhMeasurement,
-    ReadTempMeasurement,
-    GotTempMeasurement,
-    GotRhMeasurement,
+    StartingTemp,
+    WaitTemp,
+    ReadingTemp,
+    StartingRh,
+    WaitRh,
+    ReadingRh,
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -94,143 +120,117 @@ enum OnDeck {
 }
 
 pub struct SI7021<'a, A: time::Alarm + 'a> {
-    i2c: &'a i2c::I2CDevice,
+    transaction: I2CTransaction<'a>,
     alarm: &'a A,
     temp_callback: Cell<Option<&'static kernel::hil::sensors::TemperatureClient>>,
     humidity_callback: Cell<Option<&'static kernel::hil::sensors::HumidityClient>>,
     state: Cell<State>,
     on_deck: Cell<OnDeck>,
-    buffer: TakeCell<'static, [u8]>,
 }
 
 impl<'a, A: time::Alarm + 'a> SI7021<'a, A> {
     pub fn new(i2c: &'a i2c::I2CDevice, alarm: &'a A, buffer: &'static mut [u8]) -> SI7021<'a, A> {
         // setup and return struct
         SI7021 {
-            i2c: i2c,
+            transaction: I2CTransaction::new(i2c, buffer),
             alarm: alarm,
             temp_callback: Cell::new(None),
             humidity_callback: Cell::new(None),
             state: Cell::new(State::Idle),
             on_deck: Cell::new(OnDeck::Nothing),
-            buffer: TakeCell::new(buffer),
         }
     }
 
     pub fn read_id(&self) {
-        self.buffer.take().map(|buffer| {
-            // turn on i2c to send commands
-            self.i2c.enable();
+        if self.state.get() == State::Idle {
+            self.start(READ_ID, State::ReadingId);
+        }
+    }
 
-            buffer[0] = Registers::ReadElectronicIdByteOneA as u8;
-            buffer[1] = Registers::ReadElectronicIdByteOneB as u8;
-            self.i2c.write(buffer, 2);
-            self.state.set(State::SelectElectronicId1);
-        });
+    fn start(&self, ops: &'static [RegOp], state: State) {
+        if self.transaction.start(ops) == ReturnCode::SUCCESS {
+            self.state.set(state);
+        } else {
+            self.state.set(State::Idle);
+        }
     }
 
-    fn init_measurement(&self, buffer: &'static mut [u8]) {
+    fn start_measurement(&self, measurement: OnDeck) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            if self.on_deck.get() != OnDeck::Nothing {
+                return ReturnCode::EBUSY;
+            }
+            self.on_deck.set(measurement);
+            return ReturnCode::SUCCESS;
+        }
+        match measurement {
+            OnDeck::Temperature => self.start(START_TEMPERATURE, State::StartingTemp),
+            OnDeck::Humidity => self.start(START_HUMIDITY, State::StartingRh),
+            OnDeck::Nothing => {}
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn wait_for_measurement(&self, state: State) {
         let interval = (20 as u32) * <A::Frequency>::frequency() / 1000;
 
         let tics = self.alarm.now().wrapping_add(interval);
         self.alarm.set_alarm(tics);
 
         // Now wait for timer to expire
-        self.buffer.replace(buffer);
-        self.i2c.disable();
+        self.state.set(state);
     }
 
-    fn set_idle(&self, buffer: &'static mut [u8]) {
-        self.buffer.replace(buffer);
-        self.i2c.disable();
+    fn measurement_done(&self) {
         self.state.set(State::Idle);
+        let on_deck = self.on_deck.get();
+        self.on_deck.set(OnDeck::Nothing);
+        self.start_measurement(on_deck);
     }
 }
 
 impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
-    fn command_complete(&self, buffer: &'static mut [u8], _error: i2c::Error) {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let (data, error) = match self.transaction.command_complete(buffer, error) {
+            Some(result) => result,
+            None => return,
+        };
+        if error != i2c::Error::CommandComplete {
+            self.state.set(State::Idle);
+            self.on_deck.set(OnDeck::Nothing);
+            return;
+        }
+        let data = data.as_slice();
+
         match self.state.get() {
-            State::SelectElectronicId1 => {
-                self.i2c.read(buffer, 8);
-                self.state.set(State::ReadElectronicId1);
-            }
-            State::ReadElectronicId1 => {
-                buffer[6] = buffer[0];
-                buffer[7] = buffer[1];
-                buffer[8] = buffer[2];
-                buffer[9] = buffer[3];
-                buffer[10] = buffer[4];
-                buffer[11] = buffer[5];
-                buffer[12] = buffer[6];
-                buffer[13] = buffer[7];
-                buffer[0] = Registers::ReadElectronicIdByteTwoA as u8;
-                buffer[1] = Registers::ReadElectronicIdByteTwoB as u8;
-                self.i2c.write(buffer, 2);
-                self.state.set(State::SelectElectronicId2);
-            }
-            State::SelectElectronicId2 => {
-                self.i2c.read(buffer, 6);
-                self.state.set(State::ReadElectronicId2);
-            }
-            State::ReadElectronicId2 => {
-                self.set_idle(buffer);
-            }
-            State::TakeTempMeasurementInit => {
-                self.init_measurement(buffer);
-                self.state.set(State::WaitTemp);
-            }
-            State::TakeRhMeasurementInit => {
-                self.init_measurement(buffer);
-                self.state.set(State::WaitRh);
+            State::ReadingId => {
+                self.state.set(State::Idle);
             }
-            State::ReadRhMeasurement => {
-                self.i2c.read(buffer, 2);
-                self.state.set(State::GotRhMeasurement);
+            State::StartingTemp => {
+                self.wait_for_measurement(State::WaitTemp);
             }
-            State::ReadTempMeasurement => {
-                self.i2c.read(buffer, 2);
-                self.state.set(State::GotTempMeasurement);
+            State::StartingRh => {
+                self.wait_for_measurement(State::WaitRh);
             }
-            State::GotTempMeasurement => {
+            State::ReadingTemp => {
                 // Temperature in hundredths of degrees centigrade
-                let temp_raw = (((buffer[0] as u32) << 8) | (buffer[1] as u32)) as u32;
+                let temp_raw = (((data[0] as u32) << 8) | (data[1] as u32)) as u32;
                 let temp = (((temp_raw * 17572) / 65536) - 4685) as i16;
 
                 self.temp_callback
                     .get()
                     .map(|cb| cb.callback(temp as usize));
-
-                match self.on_deck.get() {
-                    OnDeck::Humidity => {
-                        self.on_deck.set(OnDeck::Nothing);
-                        buffer[0] = Registers::MeasRelativeHumidityNoHoldMode as u8;
-                        self.i2c.write(buffer, 1);
-                        self.state.set(State::TakeRhMeasurementInit);
-                    }
-                    _ => {
-                        self.set_idle(buffer);
-                    }
-                }
+                self.measurement_done();
             }
-            State::GotRhMeasurement => {
+            State::ReadingRh => {
                 // Humidity in hundredths of percent
-                let humidity_raw = (((buffer[0] as u32) << 8) | (buffer[1] as u32)) as u32;
+                let humidity_raw = (((data[0] as u32) << 8) | (data[1] as u32)) as u32;
                 let humidity = (((humidity_raw * 125 * 100) / 65536) - 600) as u16;
 
                 self.humidity_callback
                     .get()
                     .map(|cb| cb.callback(humidity as usize));
-                match self.on_deck.get() {
-                    OnDeck::Temperature => {
-                        self.on_deck.set(OnDeck::Nothing);
-                        buffer[0] = Registers::MeasTemperatureNoHoldMode as u8;
-                        self.i2c.write(buffer, 1);
-                        self.state.set(State::TakeTempMeasurementInit);
-                    }
-                    _ => {
-                        self.set_idle(buffer);
-                    }
-                }
+                self.measurement_done();
             }
             _ => {}
         }
@@ -239,25 +239,7 @@ impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
 
 impl<'a, A: time::Alarm + 'a> kernel::hil::sensors::TemperatureDriver for SI7021<'a, A> {
     fn read_temperature(&self) -> kernel::ReturnCode {
-        self.buffer
-            .take()
-            .map(|buffer| {
-                // turn on i2c to send commands
-                self.i2c.enable();
-
-                buffer[0] = Registers::MeasTemperatureNoHoldMode as u8;
-                self.i2c.write(buffer, 1);
-                self.state.set(State::TakeTempMeasurementInit);
-                ReturnCode::SUCCESS
-            })
-            .unwrap_or_else(|| {
-                if self.on_deck.get() != OnDeck::Nothing {
-                    ReturnCode::EBUSY
-                } else {
-                    self.on_deck.set(OnDeck::Temperature);
-                    ReturnCode::SUCCESS
-                }
-            })
+        self.start_measurement(OnDeck::Temperature)
     }
 
     fn set_client(&self, client: &'static kernel::hil::sensors::TemperatureClient) {
@@ -267,25 +249,7 @@ impl<'a, A: time::Alarm + 'a> kernel::hil::sensors::TemperatureDriver for SI7021
 
 impl<'a, A: time::Alarm + 'a> kernel::hil::sensors::HumidityDriver for SI7021<'a, A> {
     fn read_humidity(&self) -> kernel::ReturnCode {
-        self.buffer
-            .take()
-            .map(|buffer| {
-                // turn on i2c to send commands
-                self.i2c.enable();
-
-                buffer[0] = Registers::MeasRelativeHumidityNoHoldMode as u8;
-                self.i2c.write(buffer, 1);
-                self.state.set(State::TakeRhMeasurementInit);
-                ReturnCode::SUCCESS
-            })
-            .unwrap_or_else(|| {
-                if self.on_deck.get() != OnDeck::Nothing {
-                    ReturnCode::EBUSY
-                } else {
-                    self.on_deck.set(OnDeck::Humidity);
-                    ReturnCode::SUCCESS
-                }
-            })
+        self.start_measurement(OnDeck::Humidity)
     }
 
     fn set_client(&self, client: &'static kernel::hil::sensors::HumidityClient) {
@@ -295,16 +259,10 @@ impl<'a, A: time::Alarm + 'a> kernel::hil::sensors::HumidityDriver for SI7021<'a
 
 impl<'a, A: time::Alarm + 'a> time::Client for SI7021<'a, A> {
     fn fired(&self) {
-        self.buffer.take().map(|buffer| {
-            // turn on i2c to send commands
-            self.i2c.enable();
-
-            self.i2c.read(buffer, 2);
-            match self.state.get() {
-                State::WaitRh => self.state.set(State::ReadRhMeasurement),
-                State::WaitTemp => self.state.set(State::ReadTempMeasurement),
-                _ => (),
-            }
-        });
+        match self.state.get() {
+            State::WaitRh => self.start(READ_MEASUREMENT, State::ReadingRh),
+            State::WaitTemp => self.start(READ_MEASUREMENT, State::ReadingTemp),
+            _ => (),
+        }
     }
 }
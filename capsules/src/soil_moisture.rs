@@ -0,0 +1,175 @@
+//! Driver for resistive and capacitive soil moisture probes.
+//!
+//! Resistive probes pass a current through the soil between two electrodes.
+//! A constant current corrodes the electrodes by electrolysis within weeks,
+//! and wastes power, so the probe is powered from an excitation GPIO pin
+//! that the driver only sets high while it samples. After switching the
+//! excitation on it waits `settle_ms` for the reading to stabilize, then
+//! takes `samples` ADC samples of the probe's output, averages them and
+//! switches the excitation off again.
+//!
+//! The average is converted to a moisture between `0` and `10000` hundredths
+//! of percent using two calibration samples: `dry`, read with the probe in
+//! air or dry soil, and `wet`, read with it in water. Probes whose output
+//! falls as the moisture rises, as most capacitive probes do, simply have
+//! `wet` below `dry`. Any other resistive sensor, such as a thermistor or a
+//! light dependent resistor, can be read the same way as a percentage of the
+//! range between its two calibration points.
+//!
+//! The driver needs the ADC to itself, so a board cannot also give the same
+//! ADC to the raw ADC driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let moisture_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let soil_moisture = static_init!(
+//!     capsules::soil_moisture::SoilMoisture<
+//!         'static,
+//!         sam4l::adc::Adc,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::soil_moisture::SoilMoisture::new(
+//!         &sam4l::adc::ADC0,
+//!         &sam4l::adc::CHANNEL_AD2,
+//!         &sam4l::gpio::PA[16],
+//!         moisture_alarm,
+//!         10,
+//!         8,
+//!         3200,
+//!         1400
+//!     )
+//! );
+//! sam4l::adc::ADC0.set_client(soil_moisture);
+//! moisture_alarm.set_client(soil_moisture);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil::adc;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{self, MoistureClient};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+
+/// The moisture of saturated soil, in hundredths of percent.
+pub const SATURATED: usize = 10000;
+
+pub struct SoilMoisture<'a, A: adc::Adc + 'a, T: Alarm + 'a> {
+    adc: &'a A,
+    channel: &'a A::Channel,
+    excitation: &'a gpio::Pin,
+    alarm: &'a T,
+    settle_ms: u32,
+    samples: u8,
+    dry: u16,
+    wet: u16,
+    busy: Cell<bool>,
+    /// Samples taken so far for the current reading.
+    taken: Cell<u8>,
+    sum: Cell<u32>,
+    client: Cell<Option<&'static MoistureClient>>,
+}
+
+impl<'a, A: adc::Adc, T: Alarm> SoilMoisture<'a, A, T> {
+    /// Sample `channel` of `adc` with `excitation` powering the probe.
+    pub fn new(
+        adc: &'a A,
+        channel: &'a A::Channel,
+        excitation: &'a gpio::Pin,
+        alarm: &'a T,
+        settle_ms: u32,
+        samples: u8,
+        dry: u16,
+        wet: u16,
+    ) -> SoilMoisture<'a, A, T> {
+        excitation.make_output();
+        excitation.clear();
+        SoilMoisture {
+            adc: adc,
+            channel: channel,
+            excitation: excitation,
+            alarm: alarm,
+            settle_ms: settle_ms,
+            samples: cmp::max(samples, 1),
+            dry: dry,
+            wet: wet,
+            busy: Cell::new(false),
+            taken: Cell::new(0),
+            sum: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    fn settle_ticks(&self) -> u32 {
+        cmp::max(self.settle_ms * <T::Frequency>::frequency() / 1000, 1)
+    }
+
+    /// Convert an average sample to hundredths of percent between the dry
+    /// and wet calibration points.
+    fn moisture(&self, sample: u16) -> usize {
+        let range = self.wet as i32 - self.dry as i32;
+        if range == 0 {
+            return 0;
+        }
+        let moisture = (sample as i32 - self.dry as i32) * SATURATED as i32 / range;
+        cmp::min(cmp::max(moisture, 0), SATURATED as i32) as usize
+    }
+
+    fn finish(&self, moisture: usize) {
+        self.excitation.clear();
+        self.busy.set(false);
+        self.client.get().map(|client| client.callback(moisture));
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> sensors::Moisture for SoilMoisture<'a, A, T> {
+    fn set_client(&self, client: &'static MoistureClient) {
+        self.client.set(Some(client));
+    }
+
+    fn read_moisture(&self) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.busy.set(true);
+        self.taken.set(0);
+        self.sum.set(0);
+        self.excitation.set();
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(self.settle_ticks()));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> time::Client for SoilMoisture<'a, A, T> {
+    fn fired(&self) {
+        // The ADC only refuses a sample while it is busy, so try again later.
+        if self.busy.get() && self.adc.sample(self.channel) != ReturnCode::SUCCESS {
+            self.alarm
+                .set_alarm(self.alarm.now().wrapping_add(self.settle_ticks()));
+        }
+    }
+}
+
+impl<'a, A: adc::Adc, T: Alarm> adc::Client for SoilMoisture<'a, A, T> {
+    fn sample_ready(&self, sample: u16) {
+        if !self.busy.get() {
+            return;
+        }
+        let taken = self.taken.get() + 1;
+        self.taken.set(taken);
+        self.sum.set(self.sum.get() + sample as u32);
+        // If a further sample cannot be started, use the ones taken so far.
+        if taken < self.samples && self.adc.sample(self.channel) == ReturnCode::SUCCESS {
+            return;
+        }
+        let average = (self.sum.get() / taken as u32) as u16;
+        let moisture = self.moisture(average);
+        self.finish(moisture);
+    }
+}
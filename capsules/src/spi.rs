@@ -3,11 +3,12 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::mem;
 use kernel::common::cells::{MapCell, TakeCell};
 use kernel::hil::spi::ClockPhase;
 use kernel::hil::spi::ClockPolarity;
 use kernel::hil::spi::{SpiMasterClient, SpiMasterDevice, SpiSlaveClient, SpiSlaveDevice};
-use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x20001;
@@ -21,12 +22,17 @@ pub const DRIVER_NUM: usize = 0x20001;
 // operation, while the index variable keeps track of the
 // index an ongoing operation is at in the buffers.
 
-struct App {
+pub struct App {
     callback: Option<Callback>,
     app_read: Option<AppSlice<Shared, u8>>,
     app_write: Option<AppSlice<Shared, u8>>,
     len: usize,
     index: usize,
+    /// Bus settings the app has chosen, set on the bus before each of its
+    /// transfers. Settings it has not chosen are the bus defaults.
+    polarity: Option<ClockPolarity>,
+    phase: Option<ClockPhase>,
+    rate: Option<u32>,
 }
 
 impl Default for App {
@@ -37,6 +43,9 @@ impl Default for App {
             app_write: None,
             len: 0,
             index: 0,
+            polarity: None,
+            phase: None,
+            rate: None,
         }
     }
 }
@@ -68,8 +77,11 @@ impl Default for SlaveApp {
 
 pub struct Spi<'a, S: SpiMasterDevice + 'a> {
     spi_master: &'a S,
-    busy: Cell<bool>,
-    app: MapCell<App>,
+    /// The app whose transfer is in progress.
+    current_app: Cell<Option<AppId>>,
+    apps: Grant<App>,
+    /// The settings of the bus before any app changed them.
+    defaults: Cell<Option<(ClockPolarity, ClockPhase, u32)>>,
     kernel_read: TakeCell<'static, [u8]>,
     kernel_write: TakeCell<'static, [u8]>,
     kernel_len: Cell<usize>,
@@ -85,11 +97,12 @@ pub struct SpiSlave<'a, S: SpiSlaveDevice + 'a> {
 }
 
 impl<'a, S: SpiMasterDevice> Spi<'a, S> {
-    pub fn new(spi_master: &'a S) -> Spi<'a, S> {
+    pub fn new(spi_master: &'a S, grant: Grant<App>) -> Spi<'a, S> {
         Spi {
             spi_master: spi_master,
-            busy: Cell::new(false),
-            app: MapCell::new(App::default()),
+            current_app: Cell::new(None),
+            apps: grant,
+            defaults: Cell::new(None),
             kernel_len: Cell::new(0),
             kernel_read: TakeCell::empty(),
             kernel_write: TakeCell::empty(),
@@ -103,6 +116,24 @@ impl<'a, S: SpiMasterDevice> Spi<'a, S> {
         self.kernel_write.replace(write);
     }
 
+    /// The polarity, phase and rate `app` transfers with.
+    fn settings(&self, app: &App) -> (ClockPolarity, ClockPhase, u32) {
+        let defaults = self.defaults.get().unwrap_or_else(|| {
+            let defaults = (
+                self.spi_master.get_polarity(),
+                self.spi_master.get_phase(),
+                self.spi_master.get_rate(),
+            );
+            self.defaults.set(Some(defaults));
+            defaults
+        });
+        (
+            app.polarity.unwrap_or(defaults.0),
+            app.phase.unwrap_or(defaults.1),
+            app.rate.unwrap_or(defaults.2),
+        )
+    }
+
     // Assumes checks for busy/etc. already done
     // Updates app.index to be index + length of op
     fn do_next_read_write(&self, app: &mut App) {
@@ -111,43 +142,56 @@ impl<'a, S: SpiMasterDevice> Spi<'a, S> {
         let end = start + len;
         app.index = end;
 
-        self.kernel_write.map(|kwbuf| {
-            app.app_write.as_mut().map(|src| {
+        let (polarity, phase, rate) = self.settings(app);
+        self.spi_master.configure(polarity, phase, rate);
+
+        self.kernel_write.map(|kwbuf| match app.app_write {
+            Some(ref src) => {
                 for (i, c) in src.as_ref()[start..end].iter().enumerate() {
                     kwbuf[i] = *c;
                 }
-            });
+            }
+            // Reading only, so clock out zeros.
+            None => {
+                for c in kwbuf[0..len].iter_mut() {
+                    *c = 0;
+                }
+            }
         });
-        self.spi_master.read_write_bytes(
-            self.kernel_write.take().unwrap(),
-            self.kernel_read.take(),
-            len,
-        );
+        let kernel_read = if app.app_read.is_some() {
+            self.kernel_read.take()
+        } else {
+            None
+        };
+        self.spi_master
+            .read_write_bytes(self.kernel_write.take().unwrap(), kernel_read, len);
     }
 }
 
 impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
     fn allow(
         &self,
-        _appid: AppId,
+        appid: AppId,
         allow_num: usize,
         slice: Option<AppSlice<Shared, u8>>,
     ) -> ReturnCode {
         match allow_num {
             // Pass in a read buffer to receive bytes into.
-            0 => {
-                self.app.map(|app| {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
                     app.app_read = slice;
-                });
-                ReturnCode::SUCCESS
-            }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
             // Pass in a write buffer to transmit bytes from.
-            1 => {
-                self.app.map(|app| {
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
                     app.app_write = slice;
-                });
-                ReturnCode::SUCCESS
-            }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -156,22 +200,23 @@ impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
         &self,
         subscribe_num: usize,
         callback: Option<Callback>,
-        _app_id: AppId,
-    ) -> ReturnCode {
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
-            0 /* read_write */ => {
-                self.app.map(|app| {
-                    app.callback = callback;
-                });
-                ReturnCode::SUCCESS
-            },
-            _ => ReturnCode::ENOSUPPORT
+            0 /* read_write */ => self
+                .apps
+                .enter(app_id, |app, _| Ok(mem::replace(&mut app.callback, callback)))
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT)
         }
     }
 
     // 2: read/write buffers
-    //   - requires write buffer registered with allow
-    //   - read buffer optional
+    //   - full duplex: writes the write buffer while reading into the
+    //     read buffer
+    //   - requires at least one of the buffers registered with allow;
+    //     without a write buffer, zeros are written
+    //   - the callback's first argument is the number of bytes transferred
     // 3: set chip select
     //   - selects which peripheral (CS line) the SPI should
     //     activate
@@ -179,23 +224,26 @@ impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
     //   - invalid value will result in CS 0
     // 4: get chip select
     //   - returns current selected peripheral
-    // 5: set rate on current peripheral
+    // 5: set rate for this app
     //   - parameter in bps
-    // 6: get rate on current peripheral
+    // 6: get rate for this app
     //   - value in bps
-    // 7: set clock phase on current peripheral
+    // 7: set clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 8: get clock phase on current peripheral
+    // 8: get clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 9: set clock polarity on current peripheral
+    // 9: set clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
-    // 10: get clock polarity on current peripheral
+    // 10: get clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
     //
+    // Rate, phase and polarity are kept per app and set on the bus before
+    // each of that app's transfers.
+    //
     // x: lock spi
     //   - if you perform an operation without the lock,
     //     it implicitly acquires the lock before the
@@ -205,33 +253,33 @@ impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
     // x+1: unlock spi
     //   - does nothing if lock not held
     //
-    fn command(&self, cmd_num: usize, arg1: usize, _: usize, _: AppId) -> ReturnCode {
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             // No longer supported, wrap inside a read_write_bytes
             1 /* read_write_byte */ => ReturnCode::ENOSUPPORT,
             2 /* read_write_bytes */ => {
-                if self.busy.get() {
+                if self.current_app.get().is_some() {
                     return ReturnCode::EBUSY;
                 }
-                self.app.map_or(ReturnCode::FAIL, |app| {
-                    let mut mlen = 0;
-                    app.app_write.as_mut().map(|w| {
-                        mlen = w.len();
-                    });
-                    app.app_read.as_mut().map(|r| {
-                        mlen = cmp::min(mlen, r.len());
-                    });
+                self.apps.enter(appid, |app, _| {
+                    let write_len = app.app_write.as_ref().map(|w| w.len());
+                    let read_len = app.app_read.as_ref().map(|r| r.len());
+                    let mlen = match (write_len, read_len) {
+                        (Some(w), Some(r)) => cmp::min(w, r),
+                        (Some(len), None) | (None, Some(len)) => len,
+                        (None, None) => 0,
+                    };
                     if mlen >= arg1 {
                         app.len = arg1;
                         app.index = 0;
-                        self.busy.set(true);
+                        self.current_app.set(Some(appid));
                         self.do_next_read_write(app);
                         ReturnCode::SUCCESS
                     } else {
-                        ReturnCode::EINVAL /* write buffer too small */
+                        ReturnCode::EINVAL /* buffers too small */
                     }
-                })
+                }).unwrap_or_else(|err| err.into())
             }
             3 /* set chip select */ => {
                 // XXX: TODO: do nothing, for now, until we fix interface
@@ -245,31 +293,43 @@ impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
                 ReturnCode::ENOSUPPORT
             }
             5 /* set baud rate */ => {
-                self.spi_master.set_rate(arg1 as u32);
-                ReturnCode::SUCCESS
+                self.apps.enter(appid, |app, _| {
+                    app.rate = Some(arg1 as u32);
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
             }
             6 /* get baud rate */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_rate() as usize }
+                self.apps.enter(appid, |app, _| {
+                    ReturnCode::SuccessWithValue { value: self.settings(app).2 as usize }
+                }).unwrap_or_else(|err| err.into())
             }
             7 /* set phase */ => {
-                match arg1 {
-                    0 => self.spi_master.set_phase(ClockPhase::SampleLeading),
-                    _ => self.spi_master.set_phase(ClockPhase::SampleTrailing),
-                };
-                ReturnCode::SUCCESS
+                self.apps.enter(appid, |app, _| {
+                    app.phase = Some(match arg1 {
+                        0 => ClockPhase::SampleLeading,
+                        _ => ClockPhase::SampleTrailing,
+                    });
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
             }
             8 /* get phase */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_phase() as usize }
+                self.apps.enter(appid, |app, _| {
+                    ReturnCode::SuccessWithValue { value: self.settings(app).1 as usize }
+                }).unwrap_or_else(|err| err.into())
             }
             9 /* set polarity */ => {
-                match arg1 {
-                    0 => self.spi_master.set_polarity(ClockPolarity::IdleLow),
-                    _ => self.spi_master.set_polarity(ClockPolarity::IdleHigh),
-                };
-                ReturnCode::SUCCESS
+                self.apps.enter(appid, |app, _| {
+                    app.polarity = Some(match arg1 {
+                        0 => ClockPolarity::IdleLow,
+                        _ => ClockPolarity::IdleHigh,
+                    });
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
             }
             10 /* get polarity */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_polarity() as usize }
+                self.apps.enter(appid, |app, _| {
+                    ReturnCode::SuccessWithValue { value: self.settings(app).0 as usize }
+                }).unwrap_or_else(|err| err.into())
             }
             _ => ReturnCode::ENOSUPPORT
         }
@@ -283,31 +343,42 @@ impl<'a, S: SpiMasterDevice> SpiMasterClient for Spi<'a, S> {
         readbuf: Option<&'static mut [u8]>,
         length: usize,
     ) {
-        self.app.map(move |app| {
-            if app.app_read.is_some() {
-                let src = readbuf.as_ref().unwrap();
-                let dest = app.app_read.as_mut().unwrap();
-                let start = app.index - length;
-                let end = start + length;
+        self.kernel_write.replace(writebuf);
+        self.current_app.get().map(|appid| {
+            let res = self.apps.enter(appid, |app, _| {
+                readbuf.as_ref().map(|src| {
+                    let start = app.index - length;
+                    let end = start + length;
+                    app.app_read.as_mut().map(|dest| {
+                        let d = &mut dest.as_mut()[start..end];
+                        for (i, c) in src[0..length].iter().enumerate() {
+                            d[i] = *c;
+                        }
+                    });
+                });
+                app.index < app.len
+            });
+            readbuf.map(|buf| self.kernel_read.replace(buf));
 
-                let d = &mut dest.as_mut()[start..end];
-                for (i, c) in src[0..length].iter().enumerate() {
-                    d[i] = *c;
+            match res {
+                Ok(true) => {
+                    let _ = self
+                        .apps
+                        .enter(appid, |app, _| self.do_next_read_write(app));
                 }
-            }
-
-            self.kernel_read.put(readbuf);
-            self.kernel_write.replace(writebuf);
-
-            if app.index == app.len {
-                self.busy.set(false);
-                app.len = 0;
-                app.index = 0;
-                app.callback.take().map(|mut cb| {
-                    cb.schedule(app.len, 0, 0);
-                });
-            } else {
-                self.do_next_read_write(app);
+                Ok(false) => {
+                    self.current_app.set(None);
+                    let _ = self.apps.enter(appid, |app, _| {
+                        let len = app.len;
+                        app.len = 0;
+                        app.index = 0;
+                        app.callback.take().map(|mut cb| {
+                            cb.schedule(len, 0, 0);
+                        });
+                    });
+                }
+                // The app has gone away.
+                Err(_) => self.current_app.set(None),
             }
         });
     }
@@ -394,17 +465,21 @@ impl<'a, S: SpiSlaveDevice> Driver for SpiSlave<'a, S> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             0 /* read_write */ => {
-                self.app.map(|app| app.callback = callback);
-                ReturnCode::SUCCESS
+                Ok(self
+                    .app
+                    .map(|app| mem::replace(&mut app.callback, callback))
+                    .unwrap_or(None))
             },
             1 /* chip selected */ => {
-                self.app.map(|app| app.selected_callback = callback);
-                ReturnCode::SUCCESS
+                Ok(self
+                    .app
+                    .map(|app| mem::replace(&mut app.selected_callback, callback))
+                    .unwrap_or(None))
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => Err(ReturnCode::ENOSUPPORT)
         }
     }
 
@@ -513,7 +588,7 @@ impl<'a, S: SpiSlaveDevice> SpiSlaveClient for SpiSlave<'a, S> {
                 }
             }
 
-            self.kernel_read.put(readbuf);
+            readbuf.map(|buf| self.kernel_read.replace(buf));
             self.kernel_write.put(writebuf);
 
             if app.index == app.len {
@@ -0,0 +1,127 @@
+//! 64-bit kernel timestamp.
+//!
+//! Extends a 32-bit alarm counter to a 64-bit local time that does not wrap,
+//! and keeps a correction from local time to a network-wide time. The
+//! correction is an offset plus a rate (skew) adjustment, set by a time
+//! synchronization protocol such as `ieee802154::timesync`. Until it is set
+//! network time equals local time.
+//!
+//! Local time is counted in ticks of the underlying alarm, so the low 32 bits
+//! of a local time can be passed to that alarm's `set_alarm()`. This lets
+//! capsules on different devices act at the same network time:
+//!
+//! ```rust
+//! let local = timestamp.network_to_local(sample_time);
+//! alarm.set_alarm(local as u32);
+//! ```
+//!
+//! Wrapping of the counter is detected when the time is read, so the time
+//! must be read at least once per counter period (about three days for a
+//! 16 kHz counter). Radios that timestamp frames read it on every interrupt.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timestamp = static_init!(
+//!     capsules::timestamp::Timestamp64<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::timestamp::Timestamp64::new(timestamp_virtual_alarm));
+//! rf233.set_timestamp_source(timestamp);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{self, Alarm, Frequency};
+
+/// Skew is expressed in parts per billion.
+const PPB: i64 = 1_000_000_000;
+
+pub struct Timestamp64<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    last_now: Cell<u32>,
+    high: Cell<u32>,
+    /// Network time is `reference_network + (local - reference_local)`,
+    /// corrected by `skew_ppb`.
+    reference_local: Cell<u64>,
+    reference_network: Cell<u64>,
+    skew_ppb: Cell<i32>,
+}
+
+impl<'a, A: Alarm + 'a> Timestamp64<'a, A> {
+    pub fn new(alarm: &'a A) -> Timestamp64<'a, A> {
+        Timestamp64 {
+            alarm: alarm,
+            last_now: Cell::new(0),
+            high: Cell::new(0),
+            reference_local: Cell::new(0),
+            reference_network: Cell::new(0),
+            skew_ppb: Cell::new(0),
+        }
+    }
+
+    /// Ticks of the local clock since boot.
+    pub fn local_time(&self) -> u64 {
+        let now = self.alarm.now();
+        if now < self.last_now.get() {
+            self.high.set(self.high.get().wrapping_add(1));
+        }
+        self.last_now.set(now);
+        (self.high.get() as u64) << 32 | now as u64
+    }
+
+    /// The current network time, in local clock ticks.
+    pub fn network_time(&self) -> u64 {
+        let local = self.local_time();
+        self.local_to_network(local)
+    }
+
+    /// Convert a local time to network time.
+    pub fn local_to_network(&self, local: u64) -> u64 {
+        let elapsed = local.wrapping_sub(self.reference_local.get()) as i64;
+        let correction = elapsed * self.skew_ppb.get() as i64 / PPB;
+        self.reference_network
+            .get()
+            .wrapping_add(elapsed.wrapping_add(correction) as u64)
+    }
+
+    /// Convert a network time to local time. This is the inverse of
+    /// `local_to_network` to within a tick.
+    pub fn network_to_local(&self, network: u64) -> u64 {
+        let elapsed = network.wrapping_sub(self.reference_network.get()) as i64;
+        let correction = elapsed * self.skew_ppb.get() as i64 / (PPB + self.skew_ppb.get() as i64);
+        self.reference_local
+            .get()
+            .wrapping_add(elapsed.wrapping_sub(correction) as u64)
+    }
+
+    /// Set the correction so that local time `local` corresponds to network
+    /// time `network`, and the network clock runs `skew_ppb` parts per
+    /// billion faster than the local clock.
+    pub fn discipline(&self, local: u64, network: u64, skew_ppb: i32) {
+        self.reference_local.set(local);
+        self.reference_network.set(network);
+        self.skew_ppb.set(skew_ppb);
+    }
+
+    /// The current rate correction in parts per billion.
+    pub fn skew_ppb(&self) -> i32 {
+        self.skew_ppb.get()
+    }
+
+    /// Forget the correction, so that network time runs at the local rate
+    /// from the current network time.
+    pub fn reset(&self) {
+        let local = self.local_time();
+        let network = self.local_to_network(local);
+        self.discipline(local, network, 0);
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Timestamp for Timestamp64<'a, A> {
+    fn timestamp(&self) -> u64 {
+        self.local_time()
+    }
+
+    fn frequency(&self) -> u32 {
+        A::Frequency::frequency()
+    }
+}
@@ -277,7 +277,7 @@ impl<'a> Driver for TMP006<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // single temperature reading with callback
             0 => {
@@ -285,13 +285,13 @@ impl<'a> Driver for TMP006<'a> {
                 self.repeated_mode.set(false);
 
                 // set callback function
-                self.callback.set(callback);
+                let previous = self.callback.replace(callback);
 
                 // enable sensor
                 //  turn up the sampling rate so we get the sample faster
                 self.enable_sensor(MAX_SAMPLING_RATE);
 
-                ReturnCode::SUCCESS
+                Ok(previous)
             }
 
             // periodic temperature reading subscription
@@ -300,16 +300,16 @@ impl<'a> Driver for TMP006<'a> {
                 self.repeated_mode.set(true);
 
                 // set callback function
-                self.callback.set(callback);
+                let previous = self.callback.replace(callback);
 
                 // enable temperature sensor
                 self.enable_sensor(self.sampling_period.get());
 
-                ReturnCode::SUCCESS
+                Ok(previous)
             }
 
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
         }
     }
 
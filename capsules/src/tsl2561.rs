@@ -441,16 +441,27 @@ impl<'a> Driver for TSL2561<'a> {
         subscribe_num: usize,
         callback: Option<Callback>,
         _app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // Set a callback
             0 => {
                 // Set callback function
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
+                Ok(self.callback.replace(callback))
             }
             // default
-            _ => ReturnCode::ENOSUPPORT,
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Forget the callback of a process that terminated. A light
+    /// measurement already started still completes, but is not reported.
+    fn app_terminated(&self, app_id: AppId) {
+        let subscribed = self
+            .callback
+            .get()
+            .map_or(false, |callback| callback.app_id() == app_id);
+        if subscribed {
+            self.callback.set(None);
         }
     }
 
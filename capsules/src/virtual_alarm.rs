@@ -1,13 +1,20 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! Alarms set with `DeadlineAlarm::set_alarm_with_deadline()` also tell the
+//! power manager how late they may be handled. Boards register the
+//! `MuxAlarm` with `kernel::deadline::set_source()` for this to take effect.
 
 use core::cell::Cell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::deadline::DeadlineSource;
+use kernel::hil::time::{self, Alarm, DeadlineAlarm, Frequency, Time};
 
 pub struct VirtualMuxAlarm<'a, Alrm: Alarm + 'a> {
     mux: &'a MuxAlarm<'a, Alrm>,
     when: Cell<u32>,
+    /// The latest time the client must run, if it has a deadline.
+    deadline: Cell<Option<u32>>,
     armed: Cell<bool>,
     next: ListLink<'a, VirtualMuxAlarm<'a, Alrm>>,
     client: Cell<Option<&'a time::Client>>,
@@ -24,6 +31,7 @@ impl<'a, Alrm: Alarm> VirtualMuxAlarm<'a, Alrm> {
         VirtualMuxAlarm {
             mux: mux_alarm,
             when: Cell::new(0),
+            deadline: Cell::new(None),
             armed: Cell::new(false),
             next: ListLink::empty(),
             client: Cell::new(None),
@@ -90,6 +98,7 @@ impl<'a, Alrm: Alarm> Alarm for VirtualMuxAlarm<'a, Alrm> {
         }
 
         self.when.set(when);
+        self.deadline.set(None);
     }
 
     fn get_alarm(&self) -> u32 {
@@ -97,6 +106,13 @@ impl<'a, Alrm: Alarm> Alarm for VirtualMuxAlarm<'a, Alrm> {
     }
 }
 
+impl<'a, Alrm: Alarm> DeadlineAlarm for VirtualMuxAlarm<'a, Alrm> {
+    fn set_alarm_with_deadline(&self, tics: u32, deadline: u32) {
+        self.set_alarm(tics);
+        self.deadline.set(Some(deadline));
+    }
+}
+
 impl<'a, Alrm: Alarm> time::Client for VirtualMuxAlarm<'a, Alrm> {
     fn fired(&self) {
         self.client.get().map(|client| client.fired());
@@ -123,6 +139,30 @@ impl<'a, Alrm: Alarm> MuxAlarm<'a, Alrm> {
     }
 }
 
+impl<'a, Alrm: Alarm> DeadlineSource for MuxAlarm<'a, Alrm> {
+    fn wakeup_tolerance_us(&self) -> Option<u32> {
+        // The chip wakes up when an alarm fires, so each alarm can tolerate
+        // waking up as late as the time between firing and its deadline.
+        self.virtual_alarms
+            .iter()
+            .filter(|cur| cur.armed.get())
+            .filter_map(|cur| {
+                cur.deadline
+                    .get()
+                    .map(|deadline| deadline.wrapping_sub(cur.when.get()))
+            })
+            .min()
+            .map(|tics| {
+                let us = tics as u64 * 1_000_000 / <Alrm::Frequency>::frequency() as u64;
+                if us > u32::max_value() as u64 {
+                    u32::max_value()
+                } else {
+                    us as u32
+                }
+            })
+    }
+}
+
 fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
     now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
 }
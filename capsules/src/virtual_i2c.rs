@@ -60,10 +60,10 @@ impl<'a> MuxI2C<'a> {
             mnode.map(|node| {
                 node.buffer.take().map(|buf| {
                     match node.operation.get() {
-                        Op::Write(len) => self.i2c.write(node.addr, buf, len),
-                        Op::Read(len) => self.i2c.read(node.addr, buf, len),
+                        Op::Write(len) => self.i2c.write(node.addr.get(), buf, len),
+                        Op::Read(len) => self.i2c.read(node.addr.get(), buf, len),
                         Op::WriteRead(wlen, rlen) => {
-                            self.i2c.write_read(node.addr, buf, wlen, rlen)
+                            self.i2c.write_read(node.addr.get(), buf, wlen, rlen)
                         }
                         Op::Idle => {} // Can't get here...
                     }
@@ -85,7 +85,7 @@ enum Op {
 
 pub struct I2CDevice<'a> {
     mux: &'a MuxI2C<'a>,
-    addr: u8,
+    addr: Cell<u8>,
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
@@ -97,7 +97,7 @@ impl<'a> I2CDevice<'a> {
     pub const fn new(mux: &'a MuxI2C<'a>, addr: u8) -> I2CDevice<'a> {
         I2CDevice {
             mux: mux,
-            addr: addr,
+            addr: Cell::new(addr),
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
@@ -110,6 +110,12 @@ impl<'a> I2CDevice<'a> {
         self.mux.devices.push_head(self);
         self.client.set(Some(client));
     }
+
+    /// Change the address of the device, for clients that talk to more than
+    /// one. Must not be called while a command is outstanding.
+    pub fn set_address(&self, addr: u8) {
+        self.addr.set(addr);
+    }
 }
 
 impl<'a> I2CClient for I2CDevice<'a> {
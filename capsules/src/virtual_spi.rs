@@ -1,13 +1,15 @@
 //! Virtualize a SPI master bus to enable multiple users of the SPI bus.
 
 use core::cell::Cell;
-use kernel::common::cells::TakeCell;
-use kernel::common::{List, ListLink, ListNode};
+use kernel::common::cells::{MapCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode, SegmentList};
 use kernel::hil;
 use kernel::ReturnCode;
 
 /// The Mux struct manages multiple Spi clients. Each client may have
-/// at most one outstanding Spi request.
+/// at most one outstanding Spi request. Each client's polarity, phase and
+/// rate are remembered and set on the bus before each of its requests, so
+/// clients do not see each other's settings.
 pub struct MuxSpiMaster<'a, Spi: hil::spi::SpiMaster + 'a> {
     spi: &'a Spi,
     devices: List<'a, VirtualSpiMasterDevice<'a, Spi>>,
@@ -22,9 +24,29 @@ impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'a
         len: usize,
     ) {
         self.inflight.get().map(move |device| {
-            self.inflight.set(None);
-            self.do_next_op();
-            device.read_write_done(write_buffer, read_buffer, len);
+            if device.segments.is_some() {
+                // Keep the bus until the last segment has been written
+                let index = device.segment.get();
+                let more = device.segments.map_or(false, move |segments| {
+                    segments.put(index, write_buffer);
+                    index + 1 < segments.len()
+                });
+                if more {
+                    device.segment.set(index + 1);
+                    self.write_segment(device);
+                } else {
+                    self.inflight.set(None);
+                    self.do_next_op();
+                    device
+                        .segments
+                        .take()
+                        .map(|segments| device.write_segments_done(segments));
+                }
+            } else {
+                self.inflight.set(None);
+                self.do_next_op();
+                device.read_write_done(write_buffer, read_buffer, len);
+            }
         });
     }
 }
@@ -46,17 +68,11 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
                 .find(|node| node.operation.get() != Op::Idle);
             mnode.map(|node| {
                 self.spi.specify_chip_select(node.chip_select.get());
+                self.restore_settings(node);
                 let op = node.operation.get();
                 // Need to set idle here in case callback changes state
                 node.operation.set(Op::Idle);
                 match op {
-                    Op::Configure(cpol, cpal, rate) => {
-                        // The `chip_select` type will be correct based on
-                        // what implemented `SpiMaster`.
-                        self.spi.set_clock(cpol);
-                        self.spi.set_phase(cpal);
-                        self.spi.set_rate(rate);
-                    }
                     Op::ReadWriteBytes(len) => {
                         // Only async operations want to block by setting
                         // the devices as inflight.
@@ -66,30 +82,48 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
                             self.spi.read_write_bytes(txbuffer, rxbuffer, len);
                         });
                     }
-                    Op::SetPolarity(pol) => {
-                        self.spi.set_clock(pol);
-                    }
-                    Op::SetPhase(pal) => {
-                        self.spi.set_phase(pal);
-                    }
-                    Op::SetRate(rate) => {
-                        self.spi.set_rate(rate);
+                    Op::WriteSegments => {
+                        self.inflight.set(Some(node));
+                        node.segment.set(0);
+                        self.write_segment(node);
                     }
                     Op::Idle => {} // Can't get here...
                 }
             });
         }
     }
+
+    /// Put back the bus settings `device` last asked for, since another
+    /// device may have changed them since.
+    fn restore_settings(&self, device: &VirtualSpiMasterDevice<'a, Spi>) {
+        device.polarity.get().map(|cpol| self.spi.set_clock(cpol));
+        device.phase.get().map(|cpal| self.spi.set_phase(cpal));
+        device.rate.get().map(|rate| self.spi.set_rate(rate));
+    }
+
+    /// Write the current segment of `device`, holding chip select low after
+    /// all but the last segment.
+    fn write_segment(&self, device: &'a VirtualSpiMasterDevice<'a, Spi>) {
+        let index = device.segment.get();
+        device.segments.map(|segments| {
+            if index + 1 < segments.len() {
+                self.spi.hold_low();
+            } else {
+                self.spi.release_low();
+            }
+            let len = segments.segment_len(index);
+            segments.take(index).map(|buf| {
+                self.spi.read_write_bytes(buf, None, len);
+            });
+        });
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
 enum Op {
     Idle,
-    Configure(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32),
     ReadWriteBytes(usize),
-    SetPolarity(hil::spi::ClockPolarity),
-    SetPhase(hil::spi::ClockPhase),
-    SetRate(u32),
+    WriteSegments,
 }
 
 pub struct VirtualSpiMasterDevice<'a, Spi: hil::spi::SpiMaster + 'a> {
@@ -97,13 +131,20 @@ pub struct VirtualSpiMasterDevice<'a, Spi: hil::spi::SpiMaster + 'a> {
     chip_select: Cell<Spi::ChipSelect>,
     txbuffer: TakeCell<'static, [u8]>,
     rxbuffer: TakeCell<'static, [u8]>,
+    segments: MapCell<SegmentList>,
+    segment: Cell<usize>,
     operation: Cell<Op>,
+    /// The settings this device has asked for, applied to the bus before
+    /// each of its operations. Settings it has not set are left as they are.
+    polarity: Cell<Option<hil::spi::ClockPolarity>>,
+    phase: Cell<Option<hil::spi::ClockPhase>>,
+    rate: Cell<Option<u32>>,
     next: ListLink<'a, VirtualSpiMasterDevice<'a, Spi>>,
     client: Cell<Option<&'a hil::spi::SpiMasterClient>>,
 }
 
 impl<'a, Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
-    pub const fn new(
+    pub fn new(
         mux: &'a MuxSpiMaster<'a, Spi>,
         chip_select: Spi::ChipSelect,
     ) -> VirtualSpiMasterDevice<'a, Spi> {
@@ -112,7 +153,12 @@ impl<'a, Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
             chip_select: Cell::new(chip_select),
             txbuffer: TakeCell::empty(),
             rxbuffer: TakeCell::empty(),
+            segments: MapCell::empty(),
+            segment: Cell::new(0),
             operation: Cell::new(Op::Idle),
+            polarity: Cell::new(None),
+            phase: Cell::new(None),
+            rate: Cell::new(None),
             next: ListLink::empty(),
             client: Cell::new(None),
         }
@@ -135,6 +181,12 @@ impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for VirtualSpiMaste
             client.read_write_done(write_buffer, read_buffer, len);
         });
     }
+
+    fn write_segments_done(&self, segments: SegmentList) {
+        self.client.get().map(move |client| {
+            client.write_segments_done(segments);
+        });
+    }
 }
 
 impl<'a, Spi: hil::spi::SpiMaster> ListNode<'a, VirtualSpiMasterDevice<'a, Spi>>
@@ -147,8 +199,9 @@ impl<'a, Spi: hil::spi::SpiMaster> ListNode<'a, VirtualSpiMasterDevice<'a, Spi>>
 
 impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMasterDevice<'a, Spi> {
     fn configure(&self, cpol: hil::spi::ClockPolarity, cpal: hil::spi::ClockPhase, rate: u32) {
-        self.operation.set(Op::Configure(cpol, cpal, rate));
-        self.mux.do_next_op();
+        self.polarity.set(Some(cpol));
+        self.phase.set(Some(cpal));
+        self.rate.set(Some(rate));
     }
 
     fn read_write_bytes(
@@ -164,31 +217,44 @@ impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMaste
         ReturnCode::SUCCESS
     }
 
-    fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
-        self.operation.set(Op::SetPolarity(cpol));
+    fn write_segments(&self, segments: SegmentList) -> (ReturnCode, Option<SegmentList>) {
+        if self.segments.is_some() {
+            return (ReturnCode::EBUSY, Some(segments));
+        }
+        let empty_segment = (0..segments.len()).any(|i| segments.segment_len(i) == 0);
+        if segments.is_empty() || empty_segment {
+            return (ReturnCode::EINVAL, Some(segments));
+        }
+        self.segments.replace(segments);
+        self.operation.set(Op::WriteSegments);
         self.mux.do_next_op();
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
+        self.polarity.set(Some(cpol));
     }
 
     fn set_phase(&self, cpal: hil::spi::ClockPhase) {
-        self.operation.set(Op::SetPhase(cpal));
-        self.mux.do_next_op();
+        self.phase.set(Some(cpal));
     }
 
     fn set_rate(&self, rate: u32) {
-        self.operation.set(Op::SetRate(rate));
-        self.mux.do_next_op();
+        self.rate.set(Some(rate));
     }
 
     fn get_polarity(&self) -> hil::spi::ClockPolarity {
-        self.mux.spi.get_clock()
+        self.polarity
+            .get()
+            .unwrap_or_else(|| self.mux.spi.get_clock())
     }
 
     fn get_phase(&self) -> hil::spi::ClockPhase {
-        self.mux.spi.get_phase()
+        self.phase.get().unwrap_or_else(|| self.mux.spi.get_phase())
     }
 
     fn get_rate(&self) -> u32 {
-        self.mux.spi.get_rate()
+        self.rate.get().unwrap_or_else(|| self.mux.spi.get_rate())
     }
 }
 
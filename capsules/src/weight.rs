@@ -0,0 +1,135 @@
+//! Shared userland driver for weight and force sensors.
+//!
+//! You need a device that provides the `hil::sensors::Weight` trait.
+//!
+//! Applications can tare the sensor, after which readings are reported
+//! relative to the load at the time of the tare, for example to weigh the
+//! contents of a container rather than the container itself. The tare is
+//! shared by all applications.
+//!
+//! ```rust
+//! let weight = static_init!(
+//!     capsules::weight::WeightSensor<'static>,
+//!     capsules::weight::WeightSensor::new(hx711, kernel::Grant::create())
+//! );
+//! hil::sensors::Weight::set_client(hx711, weight);
+//! ```
+
+use core::cell::Cell;
+use core::mem;
+use kernel::hil;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x60007;
+
+/// Per-process metadata
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct WeightSensor<'a> {
+    sensor: &'a hil::sensors::Weight,
+    command_pending: Cell<bool>,
+    /// Whether the reading in progress sets the tare.
+    tare_pending: Cell<bool>,
+    tare: Cell<i32>,
+    apps: Grant<App>,
+}
+
+impl<'a> WeightSensor<'a> {
+    pub fn new(sensor: &'a hil::sensors::Weight, grant: Grant<App>) -> WeightSensor {
+        WeightSensor {
+            sensor: sensor,
+            command_pending: Cell::new(false),
+            tare_pending: Cell::new(false),
+            tare: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    fn enqueue_sensor_reading(&self, appid: AppId, tare: bool) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    ReturnCode::EBUSY
+                } else {
+                    app.pending = true;
+                    if tare {
+                        self.tare_pending.set(true);
+                    }
+                    if !self.command_pending.get() {
+                        self.command_pending.set(true);
+                        self.sensor.read_weight();
+                    }
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a> Driver for WeightSensor<'a> {
+    /// Subscribe to weight readings
+    ///
+    /// ### `subscribe`
+    ///
+    /// - `0`: Subscribe to weight readings. The callback signature is
+    /// `fn(milligrams: i32)`, where `milligrams` is the load relative to the
+    /// tare. A tare reports `0`.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    Ok(mem::replace(&mut app.callback, callback))
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ReturnCode::ENOSUPPORT),
+        }
+    }
+
+    /// Initiate weight readings
+    ///
+    /// Sensor readings are coalesced if processes request them concurrently.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Check driver presence
+    /// - `1`: Start a weight reading
+    /// - `2`: Take a reading and use it as the zero for following readings
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 => self.enqueue_sensor_reading(appid, false),
+            2 => self.enqueue_sensor_reading(appid, true),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> hil::sensors::WeightClient for WeightSensor<'a> {
+    fn callback(&self, milligrams: i32) {
+        self.command_pending.set(false);
+        if self.tare_pending.get() {
+            self.tare_pending.set(false);
+            self.tare.set(milligrams);
+        }
+        let value = milligrams.wrapping_sub(self.tare.get());
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                if let Some(mut callback) = app.callback {
+                    callback.schedule(value as usize, 0, 0);
+                }
+            }
+        });
+    }
+}
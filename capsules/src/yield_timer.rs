@@ -0,0 +1,48 @@
+//! Times out the `yield` calls of processes that wait for a limited time.
+//!
+//! The kernel only needs to be woken up when a wait times out, which it then
+//! notices itself, so this just passes an alarm to the kernel.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let yield_timer = static_init!(
+//!     capsules::yield_timer::YieldTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::yield_timer::YieldTimer::new(yield_virtual_alarm)
+//! );
+//! yield_virtual_alarm.set_client(yield_timer);
+//! kernel::set_yield_timer(yield_timer);
+//! ```
+
+use kernel;
+use kernel::hil::time::{self, Alarm, Frequency};
+
+pub struct YieldTimer<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+}
+
+impl<'a, A: Alarm + 'a> YieldTimer<'a, A> {
+    pub fn new(alarm: &'a A) -> YieldTimer<'a, A> {
+        YieldTimer { alarm: alarm }
+    }
+}
+
+impl<'a, A: Alarm + 'a> kernel::YieldTimer for YieldTimer<'a, A> {
+    fn now(&self) -> u32 {
+        self.alarm.now()
+    }
+
+    fn frequency(&self) -> u32 {
+        <A::Frequency>::frequency()
+    }
+
+    fn wake_at(&self, when: u32) {
+        self.alarm.set_alarm(when);
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for YieldTimer<'a, A> {
+    /// The interrupt has already woken the kernel
+    fn fired(&self) {}
+}
@@ -12,44 +12,94 @@
 //! * Date: August 18, 2016
 
 use core::cell::Cell;
-use kernel::common::cells::VolatileCell;
+use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 
 pub static mut CLOCK: Clock = Clock::new();
 
 #[repr(C)]
 struct ClockRegisters {
-    tasks_hfclkstart: VolatileCell<u32>,    // 0x000
-    tasks_hfclkstop: VolatileCell<u32>,     // 0x004
-    tasks_lfclkstart: VolatileCell<u32>,    // 0x008
-    tasks_lfclkstop: VolatileCell<u32>,     // 0x00c
-    tasks_cal: VolatileCell<u32>,           // 0x010
-    tasks_cstart: VolatileCell<u32>,        // 0x014
-    tasks_cstop: VolatileCell<u32>,         // 0x018
-    _reserved1: [VolatileCell<u32>; 57],    // 0x01c - 0x100
-    events_hfclkstarted: VolatileCell<u32>, // 0x100
-    events_lfclkstarted: VolatileCell<u32>, // 0x104
-    _reserved2: VolatileCell<u32>,          // 0x108
-    events_done: VolatileCell<u32>,         // 0x10c
-    events_ctto: VolatileCell<u32>,         // 0x110
-    _reserved3: [VolatileCell<u32>; 124],   // 0x110 - 0x304
-    intenset: VolatileCell<u32>,            // 0x304
-    intenclr: VolatileCell<u32>,            // 0x308
-    _reserved4: [VolatileCell<u32>; 63],    // 0x308 - 0x408
-    hfclkrun: VolatileCell<u32>,            // 0x408
-    hfclkstat: VolatileCell<u32>,           // 0x40c
-    _reserved5: [VolatileCell<u32>; 1],     // 0x410
-    lfclkrun: VolatileCell<u32>,            // 0x414
-    lfclkstat: VolatileCell<u32>,           // 0x418
-    lfclksrccopy: VolatileCell<u32>,        // 0x41c
-    _reserved6: [VolatileCell<u32>; 62],    // 0x420 - 0x518
-    lfclksrc: VolatileCell<u32>,            // 0x518
-    _reserved7: [VolatileCell<u32>; 7],     // 0x51c - 0x538
-    ctiv: VolatileCell<u32>,                // 0x538
-    _reserved8: [VolatileCell<u32>; 5],     // 0x53c - 0x550
-    xtalfreq: VolatileCell<u32>,            // 0x550
+    tasks_hfclkstart: WriteOnly<u32, Control::Register>, // 0x000
+    tasks_hfclkstop: WriteOnly<u32, Control::Register>,  // 0x004
+    tasks_lfclkstart: WriteOnly<u32, Control::Register>, // 0x008
+    tasks_lfclkstop: WriteOnly<u32, Control::Register>,  // 0x00c
+    tasks_cal: WriteOnly<u32, Control::Register>,        // 0x010
+    tasks_cstart: WriteOnly<u32, Control::Register>,     // 0x014
+    tasks_cstop: WriteOnly<u32, Control::Register>,      // 0x018
+    _reserved1: [u32; 57],                               // 0x01c - 0x100
+    events_hfclkstarted: ReadWrite<u32, Status::Register>, // 0x100
+    events_lfclkstarted: ReadWrite<u32, Status::Register>, // 0x104
+    _reserved2: u32,                                     // 0x108
+    events_done: ReadWrite<u32, Status::Register>,       // 0x10c
+    events_ctto: ReadWrite<u32, Status::Register>,       // 0x110
+    _reserved3: [u32; 124],                              // 0x110 - 0x304
+    intenset: ReadWrite<u32, Interrupt::Register>,       // 0x304
+    intenclr: ReadWrite<u32, Interrupt::Register>,       // 0x308
+    _reserved4: [u32; 63],                               // 0x308 - 0x408
+    hfclkrun: ReadOnly<u32, Status::Register>,           // 0x408
+    hfclkstat: ReadOnly<u32, HfClkStat::Register>,       // 0x40c
+    _reserved5: [u32; 1],                                // 0x410
+    lfclkrun: ReadOnly<u32, Control::Register>,          // 0x414
+    lfclkstat: ReadOnly<u32, LfClkStat::Register>,       // 0x418
+    lfclksrccopy: ReadOnly<u32, LfClkSrc::Register>,     // 0x41c
+    _reserved6: [u32; 62],                               // 0x420 - 0x518
+    lfclksrc: ReadWrite<u32, LfClkSrc::Register>,        // 0x518
+    _reserved7: [u32; 7],                                // 0x51c - 0x538
+    ctiv: ReadWrite<u32, Ctiv::Register>,                // 0x538
+    _reserved8: [u32; 5],                                // 0x53c - 0x550
+    xtalfreq: ReadWrite<u32, XtalFreqReg::Register>,     // 0x550
 }
 
+register_bitfields![u32,
+    Control [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    Status [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+    Interrupt [
+        HFCLKSTARTED OFFSET(0) NUMBITS(1),
+        LFCLKSTARTED OFFSET(1) NUMBITS(1),
+        DONE OFFSET(3) NUMBITS(1),
+        CTTO OFFSET(4) NUMBITS(1)
+    ],
+    HfClkStat [
+        SRC OFFSET(0) NUMBITS(1) [
+            RC = 0,
+            XTAL = 1
+        ],
+        STATE OFFSET(16) NUMBITS(1) [
+            RUNNING = 1
+        ]
+    ],
+    LfClkStat [
+        SRC OFFSET(0) NUMBITS(2) [
+            RC = 0,
+            XTAL = 1,
+            SYNTH = 2
+        ],
+        STATE OFFSET(16) NUMBITS(1) [
+            RUNNING = 1
+        ]
+    ],
+    LfClkSrc [
+        SRC OFFSET(0) NUMBITS(2) [
+            RC = 0,
+            XTAL = 1,
+            SYNTH = 2
+        ]
+    ],
+    Ctiv [
+        CTIV OFFSET(0) NUMBITS(7) []
+    ],
+    XtalFreqReg [
+        XTALFREQ OFFSET(0) NUMBITS(8) [
+            F16MHz = 0xFF,
+            F32MHz = 0x0
+        ]
+    ]
+];
+
 const CLOCK_BASE: StaticRef<ClockRegisters> =
     unsafe { StaticRef::new(0x40000000 as *const ClockRegisters) };
 
@@ -125,22 +175,22 @@ impl Clock {
 
     pub fn high_start(&self) {
         let regs = &*self.registers;
-        regs.tasks_hfclkstart.set(1);
+        regs.tasks_hfclkstart.write(Control::ENABLE::SET);
     }
 
     pub fn high_stop(&self) {
         let regs = &*self.registers;
-        regs.tasks_hfclkstop.set(1);
+        regs.tasks_hfclkstop.write(Control::ENABLE::SET);
     }
 
     pub fn high_started(&self) -> bool {
         let regs = &*self.registers;
-        regs.events_hfclkstarted.get() == 1
+        regs.events_hfclkstarted.is_set(Status::READY)
     }
 
     pub fn high_source(&self) -> HighClockSource {
         let regs = &*self.registers;
-        match regs.hfclkstat.get() & 1 {
+        match regs.hfclkstat.read(HfClkStat::SRC) {
             0b0 => HighClockSource::RC,
             _ => HighClockSource::XTAL,
         }
@@ -148,7 +198,7 @@ impl Clock {
 
     pub fn high_freq(&self) -> XtalFreq {
         let regs = &*self.registers;
-        match regs.xtalfreq.get() {
+        match regs.xtalfreq.read(XtalFreqReg::XTALFREQ) {
             0xff => XtalFreq::F16MHz,
             _ => XtalFreq::F32MHz,
         }
@@ -156,34 +206,34 @@ impl Clock {
 
     pub fn high_set_freq(&self, freq: XtalFreq) {
         let regs = &*self.registers;
-        regs.xtalfreq.set(freq as u32);
+        regs.xtalfreq.write(XtalFreqReg::XTALFREQ.val(freq as u32));
     }
 
     pub fn high_running(&self) -> bool {
         let regs = &*self.registers;
-        (regs.hfclkstat.get() & ClockRunning::RUN as u32) == ClockRunning::RUN as u32
+        regs.hfclkstat.matches_all(HfClkStat::STATE::RUNNING)
     }
 
     #[no_mangle]
     #[inline(never)]
     pub fn low_start(&self) {
         let regs = &*self.registers;
-        regs.tasks_lfclkstart.set(1);
+        regs.tasks_lfclkstart.write(Control::ENABLE::SET);
     }
 
     pub fn low_stop(&self) {
         let regs = &*self.registers;
-        regs.tasks_lfclkstop.set(1);
+        regs.tasks_lfclkstop.write(Control::ENABLE::SET);
     }
 
     pub fn low_started(&self) -> bool {
         let regs = &*self.registers;
-        regs.events_lfclkstarted.get() == 1
+        regs.events_lfclkstarted.is_set(Status::READY)
     }
 
     pub fn low_source(&self) -> LowClockSource {
         let regs = &*self.registers;
-        match regs.lfclkstat.get() & (LowClockSource::MASK as u32) {
+        match regs.lfclkstat.read(LfClkStat::SRC) {
             0b1 => LowClockSource::XTAL,
             0b10 => LowClockSource::SYNTH,
             _ => LowClockSource::RC,
@@ -192,11 +242,11 @@ impl Clock {
 
     pub fn low_running(&self) -> bool {
         let regs = &*self.registers;
-        (regs.lfclkstat.get() & ClockRunning::RUN as u32) == ClockRunning::RUN as u32
+        regs.lfclkstat.matches_all(LfClkStat::STATE::RUNNING)
     }
 
     pub fn low_set_source(&self, src: LowClockSource) {
         let regs = &*self.registers;
-        regs.lfclksrc.set(src as u32);
+        regs.lfclksrc.write(LfClkSrc::SRC.val(src as u32));
     }
 }
@@ -5,6 +5,9 @@
 //! Currently all fields in PAYLOAD array are configurable from user-space
 //! except the PDU_TYPE.
 //!
+//! A board with an external PA, LNA or antenna switch sets its frontend with
+//! `set_frontend`, and the radio switches it around each packet.
+//!
 //! ### Authors
 //! * Niklas Adolfsson <niklasadolfsson1@gmail.com>
 //! * Fredrik Nilsson <frednils@student.chalmers.se>
@@ -17,6 +20,7 @@ use kernel::common::cells::VolatileCell;
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::rf_frontend::RfFrontend;
 use kernel::ReturnCode;
 use nrf5x;
 use nrf5x::constants::TxPower;
@@ -119,6 +123,7 @@ pub struct Radio {
     tx_power: Cell<TxPower>,
     rx_client: Cell<Option<&'static ble_advertising::RxClient>>,
     tx_client: Cell<Option<&'static ble_advertising::TxClient>>,
+    frontend: Cell<Option<&'static RfFrontend>>,
 }
 
 impl Radio {
@@ -128,9 +133,14 @@ impl Radio {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: Cell::new(None),
             tx_client: Cell::new(None),
+            frontend: Cell::new(None),
         }
     }
 
+    pub fn set_frontend(&self, frontend: &'static RfFrontend) {
+        self.frontend.set(Some(frontend));
+    }
+
     fn ble_initialize(&self, channel: RadioChannel) {
         let regs = &*self.registers;
 
@@ -164,12 +174,14 @@ impl Radio {
 
     fn tx(&self) {
         let regs = &*self.registers;
+        self.frontend.get().map(|frontend| frontend.transmit());
         regs.ready.set(0);
         regs.txen.set(1);
     }
 
     fn rx(&self) {
         let regs = &*self.registers;
+        self.frontend.get().map(|frontend| frontend.receive());
         regs.ready.set(0);
         regs.rxen.set(1);
     }
@@ -246,6 +258,7 @@ impl Radio {
     fn radio_off(&self) {
         let regs = &*self.registers;
         regs.power.set(0);
+        self.frontend.get().map(|frontend| frontend.sleep());
     }
 
     // pre-condition validated before arriving here
@@ -3,6 +3,8 @@ use deferred_call_tasks::DeferredCallTask;
 use i2c;
 use kernel;
 use kernel::common::deferred_call;
+use kernel::common::interrupt_budget;
+use nfct;
 use nrf5x;
 use nrf5x::peripheral_interrupts::*;
 use nvmc;
@@ -46,9 +48,10 @@ impl kernel::Chip for NRF52 {
                         DeferredCallTask::Nvmc => nvmc::NVMC.handle_interrupt(),
                     }
                 } else if let Some(interrupt) = nvic::next_pending() {
-                    match interrupt {
+                    interrupt_budget::INTERRUPT_BUDGET.measure(interrupt, || match interrupt {
                         ECB => nrf5x::aes::AESECB.handle_interrupt(),
                         GPIOTE => nrf5x::gpio::PORT.handle_interrupt(),
+                        NFCT => nfct::NFCT.handle_interrupt(),
                         RADIO => radio::RADIO.handle_interrupt(),
                         RNG => nrf5x::trng::TRNG.handle_interrupt(),
                         RTC1 => nrf5x::rtc::RTC.handle_interrupt(),
@@ -87,7 +90,7 @@ impl kernel::Chip for NRF52 {
                         }
                         SPIM2_SPIS2_SPI2 => spi::SPIM2.handle_interrupt(),
                         _ => debug!("NvicIdx not supported by Tock"),
-                    }
+                    });
                     let n = nvic::Nvic::new(interrupt);
                     n.clear_pending();
                     n.enable();
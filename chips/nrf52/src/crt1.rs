@@ -1,3 +1,4 @@
+use cortexm4;
 use cortexm4::{generic_isr, ipsr_isr_number_to_str, nvic, svc_handler, systick_handler};
 
 /*
@@ -58,6 +59,10 @@ unsafe extern "C" fn hard_fault_handler() {
         :
         );
 
+    if kernel_stack && cortexm4::mpu::kernel_stack_overflowed(faulting_stack as usize) {
+        cortexm4::kernel_stack_overflow(faulting_stack);
+    }
+
     if kernel_stack {
         let stacked_r0: u32 = *offset(faulting_stack, 0);
         let stacked_r1: u32 = *offset(faulting_stack, 1);
@@ -319,24 +324,15 @@ pub unsafe extern "C" fn init() {
 
     // Workaround for Errata 66
     // "TEMP: Linearity specification not met with default settings" found at the Errata doc
-    //     NRF_TEMP->A0 = NRF_FICR->TEMP.A0;
-    //     NRF_TEMP->A1 = NRF_FICR->TEMP.A1;
-    //     NRF_TEMP->A2 = NRF_FICR->TEMP.A2;
-    //     NRF_TEMP->A3 = NRF_FICR->TEMP.A3;
-    //     NRF_TEMP->A4 = NRF_FICR->TEMP.A4;
-    //     NRF_TEMP->A5 = NRF_FICR->TEMP.A5;
-    //     NRF_TEMP->B0 = NRF_FICR->TEMP.B0;
-    //     NRF_TEMP->B1 = NRF_FICR->TEMP.B1;
-    //     NRF_TEMP->B2 = NRF_FICR->TEMP.B2;
-    //     NRF_TEMP->B3 = NRF_FICR->TEMP.B3;
-    //     NRF_TEMP->B4 = NRF_FICR->TEMP.B4;
-    //     NRF_TEMP->B5 = NRF_FICR->TEMP.B5;
-    //     NRF_TEMP->T0 = NRF_FICR->TEMP.T0;
-    //     NRF_TEMP->T1 = NRF_FICR->TEMP.T1;
-    //     NRF_TEMP->T2 = NRF_FICR->TEMP.T2;
-    //     NRF_TEMP->T3 = NRF_FICR->TEMP.T3;
-    //     NRF_TEMP->T4 = NRF_FICR->TEMP.T4;
-    // }
+    // Copy the factory calibration for the piecewise linear function
+    // (A0-A5, B0-B5, T0-T4) from FICR into the TEMP peripheral.
+    for i in 0..6 {
+        *((0x4000c520u32 + 4 * i) as (*mut u32)) = *((0x10000404u32 + 4 * i) as (*mut u32));
+        *((0x4000c540u32 + 4 * i) as (*mut u32)) = *((0x1000041cu32 + 4 * i) as (*mut u32));
+    }
+    for i in 0..5 {
+        *((0x4000c560u32 + 4 * i) as (*mut u32)) = *((0x10000434u32 + 4 * i) as (*mut u32));
+    }
 
     // Workaround for Errata 108
     // "RAM: RAM content cannot be trusted upon waking up from System ON Idle
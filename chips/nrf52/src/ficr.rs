@@ -10,6 +10,7 @@
 use core::fmt;
 use kernel::common::regs::ReadOnly;
 use kernel::common::StaticRef;
+use kernel::hil::device_id::DeviceId;
 
 const FICR_BASE: StaticRef<FicrRegisters> =
     unsafe { StaticRef::new(0x10000000 as *const FicrRegisters) };
@@ -313,6 +314,13 @@ impl Ficr {
     }
 }
 
+impl DeviceId for Ficr {
+    fn device_id(&self) -> u64 {
+        let regs = &*self.registers;
+        ((regs.deviceid1.get() as u64) << 32) | regs.deviceid0.get() as u64
+    }
+}
+
 impl fmt::Display for Ficr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -17,7 +17,9 @@ pub mod crt1;
 mod deferred_call_tasks;
 pub mod ficr;
 pub mod i2c;
+pub mod nfct;
 pub mod nvmc;
+pub mod power;
 pub mod ppi;
 pub mod radio;
 pub mod spi;
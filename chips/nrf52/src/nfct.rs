@@ -0,0 +1,631 @@
+//! Near field communication tag (NFCT), nRF52
+//!
+//! Emulates an NFC Forum Type 2 Tag that holds a single read-only NDEF
+//! message. The peripheral handles the NFC-A framing and anticollision in
+//! hardware, while this driver answers the Type 2 Tag commands: `READ`
+//! returns 16 bytes of the tag's memory, `HLTA` puts the tag to sleep and
+//! everything else, including `WRITE`, is refused with a NACK. The tag's
+//! memory is not stored anywhere; each read is built from the message:
+//!
+//! ```
+//! +----------+-------------+------------+--------------------------+------+
+//! | UID, BCC | Lock bytes  | Capability | NDEF TLV: 0x03, length,  | 0xFE |
+//! | 9 bytes  | 0xFF 0xFF   | container  | message                  |      |
+//! +----------+-------------+------------+--------------------------+------+
+//! ```
+//!
+//! The 7-byte UID is the Nordic manufacturer code followed by six bytes of
+//! the FICR device identifier, so each chip reads as a different tag.
+//!
+//! While enabled and without a reader nearby, the peripheral only senses
+//! for a field, which needs neither the high frequency crystal nor the CPU,
+//! so the chip sleeps as normal. A reader's field raises an interrupt that
+//! wakes the chip, and the driver then starts the crystal the peripheral
+//! needs to talk to the reader, if it is not already running, and stops it
+//! again once the field is gone. The field also wakes the chip from System
+//! OFF, see `power::Power::system_off()`.
+//!
+//! The NFC antenna pins must not be configured as GPIO in the UICR.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let nfc_tag = static_init!(
+//!     capsules::nfc_tag::NfcTag<'static, nrf52::nfct::Nfct>,
+//!     capsules::nfc_tag::NfcTag::new(
+//!         &nrf52::nfct::NFCT,
+//!         &mut capsules::nfc_tag::BUFFER,
+//!         kernel::Grant::create()
+//!     )
+//! );
+//! kernel::hil::nfc::NdefTag::set_client(&nrf52::nfct::NFCT, nfc_tag);
+//! ```
+
+use clock;
+use core::cell::Cell;
+use ficr;
+use kernel::common::cells::TakeCell;
+use kernel::common::regs::{ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::device_id::DeviceId;
+use kernel::hil::nfc;
+use kernel::ReturnCode;
+
+const NFCT_BASE: StaticRef<NfctRegisters> =
+    unsafe { StaticRef::new(0x40005000 as *const NfctRegisters) };
+
+#[repr(C)]
+struct NfctRegisters {
+    /// Activate the peripheral for the reader's field
+    /// - Address: 0x000 - 0x004
+    task_activate: WriteOnly<u32, Task::Register>,
+    /// Disable the peripheral
+    /// - Address: 0x004 - 0x008
+    task_disable: WriteOnly<u32, Task::Register>,
+    /// Sense for a field, using little power
+    /// - Address: 0x008 - 0x00c
+    task_sense: WriteOnly<u32, Task::Register>,
+    /// Start transmitting a frame
+    /// - Address: 0x00c - 0x010
+    task_starttx: WriteOnly<u32, Task::Register>,
+    /// Reserved
+    _reserved0: [u32; 3],
+    /// Start receiving a frame
+    /// - Address: 0x01c - 0x020
+    task_enablerxdata: WriteOnly<u32, Task::Register>,
+    /// Reserved
+    _reserved1: [u32; 1],
+    /// Move to the IDLE state of anticollision
+    /// - Address: 0x024 - 0x028
+    task_goidle: WriteOnly<u32, Task::Register>,
+    /// Move to the SLEEP_A state of anticollision
+    /// - Address: 0x028 - 0x02c
+    task_gosleep: WriteOnly<u32, Task::Register>,
+    /// Reserved
+    _reserved2: [u32; 53],
+    /// The peripheral is ready to receive and send frames
+    /// - Address: 0x100 - 0x104
+    event_ready: ReadWrite<u32, Event::Register>,
+    /// A remote field was detected
+    /// - Address: 0x104 - 0x108
+    event_fielddetected: ReadWrite<u32, Event::Register>,
+    /// The remote field was lost
+    /// - Address: 0x108 - 0x10c
+    event_fieldlost: ReadWrite<u32, Event::Register>,
+    /// Marks the start of the first symbol of a transmitted frame
+    /// - Address: 0x10c - 0x110
+    event_txframestart: ReadWrite<u32, Event::Register>,
+    /// Marks the end of the last transmitted on-air symbol of a frame
+    /// - Address: 0x110 - 0x114
+    event_txframeend: ReadWrite<u32, Event::Register>,
+    /// Marks the end of the first symbol of a received frame
+    /// - Address: 0x114 - 0x118
+    event_rxframestart: ReadWrite<u32, Event::Register>,
+    /// Received data has been checked and stored in RAM
+    /// - Address: 0x118 - 0x11c
+    event_rxframeend: ReadWrite<u32, Event::Register>,
+    /// An error has occurred, see ERRORSTATUS
+    /// - Address: 0x11c - 0x120
+    event_error: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    _reserved3: [u32; 2],
+    /// A received frame had an error, see FRAMESTATUS.RX
+    /// - Address: 0x128 - 0x12c
+    event_rxerror: ReadWrite<u32, Event::Register>,
+    /// The receive buffer is full
+    /// - Address: 0x12c - 0x130
+    event_endrx: ReadWrite<u32, Event::Register>,
+    /// Transmission of the buffer is complete
+    /// - Address: 0x130 - 0x134
+    event_endtx: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    _reserved4: [u32; 1],
+    /// Anticollision has started
+    /// - Address: 0x138 - 0x13c
+    event_autocolresstarted: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    _reserved5: [u32; 3],
+    /// A collision was detected during anticollision
+    /// - Address: 0x148 - 0x14c
+    event_collision: ReadWrite<u32, Event::Register>,
+    /// The reader selected the tag
+    /// - Address: 0x14c - 0x150
+    event_selected: ReadWrite<u32, Event::Register>,
+    /// EasyDMA is ready to receive or send frames
+    /// - Address: 0x150 - 0x154
+    event_started: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    _reserved6: [u32; 43],
+    /// Shortcuts between events and tasks
+    /// - Address: 0x200 - 0x204
+    shorts: ReadWrite<u32, Shorts::Register>,
+    /// Reserved
+    _reserved7: [u32; 63],
+    /// Enable or disable interrupts
+    /// - Address: 0x300 - 0x304
+    inten: ReadWrite<u32, Interrupt::Register>,
+    /// Enable interrupts
+    /// - Address: 0x304 - 0x308
+    intenset: ReadWrite<u32, Interrupt::Register>,
+    /// Disable interrupts
+    /// - Address: 0x308 - 0x30c
+    intenclr: ReadWrite<u32, Interrupt::Register>,
+    /// Reserved
+    _reserved8: [u32; 62],
+    /// Details of the last error, cleared by writing `1`
+    /// - Address: 0x404 - 0x408
+    errorstatus: ReadWrite<u32>,
+    /// Reserved
+    _reserved9: [u32; 1],
+    /// Result of the last received frame, cleared by writing `1`
+    /// - Address: 0x40c - 0x410
+    framestatus_rx: ReadWrite<u32, FrameStatus::Register>,
+    /// Reserved
+    _reserved10: [u32; 61],
+    /// Minimum frame delay
+    /// - Address: 0x504 - 0x508
+    framedelaymin: ReadWrite<u32>,
+    /// Maximum frame delay
+    /// - Address: 0x508 - 0x50c
+    framedelaymax: ReadWrite<u32>,
+    /// How the frame delay is applied
+    /// - Address: 0x50c - 0x510
+    framedelaymode: ReadWrite<u32>,
+    /// Packet pointer for TXD and RXD data storage in RAM
+    /// - Address: 0x510 - 0x514
+    packetptr: ReadWrite<u32>,
+    /// Size of the RAM buffer allocated for RXD and TXD data
+    /// - Address: 0x514 - 0x518
+    maxlen: ReadWrite<u32>,
+    /// Configuration of outgoing frames
+    /// - Address: 0x518 - 0x51c
+    txd_frameconfig: ReadWrite<u32, TxdFrameConfig::Register>,
+    /// Size of outgoing frame
+    /// - Address: 0x51c - 0x520
+    txd_amount: ReadWrite<u32, Amount::Register>,
+    /// Configuration of incoming frames
+    /// - Address: 0x520 - 0x524
+    rxd_frameconfig: ReadWrite<u32, RxdFrameConfig::Register>,
+    /// Size of last incoming frame, including its CRC
+    /// - Address: 0x524 - 0x528
+    rxd_amount: ReadWrite<u32, Amount::Register>,
+    /// Reserved
+    _reserved11: [u32; 26],
+    /// Last bytes of the NFCID1
+    /// - Address: 0x590 - 0x594
+    nfcid1_last: ReadWrite<u32>,
+    /// Second last bytes of a double or triple size NFCID1
+    /// - Address: 0x594 - 0x598
+    nfcid1_2nd_last: ReadWrite<u32>,
+    /// Third last bytes of a triple size NFCID1
+    /// - Address: 0x598 - 0x59c
+    nfcid1_3rd_last: ReadWrite<u32>,
+    /// Reserved
+    _reserved12: [u32; 1],
+    /// NFC-A SENS_RES auto-response settings
+    /// - Address: 0x5a0 - 0x5a4
+    sensres: ReadWrite<u32, SensRes::Register>,
+    /// NFC-A SEL_RES auto-response settings
+    /// - Address: 0x5a4 - 0x5a8
+    selres: ReadWrite<u32, SelRes::Register>,
+}
+
+register_bitfields! [u32,
+    /// Start task
+    Task [
+        ENABLE 0
+    ],
+
+    /// Read event
+    Event [
+        READY 0
+    ],
+
+    /// Shortcuts
+    Shorts [
+        FIELDDETECTED_ACTIVATE 0,
+        FIELDLOST_SENSE 1
+    ],
+
+    /// Interrupts
+    Interrupt [
+        READY 0,
+        FIELDDETECTED 1,
+        FIELDLOST 2,
+        TXFRAMESTART 3,
+        TXFRAMEEND 4,
+        RXFRAMESTART 5,
+        RXFRAMEEND 6,
+        ERROR 7,
+        RXERROR 10,
+        ENDRX 11,
+        ENDTX 12,
+        AUTOCOLRESSTARTED 14,
+        COLLISION 18,
+        SELECTED 19,
+        STARTED 20
+    ],
+
+    /// Result of the last received frame
+    FrameStatus [
+        CRCERROR 0,
+        PARITYSTATUS 2,
+        OVERRUN 3
+    ],
+
+    /// Configuration of outgoing frames
+    TxdFrameConfig [
+        PARITY OFFSET(0) NUMBITS(1) [],
+        DISCARDMODE OFFSET(1) NUMBITS(1) [
+            DISCARDEND = 0,
+            DISCARDSTART = 1
+        ],
+        SOF OFFSET(2) NUMBITS(1) [],
+        CRCMODETX OFFSET(4) NUMBITS(1) []
+    ],
+
+    /// Configuration of incoming frames
+    RxdFrameConfig [
+        PARITY 0,
+        SOF 2,
+        CRCMODERX 4
+    ],
+
+    /// Size of a frame
+    Amount [
+        DATABITS OFFSET(0) NUMBITS(3),
+        DATABYTES OFFSET(3) NUMBITS(9)
+    ],
+
+    /// NFC-A SENS_RES auto-response settings
+    SensRes [
+        BITFRAMESDD OFFSET(0) NUMBITS(5) [
+            SDD00100 = 4
+        ],
+        NFCIDSIZE OFFSET(6) NUMBITS(2) [
+            SINGLE = 0,
+            DOUBLE = 1,
+            TRIPLE = 2
+        ],
+        PLATFCONFIG OFFSET(8) NUMBITS(4) []
+    ],
+
+    /// NFC-A SEL_RES auto-response settings
+    SelRes [
+        PROTOCOL OFFSET(5) NUMBITS(2) [
+            TYPE2TAG = 0
+        ]
+    ]
+];
+
+/// Type 2 Tag commands.
+const CMD_READ: u8 = 0x30;
+const CMD_HLTA: u8 = 0x50;
+
+/// The IC manufacturer code of Nordic Semiconductor, the first byte of the
+/// UID.
+const MANUFACTURER_NORDIC: u8 = 0x5f;
+
+/// Tag memory ahead of the data area: the UID, lock bytes and capability
+/// container.
+const HEADER_LEN: usize = 16;
+
+/// A `READ` addresses 4-byte pages with one byte, so the whole tag memory is
+/// at most 1 kB. The message shares it with the header and the TLV around
+/// it.
+pub const MAX_MESSAGE_LEN: usize = 256 * 4 - HEADER_LEN - 5;
+
+/// Commands are received into and responses sent from this buffer. A `READ`
+/// response is the largest frame, at 16 bytes plus the CRC.
+static mut BUFFER: [u8; 18] = [0; 18];
+
+pub struct Nfct {
+    registers: StaticRef<NfctRegisters>,
+    client: Cell<Option<&'static nfc::Client>>,
+    message: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    uid: Cell<[u8; 7]>,
+    /// Whether the driver started the high frequency crystal for a reader's
+    /// field, and so stops it again when the field is gone.
+    started_hfxo: Cell<bool>,
+}
+
+pub static mut NFCT: Nfct = Nfct::new();
+
+impl Nfct {
+    const fn new() -> Nfct {
+        Nfct {
+            registers: NFCT_BASE,
+            client: Cell::new(None),
+            message: TakeCell::empty(),
+            len: Cell::new(0),
+            uid: Cell::new([0; 7]),
+            started_hfxo: Cell::new(false),
+        }
+    }
+
+    /// The length of the TLV that wraps the message, including its
+    /// terminator. Messages of 255 bytes or more need a 3-byte length.
+    fn tlv_len(&self) -> usize {
+        let len = self.len.get();
+        if len < 0xff {
+            len + 3
+        } else {
+            len + 5
+        }
+    }
+
+    /// The size of the data area the capability container announces, which
+    /// is a multiple of 8 bytes and at least as large as a common 64-byte
+    /// tag's.
+    fn data_area_len(&self) -> usize {
+        let len = (self.tlv_len() + 7) / 8 * 8;
+        if len < 48 {
+            48
+        } else {
+            len
+        }
+    }
+
+    /// The byte at `offset` in the tag memory.
+    fn tag_byte(&self, message: &[u8], offset: usize) -> u8 {
+        let uid = self.uid.get();
+        let len = self.len.get();
+        match offset {
+            0...2 => uid[offset],
+            3 => 0x88 ^ uid[0] ^ uid[1] ^ uid[2],
+            4...7 => uid[offset - 1],
+            8 => uid[3] ^ uid[4] ^ uid[5] ^ uid[6],
+            9 => 0x48,
+            // Lock bytes: every page is read-only.
+            10 | 11 => 0xff,
+            // Capability container: NDEF version 1.0, the data area size in
+            // units of 8 bytes, and read-only access.
+            12 => 0xe1,
+            13 => 0x10,
+            14 => (self.data_area_len() / 8) as u8,
+            15 => 0x0f,
+            _ => {
+                let offset = offset - HEADER_LEN;
+                let (header, start) = if len < 0xff {
+                    ([0x03, len as u8, 0, 0], 2)
+                } else {
+                    ([0x03, 0xff, (len >> 8) as u8, len as u8], 4)
+                };
+                if offset < start {
+                    header[offset]
+                } else if offset < start + len {
+                    message[offset - start]
+                } else if offset == start + len {
+                    0xfe
+                } else {
+                    0x00
+                }
+            }
+        }
+    }
+
+    /// Answer a `READ` of the 16 bytes from `page` on, wrapping around the
+    /// end of the tag memory.
+    fn read_pages(&self, page: usize) {
+        let memory_len = HEADER_LEN + self.data_area_len();
+        if page * 4 >= memory_len {
+            self.nack();
+            return;
+        }
+        self.message.map(|message| unsafe {
+            for i in 0..16 {
+                BUFFER[i] = self.tag_byte(message, (page * 4 + i) % memory_len);
+            }
+        });
+        self.transmit(16);
+    }
+
+    fn transmit(&self, len: usize) {
+        let regs = &*self.registers;
+        unsafe {
+            regs.packetptr.set(BUFFER.as_ptr() as u32);
+        }
+        regs.txd_frameconfig.write(
+            TxdFrameConfig::PARITY::SET
+                + TxdFrameConfig::DISCARDMODE::DISCARDSTART
+                + TxdFrameConfig::SOF::SET
+                + TxdFrameConfig::CRCMODETX::SET,
+        );
+        regs.txd_amount.write(Amount::DATABYTES.val(len as u32));
+        regs.task_starttx.write(Task::ENABLE::SET);
+    }
+
+    /// Refuse a command with a NACK, a 4-bit frame of zeros. Short frames
+    /// have neither parity nor a CRC.
+    fn nack(&self) {
+        let regs = &*self.registers;
+        unsafe {
+            BUFFER[0] = 0;
+            regs.packetptr.set(BUFFER.as_ptr() as u32);
+        }
+        regs.txd_frameconfig
+            .write(TxdFrameConfig::DISCARDMODE::DISCARDSTART + TxdFrameConfig::SOF::SET);
+        regs.txd_amount
+            .write(Amount::DATABITS.val(4) + Amount::DATABYTES.val(0));
+        regs.task_starttx.write(Task::ENABLE::SET);
+    }
+
+    /// Wait for the reader's next command.
+    fn receive(&self) {
+        let regs = &*self.registers;
+        unsafe {
+            regs.packetptr.set(BUFFER.as_ptr() as u32);
+            regs.maxlen.set(BUFFER.len() as u32);
+        }
+        regs.rxd_frameconfig.write(
+            RxdFrameConfig::PARITY::SET + RxdFrameConfig::SOF::SET + RxdFrameConfig::CRCMODERX::SET,
+        );
+        regs.task_enablerxdata.write(Task::ENABLE::SET);
+    }
+
+    /// Answer the command the reader has sent.
+    fn respond(&self) {
+        let regs = &*self.registers;
+        let status = regs.framestatus_rx.get();
+        regs.framestatus_rx.set(status);
+        let len = regs.rxd_amount.read(Amount::DATABYTES) as usize;
+        // A tag stays silent on a corrupted frame, and the reader retries.
+        if status != 0 || len < 3 {
+            self.receive();
+            return;
+        }
+        let (command, argument) = unsafe { (BUFFER[0], BUFFER[1]) };
+        match command {
+            CMD_READ => self.read_pages(argument as usize),
+            CMD_HLTA => {
+                regs.task_gosleep.write(Task::ENABLE::SET);
+            }
+            _ => self.nack(),
+        }
+    }
+
+    /// Start the high frequency crystal the peripheral needs while it talks
+    /// to a reader, unless it is already running, and activate the
+    /// peripheral.
+    fn activate(&self) {
+        let regs = &*self.registers;
+        let clock = unsafe { &clock::CLOCK };
+        let hfxo_running = || {
+            clock.high_running()
+                && match clock.high_source() {
+                    clock::HighClockSource::XTAL => true,
+                    _ => false,
+                }
+        };
+        if !hfxo_running() {
+            clock.high_start();
+            self.started_hfxo.set(true);
+            // The crystal starts within a millisecond, well before a reader
+            // sends its first command.
+            while !hfxo_running() {}
+        }
+        regs.task_activate.write(Task::ENABLE::SET);
+    }
+
+    fn release_hfxo(&self) {
+        if self.started_hfxo.get() {
+            self.started_hfxo.set(false);
+            unsafe {
+                clock::CLOCK.high_stop();
+            }
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.event_fielddetected.is_set(Event::READY) {
+            regs.event_fielddetected.write(Event::READY::CLEAR);
+            self.activate();
+            self.client.get().map(|client| client.field_detected());
+        }
+
+        if regs.event_selected.is_set(Event::READY) {
+            regs.event_selected.write(Event::READY::CLEAR);
+            self.receive();
+        }
+
+        if regs.event_rxframeend.is_set(Event::READY) {
+            regs.event_rxframeend.write(Event::READY::CLEAR);
+            self.respond();
+        }
+
+        if regs.event_rxerror.is_set(Event::READY) {
+            regs.event_rxerror.write(Event::READY::CLEAR);
+            let status = regs.framestatus_rx.get();
+            regs.framestatus_rx.set(status);
+            self.receive();
+        }
+
+        if regs.event_txframeend.is_set(Event::READY) {
+            regs.event_txframeend.write(Event::READY::CLEAR);
+            self.receive();
+        }
+
+        if regs.event_error.is_set(Event::READY) {
+            regs.event_error.write(Event::READY::CLEAR);
+            let status = regs.errorstatus.get();
+            regs.errorstatus.set(status);
+        }
+
+        // The shortcut has already returned the peripheral to sensing.
+        if regs.event_fieldlost.is_set(Event::READY) {
+            regs.event_fieldlost.write(Event::READY::CLEAR);
+            self.release_hfxo();
+            self.client.get().map(|client| client.field_lost());
+        }
+    }
+}
+
+impl nfc::NdefTag for Nfct {
+    fn set_client(&self, client: &'static nfc::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn max_message_len(&self) -> usize {
+        MAX_MESSAGE_LEN
+    }
+
+    fn enable(
+        &self,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.message.is_some() {
+            return (ReturnCode::EBUSY, Some(message));
+        }
+        if len > MAX_MESSAGE_LEN || len > message.len() {
+            return (ReturnCode::ESIZE, Some(message));
+        }
+        self.message.replace(message);
+        self.len.set(len);
+
+        let id = unsafe { ficr::FICR_INSTANCE.device_id() };
+        let mut uid = [MANUFACTURER_NORDIC, 0, 0, 0, 0, 0, 0];
+        for i in 1..7 {
+            uid[i] = (id >> ((i - 1) * 8)) as u8;
+        }
+        self.uid.set(uid);
+
+        let regs = &*self.registers;
+        regs.nfcid1_2nd_last
+            .set((uid[0] as u32) << 16 | (uid[1] as u32) << 8 | uid[2] as u32);
+        regs.nfcid1_last.set(
+            (uid[3] as u32) << 24 | (uid[4] as u32) << 16 | (uid[5] as u32) << 8 | uid[6] as u32,
+        );
+        regs.sensres
+            .write(SensRes::BITFRAMESDD::SDD00100 + SensRes::NFCIDSIZE::DOUBLE);
+        regs.selres.write(SelRes::PROTOCOL::TYPE2TAG);
+
+        regs.shorts.write(Shorts::FIELDLOST_SENSE::SET);
+        regs.intenset.write(
+            Interrupt::FIELDDETECTED::SET
+                + Interrupt::FIELDLOST::SET
+                + Interrupt::SELECTED::SET
+                + Interrupt::RXFRAMEEND::SET
+                + Interrupt::RXERROR::SET
+                + Interrupt::TXFRAMEEND::SET
+                + Interrupt::ERROR::SET,
+        );
+        regs.task_sense.write(Task::ENABLE::SET);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn disable(&self) -> Option<&'static mut [u8]> {
+        self.message.take().map(|message| {
+            let regs = &*self.registers;
+            regs.intenclr.set(0xffffffff);
+            regs.shorts.set(0);
+            regs.task_disable.write(Task::ENABLE::SET);
+            self.release_hfxo();
+            message
+        })
+    }
+}
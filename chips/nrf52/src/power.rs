@@ -0,0 +1,131 @@
+//! Power management (POWER), nRF52
+//!
+//! Minimal implementation exposing the reset reason, the general purpose
+//! retention registers, which keep their value across all resets except
+//! power-on and brown-out reset, and System OFF.
+
+use kernel::common::regs::{ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::reset::{ResetInfo, ResetReason};
+
+const POWER_BASE: StaticRef<PowerRegisters> =
+    unsafe { StaticRef::new(0x40000400 as *const PowerRegisters) };
+
+#[repr(C)]
+struct PowerRegisters {
+    /// Reset reason
+    /// - Address: 0x400 - 0x404
+    resetreas: ReadWrite<u32, ResetReas::Register>,
+    /// Reserved
+    _reserved0: [u32; 63],
+    /// Enter System OFF
+    /// - Address: 0x500 - 0x504
+    systemoff: WriteOnly<u32, SystemOff::Register>,
+    /// Reserved
+    _reserved1: [u32; 6],
+    /// General purpose retention register
+    /// - Address: 0x51C - 0x520
+    gpregret: ReadWrite<u32, GpRegRet::Register>,
+    /// General purpose retention register
+    /// - Address: 0x520 - 0x524
+    gpregret2: ReadWrite<u32, GpRegRet::Register>,
+}
+
+register_bitfields! [u32,
+    /// Reset reason. Bits are cumulative and cleared by writing `1`. If none
+    /// are set the chip was reset by the power-on or brown-out reset.
+    ResetReas [
+        /// Reset from the pin reset
+        RESETPIN 0,
+        /// Reset from the watchdog
+        DOG 1,
+        /// Reset from a soft reset (SYSRESETREQ)
+        SREQ 2,
+        /// Reset from CPU lock-up
+        LOCKUP 3,
+        /// Wake up from System OFF by DETECT from GPIO
+        OFF 16,
+        /// Wake up from System OFF by ANADETECT from LPCOMP
+        LPCOMP 17,
+        /// Wake up from System OFF by entering debug interface mode
+        DIF 18,
+        /// Wake up from System OFF by NFC field detect
+        NFC 19
+    ],
+    /// Enter System OFF
+    SystemOff [
+        SYSTEMOFF 0
+    ],
+    /// General purpose retention register
+    GpRegRet [
+        GPREGRET OFFSET(0) NUMBITS(8)
+    ]
+];
+
+pub struct Power {
+    registers: StaticRef<PowerRegisters>,
+}
+
+pub static mut POWER: Power = Power::new();
+
+impl Power {
+    const fn new() -> Power {
+        Power {
+            registers: POWER_BASE,
+        }
+    }
+
+    /// Turn the chip off until it is woken by a reset, a GPIO pin configured
+    /// for DETECT, or the field of an NFC reader if the NFCT peripheral is
+    /// sensing. Waking restarts the chip from reset, which reports
+    /// `ResetReason::Wakeup`; only the retained registers and RAM configured
+    /// for retention survive.
+    pub fn system_off(&self) -> ! {
+        let regs = &*self.registers;
+        regs.systemoff.write(SystemOff::SYSTEMOFF::SET);
+        // Entering System OFF takes effect after a few cycles.
+        loop {}
+    }
+}
+
+impl ResetInfo for Power {
+    fn reset_reason(&self) -> ResetReason {
+        let regs = &*self.registers;
+        let reas = regs.resetreas.extract();
+        // Clear the latched causes so the next reset reports only itself.
+        regs.resetreas.set(reas.get());
+
+        if reas.is_set(ResetReas::DOG) {
+            ResetReason::Watchdog
+        } else if reas.is_set(ResetReas::LOCKUP) {
+            ResetReason::Lockup
+        } else if reas.is_set(ResetReas::SREQ) {
+            ResetReason::Software
+        } else if reas.is_set(ResetReas::RESETPIN) {
+            ResetReason::ExternalPin
+        } else if reas.is_set(ResetReas::OFF)
+            || reas.is_set(ResetReas::LPCOMP)
+            || reas.is_set(ResetReas::NFC)
+        {
+            ResetReason::Wakeup
+        } else if reas.get() == 0 {
+            ResetReason::PowerOn
+        } else {
+            ResetReason::Unknown
+        }
+    }
+
+    fn read_retained(&self) -> u16 {
+        let regs = &*self.registers;
+        (regs.gpregret.read(GpRegRet::GPREGRET) | (regs.gpregret2.read(GpRegRet::GPREGRET) << 8))
+            as u16
+    }
+
+    fn write_retained(&self, value: u16) {
+        let regs = &*self.registers;
+        regs.gpregret
+            .write(GpRegRet::GPREGRET.val(value as u32 & 0xff));
+        regs.gpregret2
+            .write(GpRegRet::GPREGRET.val((value >> 8) as u32));
+    }
+}
@@ -1,4 +1,4 @@
-//! Radio driver, Bluetooth Low Energy, NRF52
+//! Radio driver, Bluetooth Low Energy and Enhanced ShockBurst, NRF52
 //!
 //! The generic radio configuration i.e., not specific to Bluetooth are functions and similar which
 //! do not start with `ble`. Moreover, Bluetooth Low Energy specific radio configuration
@@ -32,14 +32,40 @@
 //! * Payload - 2 to 255 bytes
 //!
 //! * CRC - 3 bytes
+//!
+//! ### Enhanced ShockBurst
+//!
+//! ESB packets use the same fields, configured differently:
+//!
+//! * Base and prefix - 3 to 5 bytes of address
+//!
+//! * Length - 6 bits
+//!
+//! * S1 - 3 bits, the 2-bit packet ID followed by the no-acknowledgement bit
+//!
+//! * Payload - 0 to 32 bytes
+//!
+//! * CRC - 2 bytes
+//!
+//! The radio is disabled after each packet, and its `DISABLED` interrupt moves
+//! between sending, waiting for an acknowledgement and receiving.
+//!
+//! ### RF Frontend
+//!
+//! A board with an external PA, LNA or antenna switch sets its frontend with
+//! `set_frontend`. The radio switches it to transmit or receive whenever it
+//! enables its transmitter or receiver, and to sleep when it powers off.
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
 use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::esb;
+use kernel::hil::rf_frontend::RfFrontend;
 use kernel::ReturnCode;
 use nrf5x;
 use nrf5x::constants::TxPower;
@@ -529,11 +555,48 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+/// The length and S1 bytes in front of the payload of an ESB packet in RAM.
+const ESB_HEADER_LEN: usize = 2;
+
+const ESB_CRCINIT: u32 = 0xffff;
+const ESB_CRCPOLY: u32 = 0x11021;
+
+static mut ESB_TX: [u8; ESB_HEADER_LEN + esb::MAX_PAYLOAD_LEN] =
+    [0; ESB_HEADER_LEN + esb::MAX_PAYLOAD_LEN];
+static mut ESB_RX: [u8; ESB_HEADER_LEN + esb::MAX_PAYLOAD_LEN] =
+    [0; ESB_HEADER_LEN + esb::MAX_PAYLOAD_LEN];
+static mut ESB_ACK: [u8; ESB_HEADER_LEN] = [0; ESB_HEADER_LEN];
+
+#[derive(Copy, Clone, PartialEq)]
+enum EsbState {
+    Off,
+    Tx,
+    AckWait,
+    Rx,
+    AckTx,
+}
+
 pub struct Radio {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: Cell<Option<&'static ble_advertising::RxClient>>,
     tx_client: Cell<Option<&'static ble_advertising::TxClient>>,
+    esb_rx_client: Cell<Option<&'static esb::RxClient>>,
+    esb_tx_client: Cell<Option<&'static esb::TxClient>>,
+    frontend: Cell<Option<&'static RfFrontend>>,
+    esb_state: Cell<EsbState>,
+    esb_address: Cell<[u8; 5]>,
+    esb_address_len: Cell<usize>,
+    esb_channel: Cell<u8>,
+    esb_rate: Cell<esb::DataRate>,
+    esb_auto_ack: Cell<bool>,
+    /// The packet ID of the last packet sent
+    esb_pid: Cell<u8>,
+    /// Whether ESB_TX holds a packet to retransmit
+    esb_sent: Cell<bool>,
+    /// The packet ID and CRC of the last packet received, to drop it if it
+    /// is sent again
+    esb_last_rx: Cell<Option<(u8, u32)>>,
 }
 
 pub static mut RADIO: Radio = Radio::new();
@@ -545,17 +608,35 @@ impl Radio {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: Cell::new(None),
             tx_client: Cell::new(None),
+            esb_rx_client: Cell::new(None),
+            esb_tx_client: Cell::new(None),
+            frontend: Cell::new(None),
+            esb_state: Cell::new(EsbState::Off),
+            esb_address: Cell::new([0xe7; 5]),
+            esb_address_len: Cell::new(5),
+            esb_channel: Cell::new(2),
+            esb_rate: Cell::new(esb::DataRate::Rate2Mbit),
+            esb_auto_ack: Cell::new(true),
+            esb_pid: Cell::new(0),
+            esb_sent: Cell::new(false),
+            esb_last_rx: Cell::new(None),
         }
     }
 
+    pub fn set_frontend(&self, frontend: &'static RfFrontend) {
+        self.frontend.set(Some(frontend));
+    }
+
     fn tx(&self) {
         let regs = &*self.registers;
+        self.frontend.get().map(|frontend| frontend.transmit());
         regs.event_ready.write(Event::READY::CLEAR);
         regs.task_txen.write(Task::ENABLE::SET);
     }
 
     fn rx(&self) {
         let regs = &*self.registers;
+        self.frontend.get().map(|frontend| frontend.receive());
         regs.event_ready.write(Event::READY::CLEAR);
         regs.task_rxen.write(Task::ENABLE::SET);
     }
@@ -580,6 +661,7 @@ impl Radio {
     fn radio_off(&self) {
         let regs = &*self.registers;
         regs.power.write(Task::ENABLE::CLEAR);
+        self.frontend.get().map(|frontend| frontend.sleep());
     }
 
     fn set_tx_power(&self) {
@@ -597,6 +679,10 @@ impl Radio {
     #[inline(never)]
     pub fn handle_interrupt(&self) {
         let regs = &*self.registers;
+        if self.esb_state.get() != EsbState::Off {
+            self.esb_handle_interrupt();
+            return;
+        }
         self.disable_all_interrupts();
 
         if regs.event_ready.is_set(Event::READY) {
@@ -782,6 +868,168 @@ impl Radio {
             .write(Frequency::FREQUENCY.val(channel as u32));
     }
 
+    fn esb_initialize(&self) {
+        let regs = &*self.registers;
+        self.radio_on();
+
+        self.set_tx_power();
+        regs.mode.write(match self.esb_rate.get() {
+            esb::DataRate::Rate1Mbit => Mode::MODE::NRF_1MBIT,
+            esb::DataRate::Rate2Mbit => Mode::MODE::NRF_2MBIT,
+            esb::DataRate::Rate250Kbit => Mode::MODE::NRF_250KBIT,
+        });
+        regs.frequency
+            .write(Frequency::FREQUENCY.val(self.esb_channel.get() as u32));
+
+        self.esb_set_address();
+        self.set_tx_address();
+        self.set_rx_address();
+
+        regs.pcnf0.write(
+            PacketConfiguration0::LFLEN.val(6)
+                + PacketConfiguration0::S0LEN.val(0)
+                + PacketConfiguration0::S1LEN.val(3)
+                + PacketConfiguration0::S1INCL::CLEAR
+                + PacketConfiguration0::PLEN::EIGHT,
+        );
+        regs.pcnf1.write(
+            PacketConfiguration1::WHITEEN::DISABLED
+                + PacketConfiguration1::ENDIAN::BIG
+                + PacketConfiguration1::BALEN.val(self.esb_address_len.get() as u32 - 1)
+                + PacketConfiguration1::STATLEN::CLEAR
+                + PacketConfiguration1::MAXLEN.val(esb::MAX_PAYLOAD_LEN as u32),
+        );
+
+        regs.crccnf
+            .write(CrcConfiguration::LEN::TWO + CrcConfiguration::SKIPADDR::INCLUDE);
+        regs.crcinit.set(ESB_CRCINIT);
+        regs.crcpoly.set(ESB_CRCPOLY);
+
+        regs.shorts
+            .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+        regs.intenset.write(Interrupt::DISABLED::SET);
+    }
+
+    // The radio sends the base address starting from the lowest of the BALEN
+    // most significant bytes of BASE0, then the prefix, each byte least
+    // significant bit first. ESB sends the most significant bit first, so the
+    // bits of each byte are reversed.
+    fn esb_set_address(&self) {
+        let regs = &*self.registers;
+        let address = self.esb_address.get();
+        let base_len = self.esb_address_len.get() - 1;
+        let mut base = 0;
+        for i in 0..base_len {
+            base |= (reverse_bits(address[i]) as u32) << (8 * (4 - base_len + i));
+        }
+        regs.base0.set(base);
+        regs.prefix0
+            .write(Prefix0::AP0.val(reverse_bits(address[base_len]) as u32));
+    }
+
+    fn esb_start_tx(&self) {
+        let regs = &*self.registers;
+        self.esb_initialize();
+        unsafe {
+            regs.packetptr.set(ESB_TX.as_ptr() as u32);
+        }
+        if self.esb_auto_ack.get() {
+            // Turn straight round to receive the acknowledgement
+            regs.shorts.modify(Shortcut::DISABLED_RXEN::SET);
+        }
+        self.esb_state.set(EsbState::Tx);
+        self.tx();
+    }
+
+    fn esb_transmit_done(&self) {
+        self.radio_off();
+        self.esb_state.set(EsbState::Off);
+        self.esb_tx_client
+            .get()
+            .map(|client| client.transmit_done(ReturnCode::SUCCESS));
+    }
+
+    fn esb_handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if !regs.event_disabled.is_set(Event::READY) {
+            return;
+        }
+        regs.event_disabled.write(Event::READY::CLEAR);
+        let crc_ok = regs.crcstatus.is_set(Event::READY);
+
+        match self.esb_state.get() {
+            EsbState::Tx => {
+                if self.esb_auto_ack.get() {
+                    // The receiver is already ramping up, and starts once
+                    // ready
+                    regs.shorts.modify(Shortcut::DISABLED_RXEN::CLEAR);
+                    self.frontend.get().map(|frontend| frontend.receive());
+                    unsafe {
+                        regs.packetptr.set(ESB_RX.as_ptr() as u32);
+                    }
+                    self.esb_state.set(EsbState::AckWait);
+                } else {
+                    self.esb_transmit_done();
+                }
+            }
+            EsbState::AckWait => {
+                let pid = unsafe { (ESB_RX[1] >> 1) & 0x3 };
+                if crc_ok && pid == self.esb_pid.get() {
+                    self.esb_transmit_done();
+                } else {
+                    self.rx();
+                }
+            }
+            EsbState::Rx => {
+                if !crc_ok {
+                    self.rx();
+                    return;
+                }
+                let (len, pid, no_ack) = unsafe {
+                    (
+                        ESB_RX[0] as usize,
+                        (ESB_RX[1] >> 1) & 0x3,
+                        ESB_RX[1] & 0x1 == 1,
+                    )
+                };
+                let acking = self.esb_auto_ack.get() && !no_ack;
+                if acking {
+                    // The transmitter only waits a short while, so send the
+                    // acknowledgement before passing the packet on
+                    unsafe {
+                        ESB_ACK[0] = 0;
+                        ESB_ACK[1] = (pid << 1) | 1;
+                        regs.packetptr.set(ESB_ACK.as_ptr() as u32);
+                    }
+                    self.esb_state.set(EsbState::AckTx);
+                    self.tx();
+                }
+
+                let crc = regs.rxcrc.read(ReceiveCrc::CRC);
+                if self.esb_last_rx.get() != Some((pid, crc)) {
+                    self.esb_last_rx.set(Some((pid, crc)));
+                    let len = cmp::min(len, esb::MAX_PAYLOAD_LEN);
+                    self.esb_rx_client.get().map(|client| unsafe {
+                        client.receive(&ESB_RX[ESB_HEADER_LEN..ESB_HEADER_LEN + len])
+                    });
+                }
+
+                // Unless the client stopped the radio
+                if !acking && self.esb_state.get() == EsbState::Rx {
+                    self.rx();
+                }
+            }
+            EsbState::AckTx => {
+                unsafe {
+                    regs.packetptr.set(ESB_RX.as_ptr() as u32);
+                }
+                self.esb_state.set(EsbState::Rx);
+                self.rx();
+            }
+            EsbState::Off => {}
+        }
+    }
+
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3 TRANSMITTER CHARACTERISTICS
     // Minimum Output Power : -20dBm
     // Maximum Output Power : +10dBm
@@ -838,3 +1086,111 @@ impl ble_advertising::BleConfig for Radio {
         }
     }
 }
+
+impl esb::Esb for Radio {
+    fn set_transmit_client(&self, client: &'static esb::TxClient) {
+        self.esb_tx_client.set(Some(client));
+    }
+
+    fn set_receive_client(&self, client: &'static esb::RxClient) {
+        self.esb_rx_client.set(Some(client));
+    }
+
+    fn set_address(&self, address: &[u8]) -> ReturnCode {
+        if address.len() < 3 || address.len() > 5 {
+            return ReturnCode::EINVAL;
+        }
+        if self.esb_state.get() != EsbState::Off {
+            return ReturnCode::EBUSY;
+        }
+        let mut stored = [0; 5];
+        stored[..address.len()].copy_from_slice(address);
+        self.esb_address.set(stored);
+        self.esb_address_len.set(address.len());
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, channel: u8, rate: esb::DataRate) -> ReturnCode {
+        if channel > 100 {
+            return ReturnCode::EINVAL;
+        }
+        if self.esb_state.get() != EsbState::Off {
+            return ReturnCode::EBUSY;
+        }
+        self.esb_channel.set(channel);
+        self.esb_rate.set(rate);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_auto_ack(&self, enabled: bool) {
+        self.esb_auto_ack.set(enabled);
+    }
+
+    fn transmit(&self, payload: &[u8]) -> ReturnCode {
+        if payload.len() > esb::MAX_PAYLOAD_LEN {
+            return ReturnCode::ESIZE;
+        }
+        if self.esb_state.get() != EsbState::Off {
+            return ReturnCode::EBUSY;
+        }
+        let pid = (self.esb_pid.get() + 1) & 0x3;
+        self.esb_pid.set(pid);
+        unsafe {
+            ESB_TX[0] = payload.len() as u8;
+            ESB_TX[1] = (pid << 1) | !self.esb_auto_ack.get() as u8;
+            ESB_TX[ESB_HEADER_LEN..ESB_HEADER_LEN + payload.len()].copy_from_slice(payload);
+        }
+        self.esb_sent.set(true);
+        self.esb_start_tx();
+        ReturnCode::SUCCESS
+    }
+
+    fn retransmit(&self) -> ReturnCode {
+        if !self.esb_sent.get() {
+            return ReturnCode::EINVAL;
+        }
+        if self.esb_state.get() != EsbState::Off {
+            return ReturnCode::EBUSY;
+        }
+        self.esb_start_tx();
+        ReturnCode::SUCCESS
+    }
+
+    fn start_receive(&self) -> ReturnCode {
+        if self.esb_state.get() != EsbState::Off {
+            return ReturnCode::EBUSY;
+        }
+        let regs = &*self.registers;
+        self.esb_initialize();
+        unsafe {
+            regs.packetptr.set(ESB_RX.as_ptr() as u32);
+        }
+        self.esb_state.set(EsbState::Rx);
+        self.rx();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) {
+        if self.esb_state.get() == EsbState::Off {
+            return;
+        }
+        let regs = &*self.registers;
+        self.disable_all_interrupts();
+        regs.shorts.set(0);
+        regs.task_disable.write(Task::ENABLE::SET);
+        while regs.state.get() != nrf5x::constants::RADIO_STATE_DISABLE {}
+        regs.event_disabled.write(Event::READY::CLEAR);
+        self.radio_off();
+        self.esb_state.set(EsbState::Off);
+    }
+}
+
+fn reverse_bits(byte: u8) -> u8 {
+    let mut reversed = 0;
+    for i in 0..8 {
+        if byte & (1 << i) != 0 {
+            reversed |= 0x80 >> i;
+        }
+    }
+    reversed
+}
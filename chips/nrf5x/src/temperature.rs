@@ -127,7 +127,9 @@ impl Temp {
 
         // get temperature
         // Result of temperature measurement in °C, 2's complement format, 0.25 °C
-        let temp = (regs.temp.get() / 4) * 100;
+        // steps. Convert to hundredths of a degree without dropping the
+        // fractional part or the sign.
+        let temp = (regs.temp.get() as i32) * 25;
 
         // stop measurement
         regs.task_stop.write(Task::ENABLE::SET);
@@ -287,6 +287,15 @@ impl TimerAlarm {
         self.client.set(Some(client));
     }
 
+    /// Start counting at the full 16 MHz rate of the high frequency clock,
+    /// using all 32 bits of the counter.
+    pub fn start(&self) {
+        self.registers.mode.set(0);
+        self.registers.bitmode.write(Bitmode::BITMODE::Bit32);
+        self.registers.prescaler.set(0);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+    }
+
     pub fn handle_interrupt(&self) {
         self.clear_alarm();
         self.client.get().map(|client| {
@@ -316,7 +325,7 @@ impl TimerAlarm {
 }
 
 impl hil::time::Time for TimerAlarm {
-    type Frequency = hil::time::Freq16KHz;
+    type Frequency = hil::time::Freq16MHz;
 
     fn disable(&self) {
         self.disable_interrupts();
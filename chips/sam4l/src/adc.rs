@@ -6,7 +6,9 @@
 //!
 //! - are 12 bits
 //! - use the ground pad as the negative reference
-//! - use a VCC/2 positive reference
+//! - use a VCC/2 positive reference by default, or else the internal 1V
+//!   reference or the external reference on ADVREFP
+//! - have a gain of 0.5, so the full scale is twice the reference
 //! - are right justified
 //!
 //! Samples can either be collected individually or continuously at a specified
@@ -112,6 +114,7 @@ pub struct Adc {
     continuous: Cell<bool>,
     dma_running: Cell<bool>,
     cpu_clock: Cell<bool>,
+    reference: Cell<hil::adc::Reference>,
 
     // timer fire counting for slow sampling rates
     timer_repeats: Cell<u8>,
@@ -365,6 +368,7 @@ impl Adc {
             continuous: Cell::new(false),
             dma_running: Cell::new(false),
             cpu_clock: Cell::new(false),
+            reference: Cell::new(hil::adc::Reference::Supply),
 
             // timer repeating state for slow sampling rates
             timer_repeats: Cell::new(0),
@@ -543,9 +547,13 @@ impl Adc {
             }
 
             // configure the ADC
-            let mut cfg_val = Configuration::PRESCAL.val(clock_divisor)
-                + Configuration::SPEED::ksps300
-                + Configuration::REFSEL::VccX0p5;
+            let refsel = match self.reference.get() {
+                hil::adc::Reference::Internal => Configuration::REFSEL::Internal1V,
+                hil::adc::Reference::Supply => Configuration::REFSEL::VccX0p5,
+                hil::adc::Reference::External => Configuration::REFSEL::ExternalRef1,
+            };
+            let mut cfg_val =
+                Configuration::PRESCAL.val(clock_divisor) + Configuration::SPEED::ksps300 + refsel;
 
             if self.cpu_clock.get() {
                 cfg_val += Configuration::CLKSEL::ApbClock
@@ -801,6 +809,43 @@ impl hil::adc::Adc for Adc {
     }
 }
 
+/// Implements reference selection for the ADC.
+impl hil::adc::AdcReference for Adc {
+    /// Select the reference for later samples.
+    ///
+    /// - `reference`: the internal 1V reference, VCC/2, or the external
+    ///   reference on ADVREFP
+    fn set_reference(&self, reference: hil::adc::Reference) -> ReturnCode {
+        if self.active.get() {
+            return ReturnCode::EBUSY;
+        }
+        if reference != self.reference.get() {
+            self.reference.set(reference);
+            // Force the next sample to reconfigure the ADC.
+            self.adc_clk_freq.set(0);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn get_reference(&self) -> hil::adc::Reference {
+        self.reference.get()
+    }
+
+    fn get_resolution_bits(&self) -> usize {
+        12
+    }
+
+    /// With a gain of 0.5, the full scale is twice the reference voltage.
+    /// The supply reference is VCC/2, so its full scale is VCC.
+    fn get_full_scale_uv(&self, reference_uv: u32) -> u32 {
+        match self.reference.get() {
+            hil::adc::Reference::Internal => 2_000_000,
+            hil::adc::Reference::Supply => reference_uv,
+            hil::adc::Reference::External => reference_uv.saturating_mul(2),
+        }
+    }
+}
+
 /// Implements an ADC capable of continuous sampling
 impl hil::adc::AdcHighSpeed for Adc {
     /// Capture buffered samples from the ADC continuously at a given
@@ -29,10 +29,10 @@ struct BscifRegisters {
     bgctrl: ReadWrite<u32, BandgapControl::Register>,
     bgsr: ReadOnly<u32, BandgapStatus::Register>,
     _reserved3: [u32; 4],
-    br0: ReadOnly<u32, Backup::Register>,
-    br1: ReadOnly<u32, Backup::Register>,
-    br2: ReadOnly<u32, Backup::Register>,
-    br3: ReadOnly<u32, Backup::Register>,
+    br0: ReadWrite<u32, Backup::Register>,
+    br1: ReadWrite<u32, Backup::Register>,
+    br2: ReadWrite<u32, Backup::Register>,
+    br3: ReadWrite<u32, Backup::Register>,
 }
 
 register_bitfields![u32,
@@ -382,3 +382,18 @@ pub unsafe fn disable_rc_1mhz() {
     // Wait for the RC1M to be disabled
     while BSCIF.rc1mcr.is_set(RC1MClockConfig::CLKOEN) {}
 }
+
+/// Read backup register 0. The backup registers keep their contents across
+/// all resets except power-on and brown-out resets.
+pub fn read_backup_register() -> u32 {
+    BSCIF.br0.get()
+}
+
+/// Write backup register 0.
+pub fn write_backup_register(value: u32) {
+    // Unlock the BSCIF::BR0 register
+    BSCIF
+        .unlock
+        .write(Unlock::KEY.val(0xAA) + Unlock::ADDR.val(0x78));
+    BSCIF.br0.set(value);
+}
@@ -12,6 +12,8 @@ use flashcalw;
 use gpio;
 use i2c;
 use kernel::common::deferred_call;
+use kernel::common::interrupt_budget;
+use kernel::deadline;
 use kernel::Chip;
 use pm;
 use spi;
@@ -79,7 +81,7 @@ impl Chip for Sam4l {
                         Task::Flashcalw => flashcalw::FLASH_CONTROLLER.handle_interrupt(),
                     }
                 } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
-                    match interrupt {
+                    interrupt_budget::INTERRUPT_BUDGET.measure(interrupt, || match interrupt {
                         ASTALARM => ast::AST.handle_interrupt(),
 
                         USART0 => usart::USART0.handle_interrupt(),
@@ -139,7 +141,7 @@ impl Chip for Sam4l {
                         _ => {
                             panic!("unhandled interrupt {}", interrupt);
                         }
-                    }
+                    });
                     let n = cortexm4::nvic::Nvic::new(interrupt);
                     n.clear_pending();
                     n.enable();
@@ -163,7 +165,9 @@ impl Chip for Sam4l {
     }
 
     fn sleep(&self) {
-        if pm::deep_sleep_ready() {
+        let wakeup_in_time = deadline::wakeup_tolerance_us()
+            .map_or(true, |tolerance| tolerance >= pm::DEEP_SLEEP_WAKEUP_US);
+        if pm::deep_sleep_ready() && wakeup_in_time {
             unsafe {
                 cortexm4::scb::set_sleepdeep();
             }
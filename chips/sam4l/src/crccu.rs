@@ -47,6 +47,10 @@
 //   publish the max buffer size the unit can handle.
 //
 // - Support continuous-mode CRC
+//
+// Calculations over several buffers keep the unit enabled between updates,
+// without resetting the intermediate CRC value, and read the result when
+// finalized.
 
 use core::cell::Cell;
 use kernel::common::regs::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
@@ -228,6 +232,8 @@ pub struct Crccu<'a> {
     client: Option<&'a crc::Client>,
     state: Cell<State>,
     alg: Cell<CrcAlg>,
+    // Whether a calculation started with `init()` is in progress
+    streaming: Cell<bool>,
 
     // Guaranteed room for a Descriptor with 512-byte alignment.
     // (Can we do this statically instead?)
@@ -243,11 +249,12 @@ impl<'a> Crccu<'a> {
             client: None,
             state: Cell::new(State::Invalid),
             alg: Cell::new(CrcAlg::Crc32C),
+            streaming: Cell::new(false),
             descriptor_space: [0; DSCR_RESERVE],
         }
     }
 
-    fn init(&self) {
+    fn init_descriptor(&self) {
         if self.state.get() == State::Invalid {
             self.set_descriptor(0, TCR::default(), 0);
             self.state.set(State::Initialized);
@@ -257,7 +264,7 @@ impl<'a> Crccu<'a> {
     /// Enable the CRCCU's clocks and interrupt
     fn enable(&self) {
         if self.state.get() != State::Enabled {
-            self.init();
+            self.init_descriptor();
             // see "10.7.4 Clock Mask"
             enable_clock(Clock::HSB(HSBClock::CRCCU));
             enable_clock(Clock::PBB(PBBClock::CRCCU));
@@ -316,7 +323,17 @@ impl<'a> Crccu<'a> {
         if regs.dmaisr.is_set(DmaInterrupt::DMA) {
             // A DMA transfer has completed
 
-            if self.get_tcr().interrupt_enabled() {
+            if self.get_tcr().interrupt_enabled() && self.streaming.get() {
+                // Keep the unit and its intermediate value for the next
+                // update
+                self.set_descriptor(0, TCR::default(), 0);
+                regs.dmaidr.write(DmaInterrupt::DMA::SET);
+                regs.dmadis.write(DmaDisable::DMADIS::SET);
+
+                if let Some(client) = self.get_client() {
+                    client.update_done();
+                }
+            } else if self.get_tcr().interrupt_enabled() {
                 if let Some(client) = self.get_client() {
                     let result = post_process(regs.sr.read(Status::CRC), self.alg.get());
                     client.receive_result(result);
@@ -336,37 +353,18 @@ impl<'a> Crccu<'a> {
             }
         }
     }
-}
 
-// Implement the generic CRC interface with the CRCCU
-impl<'a> crc::CRC for Crccu<'a> {
-    fn compute(&self, data: &[u8], alg: CrcAlg) -> ReturnCode {
+    /// Start a DMA transfer of `data` into the unit, continuing from the
+    /// current intermediate CRC value.
+    fn start_transfer(&self, data: &[u8]) {
         let regs: &CrccuRegisters = &*self.registers;
 
-        self.init();
-
-        if self.get_tcr().interrupt_enabled() {
-            // A computation is already in progress
-            return ReturnCode::EBUSY;
-        }
-
-        if data.len() > 2usize.pow(16) - 1 {
-            // Buffer too long
-            // TODO: Chain CRCCU computations to handle large buffers
-            return ReturnCode::ESIZE;
-        }
-
-        self.enable();
-
         // Enable DMA interrupt
         regs.dmaier.write(DmaInterrupt::DMA::SET);
 
         // Enable error interrupt
         regs.ier.write(Interrupt::ERR::SET);
 
-        // Reset intermediate CRC value
-        regs.cr.write(Control::RESET::SET);
-
         // Configure the data transfer
         let addr = data.as_ptr() as u32;
         let len = data.len() as u16;
@@ -382,20 +380,89 @@ impl<'a> crc::CRC for Crccu<'a> {
         self.set_descriptor(addr, ctrl, crc);
         regs.dscr.set(self.descriptor() as u32);
 
-        // Record what algorithm was requested
-        self.alg.set(alg);
-
         // Configure the unit to compute a checksum
         regs.mr.write(
-            Mode::DIVIDER.val(0) + poly_for_alg(alg) + Mode::COMPARE::CLEAR + Mode::ENABLE::Enabled,
+            Mode::DIVIDER.val(0)
+                + poly_for_alg(self.alg.get())
+                + Mode::COMPARE::CLEAR
+                + Mode::ENABLE::Enabled,
         );
 
         // Enable DMA channel
         regs.dmaen.write(DmaEnable::DMAEN::SET);
+    }
+}
+
+// Implement the generic CRC interface with the CRCCU
+impl<'a> crc::CRC for Crccu<'a> {
+    fn compute(&self, data: &[u8], alg: CrcAlg) -> ReturnCode {
+        let regs: &CrccuRegisters = &*self.registers;
+
+        self.init_descriptor();
+
+        if self.get_tcr().interrupt_enabled() || self.streaming.get() {
+            // A computation is already in progress
+            return ReturnCode::EBUSY;
+        }
 
+        if data.len() > 2usize.pow(16) - 1 {
+            // Buffer too long
+            // TODO: Chain CRCCU computations to handle large buffers
+            return ReturnCode::ESIZE;
+        }
+
+        self.enable();
+
+        // Reset intermediate CRC value
+        regs.cr.write(Control::RESET::SET);
+
+        // Record what algorithm was requested
+        self.alg.set(alg);
+
+        self.start_transfer(data);
         return ReturnCode::SUCCESS;
     }
 
+    fn init(&self, alg: CrcAlg) -> ReturnCode {
+        let regs: &CrccuRegisters = &*self.registers;
+
+        self.init_descriptor();
+
+        if self.get_tcr().interrupt_enabled() || self.streaming.get() {
+            // A computation is already in progress
+            return ReturnCode::EBUSY;
+        }
+
+        self.enable();
+        regs.cr.write(Control::RESET::SET);
+        self.alg.set(alg);
+        self.streaming.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn update(&self, data: &[u8]) -> ReturnCode {
+        if !self.streaming.get() {
+            return ReturnCode::EOFF;
+        }
+        if self.get_tcr().interrupt_enabled() {
+            return ReturnCode::EBUSY;
+        }
+        if data.len() > 2usize.pow(16) - 1 {
+            return ReturnCode::ESIZE;
+        }
+        self.start_transfer(data);
+        ReturnCode::SUCCESS
+    }
+
+    fn finalize(&self) -> u32 {
+        let regs: &CrccuRegisters = &*self.registers;
+
+        let result = post_process(regs.sr.read(Status::CRC), self.alg.get());
+        regs.mr.write(Mode::ENABLE::Disabled);
+        self.streaming.set(false);
+        result
+    }
+
     fn disable(&self) {
         Crccu::disable(self);
     }
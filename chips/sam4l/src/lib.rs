@@ -29,6 +29,7 @@ pub mod i2c;
 pub mod nvic;
 pub mod pm;
 pub mod scif;
+pub mod serial_num;
 pub mod spi;
 pub mod trng;
 pub mod usart;
@@ -144,6 +145,10 @@ unsafe extern "C" fn hard_fault_handler() {
         :
         );
 
+    if kernel_stack && cortexm4::mpu::kernel_stack_overflowed(faulting_stack as usize) {
+        cortexm4::kernel_stack_overflow(faulting_stack);
+    }
+
     if kernel_stack {
         let stacked_r0: u32 = *offset(faulting_stack, 0);
         let stacked_r1: u32 = *offset(faulting_stack, 1);
@@ -8,6 +8,7 @@ use flashcalw;
 use gpio;
 use kernel::common::regs::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil::reset::{ResetInfo, ResetReason};
 use kernel::ClockInterface;
 use scif;
 
@@ -988,6 +989,36 @@ unsafe fn configure_1mhz_rc() {
     PM.system_on_clocks.set(clock_mask | ClockMask::RC1M as u32);
 }
 
+impl ResetInfo for PowerManager {
+    fn reset_reason(&self) -> ResetReason {
+        let rcause = PM_REGS.rcause.extract();
+        if rcause.is_set(ResetCause::WDT) {
+            ResetReason::Watchdog
+        } else if rcause.is_set(ResetCause::BOD) || rcause.is_set(ResetCause::BOD33) {
+            ResetReason::BrownOut
+        } else if rcause.is_set(ResetCause::POR) || rcause.is_set(ResetCause::POR33) {
+            ResetReason::PowerOn
+        } else if rcause.is_set(ResetCause::OCDRST) {
+            // SYSRESETREQ is reported as an OCD reset on the SAM4L.
+            ResetReason::Software
+        } else if rcause.is_set(ResetCause::EXT) {
+            ResetReason::ExternalPin
+        } else if rcause.is_set(ResetCause::BKUP) {
+            ResetReason::Wakeup
+        } else {
+            ResetReason::Unknown
+        }
+    }
+
+    fn read_retained(&self) -> u16 {
+        bscif::read_backup_register() as u16
+    }
+
+    fn write_retained(&self, value: u16) {
+        bscif::write_backup_register(value as u32);
+    }
+}
+
 pub fn get_system_frequency() -> u32 {
     // Return the current system frequency
     unsafe {
@@ -1046,6 +1077,11 @@ macro_rules! get_clock {
     }};
 }
 
+/// A conservative bound on how long the chip takes to restart its clocks and
+/// resume executing after a wakeup from deep sleep. The chip only deep sleeps
+/// if every pending wakeup deadline tolerates this much latency.
+pub const DEEP_SLEEP_WAKEUP_US: u32 = 1000;
+
 /// Determines if the chip can safely go into deep sleep without preventing
 /// currently active peripherals from operating.
 ///
@@ -0,0 +1,39 @@
+//! The 120-bit serial number programmed into every SAM4L at the factory.
+//!
+//! The serial number is stored at a fixed address in the flash factory page
+//! (section 9.6 of the datasheet) and cannot be erased.
+
+use core::ptr;
+use kernel::hil::device_id::DeviceId;
+
+/// Address of the first byte of the serial number.
+const SERIAL_NUM_ADDRESS: usize = 0x0080020C;
+const SERIAL_NUM_LEN: usize = 15;
+
+pub struct SerialNum(());
+
+pub static mut SERIAL_NUM: SerialNum = SerialNum(());
+
+impl SerialNum {
+    /// The 15 bytes of the serial number, most significant byte first.
+    pub fn read(&self) -> [u8; SERIAL_NUM_LEN] {
+        let mut serial = [0; SERIAL_NUM_LEN];
+        for (i, byte) in serial.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile((SERIAL_NUM_ADDRESS + i) as *const u8) };
+        }
+        serial
+    }
+}
+
+impl DeviceId for SerialNum {
+    fn device_id(&self) -> u64 {
+        // Fold the serial number into 64 bits, keeping the low bytes, which
+        // differ between parts from the same wafer, in place.
+        let serial = self.read();
+        let mut id = 0u64;
+        for (i, byte) in serial.iter().rev().enumerate() {
+            id ^= (*byte as u64) << (8 * (i % 8));
+        }
+        id
+    }
+}
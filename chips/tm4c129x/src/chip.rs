@@ -4,6 +4,7 @@ use cortexm4;
 use gpt;
 use kernel::Chip;
 use uart;
+use udma;
 
 pub struct Tm4c129x {
     pub mpu: cortexm4::mpu::MPU,
@@ -32,6 +33,7 @@ impl Chip for Tm4c129x {
                     match interrupt {
                         nvic::UART0 => uart::UART0.handle_interrupt(),
                         nvic::TIMER0A => gpt::TIMER0.handle_interrupt(),
+                        nvic::UDMA => udma::UDMA.handle_interrupt(),
                         _ => {
                             panic!("unhandled interrupt {}", interrupt);
                         }
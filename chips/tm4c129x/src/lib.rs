@@ -14,6 +14,7 @@ pub mod gpt;
 pub mod nvic;
 pub mod sysctl;
 pub mod uart;
+pub mod udma;
 
 use cortexm4::{generic_isr, svc_handler, systick_handler};
 
@@ -124,6 +125,10 @@ unsafe extern "C" fn hard_fault_handler() {
         :
         );
 
+    if kernel_stack && cortexm4::mpu::kernel_stack_overflowed(faulting_stack as usize) {
+        cortexm4::kernel_stack_overflow(faulting_stack);
+    }
+
     if kernel_stack {
         let stacked_r0: u32 = *offset(faulting_stack, 0);
         let stacked_r1: u32 = *offset(faulting_stack, 1);
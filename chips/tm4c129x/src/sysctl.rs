@@ -9,6 +9,7 @@ pub enum Clock {
     TIMER(RCGCTIMER),
     GPIO(RCGCGPIO),
     UART(RCGCUART),
+    UDMA,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -414,5 +415,6 @@ pub unsafe fn enable_clock(clock: Clock) {
         Clock::TIMER(c) => regs.rcgctimer.set(regs.rcgctimer.get() | 1 << (c as u32)),
         Clock::GPIO(c) => regs.rcgcgpio.set(regs.rcgcgpio.get() | 1 << (c as u32)),
         Clock::UART(c) => regs.rcgcuart.set(regs.rcgcuart.get() | 1 << (c as u32)),
+        Clock::UDMA => regs.rcgcdma.set(regs.rcgcdma.get() | 1),
     }
 }
@@ -0,0 +1,278 @@
+//! Memory-to-memory transfers with the micro Direct Memory Access (uDMA)
+//! controller.
+//!
+//! Transfers run on the dedicated software channel in auto-request mode. The
+//! controller moves at most 1024 items per transfer, so longer copies and
+//! fills are split into chunks that are restarted from the completion
+//! interrupt. Items are words whenever both addresses are word aligned and
+//! bytes otherwise.
+
+use core::cell::Cell;
+use core::cmp;
+use core::ptr;
+use kernel::common::cells::{TakeCell, VolatileCell};
+use kernel::common::StaticRef;
+use kernel::hil::memory_dma;
+use kernel::ReturnCode;
+use sysctl;
+
+#[repr(C)]
+struct UdmaRegisters {
+    stat: VolatileCell<u32>,
+    cfg: VolatileCell<u32>,
+    ctlbase: VolatileCell<u32>,
+    altbase: VolatileCell<u32>,
+    waitstat: VolatileCell<u32>,
+    swreq: VolatileCell<u32>,
+    useburstset: VolatileCell<u32>,
+    useburstclr: VolatileCell<u32>,
+    reqmaskset: VolatileCell<u32>,
+    reqmaskclr: VolatileCell<u32>,
+    enaset: VolatileCell<u32>,
+    enaclr: VolatileCell<u32>,
+    altset: VolatileCell<u32>,
+    altclr: VolatileCell<u32>,
+    prioset: VolatileCell<u32>,
+    prioclr: VolatileCell<u32>,
+    _reserved0: [u32; 3],
+    errclr: VolatileCell<u32>,
+    _reserved1: [u32; 300],
+    chasgn: VolatileCell<u32>,
+    chis: VolatileCell<u32>,
+    _reserved2: [u32; 2],
+    chmap: [VolatileCell<u32>; 4],
+}
+
+const UDMA_BASE: StaticRef<UdmaRegisters> =
+    unsafe { StaticRef::new(0x400FF000 as *const UdmaRegisters) };
+
+/// The channel reserved for software-initiated transfers.
+const SOFTWARE_CHANNEL: usize = 30;
+
+/// The most items a single transfer can move.
+const MAX_TRANSFER_ITEMS: usize = 1024;
+
+// Fields of the channel control word.
+const DSTINC_SHIFT: u32 = 30;
+const DSTSIZE_SHIFT: u32 = 28;
+const SRCINC_SHIFT: u32 = 26;
+const SRCSIZE_SHIFT: u32 = 24;
+const ARBSIZE_SHIFT: u32 = 14;
+const XFERSIZE_SHIFT: u32 = 4;
+const XFERMODE_AUTO: u32 = 2;
+/// Increment value for an address that stays the same for every item.
+const NO_INCREMENT: u32 = 3;
+/// Rearbitrate after every 8 items.
+const ARBSIZE_8: u32 = 3;
+
+/// A channel control structure: source end pointer, destination end
+/// pointer, control word and an unused word.
+type ControlEntry = [u32; 4];
+
+/// The primary control structures, one per channel. The controller requires
+/// the table to be aligned to 1024 bytes.
+#[repr(C, align(1024))]
+struct ControlTable([ControlEntry; 32]);
+
+static mut CONTROL_TABLE: ControlTable = ControlTable([[0; 4]; 32]);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Operation {
+    Idle,
+    Copy,
+    Fill,
+}
+
+pub static mut UDMA: Udma = Udma::new(UDMA_BASE);
+
+pub struct Udma {
+    registers: StaticRef<UdmaRegisters>,
+    client: Cell<Option<&'static memory_dma::Client>>,
+    enabled: Cell<bool>,
+    operation: Cell<Operation>,
+    source: TakeCell<'static, [u8]>,
+    dest: TakeCell<'static, [u8]>,
+    /// The fill value repeated in every byte. Fills read it without
+    /// incrementing the source address.
+    fill_word: Cell<u32>,
+    len: Cell<usize>,
+    /// Bytes transferred by the chunks that have finished.
+    done: Cell<usize>,
+    /// Bytes being transferred by the current chunk.
+    chunk_len: Cell<usize>,
+}
+
+impl Udma {
+    const fn new(base_addr: StaticRef<UdmaRegisters>) -> Udma {
+        Udma {
+            registers: base_addr,
+            client: Cell::new(None),
+            enabled: Cell::new(false),
+            operation: Cell::new(Operation::Idle),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            fill_word: Cell::new(0),
+            len: Cell::new(0),
+            done: Cell::new(0),
+            chunk_len: Cell::new(0),
+        }
+    }
+
+    fn enable(&self) {
+        if self.enabled.get() {
+            return;
+        }
+        unsafe {
+            sysctl::enable_clock(sysctl::Clock::UDMA);
+        }
+
+        let regs = &*self.registers;
+        regs.cfg.set(1); // MASTEN
+        regs.ctlbase
+            .set(unsafe { &CONTROL_TABLE as *const ControlTable as u32 });
+
+        // Use the primary control structure and ignore peripheral requests.
+        let channel = 1 << SOFTWARE_CHANNEL;
+        regs.useburstclr.set(channel);
+        regs.altclr.set(channel);
+        regs.prioclr.set(channel);
+        regs.reqmaskset.set(channel);
+        self.enabled.set(true);
+    }
+
+    /// Start the next chunk of the current operation.
+    fn start_chunk(&self) {
+        let offset = self.done.get();
+        let remaining = self.len.get() - offset;
+
+        let dst_addr = self.dest.map_or(0, |dest| dest.as_ptr() as usize + offset);
+        let (src_addr, increment_source) = if self.operation.get() == Operation::Fill {
+            (self.fill_word.as_ptr() as usize, false)
+        } else {
+            (
+                self.source
+                    .map_or(0, |source| source.as_ptr() as usize + offset),
+                true,
+            )
+        };
+
+        // Items are 4 bytes (size 2) when possible, or single bytes (size 0).
+        let (size, item_bytes) = if (src_addr | dst_addr) & 3 == 0 && remaining >= 4 {
+            (2, 4)
+        } else {
+            (0, 1)
+        };
+        let items = cmp::min(remaining / item_bytes, MAX_TRANSFER_ITEMS);
+        let last = (items - 1) * item_bytes;
+        self.chunk_len.set(items * item_bytes);
+
+        let (src_end, src_inc) = if increment_source {
+            (src_addr + last, size)
+        } else {
+            (src_addr, NO_INCREMENT)
+        };
+        let control = (size << DSTINC_SHIFT)
+            | (size << DSTSIZE_SHIFT)
+            | (src_inc << SRCINC_SHIFT)
+            | (size << SRCSIZE_SHIFT)
+            | (ARBSIZE_8 << ARBSIZE_SHIFT)
+            | (((items - 1) as u32) << XFERSIZE_SHIFT)
+            | XFERMODE_AUTO;
+
+        unsafe {
+            let entry = &mut CONTROL_TABLE.0[SOFTWARE_CHANNEL];
+            ptr::write_volatile(&mut entry[0], src_end as u32);
+            ptr::write_volatile(&mut entry[1], (dst_addr + last) as u32);
+            ptr::write_volatile(&mut entry[2], control);
+        }
+
+        let regs = &*self.registers;
+        regs.enaset.set(1 << SOFTWARE_CHANNEL);
+        regs.swreq.set(1 << SOFTWARE_CHANNEL);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if regs.chis.get() & (1 << SOFTWARE_CHANNEL) == 0 {
+            return;
+        }
+        regs.chis.set(1 << SOFTWARE_CHANNEL);
+
+        self.done.set(self.done.get() + self.chunk_len.get());
+        if self.done.get() < self.len.get() {
+            self.start_chunk();
+            return;
+        }
+
+        let len = self.len.get();
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        match (operation, self.client.get(), self.dest.take()) {
+            (Operation::Copy, Some(client), Some(dest)) => {
+                self.source
+                    .take()
+                    .map(move |source| client.copy_done(source, dest, len));
+            }
+            (Operation::Fill, Some(client), Some(dest)) => client.fill_done(dest, len),
+            _ => {}
+        }
+    }
+}
+
+impl memory_dma::MemoryDma for Udma {
+    fn set_client(&self, client: &'static memory_dma::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn copy(
+        &self,
+        source: &'static mut [u8],
+        dest: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<(&'static mut [u8], &'static mut [u8])>) {
+        if self.operation.get() != Operation::Idle {
+            return (ReturnCode::EBUSY, Some((source, dest)));
+        }
+        if len == 0 {
+            return (ReturnCode::EINVAL, Some((source, dest)));
+        }
+        if source.len() < len || dest.len() < len {
+            return (ReturnCode::ESIZE, Some((source, dest)));
+        }
+
+        self.enable();
+        self.operation.set(Operation::Copy);
+        self.source.replace(source);
+        self.dest.replace(dest);
+        self.len.set(len);
+        self.done.set(0);
+        self.start_chunk();
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn fill(
+        &self,
+        dest: &'static mut [u8],
+        value: u8,
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.operation.get() != Operation::Idle {
+            return (ReturnCode::EBUSY, Some(dest));
+        }
+        if len == 0 {
+            return (ReturnCode::EINVAL, Some(dest));
+        }
+        if dest.len() < len {
+            return (ReturnCode::ESIZE, Some(dest));
+        }
+
+        self.enable();
+        self.operation.set(Operation::Fill);
+        self.fill_word.set(value as u32 * 0x01010101);
+        self.dest.replace(dest);
+        self.len.set(len);
+        self.done.set(0);
+        self.start_chunk();
+        (ReturnCode::SUCCESS, None)
+    }
+}
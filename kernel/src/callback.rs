@@ -1,50 +1,121 @@
 //! Data structure for storing a callback to userspace or kernelspace.
 
 use core::ptr::NonNull;
+use kernel_task::{self, TaskId};
 use process;
 
 /// Userspace app identifier.
+///
+/// An `AppId` refers to one process as it ran from when it last started. Once
+/// the process restarts, or another process takes its slot, the `AppId` is
+/// stale: callbacks scheduled with it are dropped and entering a grant with it
+/// fails, rather than reaching the wrong process.
+///
+/// The kernel can also masquerade as an app, so that kernel code uses drivers
+/// the way processes do. Those `AppId`s identify a kernel task instead of a
+/// process.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub struct AppId {
-    idx: usize,
+    owner: Owner,
 }
 
-/// The kernel can masquerade as an app. IDs >= this value are the kernel.
-/// These IDs are used to identify which kernel container is being accessed.
-const KERNEL_APPID_BOUNDARY: usize = 100;
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Owner {
+    Process { idx: usize, generation: usize },
+    Kernel(TaskId),
+}
 
 impl AppId {
+    /// The `AppId` of the process that is in slot `idx` now.
     pub(crate) fn new(idx: usize) -> AppId {
-        AppId { idx: idx }
+        AppId {
+            owner: Owner::Process {
+                idx: idx,
+                generation: process::get_generation(idx).unwrap_or(0),
+            },
+        }
     }
 
-    pub(crate) const fn kernel_new(idx: usize) -> AppId {
-        AppId { idx: idx }
+    /// The `AppId` of the process that was in slot `idx` with `generation`.
+    pub(crate) fn with_generation(idx: usize, generation: usize) -> AppId {
+        AppId {
+            owner: Owner::Process {
+                idx: idx,
+                generation: generation,
+            },
+        }
     }
 
-    pub const fn is_kernel(self) -> bool {
-        self.idx >= KERNEL_APPID_BOUNDARY
+    pub(crate) const fn kernel_new(task: TaskId) -> AppId {
+        AppId {
+            owner: Owner::Kernel(task),
+        }
     }
 
-    pub const fn is_kernel_idx(idx: usize) -> bool {
-        idx >= KERNEL_APPID_BOUNDARY
+    pub fn is_kernel(&self) -> bool {
+        self.task().is_some()
+    }
+
+    /// The kernel task this refers to, if it is not a process.
+    pub fn task(&self) -> Option<TaskId> {
+        match self.owner {
+            Owner::Process { .. } => None,
+            Owner::Kernel(task) => Some(task),
+        }
+    }
+
+    /// The slot of the process this refers to, if it is one.
+    pub(crate) fn process_idx(&self) -> Option<usize> {
+        match self.owner {
+            Owner::Process { idx, .. } => Some(idx),
+            Owner::Kernel(_) => None,
+        }
     }
 
+    /// The index of the process in the processes array. Kernel tasks are
+    /// numbered after the last process slot, so that their indices never
+    /// refer to a process.
     pub fn idx(&self) -> usize {
-        self.idx
+        match self.owner {
+            Owner::Process { idx, .. } => idx,
+            Owner::Kernel(task) => unsafe { process::PROCS.len() + task.number() },
+        }
+    }
+
+    /// Whether the app this refers to is still running in its slot, rather
+    /// than having restarted or been replaced since.
+    pub fn is_current(&self) -> bool {
+        match self.owner {
+            Owner::Process { idx, generation } => process::get_generation(idx) == Some(generation),
+            Owner::Kernel(_) => true,
+        }
     }
 
     pub fn get_editable_flash_range(&self) -> (usize, usize) {
-        process::get_editable_flash_range(self.idx)
+        process::get_editable_flash_range(self.idx())
+    }
+
+    pub fn get_storage_region(&self) -> Option<(usize, usize)> {
+        process::get_storage_region(self.idx())
     }
 }
 
+/// What a callback calls. A process callback can only be a function in the
+/// process, and a kernel callback only a Rust function, so one can never be
+/// mistaken for the other.
 #[derive(Clone, Copy, Debug)]
-pub enum RustOrRawFnPtr {
-    Raw {
-        ptr: NonNull<*mut ()>,
+enum Target {
+    Process {
+        app_id: AppId,
+        fn_ptr: NonNull<*mut ()>,
+        /// The driver and subscribe numbers the callback was subscribed with.
+        subscription: (usize, usize),
+        /// Whether the callback was subscribed for four values, with the
+        /// appdata naming the word the fourth value is written to.
+        four_values: bool,
     },
-    Rust {
+    Kernel {
+        task: TaskId,
         func: fn(usize, usize, usize, usize),
     },
 }
@@ -52,58 +123,176 @@ pub enum RustOrRawFnPtr {
 /// Wrapper around a function pointer.
 #[derive(Clone, Copy, Debug)]
 pub struct Callback {
-    app_id: AppId,
     appdata: usize,
-    fn_ptr: RustOrRawFnPtr,
+    target: Target,
 }
 
 impl Callback {
-    pub(crate) fn new(appid: AppId, appdata: usize, fn_ptr: NonNull<*mut ()>) -> Callback {
+    pub(crate) fn new(
+        appid: AppId,
+        appdata: usize,
+        fn_ptr: NonNull<*mut ()>,
+        subscription: (usize, usize),
+        four_values: bool,
+    ) -> Callback {
         Callback {
-            app_id: appid,
             appdata: appdata,
-            fn_ptr: RustOrRawFnPtr::Raw { ptr: fn_ptr },
+            target: Target::Process {
+                app_id: appid,
+                fn_ptr: fn_ptr,
+                subscription: subscription,
+                four_values: four_values,
+            },
         }
     }
 
     pub(crate) const fn kernel_new(
-        appid: AppId,
-        fn_ptr: fn(usize, usize, usize, usize),
+        task: TaskId,
+        appdata: usize,
+        func: fn(usize, usize, usize, usize),
     ) -> Callback {
         Callback {
-            app_id: appid,
-            appdata: 0,
-            fn_ptr: RustOrRawFnPtr::Rust { func: fn_ptr },
+            appdata: appdata,
+            target: Target::Kernel {
+                task: task,
+                func: func,
+            },
+        }
+    }
+
+    /// The process or kernel task the callback calls.
+    pub fn app_id(&self) -> AppId {
+        match self.target {
+            Target::Process { app_id, .. } => app_id,
+            Target::Kernel { task, .. } => AppId::kernel_new(task),
         }
     }
 
+    /// The address of the function in the process that the callback calls,
+    /// or `None` for a kernel callback.
+    pub(crate) fn function_address(&self) -> Option<usize> {
+        match self.target {
+            Target::Process { fn_ptr, .. } => Some(fn_ptr.as_ptr() as usize),
+            Target::Kernel { .. } => None,
+        }
+    }
+
+    /// Schedule the callback. Callbacks to processes and kernel tasks are
+    /// queued and run from the main loop, while other kernel callbacks run
+    /// immediately. Returns false if the callback could not be queued.
     pub fn schedule(&mut self, r0: usize, r1: usize, r2: usize) -> bool {
-        if self.app_id.is_kernel() {
-            let fn_ptr = match self.fn_ptr {
-                RustOrRawFnPtr::Raw { ptr } => {
-                    panic!("Attempt to rust_call a raw function pointer: ptr {:?}", ptr)
+        self.schedule_in_lane(r0, r1, r2, None, false)
+    }
+
+    /// Schedule the callback with four values, for events that carry more
+    /// than three words. A process callback subscribed with
+    /// `SUBSCRIBE_FOUR_VALUES` still gets its userdata as the last argument,
+    /// and finds the fourth value in the word the userdata points to. Other
+    /// process callbacks only get the first three values. A kernel callback
+    /// gets the fourth value in place of its appdata.
+    pub fn schedule4(&mut self, r0: usize, r1: usize, r2: usize, r3: usize) -> bool {
+        self.schedule_in_lane(r0, r1, r2, Some(r3), false)
+    }
+
+    /// Schedule a time-critical callback, such as a received radio frame that
+    /// needs a quick reply. A process runs its urgent callbacks before any
+    /// other queued callbacks.
+    pub fn schedule_urgent(&mut self, r0: usize, r1: usize, r2: usize) -> bool {
+        self.schedule_in_lane(r0, r1, r2, None, true)
+    }
+
+    /// Schedule the callback for a high-rate event, folding it into the call
+    /// that is already queued for this callback, if there is one, so that a
+    /// burst of events cannot overflow the process's queue. The process sees
+    /// `r0` and `r1` of the latest event and, as the third argument, the
+    /// number of events the call carries.
+    pub fn schedule_coalesced(&mut self, r0: usize, r1: usize) -> bool {
+        match self.target {
+            Target::Process {
+                app_id,
+                fn_ptr,
+                subscription,
+                four_values,
+            } => {
+                if !app_id.is_current() {
+                    return false;
                 }
-                RustOrRawFnPtr::Rust { func } => func,
-            };
-            fn_ptr(r0, r1, r2, self.appdata);
-            true
-        } else {
-            let fn_ptr = match self.fn_ptr {
-                RustOrRawFnPtr::Raw { ptr } => ptr,
-                RustOrRawFnPtr::Rust { func } => {
-                    panic!("Attempt to schedule rust function: func {:?}", func)
+                process::schedule_coalesced(
+                    process::FunctionCall {
+                        r0: r0,
+                        r1: r1,
+                        r2: 1,
+                        r3: self.appdata,
+                        pc: fn_ptr.as_ptr() as usize,
+                        subscription: Some(subscription),
+                        fourth_value: self.fourth_value(four_values, None),
+                    },
+                    app_id,
+                )
+            }
+            Target::Kernel { .. } => self.schedule(r0, r1, 1),
+        }
+    }
+
+    fn schedule_in_lane(
+        &mut self,
+        r0: usize,
+        r1: usize,
+        r2: usize,
+        r3: Option<usize>,
+        urgent: bool,
+    ) -> bool {
+        match self.target {
+            Target::Process {
+                app_id,
+                fn_ptr,
+                subscription,
+                four_values,
+            } => {
+                // The process has restarted or been replaced since it
+                // subscribed.
+                if !app_id.is_current() {
+                    return false;
                 }
-            };
-            process::schedule(
-                process::FunctionCall {
-                    r0: r0,
-                    r1: r1,
-                    r2: r2,
-                    r3: self.appdata,
-                    pc: fn_ptr.as_ptr() as usize,
-                },
-                self.app_id,
-            )
+                process::schedule(
+                    process::FunctionCall {
+                        r0: r0,
+                        r1: r1,
+                        r2: r2,
+                        r3: self.appdata,
+                        pc: fn_ptr.as_ptr() as usize,
+                        subscription: Some(subscription),
+                        fourth_value: self.fourth_value(four_values, r3),
+                    },
+                    app_id,
+                    urgent,
+                )
+            }
+            Target::Kernel {
+                task: TaskId::Registered(number),
+                func,
+            } => {
+                let r3 = r3.unwrap_or(self.appdata);
+                kernel_task::get(number).map_or(false, |task| task.schedule(func, (r0, r1, r2, r3)))
+            }
+            Target::Kernel {
+                task: TaskId::DebugWriter,
+                func,
+            } => {
+                func(r0, r1, r2, r3.unwrap_or(self.appdata));
+                true
+            }
+        }
+    }
+
+    /// Where and what to write as the fourth value of a call to a process
+    /// callback. A callback subscribed for four values gets 0 for events with
+    /// only three.
+    fn fourth_value(&self, four_values: bool, r3: Option<usize>) -> Option<(usize, usize)> {
+        if four_values {
+            Some((self.appdata, r3.unwrap_or(0)))
+        } else {
+            None
         }
     }
 }
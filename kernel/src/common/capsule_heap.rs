@@ -0,0 +1,157 @@
+//! Quota-limited dynamic memory for capsules.
+//!
+//! Most capsules size their state statically, but some structures, such as
+//! routing tables or GATT attribute tables, only reach their final size at
+//! runtime. A board can give those capsules memory from a `CapsuleHeap`,
+//! with a `Quota` for each capsule. Each quota reserves its own part of the
+//! heap when it is created, which fails if the heap does not have room for
+//! it, so the quotas a board creates are always there for the capsules. An
+//! allocation that would exceed a quota fails without touching memory
+//! reserved for anyone else, and is counted so the board can report it:
+//!
+//! ```rust
+//! static mut HEAP_MEMORY: [u8; 2048] = [0; 2048];
+//!
+//! let heap = static_init!(
+//!     kernel::common::capsule_heap::CapsuleHeap,
+//!     kernel::common::capsule_heap::CapsuleHeap::new(&mut HEAP_MEMORY)
+//! );
+//! let routes = static_init!(
+//!     kernel::common::capsule_heap::Quota,
+//!     kernel::common::capsule_heap::Quota::new(heap, "routes", 512)
+//!         .expect("capsule heap over-committed")
+//! );
+//!
+//! let route = routes.alloc(Route::default()).ok();
+//! let (used, limit) = routes.usage();
+//! debug!("{}: {}/{} bytes, {} failed", routes.name(), used, limit, routes.failures());
+//! ```
+//!
+//! Each quota is a bump allocator: memory is never returned, so capsules
+//! should allocate entries once and reuse them rather than allocating for
+//! each request. Alignment padding counts against the quota.
+
+use core::cell::Cell;
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::slice;
+
+pub struct CapsuleHeap {
+    memory: *mut u8,
+    memory_len: usize,
+    /// Bytes of `memory` reserved by quotas so far.
+    reserved: Cell<usize>,
+}
+
+impl CapsuleHeap {
+    pub fn new(memory: &'static mut [u8]) -> CapsuleHeap {
+        CapsuleHeap {
+            memory: memory.as_mut_ptr(),
+            memory_len: memory.len(),
+            reserved: Cell::new(0),
+        }
+    }
+
+    /// Bytes of the heap reserved by quotas, and its size.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.reserved.get(), self.memory_len)
+    }
+
+    /// Reserve the next `len` bytes of the heap, if it has room.
+    fn reserve(&self, len: usize) -> Option<*mut u8> {
+        let reserved = self.reserved.get();
+        let end = reserved.checked_add(len)?;
+        if end > self.memory_len {
+            None
+        } else {
+            self.reserved.set(end);
+            Some(unsafe { self.memory.offset(reserved as isize) })
+        }
+    }
+}
+
+/// The part of a `CapsuleHeap` one capsule may use.
+pub struct Quota {
+    memory: *mut u8,
+    name: &'static str,
+    limit: usize,
+    used: Cell<usize>,
+    failures: Cell<usize>,
+}
+
+impl Quota {
+    /// A quota of `limit` bytes of `heap`, reported as `name`. Returns
+    /// `None` if the quotas already created leave `heap` less than `limit`
+    /// bytes.
+    pub fn new(heap: &'static CapsuleHeap, name: &'static str, limit: usize) -> Option<Quota> {
+        heap.reserve(limit).map(|memory| Quota {
+            memory: memory,
+            name: name,
+            limit: limit,
+            used: Cell::new(0),
+            failures: Cell::new(0),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Bytes used and the limit.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.used.get(), self.limit)
+    }
+
+    /// The number of allocations that failed because the quota was
+    /// exhausted.
+    pub fn failures(&self) -> usize {
+        self.failures.get()
+    }
+
+    /// Allocate `size` bytes aligned for `align`.
+    fn alloc_bytes(&self, size: usize, align: usize) -> Option<*mut u8> {
+        let base = self.memory as usize;
+        let start = (base + self.used.get() + align - 1) & !(align - 1);
+        match start.checked_add(size) {
+            Some(end) if end <= base + self.limit => {
+                self.used.set(end - base);
+                Some(start as *mut u8)
+            }
+            _ => {
+                self.failures.set(self.failures.get() + 1);
+                None
+            }
+        }
+    }
+
+    /// Move `value` into memory from the quota. Returns `value` back if
+    /// there is not enough room.
+    pub fn alloc<T>(&self, value: T) -> Result<&'static mut T, T> {
+        match self.alloc_bytes(size_of::<T>(), align_of::<T>()) {
+            Some(ptr) => unsafe {
+                let ptr = ptr as *mut T;
+                ptr::write(ptr, value);
+                Ok(&mut *ptr)
+            },
+            None => Err(value),
+        }
+    }
+
+    /// Allocate `len` elements from the quota, each set to `value`.
+    pub fn alloc_slice<T: Copy>(&self, len: usize, value: T) -> Option<&'static mut [T]> {
+        let size = match size_of::<T>().checked_mul(len) {
+            Some(size) => size,
+            None => {
+                self.failures.set(self.failures.get() + 1);
+                return None;
+            }
+        };
+        self.alloc_bytes(size, align_of::<T>()).map(|ptr| unsafe {
+            let ptr = ptr as *mut T;
+            for i in 0..len {
+                ptr::write(ptr.offset(i as isize), value);
+            }
+            slice::from_raw_parts_mut(ptr, len)
+        })
+    }
+}
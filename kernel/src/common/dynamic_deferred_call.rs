@@ -0,0 +1,74 @@
+//! Deferred calls for capsules.
+//!
+//! A capsule sometimes has to call its client back later rather than from
+//! inside the call the client made, for example to report an error
+//! asynchronously without creating a call cycle. Unlike `DeferredCall`, whose
+//! tasks are an enum defined by each chip, these calls are registered at
+//! runtime, so capsules can use them on any chip.
+//!
+//! A capsule registers itself as a client when the board sets it up, and
+//! keeps the handle it gets back. Setting the handle asks the kernel to call
+//! the client from the main loop, after it has serviced interrupts:
+//!
+//! ```rust
+//! let handle = kernel::common::dynamic_deferred_call::register(rtt).unwrap();
+//! rtt.set_deferred_call_handle(handle);
+//!
+//! // Later, in the capsule:
+//! self.handle.map(|handle| handle.set());
+//! ```
+
+use core::mem;
+
+/// The maximum number of clients.
+pub const MAX_CLIENTS: usize = 16;
+
+/// Implemented by capsules that register for deferred calls.
+pub trait DynamicDeferredCallClient {
+    /// Called from the main loop once `handle` has been set.
+    fn call(&self, handle: DeferredCallHandle);
+}
+
+/// Identifies a registered client.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DeferredCallHandle(usize);
+
+static mut CLIENTS: [Option<&'static DynamicDeferredCallClient>; MAX_CLIENTS] = [None; MAX_CLIENTS];
+
+/// One bit for each client whose call is pending.
+static mut PENDING: usize = 0;
+
+/// Register `client` for deferred calls. Returns `None` if `MAX_CLIENTS`
+/// clients are already registered.
+pub unsafe fn register(client: &'static DynamicDeferredCallClient) -> Option<DeferredCallHandle> {
+    CLIENTS.iter().position(|c| c.is_none()).map(|i| {
+        CLIENTS[i] = Some(client);
+        DeferredCallHandle(i)
+    })
+}
+
+impl DeferredCallHandle {
+    /// Ask for the client to be called from the main loop. Setting a handle
+    /// that is already pending has no further effect.
+    pub fn set(&self) {
+        unsafe {
+            PENDING |= 1 << self.0;
+        }
+    }
+}
+
+/// Whether any client has a call pending.
+pub fn has_pending() -> bool {
+    unsafe { PENDING != 0 }
+}
+
+/// Call the clients whose calls are pending. Calls set while this runs are
+/// made the next time round the main loop.
+pub(crate) fn call_pending() {
+    let pending = unsafe { mem::replace(&mut PENDING, 0) };
+    for i in 0..MAX_CLIENTS {
+        if pending & (1 << i) != 0 {
+            unsafe { CLIENTS[i] }.map(|client| client.call(DeferredCallHandle(i)));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Interrupt handler latency budget.
+//!
+//! Chips call peripheral `handle_interrupt()` functions from
+//! `service_pending_interrupts()`, and every other pending interrupt waits
+//! until the handler returns. A handler that does too much work directly (for
+//! example, running a whole I2C transaction) delays everything else, so this
+//! module can time each handler and check it against a budget. Long work
+//! should instead be moved onto a deferred call.
+//!
+//! Timing is off until a board provides a time source. Once enabled, the
+//! slowest handler is recorded, and in debug builds exceeding the budget
+//! panics with the offending interrupt number:
+//!
+//! ```rust
+//! unsafe {
+//!     kernel::common::interrupt_budget::INTERRUPT_BUDGET.enable(timestamp, 100);
+//! }
+//! ```
+//!
+//! The resolution of the measurement is that of the time source, so the time
+//! source should tick considerably faster than the budget.
+
+use core::cell::Cell;
+use hil::time::Timestamp;
+use syscall;
+
+pub static mut INTERRUPT_BUDGET: InterruptBudget = InterruptBudget::new();
+
+pub struct InterruptBudget {
+    source: Cell<Option<&'static Timestamp>>,
+    budget_us: Cell<u32>,
+    /// The slowest handler so far: its interrupt number and duration in
+    /// microseconds.
+    worst: Cell<Option<(u32, u32)>>,
+}
+
+impl InterruptBudget {
+    const fn new() -> InterruptBudget {
+        InterruptBudget {
+            source: Cell::new(None),
+            budget_us: Cell::new(0),
+            worst: Cell::new(None),
+        }
+    }
+
+    /// Start timing handlers with `source`, allowing each at most
+    /// `budget_us` microseconds.
+    pub fn enable(&self, source: &'static Timestamp, budget_us: u32) {
+        self.source.set(Some(source));
+        self.budget_us.set(budget_us);
+        self.worst.set(None);
+    }
+
+    pub fn disable(&self) {
+        self.source.set(None);
+    }
+
+    /// The interrupt number and duration in microseconds of the slowest
+    /// handler since timing was enabled.
+    pub fn worst(&self) -> Option<(u32, u32)> {
+        self.worst.get()
+    }
+
+    /// Run the handler for `interrupt`, timing it if enabled.
+    pub fn measure<F: FnOnce()>(&self, interrupt: u32, handler: F) {
+        syscall::trace_interrupt(interrupt);
+        let source = match self.source.get() {
+            Some(source) => source,
+            None => return handler(),
+        };
+
+        let start = source.timestamp();
+        handler();
+        let ticks = source.timestamp().wrapping_sub(start);
+        let elapsed_us = (ticks * 1_000_000 / source.frequency() as u64) as u32;
+
+        match self.worst.get() {
+            Some((_, worst_us)) if worst_us >= elapsed_us => {}
+            _ => self.worst.set(Some((interrupt, elapsed_us))),
+        }
+
+        debug_assert!(
+            elapsed_us <= self.budget_us.get(),
+            "interrupt {} handler took {}us, budget is {}us",
+            interrupt,
+            elapsed_us,
+            self.budget_us.get()
+        );
+    }
+}
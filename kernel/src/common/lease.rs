@@ -0,0 +1,73 @@
+//! Checks on buffers lent to another layer.
+//!
+//! Layers of the kernel pass `&'static mut` buffers down and back up, for
+//! example a MAC layer passing a frame to its radio, which passes it back
+//! once the frame has been sent. Ownership moves with the buffer, so the
+//! compiler checks that only one layer uses a buffer at a time, but not that
+//! a layer gives back the buffer it was lent, gives it back only once, or
+//! gives it back at all. A layer that gets any of these wrong leaves the
+//! other layer holding a buffer that is about to be reused, which corrupts
+//! frames long after the mistake.
+//!
+//! The lending layer keeps a `Lease` for each buffer it lends, and records
+//! when the buffer leaves and comes back. In debug builds, getting back a
+//! buffer that was not lent, or lending a buffer while the last one is still
+//! out, panics with the name of the lease:
+//!
+//! ```rust
+//! // Lending
+//! self.tx_lease.lend(buf);
+//! let (result, buf) = self.radio.transmit(buf, len);
+//! buf.map(|buf| self.tx_lease.returned(buf));
+//!
+//! // In the callback
+//! self.tx_lease.returned(buf);
+//! ```
+//!
+//! A buffer is identified by its address, so a layer must give back the
+//! whole buffer it was lent, not a part of it.
+
+use core::cell::Cell;
+
+pub struct Lease {
+    name: &'static str,
+    /// The address of the buffer that is out, if any.
+    lent: Cell<Option<usize>>,
+}
+
+impl Lease {
+    /// `name` says which lease a failed check is about, such as
+    /// `"rf233 rx"`.
+    pub const fn new(name: &'static str) -> Lease {
+        Lease {
+            name: name,
+            lent: Cell::new(None),
+        }
+    }
+
+    /// Record that `buffer` is being lent. The last buffer lent must have
+    /// been returned.
+    pub fn lend(&self, buffer: &[u8]) {
+        debug_assert!(
+            self.lent.get().is_none(),
+            "{}: buffer lent before the last one was returned",
+            self.name
+        );
+        self.lent.set(Some(buffer.as_ptr() as usize));
+    }
+
+    /// Record that `buffer` has come back. It must be the buffer lent.
+    pub fn returned(&self, buffer: &[u8]) {
+        debug_assert!(
+            self.lent.get() == Some(buffer.as_ptr() as usize),
+            "{}: buffer returned that was not lent",
+            self.name
+        );
+        self.lent.set(None);
+    }
+
+    /// Whether a buffer is out.
+    pub fn is_lent(&self) -> bool {
+        self.lent.get().is_some()
+    }
+}
@@ -8,19 +8,26 @@
 
 pub use tock_regs::*;
 
+pub mod capsule_heap;
 pub mod deferred_call;
+pub mod dynamic_deferred_call;
+pub mod interrupt_budget;
+pub mod lease;
 pub mod list;
 pub mod math;
 pub mod peripherals;
+pub mod static_memory;
 pub mod utils;
 
 mod queue;
 mod ring_buffer;
+mod segment_list;
 mod static_ref;
 
 pub use self::list::{List, ListLink, ListNode};
 pub use self::queue::Queue;
 pub use self::ring_buffer::RingBuffer;
+pub use self::segment_list::{SegmentList, MAX_SEGMENTS};
 pub use self::static_ref::StaticRef;
 
 /// Create a "fake" module inside of `common` for all of the Tock `Cell` types.
@@ -9,4 +9,11 @@ pub trait Queue<T> {
 
     /// Remove all elements from the ring buffer.
     fn empty(&mut self);
+
+    /// Remove the elements for which `f` returns false, keeping the order of
+    /// the rest. Returns the number of elements removed.
+    fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) -> usize;
+
+    /// The oldest element for which `f` returns true.
+    fn find_mut<F: FnMut(&T) -> bool>(&mut self, f: F) -> Option<&mut T>;
 }
@@ -63,4 +63,35 @@ impl<'a, T: Copy> queue::Queue<T> for RingBuffer<'a, T> {
         self.head = 0;
         self.tail = 0;
     }
+
+    fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.ring.len();
+        let mut src = self.head;
+        let mut dst = self.head;
+        let mut removed = 0;
+        while src != self.tail {
+            let val = self.ring[src];
+            if f(&val) {
+                self.ring[dst] = val;
+                dst = (dst + 1) % len;
+            } else {
+                removed += 1;
+            }
+            src = (src + 1) % len;
+        }
+        self.tail = dst;
+        removed
+    }
+
+    fn find_mut<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Option<&mut T> {
+        let len = self.ring.len();
+        let mut i = self.head;
+        while i != self.tail {
+            if f(&self.ring[i]) {
+                return Some(&mut self.ring[i]);
+            }
+            i = (i + 1) % len;
+        }
+        None
+    }
 }
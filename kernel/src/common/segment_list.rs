@@ -0,0 +1,97 @@
+//! A short list of buffers transferred as one unit.
+//!
+//! Lets a driver send a frame whose parts (for example a protocol header and
+//! its payload) live in separate static buffers without first copying them
+//! into one contiguous buffer. Each segment is a buffer and the number of
+//! bytes of it that belong to the transfer.
+
+/// The maximum number of segments in a `SegmentList`.
+pub const MAX_SEGMENTS: usize = 4;
+
+pub struct SegmentList {
+    buffers: [Option<&'static mut [u8]>; MAX_SEGMENTS],
+    lengths: [usize; MAX_SEGMENTS],
+    count: usize,
+}
+
+impl SegmentList {
+    pub fn new() -> SegmentList {
+        SegmentList {
+            buffers: [None, None, None, None],
+            lengths: [0; MAX_SEGMENTS],
+            count: 0,
+        }
+    }
+
+    /// Add the first `len` bytes of `buf` as the last segment. Returns the
+    /// buffer if the list is full.
+    pub fn push(&mut self, buf: &'static mut [u8], len: usize) -> Result<(), &'static mut [u8]> {
+        if self.count == MAX_SEGMENTS {
+            return Err(buf);
+        }
+        self.lengths[self.count] = if len < buf.len() { len } else { buf.len() };
+        self.buffers[self.count] = Some(buf);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Remove the last segment, returning its buffer and length.
+    pub fn pop(&mut self) -> Option<(&'static mut [u8], usize)> {
+        if self.count == 0 {
+            return None;
+        }
+        self.count -= 1;
+        let len = self.lengths[self.count];
+        self.buffers[self.count].take().map(|buf| (buf, len))
+    }
+
+    /// The number of segments.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The length of segment `index`, or 0 if there is no such segment.
+    pub fn segment_len(&self, index: usize) -> usize {
+        if index < self.count {
+            self.lengths[index]
+        } else {
+            0
+        }
+    }
+
+    /// The total length of all segments.
+    pub fn total_len(&self) -> usize {
+        self.lengths[..self.count].iter().sum()
+    }
+
+    /// Access the buffer of segment `index`, if it is present.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        if index < self.count {
+            self.buffers[index].as_mut().map(|buf| &mut buf[..])
+        } else {
+            None
+        }
+    }
+
+    /// Take the buffer of segment `index` out of the list, for example to
+    /// hand it to hardware. It must be given back with `put` before the
+    /// list is returned to its owner.
+    pub fn take(&mut self, index: usize) -> Option<&'static mut [u8]> {
+        if index < self.count {
+            self.buffers[index].take()
+        } else {
+            None
+        }
+    }
+
+    /// Give back a buffer taken with `take`.
+    pub fn put(&mut self, index: usize, buf: &'static mut [u8]) {
+        if index < self.count {
+            self.buffers[index] = Some(buf);
+        }
+    }
+}
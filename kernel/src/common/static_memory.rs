@@ -0,0 +1,108 @@
+//! Accounting of memory allocated with `static_init!`.
+//!
+//! Each `static_init!` adds the size of the memory it reserves to a running
+//! total and, while there is room, records it by name, so that a board can
+//! see where its RAM goes:
+//!
+//! ```rust
+//! kernel::common::static_memory::for_each(|allocation| {
+//!     debug!("{:6} {}", allocation.size, allocation.name);
+//! });
+//! debug!("{:6} total", kernel::common::static_memory::total());
+//! ```
+//!
+//! Allocations beyond the first `MAX_RECORDS` are only counted in the total.
+
+use core::cell::Cell;
+
+/// The number of allocations recorded individually.
+pub const MAX_RECORDS: usize = 32;
+
+#[derive(Copy, Clone)]
+pub struct Allocation {
+    /// The type allocated, or the name given to `static_init!`.
+    pub name: &'static str,
+    pub size: usize,
+}
+
+struct Ledger {
+    records: [Cell<Option<Allocation>>; MAX_RECORDS],
+    count: Cell<usize>,
+    total: Cell<usize>,
+}
+
+// The ledger is only written during board initialization, before interrupts
+// are enabled.
+unsafe impl Sync for Ledger {}
+
+static LEDGER: Ledger = Ledger {
+    records: [
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+        Cell::new(None),
+    ],
+    count: Cell::new(0),
+    total: Cell::new(0),
+};
+
+/// Record an allocation of `size` bytes. Called by `static_init!`.
+pub fn record(name: &'static str, size: usize) {
+    LEDGER.total.set(LEDGER.total.get() + size);
+    let count = LEDGER.count.get();
+    if count < MAX_RECORDS {
+        LEDGER.records[count].set(Some(Allocation {
+            name: name,
+            size: size,
+        }));
+    }
+    LEDGER.count.set(count + 1);
+}
+
+/// The total number of bytes allocated.
+pub fn total() -> usize {
+    LEDGER.total.get()
+}
+
+/// The number of allocations made, including those not recorded
+/// individually.
+pub fn count() -> usize {
+    LEDGER.count.get()
+}
+
+/// Call `f` for each recorded allocation, in the order they were made.
+pub fn for_each<F: FnMut(Allocation)>(mut f: F) {
+    for record in LEDGER.records.iter() {
+        if let Some(allocation) = record.get() {
+            f(allocation);
+        }
+    }
+}
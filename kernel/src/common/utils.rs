@@ -6,6 +6,11 @@
 /// initialize the array to the value given and return a `&'static mut`
 /// reference to it.
 ///
+/// The memory used is recorded in `common::static_memory` under the name of the
+/// type, or under the name given as an optional third argument:
+///
+/// `static_init!(Console<'static>, Console::new(...), "console")`
+///
 /// If `std::mem::size_of<T>` ever becomes a `const` function then `static_init`
 /// will be optimized to save up to a word of memory for every use.
 ///
@@ -15,10 +20,13 @@
 /// or similar, calling this macro is inherently unsafe. The caller should take
 /// care to never call the code that initializes this buffer twice, as doing so
 /// will overwrite the value from first allocation without running its
-/// destructor.
+/// destructor. `try_static_init!` checks for this.
 #[macro_export]
 macro_rules! static_init {
     ($T:ty, $e:expr) => {
+        static_init!($T, $e, stringify!($T))
+    };
+    ($T:ty, $e:expr, $name:expr) => {
         // Ideally we could use mem::size_of<$T>, uninitialized or zerod here
         // instead of having an `Option`, however that is not currently possible
         // in Rust, so in some cases we're wasting up to a word.
@@ -28,6 +36,7 @@ macro_rules! static_init {
             // initial value into it (without dropping the initial zeros) and
             // return a reference to it.
             static mut BUF: Option<$T> = None;
+            $crate::common::static_memory::record($name, mem::size_of::<Option<$T>>());
             let tmp : &'static mut $T = mem::transmute(&mut BUF);
             ptr::write(tmp as *mut $T, $e);
             tmp
@@ -35,6 +44,37 @@ macro_rules! static_init {
     }
 }
 
+/// Like `static_init!`, but evaluates to `None` instead of overwriting the
+/// value if this invocation has already run, for code such as board
+/// components that may be initialized more than once by mistake.
+///
+/// # Safety
+///
+/// As with `static_init!`, the caller must ensure the macro is not run
+/// concurrently.
+#[macro_export]
+macro_rules! try_static_init {
+    ($T:ty, $e:expr) => {
+        try_static_init!($T, $e, stringify!($T))
+    };
+    ($T:ty, $e:expr, $name:expr) => {
+        {
+            use core::{mem, ptr};
+            static mut BUF: Option<$T> = None;
+            static mut INITIALIZED: bool = false;
+            if INITIALIZED {
+                None
+            } else {
+                INITIALIZED = true;
+                $crate::common::static_memory::record($name, mem::size_of::<Option<$T>>());
+                let tmp : &'static mut $T = mem::transmute(&mut BUF);
+                ptr::write(tmp as *mut $T, $e);
+                Some(tmp)
+            }
+        }
+    }
+}
+
 /// Allocates space in the kernel image for on-chip non-volatile storage.
 /// Storage volumes are placed after the kernel code and before relocated
 /// variables (those copied into RAM on boot). They are placed in
@@ -41,9 +41,12 @@ use core::ptr::{read_volatile, write_volatile};
 use core::{slice, str};
 use driver::Driver;
 use hil;
+use kernel_task::TaskId;
 use mem::AppSlice;
 use process;
 use returncode::ReturnCode;
+#[cfg(feature = "syscall_trace")]
+use syscall;
 
 ///////////////////////////////////////////////////////////////////
 // panic! support routines
@@ -93,10 +96,7 @@ pub unsafe fn panic_banner<W: Write>(writer: &mut W, panic_info: &PanicInfo) {
     let _ = writer.write_str("\"\r\n");
 
     // Print version of the kernel
-    let _ = writer.write_fmt(format_args!(
-        "\tKernel version {}\r\n",
-        env!("TOCK_KERNEL_VERSION")
-    ));
+    let _ = writer.write_fmt(format_args!("\tKernel version {}\r\n", ::KERNEL_VERSION));
 }
 
 /// More detailed prints about all processes.
@@ -116,6 +116,7 @@ pub unsafe fn panic_process_info<W: Write>(writer: &mut W) {
     for idx in 0..procs.len() {
         procs[idx].as_mut().map(|process| {
             process.statistics_str(writer);
+            process.grants_str(writer);
         });
     }
 }
@@ -184,7 +185,8 @@ macro_rules! debug_gpio {
 ///////////////////////////////////////////////////////////////////
 // debug! and debug_verbose! support
 
-pub const APPID_IDX: usize = 255;
+/// The app the debug writer uses the console driver as.
+const APPID: AppId = AppId::kernel_new(TaskId::DebugWriter);
 const BUF_SIZE: usize = 1024;
 
 pub struct DebugWriter {
@@ -271,26 +273,20 @@ impl DebugWriter {
                     let slice = AppSlice::new(
                         self.output_buffer.as_mut_ptr().offset(start as isize),
                         end - start,
-                        AppId::kernel_new(APPID_IDX),
+                        APPID,
                     );
                     let slice_len = slice.len();
-                    if driver.allow(AppId::kernel_new(APPID_IDX), 1, Some(slice))
-                        != ReturnCode::SUCCESS
-                    {
+                    if driver.allow(APPID, 1, Some(slice)) != ReturnCode::SUCCESS {
                         panic!("Debug print allow fail");
                     }
                     write_volatile(&mut DEBUG_WRITER.output_active_len, slice_len);
-                    if driver.subscribe(
-                        1,
-                        Some(KERNEL_CONSOLE_CALLBACK),
-                        AppId::kernel_new(APPID_IDX),
-                    ) != ReturnCode::SUCCESS
+                    if driver
+                        .subscribe(1, Some(KERNEL_CONSOLE_CALLBACK), APPID)
+                        .is_err()
                     {
                         panic!("Debug print subscribe fail");
                     }
-                    if driver.command(1, slice_len, 0, AppId::kernel_new(APPID_IDX))
-                        != ReturnCode::SUCCESS
-                    {
+                    if driver.command(1, slice_len, 0, APPID) != ReturnCode::SUCCESS {
                         panic!("Debug print command fail");
                     }
                 }
@@ -342,7 +338,7 @@ impl DebugWriter {
 unsafe impl Sync for Callback {}
 
 static KERNEL_CONSOLE_CALLBACK: Callback =
-    Callback::kernel_new(AppId::kernel_new(APPID_IDX), DebugWriter::callback);
+    Callback::kernel_new(TaskId::DebugWriter, 0, DebugWriter::callback);
 
 impl Write for DebugWriter {
     fn write_str(&mut self, s: &str) -> Result {
@@ -514,6 +510,45 @@ impl Default for Debug {
     }
 }
 
+/// Print how much CPU time each process has used, in userspace and in system
+/// calls, to find the ones keeping the chip awake. Prints nothing unless the
+/// board has set a CPU time source with `procs::set_cpu_time_source()`.
+pub unsafe fn print_cpu_times() {
+    for process in process::PROCS.iter() {
+        if let Some(p) = process.as_ref() {
+            if let Some((user_us, syscall_us)) = p.cpu_time_us() {
+                debug!(
+                    "{}: user {} us, syscall {} us",
+                    p.package_name, user_us, syscall_us
+                );
+            }
+        }
+    }
+}
+
+/// Print the last system calls made by processes, with their arguments and
+/// what they returned, and the interrupts serviced between them, oldest
+/// first. Only available when the kernel is built with the `syscall_trace`
+/// feature.
+#[cfg(feature = "syscall_trace")]
+pub unsafe fn print_syscall_trace() {
+    syscall::for_each_traced(|entry| match *entry {
+        syscall::TraceEntry::Interrupt(interrupt) => debug!("interrupt {}", interrupt),
+        syscall::TraceEntry::Syscall {
+            appid,
+            syscall,
+            args,
+            result,
+        } => {
+            let name = process::get_package_name(appid.idx()).unwrap_or("?");
+            debug!(
+                "{}: {:?}({:#x}, {}, {:#x}, {:#x}) = {}",
+                name, syscall, args[0], args[1], args[2], args[3], result
+            );
+        }
+    });
+}
+
 pub unsafe fn flush<W: Write>(writer: &mut W) {
     let debug_head = read_volatile(&DEBUG_WRITER.output_head);
     let mut debug_tail = read_volatile(&DEBUG_WRITER.output_tail);
@@ -31,7 +31,18 @@
 //! greater than or equal to zero then the driver is present. Typically this is
 //! implemented by a null command that only returns 0, but in some cases the
 //! command can also return more information, like the number of supported
-//! devices (useful for things like the number of LEDs).
+//! devices (useful for things like the number of LEDs). The kernel enforces
+//! this: a present driver that does not implement command 0 returns
+//! `SUCCESS`.
+//!
+//! Apps can also ask the kernel directly, without calling the driver, using
+//! the driver discovery interface at driver number `QUERY_DRIVER_NUM`:
+//!
+//!   * command 0 returns `SUCCESS`, so apps can check the interface exists.
+//!
+//!   * command 1 with the driver number in r2 returns the driver's interface
+//!   version (see `Driver::version()`) if the driver is present, and
+//!   `ENODEVICE` otherwise.
 //!
 //! # The `yield` System-call
 //!
@@ -42,6 +53,15 @@ use callback::{AppId, Callback};
 use mem::{AppSlice, Shared};
 use returncode::ReturnCode;
 
+/// Driver number of the kernel's driver discovery interface.
+pub const QUERY_DRIVER_NUM: usize = 0xf0000;
+
+/// Set in the subscribe number to subscribe a callback for four values. The
+/// userdata must then be the address of a word of the process's memory, and
+/// the kernel writes the fourth value of each call there before the callback
+/// runs. Drivers see the subscribe number without this bit.
+pub const SUBSCRIBE_FOUR_VALUES: usize = 1 << 31;
+
 /// `Driver`s implement the three driver-specific system calls: `subscribe`,
 /// `command` and `allow`.
 ///
@@ -66,14 +86,25 @@ pub trait Driver {
     /// application, and the application is responsible for virtualizing that
     /// timer if it needs to.
     ///
-    /// The driver should signal success or failure through the sign of the
-    /// return value from `subscribe`. A negative return value signifies an
-    /// error, while positive a return values signifies success. In addition,
-    /// the magnitude of the return value of can signify extra information such
-    /// as error type.
+    /// A `None` callback unsubscribes, and the driver should drop the callback
+    /// it holds. The kernel itself removes the calls to the old callback that
+    /// are already queued for the process (see
+    /// `Process::remove_pending_callbacks()`), so drivers need not track them.
+    ///
+    /// On success, `subscribe` returns the callback that the new one replaces,
+    /// or `None` if the application had not subscribed before, so that the
+    /// kernel can hand its function address back to the application. The
+    /// kernel only hands back a callback subscribed by the same application.
+    /// On failure the new callback is not kept, and the error is returned to
+    /// the application.
     #[allow(unused_variables)]
-    fn subscribe(&self, minor_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
-        ReturnCode::ENOSUPPORT
+    fn subscribe(
+        &self,
+        minor_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> Result<Option<Callback>, ReturnCode> {
+        Err(ReturnCode::ENOSUPPORT)
     }
 
     /// `command` instructs a driver to perform some action synchronously. This
@@ -111,4 +142,23 @@ pub trait Driver {
     ) -> ReturnCode {
         ReturnCode::ENOSUPPORT
     }
+
+    /// The minor version of the driver's system call interface, reported by
+    /// the driver discovery interface. Drivers increment it when they add
+    /// commands or subscriptions, so that apps can check that the ones they
+    /// need are supported.
+    fn version(&self) -> usize {
+        0
+    }
+
+    /// Called after a process has faulted and been restarted or stopped.
+    /// `app_id` refers to the process as it was before, and the driver should
+    /// drop the `Callback`s and `AppSlice`s it keeps for it and stop any
+    /// operation it was running for it. State kept in a `Grant` need not be
+    /// dropped, as the kernel reclaims the grants of the process itself.
+    ///
+    /// Only drivers that the process subscribed to or allowed a buffer to are
+    /// called.
+    #[allow(unused_variables)]
+    fn app_terminated(&self, app_id: AppId) {}
 }
@@ -1,35 +1,73 @@
 //! Data structure to store a list of userspace applications.
 
 use callback::AppId;
+use core::intrinsics;
 use core::marker::PhantomData;
-use core::mem::size_of;
+use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
-use core::ptr::{read_volatile, write_volatile, Unique};
+use core::ptr::{read_volatile, write, write_volatile, Unique};
 use debug;
+use kernel_task::{self, TaskId};
 use process::{self, Error};
 
 pub static mut CONTAINER_COUNTER: usize = 0;
 
+/// How many grants are named in debug output. Grants created after these are
+/// reported by number only.
+const MAX_NAMED_GRANTS: usize = 32;
+
+/// The type each grant holds, by grant number, which says which capsule
+/// created it.
+static mut GRANT_NAMES: [&'static str; MAX_NAMED_GRANTS] = [""; MAX_NAMED_GRANTS];
+
+/// The number of grants created.
+pub fn num_grants() -> usize {
+    unsafe { read_volatile(&CONTAINER_COUNTER) }
+}
+
+/// The name of the type held by grant `grant_num`, such as
+/// `capsules::console::App`.
+pub fn grant_name(grant_num: usize) -> Option<&'static str> {
+    unsafe { GRANT_NAMES.get(grant_num).cloned() }.filter(|name| !name.is_empty())
+}
+
 pub struct Grant<T: Default> {
     grant_num: usize,
     ptr: PhantomData<T>,
 }
 
 pub struct AppliedGrant<T> {
-    appid: usize,
+    appid: AppId,
+    grant_num: usize,
     grant: *mut T,
     _phantom: PhantomData<T>,
 }
 
-/// This function contains the mapping of kernel "app" numbers to their
-/// functions for getting a pointer to their grant region. Normal apps are
-/// stored in a processes array, and finding apps is a matter of iterating that
-/// array. Kernel "apps" currently (June 2018) have no such structure, so
-/// finding them is a bit more ad-hoc.
-pub unsafe fn kernel_grant_for<T>(app_id: usize) -> *mut T {
-    match app_id {
-        debug::APPID_IDX => debug::get_grant(),
-        _ => panic!("lookup for invalid kernel grant {}", app_id),
+/// This function contains the mapping of kernel tasks to their functions for
+/// getting a pointer to their grant region. Normal apps are stored in a
+/// processes array, and finding apps is a matter of iterating that array.
+/// Registered kernel tasks keep their grant regions themselves, while the
+/// debug writer has a single region shared by every grant. Returns null if the
+/// region has not been allocated.
+pub unsafe fn kernel_grant_for<T>(task: TaskId, grant_num: usize) -> *mut T {
+    match task {
+        TaskId::DebugWriter => debug::get_grant(),
+        TaskId::Registered(number) => match kernel_task::get(number) {
+            Some(task) => task.grant_for(grant_num),
+            None => panic!("lookup for invalid kernel grant {}", number),
+        },
+    }
+}
+
+/// Like `kernel_grant_for()`, but allocates the region of a kernel task if it
+/// does not exist yet.
+unsafe fn kernel_grant_for_or_alloc<T: Default>(task: TaskId, grant_num: usize) -> Option<*mut T> {
+    match task {
+        TaskId::DebugWriter => Some(debug::get_grant()),
+        TaskId::Registered(number) => match kernel_task::get(number) {
+            Some(task) => task.grant_for_or_alloc(grant_num),
+            None => panic!("lookup for invalid kernel grant {}", number),
+        },
     }
 }
 
@@ -39,36 +77,34 @@ impl<T> AppliedGrant<T> {
         F: FnOnce(&mut Owned<T>, &mut Allocator) -> R,
         R: Copy,
     {
-        if AppId::is_kernel_idx(self.appid) {
-            let mut allocator = Allocator {
-                app: None,
-                app_id: self.appid,
-            };
-            let mut root = unsafe { Owned::new(self.grant, self.appid) };
-            fun(&mut root, &mut allocator)
-        } else {
-            let mut allocator = Allocator {
-                app: unsafe { process::PROCS[self.appid].as_mut() },
-                app_id: self.appid,
-            };
-            let mut root = unsafe { Owned::new(self.grant, self.appid) };
-            fun(&mut root, &mut allocator)
-        }
+        let app = match self.appid.process_idx() {
+            Some(idx) => unsafe { process::PROCS[idx].as_mut() },
+            None => None,
+        };
+        let mut allocator = Allocator {
+            app: app,
+            app_id: self.appid,
+            grant_num: self.grant_num,
+        };
+        let mut root = unsafe { Owned::new(self.grant, self.appid) };
+        fun(&mut root, &mut allocator)
     }
 }
 
 pub struct Allocator<'a> {
     app: Option<&'a mut &'a mut process::Process<'a>>,
-    app_id: usize,
+    app_id: AppId,
+    /// The grant the allocations are counted against.
+    grant_num: usize,
 }
 
 pub struct Owned<T: ?Sized> {
     data: Unique<T>,
-    app_id: usize,
+    app_id: AppId,
 }
 
 impl<T: ?Sized> Owned<T> {
-    unsafe fn new(data: *mut T, app_id: usize) -> Owned<T> {
+    unsafe fn new(data: *mut T, app_id: AppId) -> Owned<T> {
         Owned {
             data: Unique::new_unchecked(data),
             app_id: app_id,
@@ -76,19 +112,16 @@ impl<T: ?Sized> Owned<T> {
     }
 
     pub fn appid(&self) -> AppId {
-        AppId::new(self.app_id)
+        self.app_id
     }
 }
 
 impl<T: ?Sized> Drop for Owned<T> {
     fn drop(&mut self) {
         unsafe {
-            let app_id = self.app_id;
             let data = self.data.as_ptr() as *mut u8;
-            if AppId::is_kernel_idx(app_id) {
-                /* kernel free is nop */
-;
-            } else {
+            // The kernel tasks do not free their grant memory.
+            if let Some(app_id) = self.app_id.process_idx() {
                 match process::PROCS[app_id] {
                     None => {}
                     Some(ref mut app) => {
@@ -119,17 +152,31 @@ impl<'a> Allocator<'a> {
             let app_id = self.app_id;
             match self.app.as_mut() {
                 Some(app) => app
-                    .alloc(size_of::<T>())
+                    .alloc_for_grant(size_of::<T>(), self.grant_num)
                     .map_or(Err(Error::OutOfMemory), |arr| {
                         let mut owned = Owned::new(arr.as_mut_ptr() as *mut T, app_id);
                         *owned = data;
                         Ok(owned)
                     }),
                 None => {
-                    if !AppId::is_kernel_idx(app_id) {
-                        panic!("No app for allocator for {}", app_id);
+                    let number = match app_id.task() {
+                        Some(TaskId::Registered(number)) => number,
+                        Some(TaskId::DebugWriter) => panic!("Request to allocate in kernel grant"),
+                        None => panic!("No app for allocator for {}", app_id.idx()),
+                    };
+                    match kernel_task::get(number) {
+                        Some(task) => task.alloc(size_of::<T>(), align_of::<T>()).map_or(
+                            Err(Error::OutOfMemory),
+                            |ptr| {
+                                let owned = Owned::new(ptr as *mut T, app_id);
+                                // The memory is uninitialized, so it must not
+                                // be dropped.
+                                write(owned.data.as_ptr(), data);
+                                Ok(owned)
+                            },
+                        ),
+                        None => panic!("Request to allocate in kernel grant"),
                     }
-                    panic!("Request to allocate in kernel grant");
                 }
             }
         }
@@ -138,11 +185,11 @@ impl<'a> Allocator<'a> {
 
 pub struct Borrowed<'a, T: 'a + ?Sized> {
     data: &'a mut T,
-    app_id: usize,
+    app_id: AppId,
 }
 
 impl<'a, T: 'a + ?Sized> Borrowed<'a, T> {
-    pub fn new(data: &'a mut T, app_id: usize) -> Borrowed<T> {
+    pub fn new(data: &'a mut T, app_id: AppId) -> Borrowed<T> {
         Borrowed {
             data: data,
             app_id: app_id,
@@ -150,7 +197,7 @@ impl<'a, T: 'a + ?Sized> Borrowed<'a, T> {
     }
 
     pub fn appid(&self) -> AppId {
-        AppId::new(self.app_id)
+        self.app_id
     }
 }
 
@@ -171,6 +218,9 @@ impl<T: Default> Grant<T> {
     pub unsafe fn create() -> Grant<T> {
         let ctr = read_volatile(&CONTAINER_COUNTER);
         write_volatile(&mut CONTAINER_COUNTER, ctr + 1);
+        if let Some(name) = GRANT_NAMES.get_mut(ctr) {
+            *name = intrinsics::type_name::<T>();
+        }
         Grant {
             grant_num: ctr,
             ptr: PhantomData,
@@ -179,30 +229,25 @@ impl<T: Default> Grant<T> {
 
     pub fn grant(&self, appid: AppId) -> Option<AppliedGrant<T>> {
         unsafe {
-            let app_id = appid.idx();
-            if AppId::is_kernel(appid) {
-                let cntr = kernel_grant_for::<T>(app_id);
+            let cntr = match appid.task() {
+                Some(task) => kernel_grant_for::<T>(task, self.grant_num),
+                None if !appid.is_current() => return None,
+                None => match process::PROCS.get_mut(appid.idx()) {
+                    Some(&mut Some(ref mut app)) if !app.is_terminated() => {
+                        app.grant_for::<T>(self.grant_num)
+                    }
+                    _ => return None,
+                },
+            };
+            if cntr.is_null() {
+                None
+            } else {
                 Some(AppliedGrant {
-                    appid: app_id,
+                    appid: appid,
+                    grant_num: self.grant_num,
                     grant: cntr,
                     _phantom: PhantomData,
                 })
-            } else {
-                match process::PROCS[app_id] {
-                    Some(ref mut app) => {
-                        let cntr = app.grant_for::<T>(self.grant_num);
-                        if cntr.is_null() {
-                            None
-                        } else {
-                            Some(AppliedGrant {
-                                appid: app_id,
-                                grant: cntr,
-                                _phantom: PhantomData,
-                            })
-                        }
-                    }
-                    None => None,
-                }
             }
         }
     }
@@ -213,31 +258,42 @@ impl<T: Default> Grant<T> {
         R: Copy,
     {
         unsafe {
-            let app_id = appid.idx();
-            if AppId::is_kernel(appid) {
-                let root_ptr = kernel_grant_for::<T>(app_id);
-                let mut root = Borrowed::new(&mut *root_ptr, app_id);
-                let mut allocator = Allocator {
-                    app: None,
-                    app_id: app_id,
-                };
-                let res = fun(&mut root, &mut allocator);
-                Ok(res)
+            if let Some(task) = appid.task() {
+                kernel_grant_for_or_alloc::<T>(task, self.grant_num).map_or(
+                    Err(Error::OutOfMemory),
+                    |root_ptr| {
+                        let mut root = Borrowed::new(&mut *root_ptr, appid);
+                        let mut allocator = Allocator {
+                            app: None,
+                            app_id: appid,
+                            grant_num: self.grant_num,
+                        };
+                        let res = fun(&mut root, &mut allocator);
+                        Ok(res)
+                    },
+                )
+            } else if !appid.is_current() {
+                // The process has restarted or been replaced, so the grant
+                // region the caller means no longer exists.
+                Err(Error::NoSuchApp)
             } else {
-                match process::PROCS[app_id] {
-                    Some(ref mut app) => app.grant_for_or_alloc::<T>(self.grant_num).map_or(
-                        Err(Error::OutOfMemory),
-                        move |root_ptr| {
-                            let mut root = Borrowed::new(&mut *root_ptr, app_id);
-                            let mut allocator = Allocator {
-                                app: Some(app),
-                                app_id: app_id,
-                            };
-                            let res = fun(&mut root, &mut allocator);
-                            Ok(res)
-                        },
-                    ),
-                    None => Err(Error::NoSuchApp),
+                match process::PROCS.get_mut(appid.idx()) {
+                    Some(&mut Some(ref mut app)) if !app.is_terminated() => {
+                        app.grant_for_or_alloc::<T>(self.grant_num).map_or(
+                            Err(Error::OutOfMemory),
+                            move |root_ptr| {
+                                let mut root = Borrowed::new(&mut *root_ptr, appid);
+                                let mut allocator = Allocator {
+                                    app: Some(app),
+                                    app_id: appid,
+                                    grant_num: self.grant_num,
+                                };
+                                let res = fun(&mut root, &mut allocator);
+                                Ok(res)
+                            },
+                        )
+                    }
+                    _ => Err(Error::NoSuchApp),
                 }
             }
         }
@@ -248,18 +304,30 @@ impl<T: Default> Grant<T> {
         F: Fn(&mut Owned<T>),
     {
         unsafe {
-            let itr = process::PROCS.iter_mut().filter_map(|p| p.as_mut());
-            for (app_id, app) in itr.enumerate() {
+            let itr = process::PROCS
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(app_id, p)| p.as_mut().map(|app| (app_id, app)))
+                .filter(|&(_, ref app)| !app.is_terminated());
+            for (app_id, app) in itr {
                 let root_ptr = app.grant_for::<T>(self.grant_num);
                 if !root_ptr.is_null() {
-                    let mut root = Owned::new(root_ptr, app_id);
+                    let mut root = Owned::new(root_ptr, AppId::new(app_id));
                     fun(&mut root);
                 }
             }
-            // After iterating all possible normal apps, try the debug app.
-            let root_ptr = kernel_grant_for::<T>(debug::APPID_IDX);
+            // After iterating all possible normal apps, try the kernel tasks
+            // and then the debug app.
+            for task in kernel_task::tasks() {
+                let root_ptr = task.grant_for::<T>(self.grant_num);
+                if !root_ptr.is_null() {
+                    let mut root = Owned::new(root_ptr, task.appid().unwrap());
+                    fun(&mut root);
+                }
+            }
+            let root_ptr = kernel_grant_for::<T>(TaskId::DebugWriter, self.grant_num);
             if !root_ptr.is_null() {
-                let mut root = Owned::new(root_ptr, debug::APPID_IDX);
+                let mut root = Owned::new(root_ptr, AppId::kernel_new(TaskId::DebugWriter));
                 fun(&mut root);
             }
         }
@@ -294,11 +362,22 @@ impl<'a, T: Default> Iterator for Iter<'a, T> {
                 return res;
             }
         }
-        // After running through all real apps, pass the debug app (idx 255) to
-        // the grant iterator in case it has state the capsule needs to process.
-        if self.index == self.len {
+        // Then the kernel tasks.
+        while self.index < self.len + kernel_task::MAX_KERNEL_TASKS {
+            let task = self.index - self.len;
+            self.index += 1;
+            if let Some(task) = kernel_task::get(task) {
+                let res = self.grant.grant(task.appid().unwrap());
+                if res.is_some() {
+                    return res;
+                }
+            }
+        }
+        // After running through all real apps, pass the debug writer to the
+        // grant iterator in case it has state the capsule needs to process.
+        if self.index == self.len + kernel_task::MAX_KERNEL_TASKS {
             self.index += 1;
-            let res = self.grant.grant(AppId::new(debug::APPID_IDX));
+            let res = self.grant.grant(AppId::kernel_new(TaskId::DebugWriter));
             if res.is_some() {
                 return res;
             }
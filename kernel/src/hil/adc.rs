@@ -32,6 +32,38 @@ pub trait Client {
     fn sample_ready(&self, sample: u16);
 }
 
+/// The voltage samples are measured against.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Reference {
+    /// A reference generated inside the chip.
+    Internal,
+    /// The supply voltage, or a fixed fraction of it.
+    Supply,
+    /// A voltage applied to the chip's external reference pin.
+    External,
+}
+
+/// Interface for ADCs whose reference can be chosen, so that samples can be
+/// converted to voltages.
+pub trait AdcReference: Adc {
+    /// Select the reference for later samples. Returns `EBUSY` while
+    /// sampling and `ENOSUPPORT` if the chip does not support `reference`.
+    fn set_reference(&self, reference: Reference) -> ReturnCode;
+
+    /// The reference samples are currently measured against.
+    fn get_reference(&self) -> Reference;
+
+    /// The number of bits in a sample.
+    fn get_resolution_bits(&self) -> usize;
+
+    /// The input voltage, in microvolts, that gives a full-scale sample with
+    /// the current reference. `reference_uv` is the voltage of the reference
+    /// source: the supply voltage for `Reference::Supply` or the voltage on
+    /// the pin for `Reference::External`. It is ignored for
+    /// `Reference::Internal`, whose voltage the chip knows.
+    fn get_full_scale_uv(&self, reference_uv: u32) -> u32;
+}
+
 // *** Interfaces for high-speed, buffered ADC sampling ***
 
 /// Interface for continuously sampling at a given frequency on a channel.
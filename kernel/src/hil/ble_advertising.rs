@@ -164,3 +164,76 @@ impl RadioChannel {
         }
     }
 }
+
+/// The most advertising data a legacy advertising PDU carries.
+pub const ADV_DATA_MAX_LEN: usize = 31;
+
+// Bluetooth Assigned Numbers, Generic Access Profile data types
+pub const AD_TYPE_FLAGS: u8 = 0x01;
+pub const AD_TYPE_COMPLETE_16BIT_UUIDS: u8 = 0x03;
+pub const AD_TYPE_SHORT_LOCAL_NAME: u8 = 0x08;
+pub const AD_TYPE_COMPLETE_LOCAL_NAME: u8 = 0x09;
+pub const AD_TYPE_TX_POWER_LEVEL: u8 = 0x0a;
+pub const AD_TYPE_SERVICE_DATA_16BIT: u8 = 0x16;
+pub const AD_TYPE_MANUFACTURER_DATA: u8 = 0xff;
+
+/// Flags: LE General Discoverable Mode, BR/EDR not supported.
+pub const AD_FLAGS_LE_GENERAL_DISCOVERABLE: u8 = 0x06;
+
+/// Builds advertising data: a sequence of AD structures, each a length byte,
+/// an AD type and the data (Bluetooth Core Specification Vol. 3, Part C,
+/// section 11).
+///
+/// ```rust
+/// let mut adv = [0; ADV_DATA_MAX_LEN];
+/// let len = {
+///     let mut builder = AdvDataBuilder::new(&mut adv);
+///     builder.push(AD_TYPE_FLAGS, &[AD_FLAGS_LE_GENERAL_DISCOVERABLE]);
+///     builder.push(AD_TYPE_COMPLETE_LOCAL_NAME, b"tock");
+///     builder.len()
+/// };
+/// ```
+pub struct AdvDataBuilder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> AdvDataBuilder<'a> {
+    /// Build into `buffer`, using at most `ADV_DATA_MAX_LEN` bytes of it.
+    pub fn new(buffer: &'a mut [u8]) -> AdvDataBuilder<'a> {
+        AdvDataBuilder {
+            buffer: buffer,
+            len: 0,
+        }
+    }
+
+    /// Append an AD structure of type `ad_type` holding `data`.
+    pub fn push(&mut self, ad_type: u8, data: &[u8]) -> ReturnCode {
+        self.push_with(ad_type, data.len(), |buffer| buffer.copy_from_slice(data))
+    }
+
+    /// Append an AD structure of type `ad_type` with `len` bytes of data,
+    /// which `fill` writes. Returns `ESIZE`, appending nothing, if the
+    /// structure does not fit.
+    pub fn push_with<F: FnOnce(&mut [u8])>(
+        &mut self,
+        ad_type: u8,
+        len: usize,
+        fill: F,
+    ) -> ReturnCode {
+        let end = self.len + 2 + len;
+        if end > self.buffer.len() || end > ADV_DATA_MAX_LEN {
+            return ReturnCode::ESIZE;
+        }
+        self.buffer[self.len] = (len + 1) as u8;
+        self.buffer[self.len + 1] = ad_type;
+        fill(&mut self.buffer[self.len + 2..end]);
+        self.len = end;
+        ReturnCode::SUCCESS
+    }
+
+    /// The length of the advertising data built so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
@@ -1,4 +1,9 @@
 //! Interface for CRC computation.
+//!
+//! A CRC can be computed over one buffer with `compute()`, or over several
+//! buffers in sequence: `init()` starts the calculation, each `update()` adds
+//! a buffer, and `finalize()` returns the result. The unit is reserved for the
+//! calculation from `init()` until `finalize()`.
 
 use returncode::ReturnCode;
 
@@ -28,6 +33,19 @@ pub trait CRC {
     /// Initiate a CRC calculation
     fn compute(&self, data: &[u8], CrcAlg) -> ReturnCode;
 
+    /// Start a calculation over several buffers. Returns EBUSY if the unit is
+    /// in use.
+    fn init(&self, CrcAlg) -> ReturnCode;
+
+    /// Add `data` to the calculation started with `init()`.
+    /// `Client::update_done()` is called once the unit is ready for the next
+    /// buffer. Returns EOFF if no calculation was started.
+    fn update(&self, data: &[u8]) -> ReturnCode;
+
+    /// Finish the calculation started with `init()` and return its result.
+    /// Must not be called while an update is in progress.
+    fn finalize(&self) -> u32;
+
     /// Disable the CRC unit until compute() is next called
     fn disable(&self);
 }
@@ -35,4 +53,7 @@ pub trait CRC {
 pub trait Client {
     /// Receive the successful result of a CRC calculation
     fn receive_result(&self, u32);
+
+    /// An `update()` has finished with the buffer it was given
+    fn update_done(&self);
 }
@@ -0,0 +1,14 @@
+//! Interface for reading the identifier programmed into a chip at the
+//! factory.
+//!
+//! Most chips carry a serial number or random identifier that is unique to
+//! each part and cannot be changed. The kernel uses it wherever it needs a
+//! value that is stable across reboots and reflashing but differs between
+//! boards, such as a radio's hardware address.
+
+pub trait DeviceId {
+    /// Return a 64-bit identifier for this chip. Chips whose factory
+    /// identifier is longer than 64 bits fold it into 64 bits, so the value
+    /// is unique only with very high probability.
+    fn device_id(&self) -> u64;
+}
@@ -0,0 +1,50 @@
+//! Interface for ECDSA signatures over the NIST P-256 curve.
+//!
+//! Implementations are typically secure elements that hold the private keys,
+//! so that keys never enter the MCU's memory. Keys are identified by the
+//! number of the slot they are stored in.
+
+use returncode::ReturnCode;
+
+/// The length of the SHA-256 digest of a message, which is what is signed.
+pub const DIGEST_LEN: usize = 32;
+
+/// The length of a signature: `R` followed by `S`, each 32 bytes big endian.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The length of a public key: `X` followed by `Y`, each 32 bytes big endian.
+pub const PUBLIC_KEY_LEN: usize = 64;
+
+pub trait EcdsaP256 {
+    fn set_client(&self, client: &'static Client);
+
+    /// Sign the digest in the first `DIGEST_LEN` bytes of `buffer` with the
+    /// private key in slot `key`. The signature replaces the digest, so
+    /// `buffer` must be at least `SIGNATURE_LEN` bytes long.
+    ///
+    /// On error, `buffer` is handed back. Returns `EBUSY` if an operation is
+    /// in progress and `ESIZE` if `buffer` is too short.
+    fn sign(
+        &self,
+        key: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Check that `buffer` holds a digest, followed by a signature of it,
+    /// followed by the public key of the private key that made the
+    /// signature.
+    ///
+    /// On error, `buffer` is handed back. Returns `EBUSY` if an operation is
+    /// in progress and `ESIZE` if `buffer` is too short.
+    fn verify(&self, buffer: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+pub trait Client {
+    /// A signature has been made, or failed with `result`.
+    fn sign_done(&self, result: ReturnCode, buffer: &'static mut [u8]);
+
+    /// A signature has been checked. `valid` says whether it is a signature
+    /// of the digest by the owner of the public key, and is only meaningful
+    /// if `result` is `SUCCESS`.
+    fn verify_done(&self, result: ReturnCode, valid: bool, buffer: &'static mut [u8]);
+}
@@ -0,0 +1,75 @@
+//! Interface for Enhanced ShockBurst (ESB), the proprietary 2.4 GHz packet
+//! protocol of Nordic radios.
+//!
+//! ESB is the protocol of nRF24 transceivers, so a Tock board can take the
+//! place of either end of an existing nRF24 link. Each packet carries up to
+//! `MAX_PAYLOAD_LEN` bytes to a 3 to 5 byte address, on one of 101 channels
+//! 1 MHz apart from 2400 MHz.
+//!
+//! With acknowledgements enabled, the receiver answers each packet with an
+//! empty acknowledgement, and the transmitter reports a transmission as done
+//! only once the acknowledgement arrives. The radio does not time out waiting
+//! for one: the user decides how long to wait, then stops the radio and calls
+//! `retransmit()`. A retransmitted packet keeps its packet ID, so the
+//! receiver drops it if it already received the packet and only the
+//! acknowledgement was lost.
+//!
+//! Only one of ESB and BLE can use the radio at a time, as each reconfigures
+//! it when it starts sending or receiving.
+
+use returncode::ReturnCode;
+
+/// The largest payload of a packet.
+pub const MAX_PAYLOAD_LEN: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DataRate {
+    Rate1Mbit,
+    Rate2Mbit,
+    Rate250Kbit,
+}
+
+pub trait Esb {
+    fn set_transmit_client(&self, client: &'static TxClient);
+    fn set_receive_client(&self, client: &'static RxClient);
+
+    /// Set the address packets are sent to and received on, in the order the
+    /// bytes are sent. Returns `EINVAL` if it is not 3 to 5 bytes long, and
+    /// `EBUSY` if the radio is sending or receiving.
+    fn set_address(&self, address: &[u8]) -> ReturnCode;
+
+    /// Set the channel, from 0 to 100, which is 2400 MHz plus that many MHz,
+    /// and the data rate. Returns `EINVAL` for any other channel and
+    /// `ENOSUPPORT` if the radio does not support the rate.
+    fn set_channel(&self, channel: u8, rate: DataRate) -> ReturnCode;
+
+    /// Whether packets sent ask to be acknowledged, and whether packets
+    /// received that ask for it are acknowledged.
+    fn set_auto_ack(&self, enabled: bool);
+
+    /// Send `payload` as a new packet. Returns `ESIZE` if it is longer than
+    /// `MAX_PAYLOAD_LEN`, and `EBUSY` if the radio is sending or receiving.
+    fn transmit(&self, payload: &[u8]) -> ReturnCode;
+
+    /// Send the last packet again, with its packet ID. Returns `EINVAL` if no
+    /// packet has been sent.
+    fn retransmit(&self) -> ReturnCode;
+
+    /// Receive packets until `stop()` is called.
+    fn start_receive(&self) -> ReturnCode;
+
+    /// Stop sending, waiting for an acknowledgement or receiving. The client
+    /// is not told about a transmission that is stopped.
+    fn stop(&self);
+}
+
+pub trait TxClient {
+    /// The packet has been sent and, if acknowledgements are enabled,
+    /// acknowledged.
+    fn transmit_done(&self, result: ReturnCode);
+}
+
+pub trait RxClient {
+    /// A packet has arrived. Packets that fail their CRC are not passed on.
+    fn receive(&self, payload: &[u8]);
+}
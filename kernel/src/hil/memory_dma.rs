@@ -0,0 +1,43 @@
+//! Interface for DMA controllers that can copy and fill memory.
+//!
+//! Copying or clearing a frame buffer or a large radio payload with the CPU
+//! keeps it busy for the whole transfer. On chips with a general-purpose DMA
+//! controller, `MemoryDma` moves the bytes in the background instead and
+//! tells its client when they are done. Buffers are handed to the controller
+//! for the duration of the transfer and returned to the client afterwards.
+
+use returncode::ReturnCode;
+
+/// A DMA controller that can move memory.
+pub trait MemoryDma {
+    fn set_client(&self, client: &'static Client);
+
+    /// Copy the first `len` bytes of `source` to `dest`. On error, returns
+    /// `EBUSY` if a transfer is in progress, `EINVAL` if `len` is zero or
+    /// `ESIZE` if either buffer is shorter than `len`, along with the
+    /// buffers.
+    fn copy(
+        &self,
+        source: &'static mut [u8],
+        dest: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<(&'static mut [u8], &'static mut [u8])>);
+
+    /// Set the first `len` bytes of `dest` to `value`. Errors are reported
+    /// as for `copy`.
+    fn fill(
+        &self,
+        dest: &'static mut [u8],
+        value: u8,
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+/// Client interface for `MemoryDma` transfers.
+pub trait Client {
+    /// Called when a copy of `len` bytes has finished.
+    fn copy_done(&self, source: &'static mut [u8], dest: &'static mut [u8], len: usize);
+
+    /// Called when a fill of `len` bytes has finished.
+    fn fill_done(&self, dest: &'static mut [u8], len: usize);
+}
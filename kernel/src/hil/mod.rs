@@ -4,14 +4,23 @@ pub mod adc;
 pub mod ble_advertising;
 pub mod crc;
 pub mod dac;
+pub mod device_id;
+pub mod ecdsa;
+pub mod esb;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
 pub mod i2c;
 pub mod led;
+pub mod memory_dma;
+pub mod nfc;
 pub mod nonvolatile_storage;
+pub mod pwm;
 pub mod radio;
+pub mod reset;
+pub mod rf_frontend;
 pub mod rng;
+pub mod sdio;
 pub mod sensors;
 pub mod spi;
 pub mod symmetric_encryption;
@@ -0,0 +1,40 @@
+//! Interface for emulating an NFC tag.
+//!
+//! A tag answers the reads of an NFC reader, such as a phone, with an NDEF
+//! message, for example a URL to open or the settings a new device should
+//! be provisioned with. The tag is powered by the reader's field, so
+//! emulating one costs almost nothing until a reader comes close.
+
+use returncode::ReturnCode;
+
+/// A tag that presents an NDEF message to readers.
+pub trait NdefTag {
+    fn set_client(&self, client: &'static Client);
+
+    /// The longest NDEF message the tag can hold, in bytes.
+    fn max_message_len(&self) -> usize;
+
+    /// Start presenting the NDEF message in the first `len` bytes of
+    /// `message`, and listening for a reader's field.
+    ///
+    /// On error, `message` is handed back. Returns `EBUSY` if the tag is
+    /// already enabled and `ESIZE` if `len` is longer than
+    /// `max_message_len()` or `message`.
+    fn enable(
+        &self,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Stop presenting the message and hand back its buffer, or `None` if the
+    /// tag was not enabled. A reader in the field sees the tag disappear.
+    fn disable(&self) -> Option<&'static mut [u8]>;
+}
+
+pub trait Client {
+    /// A reader's field has appeared, so a reader is about to read the tag.
+    fn field_detected(&self);
+
+    /// The reader's field has gone.
+    fn field_lost(&self);
+}
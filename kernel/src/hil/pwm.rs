@@ -0,0 +1,17 @@
+//! Interface for pulse width modulated outputs.
+
+use returncode::ReturnCode;
+
+/// A pin that outputs a pulse width modulated square wave.
+pub trait PwmPin {
+    /// Start the square wave at `frequency_hz`, high for `duty_cycle` out of
+    /// `get_maximum_duty_cycle()` of each period. Calling `start()` while the
+    /// pin is running changes the wave.
+    fn start(&self, frequency_hz: usize, duty_cycle: usize) -> ReturnCode;
+
+    /// Stop the square wave and leave the pin low.
+    fn stop(&self) -> ReturnCode;
+
+    /// The `duty_cycle` at which the pin is always high.
+    fn get_maximum_duty_cycle(&self) -> usize;
+}
@@ -6,17 +6,48 @@
 //! address of packets but does not change the address stored in hardware used
 //! for address recognition. This must be committed to hardware with a call to
 //! config_commit. Please see the relevant TRD for more details.
+//!
+//! Buffer ownership
+//! ----------------
+//!
+//! Frame buffers move between the radio and the layers above it, and the
+//! layer holding a buffer is the only one that may touch it:
+//!
+//! - A buffer passed to `transmit` belongs to the radio until it is given
+//!   back, once: either returned by `transmit` if it fails, or passed to
+//!   `TxClient::send_done`. A radio that returns an error must also return
+//!   the buffer.
+//! - A buffer passed to `set_receive_client` or `set_receive_buffer` belongs
+//!   to the radio until the radio passes it to `RxClient::receive`. The
+//!   client then owns it, and must give the same buffer back with
+//!   `set_receive_buffer`, once, before the radio can receive another frame.
+//!
+//! The same holds between each MAC layer and the layer above it, and the
+//! layers track the buffers they lend with a `common::lease::Lease`, so that
+//! debug builds catch a buffer that is given back twice, not at all, or in
+//! place of another.
 
+use common::SegmentList;
+use hil::time;
 use returncode::ReturnCode;
 pub trait TxClient {
     fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode);
+
+    /// Called when a `transmit_segments` operation finishes. Clients that
+    /// never transmit segments need not implement this.
+    fn send_segments_done(&self, _segments: SegmentList, _acked: bool, _result: ReturnCode) {}
 }
 
 pub trait RxClient {
+    /// `rssi` is the received signal strength of the frame in dBm and `lqi`
+    /// the link quality indicator reported by the radio, from 0 (worst) to
+    /// 255 (best). Radios that cannot measure either report 0.
     fn receive(
         &self,
         buf: &'static mut [u8],
         frame_len: usize,
+        rssi: i8,
+        lqi: u8,
         crc_valid: bool,
         result: ReturnCode,
     );
@@ -78,6 +109,10 @@ pub trait RadioConfig {
     fn stop(&self) -> ReturnCode;
     fn is_on(&self) -> bool;
     fn busy(&self) -> bool;
+    /// Worst-case time, in microseconds, from `start()` on a stopped radio
+    /// until it can receive. Layers that duty cycle the radio use this to
+    /// wake it early enough.
+    fn wake_latency_us(&self) -> u32;
 
     fn set_power_client(&self, client: &'static PowerClient);
 
@@ -98,6 +133,11 @@ pub trait RadioConfig {
     fn set_pan(&self, id: u16);
     fn set_tx_power(&self, power: i8) -> ReturnCode;
     fn set_channel(&self, chan: u8) -> ReturnCode;
+
+    /// Pass every received frame to the receive client regardless of its
+    /// destination address, and do not acknowledge any frames. Like the
+    /// other settings this takes effect on the next config_commit.
+    fn set_promiscuous(&self, enable: bool) -> ReturnCode;
 }
 
 pub trait RadioData {
@@ -110,4 +150,30 @@ pub trait RadioData {
         spi_buf: &'static mut [u8],
         frame_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Transmit a frame split across `segments`, for example a MAC header
+    /// and a payload held in separate buffers. The first segment must start
+    /// with PSDU_OFFSET bytes reserved for the radio, like the buffer passed
+    /// to `transmit`. `frame_len` is the total length of the MAC frame
+    /// excluding the FCS, i.e. the length of the segments minus PSDU_OFFSET.
+    /// The segments are returned on error, and otherwise through
+    /// `TxClient::send_segments_done`.
+    fn transmit_segments(
+        &self,
+        segments: SegmentList,
+        frame_len: usize,
+    ) -> (ReturnCode, Option<SegmentList>);
+
+    /// Timestamp frames against `source`. Timestamps mark the end of the
+    /// frame's start-of-frame delimiter (SFD), so that a sender and its
+    /// receivers timestamp the same instant whatever the length of the
+    /// frame, as time synchronization and ranging need. Radios that cannot
+    /// capture the SFD itself derive it from another event of the frame.
+    fn set_timestamp_source(&self, source: &'static time::Timestamp);
+    /// The timestamp of the last received frame, if a timestamp source is
+    /// set. Valid while the frame is passed to the receive client.
+    fn rx_timestamp(&self) -> Option<u64>;
+    /// The timestamp of the last transmitted frame, if a timestamp source is
+    /// set. Valid while the transmit client is notified.
+    fn tx_timestamp(&self) -> Option<u64>;
 }
@@ -0,0 +1,44 @@
+//! Interface for finding out why the chip last reset.
+//!
+//! Chips latch the cause of the most recent reset in a status register, and
+//! usually also provide a small amount of state that survives every reset
+//! except a power-on reset. Together these let the kernel tell a clean boot
+//! apart from a watchdog or brown-out recovery.
+
+/// The cause of the most recent reset.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResetReason {
+    /// Cold boot with supply voltage coming up.
+    PowerOn = 0,
+    /// Supply voltage dropped below the brown-out threshold.
+    BrownOut = 1,
+    /// The watchdog timer expired.
+    Watchdog = 2,
+    /// The CPU locked up (e.g. a fault while handling a fault).
+    Lockup = 3,
+    /// Software requested a reset (SYSRESETREQ).
+    Software = 4,
+    /// The external reset pin was asserted.
+    ExternalPin = 5,
+    /// Wake up from a deep sleep mode that resets the chip.
+    Wakeup = 6,
+    /// The chip reported a cause not covered above, or none at all.
+    Unknown = 7,
+}
+
+pub trait ResetInfo {
+    /// Return the reason for the most recent reset.
+    ///
+    /// Some chips accumulate reset causes until they are cleared, so
+    /// implementations may clear the hardware latch. Callers should read this
+    /// once at boot and cache the result.
+    fn reset_reason(&self) -> ResetReason;
+
+    /// Read a 16-bit value that is preserved across all resets except
+    /// power-on and brown-out resets.
+    fn read_retained(&self) -> u16;
+
+    /// Write the value returned by future calls to `read_retained()`. This
+    /// must be safe to call from a panic handler.
+    fn write_retained(&self, value: u16);
+}
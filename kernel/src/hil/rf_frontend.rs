@@ -0,0 +1,37 @@
+//! Interface for controlling the RF frontend of a radio.
+//!
+//! Long-range boards put a frontend between the radio and its antennas: an
+//! external power amplifier (PA) that must be enabled to transmit, a
+//! low-noise amplifier (LNA) that must be enabled to receive, and on boards
+//! with antenna diversity a switch selecting between antennas. A board
+//! configures a frontend and gives it to its radio driver, which calls it
+//! around every transmission and reception, so that neither the capsules
+//! above the radio nor applications need to know about it.
+//!
+//! The radio driver calls `transmit` just before it starts transmitting,
+//! `receive` when it starts listening and `sleep` when it turns off. Which
+//! antenna is used is up to a higher layer, which can for example try each
+//! antenna and keep the one that receives the strongest signal.
+
+use returncode::ReturnCode;
+
+pub trait RfFrontend {
+    /// Switch the frontend to transmit: enable the PA and disable the LNA.
+    fn transmit(&self);
+
+    /// Switch the frontend to receive: enable the LNA and disable the PA.
+    fn receive(&self);
+
+    /// Disable both the PA and the LNA while the radio is off.
+    fn sleep(&self);
+
+    /// The number of antennas the frontend can switch between, at least 1.
+    fn antennas(&self) -> usize;
+
+    /// Select the antenna used from the next transmission or reception,
+    /// from 0 to `antennas() - 1`. Returns `EINVAL` for any other antenna.
+    fn select_antenna(&self, antenna: usize) -> ReturnCode;
+
+    /// The antenna selected.
+    fn antenna(&self) -> usize;
+}
@@ -0,0 +1,93 @@
+//! Interface for native SD bus host controllers (SDMMC/SDIO peripherals).
+//!
+//! Unlike SPI mode, the native SD bus has separate command and data lines and
+//! can transfer data over four lanes. The host controller only moves
+//! commands, responses and data blocks; the card protocol (initialization,
+//! addressing) is handled by the layer above.
+
+use returncode::ReturnCode;
+
+/// Number of data lines used for transfers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BusWidth {
+    One,
+    Four,
+}
+
+/// The response format expected for a command.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResponseType {
+    /// No response (e.g. CMD0).
+    None,
+    /// 48-bit response with card status.
+    R1,
+    /// R1 followed by a busy signal on DAT0.
+    R1b,
+    /// 136-bit response carrying the CID or CSD register.
+    R2,
+    /// 48-bit OCR response without a valid CRC.
+    R3,
+    /// 48-bit published RCA response.
+    R6,
+    /// 48-bit card interface condition response.
+    R7,
+}
+
+/// A native SD bus host controller.
+pub trait SdioHost {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the bus clock as close as possible to, but not above, `hz`.
+    /// Identification must run at 400 kHz or below.
+    fn set_clock(&self, hz: u32) -> ReturnCode;
+
+    fn set_bus_width(&self, width: BusWidth) -> ReturnCode;
+
+    /// Whether a card is present, if the host has a card-detect input.
+    fn card_detected(&self) -> bool {
+        true
+    }
+
+    /// Send a command with no data phase. `Client::command_complete()` is
+    /// called with the response.
+    fn send_command(&self, index: u8, argument: u32, response: ResponseType) -> ReturnCode;
+
+    /// Send a command that reads `count` blocks of `block_size` bytes into
+    /// `buffer`. `Client::data_complete()` is called when all blocks have
+    /// been received.
+    fn read_blocks(
+        &self,
+        index: u8,
+        argument: u32,
+        buffer: &'static mut [u8],
+        block_size: usize,
+        count: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Send a command that writes `count` blocks of `block_size` bytes from
+    /// `buffer`. `Client::data_complete()` is called once the card has
+    /// released the busy signal after the last block.
+    fn write_blocks(
+        &self,
+        index: u8,
+        argument: u32,
+        buffer: &'static mut [u8],
+        block_size: usize,
+        count: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+/// Completion callbacks from an `SdioHost`.
+pub trait Client {
+    /// A command without a data phase completed.
+    ///
+    /// For 48-bit responses `response[0]` holds the 32-bit payload. For R2
+    /// responses the 128-bit register is stored most significant word first,
+    /// i.e. `response[0]` holds bits 127..96. A timeout is reported as
+    /// `ENOACK`, a CRC error as `FAIL`.
+    fn command_complete(&self, result: ReturnCode, response: [u32; 4]);
+
+    /// A data transfer started by `read_blocks()` or `write_blocks()`
+    /// completed.
+    fn data_complete(&self, result: ReturnCode, buffer: &'static mut [u8]);
+}
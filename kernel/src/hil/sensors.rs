@@ -51,6 +51,68 @@ pub trait AmbientLightClient {
     fn callback(&self, lux: usize);
 }
 
+/// A basic interface for a distance sensor, such as an ultrasonic or
+/// time-of-flight rangefinder.
+pub trait Distance {
+    /// Set the client to be notified when a distance reading has completed.
+    fn set_client(&self, client: &'static DistanceClient);
+
+    /// Measure the distance to the nearest object once.
+    fn read_distance(&self) -> ReturnCode {
+        ReturnCode::ENODEVICE
+    }
+}
+
+/// Client for receiving distance readings.
+pub trait DistanceClient {
+    /// Called when a distance reading has completed.
+    ///
+    /// - `millimeters`: the distance to the nearest object in millimeters, or
+    /// `None` if no object was within range of the sensor.
+    fn callback(&self, millimeters: Option<usize>);
+}
+
+/// A basic interface for a weight or force sensor, such as a load cell.
+pub trait Weight {
+    /// Set the client to be notified when a weight reading has completed.
+    fn set_client(&self, client: &'static WeightClient);
+
+    /// Get a single reading of the load on the sensor.
+    fn read_weight(&self) -> ReturnCode {
+        ReturnCode::ENODEVICE
+    }
+}
+
+/// Client for receiving weight readings.
+pub trait WeightClient {
+    /// Called when a weight reading has completed.
+    ///
+    /// - `milligrams`: the load on the sensor in milligrams relative to its
+    /// calibrated zero. A force sensor reports the weight that would exert the
+    /// same force. Negative if the load is below the zero point.
+    fn callback(&self, milligrams: i32);
+}
+
+/// A basic interface for a soil moisture sensor.
+pub trait Moisture {
+    /// Set the client to be notified when a moisture reading has completed.
+    fn set_client(&self, client: &'static MoistureClient);
+
+    /// Get a single reading of the moisture.
+    fn read_moisture(&self) -> ReturnCode {
+        ReturnCode::ENODEVICE
+    }
+}
+
+/// Client for receiving moisture readings.
+pub trait MoistureClient {
+    /// Called when a moisture reading has completed.
+    ///
+    /// - `value`: the moisture in hundredths of percent, from `0` for dry to
+    /// `10000` for saturated.
+    fn callback(&self, value: usize);
+}
+
 /// A basic interface for a 9-DOF compatible chip.
 ///
 /// This trait provides a standard interface for chips that implement
@@ -1,5 +1,6 @@
 //! Interfaces for SPI master and slave communication.
 
+use common::SegmentList;
 use core::option::Option;
 use returncode::ReturnCode;
 
@@ -32,6 +33,10 @@ pub trait SpiMasterClient {
         read_buffer: Option<&'static mut [u8]>,
         len: usize,
     );
+
+    /// Called when a `write_segments` operation finishes. Clients that never
+    /// write segments need not implement this.
+    fn write_segments_done(&self, _segments: SegmentList) {}
 }
 /// The `SpiMaster` trait for interacting with SPI slave
 /// devices at a byte or buffer level.
@@ -142,6 +147,12 @@ pub trait SpiMasterDevice {
         len: usize,
     ) -> ReturnCode;
 
+    /// Write each segment in turn as one transfer, keeping the chip selected
+    /// between segments. Completion is signaled by invoking
+    /// SpiMasterClient.write_segments_done. On error the segments are
+    /// returned immediately. Segments must not be empty.
+    fn write_segments(&self, segments: SegmentList) -> (ReturnCode, Option<SegmentList>);
+
     fn set_polarity(&self, cpol: ClockPolarity);
     fn set_phase(&self, cpal: ClockPhase);
     fn set_rate(&self, rate: u32);
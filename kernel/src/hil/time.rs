@@ -18,6 +18,18 @@ pub trait Frequency {
     fn frequency() -> u32;
 }
 
+/// A clock whose actual rate is measured while the system runs.
+///
+/// Clocks such as low-frequency RC oscillators drift from their nominal
+/// `Frequency` with temperature and supply voltage. Clients that convert
+/// between ticks and real time over long intervals should prefer the measured
+/// value.
+pub trait MeasuredFrequency {
+    /// Returns the most recently measured frequency in Hz, or the nominal
+    /// frequency if no measurement has finished yet.
+    fn measured_frequency(&self) -> u32;
+}
+
 /// 16MHz `Frequency`
 #[derive(Debug)]
 pub struct Freq16MHz;
@@ -83,6 +95,19 @@ pub trait Alarm: Time {
     fn get_alarm(&self) -> u32;
 }
 
+/// An [`Alarm`](trait.Alarm.html) whose client can say how late it may be
+/// handled.
+///
+/// Plain alarms put no constraint on how long the chip takes to wake up for
+/// them. A deadline alarm still fires at `tics`, but also tells the power
+/// manager that its client must run no later than `deadline`, so the chip
+/// only uses sleep states it can wake up from in time.
+pub trait DeadlineAlarm: Alarm {
+    /// Sets a one-shot alarm to fire when the clock reaches `tics`, which
+    /// must be handled before the clock reaches `deadline`.
+    fn set_alarm_with_deadline(&self, tics: u32, deadline: u32);
+}
+
 /// A client of an implementor of the [`Alarm`](trait.Alarm.html) trait.
 pub trait Client {
     /// Callback signaled when the alarm's clock reaches the value set in
@@ -90,6 +115,16 @@ pub trait Client {
     fn fired(&self);
 }
 
+/// A 64-bit time source that does not wrap in practice, used to timestamp
+/// events and to align them across devices.
+pub trait Timestamp {
+    /// Returns the current time in ticks of `frequency()` Hz.
+    fn timestamp(&self) -> u64;
+
+    /// The frequency of the timestamp ticks in Hz.
+    fn frequency(&self) -> u32;
+}
+
 /// The `Timer` trait models a timer that can notify when a particular interval
 /// has elapsed.
 pub trait Timer: Time {
@@ -2,11 +2,52 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! A process that shares a buffer with a peer can find out how large the peer
+//! needs it to be: each process can set the minimum size of the buffers it
+//! accepts, which `allow` enforces and other processes can query.
+//!
+//! A notification can also carry a message. The sender writes the message
+//! into the buffer it shares with the receiver, starting after a
+//! `MESSAGE_HEADER_LEN` byte header, and passes its length when notifying.
+//! The kernel checks that the message fits in the buffer and writes the
+//! length into the header as a little endian `u32`, so the receiver gets a
+//! length-prefixed message it can trust to be within the buffer.
+//!
+//! A service can also publish a message to every client subscribed to it in
+//! one operation. The service names its publish buffer by sharing a buffer
+//! with itself (allowing with its own id). On publish the kernel copies the
+//! message, with a header, into the buffer each subscriber shares with the
+//! service and notifies the subscriber through the callback it subscribed for
+//! that service.
+//!
+//! A service can also notify every client that registered a callback for it
+//! at once, as if it had notified each of them in turn.
+//!
+//! A service can declare a 16 bit version word, for example a protocol
+//! version and feature flags, which discovering the service returns next to
+//! its id, so that a client knows how to talk to it. A service can also
+//! reject a client it does not recognize: the client can then no longer
+//! discover the service, share buffers with it, notify it or subscribe to
+//! it, and the buffer it shared with the service is dropped.
+//!
+//! Shared buffers do not need to be a power of two in size. Any buffer one
+//! MPU region can cover exactly, using its subregions (see
+//! `mpu::region_layout`), can be shared: a buffer whose start and length are
+//! multiples of the subregion size of a region it fits in.
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x00010000;
 
+/// Length of the header in front of a message in a shared buffer.
+pub const MESSAGE_HEADER_LEN: usize = 4;
+
+/// Where the version word of a service is in the value returned by
+/// discovery, above the service id.
+pub const VERSION_SHIFT: usize = 8;
+
 use callback::{AppId, Callback};
+use core::mem;
 use driver::Driver;
 use grant::Grant;
 use mem::{AppSlice, Shared};
@@ -17,6 +58,14 @@ struct IPCData {
     shared_memory: [Option<AppSlice<Shared, u8>>; 8],
     client_callbacks: [Option<Callback>; 8],
     callback: Option<Callback>,
+    /// The minimum size of buffers other processes may share with this one.
+    buffer_size: usize,
+    /// Processes subscribed to messages published by this one.
+    subscribers: [bool; 8],
+    /// The version word of the service, returned by discovery.
+    version: u16,
+    /// Clients this service has rejected.
+    rejected: [bool; 8],
 }
 
 impl Default for IPCData {
@@ -25,6 +74,10 @@ impl Default for IPCData {
             shared_memory: [None, None, None, None, None, None, None, None],
             client_callbacks: [None, None, None, None, None, None, None, None],
             callback: None,
+            buffer_size: 0,
+            subscribers: [false; 8],
+            version: 0,
+            rejected: [false; 8],
         }
     }
 }
@@ -46,6 +99,10 @@ impl IPC {
         otherapp: AppId,
         cb_type: process::IPCType,
     ) {
+        if let process::IPCType::Publish = cb_type {
+            self.schedule_publish_callback(appid, otherapp);
+            return;
+        }
         self.data
             .enter(appid, |mydata, _| {
                 let callback = match cb_type {
@@ -53,6 +110,7 @@ impl IPC {
                     process::IPCType::Client => {
                         *mydata.client_callbacks.get(otherapp.idx()).unwrap_or(&None)
                     }
+                    process::IPCType::Publish => None,
                 };
                 callback
                     .map(|mut callback| {
@@ -83,6 +141,276 @@ impl IPC {
     }
 }
 
+impl IPC {
+    fn configure(&self, op: usize, arg: usize, appid: AppId) -> ReturnCode {
+        match op {
+            0 => self
+                .data
+                .enter(appid, |data, _| {
+                    data.buffer_size = arg;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or(ReturnCode::EBUSY),
+            1 => {
+                let procs = unsafe { &process::PROCS };
+                if arg == 0 || arg > procs.len() {
+                    return ReturnCode::EINVAL; /* Request to IPC to impossible process */
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.buffer_size(arg),
+                }
+            }
+            2 => self.set_subscribed(arg, true, appid),
+            3 => self.set_subscribed(arg, false, appid),
+            4 => self.publish(arg, appid),
+            5 => self.notify_all(appid),
+            6 => {
+                if arg > 0xffff {
+                    return ReturnCode::EINVAL;
+                }
+                self.data
+                    .enter(appid, |data, _| {
+                        data.version = arg as u16;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ReturnCode::EBUSY)
+            }
+            7 => self.set_rejected(arg, true, appid),
+            8 => self.set_rejected(arg, false, appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Notify `appid` that `publisher` has copied a message into the buffer
+    /// `appid` shares with it.
+    unsafe fn schedule_publish_callback(&self, appid: AppId, publisher: AppId) {
+        self.data
+            .enter(appid, |mydata, _| {
+                let callback = *mydata
+                    .client_callbacks
+                    .get(publisher.idx())
+                    .unwrap_or(&None);
+                match (callback, mydata.shared_memory.get(publisher.idx())) {
+                    (Some(mut callback), Some(&Some(ref slice))) => {
+                        callback.schedule(publisher.idx() + 1, slice.len(), slice.ptr() as usize);
+                    }
+                    _ => {}
+                }
+            })
+            .unwrap_or(());
+    }
+
+    /// The result of `appid` discovering the service with IPC id `svc_id`:
+    /// the id and the version word of the service.
+    fn discovered(&self, svc_id: usize, appid: AppId) -> ReturnCode {
+        if self.is_rejected(svc_id, appid) {
+            return ReturnCode::ECANCEL; /* Rejected by the service */
+        }
+        let version = self
+            .data
+            .enter(AppId::new(svc_id - 1), |data, _| data.version)
+            .unwrap_or(0);
+        ReturnCode::SuccessWithValue {
+            value: svc_id | (version as usize) << VERSION_SHIFT,
+        }
+    }
+
+    /// Whether the service with IPC id `svc_id` has rejected `appid`.
+    fn is_rejected(&self, svc_id: usize, appid: AppId) -> bool {
+        let procs = unsafe { &process::PROCS };
+        if svc_id == 0 || svc_id > procs.len() {
+            return false;
+        }
+        self.data
+            .enter(AppId::new(svc_id - 1), |data, _| {
+                *data.rejected.get(appid.idx()).unwrap_or(&false)
+            })
+            .unwrap_or(false)
+    }
+
+    /// Reject the client with IPC id `client_id` from the service `appid`,
+    /// or accept it again. A rejected client is unsubscribed, and the buffer
+    /// it shares with the service is dropped.
+    fn set_rejected(&self, client_id: usize, rejected: bool, appid: AppId) -> ReturnCode {
+        let procs = unsafe { &process::PROCS };
+        if client_id == 0 || client_id > procs.len() || client_id - 1 == appid.idx() {
+            return ReturnCode::EINVAL; /* Request to IPC to impossible process */
+        }
+        let client = client_id - 1;
+        let res = self
+            .data
+            .enter(appid, |data, _| {
+                if client >= data.rejected.len() {
+                    return ReturnCode::EINVAL;
+                }
+                data.rejected[client] = rejected;
+                if rejected {
+                    data.subscribers[client] = false;
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or(ReturnCode::EBUSY);
+        if res == ReturnCode::SUCCESS && rejected {
+            self.data
+                .enter(AppId::new(client), |data, _| {
+                    data.shared_memory
+                        .get_mut(appid.idx())
+                        .map(|smem| *smem = None);
+                })
+                .unwrap_or(());
+        }
+        res
+    }
+
+    /// Subscribe `appid` to, or unsubscribe it from, the messages published
+    /// by the service with IPC id `svc_id`.
+    fn set_subscribed(&self, svc_id: usize, subscribed: bool, appid: AppId) -> ReturnCode {
+        let procs = unsafe { &process::PROCS };
+        if svc_id == 0 || svc_id > procs.len() || svc_id - 1 == appid.idx() {
+            return ReturnCode::EINVAL; /* Request to IPC to impossible process */
+        }
+        if subscribed && self.is_rejected(svc_id, appid) {
+            return ReturnCode::ECANCEL; /* Rejected by the service */
+        }
+        self.data
+            .enter(AppId::new(svc_id - 1), |data, _| {
+                match data.subscribers.get_mut(appid.idx()) {
+                    Some(subscriber) => {
+                        *subscriber = subscribed;
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::EINVAL,
+                }
+            })
+            .unwrap_or(ReturnCode::EINVAL)
+    }
+
+    /// Copy the first `message_len` bytes of the publish buffer of `appid`
+    /// to each of its subscribers. Returns the number of subscribers the
+    /// message was delivered to; subscribers that have not shared a large
+    /// enough buffer with the service are skipped.
+    fn publish(&self, message_len: usize, appid: AppId) -> ReturnCode {
+        let svc = appid.idx();
+        self.data
+            .enter(appid, |data, _| {
+                let subscribers = data.subscribers;
+                let source = match data.shared_memory.get(svc) {
+                    Some(&Some(ref source)) => source,
+                    // No publish buffer
+                    _ => return ReturnCode::ENOMEM,
+                };
+                if message_len > source.len() {
+                    return ReturnCode::ESIZE;
+                }
+                let message = &source.as_ref()[..message_len];
+
+                let mut delivered = 0;
+                for (client, _) in subscribers.iter().enumerate().filter(|&(_, s)| *s) {
+                    let copied = self
+                        .data
+                        .enter(AppId::new(client), |client_data, _| {
+                            match client_data.shared_memory.get_mut(svc) {
+                                Some(&mut Some(ref mut dest))
+                                    if dest.len() >= MESSAGE_HEADER_LEN + message_len =>
+                                {
+                                    let dest = dest.as_mut();
+                                    for i in 0..MESSAGE_HEADER_LEN {
+                                        dest[i] = (message_len >> (8 * i)) as u8;
+                                    }
+                                    dest[MESSAGE_HEADER_LEN..MESSAGE_HEADER_LEN + message_len]
+                                        .copy_from_slice(message);
+                                    true
+                                }
+                                _ => false,
+                            }
+                        })
+                        .unwrap_or(false);
+                    if copied {
+                        let procs = unsafe { &mut process::PROCS };
+                        procs[client].as_mut().map(|target| {
+                            target.schedule_ipc(appid, process::IPCType::Publish);
+                        });
+                        delivered += 1;
+                    }
+                }
+                ReturnCode::SuccessWithValue { value: delivered }
+            })
+            .unwrap_or(ReturnCode::EBUSY)
+    }
+
+    /// Notify each client that has registered a callback for the service
+    /// `appid`, except those it rejected. Returns the number of clients
+    /// notified.
+    fn notify_all(&self, appid: AppId) -> ReturnCode {
+        let svc = appid.idx();
+        let procs = unsafe { &mut process::PROCS };
+        let rejected = self
+            .data
+            .enter(appid, |data, _| data.rejected)
+            .unwrap_or([false; 8]);
+        let mut notified = 0;
+        for (client, slot) in procs.iter_mut().enumerate() {
+            if client == svc || *rejected.get(client).unwrap_or(&false) {
+                continue;
+            }
+            let subscribed = self
+                .data
+                .enter(AppId::new(client), |data, _| {
+                    data.client_callbacks
+                        .get(svc)
+                        .map_or(false, |callback| callback.is_some())
+                })
+                .unwrap_or(false);
+            if subscribed {
+                slot.as_mut().map(|target| {
+                    target.schedule_ipc(appid, process::IPCType::Client);
+                    notified += 1;
+                });
+            }
+        }
+        ReturnCode::SuccessWithValue { value: notified }
+    }
+
+    /// The minimum buffer size of the process with IPC id `id`.
+    fn buffer_size(&self, id: usize) -> usize {
+        let procs = unsafe { &process::PROCS };
+        if id == 0 || id > procs.len() {
+            return 0;
+        }
+        self.data
+            .enter(AppId::new(id - 1), |data, _| data.buffer_size)
+            .unwrap_or(0)
+    }
+
+    /// Check that a message of `message_len` bytes fits in the buffer
+    /// `appid` shares with `target_id`, and write its header.
+    fn write_message_header(
+        &self,
+        target_id: usize,
+        message_len: usize,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.data
+            .enter(appid, |data, _| {
+                match data.shared_memory.get_mut(target_id - 1) {
+                    Some(&mut Some(ref mut slice)) => {
+                        if message_len > slice.len().saturating_sub(MESSAGE_HEADER_LEN) {
+                            return ReturnCode::ESIZE;
+                        }
+                        let header = slice.as_mut();
+                        for i in 0..MESSAGE_HEADER_LEN {
+                            header[i] = (message_len >> (8 * i)) as u8;
+                        }
+                        ReturnCode::SUCCESS
+                    }
+                    // No buffer shared with the target to hold the message
+                    _ => ReturnCode::ENOMEM,
+                }
+            })
+            .unwrap_or(ReturnCode::EBUSY)
+    }
+}
+
 impl Driver for IPC {
     /// subscribe enables processes using IPC to register callbacks that fire
     /// when notify() is called.
@@ -91,7 +419,7 @@ impl Driver for IPC {
         subscribe_num: usize,
         callback: Option<Callback>,
         app_id: AppId,
-    ) -> ReturnCode {
+    ) -> Result<Option<Callback>, ReturnCode> {
         match subscribe_num {
             // subscribe(0)
             //
@@ -104,10 +432,9 @@ impl Driver for IPC {
             0 => self
                 .data
                 .enter(app_id, |data, _| {
-                    data.callback = callback;
-                    ReturnCode::SUCCESS
+                    Ok(mem::replace(&mut data.callback, callback))
                 })
-                .unwrap_or(ReturnCode::EBUSY),
+                .unwrap_or(Err(ReturnCode::EBUSY)),
 
             // subscribe(>=1)
             //
@@ -118,14 +445,16 @@ impl Driver for IPC {
             // service process calls notify_client().
             svc_id => {
                 if svc_id - 1 >= 8 {
-                    ReturnCode::EINVAL /* Maximum of 8 IPC's exceeded */
+                    Err(ReturnCode::EINVAL) /* Maximum of 8 IPC's exceeded */
                 } else {
                     self.data
                         .enter(app_id, |data, _| {
-                            data.client_callbacks[svc_id - 1] = callback;
-                            ReturnCode::SUCCESS
+                            Ok(mem::replace(
+                                &mut data.client_callbacks[svc_id - 1],
+                                callback,
+                            ))
                         })
-                        .unwrap_or(ReturnCode::EBUSY)
+                        .unwrap_or(Err(ReturnCode::EBUSY))
                 }
             }
         }
@@ -136,17 +465,54 @@ impl Driver for IPC {
     /// and notifying an IPC client is done by setting client_or_svc to 1.
     /// In either case, the target_id is the same number as provided in a notify
     /// callback or as returned by allow.
+    ///
+    /// If `message_len` is not 0 the notification carries a message of that
+    /// many bytes, placed after the header in the buffer shared with the
+    /// target. Returns ESIZE if it does not fit.
+    ///
+    /// With target_id == 0, command configures buffer sizes instead:
+    ///
+    /// - `client_or_svc` 0: set the minimum size of buffers shared with this
+    ///   process to `message_len`.
+    /// - `client_or_svc` 1: get the minimum buffer size of the process
+    ///   `message_len`, to know how large a buffer to share with it.
+    /// - `client_or_svc` 2: subscribe to the messages published by the
+    ///   service `message_len`.
+    /// - `client_or_svc` 3: unsubscribe from the service `message_len`.
+    /// - `client_or_svc` 4: publish the first `message_len` bytes of this
+    ///   process's publish buffer to its subscribers.
+    /// - `client_or_svc` 5: notify every client with a callback for this
+    ///   service, returning how many were notified.
+    /// - `client_or_svc` 6: set the version word of this service, returned by
+    ///   discovery, to `message_len`, which must fit in 16 bits.
+    /// - `client_or_svc` 7: reject the client `message_len`.
+    /// - `client_or_svc` 8: accept the client `message_len` again.
+    ///
+    /// Notifying a service that has rejected this process returns ECANCEL.
     fn command(
         &self,
         target_id: usize,
         client_or_svc: usize,
-        _: usize,
+        message_len: usize,
         appid: AppId,
     ) -> ReturnCode {
         let procs = unsafe { &mut process::PROCS };
-        if target_id == 0 || target_id > procs.len() {
+        if target_id == 0 {
+            return self.configure(client_or_svc, message_len, appid);
+        }
+        if target_id > procs.len() {
             return ReturnCode::EINVAL; /* Request to IPC to impossible process */
         }
+        if client_or_svc == 0 && self.is_rejected(target_id, appid) {
+            return ReturnCode::ECANCEL; /* Rejected by the service */
+        }
+
+        if message_len != 0 {
+            let res = self.write_message_header(target_id, message_len, appid);
+            if res != ReturnCode::SUCCESS {
+                return res;
+            }
+        }
 
         let cb_type = if client_or_svc == 0 {
             process::IPCType::Service
@@ -169,13 +535,15 @@ impl Driver for IPC {
     /// If allow is called with target_id == 0, it is an IPC service discover
     /// call. The contents of the slice should be the string name of the IPC
     /// service. If this mechanism can find that service, allow will return
-    /// an ID that can be used to notify that service. Otherwise an error will
-    /// be returned.
+    /// an ID that can be used to notify that service, with the version word
+    /// of the service above it, starting at bit `VERSION_SHIFT`. Otherwise an
+    /// error will be returned: ECANCEL if the service rejected this process.
     ///
     /// If allow is called with target_id >= 1, it is a share command where the
     /// application is explicitly sharing a slice with an IPC service (as
     /// specified by the target_id). allow() simply allows both processes to
-    /// access the buffer, it does not signal the service.
+    /// access the buffer, it does not signal the service. Sharing with a
+    /// service that rejected this process returns ECANCEL.
     fn allow(
         &self,
         appid: AppId,
@@ -194,9 +562,7 @@ impl Driver for IPC {
                                 if s.len() == slice_data.len()
                                     && s.iter().zip(slice_data.iter()).all(|(c1, c2)| c1 == c2)
                                 {
-                                    return ReturnCode::SuccessWithValue {
-                                        value: (i as usize) + 1,
-                                    };
+                                    return self.discovered(i + 1, appid);
                                 }
                             }
                             &None => {}
@@ -208,6 +574,14 @@ impl Driver for IPC {
 
             return ReturnCode::EINVAL; /* AppSlice must have non-zero length */
         }
+        if self.is_rejected(target_id, appid) {
+            return ReturnCode::ECANCEL; /* Rejected by the service */
+        }
+        if let Some(ref slice) = slice {
+            if slice.len() < self.buffer_size(target_id) {
+                return ReturnCode::ESIZE; /* Smaller than the target accepts */
+            }
+        }
         return self
             .data
             .enter(appid, |data, _| {
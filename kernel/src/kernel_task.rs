@@ -0,0 +1,244 @@
+//! Trusted kernel tasks that use drivers the way processes do.
+//!
+//! A kernel task is Rust code in the kernel, such as a network manager or a
+//! storage daemon, that talks to capsules through the same `Driver` interface
+//! as applications. Each registered task gets its own `AppId`, identifying
+//! it by a `TaskId` rather than a process, so capsules keep separate grant
+//! state for it, and callbacks created for it run plain Rust functions.
+//! Those callbacks are queued and run from the main loop, like process
+//! callbacks, rather than from inside the capsule that scheduled them.
+//!
+//! A task is given a region of memory for its grants when it is created:
+//!
+//! ```rust
+//! static mut NETMGR_GRANTS: [u8; 256] = [0; 256];
+//!
+//! let netmgr = static_init!(
+//!     kernel::kernel_task::KernelTask,
+//!     kernel::kernel_task::KernelTask::new("netmgr", &mut NETMGR_GRANTS)
+//! );
+//! let appid = kernel::kernel_task::register(netmgr).unwrap();
+//!
+//! let callback = netmgr.callback(netmgr_receive, 0).unwrap();
+//! radio.subscribe(0, Some(callback), appid);
+//! radio.allow(appid, 0, netmgr.slice(&mut RX_BUFFER));
+//! ```
+
+use callback::{AppId, Callback};
+use core::cell::Cell;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, write};
+use mem::{AppSlice, Shared};
+
+/// The maximum number of kernel tasks.
+pub const MAX_KERNEL_TASKS: usize = 8;
+
+/// The maximum number of grants a kernel task can use.
+pub const MAX_GRANTS: usize = 32;
+
+/// The number of callbacks that can be waiting to run for each task.
+const QUEUE_LEN: usize = 8;
+
+/// Identifies kernel code that uses drivers in place of a process.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TaskId {
+    /// The writer behind `debug!`, which prints through the console driver.
+    DebugWriter,
+    /// The registered task with this number.
+    Registered(usize),
+}
+
+impl TaskId {
+    /// Numbers every kernel task, from 0.
+    pub(crate) fn number(&self) -> usize {
+        match *self {
+            TaskId::Registered(number) => number,
+            TaskId::DebugWriter => MAX_KERNEL_TASKS,
+        }
+    }
+}
+
+/// A callback waiting to run: the function and its four arguments.
+#[derive(Copy, Clone)]
+struct PendingCall {
+    func: fn(usize, usize, usize, usize),
+    args: (usize, usize, usize, usize),
+}
+
+pub struct KernelTask {
+    name: &'static str,
+    appid: Cell<Option<AppId>>,
+    memory: *mut u8,
+    memory_len: usize,
+    /// Bytes of `memory` allocated so far.
+    used: Cell<usize>,
+    /// The address of each grant region, or 0 if it is not allocated.
+    grant_ptrs: [Cell<usize>; MAX_GRANTS],
+    queue: [Cell<Option<PendingCall>>; QUEUE_LEN],
+    queue_head: Cell<usize>,
+    queue_len: Cell<usize>,
+    dropped_callbacks: Cell<usize>,
+}
+
+static mut TASKS: [Option<&'static KernelTask>; MAX_KERNEL_TASKS] = [None; MAX_KERNEL_TASKS];
+
+/// Give `task` an `AppId`. Returns `None` if `MAX_KERNEL_TASKS` tasks are
+/// already registered or the task was registered before.
+pub unsafe fn register(task: &'static KernelTask) -> Option<AppId> {
+    if task.appid.get().is_some() {
+        return None;
+    }
+    TASKS.iter().position(|t| t.is_none()).map(|i| {
+        let appid = AppId::kernel_new(TaskId::Registered(i));
+        TASKS[i] = Some(task);
+        task.appid.set(Some(appid));
+        appid
+    })
+}
+
+/// The registered task with `TaskId::Registered(number)`, if any.
+pub(crate) fn get(number: usize) -> Option<&'static KernelTask> {
+    unsafe { TASKS.get(number).and_then(|t| *t) }
+}
+
+/// Iterate over the registered tasks.
+pub(crate) fn tasks() -> impl Iterator<Item = &'static KernelTask> {
+    unsafe { TASKS.iter().filter_map(|t| *t) }
+}
+
+/// Whether any task has callbacks waiting to run.
+pub(crate) fn have_work() -> bool {
+    tasks().any(|task| task.queue_len.get() > 0)
+}
+
+/// Run the callbacks waiting for every task. Called from the main loop.
+pub(crate) fn dispatch_pending() {
+    for task in tasks() {
+        while let Some(call) = task.dequeue() {
+            (call.func)(call.args.0, call.args.1, call.args.2, call.args.3);
+        }
+    }
+}
+
+impl KernelTask {
+    /// Create a task that allocates its grants out of `memory`.
+    pub fn new(name: &'static str, memory: &'static mut [u8]) -> KernelTask {
+        KernelTask {
+            name: name,
+            appid: Cell::new(None),
+            memory: memory.as_mut_ptr(),
+            memory_len: memory.len(),
+            used: Cell::new(0),
+            grant_ptrs: Default::default(),
+            queue: Default::default(),
+            queue_head: Cell::new(0),
+            queue_len: Cell::new(0),
+            dropped_callbacks: Cell::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The `AppId` of the task, once registered.
+    pub fn appid(&self) -> Option<AppId> {
+        self.appid.get()
+    }
+
+    /// Bytes of grant memory used and available.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        (self.used.get(), self.memory_len)
+    }
+
+    /// The number of callbacks lost because the queue was full.
+    pub fn dropped_callbacks(&self) -> usize {
+        self.dropped_callbacks.get()
+    }
+
+    /// A callback that runs `func` for this task, with `appdata` as its
+    /// fourth argument. Returns `None` if the task is not registered.
+    pub fn callback(
+        &self,
+        func: fn(usize, usize, usize, usize),
+        appdata: usize,
+    ) -> Option<Callback> {
+        self.appid
+            .get()
+            .and_then(|appid| appid.task())
+            .map(|task| Callback::kernel_new(task, appdata, func))
+    }
+
+    /// Wrap `buffer` so it can be passed to `Driver::allow`. Returns `None`
+    /// if the task is not registered.
+    pub fn slice(&self, buffer: &'static mut [u8]) -> Option<AppSlice<Shared, u8>> {
+        self.appid
+            .get()
+            .map(|appid| AppSlice::new(buffer.as_mut_ptr(), buffer.len(), appid))
+    }
+
+    /// Queue a call of `func`. Returns false if the queue is full.
+    pub(crate) fn schedule(
+        &self,
+        func: fn(usize, usize, usize, usize),
+        args: (usize, usize, usize, usize),
+    ) -> bool {
+        let len = self.queue_len.get();
+        if len == QUEUE_LEN {
+            self.dropped_callbacks.set(self.dropped_callbacks.get() + 1);
+            return false;
+        }
+        let tail = (self.queue_head.get() + len) % QUEUE_LEN;
+        self.queue[tail].set(Some(PendingCall {
+            func: func,
+            args: args,
+        }));
+        self.queue_len.set(len + 1);
+        true
+    }
+
+    fn dequeue(&self) -> Option<PendingCall> {
+        if self.queue_len.get() == 0 {
+            return None;
+        }
+        let head = self.queue_head.get();
+        self.queue_head.set((head + 1) % QUEUE_LEN);
+        self.queue_len.set(self.queue_len.get() - 1);
+        self.queue[head].take()
+    }
+
+    /// Allocate `size` bytes aligned for `align` from the task's memory.
+    pub(crate) fn alloc(&self, size: usize, align: usize) -> Option<*mut u8> {
+        let base = self.memory as usize;
+        let start = (base + self.used.get() + align - 1) & !(align - 1);
+        let end = start + size;
+        if end > base + self.memory_len {
+            None
+        } else {
+            self.used.set(end - base);
+            Some(start as *mut u8)
+        }
+    }
+
+    /// The task's grant region for `grant_num`, or null if it has not been
+    /// allocated.
+    pub(crate) fn grant_for<T>(&self, grant_num: usize) -> *mut T {
+        self.grant_ptrs
+            .get(grant_num)
+            .map_or(ptr::null_mut(), |p| p.get() as *mut T)
+    }
+
+    pub(crate) unsafe fn grant_for_or_alloc<T: Default>(&self, grant_num: usize) -> Option<*mut T> {
+        let grant_ptr = self.grant_ptrs.get(grant_num)?;
+        if grant_ptr.get() == 0 {
+            self.alloc(size_of::<T>(), align_of::<T>()).map(|root| {
+                let root_ptr = root as *mut T;
+                write(root_ptr, Default::default());
+                grant_ptr.set(root as usize);
+                root_ptr
+            })
+        } else {
+            Some(grant_ptr.get() as *mut T)
+        }
+    }
+}
@@ -21,6 +21,10 @@ pub mod common;
 pub mod debug;
 pub mod hil;
 pub mod ipc;
+pub mod kernel_task;
+pub mod process_control;
+pub mod process_loader;
+pub mod process_memory;
 
 mod callback;
 mod driver;
@@ -35,19 +39,28 @@ mod syscall;
 mod tbfheader;
 
 pub use callback::{AppId, Callback};
-pub use driver::Driver;
+pub use driver::{Driver, QUERY_DRIVER_NUM, SUBSCRIBE_FOUR_VALUES};
 pub use grant::Grant;
 pub use mem::{AppPtr, AppSlice, Private, Shared};
 pub use platform::systick::SysTick;
-pub use platform::{mpu, Chip, Platform};
+pub use platform::{deadline, mpu, sleep, Chip, Platform};
 pub use platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use returncode::ReturnCode;
-pub use sched::kernel_loop;
+pub use sched::SchedulingPolicy;
+pub use sched::{kernel_loop, set_preemption, set_scheduling_policy};
+pub use sched::{set_syscall_filter, set_yield_timer};
+pub use syscall::{Syscall, SyscallFilter, YieldTimer, ABI_REVISION};
+
+/// The kernel version, as reported by `git describe` when it was built.
+pub const KERNEL_VERSION: &str = env!("TOCK_KERNEL_VERSION");
 
 // Export only select items from the process module. To remove the name conflict
 // this cannot be called `process`, so we use a shortened version. These
 // functions and types are used by board files to setup the platform and setup
 // processes.
 pub mod procs {
-    pub use process::{load_processes, FaultResponse, Process};
+    pub use process::{
+        load_processes, set_cpu_time_source, set_task_queue_depth, FaultResponse, Process, State,
+        DEFAULT_QUANTUM_US, DEFAULT_TASK_QUEUE_DEPTH,
+    };
 }
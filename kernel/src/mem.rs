@@ -47,7 +47,7 @@ impl<L, T> Drop for AppPtr<L, T> {
     fn drop(&mut self) {
         unsafe {
             let ps = &mut process::PROCS;
-            if ps.len() > self.process.idx() {
+            if ps.len() > self.process.idx() && self.process.is_current() {
                 ps[self.process.idx()]
                     .as_mut()
                     .map(|process| process.free(self.ptr.as_mut()));
@@ -79,6 +79,11 @@ impl<L, T> AppSlice<L, T> {
         unsafe { self.ptr.ptr.as_ref() as *const T }
     }
 
+    /// The process whose memory the slice is in.
+    pub fn app_id(&self) -> AppId {
+        self.ptr.process
+    }
+
     pub unsafe fn expose_to(&self, appid: AppId) -> bool {
         let ps = &mut process::PROCS;
         if appid.idx() != self.ptr.process.idx() && ps.len() > appid.idx() {
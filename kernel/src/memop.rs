@@ -36,6 +36,24 @@ use returncode::ReturnCode;
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
 ///   app crashes.
+/// - `12`: Register a function (r1) to be called with userdata (r2) whenever
+///   a BRK or SBRK fails, or stop calling it if r1 is 0. The function is
+///   passed the reason (1 if the heap would collide with the grant region, 2
+///   if the break would be outside the app's memory), the requested break
+///   and the start of the grant region.
+/// - `13`: Get the number of callbacks for the app that were dropped because
+///   its queue was full, since it last started.
+/// - `14`: Register a function (r1) to be called with userdata (r2) once
+///   callbacks for the app have been dropped, or stop calling it if r1 is 0.
+///   The function is passed the number of callbacks dropped so far, and is
+///   called when the queue has room again, so the app can resynchronize with
+///   the drivers whose events it missed.
+/// - `15`: Get the number of bytes the kernel uses for the app's grant region.
+/// - `16`: Get the number of bytes between the program break and the grant
+///   region, which is how much further the app can move its break.
+/// - `17`: Get the most stack the app has used, in bytes. The kernel paints
+///   the unused stack when the app specifies where its stack starts (`10`),
+///   and returns `FAIL` if the app has not.
 pub fn memop(process: &mut Process) -> ReturnCode {
     let op_type = process.r0();
     let r1 = process.r1();
@@ -43,16 +61,25 @@ pub fn memop(process: &mut Process) -> ReturnCode {
     match op_type {
         // Op Type 0: BRK
         0 /* BRK */ => {
-            process.brk(r1 as *const u8)
-                .map(|_| ReturnCode::SUCCESS)
-                .unwrap_or(ReturnCode::ENOMEM)
+            match process.brk(r1 as *const u8) {
+                Ok(_) => ReturnCode::SUCCESS,
+                Err(err) => {
+                    process.brk_denied(err, r1 as *const u8);
+                    ReturnCode::ENOMEM
+                }
+            }
         },
 
         // Op Type 1: SBRK
         1 /* SBRK */ => {
-            process.sbrk(r1 as isize)
-                .map(|addr| ReturnCode::SuccessWithValue { value: addr as usize })
-                .unwrap_or(ReturnCode::ENOMEM)
+            let requested_break = process.app_memory_break().wrapping_offset(r1 as isize);
+            match process.sbrk(r1 as isize) {
+                Ok(addr) => ReturnCode::SuccessWithValue { value: addr as usize },
+                Err(err) => {
+                    process.brk_denied(err, requested_break);
+                    ReturnCode::ENOMEM
+                }
+            }
         },
 
         // Op Type 2: Process memory start
@@ -111,6 +138,36 @@ pub fn memop(process: &mut Process) -> ReturnCode {
             ReturnCode::SUCCESS
         }
 
+        // Op Type 12: Register the function to call when a BRK or SBRK fails.
+        12 => {
+            let appdata = process.r2();
+            process.set_brk_denied_callback(r1, appdata);
+            ReturnCode::SUCCESS
+        }
+
+        // Op Type 13: The number of callbacks dropped.
+        13 => ReturnCode::SuccessWithValue { value: process.dropped_callback_count() },
+
+        // Op Type 14: Register the function to call when callbacks are
+        // dropped.
+        14 => {
+            let appdata = process.r2();
+            process.set_dropped_callback(r1, appdata);
+            ReturnCode::SUCCESS
+        }
+
+        // Op Type 15: Size of the grant region.
+        15 => ReturnCode::SuccessWithValue { value: process.grant_region_size() },
+
+        // Op Type 16: Room left for the heap.
+        16 => ReturnCode::SuccessWithValue { value: process.free_heap_size() },
+
+        // Op Type 17: Stack high-water mark.
+        17 => match process.stack_high_water_mark() {
+            Some(size) => ReturnCode::SuccessWithValue { value: size },
+            None => ReturnCode::FAIL,
+        },
+
         _ => ReturnCode::ENOSUPPORT,
     }
 }
@@ -0,0 +1,29 @@
+//! Deadlines the power manager must honor when choosing a sleep state.
+//!
+//! Deeper sleep states save more power but take longer to wake up from. The
+//! chip's `sleep()` asks `wakeup_tolerance_us()` how late it may handle the
+//! next wakeup and only enters a sleep state whose wake-up latency fits. The
+//! tolerance comes from a `DeadlineSource`, normally the alarm multiplexer,
+//! which the board registers with `set_source()`. If no source is registered,
+//! or no pending wakeup has a deadline, any sleep state may be used.
+
+/// Something that knows the deadlines of pending wakeups.
+pub trait DeadlineSource {
+    /// Returns the number of microseconds the earliest pending wakeup may be
+    /// handled late by, or `None` if no pending wakeup has a deadline.
+    fn wakeup_tolerance_us(&self) -> Option<u32>;
+}
+
+static mut SOURCE: Option<&'static DeadlineSource> = None;
+
+/// Register the source of wakeup deadlines. Boards call this once during
+/// initialization.
+pub unsafe fn set_source(source: &'static DeadlineSource) {
+    SOURCE = Some(source);
+}
+
+/// The number of microseconds the next wakeup may be handled late by, or
+/// `None` if it is not constrained.
+pub fn wakeup_tolerance_us() -> Option<u32> {
+    unsafe { SOURCE.and_then(|source| source.wakeup_tolerance_us()) }
+}
@@ -2,7 +2,9 @@
 
 use driver::Driver;
 
+pub mod deadline;
 pub mod mpu;
+pub mod sleep;
 pub mod systick;
 
 /// Interface for individual boards.
@@ -81,6 +81,17 @@ pub trait MPU {
     /// Sets the base address, size and access attributes of the given MPU
     /// region number.
     fn set_mpu(&self, region: Region);
+
+    /// Make the `len` bytes at `start`, the lowest addresses of the kernel
+    /// stack, inaccessible to the kernel, so that overflowing the stack
+    /// faults instead of corrupting the memory below it. Returns false if
+    /// the MPU cannot protect that range.
+    fn set_kernel_stack_guard(&self, start: usize, len: usize) -> bool;
+
+    /// Configure the MPU for running the kernel rather than a process: only
+    /// the kernel stack guard is active, or the MPU is disabled if there is
+    /// no guard.
+    fn enable_kernel_mpu(&self);
 }
 
 /// Noop implementation of MPU trait
@@ -100,4 +111,86 @@ impl MPU for () {
     }
 
     fn set_mpu(&self, _: Region) {}
+
+    fn set_kernel_stack_guard(&self, _: usize, _: usize) -> bool {
+        false
+    }
+
+    fn enable_kernel_mpu(&self) {}
+}
+
+/// The number of equal subregions an MPU region is divided into, each of
+/// which can be enabled or disabled on its own.
+pub const SUBREGIONS: usize = 8;
+
+/// The smallest region the MPU supports.
+const MIN_REGION_SIZE: usize = 32;
+
+/// The smallest region the MPU can divide into subregions.
+const MIN_SUBREGION_REGION_SIZE: usize = 128;
+
+/// How one MPU region covers a range of memory exactly.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RegionLayout {
+    /// The base address of the region, aligned to its size.
+    pub start: usize,
+    /// The size of the region, a power of two.
+    pub size: usize,
+    /// The first of the subregions that cover the memory.
+    pub first_subregion: usize,
+    /// The last of the subregions that cover the memory.
+    pub last_subregion: usize,
+}
+
+impl RegionLayout {
+    /// The subregion disable bits of the layout: a bit is set for each
+    /// subregion outside the memory.
+    pub fn subregion_mask(&self) -> u8 {
+        (self.first_subregion..self.last_subregion + 1).fold(!0, |mask, i| mask & !(1 << i))
+    }
+}
+
+/// Find the smallest MPU region that covers the `len` bytes at `start` and
+/// nothing else, either as a whole region or as a run of its subregions.
+///
+/// A memory range can be covered if it is a whole, aligned, power of two
+/// region of at least 32 bytes, or if its start and length are multiples of
+/// a subregion size and the range fits in one region of eight of those
+/// subregions. Returns `None` if no region fits.
+pub fn region_layout(start: usize, len: usize) -> Option<RegionLayout> {
+    if len == 0 {
+        return None;
+    }
+    let mut size = len.checked_next_power_of_two()?;
+    if size < MIN_REGION_SIZE {
+        size = MIN_REGION_SIZE;
+    }
+    // A larger subregion than the memory cannot cover it exactly
+    while size / SUBREGIONS <= len {
+        let region_start = start - start % size;
+        if region_start == start && size == len {
+            return Some(RegionLayout {
+                start: start,
+                size: size,
+                first_subregion: 0,
+                last_subregion: SUBREGIONS - 1,
+            });
+        }
+        let subregion_size = size / SUBREGIONS;
+        if size >= MIN_SUBREGION_REGION_SIZE
+            && start % subregion_size == 0
+            && len % subregion_size == 0
+            && start - region_start + len <= size
+        {
+            let first_subregion = (start - region_start) / subregion_size;
+            return Some(RegionLayout {
+                start: region_start,
+                size: size,
+                first_subregion: first_subregion,
+                last_subregion: first_subregion + len / subregion_size - 1,
+            });
+        }
+        size = size.checked_mul(2)?;
+    }
+    None
 }
@@ -0,0 +1,59 @@
+//! Board policy for how deeply the chip sleeps when the kernel is idle.
+//!
+//! When no process is ready and no interrupt is pending, the kernel puts the
+//! chip to sleep. By default it calls the chip's `sleep()`, which picks a
+//! sleep state from what the chip driver knows. A board that knows better,
+//! for example because it can use retention states the chip driver does not,
+//! or because it knows which peripherals must keep their clocks, registers a
+//! `SleepPolicy` with `set_policy()` and the kernel calls it instead.
+//!
+//! Drivers tell the policy what they are doing through `set_active()`. Each
+//! peripheral that can stop the chip from sleeping deeply is given a bit by
+//! the board, and its driver sets the bit while it is busy, for instance
+//! while a transfer is in progress. The policy is passed the active bits each
+//! time it is called.
+
+/// Chooses and enters a sleep state.
+pub trait SleepPolicy {
+    /// Sleep until the next interrupt. `active` has a bit set for each
+    /// peripheral that has reported itself active. Called with interrupts
+    /// disabled; a pending interrupt still wakes the chip.
+    fn sleep(&self, active: u32);
+}
+
+static mut POLICY: Option<&'static SleepPolicy> = None;
+
+/// One bit for each peripheral that is active.
+static mut ACTIVE: u32 = 0;
+
+/// Register the board's sleep policy. Boards call this once during
+/// initialization, before `kernel_loop()`.
+pub unsafe fn set_policy(policy: &'static SleepPolicy) {
+    POLICY = Some(policy);
+}
+
+/// Report whether the peripheral with bit `bit`, which must be less than 32,
+/// is active.
+pub fn set_active(bit: u32, active: bool) {
+    unsafe {
+        if active {
+            ACTIVE |= 1 << bit;
+        } else {
+            ACTIVE &= !(1 << bit);
+        }
+    }
+}
+
+/// The bits of the peripherals that are active.
+pub fn active() -> u32 {
+    unsafe { ACTIVE }
+}
+
+/// Sleep using the board's policy. Returns `false`, without sleeping, if the
+/// board has not registered one.
+pub(crate) fn sleep() -> bool {
+    unsafe { POLICY }.map_or(false, |policy| {
+        policy.sleep(active());
+        true
+    })
+}
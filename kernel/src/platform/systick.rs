@@ -21,6 +21,9 @@ pub trait SysTick {
     /// Returns if there is at least `us` microseconds left
     fn greater_than(&self, us: u32) -> bool;
 
+    /// Returns the number of microseconds left before the timer expires
+    fn remaining_us(&self) -> u32;
+
     /// Returns true if the timer has expired
     fn overflowed(&self) -> bool;
 
@@ -56,4 +59,8 @@ impl SysTick for () {
     fn greater_than(&self, _: u32) -> bool {
         true
     }
+
+    fn remaining_us(&self) -> u32 {
+        u32::max_value()
+    }
 }
@@ -9,9 +9,11 @@ use core::fmt::Write;
 use core::ptr::{read_volatile, write, write_volatile};
 use core::{mem, ptr, slice, str};
 use grant;
+use hil::time::Timestamp;
 
 use common::math;
 use platform::mpu;
+use process_memory;
 use returncode::ReturnCode;
 use syscall::Syscall;
 use tbfheader;
@@ -41,6 +43,92 @@ extern "C" {
 
 pub static mut PROCS: &'static mut [Option<&mut Process<'static>>] = &mut [];
 
+/// The generation the next process to be created or restarted gets. Each
+/// process, and each restart of one, has a different generation, so an
+/// `AppId` kept from before a restart or from an earlier process in the same
+/// slot no longer matches.
+static mut NEXT_GENERATION: usize = 0;
+
+unsafe fn next_generation() -> usize {
+    let generation = NEXT_GENERATION;
+    NEXT_GENERATION = NEXT_GENERATION.wrapping_add(1);
+    generation
+}
+
+/// The time a process is permitted to run before being pre-empted, unless the
+/// board sets a different quantum for it.
+pub const DEFAULT_QUANTUM_US: u32 = 10000;
+
+/// The clock used to measure how long each process runs for. Nothing is
+/// measured until a board sets it.
+static mut CPU_TIME_SOURCE: Option<&'static Timestamp> = None;
+
+/// Measure the CPU time of each process with `source`. It needs to tick
+/// considerably faster than processes switch for the times to be meaningful.
+pub unsafe fn set_cpu_time_source(source: &'static Timestamp) {
+    CPU_TIME_SOURCE = Some(source);
+}
+
+/// The current time in ticks of the CPU time source, if there is one.
+pub(crate) fn cpu_timestamp() -> Option<u64> {
+    unsafe { CPU_TIME_SOURCE.map(|source| source.timestamp()) }
+}
+
+/// How many callbacks can wait to run for a process unless the board
+/// chooses otherwise.
+pub const DEFAULT_TASK_QUEUE_DEPTH: usize = 9;
+
+/// How many urgent callbacks can wait to run for a process.
+const URGENT_TASK_QUEUE_DEPTH: usize = 3;
+
+/// How many drivers a process can give callbacks or buffers to and have them
+/// told when it terminates.
+pub const MAX_DRIVERS_USED: usize = 16;
+
+/// A `yield` with a timeout that a process is waiting in.
+#[derive(Copy, Clone)]
+struct YieldWait {
+    /// When the wait started and how long it lasts, in `YieldTimer` ticks
+    start: u32,
+    ticks: u32,
+    /// Where the process wants to know whether a callback ran
+    result: *mut usize,
+}
+
+/// Written over the unused part of a process's stack when it says where its
+/// stack starts, so that how deep the stack has grown can be found later.
+const STACK_PAINT: usize = 0xdeadc0de;
+
+fn default_task_queue_depth(_package_name: &'static str) -> usize {
+    DEFAULT_TASK_QUEUE_DEPTH
+}
+
+/// Picks the task queue depth of each process, by its package name.
+static mut TASK_QUEUE_DEPTH: fn(&'static str) -> usize = default_task_queue_depth;
+
+/// Choose how many callbacks can wait to run for each process with `depth`,
+/// which is given the package name of the process. Processes running bursty
+/// drivers may need deeper queues. The queues are carved from the memory of
+/// each process, so this must be called before `load_processes()`, and also
+/// applies to processes started at runtime.
+pub unsafe fn set_task_queue_depth(depth: fn(&'static str) -> usize) {
+    TASK_QUEUE_DEPTH = depth;
+}
+
+/// The lengths of the ring buffers for the task queue and the urgent task
+/// queue of a process. A ring buffer keeps one slot free.
+unsafe fn task_queue_lens(package_name: &'static str) -> (usize, usize) {
+    (TASK_QUEUE_DEPTH(package_name) + 1, URGENT_TASK_QUEUE_DEPTH + 1)
+}
+
+/// The app memory `load_processes()` did not give to a process. Processes
+/// started at runtime get their memory from here.
+static mut FREE_APP_MEMORY: (*mut u8, usize) = (0 as *mut u8, 0);
+
+/// How faults of processes started at runtime are handled, as selected by
+/// `load_processes()`.
+static mut RUNTIME_FAULT_RESPONSE: FaultResponse = FaultResponse::Panic;
+
 /// Helper function to load processes from flash into an array of active
 /// processes. This is the default template for loading processes, but a board
 /// is able to create its own `load_processes()` function and use that instead.
@@ -84,9 +172,69 @@ pub unsafe fn load_processes(
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
     }
+
+    FREE_APP_MEMORY = (app_memory_ptr, app_memory_size);
+    RUNTIME_FAULT_RESPONSE = fault_response;
+}
+
+/// Start the process whose TBF image is at `app_flash_address` in a free
+/// slot of the processes array, with memory left over by
+/// `load_processes()`. The process is set up like those loaded at boot,
+/// including its MPU regions, which are configured whenever it is scheduled.
+///
+/// Returns `EINVAL` if there is no valid, enabled app at the address and
+/// `ENOMEM` if there is no free slot or not enough memory left for it.
+pub(crate) unsafe fn load_process(app_flash_address: *const u8) -> Result<AppId, ReturnCode> {
+    let procs = &mut PROCS;
+    let idx = match procs.iter().position(|p| p.is_none()) {
+        Some(idx) => idx,
+        None => return Err(ReturnCode::ENOMEM),
+    };
+
+    let tbf_header = match tbfheader::parse_and_validate_tbf_header(app_flash_address) {
+        Some(tbf_header) => tbf_header,
+        None => return Err(ReturnCode::EINVAL),
+    };
+    let init_fn = app_flash_address as usize + tbf_header.get_init_function_offset() as usize;
+    if !tbf_header.is_app() || !tbf_header.enabled() || (init_fn & 0x1) != 1 {
+        return Err(ReturnCode::EINVAL);
+    }
+
+    // The MPU needs the memory of a process to be aligned to its size.
+    let app_ram_size = Process::app_ram_size(&tbf_header, app_flash_address);
+    let (free, free_size) = FREE_APP_MEMORY;
+    let padding = (app_ram_size - free as usize % app_ram_size) % app_ram_size;
+    if padding + app_ram_size > free_size {
+        return Err(ReturnCode::ENOMEM);
+    }
+
+    let start = free.offset(padding as isize);
+    let (process, _, memory_offset) =
+        Process::create(app_flash_address, start, free_size - padding, RUNTIME_FAULT_RESPONSE);
+    match process {
+        Some(process) => {
+            procs[idx] = Some(process);
+            FREE_APP_MEMORY = (
+                start.offset(memory_offset as isize),
+                free_size - padding - memory_offset,
+            );
+            Ok(AppId::new(idx))
+        }
+        None => Err(ReturnCode::EINVAL),
+    }
+}
+
+/// Whether a process is running from the TBF image at `app_flash_address`.
+pub(crate) fn is_loaded(app_flash_address: *const u8) -> bool {
+    let procs = unsafe { &PROCS };
+    procs
+        .iter()
+        .filter_map(|p| p.as_ref())
+        .any(|p| p.flash_start() == app_flash_address)
 }
 
-pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
+/// Queue `callback` for the process `appid`, in its urgent lane if `urgent`.
+pub fn schedule(callback: FunctionCall, appid: AppId, urgent: bool) -> bool {
     let procs = unsafe { &mut PROCS };
     let idx = appid.idx();
     if idx >= procs.len() {
@@ -102,18 +250,21 @@ pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
                 return false;
             }
 
-            unsafe {
-                HAVE_WORK.set(HAVE_WORK.get() + 1);
-            }
-
-            let ret = p.tasks.enqueue(Task::FunctionCall(callback));
+            let ret = if urgent {
+                p.urgent_tasks.enqueue(Task::FunctionCall(callback))
+            } else {
+                p.tasks.enqueue(Task::FunctionCall(callback))
+            };
 
             // Make a note that we lost this callback if the enqueue function
             // fails.
             if ret == false {
-                p.debug
-                    .dropped_callback_count
-                    .set(p.debug.dropped_callback_count.get() + 1);
+                p.callback_dropped();
+            } else {
+                unsafe {
+                    HAVE_WORK.set(HAVE_WORK.get() + 1);
+                }
+                p.task_queued();
             }
 
             ret
@@ -121,6 +272,41 @@ pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
     }
 }
 
+/// Queue `callback` for the process `appid`, unless a call to the same
+/// function from the same subscription is already queued. In that case that
+/// call takes the `r0` and `r1` of `callback` and its `r2` is incremented,
+/// so it counts the events it stands for.
+pub fn schedule_coalesced(callback: FunctionCall, appid: AppId) -> bool {
+    let procs = unsafe { &mut PROCS };
+    let idx = appid.idx();
+    if idx >= procs.len() {
+        return false;
+    }
+
+    let pending = procs[idx].as_mut().and_then(|p| {
+        if p.current_state() == State::Fault {
+            return None;
+        }
+        p.tasks.find_mut(|task| match *task {
+            Task::FunctionCall(ref call) => {
+                call.pc == callback.pc
+                    && call.r3 == callback.r3
+                    && call.subscription == callback.subscription
+            }
+            Task::IPC(_) => false,
+        })
+    });
+    match pending {
+        Some(&mut Task::FunctionCall(ref mut call)) => {
+            call.r0 = callback.r0;
+            call.r1 = callback.r1;
+            call.r2 = call.r2.saturating_add(callback.r2);
+            true
+        }
+        _ => schedule(callback, appid, false),
+    }
+}
+
 /// Returns the full address of the start and end of the flash region that the
 /// app owns and can write to. This includes the app's code and data and any
 /// padding at the end of the app. It does not include the TBF header, or any
@@ -141,6 +327,184 @@ pub fn get_editable_flash_range(app_idx: usize) -> (usize, usize) {
     }
 }
 
+/// Returns the layout of the app's memory, or `None` if there is no such app.
+pub(crate) fn get_memory_layout(app_idx: usize) -> Option<process_memory::MemoryLayout> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| process_memory::MemoryLayout {
+            flash_start: p.flash_start() as usize,
+            flash_end: p.flash_end() as usize,
+            memory_start: p.mem_start() as usize,
+            memory_end: p.mem_end() as usize,
+            app_break: p.app_memory_break() as usize,
+            kernel_memory_break: p.kernel_memory_break() as usize,
+            stack_pointer: p.sp(),
+            min_stack_pointer: p.debug.min_stack_pointer as usize,
+            stack_start: p.debug.app_stack_start_pointer.map(|p| p as usize),
+            heap_start: p.debug.app_heap_start_pointer.map(|p| p as usize),
+        })
+}
+
+/// Returns the number of slots for apps, including empty ones.
+pub(crate) fn num_slots() -> usize {
+    unsafe { PROCS.len() }
+}
+
+/// Returns the state of the app, or `None` if there is no such app.
+pub(crate) fn get_state(app_idx: usize) -> Option<State> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| p.current_state())
+}
+
+/// Returns how many times the app has been restarted after a fault, or
+/// `None` if there is no such app.
+pub(crate) fn get_restart_count(app_idx: usize) -> Option<usize> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| p.restart_count())
+}
+
+/// Stops the app, as `Process::stop()`. Returns `EINVAL` if there is no such
+/// app.
+pub(crate) fn stop(app_idx: usize) -> ReturnCode {
+    let procs = unsafe { &mut PROCS };
+    procs
+        .get_mut(app_idx)
+        .and_then(|p| p.as_mut())
+        .map_or(ReturnCode::EINVAL, |p| p.stop())
+}
+
+/// Resumes the app, as `Process::resume()`. Returns `EINVAL` if there is no
+/// such app.
+pub(crate) fn resume(app_idx: usize) -> ReturnCode {
+    let procs = unsafe { &mut PROCS };
+    procs
+        .get_mut(app_idx)
+        .and_then(|p| p.as_mut())
+        .map_or(ReturnCode::EINVAL, |p| p.resume())
+}
+
+/// Handles the app as if it had faulted, according to its fault response.
+/// Returns `EINVAL` if there is no such app and `EOFF` if it has already
+/// faulted and was stopped.
+pub(crate) fn fault(app_idx: usize) -> ReturnCode {
+    let procs = unsafe { &mut PROCS };
+    match procs.get_mut(app_idx).and_then(|p| p.as_mut()) {
+        None => ReturnCode::EINVAL,
+        Some(ref p) if p.current_state() == State::Fault => ReturnCode::EOFF,
+        Some(p) => {
+            process_memory::notify_fault(AppId::new(app_idx));
+            unsafe {
+                p.fault_state();
+            }
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+/// Returns the generation of the app, or `None` if there is no such app.
+pub(crate) fn get_generation(app_idx: usize) -> Option<usize> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| p.generation)
+}
+
+/// Returns the bytes of grant memory the app has allocated for the grant
+/// `grant_num`, or `None` if there is no such app or it has not used the
+/// grant.
+pub(crate) fn get_grant_bytes(app_idx: usize, grant_num: usize) -> Option<usize> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .and_then(|p| p.grant_bytes(grant_num))
+}
+
+/// Returns the name of the app from its TBF header.
+pub(crate) fn get_package_name(app_idx: usize) -> Option<&'static str> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| p.package_name)
+}
+
+/// Returns the app's registers as saved when it last stopped running.
+pub(crate) fn get_registers(app_idx: usize) -> Option<process_memory::Registers> {
+    let procs = unsafe { &PROCS };
+    procs
+        .get(app_idx)
+        .and_then(|p| p.as_ref())
+        .map(|p| process_memory::Registers {
+            r0: p.r0(),
+            r1: p.r1(),
+            r2: p.r2(),
+            r3: p.r3(),
+            r12: p.r12(),
+            lr: p.lr(),
+            pc: p.pc(),
+            xpsr: p.xpsr(),
+            sp: p.sp(),
+        })
+}
+
+/// Returns the fault status registers saved at the last app fault.
+pub(crate) fn get_fault_status() -> process_memory::FaultStatus {
+    unsafe {
+        process_memory::FaultStatus {
+            cfsr: SCB_REGISTERS[1],
+            hfsr: SCB_REGISTERS[2],
+            mmfar: SCB_REGISTERS[3],
+            bfar: SCB_REGISTERS[4],
+        }
+    }
+}
+
+/// The start and end offsets of the storage region declared in the TBF
+/// header of `process`, or `None` if it declares none or the end does not fit
+/// in a `usize`.
+fn storage_region_bounds(process: &Option<&mut Process>) -> Option<(usize, usize)> {
+    process
+        .as_ref()
+        .and_then(|p| p.header.get_storage_region())
+        .and_then(|(offset, size)| {
+            let start = offset as usize;
+            start.checked_add(size as usize).map(|end| (start, end))
+        })
+}
+
+/// Returns the offset and size of the app's region of the board's nonvolatile
+/// storage, as declared in its TBF header. A region that overlaps the region
+/// of an app loaded earlier is not granted, so a misconfigured or malicious
+/// app cannot claim another app's data. Nor is a region whose end overflows.
+pub fn get_storage_region(app_idx: usize) -> Option<(usize, usize)> {
+    let procs = unsafe { &PROCS };
+    if app_idx >= procs.len() {
+        return None;
+    }
+
+    storage_region_bounds(&procs[app_idx]).and_then(|(start, end)| {
+        let overlaps = procs[0..app_idx].iter().any(|other| {
+            storage_region_bounds(other)
+                .map_or(false, |(other_start, other_end)| start < other_end && other_start < end)
+        });
+        if overlaps {
+            None
+        } else {
+            Some((start, end - start))
+        }
+    })
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     NoSuchApp,
@@ -148,6 +512,14 @@ pub enum Error {
     AddressOutOfBounds,
 }
 
+/// Reasons passed to the brk denied callback.
+///
+/// The new break would overlap the grant region, which grows down from the
+/// end of the app's memory.
+pub const BRK_DENIED_GRANT_COLLISION: usize = 1;
+/// The new break is outside the memory allocated to the app.
+pub const BRK_DENIED_OUT_OF_BOUNDS: usize = 2;
+
 impl From<Error> for ReturnCode {
     fn from(err: Error) -> ReturnCode {
         match err {
@@ -163,18 +535,33 @@ pub enum State {
     Running,
     Yielded,
     Fault,
+    /// Stopped by the kernel while running or yielded, and not scheduled
+    /// until it is resumed. Callbacks for the process are still queued.
+    StoppedRunning,
+    StoppedYielded,
 }
 
+/// What the kernel does when a process faults.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FaultResponse {
+    /// Panic the kernel and print the state of the process. This is the most
+    /// useful while developing an app.
     Panic,
+    /// Start the process again from its flash image, with fresh memory and
+    /// grants.
     Restart,
+    /// Stop the process for good. Its memory is kept, so it can still be
+    /// inspected, but it is never scheduled again.
+    Stop,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum IPCType {
     Service,
     Client,
+    /// A message published by a service has been copied into the buffer the
+    /// client shares with it.
+    Publish,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -190,6 +577,12 @@ pub struct FunctionCall {
     pub r2: usize,
     pub r3: usize,
     pub pc: usize,
+    /// The driver and subscribe numbers of the callback that scheduled this
+    /// call, if it came from a subscription.
+    pub subscription: Option<(usize, usize)>,
+    /// For a callback subscribed for four values, the word of the process
+    /// the fourth value is written to, and the value.
+    pub fourth_value: Option<(usize, usize)>,
 }
 
 #[derive(Default)]
@@ -204,6 +597,9 @@ struct StoredRegs {
     r11: usize,
 }
 
+/// The number of syscall classes, `Syscall::YIELD_VARIANT` being the last.
+const NUM_SYSCALL_CLASSES: usize = 6;
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -223,16 +619,35 @@ struct ProcessDebug {
     /// How many syscalls have occurred since the process started.
     syscall_count: Cell<usize>,
 
+    /// How many syscalls of each class have occurred, indexed by syscall
+    /// number.
+    syscall_class_counts: [Cell<usize>; NUM_SYSCALL_CLASSES],
+
     /// What was the most recent syscall.
     last_syscall: Cell<Option<Syscall>>,
 
+    /// The driver number passed to the most recent subscribe, command or
+    /// allow.
+    last_driver_num: Cell<Option<usize>>,
+
     /// How many callbacks were dropped because the queue was insufficiently
     /// long.
     dropped_callback_count: Cell<usize>,
 
+    /// The most callbacks that have been waiting to run at once.
+    max_queued_tasks: Cell<usize>,
+
     /// How many times this process has entered into a fault condition and the
     /// kernel has restarted it.
     restart_count: Cell<usize>,
+
+    /// Ticks of the CPU time source spent running this process in userspace,
+    /// across all restarts.
+    user_ticks: Cell<u64>,
+
+    /// Ticks spent in the kernel handling system calls from this process and
+    /// delivering its callbacks, across all restarts.
+    syscall_ticks: Cell<u64>,
 }
 
 pub struct Process<'a> {
@@ -300,23 +715,60 @@ pub struct Process<'a> {
     /// How to deal with Faults occurring in the process
     fault_response: FaultResponse,
 
-    /// MPU regions are saved as a pointer-size pair.
-    ///
-    /// size is encoded as X where
-    /// SIZE = 2<sup>(X + 1)</sup> and X >= 4.
+    /// Distinguishes this process, as it has run since it last started, from
+    /// every other process that has been or will be in its slot. `AppId`s
+    /// carry it so that ones for an earlier process fail to reach this one.
+    generation: usize,
+
+    /// Scheduling priority under the priority scheduler. Higher is more
+    /// urgent.
+    priority: u32,
+
+    /// How long the process may run before it is pre-empted.
+    quantum_us: u32,
+
+    /// What is left of the current quantum, if the process was interrupted
+    /// part way through it.
+    remaining_quantum_us: u32,
+
+    /// MPU regions are saved as a pointer-length pair: the buffer shared
+    /// with the process.
     ///
     /// A null pointer represents an empty region.
     ///
     /// #### Invariants
     ///
-    /// The pointer must be aligned to the size. E.g. if the size is 32 bytes, the pointer must be
-    /// 32-byte aligned.
-    mpu_regions: [Cell<(*const u8, math::PowerOfTwo)>; 5],
+    /// `mpu::region_layout` can cover the buffer, so it is either a whole,
+    /// aligned, power of two region or a run of subregions of one.
+    mpu_regions: [Cell<(*const u8, usize)>; 5],
 
     /// Essentially a list of callbacks that want to call functions in the
     /// process.
     tasks: RingBuffer<'a, Task>,
 
+    /// Callbacks scheduled as urgent, which run before those in `tasks`.
+    urgent_tasks: RingBuffer<'a, Task>,
+
+    /// Function (and its userdata) to call when a brk or sbrk is denied, as
+    /// registered with memop.
+    brk_denied_callback: Option<(usize, usize)>,
+
+    /// Function (and its userdata) to call when callbacks have been dropped,
+    /// as registered with memop, and whether that call is due.
+    dropped_callback: Option<(usize, usize)>,
+    dropped_notice_pending: bool,
+
+    /// The `yield` with a timeout the process is waiting in, if any.
+    yield_wait: Option<YieldWait>,
+
+    /// The drivers the process has subscribed to or allowed a buffer to
+    /// since it last started.
+    drivers_used: [Option<usize>; MAX_DRIVERS_USED],
+
+    /// The generation of the process and the drivers it used, when it has
+    /// terminated and these drivers have not been told yet.
+    terminated: Option<(usize, [Option<usize>; MAX_DRIVERS_USED])>,
+
     /// Name of the app. Public so that IPC can use it.
     pub package_name: &'static str,
 
@@ -328,22 +780,39 @@ pub struct Process<'a> {
 static mut HAVE_WORK: VolatileCell<usize> = VolatileCell::new(0);
 
 pub fn processes_blocked() -> bool {
-    unsafe { HAVE_WORK.get() == 0 }
+    let work = unsafe { HAVE_WORK.get() };
+    // The work of stopped processes is still counted, but cannot be done
+    // until they are resumed.
+    work == 0 || work <= stopped_work()
+}
+
+/// The work counted in `HAVE_WORK` that belongs to stopped processes.
+fn stopped_work() -> usize {
+    let procs = unsafe { &PROCS };
+    procs
+        .iter()
+        .filter_map(|p| p.as_ref())
+        .map(|p| match p.state {
+            State::StoppedRunning => 1 + p.tasks.len() + p.urgent_tasks.len(),
+            State::StoppedYielded => p.tasks.len() + p.urgent_tasks.len(),
+            _ => 0,
+        })
+        .sum()
 }
 
 impl<'a> Process<'a> {
     pub fn schedule_ipc(&mut self, from: AppId, cb_type: IPCType) {
-        unsafe {
-            HAVE_WORK.set(HAVE_WORK.get() + 1);
-        }
         let ret = self.tasks.enqueue(Task::IPC((from, cb_type)));
 
         // Make a note that we lost this callback if the enqueue function
         // fails.
         if ret == false {
-            self.debug
-                .dropped_callback_count
-                .set(self.debug.dropped_callback_count.get() + 1);
+            self.callback_dropped();
+        } else {
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            }
+            self.task_queued();
         }
     }
 
@@ -351,6 +820,172 @@ impl<'a> Process<'a> {
         self.state
     }
 
+    /// Whether the process has something to do: it was preempted, or it is
+    /// waiting in `yield` and has a callback to run.
+    pub fn ready(&self) -> bool {
+        match self.state {
+            State::Running => true,
+            State::Yielded => self.tasks.len() + self.urgent_tasks.len() > 0,
+            State::Fault | State::StoppedRunning | State::StoppedYielded => false,
+        }
+    }
+
+    /// Stop scheduling the process until `resume()` is called. Callbacks for
+    /// it are queued in the meantime, and run once it resumes. Returns
+    /// `EALREADY` if it is already stopped and `EOFF` if it has faulted.
+    pub fn stop(&mut self) -> ReturnCode {
+        self.state = match self.state {
+            State::Running => State::StoppedRunning,
+            State::Yielded => State::StoppedYielded,
+            State::StoppedRunning | State::StoppedYielded => return ReturnCode::EALREADY,
+            State::Fault => return ReturnCode::EOFF,
+        };
+        ReturnCode::SUCCESS
+    }
+
+    /// Schedule a stopped process again, where it left off. Returns
+    /// `EALREADY` if it is not stopped and `EOFF` if it has faulted.
+    pub fn resume(&mut self) -> ReturnCode {
+        self.state = match self.state {
+            State::StoppedRunning => State::Running,
+            State::StoppedYielded => State::Yielded,
+            State::Running | State::Yielded => return ReturnCode::EALREADY,
+            State::Fault => return ReturnCode::EOFF,
+        };
+        ReturnCode::SUCCESS
+    }
+
+    /// Whether the process has faulted and been stopped for good.
+    pub fn is_terminated(&self) -> bool {
+        self.state == State::Fault
+    }
+
+    /// Whether the process has been stopped with `stop()`.
+    pub fn is_stopped(&self) -> bool {
+        match self.state {
+            State::StoppedRunning | State::StoppedYielded => true,
+            _ => false,
+        }
+    }
+
+    /// The scheduling priority, from the TBF header unless the board has set
+    /// it.
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    /// Override the scheduling priority from the TBF header. Higher values are
+    /// more urgent.
+    pub fn set_priority(&mut self, priority: u32) {
+        self.priority = priority;
+    }
+
+    /// How long the process may run before it is pre-empted.
+    pub fn quantum_us(&self) -> u32 {
+        self.quantum_us
+    }
+
+    /// Set how long the process may run before it is pre-empted, for example
+    /// shorter for a latency-sensitive process. The `SysTick` limits how long
+    /// a quantum can be, typically to a few hundred milliseconds.
+    pub fn set_quantum_us(&mut self, quantum_us: u32) {
+        self.quantum_us = quantum_us;
+        self.remaining_quantum_us = quantum_us;
+    }
+
+    /// How long the process runs for the next time it is scheduled.
+    pub fn remaining_quantum_us(&self) -> u32 {
+        self.remaining_quantum_us
+    }
+
+    /// Record what is left of the quantum when the kernel stops running the
+    /// process. The kernel passes the full quantum when the process used it
+    /// up or stopped on its own, so that only a process interrupted part way
+    /// through resumes with less.
+    pub fn set_remaining_quantum_us(&mut self, remaining_us: u32) {
+        self.remaining_quantum_us = remaining_us;
+    }
+
+    /// Whether the process has a callback waiting to run.
+    pub fn has_tasks(&self) -> bool {
+        self.tasks.len() + self.urgent_tasks.len() > 0
+    }
+
+    /// Whether `addr` is a word of the process's own memory, below its break,
+    /// where the kernel can write a result.
+    pub fn is_result_word(&self, addr: usize) -> bool {
+        let size = mem::size_of::<usize>();
+        addr % size == 0
+            && addr >= self.mem_start() as usize
+            && addr.saturating_add(size) <= self.app_break as usize
+    }
+
+    /// Wait in `yield` for a callback for at most `ticks` from `start`, and
+    /// write whether one ran to `result`, which must be a result word.
+    pub fn start_yield_wait(&mut self, start: u32, ticks: u32, result: *mut usize) {
+        self.yield_wait = Some(YieldWait {
+            start: start,
+            ticks: ticks,
+            result: result,
+        });
+    }
+
+    /// The number of ticks left at `now` before the `yield` the process is
+    /// waiting in times out, if it has a timeout.
+    pub fn yield_wait_remaining(&self, now: u32) -> Option<u32> {
+        self.yield_wait
+            .map(|wait| wait.ticks.saturating_sub(now.wrapping_sub(wait.start)))
+    }
+
+    /// End the `yield` with a timeout the process is waiting in, if any,
+    /// because a callback is about to run or because it timed out. A process
+    /// that timed out returns from `yield` with `SUCCESS`.
+    pub fn end_yield_wait(&mut self, callback_ran: bool) {
+        let wait = match self.yield_wait.take() {
+            Some(wait) => wait,
+            None => return,
+        };
+        unsafe {
+            write_volatile(wait.result, callback_ran as usize);
+        }
+        if callback_ran {
+            return;
+        }
+        // Nothing has been pushed on the stack since `pop_syscall_stack()`
+        // took the `yield` off it, so putting the stack pointer back returns
+        // the process from the `yield`.
+        unsafe {
+            self.current_stack_pointer =
+                (self.current_stack_pointer as *mut usize).offset(-8) as *mut u8;
+            HAVE_WORK.set(HAVE_WORK.get() + 1);
+        }
+        self.set_return_code(ReturnCode::SUCCESS);
+        self.state = match self.state {
+            State::StoppedYielded => State::StoppedRunning,
+            _ => State::Running,
+        };
+    }
+
+    /// Record that the process gave `driver_num` a callback or a buffer, so
+    /// that the driver is told when the process terminates. Drivers beyond
+    /// the first `MAX_DRIVERS_USED` are not recorded.
+    pub(crate) fn note_driver_used(&mut self, driver_num: usize) {
+        if self.drivers_used.contains(&Some(driver_num)) {
+            return;
+        }
+        if let Some(slot) = self.drivers_used.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(driver_num);
+        }
+    }
+
+    /// The generation the process had and the drivers it used, if it has
+    /// terminated since this was last called.
+    pub(crate) fn take_terminated(
+        &mut self,
+    ) -> Option<(usize, [Option<usize>; MAX_DRIVERS_USED])> {
+        self.terminated.take()
+    }
+
     pub fn yield_state(&mut self) {
         if self.state == State::Running {
             self.state = State::Yielded;
@@ -362,76 +997,141 @@ impl<'a> Process<'a> {
 
     pub unsafe fn fault_state(&mut self) {
         write_volatile(&mut APP_FAULT, 0);
+        // A process stopped while running still counts as work.
+        let was_running = self.state == State::Running || self.state == State::StoppedRunning;
         self.state = State::Fault;
 
-        match self.fault_response {
-            FaultResponse::Panic => {
-                // process faulted. Panic and print status
-                panic!("Process {} had a fault", self.package_name);
-            }
-            FaultResponse::Restart => {
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                if HAVE_WORK.get() < self.tasks.len() {
-                    // This case should never happen.
-                    HAVE_WORK.set(0);
-                } else {
-                    HAVE_WORK.set(HAVE_WORK.get() - self.tasks.len());
-                }
+        if self.fault_response == FaultResponse::Panic {
+            // process faulted. Panic and print status
+            panic!("Process {} had a fault", self.package_name);
+        }
+
+        // Remove the tasks that were scheduled for the app, and the process
+        // itself if it was running, from the amount of work queue.
+        let queued = self.tasks.len() + self.urgent_tasks.len() + was_running as usize;
+        if HAVE_WORK.get() < queued {
+            // This case should never happen.
+            HAVE_WORK.set(0);
+        } else {
+            HAVE_WORK.set(HAVE_WORK.get() - queued);
+        }
 
-                // And remove those tasks
-                self.tasks.empty();
-
-                // Mark that we restarted this process.
-                self.debug
-                    .restart_count
-                    .set(self.debug.restart_count.get() + 1);
-
-                // Reset some state for the process.
-                self.debug.syscall_count.set(0);
-                self.debug.last_syscall.set(None);
-                self.debug.dropped_callback_count.set(0);
-
-                // We are going to start this process over again, so need
-                // the init_fn location.
-                let app_flash_address = self.flash_start();
-                let init_fn = app_flash_address
-                    .offset(self.header.get_init_function_offset() as isize)
-                    as usize;
-                self.yield_pc = init_fn;
-                self.psr = 0x01000000;
-                self.state = State::Yielded;
-
-                // Need to reset the grant region.
-                self.grant_ptrs_reset();
-                self.kernel_memory_break = self.original_kernel_memory_break;
-
-                // Reset other memory pointers.
-                self.app_break = self.original_app_break;
-                self.current_stack_pointer = self.original_stack_pointer;
-
-                // And queue up this app to be restarted.
-                let flash_protected_size = self.header.get_protected_size() as usize;
-                let flash_app_start = app_flash_address as usize + flash_protected_size;
-
-                self.tasks.enqueue(Task::FunctionCall(FunctionCall {
-                    pc: init_fn,
-                    r0: flash_app_start,
-                    r1: self.memory.as_ptr() as usize,
-                    r2: self.memory.len() as usize,
-                    r3: self.app_break as usize,
-                }));
+        // And remove those tasks
+        self.tasks.empty();
+        self.urgent_tasks.empty();
+        self.brk_denied_callback = None;
+        self.dropped_callback = None;
+        self.dropped_notice_pending = false;
+        self.yield_wait = None;
+
+        // A process restarted again before drivers were told that it last
+        // terminated has made no system calls since, so has given them
+        // nothing more to drop.
+        if self.terminated.is_none() {
+            self.terminated = Some((self.generation, self.drivers_used));
+        }
+        self.drivers_used = [None; MAX_DRIVERS_USED];
+        self.grants_reclaim();
 
-                HAVE_WORK.set(HAVE_WORK.get() + 1);
-            }
+        if self.fault_response == FaultResponse::Stop {
+            return;
+        }
+
+        // Mark that we restarted this process. `AppId`s and callbacks from
+        // before now no longer refer to it.
+        self.debug
+            .restart_count
+            .set(self.debug.restart_count.get() + 1);
+        self.generation = next_generation();
+
+        // Reset some state for the process.
+        self.debug.syscall_count.set(0);
+        for count in self.debug.syscall_class_counts.iter() {
+            count.set(0);
+        }
+        self.debug.last_syscall.set(None);
+        self.debug.last_driver_num.set(None);
+        self.debug.dropped_callback_count.set(0);
+        self.debug.max_queued_tasks.set(0);
+        self.remaining_quantum_us = self.quantum_us;
+
+        // We are going to start this process over again, so need
+        // the init_fn location.
+        let app_flash_address = self.flash_start();
+        let init_fn =
+            app_flash_address.offset(self.header.get_init_function_offset() as isize) as usize;
+        self.yield_pc = init_fn;
+        self.psr = 0x01000000;
+        self.state = State::Yielded;
+
+        // Reset other memory pointers.
+        self.app_break = self.original_app_break;
+        self.current_stack_pointer = self.original_stack_pointer;
+
+        // And queue up this app to be restarted.
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = app_flash_address as usize + flash_protected_size;
+
+        if self.tasks.enqueue(Task::FunctionCall(FunctionCall {
+            pc: init_fn,
+            r0: flash_app_start,
+            r1: self.memory.as_ptr() as usize,
+            r2: self.memory.len() as usize,
+            r3: self.app_break as usize,
+            subscription: None,
+            fourth_value: None,
+        })) {
+            HAVE_WORK.set(HAVE_WORK.get() + 1);
+        }
+    }
+
+    /// How the kernel handles a fault of this process.
+    pub fn fault_response(&self) -> FaultResponse {
+        self.fault_response
+    }
+
+    /// Handle faults of this process differently from the response the
+    /// board loaded the processes with, for example to restart a crucial app
+    /// but stop the others.
+    pub fn set_fault_response(&mut self, fault_response: FaultResponse) {
+        self.fault_response = fault_response;
+    }
+
+    /// How many times the kernel has restarted the process after a fault.
+    pub fn restart_count(&self) -> usize {
+        self.debug.restart_count.get()
+    }
+
+    /// The generation of the process, which changes each time it restarts.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Remove the calls to the callback subscribed with `driver_num` and
+    /// `subscribe_num` that are waiting to run, so that none of them runs
+    /// after the process unsubscribes.
+    pub fn remove_pending_callbacks(&mut self, driver_num: usize, subscribe_num: usize) {
+        let keep = |task: &Task| match *task {
+            Task::FunctionCall(ref call) => call.subscription != Some((driver_num, subscribe_num)),
+            Task::IPC(_) => true,
+        };
+        let removed = self.urgent_tasks.retain(keep) + self.tasks.retain(keep);
+        unsafe {
+            HAVE_WORK.set(HAVE_WORK.get().saturating_sub(removed));
         }
     }
 
+    /// Take the next task, from the urgent lane if it has any.
     pub fn dequeue_task(&mut self) -> Option<Task> {
-        self.tasks.dequeue().map(|cb| {
+        let task = match self.urgent_tasks.dequeue() {
+            Some(task) => Some(task),
+            None => self.tasks.dequeue(),
+        };
+        task.map(|cb| {
             unsafe {
                 HAVE_WORK.set(HAVE_WORK.get() - 1);
             }
+            self.queue_dropped_notice();
             cb
         })
     }
@@ -460,6 +1160,10 @@ impl<'a> Process<'a> {
         self.kernel_memory_break
     }
 
+    pub fn app_memory_break(&self) -> *const u8 {
+        self.app_break
+    }
+
     pub fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -475,9 +1179,55 @@ impl<'a> Process<'a> {
             // We also reset the minimum stack pointer because whatever value
             // we had could be entirely wrong by now.
             self.debug.min_stack_pointer = stack_pointer;
+            self.paint_stack();
+        }
+    }
+
+    /// Fill the memory below the current stack pointer, which the process
+    /// has not yet used, with `STACK_PAINT`.
+    fn paint_stack(&self) {
+        let word = mem::size_of::<usize>();
+        let mut addr = (self.mem_start() as usize + word - 1) & !(word - 1);
+        let end = self.current_stack_pointer as usize & !(word - 1);
+        while addr < end {
+            unsafe {
+                write_volatile(addr as *mut usize, STACK_PAINT);
+            }
+            addr += word;
         }
     }
 
+    /// The most stack the process has used, in bytes, or `None` if it has not
+    /// said where its stack starts. This is the deeper of the lowest stack
+    /// pointer the kernel has seen and the lowest word of the stack paint that
+    /// has been overwritten.
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        self.debug.app_stack_start_pointer.map(|stack_start| {
+            let word = mem::size_of::<usize>();
+            let mut addr = (self.mem_start() as usize + word - 1) & !(word - 1);
+            let lowest_seen = self.debug.min_stack_pointer as usize;
+            while addr < lowest_seen {
+                if unsafe { read_volatile(addr as *const usize) } != STACK_PAINT {
+                    break;
+                }
+                addr += word;
+            }
+            (stack_start as usize).saturating_sub(addr)
+        })
+    }
+
+    /// The number of bytes the kernel uses at the top of the process's
+    /// memory, for grants and its own state.
+    pub fn grant_region_size(&self) -> usize {
+        self.mem_end() as usize - self.kernel_memory_break as usize
+    }
+
+    /// The number of bytes the process can still move its break up by before
+    /// it reaches the grant region.
+    pub fn free_heap_size(&self) -> usize {
+        self.kernel_memory_break as usize - self.app_break as usize
+    }
+
     pub fn update_heap_start_pointer(&mut self, heap_pointer: *const u8) {
         if heap_pointer >= self.mem_start() && heap_pointer < self.mem_end() {
             self.debug.app_heap_start_pointer = Some(heap_pointer);
@@ -557,7 +1307,7 @@ impl<'a> Process<'a> {
             match MPU::create_region(
                 i + 3,
                 region.get().0 as usize,
-                region.get().1.as_num::<u32>() as usize,
+                region.get().1,
                 mpu::ExecutePermission::ExecutionPermitted,
                 mpu::AccessPermission::ReadWrite,
             ) {
@@ -566,23 +1316,26 @@ impl<'a> Process<'a> {
                      Base: {:#x}, Length: {:#x}",
                     i + 3,
                     region.get().0 as usize,
-                    region.get().1.as_num::<u32>()
+                    region.get().1
                 ),
                 Some(region) => mpu.set_mpu(region),
             }
         }
     }
 
+    /// Give the process access to the `size` bytes at `base`. Returns false
+    /// if one MPU region cannot cover exactly that memory, or if the process
+    /// has no MPU region left for it.
     pub fn add_mpu_region(&self, base: *const u8, size: u32) -> bool {
-        if size >= 16 && size.count_ones() == 1 && (base as u32) % size == 0 {
-            let mpu_size = math::PowerOfTwo::floor(size);
+        let size = size as usize;
+        if mpu::region_layout(base as usize, size).is_some() {
             for region in self.mpu_regions.iter() {
                 if region.get().0 == ptr::null() {
-                    region.set((base, mpu_size));
+                    region.set((base, size));
                     return true;
                 } else if region.get().0 == base {
-                    if region.get().1 < mpu_size {
-                        region.set((base, mpu_size));
+                    if region.get().1 < size {
+                        region.set((base, size));
                     }
                     return true;
                 }
@@ -591,6 +1344,43 @@ impl<'a> Process<'a> {
         return false;
     }
 
+    /// The memory a process needs: what its TBF header asks for, but at
+    /// least enough for the kernel's state in its grant region.
+    unsafe fn app_ram_size(tbf_header: &tbfheader::TbfHeader, app_flash_address: *const u8) -> usize {
+        let mut min_app_ram_size = tbf_header.get_minimum_app_ram_size();
+
+        // First determine how much space we need in the application's
+        // memory space just for kernel and grant state. We need to make
+        // sure we allocate enough memory just for that.
+
+        // Make room for grant pointers, and the count of bytes each grant
+        // uses.
+        let grant_ptr_size = mem::size_of::<*const usize>() + mem::size_of::<usize>();
+        let grant_ptrs_num = read_volatile(&grant::CONTAINER_COUNTER);
+        let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
+
+        // Allocate memory for callback ring buffer.
+        let callback_size = mem::size_of::<Task>();
+        let (callback_len, urgent_callback_len) =
+            task_queue_lens(tbf_header.get_package_name(app_flash_address));
+        let callbacks_offset = (callback_len + urgent_callback_len) * callback_size;
+
+        // Make room to store this process's metadata.
+        let process_struct_offset = mem::size_of::<Process>();
+
+        // Need to make sure that the amount of memory we allocate for
+        // this process at least covers this state.
+        if min_app_ram_size < (grant_ptrs_offset + callbacks_offset + process_struct_offset) as u32
+        {
+            min_app_ram_size = (grant_ptrs_offset + callbacks_offset + process_struct_offset) as u32;
+        }
+
+        // TODO round app_ram_size up to a closer MPU unit.
+        // This is a very conservative approach that rounds up to power of
+        // two. We should be able to make this closer to what we actually need.
+        math::closest_power_of_two(min_app_ram_size) as usize
+    }
+
     pub unsafe fn create(
         app_flash_address: *const u8,
         remaining_app_memory: *mut u8,
@@ -607,7 +1397,6 @@ impl<'a> Process<'a> {
             }
 
             // Otherwise, actually load the app.
-            let mut min_app_ram_size = tbf_header.get_minimum_app_ram_size();
             let package_name = tbf_header.get_package_name(app_flash_address);
             let init_fn =
                 app_flash_address.offset(tbf_header.get_init_function_offset() as isize) as usize;
@@ -616,36 +1405,18 @@ impl<'a> Process<'a> {
             let initial_stack_pointer = remaining_app_memory.offset(128);
             let initial_sbrk_pointer = remaining_app_memory.offset(128);
 
-            // First determine how much space we need in the application's
-            // memory space just for kernel and grant state. We need to make
-            // sure we allocate enough memory just for that.
-
-            // Make room for grant pointers.
-            let grant_ptr_size = mem::size_of::<*const usize>();
+            // The kernel's state at the top of the process's memory: grant
+            // pointers and byte counts, the callback ring buffers and the
+            // process struct.
+            let grant_ptr_size = mem::size_of::<*const usize>() + mem::size_of::<usize>();
             let grant_ptrs_num = read_volatile(&grant::CONTAINER_COUNTER);
             let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
-
-            // Allocate memory for callback ring buffer.
             let callback_size = mem::size_of::<Task>();
-            let callback_len = 10;
-            let callbacks_offset = callback_len * callback_size;
-
-            // Make room to store this process's metadata.
+            let (callback_len, urgent_callback_len) = task_queue_lens(package_name);
+            let callbacks_offset = (callback_len + urgent_callback_len) * callback_size;
             let process_struct_offset = mem::size_of::<Process>();
 
-            // Need to make sure that the amount of memory we allocate for
-            // this process at least covers this state.
-            if min_app_ram_size
-                < (grant_ptrs_offset + callbacks_offset + process_struct_offset) as u32
-            {
-                min_app_ram_size =
-                    (grant_ptrs_offset + callbacks_offset + process_struct_offset) as u32;
-            }
-
-            // TODO round app_ram_size up to a closer MPU unit.
-            // This is a very conservative approach that rounds up to power of
-            // two. We should be able to make this closer to what we actually need.
-            let app_ram_size = math::closest_power_of_two(min_app_ram_size) as usize;
+            let app_ram_size = Process::app_ram_size(&tbf_header, app_flash_address);
 
             // Check that we can actually give this app this much memory.
             if app_ram_size > remaining_app_memory_size {
@@ -664,9 +1435,11 @@ impl<'a> Process<'a> {
             // pointers.
             kernel_memory_break = kernel_memory_break.offset(-(grant_ptrs_offset as isize));
 
-            // Set all pointers to null.
-            let opts =
-                slice::from_raw_parts_mut(kernel_memory_break as *mut *const usize, grant_ptrs_num);
+            // Set all pointers to null, and all byte counts to zero.
+            let opts = slice::from_raw_parts_mut(
+                kernel_memory_break as *mut *const usize,
+                2 * grant_ptrs_num,
+            );
             for opt in opts.iter_mut() {
                 *opt = ptr::null()
             }
@@ -675,10 +1448,14 @@ impl<'a> Process<'a> {
             // for the callbacks.
             kernel_memory_break = kernel_memory_break.offset(-(callbacks_offset as isize));
 
-            // Set up ring buffer.
-            let callback_buf =
-                slice::from_raw_parts_mut(kernel_memory_break as *mut Task, callback_len);
+            // Set up the ring buffers for both lanes.
+            let callback_buf = slice::from_raw_parts_mut(
+                kernel_memory_break as *mut Task,
+                callback_len + urgent_callback_len,
+            );
+            let (callback_buf, urgent_callback_buf) = callback_buf.split_at_mut(callback_len);
             let tasks = RingBuffer::new(callback_buf);
+            let urgent_tasks = RingBuffer::new(urgent_callback_buf);
 
             // Last thing is the process struct.
             kernel_memory_break = kernel_memory_break.offset(-(process_struct_offset as isize));
@@ -712,15 +1489,26 @@ impl<'a> Process<'a> {
 
             process.state = State::Yielded;
             process.fault_response = fault_response;
+            process.generation = next_generation();
+            process.priority = process.header.get_priority();
+            process.quantum_us = DEFAULT_QUANTUM_US;
+            process.remaining_quantum_us = DEFAULT_QUANTUM_US;
 
             process.mpu_regions = [
-                Cell::new((ptr::null(), math::PowerOfTwo::zero())),
-                Cell::new((ptr::null(), math::PowerOfTwo::zero())),
-                Cell::new((ptr::null(), math::PowerOfTwo::zero())),
-                Cell::new((ptr::null(), math::PowerOfTwo::zero())),
-                Cell::new((ptr::null(), math::PowerOfTwo::zero())),
+                Cell::new((ptr::null(), 0)),
+                Cell::new((ptr::null(), 0)),
+                Cell::new((ptr::null(), 0)),
+                Cell::new((ptr::null(), 0)),
+                Cell::new((ptr::null(), 0)),
             ];
             process.tasks = tasks;
+            process.urgent_tasks = urgent_tasks;
+            process.brk_denied_callback = None;
+            process.dropped_callback = None;
+            process.dropped_notice_pending = false;
+            process.yield_wait = None;
+            process.drivers_used = [None; MAX_DRIVERS_USED];
+            process.terminated = None;
             process.package_name = package_name;
 
             process.debug = ProcessDebug {
@@ -728,9 +1516,21 @@ impl<'a> Process<'a> {
                 app_stack_start_pointer: app_stack_start_pointer,
                 min_stack_pointer: initial_stack_pointer,
                 syscall_count: Cell::new(0),
+                syscall_class_counts: [
+                    Cell::new(0),
+                    Cell::new(0),
+                    Cell::new(0),
+                    Cell::new(0),
+                    Cell::new(0),
+                    Cell::new(0),
+                ],
                 last_syscall: Cell::new(None),
+                last_driver_num: Cell::new(None),
                 dropped_callback_count: Cell::new(0),
+                max_queued_tasks: Cell::new(0),
                 restart_count: Cell::new(0),
+                user_ticks: Cell::new(0),
+                syscall_ticks: Cell::new(0),
             };
 
             if (init_fn & 0x1) != 1 {
@@ -744,15 +1544,17 @@ impl<'a> Process<'a> {
             let flash_protected_size = process.header.get_protected_size() as usize;
             let flash_app_start = app_flash_address as usize + flash_protected_size;
 
-            process.tasks.enqueue(Task::FunctionCall(FunctionCall {
+            if process.tasks.enqueue(Task::FunctionCall(FunctionCall {
                 pc: init_fn,
                 r0: flash_app_start,
                 r1: process.memory.as_ptr() as usize,
                 r2: process.memory.len() as usize,
                 r3: process.app_break as usize,
-            }));
-
-            HAVE_WORK.set(HAVE_WORK.get() + 1);
+                subscription: None,
+                fourth_value: None,
+            })) {
+                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            }
 
             return (Some(process), app_flash_size, app_ram_size);
         }
@@ -776,6 +1578,100 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Set the function `pc` to be called with `appdata` when a brk or sbrk
+    /// is denied, or stop calling it if `pc` is 0.
+    pub fn set_brk_denied_callback(&mut self, pc: usize, appdata: usize) {
+        self.brk_denied_callback = if pc == 0 { None } else { Some((pc, appdata)) };
+    }
+
+    /// Tell the process that a brk or sbrk to `requested_break` was denied
+    /// because of `err`, if it has asked to know.
+    pub fn brk_denied(&mut self, err: Error, requested_break: *const u8) {
+        let (pc, appdata) = match self.brk_denied_callback {
+            Some(callback) => callback,
+            None => return,
+        };
+        let reason = match err {
+            Error::OutOfMemory => BRK_DENIED_GRANT_COLLISION,
+            _ => BRK_DENIED_OUT_OF_BOUNDS,
+        };
+        let ret = self.tasks.enqueue(Task::FunctionCall(FunctionCall {
+            pc: pc,
+            r0: reason,
+            r1: requested_break as usize,
+            r2: self.kernel_memory_break as usize,
+            r3: appdata,
+            subscription: None,
+            fourth_value: None,
+        }));
+        if ret {
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            }
+            self.task_queued();
+        } else {
+            self.callback_dropped();
+        }
+    }
+
+    /// Set the function `pc` to be called with `appdata` when callbacks for
+    /// the process have been dropped, or stop calling it if `pc` is 0.
+    pub fn set_dropped_callback(&mut self, pc: usize, appdata: usize) {
+        self.dropped_callback = if pc == 0 { None } else { Some((pc, appdata)) };
+        self.dropped_notice_pending = false;
+    }
+
+    /// How many callbacks were dropped because the queue was full, since
+    /// the process last started.
+    pub fn dropped_callback_count(&self) -> usize {
+        self.debug.dropped_callback_count.get()
+    }
+
+    /// Count a callback lost because the queue was full. The process is told
+    /// once the queue has room again, if it has asked to know.
+    fn callback_dropped(&mut self) {
+        self.debug
+            .dropped_callback_count
+            .set(self.debug.dropped_callback_count.get() + 1);
+        if self.dropped_callback.is_some() {
+            self.dropped_notice_pending = true;
+        }
+    }
+
+    /// Queue the call telling the process its callbacks were dropped, if one
+    /// is due and there is room for it.
+    fn queue_dropped_notice(&mut self) {
+        let (pc, appdata) = match self.dropped_callback {
+            Some(callback) if self.dropped_notice_pending => callback,
+            _ => return,
+        };
+        let ret = self.tasks.enqueue(Task::FunctionCall(FunctionCall {
+            pc: pc,
+            r0: self.debug.dropped_callback_count.get(),
+            r1: 0,
+            r2: 0,
+            r3: appdata,
+            subscription: None,
+            fourth_value: None,
+        }));
+        if ret {
+            self.dropped_notice_pending = false;
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            }
+            self.task_queued();
+        }
+    }
+
+    /// Track the most callbacks that have been waiting at once, after one
+    /// was queued.
+    fn task_queued(&self) {
+        let queued = self.tasks.len() + self.urgent_tasks.len();
+        if queued > self.debug.max_queued_tasks.get() {
+            self.debug.max_queued_tasks.set(queued);
+        }
+    }
+
     pub fn in_exposed_bounds(&self, buf_start_addr: *const u8, size: usize) -> bool {
         let buf_end_addr = unsafe { buf_start_addr.offset(size as isize) };
 
@@ -792,6 +1688,16 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Allocate `size` bytes of grant memory, counted against the grant
+    /// `grant_num`.
+    pub unsafe fn alloc_for_grant(&mut self, size: usize, grant_num: usize) -> Option<&mut [u8]> {
+        let count = self.grant_bytes_ptr(grant_num);
+        self.alloc(size).map(|memory| {
+            write_volatile(count, read_volatile(count) + size);
+            memory
+        })
+    }
+
     pub unsafe fn free<T>(&mut self, _: *mut T) {}
 
     unsafe fn grant_ptr<T>(&self, grant_num: usize) -> *mut *mut T {
@@ -799,16 +1705,55 @@ impl<'a> Process<'a> {
         (self.mem_end() as *mut *mut T).offset(-(grant_num + 1))
     }
 
-    /// Reset all `grant_ptr`s to NULL.
+    /// The count of bytes allocated for the grant `grant_num`, which is kept
+    /// below the grant pointers.
+    unsafe fn grant_bytes_ptr(&self, grant_num: usize) -> *mut usize {
+        let grant_ptrs_num = read_volatile(&grant::CONTAINER_COUNTER) as isize;
+        let grant_num = grant_num as isize;
+        (self.mem_end() as *mut usize).offset(-(grant_ptrs_num + grant_num + 1))
+    }
+
+    /// Free all grant memory of the process, so that each grant is allocated
+    /// afresh the next time it is entered, and `Grant::each()` skips the
+    /// process until then.
+    unsafe fn grants_reclaim(&mut self) {
+        self.grant_ptrs_reset();
+        self.kernel_memory_break = self.original_kernel_memory_break;
+    }
+
+    /// Reset all `grant_ptr`s to NULL and their byte counts to zero.
     unsafe fn grant_ptrs_reset(&self) {
         let grant_ptrs_num = read_volatile(&grant::CONTAINER_COUNTER);
         for grant_num in 0..grant_ptrs_num {
-            let grant_num = grant_num as isize;
-            let ctr_ptr = (self.mem_end() as *mut *mut usize).offset(-(grant_num + 1));
-            write_volatile(ctr_ptr, ptr::null_mut());
+            write_volatile(self.grant_ptr::<usize>(grant_num), ptr::null_mut());
+            write_volatile(self.grant_bytes_ptr(grant_num), 0);
+        }
+    }
+
+    /// The number of bytes of grant memory allocated for the grant
+    /// `grant_num` in this process, or `None` if the process has not used the
+    /// grant.
+    pub fn grant_bytes(&self, grant_num: usize) -> Option<usize> {
+        if grant_num >= grant::num_grants() {
+            return None;
+        }
+        unsafe {
+            if (*self.grant_ptr::<u8>(grant_num)).is_null() {
+                None
+            } else {
+                Some(read_volatile(self.grant_bytes_ptr(grant_num)))
+            }
         }
     }
 
+    /// The number of bytes at the top of the process's memory that the kernel
+    /// set aside for its own state when the process was loaded: the grant
+    /// pointers, callback queues and this struct. The rest of the grant
+    /// region is allocated by grants.
+    pub fn kernel_state_size(&self) -> usize {
+        self.mem_end() as usize - self.original_kernel_memory_break as usize
+    }
+
     pub unsafe fn grant_for<T>(&mut self, grant_num: usize) -> *mut T {
         *self.grant_ptr(grant_num)
     }
@@ -816,7 +1761,7 @@ impl<'a> Process<'a> {
     pub unsafe fn grant_for_or_alloc<T: Default>(&mut self, grant_num: usize) -> Option<*mut T> {
         let ctr_ptr = self.grant_ptr::<T>(grant_num);
         if (*ctr_ptr).is_null() {
-            self.alloc(mem::size_of::<T>()).map(|root_arr| {
+            self.alloc_for_grant(mem::size_of::<T>(), grant_num).map(|root_arr| {
                 let root_ptr = root_arr.as_mut_ptr() as *mut T;
                 // Initialize the grant contents using ptr::write, to
                 // ensure that we don't try to drop the contents of
@@ -864,6 +1809,14 @@ impl<'a> Process<'a> {
         write_volatile(stack_bottom.offset(2), callback.r2);
         write_volatile(stack_bottom.offset(3), callback.r3);
 
+        // The word was checked at subscribe, but the process may since have
+        // moved its break below it.
+        if let Some((word, value)) = callback.fourth_value {
+            if self.is_result_word(word) {
+                write_volatile(word as *mut usize, value);
+            }
+        }
+
         self.current_stack_pointer = stack_bottom as *mut u8;
         if self.current_stack_pointer < self.debug.min_stack_pointer {
             self.debug.min_stack_pointer = self.current_stack_pointer;
@@ -903,6 +1856,7 @@ impl<'a> Process<'a> {
                 2 => Some(Syscall::COMMAND),
                 3 => Some(Syscall::ALLOW),
                 4 => Some(Syscall::MEMOP),
+                5 => Some(Syscall::YIELD_VARIANT),
                 _ => None,
             }
         }
@@ -912,7 +1866,59 @@ impl<'a> Process<'a> {
         self.debug
             .syscall_count
             .set(self.debug.syscall_count.get() + 1);
-        self.debug.last_syscall.set(self.svc_number());
+        let syscall = self.svc_number();
+        self.debug.last_syscall.set(syscall);
+        if let Some(syscall) = syscall {
+            let count = &self.debug.syscall_class_counts[syscall as usize];
+            count.set(count.get() + 1);
+            match syscall {
+                Syscall::SUBSCRIBE | Syscall::COMMAND | Syscall::ALLOW => {
+                    self.debug.last_driver_num.set(Some(self.r0()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// How many syscalls of the given class the process has made since it
+    /// started.
+    pub fn syscall_class_count(&self, syscall: Syscall) -> usize {
+        self.debug.syscall_class_counts[syscall as usize].get()
+    }
+
+    /// The most recent syscall the process made.
+    pub fn last_syscall(&self) -> Option<Syscall> {
+        self.debug.last_syscall.get()
+    }
+
+    /// The driver number of the most recent subscribe, command or allow the
+    /// process made.
+    pub fn last_driver_num(&self) -> Option<usize> {
+        self.debug.last_driver_num.get()
+    }
+
+    /// Charge the process for time spent on its behalf, in ticks of the CPU
+    /// time source.
+    pub(crate) fn add_cpu_time(&self, user_ticks: u64, syscall_ticks: u64) {
+        self.debug
+            .user_ticks
+            .set(self.debug.user_ticks.get().wrapping_add(user_ticks));
+        self.debug
+            .syscall_ticks
+            .set(self.debug.syscall_ticks.get().wrapping_add(syscall_ticks));
+    }
+
+    /// The microseconds the process has spent running in userspace and the
+    /// kernel has spent handling its system calls, or `None` if the board has
+    /// not set a CPU time source.
+    pub fn cpu_time_us(&self) -> Option<(u64, u64)> {
+        unsafe { CPU_TIME_SOURCE }.map(|source| {
+            let frequency = source.frequency() as u64;
+            (
+                self.debug.user_ticks.get() * 1_000_000 / frequency,
+                self.debug.syscall_ticks.get() * 1_000_000 / frequency,
+            )
+        })
     }
 
     pub fn sp(&self) -> usize {
@@ -1148,6 +2154,27 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Print how the grant region is used: the kernel's own state, and the
+    /// bytes allocated by each grant the process has used.
+    pub fn grants_str<W: Write>(&self, writer: &mut W) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n Grant region: {} bytes, {} of them kernel state. {} bytes free.\r\n",
+            self.grant_region_size(),
+            self.kernel_state_size(),
+            self.free_heap_size()
+        ));
+        for grant_num in 0..grant::num_grants() {
+            if let Some(bytes) = self.grant_bytes(grant_num) {
+                let _ = writer.write_fmt(format_args!(
+                    "   Grant {:>2}: {:>5} bytes  {}\r\n",
+                    grant_num,
+                    bytes,
+                    grant::grant_name(grant_num).unwrap_or("?")
+                ));
+            }
+        }
+    }
+
     pub unsafe fn statistics_str<W: Write>(&mut self, writer: &mut W) {
         // Flash
         let flash_end = self.flash.as_ptr().offset(self.flash.len() as isize) as usize;
@@ -1191,9 +2218,14 @@ impl<'a> Process<'a> {
         }
 
         // application statistics
-        let events_queued = self.tasks.len();
+        let events_queued = self.tasks.len() + self.urgent_tasks.len();
+        let max_events_queued = self.debug.max_queued_tasks.get();
+        let queue_depth = TASK_QUEUE_DEPTH(self.package_name) + URGENT_TASK_QUEUE_DEPTH;
         let syscall_count = self.debug.syscall_count.get();
         let last_syscall = self.debug.last_syscall.get();
+        let last_driver_num = self.debug.last_driver_num.get();
+        let class_count =
+            |syscall: Syscall| self.debug.syscall_class_counts[syscall as usize].get();
         let dropped_callback_count = self.debug.dropped_callback_count.get();
         let restart_count = self.debug.restart_count.get();
 
@@ -1213,20 +2245,43 @@ impl<'a> Process<'a> {
         let _ = writer.write_fmt(format_args!(
             "\
              App: {}   -   [{:?}]\
-             \r\n Events Queued: {}   Syscall Count: {}   Dropped Callback Count: {}\
-             \n Restart Count: {}\n",
+             \r\n Events Queued: {}   Most Queued: {} of {}   Syscall Count: {}\
+             \r\n Dropped Callback Count: {}   Restart Count: {}\n",
             self.package_name,
             self.state,
             events_queued,
+            max_events_queued,
+            queue_depth,
             syscall_count,
             dropped_callback_count,
             restart_count,
         ));
 
+        let _ = writer.write_fmt(format_args!(
+            " Syscalls: YIELD {}  SUBSCRIBE {}  COMMAND {}  ALLOW {}  MEMOP {}  \
+             YIELD_VARIANT {}\r\n",
+            class_count(Syscall::YIELD),
+            class_count(Syscall::SUBSCRIBE),
+            class_count(Syscall::COMMAND),
+            class_count(Syscall::ALLOW),
+            class_count(Syscall::MEMOP),
+            class_count(Syscall::YIELD_VARIANT),
+        ));
+
+        if let Some((user_us, syscall_us)) = self.cpu_time_us() {
+            let _ = writer.write_fmt(format_args!(
+                " CPU Time: User {} us  Syscall {} us\r\n",
+                user_us, syscall_us,
+            ));
+        }
+
         let _ = match last_syscall {
             Some(syscall) => writer.write_fmt(format_args!(" Last Syscall: {:?}", syscall)),
             None => writer.write_fmt(format_args!(" Last Syscall: None")),
         };
+        if let Some(driver_num) = last_driver_num {
+            let _ = writer.write_fmt(format_args!("   Last Driver: {:#x}", driver_num));
+        }
 
         let _ = writer.write_fmt(format_args!("\
 \r\n\
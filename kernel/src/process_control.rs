@@ -0,0 +1,88 @@
+//! Control over which processes run, for debugging capsules.
+//!
+//! While bringing up a board it helps to stop a misbehaving process without
+//! reflashing, start it again, or make it fault to see how the rest of the
+//! system copes. A capsule that does this, such as a process console, is
+//! given a `ProcessControl`.
+//!
+//! Stopping a process only takes it off the schedule: its memory, grants
+//! and queued callbacks are kept, and callbacks for it keep being queued
+//! until the queue is full. It carries on where it left off when resumed.
+//!
+//! Creating a `ProcessControl` is `unsafe`, so only the board can decide
+//! which capsules get one:
+//!
+//! ```rust
+//! let control = kernel::process_control::ProcessControl::new();
+//! ```
+
+use callback::AppId;
+use process;
+use returncode::ReturnCode;
+
+pub struct ProcessControl {
+    _private: (),
+}
+
+impl ProcessControl {
+    /// Only code trusted to stop or fault any process should be given a
+    /// `ProcessControl`.
+    pub unsafe fn new() -> ProcessControl {
+        ProcessControl { _private: () }
+    }
+
+    /// The `AppId` of the process in slot `index`, or `None` if the slot is
+    /// empty or out of range. Used to walk all processes.
+    pub fn appid(&self, index: usize) -> Option<AppId> {
+        process::get_state(index).map(|_| AppId::new(index))
+    }
+
+    /// The number of process slots.
+    pub fn num_slots(&self) -> usize {
+        process::num_slots()
+    }
+
+    /// The first process named `name` in its TBF header.
+    pub fn find(&self, name: &str) -> Option<AppId> {
+        (0..self.num_slots())
+            .filter_map(|index| self.appid(index))
+            .find(|&appid| self.name(appid) == Some(name))
+    }
+
+    pub fn state(&self, appid: AppId) -> Option<process::State> {
+        process::get_state(appid.idx())
+    }
+
+    /// The name of the process `appid`, from its TBF header.
+    pub fn name(&self, appid: AppId) -> Option<&'static str> {
+        process::get_package_name(appid.idx())
+    }
+
+    /// How many times the process has been restarted after a fault.
+    pub fn restart_count(&self, appid: AppId) -> Option<usize> {
+        process::get_restart_count(appid.idx())
+    }
+
+    /// Stop scheduling the process until `resume()` is called. Returns
+    /// `EINVAL` if there is no such process, `EALREADY` if it is already
+    /// stopped and `EOFF` if it has faulted.
+    pub fn stop(&self, appid: AppId) -> ReturnCode {
+        process::stop(appid.idx())
+    }
+
+    /// Schedule a stopped process again. Returns `EINVAL` if there is no
+    /// such process, `EALREADY` if it is not stopped and `EOFF` if it has
+    /// faulted.
+    pub fn resume(&self, appid: AppId) -> ReturnCode {
+        process::resume(appid.idx())
+    }
+
+    /// Handle the process as if it had faulted: the fault observer is told,
+    /// then the kernel panics, restarts the process or stops it for good,
+    /// according to its fault response. A restarted process is no longer
+    /// stopped. Returns `EINVAL` if there is no such process and `EOFF` if
+    /// it has already faulted and was stopped.
+    pub fn fault(&self, appid: AppId) -> ReturnCode {
+        process::fault(appid.idx())
+    }
+}
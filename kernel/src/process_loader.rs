@@ -0,0 +1,97 @@
+//! Starting processes from images written to flash at runtime.
+//!
+//! At boot, `procs::load_processes()` starts every app it finds in flash. A
+//! board can also set aside a flash region into which new apps are written
+//! while the kernel runs, for example by a capsule that receives them over
+//! UART or the radio, and give that capsule a `ProcessLoader` for the region.
+//! Once an image has been written, the capsule asks the loader to start it.
+//! A started process takes a free slot in the processes array and memory
+//! that the processes loaded at boot did not use, so boards that want to
+//! load apps at runtime leave slots empty and app memory spare.
+//!
+//! Creating a `ProcessLoader` is `unsafe`, as whoever holds one can run any
+//! code written to its region, so only the board can decide which capsules
+//! get one:
+//!
+//! ```rust
+//! let app_loader = static_init!(
+//!     AppLoader<'static>,
+//!     AppLoader::new(kernel::process_loader::ProcessLoader::new(
+//!         &_sapps_runtime as *const u8,
+//!         0x10000
+//!     ))
+//! );
+//! ```
+
+use callback::AppId;
+use core::mem;
+use process;
+use returncode::ReturnCode;
+use tbfheader;
+
+pub struct ProcessLoader {
+    flash_start: *const u8,
+    flash_len: usize,
+}
+
+impl ProcessLoader {
+    /// Load processes from the `flash_len` bytes of flash at `flash_start`.
+    pub unsafe fn new(flash_start: *const u8, flash_len: usize) -> ProcessLoader {
+        ProcessLoader {
+            flash_start: flash_start,
+            flash_len: flash_len,
+        }
+    }
+
+    /// Start the process whose TBF image is at `offset` into the region.
+    ///
+    /// Returns `EINVAL` if there is no valid, enabled app there or it does
+    /// not fit within the region, `EALREADY` if the process is already
+    /// running and `ENOMEM` if there is no free process slot or not enough
+    /// app memory for it.
+    pub fn load(&self, offset: usize) -> Result<AppId, ReturnCode> {
+        let address = match self.image_size(offset) {
+            Some(_) => unsafe { self.flash_start.offset(offset as isize) },
+            None => return Err(ReturnCode::EINVAL),
+        };
+        if process::is_loaded(address) {
+            return Err(ReturnCode::EALREADY);
+        }
+        unsafe { process::load_process(address) }
+    }
+
+    /// Start every app in the region that is not already running, walking
+    /// the images from the start of the region until one is not valid, like
+    /// the loader does at boot. Returns how many processes were started, or
+    /// the error that stopped one from starting.
+    pub fn load_all(&self) -> Result<usize, ReturnCode> {
+        let mut offset = 0;
+        let mut started = 0;
+        while let Some(size) = self.image_size(offset) {
+            match self.load(offset) {
+                Ok(_) => started += 1,
+                // Padding, disabled apps and images already running are
+                // skipped.
+                Err(ReturnCode::EINVAL) | Err(ReturnCode::EALREADY) => {}
+                Err(err) => return Err(err),
+            }
+            offset += size;
+        }
+        Ok(started)
+    }
+
+    /// The size of the valid TBF image at `offset`, if it lies entirely
+    /// within the region.
+    fn image_size(&self, offset: usize) -> Option<usize> {
+        // A version 1 header is the largest fixed header that is read.
+        if offset + mem::size_of::<tbfheader::TbfHeaderV1>() > self.flash_len {
+            return None;
+        }
+        let header = unsafe {
+            tbfheader::parse_and_validate_tbf_header(self.flash_start.offset(offset as isize))
+        };
+        header
+            .map(|header| header.get_total_size() as usize)
+            .filter(|&size| size > 0 && offset + size <= self.flash_len)
+    }
+}
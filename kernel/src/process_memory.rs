@@ -0,0 +1,185 @@
+//! Read-only access to process memory for debugging capsules.
+//!
+//! A debugger stub or crash dumper needs to know where a process lives and
+//! to read its stack and data. Rather than each such capsule computing
+//! addresses from raw pointers, they are given a `ProcessMemory`, which
+//! reports the layout of a process and copies out ranges after checking
+//! that they lie within memory the process itself can access: its flash and
+//! its RAM below the application break. Grant memory, which holds kernel
+//! state, is never copied, but how much of it each grant has allocated can be
+//! read, to find which capsules a process's grant region went to.
+//!
+//! A capsule can also register to be told when a process faults, before the
+//! kernel restarts it, so it can record the state of the process.
+//!
+//! Creating a `ProcessMemory` is `unsafe`, so only the board can decide
+//! which capsules get one:
+//!
+//! ```rust
+//! let crash_dump = static_init!(
+//!     CrashDump<'static>,
+//!     CrashDump::new(kernel::process_memory::ProcessMemory::new())
+//! );
+//! ```
+
+use callback::AppId;
+use grant;
+use process;
+use returncode::ReturnCode;
+
+/// Where a process's memory is, as addresses.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryLayout {
+    pub flash_start: usize,
+    pub flash_end: usize,
+    pub memory_start: usize,
+    pub memory_end: usize,
+    /// The end of the memory the process can access.
+    pub app_break: usize,
+    /// The start of the grant region, at the top of the process's memory.
+    pub kernel_memory_break: usize,
+    pub stack_pointer: usize,
+    /// The lowest the stack pointer has been.
+    pub min_stack_pointer: usize,
+    /// Where the process started its stack and heap, if known.
+    pub stack_start: Option<usize>,
+    pub heap_start: Option<usize>,
+}
+
+/// The registers of a process, as saved when it last stopped running.
+#[derive(Copy, Clone, Debug)]
+pub struct Registers {
+    pub r0: usize,
+    pub r1: usize,
+    pub r2: usize,
+    pub r3: usize,
+    pub r12: usize,
+    pub lr: usize,
+    pub pc: usize,
+    pub xpsr: usize,
+    pub sp: usize,
+}
+
+/// The fault status registers saved when a process last faulted.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+/// Told when a process faults. Called before the kernel restarts or panics
+/// because of the process, while its memory and registers are as they were
+/// at the fault.
+pub trait FaultObserver {
+    fn process_faulted(&self, appid: AppId);
+}
+
+static mut FAULT_OBSERVER: Option<&'static FaultObserver> = None;
+
+/// Tell the fault observer, if any, that `appid` has faulted.
+pub(crate) fn notify_fault(appid: AppId) {
+    unsafe {
+        FAULT_OBSERVER.map(|observer| observer.process_faulted(appid));
+    }
+}
+
+pub struct ProcessMemory {
+    _private: (),
+}
+
+impl ProcessMemory {
+    /// Only code trusted to read any process's memory should be given a
+    /// `ProcessMemory`.
+    pub unsafe fn new() -> ProcessMemory {
+        ProcessMemory { _private: () }
+    }
+
+    /// The `AppId` of the process in slot `index`, or `None` if the slot is
+    /// empty or out of range. Used to walk all processes.
+    pub fn appid(&self, index: usize) -> Option<AppId> {
+        process::get_state(index).map(|_| AppId::new(index))
+    }
+
+    /// The number of process slots.
+    pub fn num_slots(&self) -> usize {
+        process::num_slots()
+    }
+
+    pub fn state(&self, appid: AppId) -> Option<process::State> {
+        process::get_state(appid.idx())
+    }
+
+    /// The memory layout of the process `appid`, or `None` if there is no
+    /// such process.
+    pub fn layout(&self, appid: AppId) -> Option<MemoryLayout> {
+        process::get_memory_layout(appid.idx())
+    }
+
+    /// The name of the process `appid`, from its TBF header.
+    pub fn name(&self, appid: AppId) -> Option<&'static str> {
+        process::get_package_name(appid.idx())
+    }
+
+    pub fn registers(&self, appid: AppId) -> Option<Registers> {
+        process::get_registers(appid.idx())
+    }
+
+    /// The number of grants, which are numbered from 0.
+    pub fn num_grants(&self) -> usize {
+        grant::num_grants()
+    }
+
+    /// The type held by the grant `grant_num`, such as
+    /// `capsules::console::App`, which says which capsule uses it.
+    pub fn grant_name(&self, grant_num: usize) -> Option<&'static str> {
+        grant::grant_name(grant_num)
+    }
+
+    /// The bytes of grant memory the process `appid` has allocated for the
+    /// grant `grant_num`, or `None` if it has not used the grant. The rest of
+    /// its grant region, `MemoryLayout::kernel_memory_break` up to the end of
+    /// its memory, holds the kernel's state for the process.
+    pub fn grant_bytes(&self, appid: AppId, grant_num: usize) -> Option<usize> {
+        process::get_grant_bytes(appid.idx(), grant_num)
+    }
+
+    /// The fault status registers from the last process fault.
+    pub fn fault_status(&self) -> FaultStatus {
+        process::get_fault_status()
+    }
+
+    /// Call `observer` whenever a process faults, in place of any observer
+    /// set before.
+    pub fn set_fault_observer(&self, observer: &'static FaultObserver) {
+        unsafe {
+            FAULT_OBSERVER = Some(observer);
+        }
+    }
+
+    /// Copy the `buffer.len()` bytes of the process's memory at `address`
+    /// into `buffer`. Returns `EINVAL` if there is no such process or
+    /// any of the range is outside its flash or its RAM below the
+    /// application break.
+    pub fn read(&self, appid: AppId, address: usize, buffer: &mut [u8]) -> ReturnCode {
+        let layout = match self.layout(appid) {
+            Some(layout) => layout,
+            None => return ReturnCode::EINVAL,
+        };
+        let end = match address.checked_add(buffer.len()) {
+            Some(end) => end,
+            None => return ReturnCode::EINVAL,
+        };
+        let in_flash = address >= layout.flash_start && end <= layout.flash_end;
+        let in_memory = address >= layout.memory_start && end <= layout.app_break;
+        if !in_flash && !in_memory {
+            return ReturnCode::EINVAL;
+        }
+
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = unsafe { *((address + i) as *const u8) };
+        }
+        ReturnCode::SUCCESS
+    }
+}
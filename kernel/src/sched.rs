@@ -1,26 +1,181 @@
 //! Tock core scheduler.
 
+use core::cmp;
 use core::ptr;
 use core::ptr::NonNull;
 
 use callback;
 use callback::{AppId, Callback};
+use common::dynamic_deferred_call;
+use driver::{QUERY_DRIVER_NUM, SUBSCRIBE_FOUR_VALUES};
 use ipc;
+use kernel_task;
 use mem::AppSlice;
 use memop;
 use platform::mpu::MPU;
+use platform::sleep;
 use platform::systick::SysTick;
 use platform::{Chip, Platform};
 use process;
 use process::{Process, Task};
+use process_memory;
 use returncode::ReturnCode;
-use syscall::Syscall;
+use syscall;
+use syscall::{Syscall, SyscallFilter, YieldTimer};
 
-/// The time a process is permitted to run before being pre-empted
-const KERNEL_TICK_DURATION_US: u32 = 10000;
 /// Skip re-scheduling a process if its quanta is nearly exhausted
 const MIN_QUANTA_THRESHOLD_US: u32 = 500;
 
+/// How the main loop chooses which process to run.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SchedulingPolicy {
+    /// Give each process a turn in order. This is the default.
+    RoundRobin,
+    /// Run the highest-priority process that is ready, taking turns among
+    /// ready processes of equal priority. Lower-priority processes only run
+    /// when no higher-priority process is ready, and are preempted as soon as
+    /// an interrupt makes one ready.
+    Priority,
+}
+
+static mut POLICY: SchedulingPolicy = SchedulingPolicy::RoundRobin;
+
+/// Select how processes are scheduled. Must be called before `kernel_loop()`.
+/// Boards that do not call it use round-robin scheduling.
+pub unsafe fn set_scheduling_policy(policy: SchedulingPolicy) {
+    POLICY = policy;
+}
+
+static mut PREEMPTION: bool = true;
+
+/// Turn timer preemption on or off. Must be called before `kernel_loop()`.
+///
+/// Without preemption the SysTick is never used, so a board can run on a chip
+/// whose SysTick is unreliable or leave it off to save power. A process then
+/// keeps the CPU until it yields: interrupts that arrive while it runs are
+/// handled and the same process resumes. A process that never yields stops
+/// all others from running.
+pub unsafe fn set_preemption(enabled: bool) {
+    PREEMPTION = enabled;
+}
+
+static mut SYSCALL_FILTER: Option<&'static SyscallFilter> = None;
+
+/// Restrict which drivers each process may use. Must be called before
+/// `kernel_loop()`. Boards that do not call it let every process use every
+/// driver.
+pub unsafe fn set_syscall_filter(filter: &'static SyscallFilter) {
+    SYSCALL_FILTER = Some(filter);
+}
+
+static mut YIELD_TIMER: Option<&'static YieldTimer> = None;
+
+/// The time the yield timer was last asked to wake the chip at.
+static mut YIELD_WAKEUP: Option<u32> = None;
+
+/// Let processes `yield` with a timeout, timed by `timer`. Must be called
+/// before `kernel_loop()`. On boards that do not call it, `YIELD_WAIT_FOR`
+/// returns `ENOSUPPORT`.
+pub unsafe fn set_yield_timer(timer: &'static YieldTimer) {
+    YIELD_TIMER = Some(timer);
+}
+
+/// Whether the board's syscall filter stops `process` from making `syscall`
+/// to the driver `driver_num`.
+fn syscall_denied(process: &Process, syscall: Syscall, driver_num: usize) -> bool {
+    unsafe { SYSCALL_FILTER }.map_or(false, |filter| !filter.allow(process, syscall, driver_num))
+}
+
+/// The process to run next under the priority policy: the ready process with
+/// the highest priority, preferring among equals the first one after `last`.
+fn next_by_priority(processes: &[Option<&mut Process>], last: usize) -> Option<usize> {
+    let len = processes.len();
+    let mut next: Option<(usize, u32)> = None;
+    for offset in 1..len + 1 {
+        let i = (last + offset) % len;
+        if let Some(ref process) = processes[i] {
+            let priority = process.priority();
+            if process.ready() && next.map_or(true, |(_, best)| priority > best) {
+                next = Some((i, priority));
+            }
+        }
+    }
+    next.map(|(i, _)| i)
+}
+
+/// `yield-no-wait`: yield only if a callback is pending. Returns whether the
+/// process yielded.
+fn yield_no_wait(process: &mut Process) -> bool {
+    let result = process.r1();
+    if !process.is_result_word(result) {
+        process.set_return_code(ReturnCode::EINVAL);
+        return false;
+    }
+    let pending = process.has_tasks();
+    unsafe {
+        ptr::write_volatile(result as *mut usize, pending as usize);
+    }
+    if !pending {
+        process.set_return_code(ReturnCode::SUCCESS);
+    }
+    pending
+}
+
+/// `yield-wait-for`: yield until a callback is pending or the timeout in r1
+/// has passed. Returns whether the process yielded.
+fn yield_wait_for(process: &mut Process) -> bool {
+    let timer = match unsafe { YIELD_TIMER } {
+        Some(timer) => timer,
+        None => {
+            process.set_return_code(ReturnCode::ENOSUPPORT);
+            return false;
+        }
+    };
+    let result = process.r2();
+    if !process.is_result_word(result) {
+        process.set_return_code(ReturnCode::EINVAL);
+        return false;
+    }
+    // Waits are capped at half the timer's range, so that they cannot be
+    // mistaken for ones that have already timed out.
+    let ticks = process.r1() as u64 * timer.frequency() as u64 / 1000;
+    let ticks = cmp::min(ticks, u32::max_value() as u64 / 2) as u32;
+    process.start_yield_wait(timer.now(), ticks, result as *mut usize);
+    if process.has_tasks() {
+        process.end_yield_wait(true);
+    }
+    true
+}
+
+/// End the `yield-wait-for` calls that have timed out, and make sure the chip
+/// wakes up when the next one does.
+unsafe fn check_yield_waits(processes: &mut [Option<&mut Process>]) {
+    let timer = match YIELD_TIMER {
+        Some(timer) => timer,
+        None => return,
+    };
+    let now = timer.now();
+    let mut next: Option<u32> = None;
+    for process in processes.iter_mut() {
+        if let Some(ref mut process) = *process {
+            match process.yield_wait_remaining(now) {
+                // The wait ends when the pending callback runs
+                Some(_) if process.has_tasks() => {}
+                Some(0) => process.end_yield_wait(false),
+                Some(remaining) => {
+                    next = Some(next.map_or(remaining, |next| cmp::min(next, remaining)))
+                }
+                None => {}
+            }
+        }
+    }
+    let wakeup = next.map(|remaining| now.wrapping_add(remaining));
+    if wakeup.is_some() && wakeup != YIELD_WAKEUP {
+        wakeup.map(|when| timer.wake_at(when));
+    }
+    YIELD_WAKEUP = wakeup;
+}
+
 /// Main loop.
 pub fn kernel_loop<P: Platform, C: Chip>(
     platform: &P,
@@ -32,29 +187,78 @@ pub fn kernel_loop<P: Platform, C: Chip>(
         process::PROCS = processes;
         &mut process::PROCS
     };
+    chip.mpu().enable_kernel_mpu();
+
+    // The last process run under the priority policy, initially such that
+    // ties go to the first process.
+    let mut last = processes.len().saturating_sub(1);
 
     loop {
         unsafe {
             chip.service_pending_interrupts();
+            dynamic_deferred_call::call_pending();
+            kernel_task::dispatch_pending();
 
+            // Processes can also be faulted from outside the loop, for
+            // example by a process console.
             for (i, p) in processes.iter_mut().enumerate() {
-                p.as_mut().map(|process| {
-                    do_process(platform, chip, process, callback::AppId::new(i), ipc);
-                });
-                if chip.has_pending_interrupts() {
-                    break;
+                p.as_mut()
+                    .map(|process| notify_terminated(platform, process, i));
+            }
+
+            match POLICY {
+                SchedulingPolicy::RoundRobin => {
+                    for (i, p) in processes.iter_mut().enumerate() {
+                        p.as_mut().map(|process| {
+                            do_process(platform, chip, process, callback::AppId::new(i), ipc);
+                        });
+                        if chip.has_pending_interrupts() {
+                            break;
+                        }
+                    }
+                }
+                SchedulingPolicy::Priority => {
+                    if let Some(i) = next_by_priority(processes, last) {
+                        last = i;
+                        processes[i].as_mut().map(|process| {
+                            do_process(platform, chip, process, callback::AppId::new(i), ipc);
+                        });
+                    }
                 }
             }
 
+            check_yield_waits(processes);
+
             chip.atomic(|| {
-                if !chip.has_pending_interrupts() && process::processes_blocked() {
-                    chip.sleep();
+                if !chip.has_pending_interrupts()
+                    && !dynamic_deferred_call::has_pending()
+                    && process::processes_blocked()
+                    && !kernel_task::have_work()
+                {
+                    // The board's sleep policy, if it has one, replaces the
+                    // chip's choice of sleep state.
+                    if !sleep::sleep() {
+                        chip.sleep();
+                    }
                 }
             });
         };
     }
 }
 
+/// Tell the drivers that a process gave callbacks or buffers to that it has
+/// terminated, if it has since this was last called.
+fn notify_terminated<P: Platform>(platform: &P, process: &mut Process, idx: usize) {
+    if let Some((generation, drivers)) = process.take_terminated() {
+        let appid = AppId::with_generation(idx, generation);
+        for &driver_num in drivers.iter().filter_map(|driver_num| driver_num.as_ref()) {
+            platform.with_driver(driver_num, |driver| {
+                driver.map(|d| d.app_terminated(appid));
+            });
+        }
+    }
+}
+
 unsafe fn do_process<P: Platform, C: Chip>(
     platform: &P,
     chip: &mut C,
@@ -62,31 +266,68 @@ unsafe fn do_process<P: Platform, C: Chip>(
     appid: AppId,
     ipc: Option<&::ipc::IPC>,
 ) {
-    let systick = chip.systick();
-    systick.reset();
-    systick.set_timer(KERNEL_TICK_DURATION_US);
-    systick.enable(true);
+    // A process stopped after a fault never runs again, and one stopped by
+    // the kernel not until it is resumed.
+    match process.current_state() {
+        process::State::Fault | process::State::StoppedRunning | process::State::StoppedYielded => {
+            return
+        }
+        _ => {}
+    }
+
+    let preemption = PREEMPTION;
+    if preemption {
+        let systick = chip.systick();
+        systick.reset();
+        systick.set_timer(process.remaining_quantum_us());
+        systick.enable(true);
+    }
+
+    // Time spent servicing interrupts inline is not charged to the process.
+    let start = process::cpu_timestamp();
+    let mut user_ticks = 0;
+    let mut interrupt_ticks = 0;
 
+    let mut expired = false;
     loop {
-        if chip.has_pending_interrupts()
-            || systick.overflowed()
-            || !systick.greater_than(MIN_QUANTA_THRESHOLD_US)
-        {
-            break;
+        if chip.has_pending_interrupts() {
+            if preemption || process.current_state() != process::State::Running {
+                break;
+            }
+            // The process was stopped by an interrupt, not a system call.
+            // Without preemption it keeps the CPU, so handle the interrupt
+            // and carry on running it.
+            let serviced = process::cpu_timestamp();
+            chip.service_pending_interrupts();
+            interrupt_ticks += ticks_since(serviced);
+        }
+        if preemption {
+            let systick = chip.systick();
+            if systick.overflowed() || !systick.greater_than(MIN_QUANTA_THRESHOLD_US) {
+                expired = true;
+                break;
+            }
         }
 
         match process.current_state() {
             process::State::Running => {
                 process.setup_mpu(chip.mpu());
                 chip.mpu().enable_mpu();
-                systick.enable(true);
+                if preemption {
+                    chip.systick().enable(true);
+                }
+                let entered = process::cpu_timestamp();
                 process.switch_to();
-                systick.enable(false);
-                chip.mpu().disable_mpu();
+                user_ticks += ticks_since(entered);
+                if preemption {
+                    chip.systick().enable(false);
+                }
+                chip.mpu().enable_kernel_mpu();
             }
             process::State::Yielded => match process.dequeue_task() {
                 None => break,
                 Some(cb) => {
+                    process.end_yield_wait(true);
                     match cb {
                         Task::FunctionCall(ccb) => {
                             process.push_function_call(ccb);
@@ -109,8 +350,12 @@ unsafe fn do_process<P: Platform, C: Chip>(
                 }
             },
             process::State::Fault => {
-                // we should never be scheduling a process in fault
-                panic!("Attempted to schedule a faulty process");
+                // The process faulted during this turn and was stopped.
+                break;
+            }
+            process::State::StoppedRunning | process::State::StoppedYielded => {
+                // An interrupt handled during this turn stopped the process.
+                break;
             }
         }
 
@@ -121,70 +366,194 @@ unsafe fn do_process<P: Platform, C: Chip>(
         // check if the app had a fault
         if process.app_fault() {
             // let process deal with it as appropriate
+            process_memory::notify_fault(appid);
             process.fault_state();
+            // Before the restarted process can give the drivers anything new.
+            notify_terminated(platform, process, appid.idx());
             continue;
         }
 
         // process had a system call, count it
         process.incr_syscall_count();
-        match process.svc_number() {
+        let svc = process.svc_number();
+        let args = [process.r0(), process.r1(), process.r2(), process.r3()];
+        match svc {
             Some(Syscall::MEMOP) => {
                 let res = memop::memop(process);
                 process.set_return_code(res);
             }
             Some(Syscall::YIELD) => {
+                syscall::trace(appid, Syscall::YIELD, args, 0);
                 process.yield_state();
                 process.pop_syscall_stack();
 
                 // There might be already enqueued callbacks
                 continue;
             }
+            Some(Syscall::YIELD_VARIANT) => {
+                let yielded = match process.r0() {
+                    syscall::YIELD_NO_WAIT => yield_no_wait(process),
+                    syscall::YIELD_WAIT_FOR => yield_wait_for(process),
+                    _ => {
+                        process.set_return_code(ReturnCode::EINVAL);
+                        false
+                    }
+                };
+                if yielded {
+                    syscall::trace(appid, Syscall::YIELD_VARIANT, args, 0);
+                    process.yield_state();
+                    process.pop_syscall_stack();
+                    continue;
+                }
+            }
             Some(Syscall::SUBSCRIBE) => {
                 let driver_num = process.r0();
-                let subdriver_num = process.r1();
+                let four_values = process.r1() & SUBSCRIBE_FOUR_VALUES != 0;
+                let subdriver_num = process.r1() & !SUBSCRIBE_FOUR_VALUES;
                 let callback_ptr_raw = process.r2() as *mut ();
                 let appdata = process.r3();
 
                 let callback_ptr = NonNull::new(callback_ptr_raw);
-                let callback = callback_ptr.map(|ptr| Callback::new(appid, appdata, ptr.cast()));
+                let callback = callback_ptr.map(|ptr| {
+                    Callback::new(
+                        appid,
+                        appdata,
+                        ptr.cast(),
+                        (driver_num, subdriver_num),
+                        four_values,
+                    )
+                });
+                let unsubscribe = callback.is_none();
 
-                let res = platform.with_driver(driver_num, |driver| match driver {
-                    Some(d) => d.subscribe(subdriver_num, callback, appid),
-                    None => ReturnCode::ENODEVICE,
+                let res = if syscall_denied(process, Syscall::SUBSCRIBE, driver_num) {
+                    Err(ReturnCode::ENOSUPPORT)
+                } else if four_values && !unsubscribe && !process.is_result_word(appdata) {
+                    // The kernel would have nowhere to put the fourth value.
+                    Err(ReturnCode::EINVAL)
+                } else {
+                    platform.with_driver(driver_num, |driver| match driver {
+                        Some(d) => d.subscribe(subdriver_num, callback, appid),
+                        None => Err(ReturnCode::ENODEVICE),
+                    })
+                };
+                // Unsubscribing also drops the calls to the old callback that
+                // are already queued, as the process may free the closure
+                // behind it as soon as this returns.
+                if unsubscribe {
+                    process.remove_pending_callbacks(driver_num, subdriver_num);
+                } else if res.is_ok() {
+                    process.note_driver_used(driver_num);
+                }
+                // The process gets back the address of the function it
+                // subscribed before, or 0 if there was none. A driver with one
+                // callback for all processes may return another process's,
+                // whose address must not leak to this one.
+                process.set_return_code(match res {
+                    Ok(previous) => previous
+                        .filter(|callback| callback.app_id() == appid)
+                        .and_then(|callback| callback.function_address())
+                        .map_or(ReturnCode::SUCCESS, |address| {
+                            ReturnCode::SuccessWithValue { value: address }
+                        }),
+                    Err(err) => err,
                 });
-                process.set_return_code(res);
             }
             Some(Syscall::COMMAND) => {
-                let res = platform.with_driver(process.r0(), |driver| match driver {
-                    Some(d) => d.command(process.r1(), process.r2(), process.r3(), appid),
-                    None => ReturnCode::ENODEVICE,
-                });
+                let minor_num = process.r1();
+                let res = if process.r0() == QUERY_DRIVER_NUM {
+                    query_driver(platform, minor_num, process.r2())
+                } else if syscall_denied(process, Syscall::COMMAND, process.r0()) {
+                    ReturnCode::ENOSUPPORT
+                } else {
+                    platform.with_driver(process.r0(), |driver| match driver {
+                        Some(d) => match d.command(minor_num, process.r2(), process.r3(), appid) {
+                            // Command 0 is how apps check that a driver is
+                            // present, so it must succeed.
+                            ReturnCode::ENOSUPPORT if minor_num == 0 => ReturnCode::SUCCESS,
+                            res => res,
+                        },
+                        None => ReturnCode::ENODEVICE,
+                    })
+                };
                 process.set_return_code(res);
             }
             Some(Syscall::ALLOW) => {
-                let res = platform.with_driver(process.r0(), |driver| {
-                    match driver {
-                        Some(d) => {
-                            let start_addr = process.r2() as *mut u8;
-                            if start_addr != ptr::null_mut() {
-                                let size = process.r3();
-                                if process.in_exposed_bounds(start_addr, size) {
-                                    let slice = AppSlice::new(start_addr as *mut u8, size, appid);
-                                    d.allow(appid, process.r1(), Some(slice))
+                let res = if syscall_denied(process, Syscall::ALLOW, process.r0()) {
+                    ReturnCode::ENOSUPPORT
+                } else {
+                    platform.with_driver(process.r0(), |driver| {
+                        match driver {
+                            Some(d) => {
+                                let start_addr = process.r2() as *mut u8;
+                                if start_addr != ptr::null_mut() {
+                                    let size = process.r3();
+                                    if process.in_exposed_bounds(start_addr, size) {
+                                        let slice =
+                                            AppSlice::new(start_addr as *mut u8, size, appid);
+                                        d.allow(appid, process.r1(), Some(slice))
+                                    } else {
+                                        ReturnCode::EINVAL /* memory not allocated to process */
+                                    }
                                 } else {
-                                    ReturnCode::EINVAL /* memory not allocated to process */
+                                    d.allow(appid, process.r1(), None)
                                 }
-                            } else {
-                                d.allow(appid, process.r1(), None)
                             }
+                            None => ReturnCode::ENODEVICE,
                         }
-                        None => ReturnCode::ENODEVICE,
-                    }
-                });
+                    })
+                };
+                if res == ReturnCode::SUCCESS && process.r2() != 0 {
+                    let driver_num = process.r0();
+                    process.note_driver_used(driver_num);
+                }
                 process.set_return_code(res);
             }
             _ => {}
         }
+        if let Some(svc) = svc {
+            syscall::trace(appid, svc, args, process.r0() as isize);
+        }
     }
+
+    let total_ticks = ticks_since(start);
+    process.add_cpu_time(
+        user_ticks,
+        total_ticks.saturating_sub(user_ticks + interrupt_ticks),
+    );
+
+    if !preemption {
+        return;
+    }
+
+    // A process interrupted part way through its quantum only gets the rest
+    // of it on its next turn, so interrupts do not lengthen how long it runs
+    // before being pre-empted.
+    let systick = chip.systick();
+    let remaining = if !expired && process.current_state() == process::State::Running {
+        systick.remaining_us()
+    } else {
+        process.quantum_us()
+    };
+    process.set_remaining_quantum_us(remaining);
     systick.reset();
 }
+
+/// The ticks of the CPU time source since `start`, or `0` if there is none.
+fn ticks_since(start: Option<u64>) -> u64 {
+    match (start, process::cpu_timestamp()) {
+        (Some(start), Some(now)) => now.wrapping_sub(start),
+        _ => 0,
+    }
+}
+
+/// Handle a command to the driver discovery interface.
+fn query_driver<P: Platform>(platform: &P, minor_num: usize, driver_num: usize) -> ReturnCode {
+    match minor_num {
+        0 => ReturnCode::SUCCESS,
+        1 => platform.with_driver(driver_num, |driver| match driver {
+            Some(d) => ReturnCode::SuccessWithValue { value: d.version() },
+            None => ReturnCode::ENODEVICE,
+        }),
+        _ => ReturnCode::ENOSUPPORT,
+    }
+}
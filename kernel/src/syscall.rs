@@ -1,4 +1,36 @@
 //! Tock syscall number definitions.
+//!
+//! When the kernel is built with the `syscall_trace` feature, it also records
+//! the last `TRACE_LEN` system calls made by processes, and the interrupts
+//! serviced between them, which `debug::print_syscall_trace()` prints. Bugs
+//! that depend on an interrupt arriving between two particular system calls
+//! are hard to reproduce, and the trace shows the interleaving that led to
+//! one. Interrupts are recorded by `InterruptBudget::measure()`, so only chips
+//! that service their interrupts through it (currently the SAM4L and the
+//! nRF52) have them in the trace.
+//!
+//! The kernel only records the trace. Replaying it after a reboot would need
+//! an emulator that can hold back interrupts and deliver each one at its
+//! recorded point, and that also restores the state of the peripherals, and
+//! there is no such emulator for Tock boards yet.
+
+use callback::AppId;
+use process::Process;
+
+/// Revision of the system call interface, incremented when system calls or
+/// kernel-defined commands (such as new memop operations) are added or
+/// changed. Each revision changed:
+///
+/// - 2: IPC buffer size negotiation and length-prefixed messages.
+/// - 3: IPC publish to all subscribed clients.
+/// - 4: the `yield-no-wait` and `yield-wait-for` variants.
+/// - 5: `subscribe` returns the callback it replaced.
+/// - 6: four-value callbacks, subscribed with `SUBSCRIBE_FOUR_VALUES`.
+/// - 7: memops 13 and 14 for dropped callbacks.
+/// - 8: memops 15 to 17 for grant, heap and stack usage.
+/// - 9: the `yield` variants move to `svc 5`.
+/// - 10: IPC command 5, notify all clients.
+pub const ABI_REVISION: usize = 10;
 
 /// The syscall number assignments.
 #[derive(Copy, Clone, Debug)]
@@ -18,4 +50,119 @@ pub enum Syscall {
 
     /// Various memory operations.
     MEMOP = 4,
+
+    /// A `yield` that can return without a callback, its variant chosen by
+    /// r0. It has its own number because a plain `yield` leaves r0 as it was.
+    #[allow(non_camel_case_types)]
+    YIELD_VARIANT = 5,
+}
+
+/// The `yield` variant, passed in r0 to `YIELD_VARIANT`, that runs a pending
+/// callback like a plain `yield` but returns at once if there is none. The kernel writes 1
+/// to the word at the address in r1 if a callback ran and 0 if not.
+pub const YIELD_NO_WAIT: usize = 1;
+
+/// The `yield` variant, passed in r0 to `YIELD_VARIANT`, that waits at most
+/// r1 milliseconds for a callback. The kernel writes 1 to the word at the
+/// address in r2 if a callback ran and 0 if the wait timed out. Returns
+/// `ENOSUPPORT` without waiting if the board has no `YieldTimer`.
+///
+/// `YIELD_VARIANT` returns `EINVAL` for any other value in r0.
+pub const YIELD_WAIT_FOR: usize = 2;
+
+/// The timer the kernel uses to end `YIELD_WAIT_FOR` calls, provided by the
+/// board and registered with `set_yield_timer()`.
+pub trait YieldTimer {
+    /// The current time, in ticks.
+    fn now(&self) -> u32;
+
+    /// The number of ticks per second.
+    fn frequency(&self) -> u32;
+
+    /// Wake the chip from sleep at `when`, replacing the wakeup asked for
+    /// before. The kernel checks which waits have timed out itself.
+    fn wake_at(&self, when: u32);
+}
+
+/// A board policy deciding which drivers each process may use, for example to
+/// let only one trusted app use the radio.
+pub trait SyscallFilter {
+    /// Whether `process` may make `syscall` to the driver `driver_num`.
+    /// Called for `subscribe`, `command` and `allow`; a denied call returns
+    /// `ENOSUPPORT` without reaching the driver.
+    fn allow(&self, process: &Process, syscall: Syscall, driver_num: usize) -> bool;
+}
+
+/// The number of system calls and interrupts the tracer keeps.
+#[cfg(feature = "syscall_trace")]
+pub const TRACE_LEN: usize = 64;
+
+/// A system call or interrupt recorded by the tracer.
+#[cfg(feature = "syscall_trace")]
+#[derive(Copy, Clone, Debug)]
+pub enum TraceEntry {
+    /// A chip serviced this interrupt.
+    Interrupt(u32),
+    /// A process made this system call.
+    Syscall {
+        appid: AppId,
+        syscall: Syscall,
+        /// r0 to r3 as the process passed them
+        args: [usize; 4],
+        /// The value returned to the process
+        result: isize,
+    },
+}
+
+#[cfg(feature = "syscall_trace")]
+static mut TRACE: [Option<TraceEntry>; TRACE_LEN] = [None; TRACE_LEN];
+
+/// The index in `TRACE` of the next entry, and so of the oldest one.
+#[cfg(feature = "syscall_trace")]
+static mut TRACE_NEXT: usize = 0;
+
+/// Record an entry, replacing the oldest one recorded.
+#[cfg(feature = "syscall_trace")]
+fn record(entry: TraceEntry) {
+    unsafe {
+        TRACE[TRACE_NEXT] = Some(entry);
+        TRACE_NEXT = (TRACE_NEXT + 1) % TRACE_LEN;
+    }
+}
+
+/// Record a system call.
+#[cfg(feature = "syscall_trace")]
+pub(crate) fn trace(appid: AppId, syscall: Syscall, args: [usize; 4], result: isize) {
+    record(TraceEntry::Syscall {
+        appid: appid,
+        syscall: syscall,
+        args: args,
+        result: result,
+    });
+}
+
+#[cfg(not(feature = "syscall_trace"))]
+#[inline(always)]
+pub(crate) fn trace(_appid: AppId, _syscall: Syscall, _args: [usize; 4], _result: isize) {}
+
+/// Record that a chip serviced `interrupt`.
+#[cfg(feature = "syscall_trace")]
+pub(crate) fn trace_interrupt(interrupt: u32) {
+    record(TraceEntry::Interrupt(interrupt));
+}
+
+#[cfg(not(feature = "syscall_trace"))]
+#[inline(always)]
+pub(crate) fn trace_interrupt(_interrupt: u32) {}
+
+/// Call `f` on each recorded entry, oldest first.
+#[cfg(feature = "syscall_trace")]
+pub(crate) fn for_each_traced<F: FnMut(&TraceEntry)>(mut f: F) {
+    unsafe {
+        for i in 0..TRACE_LEN {
+            if let Some(ref entry) = TRACE[(TRACE_NEXT + i) % TRACE_LEN] {
+                f(entry);
+            }
+        }
+    }
 }
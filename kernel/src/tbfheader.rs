@@ -63,7 +63,9 @@ pub(crate) enum TbfHeaderTypes {
     TbfHeaderMain = 1,
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
-    Unused = 5,
+    TbfHeaderStorageRegion = 5,
+    TbfHeaderPriority = 6,
+    Unused = 7,
 }
 
 /// The TLV header (T and L).
@@ -97,6 +99,23 @@ pub(crate) struct TbfHeaderV2WriteableFlashRegion {
     writeable_flash_region_size: u32,
 }
 
+/// The app's private region of the board's nonvolatile storage, as an offset
+/// and size within the space the storage driver exposes to userspace.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2StorageRegion {
+    storage_region_offset: u32,
+    storage_region_size: u32,
+}
+
+/// The app's scheduling priority, used when the board selects the priority
+/// scheduler.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2Priority {
+    priority: u32,
+}
+
 /// PIC fields for kernel provided PIC fixup.
 ///
 /// If an app wants the kernel to do the PIC fixup for it, it must pass this
@@ -123,6 +142,8 @@ pub(crate) struct TbfHeaderV2 {
     main: Option<&'static TbfHeaderV2Main>,
     package_name: Option<&'static str>,
     writeable_regions: Option<&'static [TbfHeaderV2WriteableFlashRegion]>,
+    storage_region: Option<&'static TbfHeaderV2StorageRegion>,
+    priority: Option<&'static TbfHeaderV2Priority>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -257,6 +278,25 @@ impl TbfHeader {
             _ => (0, 0),
         }
     }
+
+    /// Get the offset and size of the app's nonvolatile storage region, if it
+    /// declared one.
+    pub(crate) fn get_storage_region(&self) -> Option<(u32, u32)> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .storage_region
+                .map(|sr| (sr.storage_region_offset, sr.storage_region_size)),
+            _ => None,
+        }
+    }
+
+    /// Get the app's scheduling priority, or `0` if it did not declare one.
+    pub(crate) fn get_priority(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.priority.map_or(0, |p| p.priority),
+            _ => 0,
+        }
+    }
 }
 
 /// Converts a pointer to memory to a TbfHeader struct
@@ -358,6 +398,8 @@ pub(crate) unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option
                     &'static [TbfHeaderV2WriteableFlashRegion],
                 > = None;
                 let mut app_name_str = "";
+                let mut storage_region_pointer: Option<&TbfHeaderV2StorageRegion> = None;
+                let mut priority_pointer: Option<&TbfHeaderV2Priority> = None;
 
                 // Loop through the header looking for known options.
                 while remaining_length > mem::size_of::<TbfHeaderTlv>() {
@@ -396,6 +438,20 @@ pub(crate) unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option
                                     let _ = str::from_utf8(package_name_byte_array).map(|name_str| { app_name_str = name_str; });
                                 }
                             }
+                            TbfHeaderTypes::TbfHeaderStorageRegion => /* Storage Region */ {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2StorageRegion>() &&
+                                   tbf_tlv_header.length as usize == mem::size_of::<TbfHeaderV2StorageRegion>() {
+                                    let region = &*(address.offset(offset) as *const TbfHeaderV2StorageRegion);
+                                    storage_region_pointer = Some(region);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderPriority => /* Priority */ {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2Priority>() &&
+                                   tbf_tlv_header.length as usize == mem::size_of::<TbfHeaderV2Priority>() {
+                                    let priority = &*(address.offset(offset) as *const TbfHeaderV2Priority);
+                                    priority_pointer = Some(priority);
+                                }
+                            }
                             TbfHeaderTypes::Unused => {}
                         }
                     }
@@ -411,6 +467,8 @@ pub(crate) unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option
                     main: main_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: wfr_pointer,
+                    storage_region: storage_region_pointer,
+                    priority: priority_pointer,
                 };
 
                 Some(TbfHeader::TbfHeaderV2(tbf_header))
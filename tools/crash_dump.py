@@ -0,0 +1,69 @@
+#!/usr/bin/env python
+#
+# usage: crash_dump.py [-h] [--offset OFFSET] FILE
+#
+# Print a process crash dump written by the crash_dump capsule. FILE is an
+# image of the nonvolatile storage read back from the board; the dump starts
+# at OFFSET (default 0) within it.
+#
+# Example:
+#   crash_dump.py --offset 0x3F000 storage.bin
+
+import argparse
+import struct
+import sys
+
+DUMP_MAGIC = 0x504D4443
+DUMP_VERSION = 1
+HEADER_LEN = 120
+
+REGISTERS = ['r0', 'r1', 'r2', 'r3', 'r12', 'lr', 'pc', 'xpsr', 'sp']
+FAULT_STATUS = ['CFSR', 'HFSR', 'MMFAR', 'BFAR']
+LAYOUT = ['flash start', 'flash end', 'RAM start', 'RAM end',
+          'app break', 'kernel memory break', 'lowest sp']
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument('file')
+    parser.add_argument('--offset', type=lambda x: int(x, 0), default=0)
+    args = parser.parse_args()
+
+    with open(args.file, 'rb') as f:
+        f.seek(args.offset)
+        data = f.read()
+
+    if len(data) < HEADER_LEN:
+        sys.exit('File is too short for a crash dump')
+    magic, version, length, sequence, index = struct.unpack_from('<5I', data, 0)
+    if magic != DUMP_MAGIC:
+        sys.exit('No crash dump at offset {:#x}'.format(args.offset))
+    if version != DUMP_VERSION:
+        sys.exit('Unsupported crash dump version {}'.format(version))
+
+    name = data[20:36].split(b'\0')[0].decode('utf-8', 'replace')
+    words = struct.unpack_from('<21I', data, 36)
+
+    print('Crash dump {}: process {} ({}), {} bytes'.format(
+        sequence, index, name, length))
+    print('\nRegisters')
+    for reg, value in zip(REGISTERS, words[0:9]):
+        print('  {:5} {:#010x}'.format(reg, value))
+    print('\nFault status')
+    for reg, value in zip(FAULT_STATUS, words[9:13]):
+        print('  {:5} {:#010x}'.format(reg, value))
+    print('\nMemory map')
+    for field, value in zip(LAYOUT, words[13:20]):
+        print('  {:20} {:#010x}'.format(field, value))
+
+    stack_len = words[20]
+    sp = words[8]
+    stack = data[HEADER_LEN:HEADER_LEN + stack_len]
+    print('\nStack ({} bytes)'.format(stack_len))
+    for i in range(0, len(stack) - len(stack) % 4, 4):
+        (value,) = struct.unpack_from('<I', stack, i)
+        print('  {:#010x}: {:#010x}'.format(sp + i, value))
+
+
+if __name__ == '__main__':
+    main()